@@ -0,0 +1,68 @@
+//! A sliding window rate limiter: each request is logged in a sorted set scored
+//! by its time, and a request is allowed while the window holds few enough.
+//!
+//! Run with `cargo run -p uranus-c --example rate_limiter`. It serves an
+//! embedded uranus server, so nothing else needs to be running.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use tokio::net::TcpListener;
+use uranus_c::Client;
+use uranus_s::ZRangeBy;
+
+const WINDOW: Duration = Duration::from_millis(500);
+const LIMIT: usize = 5;
+
+struct RateLimiter {
+    client: Client,
+    requests: u64,
+}
+
+impl RateLimiter {
+    /// Logs a request of `user`, returns whether it is within the limit.
+    async fn allow(&mut self, user: &str) -> Result<bool> {
+        let key = format!("rate:{}", user);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as f64;
+        // Members must be distinct, even for requests in the same millisecond.
+        self.requests += 1;
+        let request = format!("{}-{}", now, self.requests);
+        self.client.zadd(&key, [(now, request)]).await?;
+
+        let window = ZRangeBy::Score(now - WINDOW.as_millis() as f64, now);
+        let recent = self.client.zrange(&key, window).await?;
+        Ok(recent.len() <= LIMIT)
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(uranus_s::run(listener));
+
+    let mut limiter = RateLimiter {
+        client: Client::connect(addr).await?,
+        requests: 0,
+    };
+    let mut allowed = 0;
+    for _ in 0..8 {
+        if limiter.allow("oberon").await? {
+            allowed += 1;
+        }
+    }
+    println!("{} of 8 burst requests allowed", allowed);
+    assert_eq!(allowed, LIMIT);
+    assert!(
+        limiter.allow("titania").await?,
+        "users are limited separately"
+    );
+
+    tokio::time::sleep(WINDOW).await;
+    assert!(
+        limiter.allow("oberon").await?,
+        "the window slid past the burst"
+    );
+    println!("allowed again once the window slid");
+    Ok(())
+}
@@ -0,0 +1,61 @@
+//! A session store: sessions expire on their own after a period of inactivity,
+//! and an auditor follows logins and logouts through keyspace events.
+//!
+//! Run with `cargo run -p uranus-c --example session_store`. It serves an
+//! embedded uranus server, so nothing else needs to be running.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::net::TcpListener;
+use tokio_stream::StreamExt;
+use uranus_c::Client;
+use uranus_s::ServerConfig;
+
+const IDLE_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Opens a session of `user`, or renews it.
+async fn touch(client: &mut Client, session: &str, user: &str) -> Result<()> {
+    let key = format!("session:{}", session);
+    client
+        .set_with_ttl(&key, user.to_string(), IDLE_TIMEOUT)
+        .await
+}
+
+async fn user_of(client: &mut Client, session: &str) -> Result<Option<String>> {
+    client.get(format!("session:{}", session)).await
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let config = ServerConfig {
+        keyspace_events: "Kwx".parse()?,
+        ..Default::default()
+    };
+    tokio::spawn(uranus_s::run_with_config(listener, config));
+
+    let auditor = Client::connect(addr).await?;
+    let events = auditor.watch_keys("session:*").await?;
+    tokio::spawn(async move {
+        tokio::pin!(events);
+        while let Some(Ok(event)) = events.next().await {
+            println!("audit: {} on {}", event.op, event.key);
+        }
+    });
+
+    let mut client = Client::connect(addr).await?;
+    touch(&mut client, "a1", "ariel").await?;
+    touch(&mut client, "b2", "umbriel").await?;
+
+    // ariel keeps browsing, umbriel walks away.
+    for _ in 0..3 {
+        tokio::time::sleep(IDLE_TIMEOUT / 2).await;
+        touch(&mut client, "a1", "ariel").await?;
+    }
+    assert_eq!(user_of(&mut client, "a1").await?.as_deref(), Some("ariel"));
+    assert_eq!(user_of(&mut client, "b2").await?, None);
+    println!("umbriel's session expired, ariel's is still open");
+    Ok(())
+}
@@ -0,0 +1,80 @@
+//! A URL shortener: codes map to URLs, and visits are counted per code.
+//!
+//! Run with `cargo run -p uranus-c --example url_shortener`. It serves an
+//! embedded uranus server, so nothing else needs to be running.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use anyhow::Result;
+use tokio::net::TcpListener;
+use uranus_c::{Client, Pool, PoolConfig};
+
+const ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+/// A short, stable code for `url`.
+fn code(url: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+    let mut hash = hasher.finish();
+    (0..7)
+        .map(|_| {
+            let c = ALPHABET[(hash % ALPHABET.len() as u64) as usize];
+            hash /= ALPHABET.len() as u64;
+            c as char
+        })
+        .collect()
+}
+
+async fn shorten(client: &mut Client, url: &str) -> Result<String> {
+    let code = code(url);
+    client.set(format!("url:{}", code), url).await?;
+    Ok(code)
+}
+
+/// Resolves `code`, counting the visit: each visit appends a byte to the
+/// counter, whose length is then the number of visits.
+async fn visit(client: &mut Client, code: &str) -> Result<Option<String>> {
+    let Some(url) = client.get(format!("url:{}", code)).await? else {
+        return Ok(None);
+    };
+    client.append(&format!("visits:{}", code), ".").await?;
+    Ok(Some(url))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(uranus_s::run(listener));
+
+    let pool = Pool::new(addr.to_string(), PoolConfig::default());
+    let code = shorten(
+        &mut *pool.get().await?,
+        "https://en.wikipedia.org/wiki/Uranus",
+    )
+    .await?;
+    println!("shortened to /{}", code);
+
+    // Visitors arrive concurrently, each on a pooled connection.
+    let visitors: Vec<_> = (0..10)
+        .map(|_| {
+            let pool = pool.clone();
+            let code = code.clone();
+            tokio::spawn(async move { visit(&mut *pool.get().await?, &code).await })
+        })
+        .collect();
+    for visitor in visitors {
+        let url = visitor.await??;
+        assert_eq!(url.as_deref(), Some("https://en.wikipedia.org/wiki/Uranus"));
+    }
+
+    let mut client = pool.get().await?;
+    assert_eq!(visit(&mut client, "missing").await?, None);
+    let visits = client.strlen(&format!("visits:{}", code)).await?;
+    println!("/{} was visited {} times", code, visits);
+    assert_eq!(visits, 10);
+    Ok(())
+}
@@ -0,0 +1,62 @@
+//! A synchronous client
+//!
+//! [`Client`] wraps the asynchronous [`crate::Client`] with a runtime of its
+//! own, on which each call blocks, so that programs without tokio, such as
+//! command-line tools, can talk to a server:
+//!
+//! ```ignore
+//! let mut client = uranus_c::blocking::Client::connect("127.0.0.1:12322")?;
+//! client.set("key", "value")?;
+//! let value: Option<String> = client.get("key")?;
+//! ```
+//!
+//! Calls panic when made from within an asynchronous runtime, whose threads
+//! mustn't block; use the asynchronous client there.
+
+use std::net::ToSocketAddrs;
+
+use anyhow::{anyhow, Result};
+use tokio::runtime::{self, Runtime};
+
+use crate::{FromValue, ToKey, ToValue};
+
+/// A connection to a server, see the [module documentation](self).
+pub struct Client {
+    client: crate::Client,
+    /// Drives the connection while a call blocks on it.
+    runtime: Runtime,
+}
+
+impl Client {
+    pub fn connect<T: ToSocketAddrs>(addr: T) -> Result<Client> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("no address to connect to"))?;
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let client = runtime.block_on(crate::Client::connect(addr))?;
+        Ok(Client { client, runtime })
+    }
+
+    /// See [`crate::Client::echo`].
+    pub fn echo(&mut self, echo: impl ToString) -> Result<String> {
+        self.runtime.block_on(self.client.echo(echo))
+    }
+
+    /// See [`crate::Client::get`].
+    pub fn get<V: FromValue>(&mut self, key: impl ToKey) -> Result<V> {
+        self.runtime.block_on(self.client.get(key))
+    }
+
+    /// See [`crate::Client::set`].
+    pub fn set(&mut self, key: impl ToKey, value: impl ToValue) -> Result<()> {
+        self.runtime.block_on(self.client.set(key, value))
+    }
+
+    /// Deletes `keys`, returns how many existed.
+    pub fn del(&mut self, keys: &[&str]) -> Result<i64> {
+        self.runtime.block_on(self.client.del(keys))
+    }
+}
@@ -0,0 +1,153 @@
+//! Following the redirections of a cluster, see [`uranus_s::cluster`]
+//!
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use tokio::net::TcpStream;
+use tracing::debug;
+use uranus_s::{key_slot, Asking, Cluster, Command, Connection, ErrorCode, Frame};
+
+use crate::{binary, Client, ClientError, Link};
+
+/// A request is redirected this many times at most.
+const MAX_REDIRECTIONS: usize = 5;
+
+/// Where the requests of a [`Client`] in cluster mode go.
+#[derive(Debug, Clone)]
+pub(crate) struct Routes {
+    /// The address the connection of the client is connected to.
+    home: String,
+    /// The node serving each slot, as learned from `MOVED` redirections.
+    slots: HashMap<u16, String>,
+    /// Connections to the other nodes, opened on the first redirection there.
+    nodes: HashMap<String, Link>,
+}
+
+enum Redirect {
+    Moved(u16, String),
+    Ask(String),
+}
+
+impl Redirect {
+    fn parse(reply: &Frame) -> Option<Redirect> {
+        let Frame::Error(err) = reply else {
+            return None;
+        };
+        let (code, message) = ErrorCode::split(err);
+        let (slot, addr) = message.split_once(' ')?;
+        let slot = slot.parse().ok()?;
+        let addr = addr.to_string();
+        match code {
+            ErrorCode::Moved => Some(Redirect::Moved(slot, addr)),
+            ErrorCode::Ask => Some(Redirect::Ask(addr)),
+            _ => None,
+        }
+    }
+}
+
+/// The slot of the key of `request`, if it has one.
+pub(crate) fn request_slot(request: &Frame) -> Option<u16> {
+    let cmd = Command::from_frame(request.clone()).ok()?;
+    cmd.key().map(key_slot)
+}
+
+impl Client {
+    /// Connects to the cluster node at `addr`. Requests go to the node serving
+    /// their key, as learned from the `MOVED` redirections of the cluster, and
+    /// `ASK` redirections are followed too.
+    pub async fn connect_cluster(addr: impl ToString) -> Result<Client> {
+        let addr = addr.to_string();
+        let mut client = Client::connect(addr.as_str()).await?;
+        client.cluster = Some(Routes {
+            home: addr,
+            slots: HashMap::new(),
+            nodes: HashMap::new(),
+        });
+        Ok(client)
+    }
+
+    /// The nodes known by gossip to the node, see
+    /// [`Membership::describe`](uranus_s::Membership::describe).
+    pub async fn cluster_nodes(&mut self) -> Result<String> {
+        let text = binary(self.request(Cluster::Nodes.into_frame()).await?)?;
+        Ok(String::from_utf8_lossy(&text).into_owned())
+    }
+
+    /// Sends `request` to the node serving `slot`, following redirections.
+    pub(crate) async fn request_routed(
+        &mut self,
+        request: Frame,
+        slot: Option<u16>,
+    ) -> Result<Frame> {
+        let routes = self
+            .cluster
+            .as_ref()
+            .expect("the client is in cluster mode");
+        let mut target = slot.and_then(|slot| routes.slots.get(&slot).cloned());
+        let mut asking = false;
+        for _ in 0..=MAX_REDIRECTIONS {
+            let response = match self.send_to(target.as_deref(), &request, asking).await {
+                Ok(response) => response,
+                Err(err) => {
+                    // Reconnect on the next request.
+                    if let (Some(routes), Some(addr)) = (&mut self.cluster, &target) {
+                        routes.nodes.remove(addr);
+                    }
+                    return Err(err);
+                }
+            };
+            match Redirect::parse(&response) {
+                Some(Redirect::Moved(slot, addr)) => {
+                    debug!(slot, %addr, "moved");
+                    if let Some(routes) = &mut self.cluster {
+                        routes.slots.insert(slot, addr.clone());
+                    }
+                    target = Some(addr);
+                    asking = false;
+                }
+                Some(Redirect::Ask(addr)) => {
+                    debug!(%addr, "asked");
+                    target = Some(addr);
+                    asking = true;
+                }
+                None => return self.check_response(response),
+            }
+        }
+        Err(ClientError::TooManyRedirections)?
+    }
+
+    async fn send_to(
+        &mut self,
+        addr: Option<&str>,
+        request: &Frame,
+        asking: bool,
+    ) -> Result<Frame> {
+        let link = self.link_to(addr).await?;
+        if !asking {
+            return link.request(request.clone()).await;
+        }
+        let mut replies = link
+            .requests(vec![Asking::new().into_frame(), request.clone()])
+            .await?;
+        replies
+            .pop()
+            .ok_or_else(|| ClientError::ConnectionReset.into())
+    }
+
+    async fn link_to(&mut self, addr: Option<&str>) -> Result<Link> {
+        let Some(routes) = &mut self.cluster else {
+            return Ok(self.link.clone());
+        };
+        let addr = match addr {
+            Some(addr) if addr != routes.home => addr,
+            _ => return Ok(self.link.clone()),
+        };
+        if !routes.nodes.contains_key(addr) {
+            let socket = TcpStream::connect(addr).await?;
+            let link = Link::start(Connection::new(socket));
+            routes.nodes.insert(addr.to_string(), link);
+        }
+        Ok(routes.nodes[addr].clone())
+    }
+}
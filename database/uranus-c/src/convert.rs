@@ -0,0 +1,231 @@
+//! Converting keys and values from and to Rust types
+//!
+//! [`Client::set`](crate::Client::set) takes anything [`ToKey`] and
+//! [`ToValue`], and [`Client::get`](crate::Client::get) reads into anything
+//! [`FromValue`], so that numbers are stored and read back without going
+//! through text by hand:
+//!
+//! ```ignore
+//! client.set(42, 7).await?;
+//! let n: i64 = client.get(42).await?;
+//! let missing: Option<i64> = client.get("missing").await?;
+//! ```
+//!
+//! Keys and values are bytes, so neither has to be UTF-8. Numbers are stored
+//! in their decimal form, so that other clients read them as text.
+
+use bytes::Bytes;
+
+use crate::{ClientError, Value};
+
+/// A type naming a key.
+pub trait ToKey {
+    fn to_key(&self) -> Bytes;
+}
+
+/// A type stored as the value of a key.
+pub trait ToValue {
+    fn into_value(self) -> Bytes;
+}
+
+/// A type read from a reply of the server.
+pub trait FromValue: Sized {
+    /// Fails with [`ClientError::UnexpectedType`] on replies of another type.
+    fn from_value(value: Value) -> Result<Self, ClientError>;
+}
+
+impl ToKey for str {
+    fn to_key(&self) -> Bytes {
+        Bytes::copy_from_slice(self.as_bytes())
+    }
+}
+
+impl ToKey for String {
+    fn to_key(&self) -> Bytes {
+        Bytes::copy_from_slice(self.as_bytes())
+    }
+}
+
+impl ToKey for [u8] {
+    fn to_key(&self) -> Bytes {
+        Bytes::copy_from_slice(self)
+    }
+}
+
+impl ToKey for Vec<u8> {
+    fn to_key(&self) -> Bytes {
+        Bytes::copy_from_slice(self)
+    }
+}
+
+impl ToKey for Bytes {
+    fn to_key(&self) -> Bytes {
+        self.clone()
+    }
+}
+
+impl<K: ToKey + ?Sized> ToKey for &K {
+    fn to_key(&self) -> Bytes {
+        (**self).to_key()
+    }
+}
+
+impl ToValue for &str {
+    fn into_value(self) -> Bytes {
+        Bytes::copy_from_slice(self.as_bytes())
+    }
+}
+
+impl ToValue for String {
+    fn into_value(self) -> Bytes {
+        Bytes::from(self)
+    }
+}
+
+impl ToValue for &[u8] {
+    fn into_value(self) -> Bytes {
+        Bytes::copy_from_slice(self)
+    }
+}
+
+impl ToValue for Vec<u8> {
+    fn into_value(self) -> Bytes {
+        Bytes::from(self)
+    }
+}
+
+impl ToValue for Bytes {
+    fn into_value(self) -> Bytes {
+        self
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Value, ClientError> {
+        Ok(value)
+    }
+}
+
+impl FromValue for Bytes {
+    fn from_value(value: Value) -> Result<Bytes, ClientError> {
+        match value {
+            Value::Bytes(bytes) => Ok(bytes),
+            Value::Text(text) => Ok(Bytes::from(text)),
+            value => Err(unexpected("bytes", &value)),
+        }
+    }
+}
+
+impl FromValue for Vec<u8> {
+    fn from_value(value: Value) -> Result<Vec<u8>, ClientError> {
+        Bytes::from_value(value).map(Vec::from)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<String, ClientError> {
+        match value {
+            Value::Text(text) => Ok(text),
+            Value::Bytes(bytes) => {
+                String::from_utf8(bytes.into()).map_err(|err| ClientError::UnexpectedType {
+                    expected: "a string",
+                    received: format!("{} bytes of binary data", err.as_bytes().len()),
+                })
+            }
+            value => Err(unexpected("a string", &value)),
+        }
+    }
+}
+
+/// Missing keys read as none.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Option<T>, ClientError> {
+        match value {
+            Value::Nil => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+macro_rules! integer_conversions {
+    ($($int:ty),*) => {$(
+        impl ToKey for $int {
+            fn to_key(&self) -> Bytes {
+                Bytes::from(self.to_string())
+            }
+        }
+
+        impl ToValue for $int {
+            fn into_value(self) -> Bytes {
+                Bytes::from(self.to_string())
+            }
+        }
+
+        /// Reads integer replies, and values holding a decimal integer.
+        impl FromValue for $int {
+            fn from_value(value: Value) -> Result<$int, ClientError> {
+                let expected = concat!("an integer of type ", stringify!($int));
+                let parsed = match &value {
+                    Value::Int(n) => <$int>::try_from(*n).ok(),
+                    Value::Text(text) => text.parse().ok(),
+                    Value::Bytes(bytes) => std::str::from_utf8(bytes)
+                        .ok()
+                        .and_then(|text| text.parse().ok()),
+                    _ => None,
+                };
+                parsed.ok_or_else(|| unexpected(expected, &value))
+            }
+        }
+    )*};
+}
+
+integer_conversions!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+fn unexpected(expected: &'static str, received: &Value) -> ClientError {
+    let received = match received {
+        Value::Text(text) => format!("the status {:?}", text),
+        Value::Bytes(bytes) => format!("the value {:?}", String::from_utf8_lossy(bytes)),
+        Value::Int(n) => format!("the integer {}", n),
+        Value::Array(values) => format!("an array of {} elements", values.len()),
+        Value::Nil => "nil".to_string(),
+        Value::Map(pairs) => format!("a map of {} pairs", pairs.len()),
+    };
+    ClientError::UnexpectedType { expected, received }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(42i32.to_key(), "42");
+        assert_eq!("key".to_key(), "key");
+        assert_eq!(b"\xffkey"[..].to_key(), Bytes::from_static(b"\xffkey"));
+        assert_eq!((-7i64).into_value(), Bytes::from("-7"));
+        assert_eq!(vec![0u8, 1].into_value(), Bytes::from(vec![0u8, 1]));
+
+        let counter = Value::Bytes(Bytes::from("12"));
+        assert_eq!(i64::from_value(counter.clone()).unwrap(), 12);
+        assert_eq!(u8::from_value(Value::Int(255)).unwrap(), 255);
+        assert_eq!(String::from_value(counter).unwrap(), "12");
+        assert_eq!(Option::<i64>::from_value(Value::Nil).unwrap(), None);
+        let value = Bytes::from_value(Value::Bytes(Bytes::from("v"))).unwrap();
+        assert_eq!(Vec::<u8>::from_value(Value::Bytes(value)).unwrap(), b"v");
+    }
+
+    #[test]
+    fn test_unexpected_types() {
+        let err = i64::from_value(Value::Bytes(Bytes::from("abc"))).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"Expected an integer of type i64, received the value "abc"."#
+        );
+        let err = u8::from_value(Value::Int(256)).unwrap_err();
+        assert!(err.to_string().contains("the integer 256"), "{}", err);
+        let err = Bytes::from_value(Value::Nil).unwrap_err();
+        assert_eq!(err.to_string(), "Expected bytes, received nil.");
+        let err = String::from_value(Value::Bytes(Bytes::from(vec![0xff]))).unwrap_err();
+        assert!(err.to_string().contains("binary data"), "{}", err);
+    }
+}
@@ -0,0 +1,66 @@
+//! Typed values stored as JSON
+//!
+//! Values are tagged with their content type, so that reading a key holding
+//! something else than JSON fails clearly instead of decoding garbage.
+//!
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{Client, ClientError};
+
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Prepended to the serialized value. Starts with a NUL byte so that it can't
+/// be confused with text set by other clients.
+const JSON_TAG: &[u8] = b"\0application/json\0";
+
+impl Client {
+    /// Stores `value` serialized as JSON.
+    pub async fn set_json<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<()> {
+        self.set(key, encode_json(value)?).await
+    }
+
+    /// Reads a value stored by [`Client::set_json`].
+    pub async fn get_json<T: DeserializeOwned>(&mut self, key: &str) -> Result<Option<T>> {
+        match self.get::<Option<Bytes>>(key).await? {
+            Some(value) => Ok(Some(decode_json(&value)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+fn encode_json<T: Serialize + ?Sized>(value: &T) -> Result<Bytes> {
+    let mut buf = BytesMut::from(JSON_TAG);
+    serde_json::to_writer((&mut buf).writer(), value)?;
+    Ok(buf.freeze())
+}
+
+fn decode_json<T: DeserializeOwned>(value: &[u8]) -> Result<T> {
+    let json = value
+        .strip_prefix(JSON_TAG)
+        .ok_or(ClientError::WrongContentType(JSON_CONTENT_TYPE))?;
+    Ok(serde_json::from_slice(json)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_json_round_trip() {
+        let value = HashMap::from([("answer".to_string(), vec![4, 2])]);
+        let encoded = encode_json(&value).unwrap();
+        let decoded: HashMap<String, Vec<i32>> = decode_json(&encoded).unwrap();
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn test_untagged_value() {
+        let err = decode_json::<Vec<i32>>(b"[4, 2]").unwrap_err();
+        assert!(err.to_string().contains(JSON_CONTENT_TYPE));
+    }
+}
@@ -1,12 +1,68 @@
-use anyhow::{anyhow, Result};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
 use bytes::Bytes;
 use thiserror::Error;
-use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::{
+    io::AsyncRead,
+    net::{TcpStream, ToSocketAddrs},
+    sync::mpsc,
+};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
 use tracing::debug;
-use uranus_s::{Connection, Echo, Frame, Get, Put};
+use uranus_s::{
+    parse_double, with_deadline, AclCommand, Append, Auth, BLPop, BPqPop, BgRewriteAof, BitCount,
+    Cas, ClientCommand, Config, Connection, CopyCommand, DbSize, Del, DelPrefix, Dump, Echo,
+    ErrorCode, Eval, Expire, ExpireAt, Flush, Frame, Get, GetBit, GetRange, GetRev, HDel, HGet,
+    HGetAll, HSet, Hello, Info, KeepRevs, KeyTtl, Keys, LPos, ListEnd, MSet, Migrate,
+    ObjectCommand, Persist, PfAdd, PfCount, PfMerge, Ping, Pop, PqAdd, PqPeek, PqPop, Push, Put,
+    QueueEnd, Ready, Rename, Restore, SAdd, SInterCard, SIsMember, SMembers, SRem, Scan, Script,
+    Select, SetAlgebra, SetAlgebraStore, SetBit, SetCondition, SetOperation, SetRange, StrLen,
+    StreamFields, StreamId, Ttl, Unlink, Wait, XAdd, XRange, XRead, ZAdd, ZRange, ZRangeBy, ZScore,
+};
+
+pub mod pool;
+pub use pool::*;
+
+mod cluster;
+use cluster::*;
+
+mod link;
+use link::*;
+
+pub mod sharded;
+pub use sharded::*;
+
+pub mod namespace;
+pub use namespace::*;
+
+pub mod watch;
+pub use watch::*;
+
+pub mod repl;
+
+pub mod blocking;
+
+pub mod value;
+pub use value::*;
 
+pub mod convert;
+pub use convert::*;
+
+#[cfg(feature = "serde")]
+mod json;
+
+/// A connection to a server. Clones share it, and may send requests
+/// concurrently, see [`link`]: it's cheap to clone a client for every task.
+#[derive(Clone)]
 pub struct Client {
-    connection: Connection,
+    link: Link,
+    /// Latency budget attached to every command, see [`uranus_s::deadline`].
+    deadline: Option<Duration>,
+    /// Whether `WRONGTYPE` errors are reported as [`WrongType`].
+    strict_types: bool,
+    /// Where requests go in cluster mode, see [`Client::connect_cluster`].
+    cluster: Option<Routes>,
 }
 
 #[derive(Debug, Error)]
@@ -17,57 +73,1150 @@ pub enum ClientError {
     BadResponse,
     #[error("Unexpected frame")]
     UnexpectedFrame(String),
+    #[error("The value isn't tagged as {0}.")]
+    WrongContentType(&'static str),
+    #[error("Expected {expected}, received {received}.")]
+    UnexpectedType {
+        expected: &'static str,
+        received: String,
+    },
+    #[error("The server couldn't start the command within its deadline.")]
+    DeadlineExceeded,
+    #[error("The cluster redirected the request too many times.")]
+    TooManyRedirections,
+    #[error("A sharded client needs at least one server.")]
+    NoServers,
 }
 
+/// An error reply of the server. Callers can tell them apart by downcasting
+/// the error and matching on its [code](ErrorCode), rather than on its message.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{code} {message}")]
+pub struct ServerError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ServerError {
+    /// Parses an error reply, see [`ErrorCode::split`].
+    pub fn parse(reply: &str) -> ServerError {
+        let (code, message) = ErrorCode::split(reply);
+        ServerError {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// The key holds another type of value than the command works on, e.g. `GET`
+/// on a hash. A missing key is not an error, it reads as none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("Operation against a key holding the wrong kind of value.")]
+pub struct WrongType;
+
 impl Client {
     pub async fn connect<T: ToSocketAddrs>(addr: T) -> Result<Client> {
         let socket = TcpStream::connect(addr).await?;
-        let connection = Connection::new(socket);
-        Ok(Client { connection })
+        Ok(Client::with_connection(Connection::new(socket)))
+    }
+
+    /// A client speaking over `connection`, such as one to an in-process
+    /// server, see [`uranus_s::test_util`].
+    pub fn with_connection(connection: Connection) -> Client {
+        Client {
+            link: Link::start(connection),
+            deadline: None,
+            strict_types: false,
+            cluster: None,
+        }
+    }
+
+    /// Asks the server to give up on commands it can't start within `deadline`
+    /// after receiving them, failing them with [`ClientError::DeadlineExceeded`]
+    /// instead. None waits for as long as it takes, which is the default.
+    pub fn set_deadline(&mut self, deadline: Option<Duration>) {
+        self.deadline = deadline;
+    }
+
+    /// In strict mode, commands applied to a key of the wrong type fail with
+    /// [`WrongType`], which callers can tell apart by downcasting the error.
+    /// Otherwise, the default, they fail with the message of the server.
+    /// [`Client::try_get`] distinguishes them in both modes.
+    pub fn set_strict_types(&mut self, strict: bool) {
+        self.strict_types = strict;
     }
 
     /// Send an echo message to the server.
     /// returns the echoed message, don't check the correctness.
-    /// PING is implemented by echo
     pub async fn echo(&mut self, echo: impl ToString) -> Result<String> {
         let frame = Echo::new(echo).into_frame();
-        self.connection.write_frame(&frame).await?;
-        match self.read_response().await? {
+        match self.request(frame).await? {
             Frame::Text(txt) => Ok(txt),
             _ => Err(ClientError::BadResponse)?,
         }
     }
 
-    /// Reads a message from socket.
-    async fn read_response(&mut self) -> Result<Frame> {
-        let response = self.connection.read_frame().await?;
+    /// Checks that the server is alive. Returns `PONG`, or `message` if given.
+    pub async fn ping(&mut self, message: Option<Bytes>) -> Result<Bytes> {
+        let frame = Ping::new(message).into_frame();
+        match self.request(frame).await? {
+            Frame::Text(txt) => Ok(Bytes::from(txt)),
+            Frame::Binary(binary) => Ok(binary),
+            _ => Err(ClientError::BadResponse)?,
+        }
+    }
+
+    /// Checks that the server has loaded its data. Fails with
+    /// [`ErrorCode::Loading`] while it hasn't.
+    pub async fn ready(&mut self) -> Result<()> {
+        self.expect_ok(Ready::new().into_frame()).await
+    }
+
+    /// Asks the server to speak protocol `version` on this connection, see
+    /// [`uranus_s::Protocol`]. Returns the version the server agreed on.
+    pub async fn hello(&mut self, version: i64) -> Result<i64> {
+        let frame = Hello::new(Some(version)).into_frame();
+        match self.request(frame).await? {
+            Frame::Integer(version) => Ok(version),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    /// Reads a runtime parameter of the server, none if it doesn't apply.
+    pub async fn config_get(&mut self, param: &str) -> Result<Option<String>> {
+        let frame = Config::get(param).into_frame();
+        match self.request(frame).await? {
+            Frame::Array(pair) if pair.is_empty() => Ok(None),
+            Frame::Array(mut pair) if pair.len() == 2 => match pair.pop() {
+                Some(Frame::Text(value)) => Ok(Some(value)),
+                _ => Err(ClientError::BadResponse)?,
+            },
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    /// Changes a runtime parameter of the server.
+    pub async fn config_set(&mut self, param: &str, value: impl ToString) -> Result<()> {
+        let frame = Config::set(param, value).into_frame();
+        match self.request(frame).await? {
+            Frame::Text(txt) if txt == "OK" => Ok(()),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    /// Switches this connection to the logical database `index`.
+    pub async fn select(&mut self, index: usize) -> Result<()> {
+        let frame = Select::new(index).into_frame();
+        self.expect_ok(frame).await
+    }
+
+    /// Removes every key of the selected database.
+    pub async fn flushdb(&mut self) -> Result<()> {
+        self.expect_ok(Flush::new(false).into_frame()).await
+    }
+
+    /// Removes every key of all databases.
+    pub async fn flushall(&mut self) -> Result<()> {
+        self.expect_ok(Flush::new(true).into_frame()).await
+    }
+
+    /// The number of keys in the selected database.
+    pub async fn dbsize(&mut self) -> Result<usize> {
+        let frame = DbSize::new().into_frame();
+        Ok(integer(self.request(frame).await?)? as usize)
+    }
+
+    /// The statistics of the server, of `section` only if given, as
+    /// `field:value` lines. See [`Info`].
+    pub async fn info(&mut self, section: Option<&str>) -> Result<String> {
+        let info = match section {
+            Some(section) => Info::section(section),
+            None => Info::new(),
+        };
+        let text = binary(self.request(info.into_frame()).await?)?;
+        Ok(String::from_utf8_lossy(&text).into_owned())
+    }
+
+    /// The connections of the server, a line each. See [`ClientCommand`].
+    pub async fn client_list(&mut self) -> Result<String> {
+        let text = binary(self.request(ClientCommand::List.into_frame()).await?)?;
+        Ok(String::from_utf8_lossy(&text).into_owned())
+    }
+
+    pub async fn client_getname(&mut self) -> Result<Option<String>> {
+        match self.request(ClientCommand::GetName.into_frame()).await? {
+            Frame::Binary(name) => Ok(Some(String::from_utf8_lossy(&name).into_owned())),
+            Frame::Null => Ok(None),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    pub async fn client_setname(&mut self, name: &str) -> Result<()> {
+        let frame = ClientCommand::SetName(name.to_string()).into_frame();
+        self.expect_ok(frame).await
+    }
+
+    /// Closes the connection `id` of the server.
+    pub async fn client_kill(&mut self, id: u64) -> Result<()> {
+        self.expect_ok(ClientCommand::Kill(id).into_frame()).await
+    }
+
+    /// Acts as `username`, the default user if none, on this connection from
+    /// now on. See [`uranus_s::acl`].
+    pub async fn auth(&mut self, username: Option<&str>, password: &str) -> Result<()> {
+        let frame = Auth::new(username.map(str::to_string), password).into_frame();
+        self.expect_ok(frame).await
+    }
+
+    /// Applies `rules` to the user `username` of the server, creating it if
+    /// needed.
+    pub async fn acl_setuser(&mut self, username: &str, rules: &[&str]) -> Result<()> {
+        let rules = rules.iter().map(|rule| rule.to_string()).collect();
+        let frame = AclCommand::SetUser(username.to_string(), rules).into_frame();
+        self.expect_ok(frame).await
+    }
+
+    /// Removes users of the server, returns how many existed.
+    pub async fn acl_deluser(&mut self, usernames: &[&str]) -> Result<i64> {
+        let usernames = usernames.iter().map(|name| name.to_string()).collect();
+        integer(
+            self.request(AclCommand::DelUser(usernames).into_frame())
+                .await?,
+        )
+    }
+
+    /// The user this connection acts as.
+    pub async fn acl_whoami(&mut self) -> Result<String> {
+        let user = binary(self.request(AclCommand::WhoAmI.into_frame()).await?)?;
+        Ok(String::from_utf8_lossy(&user).into_owned())
+    }
+
+    /// The keys matching the glob `pattern`, in order.
+    pub async fn keys(&mut self, pattern: &str) -> Result<Vec<Bytes>> {
+        let frame = Keys::new(pattern).into_frame();
+        let Frame::Array(frames) = self.request(frame).await? else {
+            Err(ClientError::BadResponse)?
+        };
+        frames.into_iter().map(binary).collect()
+    }
+
+    /// Runs one step of a `SCAN` iteration. Returns the cursor of the next
+    /// step, 0 once done, and the keys of this one.
+    pub async fn scan(&mut self, scan: Scan) -> Result<(u64, Vec<Bytes>)> {
+        let Frame::Array(frames) = self.request(scan.into_frame()).await? else {
+            Err(ClientError::BadResponse)?
+        };
+        let mut frames = frames.into_iter();
+        let Some(Frame::Text(cursor)) = frames.next() else {
+            Err(ClientError::BadResponse)?
+        };
+        let keys = frames.map(binary).collect::<Result<_>>()?;
+        Ok((cursor.parse()?, keys))
+    }
+
+    async fn expect_ok(&mut self, frame: Frame) -> Result<()> {
+        match self.request(frame).await? {
+            Frame::Text(txt) if txt == "OK" => Ok(()),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    /// Sets fields of the hash at `key`, returns how many of them are new.
+    pub async fn hset<F, V>(
+        &mut self,
+        key: &str,
+        pairs: impl IntoIterator<Item = (F, V)>,
+    ) -> Result<i64>
+    where
+        F: Into<Bytes>,
+        V: Into<Bytes>,
+    {
+        let pairs = pairs
+            .into_iter()
+            .map(|(field, value)| (field.into(), value.into()))
+            .collect();
+        let frame = HSet::new(key, pairs).into_frame();
+        integer(self.request(frame).await?)
+    }
+
+    pub async fn hget(&mut self, key: &str, field: impl Into<Bytes>) -> Result<Option<Bytes>> {
+        let frame = HGet::new(key, field.into()).into_frame();
+        match self.request(frame).await? {
+            Frame::Binary(binary) => Ok(Some(binary)),
+            Frame::Null => Ok(None),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    /// Removes fields of the hash at `key`, returns how many existed.
+    pub async fn hdel<F: Into<Bytes>>(
+        &mut self,
+        key: &str,
+        fields: impl IntoIterator<Item = F>,
+    ) -> Result<i64> {
+        let fields = fields.into_iter().map(Into::into).collect();
+        let frame = HDel::new(key, fields).into_frame();
+        integer(self.request(frame).await?)
+    }
+
+    /// Returns every field of the hash at `key` with its value.
+    pub async fn hgetall(&mut self, key: &str) -> Result<Vec<(Bytes, Bytes)>> {
+        let frame = HGetAll::new(key).into_frame();
+        let Frame::Array(frames) = self.request(frame).await? else {
+            Err(ClientError::BadResponse)?
+        };
+        let mut frames = frames.into_iter();
+        let mut pairs = vec![];
+        while let Some(field) = frames.next() {
+            match (field, frames.next()) {
+                (Frame::Binary(field), Some(Frame::Binary(value))) => pairs.push((field, value)),
+                _ => Err(ClientError::BadResponse)?,
+            }
+        }
+        Ok(pairs)
+    }
+
+    /// Pushes items on the priority queue at `key`, returns the length of the queue.
+    pub async fn pqadd<I: Into<Bytes>>(
+        &mut self,
+        key: &str,
+        items: impl IntoIterator<Item = (f64, I)>,
+    ) -> Result<i64> {
+        let items = items
+            .into_iter()
+            .map(|(priority, item)| (priority, item.into()))
+            .collect();
+        let frame = PqAdd::new(key, items).into_frame();
+        integer(self.request(frame).await?)
+    }
+
+    /// Takes up to `count` items from an end of the priority queue at `key`,
+    /// with their priorities.
+    pub async fn pqpop(
+        &mut self,
+        key: &str,
+        end: QueueEnd,
+        count: usize,
+    ) -> Result<Vec<(Bytes, f64)>> {
+        let frame = PqPop::new(key, end, count).into_frame();
+        let Frame::Array(frames) = self.request(frame).await? else {
+            Err(ClientError::BadResponse)?
+        };
+        prioritized(frames)
+    }
+
+    /// Takes an item from an end of the priority queue at `key`, waiting up to
+    /// `timeout` for one, or forever without a timeout.
+    pub async fn bpqpop(
+        &mut self,
+        key: &str,
+        end: QueueEnd,
+        timeout: Option<Duration>,
+    ) -> Result<Option<(Bytes, f64)>> {
+        let frame = BPqPop::new(key, end, timeout).into_frame();
+        match self.request(frame).await? {
+            Frame::Array(frames) => Ok(prioritized(frames)?.pop()),
+            Frame::Null => Ok(None),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    /// Returns the item at an end of the priority queue at `key` without taking it.
+    pub async fn pqpeek(&mut self, key: &str, end: QueueEnd) -> Result<Option<(Bytes, f64)>> {
+        let frame = PqPeek::new(key, end).into_frame();
+        match self.request(frame).await? {
+            Frame::Array(frames) => Ok(prioritized(frames)?.pop()),
+            Frame::Null => Ok(None),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    /// Pushes elements at an end of the list at `key`, returns the length of the list.
+    pub async fn push<E: Into<Bytes>>(
+        &mut self,
+        key: &str,
+        end: ListEnd,
+        elements: impl IntoIterator<Item = E>,
+    ) -> Result<i64> {
+        let elements = elements.into_iter().map(Into::into).collect();
+        let frame = Push::new(key, end, elements).into_frame();
+        integer(self.request(frame).await?)
+    }
+
+    /// Takes the element at an end of the list at `key`.
+    pub async fn pop(&mut self, key: &str, end: ListEnd) -> Result<Option<Bytes>> {
+        let frame = Pop::new(key, end).into_frame();
+        match self.request(frame).await? {
+            Frame::Binary(element) => Ok(Some(element)),
+            Frame::Null => Ok(None),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    /// Takes the first element of the list at `key`, waiting up to `timeout`
+    /// for one, or forever without a timeout.
+    pub async fn blpop(&mut self, key: &str, timeout: Option<Duration>) -> Result<Option<Bytes>> {
+        let frame = BLPop::new(key, timeout).into_frame();
+        match self.request(frame).await? {
+            Frame::Array(mut pair) if pair.len() == 2 => match pair.pop() {
+                Some(Frame::Binary(element)) => Ok(Some(element)),
+                _ => Err(ClientError::BadResponse)?,
+            },
+            Frame::Null => Ok(None),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    /// Keeps the last `depth` values set to `key`, or none with a depth of 0.
+    pub async fn keep_revisions(&mut self, key: &str, depth: usize) -> Result<()> {
+        let frame = KeepRevs::new(key, depth).into_frame();
+        match self.request(frame).await? {
+            Frame::Text(txt) if txt == "OK" => Ok(()),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    /// Returns the value set to `key` `back` writes ago, 0 being the latest, and
+    /// when it was written.
+    pub async fn get_revision(
+        &mut self,
+        key: &str,
+        back: usize,
+    ) -> Result<Option<(Bytes, SystemTime)>> {
+        let frame = GetRev::new(key, back).into_frame();
+        match self.request(frame).await? {
+            Frame::Array(pair) => match <[Frame; 2]>::try_from(pair) {
+                Ok([Frame::Binary(value), Frame::Integer(millis)]) => {
+                    let written_at = UNIX_EPOCH + Duration::from_millis(millis as u64);
+                    Ok(Some((value, written_at)))
+                }
+                _ => Err(ClientError::BadResponse)?,
+            },
+            Frame::Null => Ok(None),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    /// Adds members to the set at `key`, returns how many of them are new.
+    pub async fn sadd<M: Into<Bytes>>(
+        &mut self,
+        key: &str,
+        members: impl IntoIterator<Item = M>,
+    ) -> Result<i64> {
+        let members = members.into_iter().map(Into::into).collect();
+        let frame = SAdd::new(key, members).into_frame();
+        integer(self.request(frame).await?)
+    }
+
+    /// Removes members of the set at `key`, returns how many of them existed.
+    pub async fn srem<M: Into<Bytes>>(
+        &mut self,
+        key: &str,
+        members: impl IntoIterator<Item = M>,
+    ) -> Result<i64> {
+        let members = members.into_iter().map(Into::into).collect();
+        let frame = SRem::new(key, members).into_frame();
+        integer(self.request(frame).await?)
+    }
+
+    /// Returns every member of the set at `key`, in no particular order.
+    pub async fn smembers(&mut self, key: &str) -> Result<Vec<Bytes>> {
+        let frame = SMembers::new(key).into_frame();
+        let Frame::Array(frames) = self.request(frame).await? else {
+            Err(ClientError::BadResponse)?
+        };
+        frames
+            .into_iter()
+            .map(|frame| match frame {
+                Frame::Binary(member) => Ok(member),
+                _ => Err(ClientError::BadResponse)?,
+            })
+            .collect()
+    }
+
+    pub async fn sismember(&mut self, key: &str, member: impl Into<Bytes>) -> Result<bool> {
+        let frame = SIsMember::new(key, member.into()).into_frame();
+        Ok(integer(self.request(frame).await?)? == 1)
+    }
+
+    /// The members of every set at `keys`.
+    pub async fn sinter(&mut self, keys: &[&str]) -> Result<Vec<Bytes>> {
+        self.set_algebra(SetOperation::Inter, keys).await
+    }
+
+    /// The members of any set at `keys`.
+    pub async fn sunion(&mut self, keys: &[&str]) -> Result<Vec<Bytes>> {
+        self.set_algebra(SetOperation::Union, keys).await
+    }
+
+    /// The members of the set at the first of `keys` in none of the others.
+    pub async fn sdiff(&mut self, keys: &[&str]) -> Result<Vec<Bytes>> {
+        self.set_algebra(SetOperation::Diff, keys).await
+    }
+
+    async fn set_algebra(&mut self, op: SetOperation, keys: &[&str]) -> Result<Vec<Bytes>> {
+        let keys = keys
+            .iter()
+            .map(|key| Bytes::copy_from_slice(key.as_bytes()))
+            .collect();
+        let frame = SetAlgebra::new(op, keys).into_frame();
+        let Frame::Array(frames) = self.request(frame).await? else {
+            Err(ClientError::BadResponse)?
+        };
+        frames.into_iter().map(binary).collect()
+    }
+
+    /// Stores the members of every set at `keys` as the set at `dst`, returns
+    /// how many there are.
+    pub async fn sinterstore(&mut self, dst: &str, keys: &[&str]) -> Result<i64> {
+        self.set_algebra_store(SetOperation::Inter, dst, keys).await
+    }
+
+    /// Stores the members of any set at `keys` as the set at `dst`, returns
+    /// how many there are.
+    pub async fn sunionstore(&mut self, dst: &str, keys: &[&str]) -> Result<i64> {
+        self.set_algebra_store(SetOperation::Union, dst, keys).await
+    }
+
+    /// Stores the members of the first set at `keys` in none of the others as
+    /// the set at `dst`, returns how many there are.
+    pub async fn sdiffstore(&mut self, dst: &str, keys: &[&str]) -> Result<i64> {
+        self.set_algebra_store(SetOperation::Diff, dst, keys).await
+    }
+
+    async fn set_algebra_store(
+        &mut self,
+        op: SetOperation,
+        dst: &str,
+        keys: &[&str],
+    ) -> Result<i64> {
+        let keys = keys
+            .iter()
+            .map(|key| Bytes::copy_from_slice(key.as_bytes()))
+            .collect();
+        integer(
+            self.request(SetAlgebraStore::new(op, dst, keys).into_frame())
+                .await?,
+        )
+    }
+
+    /// The number of members of every set at `keys`, counting up to `limit`
+    /// if given.
+    pub async fn sintercard(&mut self, keys: &[&str], limit: Option<usize>) -> Result<i64> {
+        let keys = keys
+            .iter()
+            .map(|key| Bytes::copy_from_slice(key.as_bytes()))
+            .collect();
+        integer(
+            self.request(SInterCard::new(keys, limit).into_frame())
+                .await?,
+        )
+    }
+
+    /// Sets the score of members of the sorted set at `key`, returns how many of
+    /// them are new.
+    pub async fn zadd<M: Into<Bytes>>(
+        &mut self,
+        key: &str,
+        members: impl IntoIterator<Item = (f64, M)>,
+    ) -> Result<i64> {
+        let members = members
+            .into_iter()
+            .map(|(score, member)| (score, member.into()))
+            .collect();
+        let frame = ZAdd::new(key, members).into_frame();
+        integer(self.request(frame).await?)
+    }
+
+    pub async fn zscore(&mut self, key: &str, member: impl Into<Bytes>) -> Result<Option<f64>> {
+        let frame = ZScore::new(key, member.into()).into_frame();
+        match self.request(frame).await? {
+            Frame::Null => Ok(None),
+            frame => Ok(Some(double(frame)?)),
+        }
+    }
+
+    /// Returns members of the sorted set at `key` in score order, with their scores.
+    pub async fn zrange(&mut self, key: &str, by: ZRangeBy) -> Result<Vec<(Bytes, f64)>> {
+        let frame = ZRange::new(key, by).with_scores().into_frame();
+        let Frame::Array(frames) = self.request(frame).await? else {
+            Err(ClientError::BadResponse)?
+        };
+        prioritized(frames)
+    }
+
+    /// Runs the Lua `script` with `keys` in `KEYS` and `args` in `ARGV`, and
+    /// returns what it returned. The server caches it for [`Client::evalsha`].
+    pub async fn eval<A: Into<Bytes>>(
+        &mut self,
+        script: &str,
+        keys: &[&str],
+        args: impl IntoIterator<Item = A>,
+    ) -> Result<Frame> {
+        let script = Script::Source(script.to_string());
+        self.request(eval_frame(script, keys, args)).await
+    }
+
+    /// Like [`Client::eval`], for the script of SHA1 `digest` the server
+    /// cached, see [`Scripts::digest`](uranus_s::Scripts::digest).
+    pub async fn evalsha<A: Into<Bytes>>(
+        &mut self,
+        digest: &str,
+        keys: &[&str],
+        args: impl IntoIterator<Item = A>,
+    ) -> Result<Frame> {
+        let script = Script::Digest(digest.to_string());
+        self.request(eval_frame(script, keys, args)).await
+    }
+
+    /// Sends an arbitrary command, made of its name and arguments, and returns
+    /// the raw reply. See [`Client::command`] for a decoded one.
+    pub async fn raw_command(&mut self, args: Vec<Bytes>) -> Result<Frame> {
+        let frame = Frame::Array(args.into_iter().map(Frame::Binary).collect());
+        self.request(frame).await
+    }
+
+    /// Sends a request and reads its response.
+    async fn request(&mut self, frame: Frame) -> Result<Frame> {
+        let slot = self.cluster.as_ref().and_then(|_| request_slot(&frame));
+        let frame = match self.deadline {
+            Some(deadline) => with_deadline(deadline, frame),
+            None => frame,
+        };
+        debug!(request = ?frame);
+        if self.cluster.is_some() {
+            return self.request_routed(frame, slot).await;
+        }
+        let response = self.link.request(frame).await?;
+        self.check_response(response)
+    }
+
+    /// Turns error replies into errors.
+    fn check_response(&self, response: Frame) -> Result<Frame> {
         debug!(?response);
         match response {
-            Some(Frame::Error(err)) => Err(anyhow!(err)),
-            Some(frame) => Ok(frame),
-            None => Err(ClientError::ConnectionReset)?,
+            Frame::Error(reply) => {
+                let err = ServerError::parse(&reply);
+                match err.code {
+                    ErrorCode::Deadline => Err(ClientError::DeadlineExceeded)?,
+                    ErrorCode::WrongType if self.strict_types => Err(WrongType)?,
+                    _ => Err(err)?,
+                }
+            }
+            frame => Ok(frame),
         }
     }
 
-    pub async fn get(&mut self, key: &str) -> Result<Option<Bytes>> {
+    /// Reads the value of `key` as a `V`, see [`convert`]: as an
+    /// `Option<Bytes>`, or an `Option<i64>` for a counter, none if the key is
+    /// missing.
+    pub async fn get<V: FromValue>(&mut self, key: impl ToKey) -> Result<V> {
+        let frame = Get::new(key.to_key()).into_frame();
+        let value = Value::try_from(self.request(frame).await?)?;
+        Ok(V::from_value(value)?)
+    }
+
+    /// Like [`Client::get`], but yields the value in chunks as they arrive
+    /// instead of buffering it whole, so that values too large for memory can
+    /// be read. The requests sent on the connection after it, by clones too,
+    /// wait until the stream is read to the end or dropped. It isn't routed in
+    /// cluster mode.
+    pub async fn get_stream(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<impl Stream<Item = Result<Bytes>>>> {
         let frame = Get::new(key).into_frame();
-        debug!(request = ?frame);
-        self.connection.write_frame(&frame).await?;
-        match self.read_response().await? {
-            Frame::Text(txt) => Ok(Some(txt.into())),
-            Frame::Binary(binary) => Ok(Some(binary)),
+        let frame = match self.deadline {
+            Some(deadline) => with_deadline(deadline, frame),
+            None => frame,
+        };
+        let chunks = match self.link.stream(frame).await? {
+            Streamed::Bulk(chunks) => chunks,
+            Streamed::Frame(frame) => {
+                let value = match self.check_response(frame)? {
+                    Frame::Null => return Ok(None),
+                    Frame::Text(txt) => Bytes::from(txt),
+                    frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+                };
+                let (sender, chunks) = mpsc::channel(1);
+                sender.try_send(Ok(value))?;
+                chunks
+            }
+        };
+        Ok(Some(ReceiverStream::new(chunks)))
+    }
+
+    /// Like [`Client::get`], but the caller has to handle a key of the wrong
+    /// type apart from the other errors: the outer result fails on connection
+    /// and protocol errors only, the inner one on [`WrongType`].
+    pub async fn try_get(&mut self, key: &str) -> Result<Result<Option<Bytes>, WrongType>> {
+        match self.get(key).await {
+            Ok(value) => Ok(Ok(value)),
+            Err(err) if is_wrong_type(&err) => Ok(Err(WrongType)),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Sets `key` to `value`, see [`convert`].
+    pub async fn set(&mut self, key: impl ToKey, value: impl ToValue) -> Result<()> {
+        self.put(Put::new(key.to_key(), value.into_value())).await
+    }
+
+    /// Sets every key to its value at once.
+    pub async fn mset(&mut self, pairs: &[(&str, &[u8])]) -> Result<()> {
+        let pairs = pairs
+            .iter()
+            .map(|(key, value)| {
+                (
+                    Bytes::copy_from_slice(key.as_bytes()),
+                    Bytes::copy_from_slice(value),
+                )
+            })
+            .collect();
+        self.expect_ok(MSet::new(pairs).into_frame()).await
+    }
+
+    /// Like [`Client::set`], but the value is the `len` bytes read from
+    /// `reader`, sent as they are read rather than held whole in memory. The
+    /// requests sent on the connection after it, by clones too, wait until
+    /// it's sent. Fails if `reader` ends short, closing the connection since
+    /// part of the value went out already. It isn't routed in cluster mode.
+    pub async fn set_from_reader(
+        &mut self,
+        key: &str,
+        reader: impl AsyncRead + Send + Unpin + 'static,
+        len: usize,
+    ) -> Result<()> {
+        let frame = Put::new(key, Bytes::new()).into_frame();
+        let frame = match self.deadline {
+            Some(deadline) => with_deadline(deadline, frame),
+            None => frame,
+        };
+        let Frame::Array(entries) = frame else {
+            unreachable!("commands are arrays");
+        };
+        // The value goes last.
+        let at = entries.len() - 1;
+        let response = self.link.upload(entries, at, len, Box::new(reader)).await?;
+        match self.check_response(response)? {
+            Frame::Text(txt) if txt == "OK" => Ok(()),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    /// Like [`Client::set`], but applied at most once however many times it is sent
+    /// with the same `id`, so it can be retried safely after a timeout.
+    pub async fn set_with_id(
+        &mut self,
+        key: &str,
+        value: impl Into<Bytes>,
+        id: &str,
+    ) -> Result<()> {
+        self.put(Put::new(key, value.into()).with_id(id)).await
+    }
+
+    /// Appends `value` to the string at `key`, returns the length of the string.
+    pub async fn append(&mut self, key: &str, value: impl Into<Bytes>) -> Result<i64> {
+        let frame = Append::new(key, value.into()).into_frame();
+        integer(self.request(frame).await?)
+    }
+
+    pub async fn strlen(&mut self, key: &str) -> Result<i64> {
+        let frame = StrLen::new(key).into_frame();
+        integer(self.request(frame).await?)
+    }
+
+    /// Returns the bytes of the string at `key` from offset `start` to `end`
+    /// included, negative offsets counting from the end.
+    pub async fn getrange(&mut self, key: &str, start: i64, end: i64) -> Result<Bytes> {
+        let frame = GetRange::new(key, start, end).into_frame();
+        match self.request(frame).await? {
+            Frame::Binary(binary) => Ok(binary),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    /// Overwrites the string at `key` with `value` from `offset` on, returns
+    /// its length afterwards.
+    pub async fn setrange(
+        &mut self,
+        key: &str,
+        offset: usize,
+        value: impl Into<Bytes>,
+    ) -> Result<i64> {
+        let frame = SetRange::new(key, offset, value.into()).into_frame();
+        integer(self.request(frame).await?)
+    }
+
+    /// Sets the bit at `offset` of the string at `key`, returns its previous
+    /// value.
+    pub async fn setbit(&mut self, key: &str, offset: u64, bit: bool) -> Result<bool> {
+        let frame = SetBit::new(key, offset, bit).into_frame();
+        Ok(integer(self.request(frame).await?)? == 1)
+    }
+
+    pub async fn getbit(&mut self, key: &str, offset: u64) -> Result<bool> {
+        let frame = GetBit::new(key, offset).into_frame();
+        Ok(integer(self.request(frame).await?)? == 1)
+    }
+
+    /// The number of bits set in the string at `key`, see [`BitCount`].
+    pub async fn bitcount(&mut self, bitcount: BitCount) -> Result<i64> {
+        integer(self.request(bitcount.into_frame()).await?)
+    }
+
+    /// The indexes of an element in a list, see [`LPos`]. A search without
+    /// `COUNT` finds one at most.
+    pub async fn lpos(&mut self, lpos: LPos) -> Result<Vec<i64>> {
+        match self.request(lpos.into_frame()).await? {
+            Frame::Integer(index) => Ok(vec![index]),
+            Frame::Null => Ok(vec![]),
+            Frame::Array(frames) => frames.into_iter().map(integer).collect(),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    /// Counts elements in the HyperLogLog at `key`, returns whether its
+    /// estimate may have changed.
+    pub async fn pfadd<E: Into<Bytes>>(
+        &mut self,
+        key: &str,
+        elements: impl IntoIterator<Item = E>,
+    ) -> Result<bool> {
+        let elements = elements.into_iter().map(Into::into).collect();
+        let frame = PfAdd::new(key, elements).into_frame();
+        Ok(integer(self.request(frame).await?)? == 1)
+    }
+
+    /// The estimated number of distinct elements in the HyperLogLogs at `keys`.
+    pub async fn pfcount(&mut self, keys: &[&str]) -> Result<i64> {
+        let keys = keys
+            .iter()
+            .map(|key| Bytes::copy_from_slice(key.as_bytes()))
+            .collect();
+        integer(self.request(PfCount::new(keys).into_frame()).await?)
+    }
+
+    /// Merges the HyperLogLogs at `sources` into the one at `dst`.
+    pub async fn pfmerge(&mut self, dst: &str, sources: &[&str]) -> Result<()> {
+        let sources = sources
+            .iter()
+            .map(|source| Bytes::copy_from_slice(source.as_bytes()))
+            .collect();
+        let frame = PfMerge::new(dst, sources).into_frame();
+        self.expect_ok(frame).await
+    }
+
+    /// Appends an entry to the stream at `key`, see [`XAdd`]. Returns the ID
+    /// of the entry.
+    pub async fn xadd(&mut self, xadd: XAdd) -> Result<StreamId> {
+        let id = binary(self.request(xadd.into_frame()).await?)?;
+        Ok(std::str::from_utf8(&id)?.parse()?)
+    }
+
+    /// Entries of the stream at a key, see [`XRange`].
+    pub async fn xrange(&mut self, xrange: XRange) -> Result<Vec<(StreamId, StreamFields)>> {
+        let Frame::Array(frames) = self.request(xrange.into_frame()).await? else {
+            Err(ClientError::BadResponse)?
+        };
+        let mut frames = frames.into_iter().peekable();
+        let mut entries = vec![];
+        while frames.peek().is_some() {
+            entries.push(stream_entry(&mut frames)?);
+        }
+        Ok(entries)
+    }
+
+    /// Entries of streams after given IDs, each with the key of its stream, see
+    /// [`XRead`]. Empty if there are none, once the timeout is over when
+    /// blocking.
+    pub async fn xread(&mut self, xread: XRead) -> Result<Vec<(Bytes, StreamId, StreamFields)>> {
+        let frames = match self.request(xread.into_frame()).await? {
+            Frame::Array(frames) => frames,
+            Frame::Null => return Ok(vec![]),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        };
+        let mut frames = frames.into_iter();
+        let mut entries = vec![];
+        while let Some(key) = frames.next() {
+            let (id, fields) = stream_entry(&mut frames)?;
+            entries.push((binary(key)?, id, fields));
+        }
+        Ok(entries)
+    }
+
+    /// Sets `key` to `value` only if it is currently `expected`, returns whether
+    /// it was set.
+    pub async fn cas(
+        &mut self,
+        key: &str,
+        expected: impl Into<Bytes>,
+        value: impl Into<Bytes>,
+    ) -> Result<bool> {
+        let frame = Cas::new(key, expected.into(), value.into()).into_frame();
+        Ok(integer(self.request(frame).await?)? == 1)
+    }
+
+    /// Like [`Client::set`], but the key expires after `ttl`.
+    pub async fn set_with_ttl(
+        &mut self,
+        key: &str,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) -> Result<()> {
+        self.put(Put::new(key, value.into()).with_ttl(ttl)).await
+    }
+
+    /// Makes `key` expire after `ttl`, to the millisecond. Returns whether
+    /// there is such a key.
+    pub async fn expire(&mut self, key: &str, ttl: Duration) -> Result<bool> {
+        let frame = Expire::new(key, ttl).into_frame();
+        Ok(integer(self.request(frame).await?)? == 1)
+    }
+
+    /// Makes `key` expire at `at`, to the millisecond, or deletes it if `at`
+    /// is past. Returns whether there is such a key.
+    pub async fn expire_at(&mut self, key: &str, at: SystemTime) -> Result<bool> {
+        let frame = ExpireAt::new(key, at).into_frame();
+        Ok(integer(self.request(frame).await?)? == 1)
+    }
+
+    /// How long `key` has left to live, to the millisecond.
+    pub async fn ttl(&mut self, key: &str) -> Result<KeyTtl> {
+        let frame = Ttl::new(key).into_frame();
+        match integer(self.request(frame).await?)? {
+            -2 => Ok(KeyTtl::Missing),
+            -1 => Ok(KeyTtl::Persistent),
+            millis => Ok(KeyTtl::Remaining(Duration::from_millis(millis as u64))),
+        }
+    }
+
+    /// How often `key` is used, as the logarithmic counter `allkeys-lfu`
+    /// evicts by, none if there is no such key. Fails under other eviction
+    /// policies.
+    pub async fn object_freq(&mut self, key: &str) -> Result<Option<u8>> {
+        match self
+            .request(ObjectCommand::Freq(Bytes::copy_from_slice(key.as_bytes())).into_frame())
+            .await?
+        {
+            Frame::Null => Ok(None),
+            frame => Ok(Some(integer(frame)? as u8)),
+        }
+    }
+
+    /// Moves the value of `src` to `dst`, overwriting it. Fails if there is no
+    /// `src`.
+    pub async fn rename(&mut self, src: &str, dst: &str) -> Result<()> {
+        self.expect_ok(Rename::new(src, dst).into_frame()).await
+    }
+
+    /// Copies the value of `src` to `dst`, overwriting it only if `replace`.
+    /// Returns whether it was copied.
+    pub async fn copy(&mut self, src: &str, dst: &str, replace: bool) -> Result<bool> {
+        let mut copy = CopyCommand::new(src, dst);
+        if replace {
+            copy = copy.replacing();
+        }
+        Ok(integer(self.request(copy.into_frame()).await?)? == 1)
+    }
+
+    /// The value of `key` serialized, to be recreated by [`Client::restore`],
+    /// none if there is no such key.
+    pub async fn dump(&mut self, key: &str) -> Result<Option<Bytes>> {
+        match self.request(Dump::new(key).into_frame()).await? {
+            Frame::Binary(blob) => Ok(Some(blob)),
             Frame::Null => Ok(None),
             frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
         }
     }
 
-    pub async fn set(&mut self, key: &str, value: impl Into<Bytes>) -> Result<()> {
-        let frame = Put::new(key.to_owned(), value.into()).into_frame();
-        debug!(request = ?frame);
-        self.connection.write_frame(&frame).await?;
-        match self.read_response().await? {
+    /// Recreates `key` from a [`Client::dump`] of its value, expiring after
+    /// `ttl` if given. Fails if `key` exists, unless `replace`.
+    pub async fn restore(
+        &mut self,
+        key: &str,
+        ttl: Option<Duration>,
+        blob: Bytes,
+        replace: bool,
+    ) -> Result<()> {
+        let mut restore = Restore::new(key, ttl, blob);
+        if replace {
+            restore = restore.replacing();
+        }
+        self.expect_ok(restore.into_frame()).await
+    }
+
+    /// Moves `key` to the server at `host:port`, which may take at most
+    /// `timeout`. Returns false if there is no such key.
+    pub async fn migrate(
+        &mut self,
+        host: &str,
+        port: u16,
+        key: &str,
+        timeout: Option<Duration>,
+    ) -> Result<bool> {
+        let frame = Migrate::new(host, port, key, timeout).into_frame();
+        match self.request(frame).await? {
+            Frame::Text(reply) if reply == "OK" => Ok(true),
+            Frame::Text(reply) if reply == "NOKEY" => Ok(false),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    /// Removes `keys`, returns how many existed.
+    pub async fn del(&mut self, keys: &[&str]) -> Result<i64> {
+        let keys = keys
+            .iter()
+            .map(|key| Bytes::copy_from_slice(key.as_bytes()))
+            .collect();
+        integer(self.request(Del::new(keys).into_frame()).await?)
+    }
+
+    /// Deletes the keys as [`Client::del`] does, their values being freed in
+    /// the background if large. Returns how many existed.
+    pub async fn unlink(&mut self, keys: &[&str]) -> Result<i64> {
+        let keys = keys
+            .iter()
+            .map(|key| Bytes::copy_from_slice(key.as_bytes()))
+            .collect();
+        integer(self.request(Unlink::new(keys).into_frame()).await?)
+    }
+
+    /// Deletes every key starting with `prefix`, returns how many existed. It
+    /// isn't routed in cluster mode.
+    pub async fn del_prefix(&mut self, prefix: &str) -> Result<i64> {
+        integer(self.request(DelPrefix::new(prefix).into_frame()).await?)
+    }
+
+    /// Starts compacting the append-only file of the server in the background.
+    /// Fails if it is disabled, or being compacted already.
+    pub async fn bgrewriteaof(&mut self) -> Result<()> {
+        match self.request(BgRewriteAof::new().into_frame()).await? {
+            Frame::Text(_) => Ok(()),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    /// Waits until `replicas` replicas of the server applied the writes sent
+    /// so far, for at most `timeout`, or indefinitely without one. Returns how
+    /// many did.
+    pub async fn wait(&mut self, replicas: usize, timeout: Option<Duration>) -> Result<i64> {
+        integer(
+            self.request(Wait::new(replicas, timeout).into_frame())
+                .await?,
+        )
+    }
+
+    /// Removes the TTL of `key`, returns whether it had one.
+    pub async fn persist(&mut self, key: &str) -> Result<bool> {
+        let frame = Persist::new(key).into_frame();
+        Ok(integer(self.request(frame).await?)? == 1)
+    }
+
+    /// Sets `key` only if it has no value, expiring after `ttl` if given.
+    /// Returns whether it was set, so a lock is taken by whoever gets `true`.
+    pub async fn set_nx(
+        &mut self,
+        key: &str,
+        value: impl Into<Bytes>,
+        ttl: Option<Duration>,
+    ) -> Result<bool> {
+        let mut put = Put::new(key, value.into()).with_condition(SetCondition::Absent);
+        if let Some(ttl) = ttl {
+            put = put.with_ttl(ttl);
+        }
+        self.put_if(put).await
+    }
+
+    /// Sets `key` only if it already has a value, returns whether it was set.
+    pub async fn set_xx(&mut self, key: &str, value: impl Into<Bytes>) -> Result<bool> {
+        let put = Put::new(key, value.into()).with_condition(SetCondition::Present);
+        self.put_if(put).await
+    }
+
+    async fn put_if(&mut self, put: Put) -> Result<bool> {
+        match self.request(put.into_frame()).await? {
+            Frame::Text(txt) if txt == "OK" => Ok(true),
+            Frame::Null => Ok(false),
+            frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        }
+    }
+
+    async fn put(&mut self, put: Put) -> Result<()> {
+        let frame = put.into_frame();
+        match self.request(frame).await? {
             Frame::Text(txt) if txt == "OK" => Ok(()),
             frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
         }
     }
 }
+
+fn eval_frame<A: Into<Bytes>>(
+    script: Script,
+    keys: &[&str],
+    args: impl IntoIterator<Item = A>,
+) -> Frame {
+    let keys = keys
+        .iter()
+        .map(|key| Bytes::copy_from_slice(key.as_bytes()))
+        .collect();
+    let args = args.into_iter().map(Into::into).collect();
+    Eval::new(script, keys, args).into_frame()
+}
+
+fn integer(frame: Frame) -> Result<i64> {
+    match frame {
+        Frame::Integer(val) => Ok(val),
+        frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+    }
+}
+
+fn binary(frame: Frame) -> Result<Bytes> {
+    match frame {
+        Frame::Binary(binary) => Ok(binary),
+        _ => Err(ClientError::BadResponse)?,
+    }
+}
+
+/// Decodes a flat array of items each followed by its priority or score.
+fn prioritized(frames: Vec<Frame>) -> Result<Vec<(Bytes, f64)>> {
+    let mut frames = frames.into_iter();
+    let mut items = vec![];
+    while let Some(item) = frames.next() {
+        match (item, frames.next()) {
+            (Frame::Binary(item), Some(priority)) => items.push((item, double(priority)?)),
+            _ => Err(ClientError::BadResponse)?,
+        }
+    }
+    Ok(items)
+}
+
+/// Decodes the stream entry at the front of `frames`: its ID, the number of
+/// its fields, then each field followed by its value.
+fn stream_entry(frames: &mut impl Iterator<Item = Frame>) -> Result<(StreamId, StreamFields)> {
+    let mut next = || frames.next().ok_or(ClientError::BadResponse);
+    let id = binary(next()?)?;
+    let id = std::str::from_utf8(&id)?.parse()?;
+    let mut fields = vec![];
+    for _ in 0..integer(next()?)? {
+        fields.push((binary(next()?)?, binary(next()?)?));
+    }
+    Ok((id, fields))
+}
+
+/// Reads a double, sent as text to clients speaking protocol version 2.
+fn double(frame: Frame) -> Result<f64> {
+    match frame {
+        Frame::Double(val) => Ok(val),
+        Frame::Text(txt) => parse_double(&txt),
+        Frame::Binary(binary) => parse_double(std::str::from_utf8(&binary)?),
+        frame => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+    }
+}
+
+/// Whether `err` is the server refusing a key of the wrong type, whatever the
+/// mode of the client.
+fn is_wrong_type(err: &anyhow::Error) -> bool {
+    let server_error = err.downcast_ref::<ServerError>();
+    err.is::<WrongType>() || server_error.is_some_and(|err| err.code == ErrorCode::WrongType)
+}
@@ -0,0 +1,386 @@
+//! The connection of a [`Client`](crate::Client), shared by its clones
+//!
+//! A background task owns the connection. Clients hand it their requests over a
+//! channel, each with a oneshot channel for the replies. The task writes the
+//! requests in the order it receives them, batching those queued meanwhile,
+//! and the server replies in the same order, so the replies are matched to
+//! the requests waiting first in line. Many tasks can then share a connection,
+//! their requests in flight at once rather than in lock step.
+//!
+//! A request blocking on the server, say `BLPOP`, holds up the replies to the
+//! requests sent after it, as does a value streamed until it's received whole.
+//! Likewise, a value uploaded from a reader holds up the requests sent after
+//! it until it's read to the end. Once subscribed, the connection only
+//! receives the messages published, so further requests fail.
+
+use std::collections::VecDeque;
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use tokio::{
+    io::AsyncRead,
+    sync::{mpsc, oneshot},
+};
+use tracing::debug;
+use uranus_s::{Connection, Frame, FrameError, FrameHead};
+
+use crate::ClientError;
+
+/// How many requests may be queued for the task before clients wait.
+const QUEUE_SIZE: usize = 256;
+/// How many chunks of a streamed bulk string may be received ahead of the
+/// client, before the task waits for it.
+const STREAM_CHUNKS: usize = 16;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Link {
+    sender: mpsc::Sender<Message>,
+}
+
+enum Message {
+    /// Sends the requests back to back, replies with their replies.
+    Requests {
+        requests: Vec<Frame>,
+        replies: oneshot::Sender<Result<Vec<Frame>>>,
+    },
+    /// Sends a request whose reply may be a bulk string too large to buffer.
+    Stream {
+        request: Frame,
+        reply: oneshot::Sender<Result<Streamed>>,
+    },
+    /// Sends the request `entries`, its entry `at` a bulk string of `len`
+    /// bytes read from `body` as it's sent.
+    Upload {
+        entries: Vec<Frame>,
+        at: usize,
+        len: usize,
+        body: Box<dyn AsyncRead + Send + Unpin>,
+        replies: oneshot::Sender<Result<Vec<Frame>>>,
+    },
+    /// Sends a subscribing request, forwards every frame received from then on.
+    Subscribe {
+        request: Frame,
+        frames: mpsc::Sender<Result<Frame>>,
+    },
+}
+
+/// What a request in flight waits for.
+enum Waiter {
+    Replies {
+        expected: usize,
+        received: Vec<Frame>,
+        replies: oneshot::Sender<Result<Vec<Frame>>>,
+    },
+    Stream(oneshot::Sender<Result<Streamed>>),
+    Subscribed(mpsc::Sender<Result<Frame>>),
+}
+
+/// The reply to a streamed request.
+pub(crate) enum Streamed {
+    /// The reply wasn't a bulk string.
+    Frame(Frame),
+    /// The chunks of the bulk string, as they arrive.
+    Bulk(mpsc::Receiver<Result<Bytes>>),
+}
+
+impl Link {
+    /// Starts the task serving `connection`, so it must be called within a
+    /// tokio runtime. The task stops once every clone of the link is dropped.
+    pub(crate) fn start(connection: Connection) -> Link {
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(drive(connection, receiver));
+        Link { sender }
+    }
+
+    pub(crate) async fn request(&self, request: Frame) -> Result<Frame> {
+        let mut replies = self.requests(vec![request]).await?;
+        replies
+            .pop()
+            .ok_or_else(|| ClientError::ConnectionReset.into())
+    }
+
+    /// Sends `requests` with no request of another clone in between.
+    pub(crate) async fn requests(&self, requests: Vec<Frame>) -> Result<Vec<Frame>> {
+        // Checked here, as a frame the task fails to write fails the connection.
+        for request in &requests {
+            if let Frame::Array(entries) = request {
+                if entries.iter().any(|entry| matches!(entry, Frame::Array(_))) {
+                    Err(FrameError::Recursive)?
+                }
+            }
+        }
+        let (replies, received) = oneshot::channel();
+        self.send(Message::Requests { requests, replies }).await?;
+        received.await.map_err(|_| ClientError::ConnectionReset)?
+    }
+
+    /// Sends `request`, whose reply is received in chunks if it's a bulk
+    /// string. The replies to the requests sent after it wait until all of
+    /// it is received.
+    pub(crate) async fn stream(&self, request: Frame) -> Result<Streamed> {
+        let (reply, received) = oneshot::channel();
+        self.send(Message::Stream { request, reply }).await?;
+        received.await.map_err(|_| ClientError::ConnectionReset)?
+    }
+
+    /// Sends the request `entries`, its entry `at` replaced by a bulk string
+    /// of `len` bytes read from `body`. Fails if `body` ends short, which
+    /// fails the connection too.
+    pub(crate) async fn upload(
+        &self,
+        entries: Vec<Frame>,
+        at: usize,
+        len: usize,
+        body: Box<dyn AsyncRead + Send + Unpin>,
+    ) -> Result<Frame> {
+        if entries.iter().any(|entry| matches!(entry, Frame::Array(_))) {
+            Err(FrameError::Recursive)?
+        }
+        let (replies, received) = oneshot::channel();
+        self.send(Message::Upload {
+            entries,
+            at,
+            len,
+            body,
+            replies,
+        })
+        .await?;
+        let mut replies = received.await.map_err(|_| ClientError::ConnectionReset)??;
+        replies
+            .pop()
+            .ok_or_else(|| ClientError::ConnectionReset.into())
+    }
+
+    /// Sends `request`, then receives the reply and the frames following it.
+    pub(crate) async fn subscribe(&self, request: Frame) -> Result<mpsc::Receiver<Result<Frame>>> {
+        let (frames, received) = mpsc::channel(QUEUE_SIZE);
+        self.send(Message::Subscribe { request, frames }).await?;
+        Ok(received)
+    }
+
+    async fn send(&self, message: Message) -> Result<()> {
+        self.sender
+            .send(message)
+            .await
+            .map_err(|_| ClientError::ConnectionReset.into())
+    }
+}
+
+/// What the task waits for.
+#[derive(Default)]
+struct State {
+    /// The requests in flight, the first sent first.
+    waiting: VecDeque<Waiter>,
+    /// Where the frames go once subscribed.
+    subscribed: Option<mpsc::Sender<Result<Frame>>>,
+    /// The bulk string being streamed.
+    body: Option<Body>,
+}
+
+struct Body {
+    remaining: usize,
+    chunks: mpsc::Sender<Result<Bytes>>,
+}
+
+/// What the task reads next.
+enum Incoming {
+    Frame(Option<Frame>),
+    Head(Option<FrameHead>),
+    Chunk(Bytes),
+}
+
+async fn drive(mut connection: Connection, mut receiver: mpsc::Receiver<Message>) {
+    let mut state = State::default();
+    let result = loop {
+        let reading =
+            !state.waiting.is_empty() || state.subscribed.is_some() || state.body.is_some();
+        tokio::select! {
+            message = receiver.recv() => {
+                let Some(message) = message else {
+                    break Ok(());
+                };
+                if let Err(err) = send(&mut connection, &mut receiver, message, &mut state).await {
+                    break Err(err);
+                }
+            }
+            incoming = read(&mut connection, &state), if reading => {
+                if let Err(err) = receive(incoming, &mut state).await {
+                    break Err(err);
+                }
+            }
+        }
+    };
+    if let Err(err) = result {
+        debug!(cause = %err, "the connection failed");
+        let cause = err.to_string();
+        if let Some(body) = state.body {
+            let _ = body.chunks.send(Err(anyhow!(cause.clone()))).await;
+        }
+        let subscribed = state.subscribed.map(Waiter::Subscribed);
+        for waiter in state.waiting.into_iter().chain(subscribed) {
+            match waiter {
+                Waiter::Replies { replies, .. } => {
+                    let _ = replies.send(Err(anyhow!(cause.clone())));
+                }
+                Waiter::Stream(reply) => {
+                    let _ = reply.send(Err(anyhow!(cause.clone())));
+                }
+                Waiter::Subscribed(frames) => {
+                    let _ = frames.send(Err(anyhow!(cause.clone()))).await;
+                }
+            }
+        }
+    }
+}
+
+/// Writes the requests of `message`, and of the messages queued meanwhile, at
+/// once.
+async fn send(
+    connection: &mut Connection,
+    receiver: &mut mpsc::Receiver<Message>,
+    mut message: Message,
+    state: &mut State,
+) -> Result<()> {
+    loop {
+        // Nothing but published messages are received once subscribed.
+        let subscribing = state.subscribed.is_some()
+            || matches!(state.waiting.back(), Some(Waiter::Subscribed(_)));
+        let refused = || anyhow!("the connection is subscribed");
+        match message {
+            Message::Requests { replies, .. } if subscribing => {
+                let _ = replies.send(Err(refused()));
+            }
+            Message::Requests { requests, replies } => {
+                for request in &requests {
+                    connection.queue_frame(request)?;
+                }
+                state.waiting.push_back(Waiter::Replies {
+                    expected: requests.len(),
+                    received: Vec::with_capacity(requests.len()),
+                    replies,
+                });
+            }
+            Message::Stream { reply, .. } if subscribing => {
+                let _ = reply.send(Err(refused()));
+            }
+            Message::Stream { request, reply } => {
+                connection.queue_frame(&request)?;
+                state.waiting.push_back(Waiter::Stream(reply));
+            }
+            Message::Upload { replies, .. } if subscribing => {
+                let _ = replies.send(Err(refused()));
+            }
+            Message::Upload {
+                entries,
+                at,
+                len,
+                mut body,
+                replies,
+            } => {
+                // Sends the requests queued before it along.
+                if let Err(err) = connection
+                    .write_streamed(&entries, at, len, &mut body)
+                    .await
+                {
+                    let _ = replies.send(Err(anyhow!(err.to_string())));
+                    return Err(err);
+                }
+                state.waiting.push_back(Waiter::Replies {
+                    expected: 1,
+                    received: Vec::with_capacity(1),
+                    replies,
+                });
+            }
+            Message::Subscribe { frames, .. } if subscribing => {
+                let _ = frames.send(Err(refused())).await;
+            }
+            Message::Subscribe { request, frames } => {
+                connection.queue_frame(&request)?;
+                state.waiting.push_back(Waiter::Subscribed(frames));
+            }
+        }
+        match receiver.try_recv() {
+            Ok(next) => message = next,
+            Err(_) => break,
+        }
+    }
+    connection.flush().await
+}
+
+/// Reads the rest of the bulk string being streamed, or the start of the
+/// reply to a streamed request, or else a whole frame.
+async fn read(connection: &mut Connection, state: &State) -> Result<Incoming> {
+    if let Some(body) = &state.body {
+        return Ok(Incoming::Chunk(
+            connection.read_chunk(body.remaining).await?,
+        ));
+    }
+    if let Some(Waiter::Stream(_)) = state.waiting.front() {
+        return Ok(Incoming::Head(connection.read_head().await?));
+    }
+    Ok(Incoming::Frame(connection.read_frame().await?))
+}
+
+/// Hands what was read to the request waiting first in line, or to the
+/// subscriber.
+async fn receive(incoming: Result<Incoming>, state: &mut State) -> Result<()> {
+    let frame = match incoming? {
+        Incoming::Frame(Some(frame)) | Incoming::Head(Some(FrameHead::Frame(frame))) => frame,
+        Incoming::Frame(None) | Incoming::Head(None) => Err(ClientError::ConnectionReset)?,
+        Incoming::Head(Some(FrameHead::Bulk(len))) => {
+            let Some(Waiter::Stream(reply)) = state.waiting.pop_front() else {
+                unreachable!("only streamed requests read heads");
+            };
+            let (chunks, received) = mpsc::channel(STREAM_CHUNKS);
+            // The body is read through even if the client gave up on it.
+            let _ = reply.send(Ok(Streamed::Bulk(received)));
+            state.body = Some(Body {
+                remaining: len,
+                chunks,
+            });
+            return Ok(());
+        }
+        Incoming::Chunk(chunk) => {
+            let body = state.body.as_mut().expect("a body is streamed");
+            body.remaining -= chunk.len();
+            if !chunk.is_empty() {
+                let _ = body.chunks.send(Ok(chunk)).await;
+            }
+            if body.remaining == 0 {
+                state.body = None;
+            }
+            return Ok(());
+        }
+    };
+    match state.waiting.pop_front() {
+        Some(Waiter::Replies {
+            expected,
+            mut received,
+            replies,
+        }) => {
+            received.push(frame);
+            if received.len() < expected {
+                state.waiting.push_front(Waiter::Replies {
+                    expected,
+                    received,
+                    replies,
+                });
+            } else {
+                // The client may have given up waiting.
+                let _ = replies.send(Ok(received));
+            }
+        }
+        Some(Waiter::Stream(reply)) => {
+            let _ = reply.send(Ok(Streamed::Frame(frame)));
+        }
+        Some(Waiter::Subscribed(frames)) => {
+            frames.send(Ok(frame)).await?;
+            state.subscribed = Some(frames);
+        }
+        None => match &state.subscribed {
+            Some(frames) => frames.send(Ok(frame)).await?,
+            None => Err(ClientError::UnexpectedFrame(format!("{}", frame)))?,
+        },
+    }
+    Ok(())
+}
@@ -1,18 +1,43 @@
 use anyhow::Result;
-use uranus_c::Client;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use uranus_c::{
+    repl::{format_reply, parse_line},
+    Client,
+};
 
-const HELLO: &str = "Welcome to uranus client";
+const DEFAULT_ADDR: &str = "127.0.0.1:12322";
 
 #[tokio::main]
-async fn main() {
-    cmain().await.expect("Error");
+pub async fn main() {
+    cmain().await.unwrap();
 }
 
+/// Reads commands from stdin, one per line, and prints the replies, see
+/// [`uranus_c::repl`] for the syntax.
 async fn cmain() -> Result<()> {
-    tracing_subscriber::fmt::try_init().unwrap();
-    println!("{}", HELLO);
-    let mut client = Client::connect("127.0.0.1:12322").await?;
-    client.echo("PING").await?;
-    println!("uranus connected and pinged the server");
-    Ok(())
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_ADDR.to_string());
+    let mut client = Client::connect(&addr).await?;
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+    loop {
+        stdout.write_all(format!("{}> ", addr).as_bytes()).await?;
+        stdout.flush().await?;
+        let Some(line) = lines.next_line().await? else {
+            return Ok(());
+        };
+        let args = match parse_line(&line) {
+            Ok(args) if args.is_empty() => continue,
+            Ok(args) => args,
+            Err(err) => {
+                println!("(error) {}", err);
+                continue;
+            }
+        };
+        match client.raw_command(args).await {
+            Ok(reply) => println!("{}", format_reply(&reply)),
+            Err(err) => println!("(error) {}", err),
+        }
+    }
 }
@@ -0,0 +1,107 @@
+//! Sharing a server between tenants by prefixing their keys
+//!
+//! A [`NamespacedClient`] puts its namespace in front of every key it sends,
+//! and strips it from every key it gets back, so that each tenant of an
+//! application sees a keyspace of its own. `KEYS` and `SCAN` only go through
+//! the keys of the namespace, and [`NamespacedClient::flush`] deletes them all
+//! with `DELPREFIX`.
+//!
+//! Namespaces are a convention of the clients, not a boundary: the server
+//! knows nothing of them, see [ACL](uranus_s::acl) to keep tenants apart.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use bytes::Bytes;
+use uranus_s::{glob_escape, KeyTtl, Scan};
+
+use crate::{Client, ToValue};
+
+/// A client whose keys all live under a namespace, such as `tenant:42:`.
+#[derive(Clone)]
+pub struct NamespacedClient {
+    client: Client,
+    namespace: String,
+}
+
+impl NamespacedClient {
+    pub fn new(client: Client, namespace: impl ToString) -> NamespacedClient {
+        NamespacedClient {
+            client,
+            namespace: namespace.to_string(),
+        }
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// The client sending the requests, whose keys aren't prefixed.
+    pub fn inner(&mut self) -> &mut Client {
+        &mut self.client
+    }
+
+    pub async fn get(&mut self, key: &str) -> Result<Option<Bytes>> {
+        let key = self.key(key);
+        self.client.get(&key).await
+    }
+
+    pub async fn set(&mut self, key: &str, value: impl ToValue) -> Result<()> {
+        let key = self.key(key);
+        self.client.set(&key, value).await
+    }
+
+    /// Deletes `keys`, returns how many existed.
+    pub async fn del(&mut self, keys: &[&str]) -> Result<i64> {
+        let keys: Vec<String> = keys.iter().map(|key| self.key(key)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        self.client.del(&keys).await
+    }
+
+    /// Sets `key` to expire after `ttl`, returns whether it exists.
+    pub async fn expire(&mut self, key: &str, ttl: Duration) -> Result<bool> {
+        let key = self.key(key);
+        self.client.expire(&key, ttl).await
+    }
+
+    pub async fn ttl(&mut self, key: &str) -> Result<KeyTtl> {
+        let key = self.key(key);
+        self.client.ttl(&key).await
+    }
+
+    /// The keys of the namespace matching `pattern`, without the namespace.
+    pub async fn keys(&mut self, pattern: &str) -> Result<Vec<Bytes>> {
+        let pattern = self.pattern(pattern);
+        let keys = self.client.keys(&pattern).await?;
+        Ok(keys.into_iter().map(|key| self.strip(key)).collect())
+    }
+
+    /// Runs one step of a `SCAN` iteration over the keys of the namespace, see
+    /// [`Client::scan`].
+    pub async fn scan(&mut self, mut scan: Scan) -> Result<(u64, Vec<Bytes>)> {
+        scan.pattern = Some(self.pattern(scan.pattern.as_deref().unwrap_or("*")));
+        let (cursor, keys) = self.client.scan(scan).await?;
+        Ok((
+            cursor,
+            keys.into_iter().map(|key| self.strip(key)).collect(),
+        ))
+    }
+
+    /// Deletes every key of the namespace, returns how many there were.
+    pub async fn flush(&mut self) -> Result<i64> {
+        self.client.del_prefix(&self.namespace).await
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.namespace, key)
+    }
+
+    /// `pattern` matching within the namespace only.
+    fn pattern(&self, pattern: &str) -> String {
+        format!("{}{}", glob_escape(&self.namespace), pattern)
+    }
+
+    fn strip(&self, key: Bytes) -> Bytes {
+        key.slice(self.namespace.len().min(key.len())..)
+    }
+}
@@ -0,0 +1,148 @@
+//! A pool of [`Client`]s shared by concurrent tasks
+//!
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::debug;
+
+use crate::Client;
+
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// At most this many connections are open, checked out or idle. Further
+    /// [`Pool::get`] calls wait until a connection is returned.
+    pub max_size: usize,
+    /// [`Pool::warm_up`] opens connections until this many are idle.
+    pub min_idle: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_size: 16,
+            min_idle: 0,
+        }
+    }
+}
+
+/// [`Pool`] keeps connections to one server. It's cheap to clone, and clones
+/// share the same connections.
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    addr: String,
+    idle: Mutex<Vec<Client>>,
+    permits: Arc<Semaphore>,
+    config: PoolConfig,
+}
+
+impl Pool {
+    /// Creates an empty pool, connections are opened on demand.
+    pub fn new(addr: impl ToString, config: PoolConfig) -> Pool {
+        Pool {
+            inner: Arc::new(PoolInner {
+                addr: addr.to_string(),
+                idle: Mutex::new(vec![]),
+                permits: Arc::new(Semaphore::new(config.max_size)),
+                config,
+            }),
+        }
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.inner.addr
+    }
+
+    pub fn idle_connections(&self) -> usize {
+        self.inner.idle.lock().unwrap().len()
+    }
+
+    /// Opens connections until `min_idle` are idle, failing if the server can't be
+    /// reached or doesn't answer a PING.
+    pub async fn warm_up(&self) -> Result<()> {
+        let missing = self
+            .inner
+            .config
+            .min_idle
+            .saturating_sub(self.idle_connections());
+        for _ in 0..missing {
+            let client = self.connect().await?;
+            self.inner.idle.lock().unwrap().push(client);
+        }
+        Ok(())
+    }
+
+    /// Checks out a connection, waiting if `max_size` are in use. Idle connections
+    /// are PINGed first, and dropped if they don't answer. The connection goes
+    /// back to the pool when the returned guard is dropped.
+    pub async fn get(&self) -> Result<PooledClient> {
+        let permit = self.inner.permits.clone().acquire_owned().await?;
+        loop {
+            let idle = self.inner.idle.lock().unwrap().pop();
+            let Some(mut client) = idle else {
+                break;
+            };
+            if healthy(&mut client).await {
+                return Ok(self.guard(client, permit));
+            }
+            debug!(addr = %self.inner.addr, "dropped a broken pooled connection");
+        }
+        let client = self.connect().await?;
+        Ok(self.guard(client, permit))
+    }
+
+    async fn connect(&self) -> Result<Client> {
+        let mut client = Client::connect(self.inner.addr.as_str()).await?;
+        client.ping(None).await?;
+        Ok(client)
+    }
+
+    fn guard(&self, client: Client, permit: OwnedSemaphorePermit) -> PooledClient {
+        PooledClient {
+            client: Some(client),
+            pool: self.inner.clone(),
+            _permit: permit,
+        }
+    }
+}
+
+async fn healthy(client: &mut Client) -> bool {
+    matches!(client.ping(None).await, Ok(pong) if pong == "PONG")
+}
+
+/// A [`Client`] checked out of a [`Pool`], returned to it on drop.
+pub struct PooledClient {
+    client: Option<Client>,
+    pool: Arc<PoolInner>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for PooledClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut Client {
+        self.client.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            self.pool.idle.lock().unwrap().push(client);
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Line editing of the interactive client
+//!
+//! Arguments are separated by spaces. Binary-safe arguments can be written as:
+//!
+//! - `"double quoted"`, with `\n`, `\r`, `\t`, `\\`, `\"` and `\xNN` escapes;
+//! - `'single quoted'`, taken literally;
+//! - `x'cafe00'`, a hex literal.
+//!
+//! Binary replies which aren't printable text are shown as a hexdump.
+//!
+
+use std::fmt::Write;
+
+use bytes::Bytes;
+use thiserror::Error;
+use uranus_s::{format_double, Frame};
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ReplError {
+    #[error("unterminated quote")]
+    UnterminatedQuote,
+    #[error("invalid escape sequence '\\{0}'")]
+    BadEscape(String),
+    #[error("invalid hex literal")]
+    BadHex,
+}
+
+/// Splits a line into the arguments of a command.
+pub fn parse_line(line: &str) -> Result<Vec<Bytes>, ReplError> {
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&c) = chars.peek() else {
+            return Ok(args);
+        };
+        let mut arg = vec![];
+        match c {
+            '"' => {
+                chars.next();
+                double_quoted(&mut chars, &mut arg)?;
+            }
+            '\'' => {
+                chars.next();
+                single_quoted(&mut chars, &mut arg)?;
+            }
+            'x' if line_rest_starts_with(&chars, "x'") => {
+                chars.next();
+                chars.next();
+                let mut hex = vec![];
+                single_quoted(&mut chars, &mut hex)?;
+                arg = decode_hex(&hex)?;
+            }
+            _ => {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    push_char(&mut arg, c);
+                }
+            }
+        }
+        args.push(Bytes::from(arg));
+    }
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn line_rest_starts_with(chars: &Chars, prefix: &str) -> bool {
+    chars.clone().take(prefix.len()).eq(prefix.chars())
+}
+
+fn push_char(arg: &mut Vec<u8>, c: char) {
+    arg.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+fn single_quoted(chars: &mut Chars, arg: &mut Vec<u8>) -> Result<(), ReplError> {
+    for c in chars.by_ref() {
+        if c == '\'' {
+            return Ok(());
+        }
+        push_char(arg, c);
+    }
+    Err(ReplError::UnterminatedQuote)
+}
+
+fn double_quoted(chars: &mut Chars, arg: &mut Vec<u8>) -> Result<(), ReplError> {
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Ok(()),
+            '\\' => {
+                let escaped = chars.next().ok_or(ReplError::UnterminatedQuote)?;
+                match escaped {
+                    'n' => arg.push(b'\n'),
+                    'r' => arg.push(b'\r'),
+                    't' => arg.push(b'\t'),
+                    '\\' | '"' => arg.push(escaped as u8),
+                    'x' => {
+                        let digits: String = chars.by_ref().take(2).collect();
+                        let byte = u8::from_str_radix(&digits, 16)
+                            .map_err(|_| ReplError::BadEscape(format!("x{}", digits)))?;
+                        arg.push(byte);
+                    }
+                    _ => Err(ReplError::BadEscape(escaped.to_string()))?,
+                }
+            }
+            _ => push_char(arg, c),
+        }
+    }
+    Err(ReplError::UnterminatedQuote)
+}
+
+fn decode_hex(hex: &[u8]) -> Result<Vec<u8>, ReplError> {
+    if !hex.len().is_multiple_of(2) {
+        Err(ReplError::BadHex)?
+    }
+    hex.chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).map_err(|_| ReplError::BadHex)?;
+            u8::from_str_radix(pair, 16).map_err(|_| ReplError::BadHex)
+        })
+        .collect()
+}
+
+/// Renders a reply for the terminal.
+pub fn format_reply(frame: &Frame) -> String {
+    match frame {
+        Frame::Array(frames) if frames.is_empty() => "(empty array)".to_string(),
+        Frame::Array(frames) => {
+            let mut out = String::new();
+            for (i, frame) in frames.iter().enumerate() {
+                let item = format_reply(frame).replace('\n', "\n   ");
+                let _ = writeln!(out, "{}) {}", i + 1, item);
+            }
+            out.pop();
+            out
+        }
+        Frame::Text(text) => text.clone(),
+        Frame::Error(err) => format!("(error) {}", err),
+        Frame::Integer(val) => format!("(integer) {}", val),
+        Frame::Double(val) => format!("(double) {}", format_double(*val)),
+        Frame::Binary(binary) => format_binary(binary),
+        Frame::Null => "(nil)".to_string(),
+    }
+}
+
+/// Quoted if the bytes are printable text, a hexdump otherwise.
+fn format_binary(binary: &[u8]) -> String {
+    match std::str::from_utf8(binary) {
+        Ok(text)
+            if !text
+                .chars()
+                .any(|c| c.is_control() && c != '\n' && c != '\t') =>
+        {
+            format!("{:?}", text)
+        }
+        _ => hexdump(binary),
+    }
+}
+
+/// 16 bytes per line: the offset, the bytes in hex, then as ASCII.
+fn hexdump(binary: &[u8]) -> String {
+    let mut out = String::new();
+    for (line, chunk) in binary.chunks(16).enumerate() {
+        let _ = write!(out, "{:08x} ", line * 16);
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(byte) => {
+                    let _ = write!(out, " {:02x}", byte);
+                }
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("  |");
+        out.extend(chunk.iter().map(|&byte| match byte {
+            0x20..=0x7e => byte as char,
+            _ => '.',
+        }));
+        out.push_str("|\n");
+    }
+    out.pop();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let args = parse_line(r#"set  "a key\x00\n" 'raw \n' x'cafe' plain"#).unwrap();
+        let expected: [&[u8]; 5] = [b"set", b"a key\0\n", b"raw \\n", &[0xca, 0xfe], b"plain"];
+        assert_eq!(args, expected);
+
+        assert_eq!(parse_line("get \"key"), Err(ReplError::UnterminatedQuote));
+        assert_eq!(
+            parse_line(r#"get "\q""#),
+            Err(ReplError::BadEscape("q".to_string()))
+        );
+        assert_eq!(parse_line("get x'abc'"), Err(ReplError::BadHex));
+    }
+
+    #[test]
+    fn test_format_binary() {
+        let text = Frame::Binary(Bytes::from("uranus"));
+        assert_eq!(format_reply(&text), "\"uranus\"");
+        let binary = Frame::Binary(Bytes::from_static(b"\x00\xffab"));
+        assert_eq!(
+            format_reply(&binary),
+            "00000000  00 ff 61 62                                      |..ab|"
+        );
+    }
+}
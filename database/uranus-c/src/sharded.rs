@@ -0,0 +1,189 @@
+//! Spreading keys over independent servers by consistent hashing
+//!
+//! Unlike [cluster mode](uranus_s::cluster), the servers know nothing of each
+//! other: a [`ShardedClient`] picks the server of each key itself. Each server
+//! is placed on a [`HashRing`] at [`VIRTUAL_NODES`] points, and a key goes to
+//! the server of the first point from its hash on. Keys spread evenly, and
+//! adding or removing a server only moves the keys of its points.
+//!
+
+use anyhow::Result;
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{Client, ClientError, ToValue};
+
+/// The points of each server on the ring.
+pub const VIRTUAL_NODES: usize = 160;
+
+/// Which server each key goes to.
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    nodes: Vec<String>,
+    /// The points of the ring in order, with the index of their node.
+    points: Vec<(u64, usize)>,
+}
+
+impl HashRing {
+    pub fn new<I>(nodes: I) -> HashRing
+    where
+        I: IntoIterator,
+        I::Item: ToString,
+    {
+        let nodes: Vec<String> = nodes.into_iter().map(|node| node.to_string()).collect();
+        let mut points = vec![];
+        for (index, node) in nodes.iter().enumerate() {
+            for point in 0..VIRTUAL_NODES {
+                points.push((hash(format!("{}#{}", node, point).as_bytes()), index));
+            }
+        }
+        points.sort_unstable();
+        HashRing { nodes, points }
+    }
+
+    pub fn nodes(&self) -> &[String] {
+        &self.nodes
+    }
+
+    /// The index in [`HashRing::nodes`] of the node serving `key`, none if
+    /// the ring is empty.
+    pub fn node(&self, key: &[u8]) -> Option<usize> {
+        let hash = hash(key);
+        let point = self.points.partition_point(|&(point, _)| point < hash);
+        let (_, node) = self.points.get(point).or(self.points.first())?;
+        Some(*node)
+    }
+}
+
+/// 64-bit FNV-1a, its bits mixed further so that close inputs land far apart.
+fn hash(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+/// A client of several independent servers, routing each key to one of them
+/// by its [`HashRing`]. A connection broken by an error is reopened on the
+/// next request to its server.
+pub struct ShardedClient {
+    ring: HashRing,
+    clients: Vec<Option<Client>>,
+}
+
+impl ShardedClient {
+    /// Connects to every server at `addrs`, failing if any is unreachable.
+    pub async fn connect<I>(addrs: I) -> Result<ShardedClient>
+    where
+        I: IntoIterator,
+        I::Item: ToString,
+    {
+        let ring = HashRing::new(addrs);
+        if ring.nodes().is_empty() {
+            Err(ClientError::NoServers)?
+        }
+        let mut clients = vec![];
+        for addr in ring.nodes() {
+            clients.push(Some(Client::connect(addr.as_str()).await?));
+        }
+        Ok(ShardedClient { ring, clients })
+    }
+
+    pub fn ring(&self) -> &HashRing {
+        &self.ring
+    }
+
+    /// The address of the server of `key`.
+    pub fn node(&self, key: &str) -> &str {
+        &self.ring.nodes()[self.index(key)]
+    }
+
+    pub async fn get(&mut self, key: &str) -> Result<Option<Bytes>> {
+        let node = self.index(key);
+        let result = self.client(node).await?.get(key).await;
+        self.check(node, result)
+    }
+
+    pub async fn set(&mut self, key: &str, value: impl ToValue) -> Result<()> {
+        let node = self.index(key);
+        let result = self.client(node).await?.set(key, value).await;
+        self.check(node, result)
+    }
+
+    /// Deletes `keys` from their servers, returns how many existed.
+    pub async fn del(&mut self, keys: &[&str]) -> Result<i64> {
+        let mut deleted = 0;
+        for node in 0..self.clients.len() {
+            let keys: Vec<&str> = keys
+                .iter()
+                .copied()
+                .filter(|key| self.index(key) == node)
+                .collect();
+            if keys.is_empty() {
+                continue;
+            }
+            let result = self.client(node).await?.del(&keys).await;
+            deleted += self.check(node, result)?;
+        }
+        Ok(deleted)
+    }
+
+    fn index(&self, key: &str) -> usize {
+        self.ring
+            .node(key.as_bytes())
+            .expect("a sharded client has servers")
+    }
+
+    async fn client(&mut self, node: usize) -> Result<&mut Client> {
+        if self.clients[node].is_none() {
+            let addr = &self.ring.nodes()[node];
+            debug!(%addr, "reconnecting");
+            self.clients[node] = Some(Client::connect(addr.as_str()).await?);
+        }
+        Ok(self.clients[node].as_mut().unwrap())
+    }
+
+    /// Drops the connection to `node` if `result` failed for it is broken.
+    fn check<T>(&mut self, node: usize, result: Result<T>) -> Result<T> {
+        if let Err(err) = &result {
+            let reset = matches!(
+                err.downcast_ref::<ClientError>(),
+                Some(ClientError::ConnectionReset)
+            );
+            if reset || err.downcast_ref::<std::io::Error>().is_some() {
+                self.clients[node] = None;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_ring() {
+        let ring = HashRing::new(["a:6379", "b:6379", "c:6379"]);
+        let keys: Vec<String> = (0..3000).map(|i| format!("key:{}", i)).collect();
+        let mut counts = [0; 3];
+        for key in &keys {
+            counts[ring.node(key.as_bytes()).unwrap()] += 1;
+        }
+        assert!(counts.iter().all(|&count| count > 700), "{:?}", counts);
+
+        // Keys only move to the new node.
+        let grown = HashRing::new(["a:6379", "b:6379", "c:6379", "d:6379"]);
+        for key in &keys {
+            let node = grown.node(key.as_bytes()).unwrap();
+            assert!(node == 3 || node == ring.node(key.as_bytes()).unwrap());
+        }
+        assert_eq!(HashRing::new(Vec::<String>::new()).node(b"key"), None);
+    }
+}
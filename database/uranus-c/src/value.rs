@@ -0,0 +1,123 @@
+//! Replies decoded into plain values
+//!
+//! The typed methods of [`Client`] each read the reply of their command.
+//! [`Client::command`] sends any other command, and hands back its reply as a
+//! [`Value`].
+
+use anyhow::Result;
+use bytes::Bytes;
+use uranus_s::{format_double, Frame};
+
+use crate::{Client, ClientError, ServerError};
+
+/// A reply of the server.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// A status reply, as `OK`.
+    Text(String),
+    /// A bulk string. Doubles are decoded to their text form too, as the
+    /// second version of the protocol sends them, see
+    /// [`Protocol`](uranus_s::Protocol).
+    Bytes(Bytes),
+    Int(i64),
+    Array(Vec<Value>),
+    Nil,
+    /// Field-value pairs. The protocol has no map frame, so replies such as
+    /// `HGETALL` are decoded as flat arrays, which [`Value::into_map`] pairs.
+    Map(Vec<(Value, Value)>),
+}
+
+impl Value {
+    /// Pairs up the elements of a flat array of fields and values, as
+    /// `HGETALL` replies. Maps are returned as they are.
+    pub fn into_map(self) -> Result<Value, ClientError> {
+        match self {
+            Value::Array(values) if values.len() % 2 == 0 => {
+                let mut values = values.into_iter();
+                let mut pairs = Vec::with_capacity(values.len() / 2);
+                while let (Some(field), Some(value)) = (values.next(), values.next()) {
+                    pairs.push((field, value));
+                }
+                Ok(Value::Map(pairs))
+            }
+            Value::Map(pairs) => Ok(Value::Map(pairs)),
+            value => Err(ClientError::UnexpectedFrame(format!("{:?}", value))),
+        }
+    }
+}
+
+impl TryFrom<Frame> for Value {
+    type Error = ServerError;
+
+    /// Fails on error replies, and on them only.
+    fn try_from(frame: Frame) -> Result<Value, ServerError> {
+        Ok(match frame {
+            Frame::Text(text) => Value::Text(text),
+            Frame::Error(reply) => return Err(ServerError::parse(&reply)),
+            Frame::Integer(n) => Value::Int(n),
+            Frame::Double(val) => Value::Bytes(Bytes::from(format_double(val))),
+            Frame::Binary(binary) => Value::Bytes(binary),
+            Frame::Array(frames) => Value::Array(
+                frames
+                    .into_iter()
+                    .map(Value::try_from)
+                    .collect::<Result<_, _>>()?,
+            ),
+            Frame::Null => Value::Nil,
+        })
+    }
+}
+
+impl Client {
+    /// Sends an arbitrary command, made of its name and arguments, as
+    /// `client.command(&["HGETALL", "user"])`, for commands the
+    /// typed methods don't cover. Error replies fail as the typed methods do.
+    pub async fn command(&mut self, args: &[&str]) -> Result<Value> {
+        let args = args.iter().map(|arg| Bytes::from(arg.to_string()));
+        let reply = self.raw_command(args.collect()).await?;
+        Ok(Value::try_from(reply)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uranus_s::ErrorCode;
+
+    use super::*;
+
+    #[test]
+    fn test_decode_frames() {
+        let frame = Frame::Array(vec![
+            Frame::Text("OK".to_string()),
+            Frame::Integer(7),
+            Frame::Double(1.5),
+            Frame::Binary(Bytes::from("value")),
+            Frame::Null,
+        ]);
+        assert_eq!(
+            Value::try_from(frame).unwrap(),
+            Value::Array(vec![
+                Value::Text("OK".to_string()),
+                Value::Int(7),
+                Value::Bytes(Bytes::from("1.5")),
+                Value::Bytes(Bytes::from("value")),
+                Value::Nil,
+            ])
+        );
+
+        let nested = Frame::Array(vec![Frame::Error("WRONGTYPE wrong kind".to_string())]);
+        let err = Value::try_from(nested).unwrap_err();
+        assert_eq!(err.code, ErrorCode::WrongType);
+    }
+
+    #[test]
+    fn test_into_map() {
+        let pairs = Value::Array(vec![Value::Text("a".to_string()), Value::Int(1)]);
+        assert_eq!(
+            pairs.into_map().unwrap(),
+            Value::Map(vec![(Value::Text("a".to_string()), Value::Int(1))])
+        );
+        assert!(Value::Array(vec![Value::Nil]).into_map().is_err());
+        assert!(Value::Int(1).into_map().is_err());
+    }
+}
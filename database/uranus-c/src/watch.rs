@@ -0,0 +1,71 @@
+//! Typed keyspace events
+//!
+
+use std::time::SystemTime;
+
+use anyhow::Result;
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use uranus_s::{Frame, Subscribe, KEYSPACE_PREFIX};
+
+use crate::{Client, ClientError};
+
+const EVENTS_BUFFER: usize = 64;
+
+/// A write to a key, reported by [`Client::watch_keys`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: String,
+    /// The name of the command which wrote the key, e.g. `set` or `hdel`.
+    pub op: String,
+    /// When the client received the event.
+    pub timestamp: SystemTime,
+}
+
+impl Client {
+    /// Watches writes to the keys matching the glob `pattern`. The server must have
+    /// keyspace events enabled. The connection is dedicated to watching from then
+    /// on, so the client is consumed, and requests of its clones fail; the
+    /// stream ends after yielding an error.
+    pub async fn watch_keys(self, pattern: &str) -> Result<impl Stream<Item = Result<KeyEvent>>> {
+        let channel = format!("{}{}", KEYSPACE_PREFIX, pattern);
+        let frame = Subscribe::patterns(vec![channel]).into_frame();
+        let mut frames = self.link.subscribe(frame).await?;
+        let subscribed = frames.recv().await.ok_or(ClientError::ConnectionReset)?;
+        self.check_response(subscribed?)?;
+
+        let (sender, receiver) = mpsc::channel(EVENTS_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let event = match frames.recv().await {
+                    Some(Ok(frame)) => self.check_response(frame).and_then(key_event),
+                    Some(Err(err)) => Err(err),
+                    None => Err(ClientError::ConnectionReset.into()),
+                };
+                let failed = event.is_err();
+                if sender.send(event).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(ReceiverStream::new(receiver))
+    }
+}
+
+/// Decodes a `pmessage` frame published on a keyspace channel.
+fn key_event(frame: Frame) -> Result<KeyEvent> {
+    let Frame::Array(parts) = frame else {
+        Err(ClientError::BadResponse)?
+    };
+    let [Frame::Text(kind), _, Frame::Text(channel), Frame::Binary(op)] = &parts[..] else {
+        Err(ClientError::BadResponse)?
+    };
+    match channel.strip_prefix(KEYSPACE_PREFIX) {
+        Some(key) if kind == "pmessage" => Ok(KeyEvent {
+            key: key.to_string(),
+            op: String::from_utf8_lossy(op).into_owned(),
+            timestamp: SystemTime::now(),
+        }),
+        _ => Err(ClientError::BadResponse)?,
+    }
+}
@@ -0,0 +1,352 @@
+//! Blocks of sorted entries, which tables are made of
+//!
+//! A block holds entries in key order. As neighbouring keys tend to share
+//! their start, an entry stores the length of the prefix its key shares with
+//! the previous one, the length of the rest of the key, the length of its
+//! value, all as varints, then the rest of the key and the value. Every
+//! `restart_interval` entries the key is stored whole instead, at a restart
+//! point. The block ends with the offsets of the restart points and their
+//! number, 32-bit little-endian, so that a seek binary searches them then
+//! scans at most one interval, as in LevelDB.
+//!
+
+use std::ops::Range;
+
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::StorageError;
+
+/// Appends `n` to `dst`, 7 bits a byte from the lowest, the high bit of each
+/// byte but the last set.
+pub(crate) fn put_varint(dst: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        dst.put_u8(n as u8 | 0x80);
+        n >>= 7;
+    }
+    dst.put_u8(n as u8);
+}
+
+/// Reads a varint off the start of `src`, none if it is cut short or too long.
+pub(crate) fn get_varint(src: &mut &[u8]) -> Option<u64> {
+    let mut n = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = src.split_first()?;
+        *src = rest;
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte < 0x80 {
+            return Some(n);
+        }
+    }
+    None
+}
+
+fn malformed() -> StorageError {
+    StorageError::Corrupted("malformed block".to_string())
+}
+
+/// Where a block is in its file, with its length and trailer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BlockHandle {
+    pub(crate) offset: u64,
+    pub(crate) len: u64,
+}
+
+impl BlockHandle {
+    pub(crate) fn range(&self) -> Range<usize> {
+        self.offset as usize..(self.offset + self.len) as usize
+    }
+
+    /// Appends the offset then the length, as varints.
+    pub(crate) fn encode(&self, dst: &mut Vec<u8>) {
+        put_varint(dst, self.offset);
+        put_varint(dst, self.len);
+    }
+
+    pub(crate) fn decode(mut src: &[u8]) -> Result<BlockHandle, StorageError> {
+        let offset = get_varint(&mut src).ok_or_else(malformed)?;
+        let len = get_varint(&mut src).ok_or_else(malformed)?;
+        if !src.is_empty() {
+            return Err(malformed());
+        }
+        Ok(BlockHandle { offset, len })
+    }
+}
+
+/// Builds a block out of entries added in key order.
+pub(crate) struct BlockBuilder {
+    buf: Vec<u8>,
+    restarts: Vec<u32>,
+    restart_interval: usize,
+    /// The entries since the last restart point.
+    counter: usize,
+    last_key: Vec<u8>,
+}
+
+impl BlockBuilder {
+    /// A builder storing every `restart_interval`-th key whole.
+    pub(crate) fn new(restart_interval: usize) -> BlockBuilder {
+        BlockBuilder {
+            buf: vec![],
+            restarts: vec![0],
+            restart_interval: restart_interval.max(1),
+            counter: 0,
+            last_key: vec![],
+        }
+    }
+
+    /// Appends an entry, whose key must come after those of the block.
+    pub(crate) fn add(&mut self, key: &[u8], value: &[u8]) {
+        debug_assert!(self.is_empty() || key > &self.last_key[..]);
+        let shared = if self.counter < self.restart_interval {
+            let pairs = self.last_key.iter().zip(key);
+            pairs.take_while(|(last, byte)| last == byte).count()
+        } else {
+            self.restarts.push(self.buf.len() as u32);
+            self.counter = 0;
+            0
+        };
+        put_varint(&mut self.buf, shared as u64);
+        put_varint(&mut self.buf, (key.len() - shared) as u64);
+        put_varint(&mut self.buf, value.len() as u64);
+        self.buf.put_slice(&key[shared..]);
+        self.buf.put_slice(value);
+        self.last_key.truncate(shared);
+        self.last_key.extend_from_slice(&key[shared..]);
+        self.counter += 1;
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// The key of the last entry added.
+    pub(crate) fn last_key(&self) -> &[u8] {
+        &self.last_key
+    }
+
+    /// How long the block would be if finished now.
+    pub(crate) fn size(&self) -> usize {
+        self.buf.len() + 4 * self.restarts.len() + 4
+    }
+
+    /// Ends the block, handing it back, and starts the next.
+    pub(crate) fn finish(&mut self) -> Vec<u8> {
+        let mut block = std::mem::take(&mut self.buf);
+        let count = self.restarts.len() as u32;
+        for restart in self.restarts.drain(..) {
+            block.put_u32_le(restart);
+        }
+        block.put_u32_le(count);
+        self.restarts.push(0);
+        self.counter = 0;
+        self.last_key.clear();
+        block
+    }
+}
+
+/// A block read back, see [`BlockBuilder`].
+#[derive(Debug, Clone)]
+pub(crate) struct Block {
+    data: Bytes,
+    /// Where the offsets of the restart points start, and the entries end.
+    restarts: usize,
+    num_restarts: usize,
+}
+
+impl Block {
+    pub(crate) fn new(data: Bytes) -> Result<Block, StorageError> {
+        if data.len() < 4 {
+            return Err(malformed());
+        }
+        let num_restarts = (&data[data.len() - 4..]).get_u32_le() as usize;
+        let restarts = (data.len() - 4)
+            .checked_sub(4 * num_restarts)
+            .filter(|_| num_restarts > 0)
+            .ok_or_else(malformed)?;
+        Ok(Block {
+            data,
+            restarts,
+            num_restarts,
+        })
+    }
+
+    /// An iterator over the entries, not positioned yet.
+    pub(crate) fn iter(&self) -> BlockIter {
+        BlockIter {
+            block: self.clone(),
+            next: self.restarts,
+            key: vec![],
+            value: 0..0,
+            valid: false,
+        }
+    }
+
+    fn restart(&self, index: usize) -> Result<usize, StorageError> {
+        let offset = self.restarts + 4 * index;
+        let restart = (&self.data[offset..offset + 4]).get_u32_le() as usize;
+        if restart > self.restarts {
+            return Err(malformed());
+        }
+        Ok(restart)
+    }
+
+    /// Decodes the lengths of the entry at `offset`, returns the key prefix
+    /// it shares, the rest of its key and its value.
+    fn entry(&self, offset: usize) -> Result<(usize, Range<usize>, Range<usize>), StorageError> {
+        let mut src = &self.data[offset..self.restarts];
+        let shared = get_varint(&mut src).ok_or_else(malformed)? as usize;
+        let unshared = get_varint(&mut src).ok_or_else(malformed)? as usize;
+        let value_len = get_varint(&mut src).ok_or_else(malformed)? as usize;
+        if src.len() < unshared + value_len {
+            return Err(malformed());
+        }
+        let start = self.restarts - src.len();
+        let value = start + unshared;
+        Ok((shared, start..value, value..value + value_len))
+    }
+}
+
+/// The entries of a [`Block`], in order.
+pub(crate) struct BlockIter {
+    block: Block,
+    /// Where the entry after the current one starts.
+    next: usize,
+    key: Vec<u8>,
+    value: Range<usize>,
+    valid: bool,
+}
+
+impl BlockIter {
+    pub(crate) fn seek_to_first(&mut self) -> Result<(), StorageError> {
+        self.next = 0;
+        self.key.clear();
+        self.advance()
+    }
+
+    /// Moves to the first entry whose key isn't before `target`, if any.
+    pub(crate) fn seek(&mut self, target: &[u8]) -> Result<(), StorageError> {
+        // The last restart point whose key is before the target, where the
+        // scan starts.
+        let (mut low, mut high) = (0, self.block.num_restarts);
+        while low + 1 < high {
+            let mid = (low + high) / 2;
+            let offset = self.block.restart(mid)?;
+            let (shared, key, _) = self.block.entry(offset)?;
+            if shared != 0 {
+                return Err(malformed());
+            }
+            if &self.block.data[key] < target {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        self.next = self.block.restart(low)?;
+        self.key.clear();
+        self.advance()?;
+        while self.valid && &self.key[..] < target {
+            self.advance()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn next(&mut self) -> Result<(), StorageError> {
+        self.advance()
+    }
+
+    fn advance(&mut self) -> Result<(), StorageError> {
+        if self.next >= self.block.restarts {
+            self.valid = false;
+            return Ok(());
+        }
+        let (shared, rest, value) = self.block.entry(self.next)?;
+        if shared > self.key.len() {
+            return Err(malformed());
+        }
+        self.key.truncate(shared);
+        self.key.extend_from_slice(&self.block.data[rest]);
+        self.next = value.end;
+        self.value = value;
+        self.valid = true;
+        Ok(())
+    }
+
+    pub(crate) fn is_valid(&self) -> bool {
+        self.valid
+    }
+
+    pub(crate) fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub(crate) fn value(&self) -> Bytes {
+        self.block.data.slice(self.value.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(entries: &[(String, String)], restart_interval: usize) -> Block {
+        let mut builder = BlockBuilder::new(restart_interval);
+        for (key, value) in entries {
+            builder.add(key.as_bytes(), value.as_bytes());
+        }
+        let size = builder.size();
+        let block = builder.finish();
+        assert_eq!(block.len(), size);
+        assert!(builder.is_empty());
+        Block::new(Bytes::from(block)).unwrap()
+    }
+
+    #[test]
+    fn test_varint() {
+        for n in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buf = vec![];
+            put_varint(&mut buf, n);
+            let mut src = &buf[..];
+            assert_eq!(get_varint(&mut src), Some(n));
+            assert!(src.is_empty());
+            assert_eq!(get_varint(&mut &buf[..buf.len() - 1]), None);
+        }
+    }
+
+    #[test]
+    fn test_block() {
+        let entries: Vec<(String, String)> = (0..100)
+            .map(|i| (format!("user:{:04}", i * 2), i.to_string()))
+            .collect();
+        for restart_interval in [1, 4, 16, 1000] {
+            let block = build(&entries, restart_interval);
+            let mut iter = block.iter();
+            iter.seek_to_first().unwrap();
+            for (key, value) in &entries {
+                assert_eq!(iter.key(), key.as_bytes());
+                assert_eq!(iter.value(), value.as_bytes());
+                iter.next().unwrap();
+            }
+            assert!(!iter.is_valid());
+
+            iter.seek(b"user:0042").unwrap();
+            assert_eq!(iter.key(), b"user:0042");
+            iter.seek(b"user:0043").unwrap();
+            assert_eq!(iter.key(), b"user:0044");
+            iter.seek(b"").unwrap();
+            assert_eq!(iter.key(), b"user:0000");
+            iter.seek(b"user:0199").unwrap();
+            assert!(!iter.is_valid());
+        }
+
+        // Shared prefixes are only stored once between restart points.
+        let whole = build(&entries, 1).data.len();
+        assert!(build(&entries, 16).data.len() < whole / 2);
+
+        let empty = build(&[], 16);
+        let mut iter = empty.iter();
+        iter.seek_to_first().unwrap();
+        assert!(!iter.is_valid());
+        iter.seek(b"key").unwrap();
+        assert!(!iter.is_valid());
+    }
+}
@@ -0,0 +1,245 @@
+//! A binary encoding of values, for engines keeping them on disk
+//!
+//! A value is its type tag followed by its data. Lengths and counts are 32-bit
+//! and numbers 64-bit, all little-endian, and floats are stored by their bits.
+//!
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bytes::{Buf, BufMut, Bytes};
+use thiserror::Error;
+
+use crate::{PriorityQueue, SortedSet, Stream, StreamId, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("invalid value encoding")]
+pub struct InvalidEncoding;
+
+const STRING: u8 = 0;
+const HASH: u8 = 1;
+const QUEUE: u8 = 2;
+const LIST: u8 = 3;
+const SET: u8 = 4;
+const SORTED_SET: u8 = 5;
+const STREAM: u8 = 6;
+
+impl Value {
+    /// Appends the encoding of this value to `dst`, which [`Value::decode`]
+    /// reads back.
+    pub fn encode(&self, dst: &mut Vec<u8>) {
+        match self {
+            Value::String(string) => {
+                dst.put_u8(STRING);
+                put_bytes(dst, string);
+            }
+            Value::Hash(hash) => {
+                dst.put_u8(HASH);
+                dst.put_u32_le(hash.len() as u32);
+                for (field, value) in hash {
+                    put_bytes(dst, field);
+                    put_bytes(dst, value);
+                }
+            }
+            Value::Queue(queue) => {
+                dst.put_u8(QUEUE);
+                dst.put_u32_le(queue.len() as u32);
+                for (item, priority) in queue.iter() {
+                    dst.put_f64_le(priority);
+                    put_bytes(dst, item);
+                }
+            }
+            Value::List(list) => {
+                dst.put_u8(LIST);
+                dst.put_u32_le(list.len() as u32);
+                for element in list {
+                    put_bytes(dst, element);
+                }
+            }
+            Value::Set(set) => {
+                dst.put_u8(SET);
+                dst.put_u32_le(set.len() as u32);
+                for member in set {
+                    put_bytes(dst, member);
+                }
+            }
+            Value::SortedSet(set) => {
+                dst.put_u8(SORTED_SET);
+                dst.put_u32_le(set.len() as u32);
+                for (member, score) in set.range_by_rank(0, -1) {
+                    dst.put_f64_le(score);
+                    put_bytes(dst, member);
+                }
+            }
+            Value::Stream(stream) => {
+                dst.put_u8(STREAM);
+                dst.put_u32_le(stream.len() as u32);
+                for (id, fields) in stream.range(StreamId::MIN, StreamId::MAX) {
+                    dst.put_u64_le(id.ms);
+                    dst.put_u64_le(id.seq);
+                    dst.put_u32_le(fields.len() as u32);
+                    for (field, value) in fields {
+                        put_bytes(dst, field);
+                        put_bytes(dst, value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads a value written by [`Value::encode`], which must take all of
+    /// `src`. The byte strings of the value share `src`.
+    pub fn decode(mut src: Bytes) -> Result<Value, InvalidEncoding> {
+        let src = &mut src;
+        let value = match get_u8(src)? {
+            STRING => Value::String(get_bytes(src)?),
+            HASH => {
+                let mut hash = HashMap::new();
+                for _ in 0..get_u32(src)? {
+                    hash.insert(get_bytes(src)?, get_bytes(src)?);
+                }
+                Value::Hash(hash)
+            }
+            QUEUE => {
+                let mut queue = PriorityQueue::new();
+                for _ in 0..get_u32(src)? {
+                    let priority = get_f64(src)?;
+                    queue.push(priority, get_bytes(src)?);
+                }
+                Value::Queue(queue)
+            }
+            LIST => {
+                let mut list = VecDeque::new();
+                for _ in 0..get_u32(src)? {
+                    list.push_back(get_bytes(src)?);
+                }
+                Value::List(list)
+            }
+            SET => {
+                let mut set = HashSet::new();
+                for _ in 0..get_u32(src)? {
+                    set.insert(get_bytes(src)?);
+                }
+                Value::Set(set)
+            }
+            SORTED_SET => {
+                let mut set = SortedSet::new();
+                for _ in 0..get_u32(src)? {
+                    let score = get_f64(src)?;
+                    set.insert(get_bytes(src)?, score);
+                }
+                Value::SortedSet(set)
+            }
+            STREAM => {
+                let mut stream = Stream::new();
+                for _ in 0..get_u32(src)? {
+                    let id = StreamId::new(get_u64(src)?, get_u64(src)?);
+                    let mut fields = vec![];
+                    for _ in 0..get_u32(src)? {
+                        fields.push((get_bytes(src)?, get_bytes(src)?));
+                    }
+                    if !stream.add(id, fields) {
+                        return Err(InvalidEncoding);
+                    }
+                }
+                Value::Stream(stream)
+            }
+            _ => return Err(InvalidEncoding),
+        };
+        if src.has_remaining() {
+            return Err(InvalidEncoding);
+        }
+        Ok(value)
+    }
+}
+
+fn put_bytes(dst: &mut Vec<u8>, bytes: &[u8]) {
+    dst.put_u32_le(bytes.len() as u32);
+    dst.put_slice(bytes);
+}
+
+fn get_u8(src: &mut Bytes) -> Result<u8, InvalidEncoding> {
+    need(src, 1)?;
+    Ok(src.get_u8())
+}
+
+fn get_u32(src: &mut Bytes) -> Result<u32, InvalidEncoding> {
+    need(src, 4)?;
+    Ok(src.get_u32_le())
+}
+
+fn get_u64(src: &mut Bytes) -> Result<u64, InvalidEncoding> {
+    need(src, 8)?;
+    Ok(src.get_u64_le())
+}
+
+fn get_f64(src: &mut Bytes) -> Result<f64, InvalidEncoding> {
+    need(src, 8)?;
+    Ok(src.get_f64_le())
+}
+
+fn get_bytes(src: &mut Bytes) -> Result<Bytes, InvalidEncoding> {
+    let len = get_u32(src)? as usize;
+    need(src, len)?;
+    Ok(src.split_to(len))
+}
+
+fn need(src: &Bytes, len: usize) -> Result<(), InvalidEncoding> {
+    if src.remaining() < len {
+        return Err(InvalidEncoding);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(value: Value) {
+        let mut encoded = vec![];
+        value.encode(&mut encoded);
+        assert_eq!(Value::decode(Bytes::from(encoded)), Ok(value));
+    }
+
+    #[test]
+    fn test_codec() {
+        let b = |s: &'static str| Bytes::from(s);
+        round_trip(Value::String(b("binary\r\n\0")));
+        round_trip(Value::Hash(HashMap::from([
+            (b("f"), b("v")),
+            (b("g"), b("")),
+        ])));
+        round_trip(Value::List(VecDeque::from([b("a"), b("b"), b("a")])));
+        round_trip(Value::Set(HashSet::from([b("x"), b("y")])));
+        let mut set = SortedSet::new();
+        set.insert(b("low"), -1.5);
+        set.insert(b("high"), f64::INFINITY);
+        round_trip(Value::SortedSet(set));
+        let mut stream = Stream::new();
+        stream.add(StreamId::new(5, 0), vec![(b("field"), b("value"))]);
+        stream.add(StreamId::new(5, 1), vec![]);
+        round_trip(Value::Stream(stream));
+
+        // Items of a queue are renumbered, so only their order is compared.
+        let mut queue = PriorityQueue::new();
+        queue.push(1.0, b("first"));
+        queue.push(1.0, b("second"));
+        queue.push(0.5, b("urgent"));
+        let mut encoded = vec![];
+        Value::Queue(queue.clone()).encode(&mut encoded);
+        let Ok(Value::Queue(decoded)) = Value::decode(Bytes::from(encoded.clone())) else {
+            panic!("a queue decodes to a queue");
+        };
+        assert!(decoded.iter().eq(queue.iter()));
+
+        assert_eq!(
+            Value::decode(Bytes::from(encoded[..encoded.len() - 1].to_vec())),
+            Err(InvalidEncoding)
+        );
+        encoded.push(0);
+        assert_eq!(Value::decode(Bytes::from(encoded)), Err(InvalidEncoding));
+        assert_eq!(
+            Value::decode(Bytes::from_static(&[42])),
+            Err(InvalidEncoding)
+        );
+    }
+}
@@ -0,0 +1,532 @@
+//! Transparent compression of string values
+//!
+//! [`Compressed`] wraps an engine and compresses the strings stored through it
+//! which are at least [`ValueCompression::threshold`] bytes long, with the
+//! [`Compression`] in effect, and decompresses them as they are read back.
+//! Large JSON blobs, say, then take a fraction of the memory of a cache.
+//!
+//! A compressed string starts with a header byte telling its encoding. These
+//! bytes never occur in UTF-8, so text stored as is can't be mistaken for a
+//! compressed string. Strings which aren't worth compressing but happen to
+//! start with one of them are stored behind a header byte too. As every
+//! string is decoded whatever the compression in effect, it can change at
+//! runtime: strings already stored keep their encoding.
+//!
+//! lz4 and zstd are behind the `lz4` and `zstd` features.
+
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{EvictionPolicy, Storage, StorageError, TableStats, Value, WriteStall};
+
+/// Strings shorter than this aren't compressed by default.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+
+/// The header of a string stored as is, which would otherwise be taken for a
+/// compressed one.
+const RAW: u8 = 0xfd;
+const LZ4: u8 = 0xfe;
+const ZSTD: u8 = 0xff;
+
+/// The level zstd compresses at, its default one.
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// How string values are compressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Values are stored as is.
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    /// The name of the compression in the configuration.
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Lz4 => "lz4",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// Whether the server was built with it.
+    pub fn is_available(self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Lz4 => cfg!(feature = "lz4"),
+            Compression::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    fn from_u8(compression: u8) -> Compression {
+        Compression::from_code(compression).unwrap_or_default()
+    }
+
+    /// The compression stored as `code`, see [`Compression::code`].
+    pub(crate) fn from_code(code: u8) -> Option<Compression> {
+        match code {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz4),
+            2 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// The byte standing for the compression in files.
+    pub(crate) fn code(self) -> u8 {
+        self as u8
+    }
+
+    /// Compresses `value` behind its header byte.
+    fn compress(self, value: &[u8]) -> Result<Bytes> {
+        let tag = match self {
+            Compression::Lz4 => LZ4,
+            Compression::Zstd => ZSTD,
+            compression => Err(StorageError::UnsupportedCompression(
+                compression.to_string(),
+            ))?,
+        };
+        Ok(header(tag, &self.compress_raw(value)?))
+    }
+
+    /// Compresses `data`, without a header.
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    pub(crate) fn compress_raw(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(zstd::bulk::compress(data, ZSTD_LEVEL)?),
+            compression => Err(StorageError::UnsupportedCompression(
+                compression.to_string(),
+            ))?,
+        }
+    }
+
+    /// Decompresses what [`Compression::compress_raw`] returned, or
+    /// [`Compression::compress`] without its header byte.
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    pub(crate) fn decompress(self, data: &[u8]) -> Result<Bytes> {
+        let corrupted = |err: &dyn fmt::Display| StorageError::Corrupted(err.to_string());
+        let decompressed: Vec<u8> = match self {
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                lz4_flex::decompress_size_prepended(data).map_err(|err| corrupted(&err))?
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::stream::decode_all(data).map_err(|err| corrupted(&err))?,
+            compression => Err(StorageError::UnsupportedCompression(
+                compression.to_string(),
+            ))?,
+        };
+        Ok(decompressed.into())
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Compression {
+    type Err = StorageError;
+
+    /// Only parses the compressions the server was built with.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let compression = match name.to_lowercase().as_str() {
+            "none" => Compression::None,
+            "lz4" => Compression::Lz4,
+            "zstd" => Compression::Zstd,
+            _ => return Err(StorageError::UnsupportedCompression(name.to_string())),
+        };
+        if !compression.is_available() {
+            return Err(StorageError::UnsupportedCompression(name.to_string()));
+        }
+        Ok(compression)
+    }
+}
+
+/// The compression in effect and how well it does, shared by several engines
+/// as the memory limit is. The compression can change at runtime.
+#[derive(Debug, Default)]
+pub struct ValueCompression {
+    compression: AtomicU8,
+    threshold: AtomicUsize,
+    /// The number of values compressed so far.
+    compressed: AtomicU64,
+    /// Their size before compression.
+    input_bytes: AtomicU64,
+    /// Their size after.
+    output_bytes: AtomicU64,
+}
+
+impl ValueCompression {
+    pub fn new(compression: Compression, threshold: usize) -> ValueCompression {
+        ValueCompression {
+            compression: AtomicU8::new(compression as u8),
+            threshold: AtomicUsize::new(threshold),
+            ..Default::default()
+        }
+    }
+
+    pub fn compression(&self) -> Compression {
+        Compression::from_u8(self.compression.load(Ordering::Relaxed))
+    }
+
+    pub fn set_compression(&self, compression: Compression) {
+        self.compression.store(compression as u8, Ordering::Relaxed);
+    }
+
+    /// Strings shorter than this are stored as is.
+    pub fn threshold(&self) -> usize {
+        self.threshold.load(Ordering::Relaxed)
+    }
+
+    pub fn set_threshold(&self, threshold: usize) {
+        self.threshold.store(threshold, Ordering::Relaxed);
+    }
+
+    /// The number of values compressed so far.
+    pub fn compressed(&self) -> u64 {
+        self.compressed.load(Ordering::Relaxed)
+    }
+
+    /// The size of the values compressed so far, before and after.
+    pub fn bytes(&self) -> (u64, u64) {
+        (
+            self.input_bytes.load(Ordering::Relaxed),
+            self.output_bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    /// How many times smaller the values compressed so far got, 1 if none
+    /// was.
+    pub fn ratio(&self) -> f64 {
+        match self.bytes() {
+            (_, 0) => 1.0,
+            (input, output) => input as f64 / output as f64,
+        }
+    }
+
+    /// `value` as it is stored.
+    fn encode(&self, value: Value) -> Result<Value> {
+        let Value::String(string) = value else {
+            return Ok(value);
+        };
+        let compression = self.compression();
+        if compression != Compression::None && string.len() >= self.threshold() {
+            let compressed = compression.compress(&string)?;
+            // Incompressible data grows a little instead.
+            if compressed.len() < string.len() {
+                self.compressed.fetch_add(1, Ordering::Relaxed);
+                let output = compressed.len() as u64;
+                self.input_bytes
+                    .fetch_add(string.len() as u64, Ordering::Relaxed);
+                self.output_bytes.fetch_add(output, Ordering::Relaxed);
+                return Ok(Value::String(compressed));
+            }
+        }
+        match string.first() {
+            Some(&first) if first >= RAW => Ok(Value::String(header(RAW, &string))),
+            _ => Ok(Value::String(string)),
+        }
+    }
+}
+
+/// `value` as it was before [`ValueCompression::encode`].
+fn decode(value: Value) -> Result<Value> {
+    match value {
+        Value::String(string) => Ok(Value::String(decode_string(string)?)),
+        value => Ok(value),
+    }
+}
+
+fn decode_string(string: Bytes) -> Result<Bytes> {
+    match string.first() {
+        Some(&RAW) => Ok(string.slice(1..)),
+        Some(&LZ4) => Compression::Lz4.decompress(&string[1..]),
+        Some(&ZSTD) => Compression::Zstd.decompress(&string[1..]),
+        _ => Ok(string),
+    }
+}
+
+fn header(header: u8, data: &[u8]) -> Bytes {
+    let mut bytes = BytesMut::with_capacity(data.len() + 1);
+    bytes.put_u8(header);
+    bytes.put_slice(data);
+    bytes.freeze()
+}
+
+/// A [`Storage`] engine compressing the string values stored in `inner` by a
+/// [`ValueCompression`]. Engines under it see the values compressed, an
+/// [`Accounted`](crate::Accounted) one accounts for their compressed size.
+///
+/// Read-modify-write cycles take a value out and put it back, often unchanged
+/// when they only read it. The last string decoded by [`Storage::remove`] is
+/// remembered along with its encoding, which is stored back as is if the
+/// same string is put back under the same key, rather than compressed again.
+pub struct Compressed<S> {
+    inner: S,
+    compression: Arc<ValueCompression>,
+    removed: Option<Removed>,
+}
+
+struct Removed {
+    key: Bytes,
+    decoded: Bytes,
+    encoded: Bytes,
+}
+
+impl<S: Storage> Compressed<S> {
+    pub fn new(inner: S, compression: Arc<ValueCompression>) -> Compressed<S> {
+        Compressed {
+            inner,
+            compression,
+            removed: None,
+        }
+    }
+
+    /// `value` as it is stored under `key`.
+    fn encode(&mut self, key: &Bytes, value: Value) -> Result<Value> {
+        if let (Some(removed), Value::String(string)) = (self.removed.take(), &value) {
+            let unchanged = removed.decoded.as_ptr() == string.as_ptr()
+                && removed.decoded.len() == string.len();
+            if unchanged && removed.key == key {
+                return Ok(Value::String(removed.encoded));
+            }
+        }
+        self.compression.encode(value)
+    }
+}
+
+impl<S: Storage> Storage for Compressed<S> {
+    fn put(&mut self, key: Bytes, value: Value) -> Result<()> {
+        let value = self.encode(&key, value)?;
+        self.inner.put(key, value)
+    }
+
+    fn delete(&mut self, key: Bytes) -> Result<()> {
+        self.inner.delete(key)
+    }
+
+    fn get(&self, key: Bytes) -> Result<Option<Value>> {
+        self.inner.get(key)?.map(decode).transpose()
+    }
+
+    fn contains(&self, key: Bytes) -> Result<bool> {
+        self.inner.contains(key)
+    }
+
+    fn remove(&mut self, key: Bytes) -> Result<Option<Value>> {
+        self.removed = None;
+        let encoded = match self.inner.remove(key.clone())? {
+            Some(Value::String(encoded)) => encoded,
+            value => return Ok(value),
+        };
+        let decoded = decode_string(encoded.clone())?;
+        // Strings stored as is are their own encoding.
+        if decoded.as_ptr() != encoded.as_ptr() {
+            self.removed = Some(Removed {
+                key,
+                decoded: decoded.clone(),
+                encoded,
+            });
+        }
+        Ok(Some(Value::String(decoded)))
+    }
+
+    fn scan(&self) -> Result<Vec<(Bytes, Value)>> {
+        self.inner
+            .scan()?
+            .into_iter()
+            .map(|(key, value)| Ok((key, decode(value)?)))
+            .collect()
+    }
+
+    fn keys(&self) -> Result<Vec<Bytes>> {
+        self.inner.keys()
+    }
+
+    fn keys_with_prefix(&self, prefix: &[u8]) -> Result<Vec<Bytes>> {
+        self.inner.keys_with_prefix(prefix)
+    }
+
+    fn len(&self) -> Result<usize> {
+        self.inner.len()
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.inner.clear()
+    }
+
+    fn shard_count(&self) -> Option<usize> {
+        self.inner.shard_count()
+    }
+
+    fn reshard(&mut self, shards: usize) -> Result<()> {
+        self.inner.reshard(shards)
+    }
+
+    fn migrate_step(&mut self) -> bool {
+        self.inner.migrate_step()
+    }
+
+    fn supports_ttl(&self) -> bool {
+        self.inner.supports_ttl()
+    }
+
+    fn blocks(&self) -> bool {
+        self.inner.blocks()
+    }
+
+    fn write_stall(&self) -> WriteStall {
+        self.inner.write_stall()
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        self.inner.compact()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn put_with_ttl(&mut self, key: Bytes, value: Value, expires_at: SystemTime) -> Result<()> {
+        let value = self.encode(&key, value)?;
+        self.inner.put_with_ttl(key, value, expires_at)
+    }
+
+    fn expires_at(&self, key: &Bytes) -> Option<SystemTime> {
+        self.inner.expires_at(key)
+    }
+
+    fn expire(&mut self, now: SystemTime) -> Vec<Bytes> {
+        self.inner.expire(now)
+    }
+
+    fn least_recent_use(&self) -> Option<u64> {
+        self.inner.least_recent_use()
+    }
+
+    fn table_stats(&self) -> Option<TableStats> {
+        self.inner.table_stats()
+    }
+
+    fn least_frequent_use(&self) -> Option<u8> {
+        self.inner.least_frequent_use()
+    }
+
+    fn frequency(&self, key: &Bytes) -> Option<u8> {
+        self.inner.frequency(key)
+    }
+
+    fn evict(&mut self, policy: EvictionPolicy) -> Option<Bytes> {
+        self.inner.evict(policy)
+    }
+
+    /// Moves the value as it is stored, without decompressing it.
+    fn rename(&mut self, src: Bytes, dst: Bytes) -> Result<bool> {
+        self.inner.rename(src, dst)
+    }
+
+    /// Copies the value as it is stored, without decompressing it.
+    fn copy(&mut self, src: Bytes, dst: Bytes, replace: bool) -> Result<bool> {
+        self.inner.copy(src, dst, replace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StdHashKV;
+
+    fn compressed(compression: Compression) -> (Compressed<StdHashKV>, Arc<ValueCompression>) {
+        let compression = Arc::new(ValueCompression::new(compression, 64));
+        (
+            Compressed::new(StdHashKV::new(), compression.clone()),
+            compression,
+        )
+    }
+
+    fn string(value: impl Into<Bytes>) -> Value {
+        Value::String(value.into())
+    }
+
+    #[test]
+    fn test_uncompressed_strings() {
+        let (mut kv, compression) = compressed(Compression::None);
+        let json = Bytes::from("{\"field\":\"value\"}".repeat(100));
+        let escaped = Bytes::from(vec![RAW, LZ4, ZSTD]);
+        for value in [json.clone(), escaped.clone(), Bytes::new()] {
+            kv.put(value.clone(), string(value.clone())).unwrap();
+            assert_eq!(kv.get(value.clone()).unwrap(), Some(string(value)));
+        }
+        // Text is stored as is, the string starting with a header escaped.
+        assert_eq!(kv.inner.get(json.clone()).unwrap(), Some(string(json)));
+        let stored = kv.inner.get(escaped.clone()).unwrap();
+        assert_eq!(stored, Some(string(header(RAW, &escaped))));
+        assert_eq!(compression.compressed(), 0);
+        assert_eq!(compression.ratio(), 1.0);
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_lz4() {
+        check_compression(Compression::Lz4);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() {
+        check_compression(Compression::Zstd);
+    }
+
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    fn check_compression(algorithm: Compression) {
+        let (mut kv, compression) = compressed(algorithm);
+        let json = Bytes::from("{\"field\":\"value\"}".repeat(100));
+        kv.put(Bytes::from("json"), string(json.clone())).unwrap();
+        kv.put(Bytes::from("short"), string("value")).unwrap();
+        let stored = kv.inner.get(Bytes::from("json")).unwrap().unwrap();
+        assert!(stored.approximate_size() < json.len() / 4);
+        assert_eq!(
+            kv.get(Bytes::from("json")).unwrap(),
+            Some(string(json.clone()))
+        );
+        assert_eq!(kv.get(Bytes::from("short")).unwrap(), Some(string("value")));
+        assert_eq!(compression.compressed(), 1);
+        assert!(compression.ratio() > 4.0);
+
+        // Values already stored are still read once compression is off.
+        compression.set_compression(Compression::None);
+        kv.put(Bytes::from("plain"), string(json.clone())).unwrap();
+        assert_eq!(
+            kv.inner.get(Bytes::from("plain")).unwrap(),
+            Some(string(json.clone()))
+        );
+        assert!(kv
+            .rename(Bytes::from("json"), Bytes::from("moved"))
+            .unwrap());
+        // Put back unchanged, as read-modify-write cycles which only read do.
+        compression.set_compression(algorithm);
+        let moved = kv.remove(Bytes::from("moved")).unwrap();
+        assert_eq!(moved, Some(string(json.clone())));
+        kv.put(Bytes::from("moved"), moved.unwrap()).unwrap();
+        assert_eq!(compression.compressed(), 1);
+        assert_eq!(kv.get(Bytes::from("moved")).unwrap(), Some(string(json)));
+    }
+}
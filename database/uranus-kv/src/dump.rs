@@ -0,0 +1,94 @@
+//! Self-checking serialization of single values, for moving keys between
+//! servers
+//!
+//! A dump is the [`codec`](crate::codec) encoding of the value, the one the
+//! LSM engine persists, followed by the version of that encoding on 2 bytes and
+//! the CRC-32C of both on 4, little-endian. A blob which was damaged on the
+//! way, or made by a newer server, is refused rather than restored. The
+//! version is raised whenever the encoding changes, and older ones stay
+//! readable.
+//!
+
+use bytes::{Buf, BufMut, Bytes};
+use thiserror::Error;
+
+use crate::{crc32c, InvalidEncoding, Value};
+
+/// The version of the encoding written by [`dump`].
+pub const DUMP_VERSION: u16 = 1;
+
+/// The version and the checksum following the value.
+const TRAILER_LEN: usize = 2 + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum DumpError {
+    #[error("the checksum of the dump doesn't match")]
+    Checksum,
+    #[error("dump version {0} is unknown")]
+    Version(u16),
+    #[error(transparent)]
+    Encoding(#[from] InvalidEncoding),
+}
+
+/// Serializes `value`, which [`restore`] reads back.
+pub fn dump(value: &Value) -> Bytes {
+    let mut blob = vec![];
+    value.encode(&mut blob);
+    blob.put_u16_le(DUMP_VERSION);
+    let checksum = crc32c(&blob);
+    blob.put_u32_le(checksum);
+    Bytes::from(blob)
+}
+
+/// Reads a value serialized by [`dump`]. Its byte strings share `blob`.
+pub fn restore(mut blob: Bytes) -> Result<Value, DumpError> {
+    if blob.len() < TRAILER_LEN {
+        return Err(InvalidEncoding.into());
+    }
+    let mut checksum = blob.split_off(blob.len() - 4);
+    if crc32c(&blob) != checksum.get_u32_le() {
+        return Err(DumpError::Checksum);
+    }
+    let mut version = blob.split_off(blob.len() - 2);
+    match version.get_u16_le() {
+        DUMP_VERSION => Ok(Value::decode(blob)?),
+        version => Err(DumpError::Version(version)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_dump() {
+        let hash = HashMap::from([(Bytes::from("field"), Bytes::from("value"))]);
+        for value in [Value::String(Bytes::from("a\r\nb")), Value::Hash(hash)] {
+            assert_eq!(restore(dump(&value)), Ok(value));
+        }
+
+        let blob = dump(&Value::String(Bytes::from("value"))).to_vec();
+        let mut flipped = blob.clone();
+        flipped[3] ^= 1;
+        assert_eq!(restore(Bytes::from(flipped)), Err(DumpError::Checksum));
+        assert_eq!(
+            restore(Bytes::from(blob[..blob.len() - 1].to_vec())),
+            Err(DumpError::Checksum)
+        );
+        assert_eq!(
+            restore(Bytes::from_static(b"\x01")),
+            Err(DumpError::Encoding(InvalidEncoding))
+        );
+
+        let mut newer = blob[..blob.len() - TRAILER_LEN].to_vec();
+        newer.put_u16_le(DUMP_VERSION + 1);
+        let checksum = crc32c(&newer);
+        newer.put_u32_le(checksum);
+        assert_eq!(
+            restore(Bytes::from(newer)),
+            Err(DumpError::Version(DUMP_VERSION + 1))
+        );
+    }
+}
@@ -0,0 +1,631 @@
+//! Bounding the memory held by the keys
+//!
+//! [`Accounted`] wraps an engine and tracks the approximate size of every key
+//! it stores in a [`MemoryLimit`], which may be shared by several engines, such
+//! as the logical databases of a server. Once the limit is exceeded, keys are
+//! [evicted](Storage::evict) by the [`EvictionPolicy`] in effect.
+//!
+//! For [`EvictionPolicy::Lfu`], every key has an 8-bit counter of how often it
+//! is used, as Redis' LFU: it is incremented with a probability falling as it
+//! grows, so that 255 stands for about a million uses, and decremented for
+//! every minute the key goes unused, so that keys once hot cool down. The key
+//! evicted is the least frequently used of a few drawn at random.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{linked_list::LinkedList, Storage, StorageError, TableStats, Value, WriteStall};
+
+/// Which keys go first when memory runs out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Nothing is evicted, writes fail instead.
+    #[default]
+    NoEviction,
+    /// The least recently used keys.
+    Lru,
+    /// Keys drawn at random.
+    Random,
+    /// The least frequently used keys, see [`Storage::frequency`].
+    Lfu,
+}
+
+impl EvictionPolicy {
+    /// The name of the policy in the configuration, as in Redis.
+    pub fn name(self) -> &'static str {
+        match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::Lru => "allkeys-lru",
+            EvictionPolicy::Random => "allkeys-random",
+            EvictionPolicy::Lfu => "allkeys-lfu",
+        }
+    }
+
+    fn from_u8(policy: u8) -> EvictionPolicy {
+        match policy {
+            1 => EvictionPolicy::Lru,
+            2 => EvictionPolicy::Random,
+            3 => EvictionPolicy::Lfu,
+            _ => EvictionPolicy::NoEviction,
+        }
+    }
+}
+
+impl fmt::Display for EvictionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = StorageError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "noeviction" => Ok(EvictionPolicy::NoEviction),
+            "allkeys-lru" => Ok(EvictionPolicy::Lru),
+            "allkeys-random" => Ok(EvictionPolicy::Random),
+            "allkeys-lfu" => Ok(EvictionPolicy::Lfu),
+            _ => Err(StorageError::UnknownPolicy(name.to_string())),
+        }
+    }
+}
+
+/// How much memory the keys may take, and how much they do. All of it can
+/// change at runtime.
+#[derive(Debug, Default)]
+pub struct MemoryLimit {
+    /// In bytes, 0 for no limit.
+    max: AtomicUsize,
+    policy: AtomicU8,
+    used: AtomicUsize,
+    evicted: AtomicU64,
+    /// Ticks at every use of a key, so that engines sharing the limit can
+    /// tell which of them holds the least recently used one.
+    clock: AtomicU64,
+}
+
+impl MemoryLimit {
+    pub fn new(max: usize, policy: EvictionPolicy) -> MemoryLimit {
+        MemoryLimit {
+            max: AtomicUsize::new(max),
+            policy: AtomicU8::new(policy as u8),
+            used: AtomicUsize::new(0),
+            evicted: AtomicU64::new(0),
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    pub fn max(&self) -> usize {
+        self.max.load(Ordering::Relaxed)
+    }
+
+    pub fn set_max(&self, max: usize) {
+        self.max.store(max, Ordering::Relaxed);
+    }
+
+    pub fn policy(&self) -> EvictionPolicy {
+        EvictionPolicy::from_u8(self.policy.load(Ordering::Relaxed))
+    }
+
+    pub fn set_policy(&self, policy: EvictionPolicy) {
+        self.policy.store(policy as u8, Ordering::Relaxed);
+    }
+
+    /// The approximate size of the keys stored, in bytes.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// The number of keys evicted so far.
+    pub fn evicted(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+
+    pub fn exceeded(&self) -> bool {
+        let max = self.max();
+        max != 0 && self.used() > max
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// The bookkeeping of a key on top of its key and value.
+const ENTRY_OVERHEAD: usize = 64;
+
+/// The approximate size of `key` holding `value`.
+pub fn entry_size(key: &Bytes, value: &Value) -> usize {
+    key.len() + value.approximate_size() + ENTRY_OVERHEAD
+}
+
+/// The counter of a new key, as Redis' `LFU_INIT_VAL`, so that it isn't
+/// evicted before it had a chance to be used again.
+const LFU_INIT_VAL: u8 = 5;
+/// How slowly the counter grows, as Redis' default `lfu-log-factor`.
+const LFU_LOG_FACTOR: f64 = 10.0;
+/// The minutes of disuse after which the counter is decremented, as Redis'
+/// default `lfu-decay-time`.
+const LFU_DECAY_MINUTES: u16 = 1;
+/// The keys drawn to evict the least frequently used of them, as Redis'
+/// default `maxmemory-samples`.
+const LFU_SAMPLES: usize = 5;
+
+/// The counter of a key and the minute it was last used at, modulo 2^16,
+/// packed as `minute << 8 | counter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Lfu(u32);
+
+impl Lfu {
+    fn new(counter: u8, minute: u16) -> Lfu {
+        Lfu((minute as u32) << 8 | counter as u32)
+    }
+
+    fn minute(self) -> u16 {
+        (self.0 >> 8) as u16
+    }
+
+    /// The counter decremented for every [`LFU_DECAY_MINUTES`] since the key
+    /// was last used.
+    fn counter(self, now: u16) -> u8 {
+        let periods = now.wrapping_sub(self.minute()) / LFU_DECAY_MINUTES;
+        (self.0 as u8).saturating_sub(periods.min(u8::MAX as u16) as u8)
+    }
+
+    /// Counts a use at `now`, incrementing the counter with a probability of
+    /// `1 / ((counter - LFU_INIT_VAL) * LFU_LOG_FACTOR + 1)`, `random` being
+    /// drawn uniformly in `[0, 1)`.
+    fn used(self, now: u16, random: f64) -> Lfu {
+        let counter = self.counter(now);
+        if counter == u8::MAX {
+            return Lfu::new(counter, now);
+        }
+        let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+        let probability = 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+        let counter = if random < probability {
+            counter + 1
+        } else {
+            counter
+        };
+        Lfu::new(counter, now)
+    }
+}
+
+/// The current minute, modulo 2^16.
+fn minute() -> u16 {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (elapsed.as_secs() / 60) as u16
+}
+
+/// A [`Storage`] engine accounting for the memory of its keys in a
+/// [`MemoryLimit`], and able to [evict](Storage::evict) them.
+pub struct Accounted<S> {
+    inner: S,
+    limit: Arc<MemoryLimit>,
+    entries: HashMap<Bytes, Entry>,
+    /// The keys by recency of use, the least recent at the tail. Reads
+    /// reorder it, hence the mutex.
+    recency: Mutex<LinkedList>,
+    /// The keys by their node in `recency`, to find the key of the tail, and
+    /// to draw keys at random.
+    nodes: Vec<Option<Bytes>>,
+    /// When the key of each node was last used, by the clock of the limit.
+    used_at: Vec<AtomicU64>,
+    /// How often the key of each node is used, see [`Lfu`].
+    lfu: Vec<AtomicU32>,
+    /// The key last removed and its [`Lfu`], which are restored if it is put
+    /// right back, as servers updating values in place do.
+    removed: Option<(Bytes, Lfu)>,
+    /// xorshift64 state for random eviction and LFU increments. Reads draw
+    /// from it too, hence the atomic.
+    rng: AtomicU64,
+}
+
+struct Entry {
+    size: usize,
+    node: usize,
+}
+
+impl<S: Storage> Accounted<S> {
+    /// Accounts for the keys `inner` holds already, and for the later ones.
+    pub fn new(inner: S, limit: Arc<MemoryLimit>) -> Result<Accounted<S>> {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+        let mut accounted = Accounted {
+            inner,
+            limit,
+            entries: HashMap::new(),
+            recency: Mutex::new(LinkedList::with_capacity(0)),
+            nodes: vec![],
+            used_at: vec![],
+            lfu: vec![],
+            removed: None,
+            rng: AtomicU64::new(seed | 1),
+        };
+        for (key, value) in accounted.inner.scan()? {
+            accounted.track(key.clone(), entry_size(&key, &value));
+        }
+        Ok(accounted)
+    }
+
+    fn track(&mut self, key: Bytes, size: usize) {
+        let recency = self.recency.get_mut().unwrap();
+        if let Some(entry) = self.entries.get_mut(&key) {
+            self.limit.used.fetch_sub(entry.size, Ordering::Relaxed);
+            self.limit.used.fetch_add(size, Ordering::Relaxed);
+            entry.size = size;
+            recency.promote(entry.node);
+            let node = entry.node;
+            self.used(node);
+            return;
+        }
+        let node = recency.push_head(0);
+        if self.nodes.len() <= node {
+            self.nodes.resize(node + 1, None);
+            self.used_at.resize_with(node + 1, AtomicU64::default);
+            self.lfu.resize_with(node + 1, AtomicU32::default);
+        }
+        self.nodes[node] = Some(key.clone());
+        self.used_at[node].store(self.limit.tick(), Ordering::Relaxed);
+        match self.removed.take() {
+            Some((removed, lfu)) if removed == key => {
+                self.lfu[node].store(lfu.0, Ordering::Relaxed);
+                self.used(node);
+            }
+            _ => {
+                let lfu = Lfu::new(LFU_INIT_VAL, minute());
+                self.lfu[node].store(lfu.0, Ordering::Relaxed);
+            }
+        }
+        self.limit.used.fetch_add(size, Ordering::Relaxed);
+        self.entries.insert(key, Entry { size, node });
+    }
+
+    fn untrack(&mut self, key: &Bytes) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.limit.used.fetch_sub(entry.size, Ordering::Relaxed);
+            self.recency.get_mut().unwrap().remove(entry.node);
+            self.nodes[entry.node] = None;
+        }
+    }
+
+    fn untrack_all(&mut self) {
+        let used = self.entries.values().map(|entry| entry.size).sum();
+        self.limit.used.fetch_sub(used, Ordering::Relaxed);
+        self.entries.clear();
+        self.recency = Mutex::new(LinkedList::with_capacity(0));
+        self.nodes.clear();
+        self.used_at.clear();
+        self.lfu.clear();
+        self.removed = None;
+    }
+
+    /// Counts a use of the key of `node`, for both LRU and LFU.
+    fn used(&self, node: usize) {
+        self.used_at[node].store(self.limit.tick(), Ordering::Relaxed);
+        let lfu = Lfu(self.lfu[node].load(Ordering::Relaxed));
+        let random = (self.random() >> 11) as f64 / (1u64 << 53) as f64;
+        let lfu = lfu.used(minute(), random);
+        self.lfu[node].store(lfu.0, Ordering::Relaxed);
+    }
+
+    /// Concurrent reads may draw the same number, which is fine for sampling.
+    fn random(&self) -> u64 {
+        let mut rng = self.rng.load(Ordering::Relaxed);
+        rng ^= rng << 13;
+        rng ^= rng >> 7;
+        rng ^= rng << 17;
+        self.rng.store(rng, Ordering::Relaxed);
+        rng
+    }
+
+    fn random_key(&self) -> Option<Bytes> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        // Nodes are reused once freed, so there are few holes to skip.
+        let start = self.random() as usize % self.nodes.len();
+        let nodes = self.nodes[start..].iter().chain(&self.nodes[..start]);
+        nodes.flatten().next().cloned()
+    }
+
+    /// The least frequently used of [`LFU_SAMPLES`] keys drawn at random, or
+    /// of all of them if there are no more, and its counter.
+    fn least_frequent_key(&self) -> Option<(Bytes, u8)> {
+        let now = minute();
+        let sampled: Vec<Bytes> = if self.entries.len() <= LFU_SAMPLES {
+            self.entries.keys().cloned().collect()
+        } else {
+            (0..LFU_SAMPLES).filter_map(|_| self.random_key()).collect()
+        };
+        sampled
+            .into_iter()
+            .map(|key| {
+                let node = self.entries[&key].node;
+                (
+                    key,
+                    Lfu(self.lfu[node].load(Ordering::Relaxed)).counter(now),
+                )
+            })
+            .min_by_key(|(_, counter)| *counter)
+    }
+}
+
+impl<S: Storage> Storage for Accounted<S> {
+    fn put(&mut self, key: Bytes, value: Value) -> Result<()> {
+        let size = entry_size(&key, &value);
+        self.inner.put(key.clone(), value)?;
+        self.track(key, size);
+        Ok(())
+    }
+
+    fn delete(&mut self, key: Bytes) -> Result<()> {
+        self.inner.delete(key.clone())?;
+        self.untrack(&key);
+        Ok(())
+    }
+
+    /// Counts as a use of `key`.
+    fn get(&self, key: Bytes) -> Result<Option<Value>> {
+        let value = self.inner.get(key.clone())?;
+        if let Some(entry) = self.entries.get(&key) {
+            self.recency.lock().unwrap().promote(entry.node);
+            self.used(entry.node);
+        }
+        Ok(value)
+    }
+
+    fn contains(&self, key: Bytes) -> Result<bool> {
+        self.inner.contains(key)
+    }
+
+    fn remove(&mut self, key: Bytes) -> Result<Option<Value>> {
+        let value = self.inner.remove(key.clone())?;
+        self.removed = (self.entries.get(&key)).map(|entry| {
+            let lfu = Lfu(self.lfu[entry.node].load(Ordering::Relaxed));
+            (key.clone(), lfu)
+        });
+        self.untrack(&key);
+        Ok(value)
+    }
+
+    fn scan(&self) -> Result<Vec<(Bytes, Value)>> {
+        self.inner.scan()
+    }
+
+    fn keys(&self) -> Result<Vec<Bytes>> {
+        self.inner.keys()
+    }
+
+    fn keys_with_prefix(&self, prefix: &[u8]) -> Result<Vec<Bytes>> {
+        self.inner.keys_with_prefix(prefix)
+    }
+
+    fn len(&self) -> Result<usize> {
+        self.inner.len()
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.inner.clear()?;
+        self.untrack_all();
+        Ok(())
+    }
+
+    fn shard_count(&self) -> Option<usize> {
+        self.inner.shard_count()
+    }
+
+    fn reshard(&mut self, shards: usize) -> Result<()> {
+        self.inner.reshard(shards)
+    }
+
+    fn migrate_step(&mut self) -> bool {
+        self.inner.migrate_step()
+    }
+
+    fn supports_ttl(&self) -> bool {
+        self.inner.supports_ttl()
+    }
+
+    fn blocks(&self) -> bool {
+        self.inner.blocks()
+    }
+
+    fn write_stall(&self) -> WriteStall {
+        self.inner.write_stall()
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        self.inner.compact()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn put_with_ttl(&mut self, key: Bytes, value: Value, expires_at: SystemTime) -> Result<()> {
+        let size = entry_size(&key, &value);
+        self.inner.put_with_ttl(key.clone(), value, expires_at)?;
+        self.track(key, size);
+        Ok(())
+    }
+
+    fn expires_at(&self, key: &Bytes) -> Option<SystemTime> {
+        self.inner.expires_at(key)
+    }
+
+    fn expire(&mut self, now: SystemTime) -> Vec<Bytes> {
+        let expired = self.inner.expire(now);
+        for key in &expired {
+            self.untrack(key);
+        }
+        expired
+    }
+
+    fn compare_and_swap(&mut self, key: Bytes, expected: &Value, new: Value) -> Result<bool> {
+        let size = entry_size(&key, &new);
+        let swapped = self.inner.compare_and_swap(key.clone(), expected, new)?;
+        if swapped {
+            self.track(key, size);
+        }
+        Ok(swapped)
+    }
+
+    fn least_recent_use(&self) -> Option<u64> {
+        let tail = self.recency.lock().unwrap().tail()?;
+        Some(self.used_at[tail].load(Ordering::Relaxed))
+    }
+
+    /// Of a sample of the keys, see [`Accounted::least_frequent_key`].
+    fn table_stats(&self) -> Option<TableStats> {
+        self.inner.table_stats()
+    }
+
+    fn least_frequent_use(&self) -> Option<u8> {
+        Some(self.least_frequent_key()?.1)
+    }
+
+    fn frequency(&self, key: &Bytes) -> Option<u8> {
+        let entry = self.entries.get(key)?;
+        Some(Lfu(self.lfu[entry.node].load(Ordering::Relaxed)).counter(minute()))
+    }
+
+    fn evict(&mut self, policy: EvictionPolicy) -> Option<Bytes> {
+        let key = match policy {
+            EvictionPolicy::NoEviction => return None,
+            EvictionPolicy::Lru => {
+                let tail = self.recency.get_mut().unwrap().tail()?;
+                self.nodes[tail].clone()?
+            }
+            EvictionPolicy::Random => self.random_key()?,
+            EvictionPolicy::Lfu => self.least_frequent_key()?.0,
+        };
+        self.inner.remove(key.clone()).ok()?;
+        self.untrack(&key);
+        self.limit.evicted.fetch_add(1, Ordering::Relaxed);
+        Some(key)
+    }
+}
+
+impl<S> Drop for Accounted<S> {
+    fn drop(&mut self) {
+        let used = self.entries.values().map(|entry| entry.size).sum();
+        self.limit.used.fetch_sub(used, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StdHashKV;
+
+    fn key(i: usize) -> Bytes {
+        Bytes::from(format!("key{}", i))
+    }
+
+    fn value() -> Value {
+        Value::String(Bytes::from(vec![0; 100]))
+    }
+
+    #[test]
+    fn test_accounting() {
+        let limit = Arc::new(MemoryLimit::new(0, EvictionPolicy::Lru));
+        let mut kv = Accounted::new(StdHashKV::new(), limit.clone()).unwrap();
+        let size = entry_size(&key(0), &value());
+        for i in 0..10 {
+            kv.put(key(i), value()).unwrap();
+        }
+        kv.put(key(0), value()).unwrap();
+        assert_eq!(limit.used(), 10 * size);
+        kv.remove(key(0)).unwrap();
+        kv.delete(key(1)).unwrap();
+        assert_eq!(limit.used(), 8 * size);
+        kv.clear().unwrap();
+        assert_eq!(limit.used(), 0);
+
+        kv.put(key(0), value()).unwrap();
+        drop(kv);
+        assert_eq!(limit.used(), 0);
+
+        let mut inner = StdHashKV::new();
+        inner.put(key(0), value()).unwrap();
+        inner.put(key(1), value()).unwrap();
+        let _kv = Accounted::new(inner, limit.clone()).unwrap();
+        assert_eq!(limit.used(), 2 * size);
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let limit = Arc::new(MemoryLimit::new(0, EvictionPolicy::Lru));
+        let mut kv = Accounted::new(StdHashKV::new(), limit.clone()).unwrap();
+        for i in 0..4 {
+            kv.put(key(i), value()).unwrap();
+        }
+        kv.get(key(0)).unwrap();
+        kv.put(key(1), value()).unwrap();
+
+        assert_eq!(kv.evict(EvictionPolicy::Lru), Some(key(2)));
+        assert_eq!(kv.evict(EvictionPolicy::Lru), Some(key(3)));
+        assert_eq!(kv.evict(EvictionPolicy::Lru), Some(key(0)));
+        assert_eq!(kv.get(key(2)).unwrap(), None);
+        assert_eq!(kv.evict(EvictionPolicy::NoEviction), None);
+        assert!(kv.evict(EvictionPolicy::Random).is_some());
+        assert_eq!(kv.evict(EvictionPolicy::Random), None);
+        assert_eq!(limit.evicted(), 4);
+        assert_eq!(limit.used(), 0);
+    }
+
+    #[test]
+    fn test_lfu_counter() {
+        let lfu = Lfu::new(LFU_INIT_VAL, 100);
+        assert_eq!(lfu.used(100, 0.99).counter(100), LFU_INIT_VAL + 1);
+        let hot = Lfu::new(LFU_INIT_VAL + 10, 100);
+        // Incremented with a probability of 1 / 101.
+        assert_eq!(hot.used(100, 0.5).counter(100), LFU_INIT_VAL + 10);
+        assert_eq!(hot.used(100, 0.001).counter(100), LFU_INIT_VAL + 11);
+        assert_eq!(Lfu::new(u8::MAX, 100).used(100, 0.0).counter(100), u8::MAX);
+
+        assert_eq!(hot.counter(103), LFU_INIT_VAL + 7);
+        assert_eq!(hot.counter(1000), 0);
+        // The minute wraps around.
+        assert_eq!(Lfu::new(10, u16::MAX).counter(1), 8);
+    }
+
+    #[test]
+    fn test_lfu_eviction() {
+        let limit = Arc::new(MemoryLimit::new(0, EvictionPolicy::Lfu));
+        let mut kv = Accounted::new(StdHashKV::new(), limit.clone()).unwrap();
+        kv.put(key(0), value()).unwrap();
+        assert_eq!(kv.frequency(&key(0)), Some(LFU_INIT_VAL));
+        for _ in 0..100 {
+            kv.get(key(0)).unwrap();
+        }
+        let hot = kv.frequency(&key(0)).unwrap();
+        assert!(hot > LFU_INIT_VAL + 1, "{}", hot);
+        // Updates take the value out and put it back, the counter stays.
+        let value = kv.remove(key(0)).unwrap().unwrap();
+        kv.put(key(0), value).unwrap();
+        assert!(kv.frequency(&key(0)).unwrap() >= hot);
+
+        kv.put(key(1), self::value()).unwrap();
+        for _ in 0..10 {
+            assert_eq!(kv.evict(EvictionPolicy::Lfu), Some(key(1)));
+            kv.put(key(1), self::value()).unwrap();
+        }
+        assert_eq!(kv.frequency(&key(2)), None);
+        assert_eq!(limit.evicted(), 10);
+    }
+}
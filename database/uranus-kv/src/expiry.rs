@@ -0,0 +1,58 @@
+//! Expiry deadlines of keys
+//!
+
+use std::{collections::HashMap, time::SystemTime};
+
+use bytes::Bytes;
+
+/// When keys having a TTL expire. Engines expiring keys natively keep one next
+/// to their data, others get one kept for them by their user.
+#[derive(Debug, Default)]
+pub struct Expiries {
+    deadlines: HashMap<Bytes, SystemTime>,
+}
+
+impl Expiries {
+    pub fn new() -> Expiries {
+        Expiries::default()
+    }
+
+    pub fn set(&mut self, key: Bytes, expires_at: SystemTime) {
+        self.deadlines.insert(key, expires_at);
+    }
+
+    /// Makes `key` persistent.
+    pub fn clear(&mut self, key: &Bytes) {
+        self.deadlines.remove(key);
+    }
+
+    pub fn get(&self, key: &Bytes) -> Option<SystemTime> {
+        self.deadlines.get(key).copied()
+    }
+
+    pub fn is_expired(&self, key: &Bytes, now: SystemTime) -> bool {
+        matches!(self.get(key), Some(expires_at) if expires_at <= now)
+    }
+
+    /// How many keys have expired by `now`.
+    pub fn count_expired(&self, now: SystemTime) -> usize {
+        self.deadlines
+            .values()
+            .filter(|expires_at| **expires_at <= now)
+            .count()
+    }
+
+    /// Forgets the keys expired by `now`, and returns them.
+    pub fn take_expired(&mut self, now: SystemTime) -> Vec<Bytes> {
+        let expired: Vec<Bytes> = self
+            .deadlines
+            .iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            self.deadlines.remove(key);
+        }
+        expired
+    }
+}
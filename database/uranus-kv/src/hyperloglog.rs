@@ -0,0 +1,167 @@
+//! HyperLogLog, counting distinct elements approximately in fixed space
+//!
+//! Each element is hashed to one of [`HLL_REGISTERS`] registers, which keeps
+//! the longest run of trailing zeros seen among the rest of the hashes it got.
+//! The count is estimated from the harmonic mean of the registers, within
+//! about 0.81% (1.04 / √[`HLL_REGISTERS`]) of the truth.
+//!
+//! Registers are stored densely, a byte each after a header, so that a
+//! HyperLogLog fits in a string value.
+//!
+
+use bytes::Bytes;
+
+/// The number of bits of the hash choosing the register.
+const INDEX_BITS: u32 = 14;
+pub const HLL_REGISTERS: usize = 1 << INDEX_BITS;
+/// The header of the string encoding.
+const MAGIC: &[u8] = b"HYLL";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    registers: Box<[u8]>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog {
+            registers: vec![0; HLL_REGISTERS].into_boxed_slice(),
+        }
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> HyperLogLog {
+        HyperLogLog::default()
+    }
+
+    /// Decodes a string made by [`HyperLogLog::to_bytes`], none if it isn't
+    /// one.
+    pub fn from_bytes(data: &[u8]) -> Option<HyperLogLog> {
+        let registers = data.strip_prefix(MAGIC)?;
+        let max = (64 - INDEX_BITS + 1) as u8;
+        if registers.len() != HLL_REGISTERS || registers.iter().any(|&register| register > max) {
+            return None;
+        }
+        Some(HyperLogLog {
+            registers: registers.into(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut data = Vec::with_capacity(MAGIC.len() + HLL_REGISTERS);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&self.registers);
+        Bytes::from(data)
+    }
+
+    /// Counts `element`, returns whether a register changed, that is whether
+    /// the estimate may have.
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let hash = hash(element);
+        let index = (hash & (HLL_REGISTERS as u64 - 1)) as usize;
+        // The sentinel bit bounds the run for the hashes ending in zeros.
+        let rest = (hash >> INDEX_BITS) | (1 << (64 - INDEX_BITS));
+        let run = rest.trailing_zeros() as u8 + 1;
+        if run > self.registers[index] {
+            self.registers[index] = run;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Makes this count the elements of `other` too.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (register, &theirs) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *register = (*register).max(theirs);
+        }
+    }
+
+    /// The estimated number of distinct elements added.
+    pub fn count(&self) -> u64 {
+        let m = HLL_REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&register| 2f64.powi(-(register as i32)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self
+            .registers
+            .iter()
+            .filter(|&&register| register == 0)
+            .count();
+        if zeros == 0 {
+            return estimate.round() as u64;
+        }
+        // Few elements leave registers empty, and linear counting is closer
+        // until about 3.2 elements a register: the raw estimate overshoots by
+        // a few percent below that, more than linear counting's error.
+        let linear = m * (m / zeros as f64).ln();
+        if linear <= 3.2 * m {
+            linear.round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+/// 64-bit FNV-1a, its bits mixed further so that close inputs land far apart.
+/// It must not change, or stored HyperLogLogs would count elements twice.
+fn hash(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(hll: &HyperLogLog, actual: u64) -> f64 {
+        (hll.count() as f64 - actual as f64).abs() / actual as f64
+    }
+
+    #[test]
+    fn test_error_bounds() {
+        let mut hll = HyperLogLog::new();
+        let mut added = 0;
+        for checkpoint in [10, 1_000, 100_000, 1_000_000] {
+            while added < checkpoint {
+                hll.add(format!("element:{}", added).as_bytes());
+                added += 1;
+            }
+            // Three standard errors.
+            assert!(error(&hll, added) < 0.025, "{} for {}", hll.count(), added);
+        }
+        // Elements counted already don't change it.
+        assert!(!(0..1000).any(|i| hll.add(format!("element:{}", i).as_bytes())));
+        assert_eq!(HyperLogLog::new().count(), 0);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut left = HyperLogLog::new();
+        let mut right = HyperLogLog::new();
+        for i in 0..60_000 {
+            left.add(format!("{}", i).as_bytes());
+            right.add(format!("{}", i + 30_000).as_bytes());
+        }
+        left.merge(&right);
+        assert!(error(&left, 90_000) < 0.025, "{}", left.count());
+
+        let decoded = HyperLogLog::from_bytes(&left.to_bytes()).unwrap();
+        assert_eq!(decoded, left);
+        assert!(HyperLogLog::from_bytes(b"HYLL").is_none());
+        assert!(HyperLogLog::from_bytes(&[0; MAGIC.len() + HLL_REGISTERS]).is_none());
+    }
+}
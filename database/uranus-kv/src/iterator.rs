@@ -0,0 +1,260 @@
+//! Iterating over sorted runs of keys
+//!
+//! A [`StorageIterator`] walks the entries of one sorted source, such as the
+//! memtable or a table of the [LSM engine](crate::lsm), in the order of their
+//! keys. An entry either holds a value or removes its key, a tombstone which
+//! hides what older sources hold for it.
+//!
+//! [`MergingIterator`] presents several sources, from the newest, as one: for
+//! each key, only the entry of the newest source holding it is seen, and
+//! tombstones are skipped, so that it walks what a read would find.
+
+use std::{cmp::Reverse, collections::BinaryHeap, time::SystemTime};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+/// A cursor over entries sorted by key. It starts at the first entry, and
+/// [`StorageIterator::key`] and [`StorageIterator::value`] may only be called
+/// while it [is valid](StorageIterator::is_valid).
+pub trait StorageIterator {
+    /// Moves to the first entry whose key is at least `key`.
+    fn seek(&mut self, key: &[u8]) -> Result<()>;
+
+    /// Moves to the next entry.
+    fn next(&mut self) -> Result<()>;
+
+    /// Whether there is an entry here, rather than the end.
+    fn is_valid(&self) -> bool;
+
+    fn key(&self) -> &Bytes;
+
+    /// The value of the entry, none if it removes its key.
+    fn value(&self) -> Option<&Bytes>;
+
+    /// When the key of the entry expires, if it does.
+    fn expires_at(&self) -> Option<SystemTime> {
+        None
+    }
+}
+
+impl<I: StorageIterator + ?Sized> StorageIterator for Box<I> {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        (**self).seek(key)
+    }
+
+    fn next(&mut self) -> Result<()> {
+        (**self).next()
+    }
+
+    fn is_valid(&self) -> bool {
+        (**self).is_valid()
+    }
+
+    fn key(&self) -> &Bytes {
+        (**self).key()
+    }
+
+    fn value(&self) -> Option<&Bytes> {
+        (**self).value()
+    }
+
+    fn expires_at(&self) -> Option<SystemTime> {
+        (**self).expires_at()
+    }
+}
+
+/// The sources merged into one sorted view, see the [module
+/// documentation](self).
+pub struct MergingIterator<I> {
+    /// From the newest.
+    sources: Vec<I>,
+    /// The key each valid source is at, with its position in `sources` so
+    /// that the newest comes first among equal keys.
+    heap: BinaryHeap<Reverse<(Bytes, usize)>>,
+}
+
+impl<I: StorageIterator> MergingIterator<I> {
+    /// Merges `sources`, from the newest, each at its first entry.
+    pub fn new(sources: Vec<I>) -> Result<MergingIterator<I>> {
+        let mut merged = MergingIterator {
+            sources,
+            heap: BinaryHeap::new(),
+        };
+        merged.fill();
+        merged.skip_removed()?;
+        Ok(merged)
+    }
+
+    fn fill(&mut self) {
+        self.heap = self
+            .sources
+            .iter()
+            .enumerate()
+            .filter(|(_, source)| source.is_valid())
+            .map(|(i, source)| Reverse((source.key().clone(), i)))
+            .collect();
+    }
+
+    /// The source whose entry is seen.
+    fn current(&self) -> Option<&I> {
+        let Reverse((_, i)) = self.heap.peek()?;
+        Some(&self.sources[*i])
+    }
+
+    /// Moves every source at the current key past it.
+    fn advance(&mut self) -> Result<()> {
+        let Some(Reverse((key, _))) = self.heap.peek().cloned() else {
+            return Ok(());
+        };
+        while let Some(Reverse((next, i))) = self.heap.peek() {
+            if *next != key {
+                break;
+            }
+            let i = *i;
+            self.heap.pop();
+            let source = &mut self.sources[i];
+            source.next()?;
+            if source.is_valid() {
+                self.heap.push(Reverse((source.key().clone(), i)));
+            }
+        }
+        Ok(())
+    }
+
+    fn skip_removed(&mut self) -> Result<()> {
+        while matches!(self.current(), Some(source) if source.value().is_none()) {
+            self.advance()?;
+        }
+        Ok(())
+    }
+}
+
+impl<I: StorageIterator> StorageIterator for MergingIterator<I> {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        for source in &mut self.sources {
+            source.seek(key)?;
+        }
+        self.fill();
+        self.skip_removed()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.advance()?;
+        self.skip_removed()
+    }
+
+    fn is_valid(&self) -> bool {
+        !self.heap.is_empty()
+    }
+
+    fn key(&self) -> &Bytes {
+        let Reverse((key, _)) = self.heap.peek().expect("the iterator is valid");
+        key
+    }
+
+    fn value(&self) -> Option<&Bytes> {
+        self.current()?.value()
+    }
+
+    fn expires_at(&self) -> Option<SystemTime> {
+        self.current()?.expires_at()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Entries kept in a vector, sorted by key.
+    struct VecIterator {
+        entries: Vec<(Bytes, Option<Bytes>)>,
+        at: usize,
+    }
+
+    impl VecIterator {
+        fn new(entries: &[(&str, Option<&str>)]) -> VecIterator {
+            let entries = entries
+                .iter()
+                .map(|(key, value)| {
+                    let value = value.map(|value| Bytes::from(value.to_string()));
+                    (Bytes::from(key.to_string()), value)
+                })
+                .collect();
+            VecIterator { entries, at: 0 }
+        }
+    }
+
+    impl StorageIterator for VecIterator {
+        fn seek(&mut self, key: &[u8]) -> Result<()> {
+            self.at = self.entries.partition_point(|(k, _)| &k[..] < key);
+            Ok(())
+        }
+
+        fn next(&mut self) -> Result<()> {
+            self.at += 1;
+            Ok(())
+        }
+
+        fn is_valid(&self) -> bool {
+            self.at < self.entries.len()
+        }
+
+        fn key(&self) -> &Bytes {
+            &self.entries[self.at].0
+        }
+
+        fn value(&self) -> Option<&Bytes> {
+            self.entries[self.at].1.as_ref()
+        }
+    }
+
+    fn collect(iter: &mut impl StorageIterator) -> Vec<(String, String)> {
+        let mut entries = vec![];
+        while iter.is_valid() {
+            let value = iter.value().unwrap();
+            entries.push((
+                String::from_utf8(iter.key().to_vec()).unwrap(),
+                String::from_utf8(value.to_vec()).unwrap(),
+            ));
+            iter.next().unwrap();
+        }
+        entries
+    }
+
+    fn pairs(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_merging_iterator() {
+        let newest = VecIterator::new(&[("b", None), ("c", Some("c2")), ("e", Some("e2"))]);
+        let oldest = VecIterator::new(&[
+            ("a", Some("a1")),
+            ("b", Some("b1")),
+            ("c", Some("c1")),
+            ("d", None),
+            ("f", Some("f1")),
+        ]);
+        let mut merged = MergingIterator::new(vec![newest, oldest]).unwrap();
+        assert_eq!(
+            collect(&mut merged),
+            pairs(&[("a", "a1"), ("c", "c2"), ("e", "e2"), ("f", "f1")])
+        );
+
+        merged.seek(b"b").unwrap();
+        assert_eq!(merged.key(), &Bytes::from("c"));
+        merged.seek(b"d").unwrap();
+        assert_eq!(collect(&mut merged), pairs(&[("e", "e2"), ("f", "f1")]));
+        merged.seek(b"g").unwrap();
+        assert!(!merged.is_valid());
+
+        // Only tombstones.
+        let removed = VecIterator::new(&[("a", None)]);
+        let merged = MergingIterator::new(vec![removed, VecIterator::new(&[])]).unwrap();
+        assert!(!merged.is_valid());
+    }
+}
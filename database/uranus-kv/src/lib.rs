@@ -1,13 +1,250 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::{collections::HashMap, fmt::Debug, time::SystemTime};
 
 use anyhow::Result;
 use bytes::Bytes;
 use thiserror::Error;
 
+pub mod value;
+pub use value::*;
+
+pub mod expiry;
+pub use expiry::*;
+
+pub mod queue;
+pub use queue::*;
+
+pub mod sorted_set;
+pub use sorted_set::*;
+
+pub mod hyperloglog;
+pub use hyperloglog::*;
+
+pub mod stream;
+pub use stream::*;
+
+pub mod eviction;
+pub use eviction::*;
+
+pub mod compression;
+pub use compression::*;
+
+pub mod checksum;
+pub use checksum::*;
+
+pub mod codec;
+pub use codec::*;
+
+pub mod dump;
+pub use dump::*;
+
+pub mod block;
+
+pub mod lsm;
+pub use lsm::*;
+
+pub mod manifest;
+pub use manifest::*;
+
+pub mod iterator;
+pub use iterator::*;
+
 pub trait Storage {
-    fn put(&mut self, key: Bytes, value: Bytes) -> Result<()>;
+    fn put(&mut self, key: Bytes, value: Value) -> Result<()>;
     fn delete(&mut self, key: Bytes) -> Result<()>;
-    fn get(&self, key: Bytes) -> Result<Option<Bytes>>;
+    fn get(&self, key: Bytes) -> Result<Option<Value>>;
+    /// Removes `key`, handing back its value if it had one.
+    fn remove(&mut self, key: Bytes) -> Result<Option<Value>>;
+    /// Returns every key-value pair currently stored, in no particular order.
+    fn scan(&self) -> Result<Vec<(Bytes, Value)>>;
+
+    /// Every key stored, in no particular order.
+    fn keys(&self) -> Result<Vec<Bytes>> {
+        Ok(self.scan()?.into_iter().map(|(key, _)| key).collect())
+    }
+
+    /// Every key starting with `prefix`, in order. Engines keeping their keys
+    /// ordered may find them without going through all the others.
+    fn keys_with_prefix(&self, prefix: &[u8]) -> Result<Vec<Bytes>> {
+        let mut keys = self.keys()?;
+        keys.retain(|key| key.starts_with(prefix));
+        keys.sort_unstable();
+        Ok(keys)
+    }
+
+    /// The number of keys stored.
+    fn len(&self) -> Result<usize> {
+        Ok(self.scan()?.len())
+    }
+
+    fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Whether `key` is stored. Unlike [`Storage::get`], it doesn't count as
+    /// a use of the key, see [`Accounted`].
+    fn contains(&self, key: Bytes) -> Result<bool> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Removes every key.
+    fn clear(&mut self) -> Result<()> {
+        for (key, _) in self.scan()? {
+            self.remove(key)?;
+        }
+        Ok(())
+    }
+
+    /// The number of internal shards, for engines that have them.
+    fn shard_count(&self) -> Option<usize> {
+        None
+    }
+
+    /// Changes the number of internal shards. Keys may be moved lazily, by
+    /// later calls to [`Storage::migrate_step`].
+    fn reshard(&mut self, _shards: usize) -> Result<()> {
+        Err(StorageError::Unsupported)?
+    }
+
+    /// Does a bounded amount of pending resharding work, returns whether some is left.
+    fn migrate_step(&mut self) -> bool {
+        false
+    }
+
+    /// Whether the engine expires keys itself, see [`Storage::put_with_ttl`].
+    /// Users of other engines have to keep track of expiry on their own.
+    fn supports_ttl(&self) -> bool {
+        false
+    }
+
+    /// Whether operations may wait on I/O, as those of disk-backed engines do.
+    /// The server runs them off its event loop so that they don't stall other
+    /// connections.
+    fn blocks(&self) -> bool {
+        false
+    }
+
+    /// How far the engine is behind the work its writes left for later, which
+    /// writers should hold back by, see [`Storage::compact`].
+    fn write_stall(&self) -> WriteStall {
+        WriteStall::None
+    }
+
+    /// Does the work the writes left for later, such as merging tables. The
+    /// owner of the engine calls it periodically.
+    fn compact(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Makes the writes so far durable, for engines which keep them on disk.
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Stores `value` under `key` until `expires_at`, after which reads miss it.
+    /// A later [`Storage::put`] makes the key persistent again.
+    fn put_with_ttl(&mut self, _key: Bytes, _value: Value, _expires_at: SystemTime) -> Result<()> {
+        Err(StorageError::Unsupported)?
+    }
+
+    /// When `key` expires, if it has a TTL.
+    fn expires_at(&self, _key: &Bytes) -> Option<SystemTime> {
+        None
+    }
+
+    /// Reclaims the space of the keys expired by `now`, returns them.
+    fn expire(&mut self, _now: SystemTime) -> Vec<Bytes> {
+        vec![]
+    }
+
+    /// When the key [`Storage::evict`] would pick first under
+    /// [`EvictionPolicy::Lru`] was last used, so that engines sharing a
+    /// [`MemoryLimit`] can evict the least recently used key of them all.
+    /// Uses are numbered by the limit, see [`Accounted`].
+    fn least_recent_use(&self) -> Option<u64> {
+        None
+    }
+
+    /// How the tables of the engine fare, if it keeps its keys in tables, see
+    /// [`LsmKV::stats`].
+    fn table_stats(&self) -> Option<TableStats> {
+        None
+    }
+
+    /// How often the key [`Storage::evict`] would pick first under
+    /// [`EvictionPolicy::Lfu`] is used, so that engines sharing a
+    /// [`MemoryLimit`] can evict the least frequently used key of them all.
+    /// See [`Storage::frequency`].
+    fn least_frequent_use(&self) -> Option<u8> {
+        None
+    }
+
+    /// How often `key` is used, as the logarithmic counter [`Storage::evict`]
+    /// goes by under [`EvictionPolicy::Lfu`], decayed while it goes unused.
+    /// Engines which don't track their keys, see [`Accounted`], don't know.
+    fn frequency(&self, _key: &Bytes) -> Option<u8> {
+        None
+    }
+
+    /// Removes a key chosen by `policy` to free memory, returns it. Engines
+    /// which don't track their keys, see [`Accounted`], evict nothing.
+    fn evict(&mut self, _policy: EvictionPolicy) -> Option<Bytes> {
+        None
+    }
+
+    /// Replaces the value of `key` by `new` only if it is `expected`, keeping
+    /// its TTL. Returns whether it was replaced.
+    fn compare_and_swap(&mut self, key: Bytes, expected: &Value, new: Value) -> Result<bool> {
+        if self.get(key.clone())?.as_ref() != Some(expected) {
+            return Ok(false);
+        }
+        match self.expires_at(&key) {
+            Some(expires_at) => self.put_with_ttl(key, new, expires_at)?,
+            None => self.put(key, new)?,
+        }
+        Ok(true)
+    }
+
+    /// Moves the value of `src` to `dst` with its TTL, overwriting `dst`.
+    /// Returns whether there was such a key.
+    fn rename(&mut self, src: Bytes, dst: Bytes) -> Result<bool> {
+        let expires_at = self.expires_at(&src);
+        let Some(value) = self.remove(src)? else {
+            return Ok(false);
+        };
+        match expires_at {
+            Some(expires_at) => self.put_with_ttl(dst, value, expires_at)?,
+            None => self.put(dst, value)?,
+        }
+        Ok(true)
+    }
+
+    /// Copies the value of `src` to `dst` with its TTL, unless `dst` exists
+    /// and not `replace`. Returns whether it was copied.
+    fn copy(&mut self, src: Bytes, dst: Bytes, replace: bool) -> Result<bool> {
+        if !replace && self.get(dst.clone())?.is_some() {
+            return Ok(false);
+        }
+        let Some(value) = self.get(src.clone())? else {
+            return Ok(false);
+        };
+        match self.expires_at(&src) {
+            Some(expires_at) => self.put_with_ttl(dst, value, expires_at)?,
+            None => self.put(dst, value)?,
+        }
+        Ok(true)
+    }
+}
+
+/// How writers should pace themselves so that an engine catches up with its
+/// background work, as by the slowdown and stop triggers of LevelDB.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WriteStall {
+    #[default]
+    None,
+    /// Writes should be delayed a little, leaving time to the engine.
+    Slowdown,
+    /// Writes should wait until the engine has caught up.
+    Stop,
 }
 
 impl Debug for dyn Storage + Send + Sync {
@@ -17,7 +254,8 @@ impl Debug for dyn Storage + Send + Sync {
 }
 
 pub struct StdHashKV {
-    hashmap: HashMap<Bytes, Bytes>,
+    hashmap: HashMap<Bytes, Value>,
+    expiries: Expiries,
 }
 
 #[derive(Debug, Error)]
@@ -28,26 +266,105 @@ pub enum StorageError {
     DeleteFailed,
     #[error("get failed")]
     GetFailed,
+    #[error("not supported by this storage engine")]
+    Unsupported,
+    #[error("command not allowed when used memory > 'maxmemory'.")]
+    OutOfMemory,
+    #[error("unknown eviction policy '{0}'")]
+    UnknownPolicy(String),
+    #[error("unknown WAL fsync policy '{0}'")]
+    UnknownWalSync(String),
+    #[error("unsupported compression '{0}'")]
+    UnsupportedCompression(String),
+    #[error("corrupted data: {0}")]
+    Corrupted(String),
+    #[error("unsupported table version {0}")]
+    UnsupportedTableVersion(u8),
 }
 
 impl Storage for StdHashKV {
     /// put here is almost always succeed, but for other storage systems that may not be the case..
-    fn put(&mut self, key: Bytes, value: Bytes) -> Result<()> {
+    fn put(&mut self, key: Bytes, value: Value) -> Result<()> {
+        self.expiries.clear(&key);
         self.hashmap.insert(key, value);
         Ok(())
     }
 
     fn delete(&mut self, key: Bytes) -> Result<()> {
-        self.hashmap
-            .remove(&key)
-            .ok_or(StorageError::DeleteFailed)?;
+        self.remove(key)?.ok_or(StorageError::DeleteFailed)?;
         Ok(())
     }
 
-    fn get(&self, key: Bytes) -> Result<Option<Bytes>> {
+    fn get(&self, key: Bytes) -> Result<Option<Value>> {
+        if self.expiries.is_expired(&key, SystemTime::now()) {
+            return Ok(None);
+        }
         let result = self.hashmap.get(&key).map(|x| x.to_owned());
         Ok(result)
     }
+
+    fn remove(&mut self, key: Bytes) -> Result<Option<Value>> {
+        let expired = self.expiries.is_expired(&key, SystemTime::now());
+        self.expiries.clear(&key);
+        let value = self.hashmap.remove(&key);
+        Ok(value.filter(|_| !expired))
+    }
+
+    fn scan(&self) -> Result<Vec<(Bytes, Value)>> {
+        let now = SystemTime::now();
+        let pairs = self
+            .hashmap
+            .iter()
+            .filter(|(k, _)| !self.expiries.is_expired(k, now))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        Ok(pairs)
+    }
+
+    fn keys(&self) -> Result<Vec<Bytes>> {
+        let now = SystemTime::now();
+        let keys = self.hashmap.keys();
+        Ok(keys
+            .filter(|key| !self.expiries.is_expired(key, now))
+            .cloned()
+            .collect())
+    }
+
+    /// Keys expired but not reclaimed yet are not counted.
+    fn len(&self) -> Result<usize> {
+        let expired = self.expiries.count_expired(SystemTime::now());
+        Ok(self.hashmap.len() - expired)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.hashmap.clear();
+        self.expiries = Expiries::new();
+        Ok(())
+    }
+
+    fn supports_ttl(&self) -> bool {
+        true
+    }
+
+    fn put_with_ttl(&mut self, key: Bytes, value: Value, expires_at: SystemTime) -> Result<()> {
+        self.expiries.set(key.clone(), expires_at);
+        self.hashmap.insert(key, value);
+        Ok(())
+    }
+
+    fn expires_at(&self, key: &Bytes) -> Option<SystemTime> {
+        self.expiries.get(key)
+    }
+
+    /// Expired keys are hidden from reads as soon as they expire, this only
+    /// frees their memory.
+    fn expire(&mut self, now: SystemTime) -> Vec<Bytes> {
+        let expired = self.expiries.take_expired(now);
+        for key in &expired {
+            self.hashmap.remove(key);
+        }
+        expired
+    }
 }
 
 impl Default for StdHashKV {
@@ -60,6 +377,7 @@ impl StdHashKV {
     pub fn new() -> StdHashKV {
         StdHashKV {
             hashmap: HashMap::new(),
+            expiries: Expiries::new(),
         }
     }
 }
@@ -67,7 +385,7 @@ impl StdHashKV {
 pub struct KV {}
 
 impl Storage for KV {
-    fn put(&mut self, _: Bytes, _: Bytes) -> Result<()> {
+    fn put(&mut self, _: Bytes, _: Value) -> Result<()> {
         todo!()
     }
 
@@ -75,14 +393,23 @@ impl Storage for KV {
         todo!()
     }
 
-    fn get(&self, _: Bytes) -> Result<Option<Bytes>> {
+    fn get(&self, _: Bytes) -> Result<Option<Value>> {
         todo!()
     }
+
+    fn remove(&mut self, _: Bytes) -> Result<Option<Value>> {
+        Err(StorageError::Unsupported)?
+    }
+
+    fn scan(&self) -> Result<Vec<(Bytes, Value)>> {
+        Err(StorageError::Unsupported)?
+    }
 }
 
 pub mod arena;
-pub mod memtable;
 pub mod linked_list;
+pub mod memtable;
+pub mod sharded;
 
 pub fn add(left: usize, right: usize) -> usize {
     left + right
@@ -97,4 +424,57 @@ mod tests {
         let result = add(2, 2);
         assert_eq!(result, 4);
     }
+
+    #[test]
+    fn test_native_ttl() {
+        let mut kv = StdHashKV::new();
+        let value = Value::String(Bytes::from("value"));
+        let past = SystemTime::now() - std::time::Duration::from_secs(1);
+        kv.put_with_ttl(Bytes::from("gone"), value.clone(), past)
+            .unwrap();
+        let future = SystemTime::now() + std::time::Duration::from_secs(60);
+        kv.put_with_ttl(Bytes::from("kept"), value.clone(), future)
+            .unwrap();
+
+        assert_eq!(kv.get(Bytes::from("gone")).unwrap(), None);
+        assert_eq!(kv.get(Bytes::from("kept")).unwrap(), Some(value.clone()));
+        assert_eq!(kv.expires_at(&Bytes::from("kept")), Some(future));
+        assert_eq!(kv.scan().unwrap().len(), 1);
+        assert_eq!(kv.expire(SystemTime::now()), [Bytes::from("gone")]);
+
+        kv.put(Bytes::from("kept"), value).unwrap();
+        assert_eq!(kv.expires_at(&Bytes::from("kept")), None);
+    }
+
+    #[test]
+    fn test_rename_and_copy() {
+        let mut kv = StdHashKV::new();
+        let value = Value::String(Bytes::from("value"));
+        let future = SystemTime::now() + std::time::Duration::from_secs(60);
+        kv.put_with_ttl(Bytes::from("src"), value.clone(), future)
+            .unwrap();
+        kv.put(Bytes::from("dst"), Value::String(Bytes::from("old")))
+            .unwrap();
+
+        assert!(kv.rename(Bytes::from("src"), Bytes::from("dst")).unwrap());
+        assert_eq!(kv.get(Bytes::from("src")).unwrap(), None);
+        assert_eq!(kv.get(Bytes::from("dst")).unwrap(), Some(value.clone()));
+        assert_eq!(kv.expires_at(&Bytes::from("dst")), Some(future));
+        assert!(!kv.rename(Bytes::from("src"), Bytes::from("dst")).unwrap());
+
+        kv.put(Bytes::from("other"), Value::String(Bytes::from("old")))
+            .unwrap();
+        assert!(!kv
+            .copy(Bytes::from("dst"), Bytes::from("other"), false)
+            .unwrap());
+        assert!(kv
+            .copy(Bytes::from("dst"), Bytes::from("other"), true)
+            .unwrap());
+        assert_eq!(kv.get(Bytes::from("other")).unwrap(), Some(value.clone()));
+        assert_eq!(kv.expires_at(&Bytes::from("other")), Some(future));
+        assert!(kv
+            .copy(Bytes::from("dst"), Bytes::from("new"), false)
+            .unwrap());
+        assert_eq!(kv.get(Bytes::from("dst")).unwrap(), Some(value));
+    }
 }
@@ -123,6 +123,10 @@ impl LinkedList {
         self.nodes.len() - self.free.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     fn valid_index(&self, index: Index) -> bool {
         index != HEAD && index != TAIL && index - OFFSET < self.nodes.len()
     }
@@ -0,0 +1,1791 @@
+//! A log-structured merge tree keeping its keys on disk
+//!
+//! Writes are appended to a write-ahead log and kept in a memtable. Once the
+//! log grows past [`LsmConfig::memtable_size`] the memtable is written out as a
+//! sorted table and the log is started over. Reads look at the memtable, then
+//! at the tables from the newest. When there are more than
+//! [`LsmConfig::max_tables`] tables they are all merged into one, which drops
+//! deleted and expired keys.
+//!
+//! The merge holds up the write which flushed, unless it is left to
+//! [`Storage::compact`] by [`LsmConfig::background_merges`]. The tables then
+//! pile up while writes outpace the merges, so past
+//! [`LsmConfig::slowdown_tables`] the engine asks writers to slow down, and
+//! past [`LsmConfig::stop_tables`] to stop, see [`WriteStall`]. A write
+//! flushing past the latter merges the tables itself regardless.
+//!
+//! Each write is appended to the log before it is applied, but reaches the
+//! disk as [`LsmConfig::wal_sync`] says, see [`WalSync`]: by default the log
+//! is synced once a second by a thread of its own, so that a crash of the
+//! machine loses at most the last second of writes. A crash of the process
+//! alone loses nothing, the writes having reached the operating system.
+//!
+//! The log is a sequence of records: the CRC-32C of the payload, its length,
+//! then the payload, which is the length of the key, the key, an expiry
+//! deadline in nanoseconds since the epoch (0 if none), then the value encoded
+//! by [`Value::encode`] unless the record deletes the key. A table named
+//! `<first>-<last>.sst` holds what the flushes `first` to `last` wrote, as
+//! LevelDB lays out its tables. It starts with [`TABLE_MAGIC`] and
+//! [`TABLE_VERSION`], then holds data blocks of about
+//! [`LsmConfig::block_size`] bytes whose entries are the keys and their
+//! records, with the prefixes of the keys compressed, see [`crate::block`].
+//! The index is partitioned: index blocks map the last key of each data block
+//! to where it is, and a top-level index block, after them, maps the last key
+//! of each index block to where that is. The table ends with where the
+//! top-level index is, then the magic and the version again. Only the
+//! top-level index is kept in memory, so a read goes through an index block
+//! then a data block.
+//!
+//! Every block is compressed by [`LsmConfig::block_compression`] unless that
+//! doesn't make it smaller, and stored as its length, the block, then a
+//! trailer of the byte of its compression and the CRC-32C of both. Tables of
+//! an earlier version, and those written before versions, plain sequences of
+//! records, are rewritten as they are opened. Those of a later version are
+//! refused rather than misread. [`LsmKV::stats`] tells how well the blocks
+//! compress and how much reads fetch from disk for the records they are
+//! after, see [`TableStats`].
+//! The [manifest](crate::manifest) lists the live tables: a flush or a merge
+//! takes effect once it is updated, and the tables it doesn't list are
+//! removed on opening, so that a crash in between loses nothing.
+//!
+//! [`LsmKV::iter`] walks the keys in order, merging the memtable and the
+//! tables with a [`MergingIterator`], as merges do.
+//!
+//! Keys are all kept in memory, values only while in the memtable, though the
+//! tables don't index them in memory. Keys whose
+//! deadline passed while the engine was closed are dropped as it opens, as if
+//! removed, rather than loaded to be swept.
+//!
+
+use std::{
+    collections::{btree_map, BTreeMap, HashMap, HashSet},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    ops::{Add, Bound, Range},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+        Arc, Mutex, Weak,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::{
+    block::{Block, BlockBuilder, BlockHandle, BlockIter},
+    crc32c, Compression, Expiries, MergingIterator, Storage, StorageError, StorageIterator,
+    TableId, Value, Version, VersionEdit, VersionSet, WriteStall,
+};
+
+const LOG_FILE: &str = "wal.log";
+const HEADER: usize = 8;
+/// The start and the end of a table, before its format version.
+pub const TABLE_MAGIC: &[u8; 7] = b"URNSST\x00";
+/// The format of the tables written. Version 1 held the records as in the
+/// log, in blocks, which were indexed by reading them all.
+pub const TABLE_VERSION: u8 = 2;
+/// The magic and the version.
+const TABLE_HEADER: usize = 8;
+/// The offset and the length of the top-level index, then the header again.
+const TABLE_FOOTER: usize = 24;
+/// The length before a block.
+const BLOCK_HEADER: usize = 4;
+/// The compression byte and the checksum after a block.
+const BLOCK_TRAILER: usize = 5;
+
+#[derive(Debug, Clone)]
+pub struct LsmConfig {
+    /// Where the log and the tables are, created if needed.
+    pub dir: PathBuf,
+    /// How many bytes the log may hold before the memtable is flushed.
+    pub memtable_size: usize,
+    /// How many tables there may be before they are merged.
+    pub max_tables: usize,
+    /// Leave the merges to [`Storage::compact`], which the owner of the engine
+    /// calls periodically.
+    pub background_merges: bool,
+    /// With background merges, writes should slow down from this many tables.
+    pub slowdown_tables: usize,
+    /// With background merges, writes should stop from this many tables.
+    pub stop_tables: usize,
+    /// Records are written to tables by blocks of about this many bytes,
+    /// before compression. Reads fetch whole blocks.
+    pub block_size: usize,
+    /// Every this many keys of a block is stored whole rather than after the
+    /// prefix it shares with the previous one. Fewer make seeks scan less but
+    /// blocks larger.
+    pub block_restart_interval: usize,
+    /// How the blocks of the tables written from now on are compressed, the
+    /// tables already written keeping theirs.
+    pub block_compression: Compression,
+    /// When the log is synced to the disk. It is shared by the engines opened
+    /// with clones of this config, which all follow its changes.
+    pub wal_sync: Arc<WalSyncPolicy>,
+}
+
+impl LsmConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> LsmConfig {
+        LsmConfig {
+            dir: dir.into(),
+            memtable_size: 4 << 20,
+            max_tables: 8,
+            background_merges: false,
+            slowdown_tables: 16,
+            stop_tables: 24,
+            block_size: 4 << 10,
+            block_restart_interval: 16,
+            block_compression: Compression::None,
+            wal_sync: Arc::default(),
+        }
+    }
+}
+
+/// When the writes appended to the log are synced to the disk, as Redis'
+/// `appendfsync`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WalSync {
+    /// After every write, which is slow but loses nothing on a crash.
+    Always,
+    /// Once a second, in the background.
+    #[default]
+    EverySecond,
+    /// Whenever the operating system sees fit.
+    No,
+}
+
+impl WalSync {
+    /// The name of the policy in the configuration.
+    pub fn name(self) -> &'static str {
+        match self {
+            WalSync::Always => "always",
+            WalSync::EverySecond => "everysec",
+            WalSync::No => "no",
+        }
+    }
+
+    fn from_u8(policy: u8) -> WalSync {
+        match policy {
+            0 => WalSync::Always,
+            2 => WalSync::No,
+            _ => WalSync::EverySecond,
+        }
+    }
+}
+
+impl fmt::Display for WalSync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for WalSync {
+    type Err = StorageError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "always" => Ok(WalSync::Always),
+            "everysec" => Ok(WalSync::EverySecond),
+            "no" => Ok(WalSync::No),
+            _ => Err(StorageError::UnknownWalSync(name.to_string())),
+        }
+    }
+}
+
+/// The [`WalSync`] in effect, which can change at runtime, and the syncs of
+/// the logs following it so far.
+#[derive(Debug)]
+pub struct WalSyncPolicy {
+    policy: AtomicU8,
+    syncs: AtomicU64,
+}
+
+impl WalSyncPolicy {
+    pub fn new(policy: WalSync) -> WalSyncPolicy {
+        WalSyncPolicy {
+            policy: AtomicU8::new(policy as u8),
+            syncs: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self) -> WalSync {
+        WalSync::from_u8(self.policy.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, policy: WalSync) {
+        self.policy.store(policy as u8, Ordering::Relaxed);
+    }
+
+    /// How many times the logs were synced so far, by any policy.
+    pub fn syncs(&self) -> u64 {
+        self.syncs.load(Ordering::Relaxed)
+    }
+
+    fn sync(&self, log: &File) -> Result<()> {
+        log.sync_data()?;
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Default for WalSyncPolicy {
+    fn default() -> Self {
+        WalSyncPolicy::new(WalSync::default())
+    }
+}
+
+/// The log as the thread syncing it once a second sees it.
+#[derive(Debug)]
+struct PendingSync {
+    log: File,
+    /// Whether there are writes to sync.
+    dirty: AtomicBool,
+}
+
+/// Syncs the log once a second while there are writes to and the policy is
+/// [`WalSync::EverySecond`], until the engine is dropped.
+fn sync_every_second(pending: Weak<PendingSync>, policy: Arc<WalSyncPolicy>) {
+    loop {
+        thread::sleep(Duration::from_secs(1));
+        let Some(pending) = pending.upgrade() else {
+            return;
+        };
+        if policy.get() == WalSync::EverySecond && pending.dirty.swap(false, Ordering::Relaxed) {
+            if let Err(err) = policy.sync(&pending.log) {
+                tracing::warn!("failed to sync the log: {}", err);
+            }
+        }
+    }
+}
+
+/// What a record says about its key: its value, or that it was removed.
+#[derive(Debug, Clone)]
+struct Record {
+    value: Option<Bytes>,
+    expires_at: Option<SystemTime>,
+}
+
+impl Record {
+    /// The value, none if the record removes its key or expired by `now`.
+    fn value_at(&self, now: SystemTime) -> Option<&Bytes> {
+        match self.expires_at {
+            Some(expires_at) if expires_at <= now => None,
+            _ => self.value.as_ref(),
+        }
+    }
+
+    fn encode(&self, key: &[u8], dst: &mut Vec<u8>) {
+        let start = dst.len();
+        dst.put_u64_le(0);
+        dst.put_u32_le(key.len() as u32);
+        dst.put_slice(key);
+        self.encode_body(dst);
+        let payload = &dst[start + HEADER..];
+        let (crc, len) = (crc32c(payload), payload.len() as u32);
+        dst[start..start + 4].copy_from_slice(&crc.to_le_bytes());
+        dst[start + 4..start + HEADER].copy_from_slice(&len.to_le_bytes());
+    }
+
+    /// Appends what follows the key: the deadline, then the value if any.
+    /// Tables store it as the value of the key.
+    fn encode_body(&self, dst: &mut Vec<u8>) {
+        let nanos = self.expires_at.map_or(0, |expires_at| {
+            let since_epoch = expires_at.duration_since(SystemTime::UNIX_EPOCH);
+            since_epoch.map_or(1, |since| (since.as_nanos() as u64).max(1))
+        });
+        dst.put_u64_le(nanos);
+        if let Some(value) = &self.value {
+            dst.put_u8(1);
+            dst.put_slice(value);
+        } else {
+            dst.put_u8(0);
+        }
+    }
+
+    /// Reads the record at the start of `src`, returns it with its key and
+    /// length, or `None` if `src` ends before it does.
+    fn decode(src: &Bytes) -> Result<Option<(Bytes, Record, usize)>, StorageError> {
+        if src.len() < HEADER {
+            return Ok(None);
+        }
+        let mut header = &src[..HEADER];
+        let crc = header.get_u32_le();
+        let len = header.get_u32_le() as usize;
+        if src.len() - HEADER < len {
+            return Ok(None);
+        }
+        let mut payload = src.slice(HEADER..HEADER + len);
+        if crc32c(&payload) != crc {
+            return Err(StorageError::Corrupted("checksum mismatch".to_string()));
+        }
+        let malformed = || StorageError::Corrupted("malformed record".to_string());
+        if payload.remaining() < 4 {
+            return Err(malformed());
+        }
+        let key_len = payload.get_u32_le() as usize;
+        if payload.remaining() < key_len {
+            return Err(malformed());
+        }
+        let key = payload.split_to(key_len);
+        let record = Record::decode_body(payload)?;
+        Ok(Some((key, record, HEADER + len)))
+    }
+
+    /// Reads back what [`Record::encode_body`] wrote.
+    fn decode_body(mut body: Bytes) -> Result<Record, StorageError> {
+        let malformed = || StorageError::Corrupted("malformed record".to_string());
+        // The deadline and the tag.
+        if body.remaining() < 9 {
+            return Err(malformed());
+        }
+        let nanos = body.get_u64_le();
+        let expires_at = (nanos != 0).then(|| SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos));
+        let value = match body.get_u8() {
+            0 if body.is_empty() => None,
+            1 => Some(body),
+            _ => return Err(malformed()),
+        };
+        Ok(Record { value, expires_at })
+    }
+}
+
+/// A record with its key, and where it is in its file.
+type Located = (Range<usize>, Bytes, Record);
+
+/// The records at the start of `src`, and how much of `src` they take. Only an
+/// incomplete record ends them early.
+fn decode_records(src: &Bytes) -> Result<(Vec<Located>, usize), StorageError> {
+    let mut records = vec![];
+    let mut offset = 0;
+    while let Some((key, record, len)) = Record::decode(&src.slice(offset..))? {
+        records.push((offset..offset + len, key, record));
+        offset += len;
+    }
+    Ok((records, offset))
+}
+
+/// How the tables of an engine fare, see [`LsmKV::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableStats {
+    /// The data and index blocks written by flushes and merges.
+    pub blocks_written: u64,
+    /// Their size before compression.
+    pub block_bytes: u64,
+    /// Their size as stored, with their length and trailer.
+    pub stored_bytes: u64,
+    /// The blocks read back by reads and merges, the index blocks they go
+    /// through included.
+    pub blocks_read: u64,
+    /// Their size as stored.
+    pub bytes_read: u64,
+    /// The records read out of the data blocks.
+    pub records_read: u64,
+    /// Their size, with their key.
+    pub record_bytes: u64,
+}
+
+impl TableStats {
+    /// How many times smaller the blocks written got, 1 if none was.
+    pub fn compression_ratio(&self) -> f64 {
+        match self.stored_bytes {
+            0 => 1.0,
+            stored => self.block_bytes as f64 / stored as f64,
+        }
+    }
+
+    /// How many bytes were read from disk per byte of the records read, 1 if
+    /// none was. Point reads fetch an index block and a data block for one
+    /// record, iterations and merges read every record of the blocks they
+    /// fetch.
+    pub fn read_amplification(&self) -> f64 {
+        match self.record_bytes {
+            0 => 1.0,
+            records => self.bytes_read as f64 / records as f64,
+        }
+    }
+}
+
+/// Sums the statistics of several engines.
+impl Add for TableStats {
+    type Output = TableStats;
+
+    fn add(self, other: TableStats) -> TableStats {
+        TableStats {
+            blocks_written: self.blocks_written + other.blocks_written,
+            block_bytes: self.block_bytes + other.block_bytes,
+            stored_bytes: self.stored_bytes + other.stored_bytes,
+            blocks_read: self.blocks_read + other.blocks_read,
+            bytes_read: self.bytes_read + other.bytes_read,
+            records_read: self.records_read + other.records_read,
+            record_bytes: self.record_bytes + other.record_bytes,
+        }
+    }
+}
+
+/// The counters behind [`TableStats`], shared by the tables of an engine,
+/// which are read concurrently.
+#[derive(Debug, Default)]
+struct Counters {
+    blocks_written: AtomicU64,
+    block_bytes: AtomicU64,
+    stored_bytes: AtomicU64,
+    blocks_read: AtomicU64,
+    bytes_read: AtomicU64,
+    records_read: AtomicU64,
+    record_bytes: AtomicU64,
+}
+
+impl Counters {
+    fn snapshot(&self) -> TableStats {
+        TableStats {
+            blocks_written: self.blocks_written.load(Ordering::Relaxed),
+            block_bytes: self.block_bytes.load(Ordering::Relaxed),
+            stored_bytes: self.stored_bytes.load(Ordering::Relaxed),
+            blocks_read: self.blocks_read.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            records_read: self.records_read.load(Ordering::Relaxed),
+            record_bytes: self.record_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Appends `block` to `dst` compressed by `compression`, or as is if that
+/// doesn't make it smaller, with its length and trailer. Returns how many
+/// bytes it takes.
+fn encode_block(block: &[u8], compression: Compression, dst: &mut Vec<u8>) -> Result<usize> {
+    let compressed = match compression {
+        Compression::None => None,
+        compression => Some(compression.compress_raw(block)?),
+    };
+    let (compression, stored) = match &compressed {
+        Some(compressed) if compressed.len() < block.len() => (compression, compressed.as_slice()),
+        _ => (Compression::None, block),
+    };
+    dst.put_u32_le(stored.len() as u32);
+    let start = dst.len();
+    dst.put_slice(stored);
+    dst.put_u8(compression.code());
+    let crc = crc32c(&dst[start..]);
+    dst.put_u32_le(crc);
+    Ok(BLOCK_HEADER + stored.len() + BLOCK_TRAILER)
+}
+
+/// Reads the block at the start of `src`, returns it decompressed and how
+/// much of `src` it takes.
+fn decode_block(src: &Bytes) -> Result<(Bytes, usize)> {
+    let truncated = || StorageError::Corrupted("truncated block".to_string());
+    if src.len() < BLOCK_HEADER {
+        Err(truncated())?
+    }
+    let len = (&src[..BLOCK_HEADER]).get_u32_le() as usize;
+    let end = BLOCK_HEADER + len + BLOCK_TRAILER;
+    if src.len() < end {
+        Err(truncated())?
+    }
+    let checked = &src[BLOCK_HEADER..end - 4];
+    if crc32c(checked) != (&src[end - 4..end]).get_u32_le() {
+        Err(StorageError::Corrupted("checksum mismatch".to_string()))?
+    }
+    let code = checked[len];
+    let compression = Compression::from_code(code)
+        .ok_or_else(|| StorageError::Corrupted(format!("unknown block compression {}", code)))?;
+    let records = match compression {
+        Compression::None => src.slice(BLOCK_HEADER..BLOCK_HEADER + len),
+        compression => compression.decompress(&checked[..len])?,
+    };
+    Ok((records, end))
+}
+
+/// Writes the blocks of a table as its entries come, in order.
+struct TableBuilder<'a> {
+    config: &'a LsmConfig,
+    counters: &'a Counters,
+    contents: Vec<u8>,
+    data: BlockBuilder,
+    /// The index block of the data blocks written since the last one.
+    partition: BlockBuilder,
+    top: BlockBuilder,
+}
+
+impl<'a> TableBuilder<'a> {
+    fn new(config: &'a LsmConfig, counters: &'a Counters) -> TableBuilder<'a> {
+        let mut contents = TABLE_MAGIC.to_vec();
+        contents.put_u8(TABLE_VERSION);
+        TableBuilder {
+            config,
+            counters,
+            contents,
+            data: BlockBuilder::new(config.block_restart_interval),
+            // Index blocks are searched without scanning, as in LevelDB.
+            partition: BlockBuilder::new(1),
+            top: BlockBuilder::new(1),
+        }
+    }
+
+    fn add(&mut self, key: &[u8], record: &Record) -> Result<()> {
+        let mut body = vec![];
+        record.encode_body(&mut body);
+        self.data.add(key, &body);
+        if self.data.size() >= self.config.block_size {
+            self.finish_data()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the data block out and indexes it by its last key, then the
+    /// index block if that fills it.
+    fn finish_data(&mut self) -> Result<()> {
+        let key = self.data.last_key().to_vec();
+        let block = self.data.finish();
+        let handle = self.write_block(&block)?;
+        let mut value = vec![];
+        handle.encode(&mut value);
+        self.partition.add(&key, &value);
+        if self.partition.size() >= self.config.block_size {
+            self.finish_partition()?;
+        }
+        Ok(())
+    }
+
+    fn finish_partition(&mut self) -> Result<()> {
+        let key = self.partition.last_key().to_vec();
+        let block = self.partition.finish();
+        let handle = self.write_block(&block)?;
+        let mut value = vec![];
+        handle.encode(&mut value);
+        self.top.add(&key, &value);
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: &[u8]) -> Result<BlockHandle> {
+        let offset = self.contents.len() as u64;
+        let compression = self.config.block_compression;
+        let stored = encode_block(block, compression, &mut self.contents)?;
+        let counters = self.counters;
+        counters.blocks_written.fetch_add(1, Ordering::Relaxed);
+        (counters.block_bytes).fetch_add(block.len() as u64, Ordering::Relaxed);
+        (counters.stored_bytes).fetch_add(stored as u64, Ordering::Relaxed);
+        Ok(BlockHandle {
+            offset,
+            len: stored as u64,
+        })
+    }
+
+    /// The whole table, ending with the top-level index and the footer.
+    fn finish(mut self) -> Result<Vec<u8>> {
+        if !self.data.is_empty() {
+            self.finish_data()?;
+        }
+        if !self.partition.is_empty() {
+            self.finish_partition()?;
+        }
+        let block = self.top.finish();
+        let top = self.write_block(&block)?;
+        self.contents.put_u64_le(top.offset);
+        self.contents.put_u64_le(top.len);
+        self.contents.put_slice(TABLE_MAGIC);
+        self.contents.put_u8(TABLE_VERSION);
+        Ok(self.contents)
+    }
+}
+
+/// The records of a table of an earlier version, `None` if written before
+/// versions.
+fn decode_old_table(contents: &Bytes, version: Option<u8>) -> Result<BTreeMap<Bytes, Record>> {
+    let mut entries = BTreeMap::new();
+    let mut decode = |records: &Bytes| -> Result<()> {
+        let (located, len) = decode_records(records)?;
+        if len != records.len() {
+            Err(StorageError::Corrupted("truncated".to_string()))?
+        }
+        entries.extend(located.into_iter().map(|(_, key, record)| (key, record)));
+        Ok(())
+    };
+    match version {
+        None => decode(contents)?,
+        Some(1) => {
+            let mut offset = TABLE_HEADER;
+            while offset < contents.len() {
+                let (records, len) = decode_block(&contents.slice(offset..))?;
+                decode(&records)?;
+                offset += len;
+            }
+        }
+        Some(version) => Err(StorageError::Corrupted(format!(
+            "unknown table version {}",
+            version
+        )))?,
+    }
+    Ok(entries)
+}
+
+/// The last key of each index block of a table, and where it is.
+type TopIndex = Vec<(Bytes, BlockHandle)>;
+
+/// A sorted table, of which only the top-level index is kept in memory.
+struct Table {
+    id: TableId,
+    path: PathBuf,
+    file: Mutex<File>,
+    index: TopIndex,
+    counters: Arc<Counters>,
+}
+
+impl Table {
+    fn path(dir: &Path, id: TableId, extension: &str) -> PathBuf {
+        dir.join(format!("{:06}-{:06}.{}", id.first, id.last, extension))
+    }
+
+    /// Parses a table file name into the flushes it holds.
+    fn parse_name(path: &Path) -> Option<TableId> {
+        let (first, last) = path.file_stem()?.to_str()?.split_once('-')?;
+        Some(TableId::new(first.parse().ok()?, last.parse().ok()?))
+    }
+
+    /// Opens a table, returns it with its records in order. A table of an
+    /// earlier version is rewritten by `config`.
+    fn load(
+        path: PathBuf,
+        id: TableId,
+        config: &LsmConfig,
+        counters: &Arc<Counters>,
+    ) -> Result<(Table, Vec<(Bytes, Record)>)> {
+        let corrupted =
+            |reason: String| StorageError::Corrupted(format!("{}: {}", path.display(), reason));
+        let contents = Bytes::from(fs::read(&path)?);
+        let version = (contents.strip_prefix(&TABLE_MAGIC[..]))
+            .map(|rest| rest.first().copied().unwrap_or_default());
+        match version {
+            Some(TABLE_VERSION) => {}
+            Some(version) if version > TABLE_VERSION => {
+                Err(StorageError::UnsupportedTableVersion(version))?
+            }
+            _ => {
+                let entries = decode_old_table(&contents, version)
+                    .map_err(|err| corrupted(err.to_string()))?;
+                let table = Table::write(config, id, &entries, counters)?;
+                if table.path != path {
+                    fs::remove_file(&path)?;
+                }
+                tracing::info!(
+                    "rewrote {} in table version {}",
+                    path.display(),
+                    TABLE_VERSION
+                );
+                return Ok((table, entries.into_iter().collect()));
+            }
+        }
+        let (index, entries) =
+            Table::decode(&contents).map_err(|err| corrupted(err.to_string()))?;
+        let table = Table {
+            id,
+            file: Mutex::new(File::open(&path)?),
+            path,
+            index,
+            counters: counters.clone(),
+        };
+        Ok((table, entries))
+    }
+
+    /// Reads the whole of a table, returns its top-level index and its
+    /// records in order.
+    fn decode(contents: &Bytes) -> Result<(TopIndex, Vec<(Bytes, Record)>)> {
+        let truncated = || StorageError::Corrupted("truncated".to_string());
+        let end = (contents.len())
+            .checked_sub(TABLE_FOOTER)
+            .filter(|&end| end >= TABLE_HEADER)
+            .ok_or_else(truncated)?;
+        let mut footer = &contents[end..];
+        let top = BlockHandle {
+            offset: footer.get_u64_le(),
+            len: footer.get_u64_le(),
+        };
+        if footer != &contents[..TABLE_HEADER] {
+            Err(truncated())?
+        }
+        let block = |handle: BlockHandle| -> Result<BlockIter> {
+            let range = handle.range();
+            if range.start < TABLE_HEADER || range.end > end {
+                Err(StorageError::Corrupted("block out of bounds".to_string()))?
+            }
+            let (block, _) = decode_block(&contents.slice(range))?;
+            let mut iter = Block::new(block)?.iter();
+            iter.seek_to_first()?;
+            Ok(iter)
+        };
+        let mut index = vec![];
+        let mut entries = vec![];
+        let mut top = block(top)?;
+        while top.is_valid() {
+            let handle = BlockHandle::decode(&top.value())?;
+            index.push((Bytes::copy_from_slice(top.key()), handle));
+            let mut partition = block(handle)?;
+            while partition.is_valid() {
+                let mut data = block(BlockHandle::decode(&partition.value())?)?;
+                while data.is_valid() {
+                    let record = Record::decode_body(data.value())?;
+                    entries.push((Bytes::copy_from_slice(data.key()), record));
+                    data.next()?;
+                }
+                partition.next()?;
+            }
+            top.next()?;
+        }
+        Ok((index, entries))
+    }
+
+    /// Writes `entries` as the table `id` in `config.dir`. The file only gets
+    /// its name once complete.
+    fn write(
+        config: &LsmConfig,
+        id: TableId,
+        entries: &BTreeMap<Bytes, Record>,
+        counters: &Arc<Counters>,
+    ) -> Result<Table> {
+        let mut builder = TableBuilder::new(config, counters);
+        for (key, record) in entries {
+            builder.add(key, record)?;
+        }
+        let contents = builder.finish()?;
+        let tmp = Table::path(&config.dir, id, "tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&contents)?;
+        file.sync_all()?;
+        let path = Table::path(&config.dir, id, "sst");
+        fs::rename(&tmp, &path)?;
+        let (table, _) = Table::load(path, id, config, counters)?;
+        Ok(table)
+    }
+
+    /// The index block which would have `key`, if it isn't past the end.
+    fn partition(&self, key: &[u8]) -> usize {
+        self.index.partition_point(|(last, _)| &last[..] < key)
+    }
+
+    fn get(&self, key: &Bytes) -> Result<Option<Record>> {
+        let Some((_, handle)) = self.index.get(self.partition(key)) else {
+            return Ok(None);
+        };
+        let mut index = self.read_block(*handle)?;
+        index.seek(key)?;
+        if !index.is_valid() {
+            return Ok(None);
+        }
+        let mut data = self.read_block(BlockHandle::decode(&index.value())?)?;
+        data.seek(key)?;
+        if !data.is_valid() || data.key() != &key[..] {
+            return Ok(None);
+        }
+        let (_, record) = self.entry(&data)?;
+        Ok(Some(record))
+    }
+
+    /// Reads the block at `handle` from the file.
+    fn read_block(&self, handle: BlockHandle) -> Result<BlockIter> {
+        let range = handle.range();
+        let mut buf = vec![0; range.len()];
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(range.start as u64))?;
+            file.read_exact(&mut buf)?;
+        }
+        let counters = &self.counters;
+        counters.blocks_read.fetch_add(1, Ordering::Relaxed);
+        (counters.bytes_read).fetch_add(range.len() as u64, Ordering::Relaxed);
+        let corrupted = |err: anyhow::Error| {
+            StorageError::Corrupted(format!("{}: {}", self.path.display(), err))
+        };
+        let (block, _) = decode_block(&Bytes::from(buf)).map_err(corrupted)?;
+        let block = Block::new(block).map_err(|err| corrupted(err.into()))?;
+        Ok(block.iter())
+    }
+
+    /// The entry `data` is at.
+    fn entry(&self, data: &BlockIter) -> Result<(Bytes, Record)> {
+        let (key, body) = (data.key(), data.value());
+        let counters = &self.counters;
+        counters.records_read.fetch_add(1, Ordering::Relaxed);
+        let len = key.len() + body.len();
+        (counters.record_bytes).fetch_add(len as u64, Ordering::Relaxed);
+        let record = Record::decode_body(body)?;
+        Ok((Bytes::copy_from_slice(key), record))
+    }
+}
+
+/// The entries of the memtable, with those expired by `now` as removals.
+struct MemtableIterator<'a> {
+    memtable: &'a BTreeMap<Bytes, Record>,
+    range: btree_map::Range<'a, Bytes, Record>,
+    current: Option<(&'a Bytes, &'a Record)>,
+    now: SystemTime,
+}
+
+impl<'a> MemtableIterator<'a> {
+    fn new(memtable: &'a BTreeMap<Bytes, Record>, now: SystemTime) -> MemtableIterator<'a> {
+        let mut range = memtable.range::<Bytes, _>(..);
+        let current = range.next();
+        MemtableIterator {
+            memtable,
+            range,
+            current,
+            now,
+        }
+    }
+}
+
+impl StorageIterator for MemtableIterator<'_> {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.range = self
+            .memtable
+            .range::<[u8], _>((Bound::Included(key), Bound::Unbounded));
+        self.current = self.range.next();
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.current = self.range.next();
+        Ok(())
+    }
+
+    fn is_valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn key(&self) -> &Bytes {
+        self.current.expect("the iterator is valid").0
+    }
+
+    fn value(&self) -> Option<&Bytes> {
+        self.current?.1.value_at(self.now)
+    }
+
+    fn expires_at(&self) -> Option<SystemTime> {
+        self.current?.1.expires_at
+    }
+}
+
+/// The entries of a table, read one at a time, with those expired by `now`
+/// as removals. The index block and the data block of the current entry are
+/// kept for the next ones.
+struct TableIterator<'a> {
+    table: &'a Table,
+    /// The position of `index` in the top-level index.
+    partition: usize,
+    /// Positioned at the data block being read, or to be read if `data` is
+    /// none.
+    index: Option<BlockIter>,
+    data: Option<BlockIter>,
+    current: Option<(Bytes, Record)>,
+    now: SystemTime,
+}
+
+impl<'a> TableIterator<'a> {
+    fn new(table: &'a Table, now: SystemTime) -> Result<TableIterator<'a>> {
+        let mut iter = TableIterator {
+            table,
+            partition: 0,
+            index: None,
+            data: None,
+            current: None,
+            now,
+        };
+        iter.seek(b"")?;
+        Ok(iter)
+    }
+
+    /// Moves on to the blocks of the next entry if the data block is done.
+    fn settle(&mut self) -> Result<()> {
+        loop {
+            match &self.data {
+                Some(data) if data.is_valid() => {
+                    self.current = Some(self.table.entry(data)?);
+                    return Ok(());
+                }
+                Some(_) => {
+                    self.data = None;
+                    if let Some(index) = &mut self.index {
+                        index.next()?;
+                    }
+                }
+                None => {}
+            }
+            match &self.index {
+                Some(index) if index.is_valid() => {
+                    let handle = BlockHandle::decode(&index.value())?;
+                    let mut data = self.table.read_block(handle)?;
+                    data.seek_to_first()?;
+                    self.data = Some(data);
+                }
+                Some(_) => {
+                    self.index = None;
+                    self.partition += 1;
+                }
+                None => {
+                    let Some((_, handle)) = self.table.index.get(self.partition) else {
+                        self.current = None;
+                        return Ok(());
+                    };
+                    let mut index = self.table.read_block(*handle)?;
+                    index.seek_to_first()?;
+                    self.index = Some(index);
+                }
+            }
+        }
+    }
+}
+
+impl StorageIterator for TableIterator<'_> {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.partition = self.table.partition(key);
+        self.index = None;
+        self.data = None;
+        if let Some((_, handle)) = self.table.index.get(self.partition) {
+            let mut index = self.table.read_block(*handle)?;
+            index.seek(key)?;
+            if index.is_valid() {
+                let mut data = self
+                    .table
+                    .read_block(BlockHandle::decode(&index.value())?)?;
+                data.seek(key)?;
+                self.data = Some(data);
+            }
+            self.index = Some(index);
+        }
+        self.settle()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if let Some(data) = &mut self.data {
+            data.next()?;
+        }
+        self.settle()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn key(&self) -> &Bytes {
+        &self.current.as_ref().expect("the iterator is valid").0
+    }
+
+    fn value(&self) -> Option<&Bytes> {
+        self.current.as_ref()?.1.value_at(self.now)
+    }
+
+    fn expires_at(&self) -> Option<SystemTime> {
+        self.current.as_ref()?.1.expires_at
+    }
+}
+
+/// The live tables of a directory written before manifests, all at level 0:
+/// a table is obsolete once merged into another.
+fn unlisted_version(ids: Vec<TableId>) -> Version {
+    let covers = |other: &TableId, id: &TableId| {
+        other != id && other.first <= id.first && id.last <= other.last
+    };
+    let mut live: Vec<TableId> = (ids.iter())
+        .filter(|id| !ids.iter().any(|other| covers(other, id)))
+        .copied()
+        .collect();
+    live.sort_by_key(|id| id.last);
+    Version { levels: vec![live] }
+}
+
+/// A storage engine keeping its data in a directory, see the [module
+/// documentation](self). It expires keys itself.
+pub struct LsmKV {
+    config: LsmConfig,
+    log: File,
+    log_size: usize,
+    memtable: BTreeMap<Bytes, Record>,
+    /// From the oldest.
+    tables: Vec<Table>,
+    versions: VersionSet,
+    /// The keys having a value, expired or not.
+    live: HashSet<Bytes>,
+    expiries: Expiries,
+    counters: Arc<Counters>,
+    pending: Arc<PendingSync>,
+}
+
+impl LsmKV {
+    /// Opens the engine in `config.dir`, recovering what it held. The end of
+    /// the log is dropped if its last write didn't complete, but any other
+    /// damage is an error.
+    pub fn open(config: LsmConfig) -> Result<LsmKV> {
+        if !config.block_compression.is_available() {
+            let compression = config.block_compression.to_string();
+            Err(StorageError::UnsupportedCompression(compression))?
+        }
+        fs::create_dir_all(&config.dir)?;
+        let mut files = HashMap::new();
+        for entry in fs::read_dir(&config.dir)? {
+            let path = entry?.path();
+            match path.extension().and_then(|extension| extension.to_str()) {
+                Some("tmp") => fs::remove_file(&path)?,
+                Some("sst") => {
+                    let id = Table::parse_name(&path).ok_or_else(|| {
+                        StorageError::Corrupted(format!("unexpected table {}", path.display()))
+                    })?;
+                    files.insert(id, path);
+                }
+                _ => {}
+            }
+        }
+        let versions = match VersionSet::recover(&config.dir)? {
+            Some(versions) => versions,
+            None => {
+                let ids: Vec<TableId> = files.keys().copied().collect();
+                let next_flush = ids.iter().map(|id| id.last + 1).max().unwrap_or(1);
+                VersionSet::create(&config.dir, unlisted_version(ids), next_flush)?
+            }
+        };
+        for (id, path) in &files {
+            if !versions.current().contains(*id) {
+                fs::remove_file(path)?;
+            }
+        }
+        let current: Vec<TableId> = versions.current().tables().map(|(_, id)| id).collect();
+
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(config.dir.join(LOG_FILE))?;
+        let pending = Arc::new(PendingSync {
+            log: log.try_clone()?,
+            dirty: AtomicBool::new(false),
+        });
+        let (weak, policy) = (Arc::downgrade(&pending), config.wal_sync.clone());
+        thread::Builder::new()
+            .name("wal fsync".to_string())
+            .spawn(move || sync_every_second(weak, policy))?;
+        let mut kv = LsmKV {
+            log,
+            config,
+            log_size: 0,
+            memtable: BTreeMap::new(),
+            tables: vec![],
+            versions,
+            live: HashSet::new(),
+            expiries: Expiries::new(),
+            counters: Arc::default(),
+            pending,
+        };
+        let now = SystemTime::now();
+        for id in current {
+            let path = files.remove(&id).ok_or_else(|| {
+                let path = Table::path(&kv.config.dir, id, "sst");
+                StorageError::Corrupted(format!("missing table {}", path.display()))
+            })?;
+            let (table, records) = Table::load(path, id, &kv.config, &kv.counters)?;
+            for (key, record) in records {
+                kv.track_loaded(key, &record, now);
+            }
+            kv.tables.push(table);
+        }
+        kv.recover_log(now)?;
+        Ok(kv)
+    }
+
+    fn recover_log(&mut self, now: SystemTime) -> Result<()> {
+        let path = self.config.dir.join(LOG_FILE);
+        let contents = Bytes::from(fs::read(&path)?);
+        let (records, len) = decode_records(&contents)
+            .map_err(|err| StorageError::Corrupted(format!("{}: {}", path.display(), err)))?;
+        if len < contents.len() {
+            tracing::warn!(
+                "dropping an incomplete write at the end of {}",
+                path.display()
+            );
+            self.log.set_len(len as u64)?;
+        }
+        for (_, key, record) in records {
+            self.track_loaded(key.clone(), &record, now);
+            self.memtable.insert(key, record);
+        }
+        self.log_size = len;
+        Ok(())
+    }
+
+    /// Keeps the keys and their deadlines in line with `record`.
+    fn track(&mut self, key: Bytes, record: &Record) {
+        match (&record.value, record.expires_at) {
+            (None, _) => {
+                self.expiries.clear(&key);
+                self.live.remove(&key);
+            }
+            (Some(_), Some(expires_at)) => {
+                self.expiries.set(key.clone(), expires_at);
+                self.live.insert(key);
+            }
+            (Some(_), None) => {
+                self.expiries.clear(&key);
+                self.live.insert(key);
+            }
+        }
+    }
+
+    /// Like [`LsmKV::track`] for a record read back at `now`, except that one
+    /// already expired removes its key. Merges drop the record itself.
+    fn track_loaded(&mut self, key: Bytes, record: &Record, now: SystemTime) {
+        match record.expires_at {
+            Some(expires_at) if expires_at <= now => {
+                self.expiries.clear(&key);
+                self.live.remove(&key);
+            }
+            _ => self.track(key, record),
+        }
+    }
+
+    fn write(&mut self, key: Bytes, record: Record) -> Result<()> {
+        let mut buf = vec![];
+        record.encode(&key, &mut buf);
+        self.log.write_all(&buf)?;
+        match self.config.wal_sync.get() {
+            WalSync::Always => self.config.wal_sync.sync(&self.log)?,
+            WalSync::EverySecond => self.pending.dirty.store(true, Ordering::Relaxed),
+            WalSync::No => {}
+        }
+        self.log_size += buf.len();
+        self.track(key.clone(), &record);
+        self.memtable.insert(key, record);
+        if self.log_size >= self.config.memtable_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the memtable out as a table and starts the log over.
+    fn flush(&mut self) -> Result<()> {
+        if self.memtable.is_empty() {
+            return Ok(());
+        }
+        let number = self.versions.next_flush();
+        let id = TableId::new(number, number);
+        let table = Table::write(&self.config, id, &self.memtable, &self.counters)?;
+        self.versions.apply(VersionEdit::default().add(0, id))?;
+        self.tables.push(table);
+        self.log.set_len(0)?;
+        self.log_size = 0;
+        self.memtable.clear();
+        let due = if self.config.background_merges {
+            self.config.stop_tables.max(1)
+        } else {
+            self.config.max_tables.max(1)
+        };
+        if self.tables.len() > due {
+            self.merge()?;
+        }
+        Ok(())
+    }
+
+    /// Merges every table into one. Being the oldest data, the result doesn't
+    /// need to remember removed or expired keys.
+    fn merge(&mut self) -> Result<()> {
+        let (Some(oldest), Some(newest)) = (self.tables.first(), self.tables.last()) else {
+            return Ok(());
+        };
+        let id = TableId::new(oldest.id.first, newest.id.last);
+        let now = SystemTime::now();
+        let sources = (self.tables.iter().rev())
+            .map(|table| TableIterator::new(table, now))
+            .collect::<Result<_>>()?;
+        let mut iter = MergingIterator::new(sources)?;
+        let mut entries = BTreeMap::new();
+        while iter.is_valid() {
+            let record = Record {
+                value: iter.value().cloned(),
+                expires_at: iter.expires_at(),
+            };
+            entries.insert(iter.key().clone(), record);
+            iter.next()?;
+        }
+        let merged = Table::write(&self.config, id, &entries, &self.counters)?;
+        let edit =
+            (self.tables.iter()).fold(VersionEdit::default(), |edit, table| edit.remove(table.id));
+        self.versions.apply(edit.add(1, id))?;
+        for table in std::mem::replace(&mut self.tables, vec![merged]) {
+            fs::remove_file(&table.path)?;
+        }
+        Ok(())
+    }
+
+    /// Iterates over the keys and their values as of `now`, in order. The
+    /// iterator sees the engine as it is, so it can't outlive a write.
+    pub fn iter(&self, now: SystemTime) -> Result<MergingIterator<Box<dyn StorageIterator + '_>>> {
+        let mut sources: Vec<Box<dyn StorageIterator>> =
+            vec![Box::new(MemtableIterator::new(&self.memtable, now))];
+        for table in self.tables.iter().rev() {
+            sources.push(Box::new(TableIterator::new(table, now)?));
+        }
+        MergingIterator::new(sources)
+    }
+
+    /// How the tables fared since the engine was opened.
+    pub fn stats(&self) -> TableStats {
+        self.counters.snapshot()
+    }
+
+    fn lookup(&self, key: &Bytes) -> Result<Option<Record>> {
+        if let Some(record) = self.memtable.get(key) {
+            return Ok(Some(record.clone()));
+        }
+        for table in self.tables.iter().rev() {
+            if let Some(record) = table.get(key)? {
+                return Ok(Some(record));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Storage for LsmKV {
+    fn put(&mut self, key: Bytes, value: Value) -> Result<()> {
+        let mut encoded = vec![];
+        value.encode(&mut encoded);
+        let value = Some(Bytes::from(encoded));
+        self.write(
+            key,
+            Record {
+                value,
+                expires_at: None,
+            },
+        )
+    }
+
+    fn delete(&mut self, key: Bytes) -> Result<()> {
+        self.remove(key)?.ok_or(StorageError::DeleteFailed)?;
+        Ok(())
+    }
+
+    fn get(&self, key: Bytes) -> Result<Option<Value>> {
+        if !self.live.contains(&key) || self.expiries.is_expired(&key, SystemTime::now()) {
+            return Ok(None);
+        }
+        let Some(encoded) = self.lookup(&key)?.and_then(|record| record.value) else {
+            return Ok(None);
+        };
+        let value =
+            Value::decode(encoded).map_err(|err| StorageError::Corrupted(err.to_string()))?;
+        Ok(Some(value))
+    }
+
+    fn remove(&mut self, key: Bytes) -> Result<Option<Value>> {
+        if !self.live.contains(&key) {
+            return Ok(None);
+        }
+        let value = self.get(key.clone())?;
+        let tombstone = Record {
+            value: None,
+            expires_at: None,
+        };
+        self.write(key, tombstone)?;
+        Ok(value)
+    }
+
+    fn scan(&self) -> Result<Vec<(Bytes, Value)>> {
+        let mut pairs = vec![];
+        for key in self.keys()? {
+            if let Some(value) = self.get(key.clone())? {
+                pairs.push((key, value));
+            }
+        }
+        Ok(pairs)
+    }
+
+    fn keys(&self) -> Result<Vec<Bytes>> {
+        let now = SystemTime::now();
+        let keys = self.live.iter();
+        Ok(keys
+            .filter(|key| !self.expiries.is_expired(key, now))
+            .cloned()
+            .collect())
+    }
+
+    /// Keys expired but not reclaimed yet are not counted.
+    fn len(&self) -> Result<usize> {
+        let expired = self.expiries.count_expired(SystemTime::now());
+        Ok(self.live.len() - expired)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        let edit =
+            (self.tables.iter()).fold(VersionEdit::default(), |edit, table| edit.remove(table.id));
+        self.versions.apply(edit)?;
+        for table in self.tables.drain(..) {
+            fs::remove_file(&table.path)?;
+        }
+        self.log.set_len(0)?;
+        self.log_size = 0;
+        self.memtable.clear();
+        self.live.clear();
+        self.expiries = Expiries::new();
+        Ok(())
+    }
+
+    fn supports_ttl(&self) -> bool {
+        true
+    }
+
+    fn blocks(&self) -> bool {
+        true
+    }
+
+    fn write_stall(&self) -> WriteStall {
+        let tables = self.tables.len();
+        if !self.config.background_merges {
+            WriteStall::None
+        } else if tables >= self.config.stop_tables {
+            WriteStall::Stop
+        } else if tables >= self.config.slowdown_tables {
+            WriteStall::Slowdown
+        } else {
+            WriteStall::None
+        }
+    }
+
+    /// Merges the tables if there are more than [`LsmConfig::max_tables`].
+    fn compact(&mut self) -> Result<()> {
+        if self.tables.len() > self.config.max_tables.max(1) {
+            self.merge()?;
+        }
+        Ok(())
+    }
+
+    fn table_stats(&self) -> Option<TableStats> {
+        Some(self.stats())
+    }
+
+    /// Syncs the log; the tables are synced as they are written.
+    fn sync(&mut self) -> Result<()> {
+        self.config.wal_sync.sync(&self.log)
+    }
+
+    fn put_with_ttl(&mut self, key: Bytes, value: Value, expires_at: SystemTime) -> Result<()> {
+        let mut encoded = vec![];
+        value.encode(&mut encoded);
+        let value = Some(Bytes::from(encoded));
+        let expires_at = Some(expires_at);
+        self.write(key, Record { value, expires_at })
+    }
+
+    fn expires_at(&self, key: &Bytes) -> Option<SystemTime> {
+        self.expiries.get(key)
+    }
+
+    /// Expired keys are hidden from reads as soon as they expire, this writes
+    /// their removal so that a merge can drop them.
+    fn expire(&mut self, now: SystemTime) -> Vec<Bytes> {
+        let expired = self.expiries.take_expired(now);
+        for key in &expired {
+            let tombstone = Record {
+                value: None,
+                expires_at: None,
+            };
+            if let Err(err) = self.write(key.clone(), tombstone) {
+                tracing::error!("failed to remove expired key {:?}: {}", key, err);
+            }
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MANIFEST_FILE;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("uranus-lsm-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(Bytes::from(s.to_string()))
+    }
+
+    #[test]
+    fn test_flush_and_merge() {
+        let dir = scratch_dir("merge");
+        let config = LsmConfig {
+            memtable_size: 256,
+            max_tables: 2,
+            ..LsmConfig::new(&dir)
+        };
+        let mut kv = LsmKV::open(config.clone()).unwrap();
+        for i in 0..100 {
+            kv.put(Bytes::from(format!("key{}", i)), string(&i.to_string()))
+                .unwrap();
+        }
+        for i in 0..50 {
+            kv.delete(Bytes::from(format!("key{}", i))).unwrap();
+        }
+        assert!(kv.tables.len() <= 3);
+        drop(kv);
+
+        let kv = LsmKV::open(config).unwrap();
+        assert_eq!(kv.len().unwrap(), 50);
+        assert_eq!(kv.get(Bytes::from("key10")).unwrap(), None);
+        assert_eq!(kv.get(Bytes::from("key70")).unwrap(), Some(string("70")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_background_merges() {
+        let dir = scratch_dir("background");
+        let config = LsmConfig {
+            memtable_size: 64,
+            max_tables: 2,
+            background_merges: true,
+            slowdown_tables: 4,
+            stop_tables: 6,
+            ..LsmConfig::new(&dir)
+        };
+        let mut kv = LsmKV::open(config).unwrap();
+        let mut stalls = vec![];
+        for i in 0..40 {
+            kv.put(Bytes::from(format!("key{}", i)), string("value"))
+                .unwrap();
+            stalls.push(kv.write_stall());
+        }
+        // Tables pile up, but never past the stop trigger.
+        assert!(stalls.contains(&WriteStall::Slowdown));
+        assert!(stalls.contains(&WriteStall::Stop));
+        assert!(kv.tables.len() <= 6);
+
+        kv.compact().unwrap();
+        assert!(kv.tables.len() <= 2);
+        assert_eq!(kv.write_stall(), WriteStall::None);
+        assert_eq!(kv.len().unwrap(), 40);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_expired_while_closed() {
+        let dir = scratch_dir("expired");
+        let config = LsmConfig {
+            memtable_size: 64,
+            ..LsmConfig::new(&dir)
+        };
+        let mut kv = LsmKV::open(config.clone()).unwrap();
+        let soon = SystemTime::now() + Duration::from_millis(50);
+        let later = SystemTime::now() + Duration::from_secs(60);
+        // An older value in a table mustn't come back either.
+        kv.put(Bytes::from("short"), string("old")).unwrap();
+        kv.put(Bytes::from("filler"), string("x".repeat(64).as_str()))
+            .unwrap();
+        assert!(!kv.tables.is_empty());
+        kv.put_with_ttl(Bytes::from("short"), string("new"), soon)
+            .unwrap();
+        kv.put_with_ttl(Bytes::from("long"), string("kept"), later)
+            .unwrap();
+        drop(kv);
+
+        std::thread::sleep(Duration::from_millis(100));
+        let mut kv = LsmKV::open(config).unwrap();
+        assert_eq!(kv.get(Bytes::from("short")).unwrap(), None);
+        assert_eq!(kv.expires_at(&Bytes::from("short")), None);
+        assert_eq!(kv.get(Bytes::from("long")).unwrap(), Some(string("kept")));
+        assert_eq!(kv.expires_at(&Bytes::from("long")), Some(later));
+        assert_eq!(kv.len().unwrap(), 2);
+        // Nothing is left to sweep.
+        assert!(kv.expire(SystemTime::now()).is_empty());
+        kv.compact().unwrap();
+        assert_eq!(kv.get(Bytes::from("short")).unwrap(), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_iter() {
+        let dir = scratch_dir("iter");
+        let config = LsmConfig {
+            memtable_size: 128,
+            ..LsmConfig::new(&dir)
+        };
+        let mut kv = LsmKV::open(config).unwrap();
+        for i in 0..20 {
+            kv.put(Bytes::from(format!("key{:02}", i)), string("old"))
+                .unwrap();
+        }
+        assert!(kv.tables.len() > 1);
+        kv.put(Bytes::from("key03"), string("new")).unwrap();
+        kv.delete(Bytes::from("key05")).unwrap();
+        let past = SystemTime::now() - Duration::from_secs(1);
+        kv.put_with_ttl(Bytes::from("key07"), string("new"), past)
+            .unwrap();
+
+        let now = SystemTime::now();
+        let mut iter = kv.iter(now).unwrap();
+        let mut keys = vec![];
+        while iter.is_valid() {
+            let value = Value::decode(iter.value().unwrap().clone()).unwrap();
+            assert_eq!(kv.get(iter.key().clone()).unwrap(), Some(value));
+            keys.push(iter.key().clone());
+            iter.next().unwrap();
+        }
+        let expected: Vec<_> = (0..20)
+            .filter(|i| ![5, 7].contains(i))
+            .map(|i| Bytes::from(format!("key{:02}", i)))
+            .collect();
+        assert_eq!(keys, expected);
+
+        iter.seek(b"key05").unwrap();
+        assert_eq!(iter.key(), &Bytes::from("key06"));
+        iter.seek(b"key2").unwrap();
+        assert!(!iter.is_valid());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_manifest_recovery() {
+        let dir = scratch_dir("manifest");
+        let config = LsmConfig {
+            memtable_size: 128,
+            ..LsmConfig::new(&dir)
+        };
+        let mut kv = LsmKV::open(config.clone()).unwrap();
+        for i in 0..20 {
+            kv.put(Bytes::from(format!("key{:02}", i)), string("value"))
+                .unwrap();
+        }
+        let tables: Vec<TableId> = kv.tables.iter().map(|table| table.id).collect();
+        assert_eq!(
+            kv.versions
+                .current()
+                .tables()
+                .map(|(_, id)| id)
+                .collect::<Vec<_>>(),
+            tables
+        );
+        drop(kv);
+
+        // A merge which crashed before updating the manifest left its table,
+        // holding stale data.
+        let stray = TableId::new(1, 99);
+        let mut entries = BTreeMap::new();
+        let record = Record {
+            value: Some(Bytes::from("stale")),
+            expires_at: None,
+        };
+        entries.insert(Bytes::from("key00"), record);
+        Table::write(&config, stray, &entries, &Arc::default()).unwrap();
+        let kv = LsmKV::open(config.clone()).unwrap();
+        assert!(!Table::path(&dir, stray, "sst").exists());
+        assert_eq!(kv.get(Bytes::from("key00")).unwrap(), Some(string("value")));
+        assert_eq!(kv.len().unwrap(), 20);
+        drop(kv);
+
+        // A directory without a manifest gets one.
+        fs::remove_file(dir.join(MANIFEST_FILE)).unwrap();
+        let kv = LsmKV::open(config).unwrap();
+        assert_eq!(kv.len().unwrap(), 20);
+        assert!(dir.join(MANIFEST_FILE).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_wal_sync() {
+        let dir = scratch_dir("wal-sync");
+        let policy = Arc::new(WalSyncPolicy::new(WalSync::Always));
+        let config = LsmConfig {
+            wal_sync: policy.clone(),
+            ..LsmConfig::new(&dir)
+        };
+        let mut kv = LsmKV::open(config).unwrap();
+        for i in 0..10 {
+            kv.put(Bytes::from(format!("key{}", i)), string("value"))
+                .unwrap();
+        }
+        assert_eq!(policy.syncs(), 10);
+
+        policy.set(WalSync::No);
+        kv.put(Bytes::from("no"), string("value")).unwrap();
+        assert_eq!(policy.syncs(), 10);
+
+        // The writes of the last second are synced together, in the background.
+        policy.set(WalSync::EverySecond);
+        for i in 0..10 {
+            kv.put(Bytes::from(format!("everysec{}", i)), string("value"))
+                .unwrap();
+        }
+        assert_eq!(policy.syncs(), 10);
+        for _ in 0..30 {
+            if policy.syncs() > 10 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(policy.syncs(), 11);
+        std::thread::sleep(Duration::from_millis(1100));
+        assert_eq!(policy.syncs(), 11);
+
+        assert_eq!("everysec".parse::<WalSync>().unwrap(), WalSync::EverySecond);
+        assert!("sometimes".parse::<WalSync>().is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_log_recovery() {
+        let dir = scratch_dir("log");
+        let mut kv = LsmKV::open(LsmConfig::new(&dir)).unwrap();
+        kv.put(Bytes::from("a"), string("1")).unwrap();
+        kv.put(Bytes::from("b"), string("2")).unwrap();
+        drop(kv);
+
+        // A write cut short is dropped.
+        let log = dir.join(LOG_FILE);
+        let mut contents = fs::read(&log).unwrap();
+        let complete = contents.len();
+        contents.extend_from_slice(&[7, 0, 0, 0, 200]);
+        fs::write(&log, &contents).unwrap();
+        let kv = LsmKV::open(LsmConfig::new(&dir)).unwrap();
+        assert_eq!(kv.get(Bytes::from("b")).unwrap(), Some(string("2")));
+        assert_eq!(fs::metadata(&log).unwrap().len(), complete as u64);
+        drop(kv);
+
+        // A damaged one is not.
+        contents.truncate(complete);
+        contents[complete - 1] ^= 0xff;
+        fs::write(&log, &contents).unwrap();
+        let err = LsmKV::open(LsmConfig::new(&dir)).err().unwrap();
+        assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn fill(config: &LsmConfig, value: &str) -> LsmKV {
+        let mut kv = LsmKV::open(config.clone()).unwrap();
+        for i in 0..50 {
+            kv.put(Bytes::from(format!("key{:02}", i)), string(value))
+                .unwrap();
+        }
+        kv.flush().unwrap();
+        kv
+    }
+
+    #[test]
+    fn test_blocks() {
+        let dir = scratch_dir("blocks");
+        let config = LsmConfig {
+            block_size: 64,
+            block_restart_interval: 4,
+            ..LsmConfig::new(&dir)
+        };
+        let kv = fill(&config, "value");
+        assert!(kv.tables.iter().all(|table| table.index.len() > 1));
+        let written = kv.stats();
+        assert!(written.compression_ratio() < 1.0);
+        assert_eq!(written.blocks_read, 0);
+        assert_eq!(written.read_amplification(), 1.0);
+
+        // A point read fetches an index block and a data block for one record.
+        assert_eq!(kv.get(Bytes::from("key07")).unwrap(), Some(string("value")));
+        assert_eq!(kv.get(Bytes::from("key99")).unwrap(), None);
+        let read = kv.stats();
+        assert_eq!((read.blocks_read, read.records_read), (2, 1));
+        assert!(read.read_amplification() > 2.0);
+
+        // Iterating reads each block once, but the top-level index.
+        let mut iter = kv.iter(SystemTime::now()).unwrap();
+        while iter.is_valid() {
+            iter.next().unwrap();
+        }
+        let iterated = kv.stats();
+        assert_eq!(iterated.records_read, 51);
+        let tables = kv.tables.len() as u64;
+        let blocks = iterated.blocks_read - read.blocks_read;
+        assert_eq!(blocks, written.blocks_written - tables);
+
+        // Seeks go across blocks.
+        iter.seek(b"key255").unwrap();
+        let mut keys = vec![];
+        while iter.is_valid() {
+            keys.push(iter.key().clone());
+            iter.next().unwrap();
+        }
+        let expected: Vec<_> = (26..50).map(|i| Bytes::from(format!("key{}", i))).collect();
+        assert_eq!(keys, expected);
+        drop(iter);
+        drop(kv);
+
+        // A damaged block is caught.
+        let path = Table::path(&dir, TableId::new(1, 1), "sst");
+        let mut contents = fs::read(&path).unwrap();
+        contents[TABLE_HEADER + BLOCK_HEADER] ^= 0xff;
+        fs::write(&path, &contents).unwrap();
+        let err = LsmKV::open(config).err().unwrap();
+        assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_compressed_blocks() {
+        let dir = scratch_dir("compressed");
+        let config = LsmConfig {
+            block_compression: Compression::Lz4,
+            ..LsmConfig::new(&dir)
+        };
+        let kv = fill(&config, &"value".repeat(20));
+        assert!(kv.stats().compression_ratio() > 2.0);
+        drop(kv);
+
+        // Tables keep their compression when the engine changes it.
+        let config = LsmConfig {
+            block_compression: Compression::None,
+            ..config
+        };
+        let kv = LsmKV::open(config).unwrap();
+        let value = string(&"value".repeat(20));
+        assert_eq!(kv.get(Bytes::from("key42")).unwrap(), Some(value));
+        let contents = fs::read(&kv.tables[0].path).unwrap();
+        let len = (&contents[TABLE_HEADER..]).get_u32_le() as usize;
+        let trailer = TABLE_HEADER + BLOCK_HEADER + len;
+        assert_eq!(contents[trailer], Compression::Lz4.code());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_table_versions() {
+        let dir = scratch_dir("versions");
+        let config = LsmConfig::new(&dir);
+        let kv = fill(&config, "value");
+        let path = kv.tables[0].path.clone();
+        drop(kv);
+        let (_, entries) =
+            Table::load(path.clone(), TableId::new(1, 1), &config, &Arc::default()).unwrap();
+        let mut records = vec![];
+        for (key, record) in entries {
+            record.encode(&key, &mut records);
+        }
+        let current = fs::read(&path).unwrap();
+
+        // Tables written before versions were their records one after
+        // another, those of version 1 had them in blocks. Both are rewritten.
+        let mut blocks = TABLE_MAGIC.to_vec();
+        blocks.put_u8(1);
+        encode_block(&records, Compression::None, &mut blocks).unwrap();
+        for old in [records, blocks] {
+            fs::write(&path, old).unwrap();
+            let kv = LsmKV::open(config.clone()).unwrap();
+            assert_eq!(kv.len().unwrap(), 50);
+            assert_eq!(kv.get(Bytes::from("key33")).unwrap(), Some(string("value")));
+            drop(kv);
+            assert_eq!(fs::read(&path).unwrap(), current);
+        }
+
+        // Those of a later version are refused.
+        let mut later = current;
+        later[TABLE_HEADER - 1] += 1;
+        fs::write(&path, later).unwrap();
+        let err = LsmKV::open(config).err().unwrap();
+        let err = err.downcast_ref::<StorageError>().unwrap();
+        assert!(matches!(err, StorageError::UnsupportedTableVersion(3)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,234 @@
+//! The tables an LSM engine is made of
+//!
+//! The `MANIFEST` file of an [`LsmKV`](crate::LsmKV) directory lists its live
+//! tables by level, and the number of the next flush. Each change is written
+//! as a new file renamed over the old one, so the manifest is always either
+//! version in full, and the engine only takes a table in or removes one once
+//! the manifest says so. Flushes and merges interrupted by a crash leave
+//! tables the manifest doesn't list, which are deleted on opening.
+//!
+//! Level 0 holds the tables flushed from the memtable, whose keys overlap,
+//! and level 1 the table they are merged into.
+//!
+//! The file is text: a line `#<checksum>` holding the CRC-32C of the rest in
+//! hex, a line `next_flush <number>`, then a line `table <level> <first>
+//! <last>` per table, from the oldest of each level.
+
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{crc32c, StorageError};
+
+pub const MANIFEST_FILE: &str = "MANIFEST";
+
+/// A table, by the flushes it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TableId {
+    pub first: u64,
+    pub last: u64,
+}
+
+impl TableId {
+    pub fn new(first: u64, last: u64) -> TableId {
+        TableId { first, last }
+    }
+}
+
+/// The live tables, by level.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Version {
+    pub levels: Vec<Vec<TableId>>,
+}
+
+impl Version {
+    /// Every table, from the oldest data: the deepest level first.
+    pub fn tables(&self) -> impl Iterator<Item = (usize, TableId)> + '_ {
+        let levels = self.levels.iter().enumerate().rev();
+        levels.flat_map(|(level, tables)| tables.iter().map(move |table| (level, *table)))
+    }
+
+    pub fn contains(&self, table: TableId) -> bool {
+        self.tables().any(|(_, live)| live == table)
+    }
+}
+
+/// A change of the live tables.
+#[derive(Debug, Clone, Default)]
+pub struct VersionEdit {
+    pub added: Vec<(usize, TableId)>,
+    pub removed: Vec<TableId>,
+}
+
+impl VersionEdit {
+    pub fn add(mut self, level: usize, table: TableId) -> VersionEdit {
+        self.added.push((level, table));
+        self
+    }
+
+    pub fn remove(mut self, table: TableId) -> VersionEdit {
+        self.removed.push(table);
+        self
+    }
+}
+
+/// The current [`Version`] of an engine, kept in line with its manifest.
+#[derive(Debug)]
+pub struct VersionSet {
+    dir: PathBuf,
+    current: Version,
+    next_flush: u64,
+}
+
+impl VersionSet {
+    /// Reads the manifest in `dir`, none if there is none yet.
+    pub fn recover(dir: &Path) -> Result<Option<VersionSet>> {
+        let path = dir.join(MANIFEST_FILE);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => Err(err)?,
+        };
+        let corrupted =
+            |reason: &str| StorageError::Corrupted(format!("{}: {}", path.display(), reason));
+        let (header, body) = contents
+            .split_once('\n')
+            .ok_or_else(|| corrupted("missing checksum"))?;
+        let crc = header
+            .strip_prefix('#')
+            .and_then(|crc| u32::from_str_radix(crc, 16).ok())
+            .ok_or_else(|| corrupted("malformed checksum"))?;
+        if crc32c(body.as_bytes()) != crc {
+            Err(corrupted("checksum mismatch"))?
+        }
+
+        let mut versions = VersionSet {
+            dir: dir.to_path_buf(),
+            current: Version::default(),
+            next_flush: 1,
+        };
+        for line in body.lines() {
+            let fields: Vec<u64> = line
+                .split(' ')
+                .skip(1)
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map_err(|_| corrupted("malformed line"))?;
+            match (line.split(' ').next(), fields.as_slice()) {
+                (Some("next_flush"), &[next]) => versions.next_flush = next,
+                (Some("table"), &[level, first, last]) => {
+                    versions.push(level as usize, TableId::new(first, last))
+                }
+                _ => Err(corrupted("malformed line"))?,
+            }
+        }
+        Ok(Some(versions))
+    }
+
+    /// Writes the manifest of `version` in `dir`, replacing any.
+    pub fn create(dir: &Path, version: Version, next_flush: u64) -> Result<VersionSet> {
+        let versions = VersionSet {
+            dir: dir.to_path_buf(),
+            current: version,
+            next_flush,
+        };
+        versions.save()?;
+        Ok(versions)
+    }
+
+    pub fn current(&self) -> &Version {
+        &self.current
+    }
+
+    /// The number of the next flush, past every table of the manifest.
+    pub fn next_flush(&self) -> u64 {
+        self.next_flush
+    }
+
+    /// Writes the manifest with `edit` applied, then makes it current.
+    pub fn apply(&mut self, edit: VersionEdit) -> Result<()> {
+        let mut next = VersionSet {
+            dir: self.dir.clone(),
+            current: self.current.clone(),
+            next_flush: self.next_flush,
+        };
+        for level in &mut next.current.levels {
+            level.retain(|table| !edit.removed.contains(table));
+        }
+        for (level, table) in edit.added {
+            next.push(level, table);
+        }
+        next.save()?;
+        *self = next;
+        Ok(())
+    }
+
+    fn push(&mut self, level: usize, table: TableId) {
+        if self.current.levels.len() <= level {
+            self.current.levels.resize(level + 1, vec![]);
+        }
+        self.current.levels[level].push(table);
+        self.next_flush = self.next_flush.max(table.last + 1);
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut body = format!("next_flush {}\n", self.next_flush);
+        for (level, tables) in self.current.levels.iter().enumerate() {
+            for table in tables {
+                writeln!(body, "table {} {} {}", level, table.first, table.last)?;
+            }
+        }
+        let tmp = self.dir.join(format!("{}.tmp", MANIFEST_FILE));
+        let mut file = File::create(&tmp)?;
+        write!(file, "#{:08x}\n{}", crc32c(body.as_bytes()), body)?;
+        file.sync_all()?;
+        fs::rename(&tmp, self.dir.join(MANIFEST_FILE))?;
+        // The rename itself has to be durable.
+        File::open(&self.dir)?.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_set() {
+        let dir = std::env::temp_dir().join(format!("uranus-manifest-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        assert!(VersionSet::recover(&dir).unwrap().is_none());
+
+        let mut versions = VersionSet::create(&dir, Version::default(), 1).unwrap();
+        let (a, b, merged) = (TableId::new(1, 1), TableId::new(2, 2), TableId::new(1, 2));
+        versions.apply(VersionEdit::default().add(0, a)).unwrap();
+        versions.apply(VersionEdit::default().add(0, b)).unwrap();
+        assert_eq!(versions.next_flush(), 3);
+        let edit = VersionEdit::default().remove(a).remove(b).add(1, merged);
+        versions.apply(edit).unwrap();
+        versions
+            .apply(VersionEdit::default().add(0, TableId::new(3, 3)))
+            .unwrap();
+
+        let recovered = VersionSet::recover(&dir).unwrap().unwrap();
+        assert_eq!(recovered.current(), versions.current());
+        assert_eq!(recovered.next_flush(), 4);
+        let tables: Vec<_> = recovered.current().tables().collect();
+        assert_eq!(tables, vec![(1, merged), (0, TableId::new(3, 3))]);
+        assert!(!recovered.current().contains(a));
+
+        // A damaged manifest is refused.
+        let path = dir.join(MANIFEST_FILE);
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::write(&path, contents.replace("table 1", "table 2")).unwrap();
+        let err = VersionSet::recover(&dir).unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
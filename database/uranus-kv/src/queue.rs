@@ -0,0 +1,95 @@
+//! Priority queues
+//!
+
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+
+use crate::sorted_set::Score;
+
+/// Items ordered by a floating point priority, which can be taken from either
+/// end. Items of equal priority come out in insertion order from the low end,
+/// and in reverse from the high end.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PriorityQueue {
+    items: BTreeMap<(Score, u64), Bytes>,
+    next_seq: u64,
+}
+
+impl PriorityQueue {
+    pub fn new() -> PriorityQueue {
+        PriorityQueue::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// See [`Value::approximate_size`](crate::Value::approximate_size).
+    pub fn approximate_size(&self) -> usize {
+        crate::value::sampled(self.len(), self.items.values().map(Bytes::len))
+    }
+
+    pub fn push(&mut self, priority: f64, item: Bytes) {
+        self.items.insert((Score(priority), self.next_seq), item);
+        self.next_seq += 1;
+    }
+
+    pub fn pop_min(&mut self) -> Option<(Bytes, f64)> {
+        self.items
+            .pop_first()
+            .map(|((priority, _), item)| (item, priority.0))
+    }
+
+    pub fn pop_max(&mut self) -> Option<(Bytes, f64)> {
+        self.items
+            .pop_last()
+            .map(|((priority, _), item)| (item, priority.0))
+    }
+
+    pub fn peek_min(&self) -> Option<(&Bytes, f64)> {
+        self.items
+            .first_key_value()
+            .map(|((priority, _), item)| (item, priority.0))
+    }
+
+    pub fn peek_max(&self) -> Option<(&Bytes, f64)> {
+        self.items
+            .last_key_value()
+            .map(|((priority, _), item)| (item, priority.0))
+    }
+
+    /// The items from the low end to the high end, in the order they pop.
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, f64)> {
+        self.items
+            .iter()
+            .map(|((priority, _), item)| (item, priority.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pop_both_ends() {
+        let mut queue = PriorityQueue::new();
+        queue.push(2.0, Bytes::from("b"));
+        queue.push(-1.5, Bytes::from("a"));
+        queue.push(2.0, Bytes::from("c"));
+        queue.push(7.0, Bytes::from("d"));
+
+        assert_eq!(queue.peek_min(), Some((&Bytes::from("a"), -1.5)));
+        assert_eq!(queue.pop_max(), Some((Bytes::from("d"), 7.0)));
+        assert_eq!(queue.pop_min(), Some((Bytes::from("a"), -1.5)));
+        assert_eq!(queue.pop_min(), Some((Bytes::from("b"), 2.0)));
+        assert_eq!(queue.peek_max(), Some((&Bytes::from("c"), 2.0)));
+        assert_eq!(queue.pop_max(), Some((Bytes::from("c"), 2.0)));
+        assert!(queue.is_empty());
+        assert_eq!(queue.pop_min(), None);
+    }
+}
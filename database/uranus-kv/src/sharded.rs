@@ -0,0 +1,208 @@
+//! An in-memory engine splitting the keyspace over several hash maps
+//!
+//! The shard count can be changed online: the old shards are kept aside and
+//! moved into the new layout one at a time, the way Redis rehashes its dicts
+//! incrementally, so no single operation pays for moving the whole keyspace.
+//!
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    mem,
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{Storage, StorageError, Value};
+
+type Shard = HashMap<Bytes, Value>;
+
+pub struct ShardedKV {
+    shards: Vec<Shard>,
+    migration: Option<Migration>,
+}
+
+/// The layout being moved away from, and the next of its shards to move.
+struct Migration {
+    shards: Vec<Shard>,
+    next: usize,
+}
+
+fn new_shards(count: usize) -> Vec<Shard> {
+    assert!(count > 0, "a sharded engine needs at least one shard");
+    (0..count).map(|_| HashMap::new()).collect()
+}
+
+fn shard_index(key: &Bytes, count: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % count as u64) as usize
+}
+
+impl ShardedKV {
+    pub fn new(shards: usize) -> ShardedKV {
+        ShardedKV {
+            shards: new_shards(shards),
+            migration: None,
+        }
+    }
+
+    pub fn is_migrating(&self) -> bool {
+        self.migration.is_some()
+    }
+
+    fn shard_mut(&mut self, key: &Bytes) -> &mut Shard {
+        let index = shard_index(key, self.shards.len());
+        &mut self.shards[index]
+    }
+
+    /// Where `key` lived before the running migration, if that shard hasn't been moved yet.
+    fn old_shard_mut(&mut self, key: &Bytes) -> Option<&mut Shard> {
+        let migration = self.migration.as_mut()?;
+        let index = shard_index(key, migration.shards.len());
+        migration.shards.get_mut(index)
+    }
+
+    fn old_shard(&self, key: &Bytes) -> Option<&Shard> {
+        let migration = self.migration.as_ref()?;
+        let index = shard_index(key, migration.shards.len());
+        migration.shards.get(index)
+    }
+}
+
+impl Storage for ShardedKV {
+    fn put(&mut self, key: Bytes, value: Value) -> Result<()> {
+        self.migrate_step();
+        if let Some(old) = self.old_shard_mut(&key) {
+            old.remove(&key);
+        }
+        self.shard_mut(&key).insert(key, value);
+        Ok(())
+    }
+
+    fn delete(&mut self, key: Bytes) -> Result<()> {
+        self.remove(key)?.ok_or(StorageError::DeleteFailed)?;
+        Ok(())
+    }
+
+    fn get(&self, key: Bytes) -> Result<Option<Value>> {
+        let index = shard_index(&key, self.shards.len());
+        let result = self.shards[index]
+            .get(&key)
+            .or_else(|| self.old_shard(&key)?.get(&key))
+            .cloned();
+        Ok(result)
+    }
+
+    fn remove(&mut self, key: Bytes) -> Result<Option<Value>> {
+        self.migrate_step();
+        let old = self.old_shard_mut(&key).and_then(|old| old.remove(&key));
+        let new = self.shard_mut(&key).remove(&key);
+        Ok(new.or(old))
+    }
+
+    fn scan(&self) -> Result<Vec<(Bytes, Value)>> {
+        let old = self.migration.iter().flat_map(|m| m.shards.iter());
+        let pairs = self
+            .shards
+            .iter()
+            .chain(old)
+            .flat_map(|shard| shard.iter())
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        Ok(pairs)
+    }
+
+    fn keys(&self) -> Result<Vec<Bytes>> {
+        let old = self.migration.iter().flat_map(|m| m.shards.iter());
+        let shards = self.shards.iter().chain(old);
+        Ok(shards.flat_map(|shard| shard.keys()).cloned().collect())
+    }
+
+    fn len(&self) -> Result<usize> {
+        let old = self.migration.iter().flat_map(|m| m.shards.iter());
+        Ok(self.shards.iter().chain(old).map(Shard::len).sum())
+    }
+
+    /// Drops the running migration along with the keys.
+    fn clear(&mut self) -> Result<()> {
+        self.shards = new_shards(self.shards.len());
+        self.migration = None;
+        Ok(())
+    }
+
+    fn shard_count(&self) -> Option<usize> {
+        Some(self.shards.len())
+    }
+
+    /// Starts moving every key into `shards` new shards. A migration still
+    /// running is finished first.
+    fn reshard(&mut self, shards: usize) -> Result<()> {
+        while self.migrate_step() {}
+        let old = mem::replace(&mut self.shards, new_shards(shards));
+        self.migration = Some(Migration {
+            shards: old,
+            next: 0,
+        });
+        Ok(())
+    }
+
+    /// Moves one old shard into the new layout.
+    fn migrate_step(&mut self) -> bool {
+        let Some(migration) = &mut self.migration else {
+            return false;
+        };
+        if let Some(shard) = migration.shards.get_mut(migration.next) {
+            let count = self.shards.len();
+            for (key, value) in shard.drain() {
+                let index = shard_index(&key, count);
+                // a key written during the migration already has its newest value in place
+                self.shards[index].entry(key).or_insert(value);
+            }
+            migration.next += 1;
+        }
+        if migration.next >= migration.shards.len() {
+            self.migration = None;
+            false
+        } else {
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(i: usize) -> Bytes {
+        Bytes::from(format!("key{}", i))
+    }
+
+    fn string(s: &'static str) -> Value {
+        Value::String(Bytes::from(s))
+    }
+
+    #[test]
+    fn test_online_reshard() {
+        let mut kv = ShardedKV::new(4);
+        for i in 0..100 {
+            kv.put(key(i), string("old")).unwrap();
+        }
+
+        kv.reshard(16).unwrap();
+        assert!(kv.is_migrating());
+        kv.put(key(0), string("new")).unwrap();
+        kv.delete(key(1)).unwrap();
+        for i in 2..100 {
+            assert_eq!(kv.get(key(i)).unwrap(), Some(string("old")));
+        }
+
+        while kv.migrate_step() {}
+        assert!(!kv.is_migrating());
+        assert_eq!(kv.shard_count(), Some(16));
+        assert_eq!(kv.get(key(0)).unwrap(), Some(string("new")));
+        assert_eq!(kv.get(key(1)).unwrap(), None);
+        assert_eq!(kv.scan().unwrap().len(), 99);
+    }
+}
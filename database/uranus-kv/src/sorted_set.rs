@@ -0,0 +1,138 @@
+//! Sorted sets
+//!
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+    ops::Bound,
+};
+
+use bytes::Bytes;
+
+/// Distinct members, each with a score, ordered by score then by member.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SortedSet {
+    ordered: BTreeSet<(Score, Bytes)>,
+    scores: HashMap<Bytes, f64>,
+}
+
+/// A float, totally ordered so that it can key a map.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Score(pub(crate) f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl SortedSet {
+    pub fn new() -> SortedSet {
+        SortedSet::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// See [`Value::approximate_size`](crate::Value::approximate_size).
+    /// Members are held twice, by score and by name.
+    pub fn approximate_size(&self) -> usize {
+        2 * crate::value::sampled(self.len(), self.scores.keys().map(Bytes::len))
+    }
+
+    /// Sets the score of `member`, returns whether it is new.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.ordered.remove(&(Score(previous), member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+        previous.is_none()
+    }
+
+    pub fn score(&self, member: &Bytes) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Members ranked `start` to `stop` included, from the lowest score. Negative
+    /// ranks count from the highest score, -1 being the last member.
+    pub fn range_by_rank(&self, start: i64, stop: i64) -> Vec<(&Bytes, f64)> {
+        let len = self.len() as i64;
+        let start = if start < 0 { len + start } else { start }.max(0);
+        let stop = if stop < 0 { len + stop } else { stop }.min(len - 1);
+        if start > stop {
+            return vec![];
+        }
+        self.ordered
+            .iter()
+            .skip(start as usize)
+            .take((stop - start + 1) as usize)
+            .map(|(score, member)| (member, score.0))
+            .collect()
+    }
+
+    /// Members scored from `min` to `max` included, from the lowest score.
+    pub fn range_by_score(&self, min: f64, max: f64) -> Vec<(&Bytes, f64)> {
+        if Score(min) > Score(max) {
+            return vec![];
+        }
+        let range = (
+            Bound::Included((Score(min), Bytes::new())),
+            Bound::Unbounded,
+        );
+        self.ordered
+            .range(range)
+            .take_while(|(score, _)| *score <= Score(max))
+            .map(|(score, member)| (member, score.0))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranges() {
+        let mut set = SortedSet::new();
+        assert!(set.insert(Bytes::from("b"), 2.0));
+        assert!(set.insert(Bytes::from("a"), 1.0));
+        assert!(set.insert(Bytes::from("c"), 3.0));
+        assert!(!set.insert(Bytes::from("a"), 4.0));
+
+        let members = |range: Vec<(&Bytes, f64)>| -> Vec<Bytes> {
+            range
+                .into_iter()
+                .map(|(member, _)| member.clone())
+                .collect()
+        };
+        assert_eq!(members(set.range_by_rank(0, -1)), ["b", "c", "a"]);
+        assert_eq!(members(set.range_by_rank(-2, 10)), ["c", "a"]);
+        assert!(set.range_by_rank(2, 1).is_empty());
+        assert_eq!(members(set.range_by_score(2.0, 3.0)), ["b", "c"]);
+        assert_eq!(
+            set.range_by_score(4.0, f64::INFINITY),
+            [(&Bytes::from("a"), 4.0)]
+        );
+        assert_eq!(set.score(&Bytes::from("a")), Some(4.0));
+    }
+}
@@ -0,0 +1,179 @@
+//! Streams, append-only logs of entries under increasing IDs
+//!
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    ops::Bound::{Excluded, Unbounded},
+    str::FromStr,
+};
+
+use bytes::Bytes;
+use thiserror::Error;
+
+/// The fields of a stream entry, in the order they were given.
+pub type StreamFields = Vec<(Bytes, Bytes)>;
+
+/// Identifies a stream entry, written `ms-seq`: usually the time it was added
+/// at in milliseconds, and a sequence number among the entries of that time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId::new(0, 0);
+    pub const MAX: StreamId = StreamId::new(u64::MAX, u64::MAX);
+
+    pub const fn new(ms: u64, seq: u64) -> StreamId {
+        StreamId { ms, seq }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("Invalid stream ID specified as stream command argument")]
+pub struct InvalidStreamId;
+
+impl FromStr for StreamId {
+    type Err = InvalidStreamId;
+
+    /// Parses `ms-seq`, or `ms` alone for the first ID of that time.
+    fn from_str(s: &str) -> Result<StreamId, InvalidStreamId> {
+        let (ms, seq) = s.split_once('-').unwrap_or((s, "0"));
+        match (ms.parse(), seq.parse()) {
+            (Ok(ms), Ok(seq)) => Ok(StreamId::new(ms, seq)),
+            _ => Err(InvalidStreamId),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, StreamFields>,
+    last_id: StreamId,
+}
+
+impl Stream {
+    pub fn new() -> Stream {
+        Stream::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The ID of the last entry added, 0-0 before the first one.
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// The ID of an entry added at `ms`: the next sequence number of `ms`, or
+    /// of the last ID if that is later. None once IDs are exhausted.
+    pub fn next_id(&self, ms: u64) -> Option<StreamId> {
+        if ms > self.last_id.ms {
+            return Some(StreamId::new(ms, 0));
+        }
+        let seq = self.last_id.seq.checked_add(1);
+        match seq {
+            Some(seq) => Some(StreamId::new(self.last_id.ms, seq)),
+            None => self
+                .last_id
+                .ms
+                .checked_add(1)
+                .map(|ms| StreamId::new(ms, 0)),
+        }
+    }
+
+    /// Appends an entry under `id`, unless `id` isn't greater than the last
+    /// ID. Returns whether it was added.
+    pub fn add(&mut self, id: StreamId, fields: StreamFields) -> bool {
+        if id <= self.last_id {
+            return false;
+        }
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        true
+    }
+
+    /// The entries from `start` to `end` included, in order.
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+    ) -> impl Iterator<Item = (&StreamId, &StreamFields)> {
+        (start <= end)
+            .then(|| self.entries.range(start..=end))
+            .into_iter()
+            .flatten()
+    }
+
+    /// The entries added after `id`, in order.
+    pub fn after(&self, id: StreamId) -> impl Iterator<Item = (&StreamId, &StreamFields)> {
+        self.entries.range((Excluded(id), Unbounded))
+    }
+
+    /// See [`Value::approximate_size`](crate::Value::approximate_size).
+    pub fn approximate_size(&self) -> usize {
+        let entries = self.entries.values().map(|fields| {
+            let data: usize = fields.iter().map(|(f, v)| f.len() + v.len()).sum();
+            data + std::mem::size_of::<StreamId>()
+        });
+        crate::value::sampled(self.len(), entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(value: &'static str) -> StreamFields {
+        vec![(Bytes::from("field"), Bytes::from(value))]
+    }
+
+    #[test]
+    fn test_stream() {
+        let mut stream = Stream::new();
+        assert_eq!(stream.next_id(5), Some(StreamId::new(5, 0)));
+        assert!(!stream.add(StreamId::MIN, fields("zero")));
+        assert!(stream.add(StreamId::new(5, 0), fields("a")));
+        assert_eq!(stream.next_id(5), Some(StreamId::new(5, 1)));
+        // A clock gone back doesn't go back on the IDs.
+        assert_eq!(stream.next_id(3), Some(StreamId::new(5, 1)));
+        assert!(stream.add(StreamId::new(5, 1), fields("b")));
+        assert!(!stream.add(StreamId::new(4, 9), fields("late")));
+        assert!(stream.add(StreamId::new(7, 0), fields("c")));
+
+        let ids = |entries: Vec<(&StreamId, &StreamFields)>| -> Vec<String> {
+            entries.into_iter().map(|(id, _)| id.to_string()).collect()
+        };
+        let all = stream.range(StreamId::MIN, StreamId::MAX).collect();
+        assert_eq!(ids(all), ["5-0", "5-1", "7-0"]);
+        let fives = stream.range(StreamId::new(5, 0), StreamId::new(5, u64::MAX));
+        assert_eq!(ids(fives.collect()), ["5-0", "5-1"]);
+        assert_eq!(stream.range(StreamId::new(7, 0), StreamId::MIN).count(), 0);
+        assert_eq!(
+            ids(stream.after(StreamId::new(5, 0)).collect()),
+            ["5-1", "7-0"]
+        );
+        assert_eq!(stream.after(stream.last_id()).count(), 0);
+
+        assert_eq!("5-1".parse(), Ok(StreamId::new(5, 1)));
+        assert_eq!("5".parse(), Ok(StreamId::new(5, 0)));
+        assert_eq!("5-".parse::<StreamId>(), Err(InvalidStreamId));
+        assert_eq!("-1".parse::<StreamId>(), Err(InvalidStreamId));
+        let mut full = Stream::new();
+        full.add(StreamId::MAX, fields("last"));
+        assert_eq!(full.next_id(0), None);
+    }
+}
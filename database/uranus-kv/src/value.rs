@@ -0,0 +1,74 @@
+//! Values held by keys
+//!
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bytes::Bytes;
+
+use crate::{PriorityQueue, SortedSet, Stream};
+
+/// What an element of a collection takes on top of its data.
+const ELEMENT_OVERHEAD: usize = 32;
+/// Collections larger than this are measured on a sample of their elements.
+const SAMPLE: usize = 16;
+
+/// The value of a key. Commands working on one type refuse keys holding another.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(Bytes),
+    Hash(HashMap<Bytes, Bytes>),
+    Queue(PriorityQueue),
+    List(VecDeque<Bytes>),
+    Set(HashSet<Bytes>),
+    SortedSet(SortedSet),
+    Stream(Stream),
+}
+
+impl Value {
+    /// The name of the type of this value.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Hash(_) => "hash",
+            Value::Queue(_) => "queue",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::SortedSet(_) => "zset",
+            Value::Stream(_) => "stream",
+        }
+    }
+
+    /// Roughly how many bytes the value takes. Large collections are measured
+    /// on a sample, so this is cheap whatever their size.
+    pub fn approximate_size(&self) -> usize {
+        match self {
+            Value::String(string) => string.len(),
+            Value::Hash(hash) => {
+                let fields = hash.iter().map(|(field, value)| field.len() + value.len());
+                sampled(hash.len(), fields)
+            }
+            Value::Queue(queue) => queue.approximate_size(),
+            Value::List(list) => sampled(list.len(), list.iter().map(Bytes::len)),
+            Value::Set(set) => sampled(set.len(), set.iter().map(Bytes::len)),
+            Value::SortedSet(set) => set.approximate_size(),
+            Value::Stream(stream) => stream.approximate_size(),
+        }
+    }
+}
+
+/// The size of `len` elements, extrapolated from the sizes of the first ones.
+pub(crate) fn sampled(len: usize, sizes: impl Iterator<Item = usize>) -> usize {
+    let (count, sum) = sizes
+        .take(SAMPLE)
+        .fold((0, 0), |(count, sum), size| (count + 1, sum + size));
+    if count == 0 {
+        return 0;
+    }
+    len * (sum / count + ELEMENT_OVERHEAD)
+}
+
+impl From<Bytes> for Value {
+    fn from(value: Bytes) -> Self {
+        Value::String(value)
+    }
+}
@@ -0,0 +1,23 @@
+//! Feeds arbitrary bytes to the frame decoder, which must reject malformed
+//! input with an error rather than panic. Run with
+//! `cargo +nightly fuzz run frame_parse` from `database/uranus-s`.
+
+#![no_main]
+
+use std::io::Cursor;
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use uranus_s::{Frame, FrameLimits};
+
+fuzz_target!(|data: &[u8]| {
+    let limits = FrameLimits {
+        max_bulk_len: 1024 * 1024,
+        max_array_len: 1024,
+        max_frame_size: 1024 * 1024,
+    };
+    let _ = Frame::check(&mut Cursor::new(data), &limits);
+    // A connection parses only what the check found complete, but parsing
+    // anything else must not panic either.
+    let _ = Frame::parse(&mut Bytes::copy_from_slice(data));
+});
@@ -0,0 +1,442 @@
+//! Users, and the commands and keys they may use
+//!
+//! Connections act as the `default` user until `AUTH` binds them to another.
+//! Out of the box `default` needs no password and may run anything, so a
+//! server without users configured behaves as if there were no ACL at all.
+//! Users are configured by [`ServerConfig::acl`](crate::ServerConfig::acl) or
+//! `ACL SETUSER`, with the rules of Redis:
+//!
+//! - `on` and `off` enable and disable the user. Disabled users can't
+//!   authenticate, and connections bound to them can't run anything.
+//! - `>password` adds a password, `<password` removes it. `nopass` accepts any
+//!   password, `resetpass` none.
+//! - `+@category` and `-@category` allow and deny the commands of a category,
+//!   `+name` and `-name` a single command. Later rules take precedence over
+//!   earlier ones. `allcommands` and `nocommands` stand for `+@all` and
+//!   `-@all`.
+//! - `~pattern` allows the keys matching the glob `pattern`, `allkeys` every
+//!   key and `resetkeys` none. A command is denied if one of its keys isn't
+//!   allowed.
+//! - `reset` goes back to a new user: disabled, without password, commands or
+//!   keys.
+//!
+//! The categories are `read`, `write`, `blocking`, `admin`, `pubsub` and
+//! `scripting`, as flagged in the [registry](crate::registry), and `all`.
+//! `AUTH` and `PING` are always allowed. Passwords are kept hashed.
+//!
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, RwLock},
+};
+
+use thiserror::Error;
+
+use crate::{glob_match, registry, Command, CommandSpec};
+
+/// The user connections act as until they authenticate.
+pub const DEFAULT_USER: &str = "default";
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AclError {
+    #[error("Error in ACL SETUSER modifier '{0}': Syntax error")]
+    Syntax(String),
+    #[error("Error in ACL SETUSER modifier '{0}': Unknown command or category name in ACL")]
+    UnknownCommand(String),
+}
+
+/// Why a command is refused, see [`Acl::check`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AclDenied {
+    #[error("Authentication required.")]
+    NoAuth,
+    #[error("User {user} has no permissions to run the '{command}' command")]
+    Command { user: String, command: &'static str },
+    #[error("No permissions to access a key")]
+    Key,
+}
+
+/// The commands of a category have its flag in the registry, but `all`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Category {
+    All,
+    Flagged(&'static str, u8),
+}
+
+const CATEGORIES: &[(&str, u8)] = &[
+    ("read", registry::READ),
+    ("write", registry::WRITE),
+    ("blocking", registry::BLOCKING),
+    ("admin", registry::ADMIN),
+    ("pubsub", registry::PUBSUB),
+    ("scripting", registry::SCRIPTING),
+];
+
+impl Category {
+    fn from_name(name: &str) -> Option<Category> {
+        let name = name.to_lowercase();
+        if name == "all" {
+            return Some(Category::All);
+        }
+        CATEGORIES
+            .iter()
+            .find(|(category, _)| *category == name)
+            .map(|&(category, flag)| Category::Flagged(category, flag))
+    }
+
+    fn contains(self, spec: &CommandSpec) -> bool {
+        match self {
+            Category::All => true,
+            Category::Flagged(_, flag) => spec.has(flag),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Category::All => "all",
+            Category::Flagged(name, _) => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CommandRule {
+    Category(Category),
+    Command(&'static str),
+}
+
+impl CommandRule {
+    fn matches(self, spec: &CommandSpec) -> bool {
+        match self {
+            CommandRule::Category(category) => category.contains(spec),
+            CommandRule::Command(name) => spec.name == name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclUser {
+    pub name: String,
+    enabled: bool,
+    nopass: bool,
+    /// The SHA1 digests of the passwords.
+    passwords: Vec<String>,
+    /// Each rule allowing or denying some commands, in the order given.
+    commands: Vec<(bool, CommandRule)>,
+    /// The glob patterns of the keys allowed.
+    keys: Vec<String>,
+}
+
+impl AclUser {
+    /// A user which can't do anything yet, see [`AclUser::with_rules`].
+    pub fn new(name: impl ToString) -> AclUser {
+        AclUser {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: vec![],
+            commands: vec![],
+            keys: vec![],
+        }
+    }
+
+    /// `new(name)` with `rules` applied, e.g. `["on", ">secret", "+@read",
+    /// "~cache:*"]`.
+    pub fn with_rules<R: AsRef<str>>(
+        name: impl ToString,
+        rules: impl IntoIterator<Item = R>,
+    ) -> Result<AclUser, AclError> {
+        let mut user = AclUser::new(name);
+        for rule in rules {
+            user.apply_rule(rule.as_ref())?;
+        }
+        Ok(user)
+    }
+
+    /// Applies a single rule, see [`crate::acl`]. The user is left as it was
+    /// if the rule is invalid.
+    pub fn apply_rule(&mut self, rule: &str) -> Result<(), AclError> {
+        if let Some(password) = rule.strip_prefix('>') {
+            let digest = digest(password);
+            if !self.passwords.contains(&digest) {
+                self.passwords.push(digest);
+            }
+            self.nopass = false;
+            return Ok(());
+        }
+        if let Some(password) = rule.strip_prefix('<') {
+            let digest = digest(password);
+            self.passwords.retain(|kept| *kept != digest);
+            return Ok(());
+        }
+        if let Some(pattern) = rule.strip_prefix('~') {
+            self.keys.push(pattern.to_string());
+            return Ok(());
+        }
+        let command = match (rule.strip_prefix('+'), rule.strip_prefix('-')) {
+            (Some(name), _) => Some((true, name)),
+            (_, Some(name)) => Some((false, name)),
+            _ => None,
+        };
+        if let Some((allowed, name)) = command {
+            let unknown = || AclError::UnknownCommand(rule.to_string());
+            let command = match name.strip_prefix('@') {
+                Some(category) => {
+                    CommandRule::Category(Category::from_name(category).ok_or_else(unknown)?)
+                }
+                None => CommandRule::Command(CommandSpec::lookup(name).ok_or_else(unknown)?.name),
+            };
+            self.allow(allowed, command);
+            return Ok(());
+        }
+        match rule.to_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allcommands" => self.allow(true, CommandRule::Category(Category::All)),
+            "nocommands" => self.allow(false, CommandRule::Category(Category::All)),
+            "allkeys" => self.keys = vec!["*".to_string()],
+            "resetkeys" => self.keys.clear(),
+            "reset" => *self = AclUser::new(&self.name),
+            _ => Err(AclError::Syntax(rule.to_string()))?,
+        }
+        Ok(())
+    }
+
+    /// Adds a rule on the commands. Rules on every command make the earlier
+    /// ones moot, so they are dropped.
+    fn allow(&mut self, allowed: bool, rule: CommandRule) {
+        if rule == CommandRule::Category(Category::All) {
+            self.commands.clear();
+        }
+        self.commands.push((allowed, rule));
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Whether `password` is one of the passwords of this user, if it has
+    /// any.
+    pub fn accepts_password(&self, password: &str) -> bool {
+        self.nopass || self.passwords.contains(&digest(password))
+    }
+
+    /// Whether the last rule on the command of `spec` allows it.
+    pub fn may_run(&self, spec: &CommandSpec) -> bool {
+        self.commands
+            .iter()
+            .rev()
+            .find(|(_, rule)| rule.matches(spec))
+            .is_some_and(|(allowed, _)| *allowed)
+    }
+
+    pub fn may_access(&self, key: &[u8]) -> bool {
+        self.keys
+            .iter()
+            .any(|pattern| glob_match(pattern.as_bytes(), key))
+    }
+}
+
+/// The rules of the user, in the form `ACL SETUSER` takes them back.
+impl fmt::Display for AclUser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "user {} {}",
+            self.name,
+            if self.enabled { "on" } else { "off" }
+        )?;
+        if self.nopass {
+            f.write_str(" nopass")?;
+        }
+        for password in &self.passwords {
+            write!(f, " #{}", password)?;
+        }
+        for pattern in &self.keys {
+            write!(f, " ~{}", pattern)?;
+        }
+        if self.commands.is_empty() {
+            f.write_str(" -@all")?;
+        }
+        for (allowed, rule) in &self.commands {
+            f.write_str(if *allowed { " +" } else { " -" })?;
+            match rule {
+                CommandRule::Category(category) => write!(f, "@{}", category.name())?,
+                CommandRule::Command(name) => f.write_str(name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn digest(password: &str) -> String {
+    sha1_smol::Sha1::from(password).digest().to_string()
+}
+
+/// The users of the server.
+#[derive(Debug, Clone)]
+pub struct Acl {
+    users: Arc<RwLock<HashMap<String, AclUser>>>,
+}
+
+impl Acl {
+    /// The default user, replaced by the one in `users` of that name if any,
+    /// and `users`.
+    pub fn new(users: &[AclUser]) -> Acl {
+        let default = AclUser::with_rules(DEFAULT_USER, ["on", "nopass", "allkeys", "allcommands"])
+            .expect("the default rules are valid");
+        let users = std::iter::once(default)
+            .chain(users.iter().cloned())
+            .map(|user| (user.name.clone(), user))
+            .collect();
+        Acl {
+            users: Arc::new(RwLock::new(users)),
+        }
+    }
+
+    /// Applies `rules` to the user `name`, creating it if needed. Nothing
+    /// changes if one of them is invalid.
+    pub fn set_user(&self, name: &str, rules: &[String]) -> Result<(), AclError> {
+        let mut users = self.users.write().unwrap();
+        let mut user = users
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| AclUser::new(name));
+        for rule in rules {
+            user.apply_rule(rule)?;
+        }
+        users.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    /// Removes the user `name`, returns whether it existed. The default user
+    /// can't be removed.
+    pub fn delete_user(&self, name: &str) -> bool {
+        name != DEFAULT_USER && self.users.write().unwrap().remove(name).is_some()
+    }
+
+    pub fn user(&self, name: &str) -> Option<AclUser> {
+        self.users.read().unwrap().get(name).cloned()
+    }
+
+    /// Every user, by name.
+    pub fn users(&self) -> Vec<AclUser> {
+        let mut users: Vec<_> = self.users.read().unwrap().values().cloned().collect();
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+        users
+    }
+
+    /// Whether `user` is enabled and `password` is one of its passwords.
+    pub fn authenticate(&self, user: &str, password: &str) -> bool {
+        let users = self.users.read().unwrap();
+        users
+            .get(user)
+            .is_some_and(|user| user.is_enabled() && user.accepts_password(password))
+    }
+
+    /// Whether a connection bound to `user` may run `cmd`. Connections which
+    /// never authenticated, with no user, act as the default user as long as
+    /// it needs no password.
+    pub fn check(&self, user: Option<&str>, cmd: &Command) -> Result<(), AclDenied> {
+        if !cmd.requires_auth() {
+            return Ok(());
+        }
+        let users = self.users.read().unwrap();
+        let found = users.get(user.unwrap_or(DEFAULT_USER));
+        let user = match found {
+            Some(found) if found.enabled && (user.is_some() || found.nopass) => found,
+            _ => return Err(AclDenied::NoAuth),
+        };
+        if !user.may_run(cmd.spec()) {
+            return Err(AclDenied::Command {
+                user: user.name.clone(),
+                command: cmd.name(),
+            });
+        }
+        if !cmd.keys().into_iter().all(|key| user.may_access(key)) {
+            return Err(AclDenied::Key);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Frame;
+
+    fn command(words: &[&str]) -> Command {
+        let frames = words
+            .iter()
+            .map(|word| Frame::Text(word.to_string()))
+            .collect();
+        Command::from_frame(Frame::Array(frames)).unwrap()
+    }
+
+    #[test]
+    fn test_acl() {
+        let reader = AclUser::with_rules("reader", ["on", ">secret", "+@read", "~cache:*"]);
+        let acl = Acl::new(&[reader.unwrap()]);
+        let get = command(&["get", "cache:a"]);
+        assert_eq!(acl.check(None, &get), Ok(()));
+        assert!(!acl.authenticate("reader", "wrong"));
+        assert!(acl.authenticate("reader", "secret"));
+
+        assert_eq!(acl.check(Some("reader"), &get), Ok(()));
+        let set = command(&["set", "cache:a", "1"]);
+        assert!(matches!(
+            acl.check(Some("reader"), &set),
+            Err(AclDenied::Command { command: "set", .. })
+        ));
+        let other = command(&["get", "other"]);
+        assert_eq!(acl.check(Some("reader"), &other), Err(AclDenied::Key));
+        let ping = command(&["ping"]);
+        assert_eq!(acl.check(Some("nobody"), &ping), Ok(()));
+        assert_eq!(acl.check(Some("nobody"), &get), Err(AclDenied::NoAuth));
+
+        // Later rules take precedence.
+        let rules = [
+            "-get".to_string(),
+            "+set".to_string(),
+            "-@write".to_string(),
+        ];
+        acl.set_user("reader", &rules).unwrap();
+        let user = acl.user("reader").unwrap();
+        assert_eq!(
+            user.to_string(),
+            format!(
+                "user reader on #{} ~cache:* +@read -get +set -@write",
+                digest("secret")
+            )
+        );
+        assert!(acl.check(Some("reader"), &get).is_err());
+        assert!(acl.check(Some("reader"), &set).is_err());
+        acl.set_user("reader", &["+@all".to_string()]).unwrap();
+        assert_eq!(acl.check(Some("reader"), &set), Ok(()));
+
+        let invalid = ["off".to_string(), "+nosuchcommand".to_string()];
+        assert!(matches!(
+            acl.set_user("reader", &invalid),
+            Err(AclError::UnknownCommand(_))
+        ));
+        assert!(acl.user("reader").unwrap().is_enabled());
+
+        // The default user may require a password too.
+        acl.set_user(DEFAULT_USER, &[">hunter2".to_string()])
+            .unwrap();
+        assert_eq!(acl.check(None, &get), Err(AclDenied::NoAuth));
+        assert_eq!(acl.check(Some(DEFAULT_USER), &get), Ok(()));
+        assert!(!acl.delete_user(DEFAULT_USER));
+        assert!(acl.delete_user("reader"));
+        assert_eq!(acl.check(Some("reader"), &get), Err(AclDenied::NoAuth));
+    }
+}
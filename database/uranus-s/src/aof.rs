@@ -0,0 +1,721 @@
+//! Append-only file persistence
+//!
+//! Every write request is appended to the file in the wire format once it is
+//! applied, preceded by a `SELECT` whenever it works on another logical
+//! database than the previous one. On startup the file is replayed through
+//! the normal command path, so it can be inspected and even edited by hand.
+//! The reply to a write is held until it is appended, so that once
+//! acknowledged it survives the server being killed, if not the machine
+//! crashing before the file is synced, see [`Fsync`].
+//!
+//! Each append is a record: a line `#<length> <checksum>` holding the length
+//! of the requests following it and their CRC-32C in hex. A record which
+//! doesn't match its checksum fails the startup rather than loading bad data,
+//! and [`verify_aof`] checks a file without loading it, as `uranus-s --verify
+//! <file>` does. Requests without the line, say added by hand, are replayed
+//! unchecked.
+//!
+//! Blocking pops and `MIGRATE` may wait while other writes are logged, so
+//! rather than the request they log the value of their key once they return,
+//! as `DEL` followed by the commands recreating it. `XADD *` is logged with the time it was
+//! received at, so that replaying it gives its entry the same ID.
+//!
+//! The file only grows, so it is [rewritten](AppendOnlyFile::rewrite) in the
+//! background from time to time: the keys are written to a new file as the
+//! fewest commands recreating them, the writes logged meanwhile are appended,
+//! and the new file replaces the old one.
+//!
+//! TTLs are logged as the time they end at, `SET ... PXAT` and `PEXPIREAT`
+//! rather than `PX` and `PEXPIRE`, so a restart doesn't extend them, and keys
+//! whose time passed while the server was down are deleted as they are
+//! replayed rather than loaded. Neither expiries nor evictions are logged,
+//! evicted keys are evicted again by the writes past the memory limit after a
+//! restart.
+
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Cursor, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use bytes::{Buf, Bytes, BytesMut};
+use thiserror::Error;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::OwnedMutexGuard,
+    time,
+};
+use tracing::{error, info, warn};
+use uranus_kv::{crc32c, Value};
+
+use crate::{
+    spawn_named, Command, Connection, Database, Del, ExpireAt, Frame, FrameLimits, HSet, KeyTtl,
+    ListEnd, NewId, PqAdd, Protocol, Push, Put, SAdd, Select, Shared, StreamId, XAdd, ZAdd,
+};
+
+/// How the append-only file is configured, see [`ServerConfig::aof`](crate::ServerConfig::aof).
+#[derive(Debug, Clone)]
+pub struct AofConfig {
+    pub path: PathBuf,
+    pub fsync: Fsync,
+    /// The file is rewritten once it grew by this percentage since the last
+    /// rewrite, 0 to only rewrite on `BGREWRITEAOF`.
+    pub rewrite_growth: u64,
+    /// The file isn't rewritten automatically while smaller than this, in bytes.
+    pub rewrite_min_size: u64,
+}
+
+const DEFAULT_REWRITE_GROWTH: u64 = 100;
+const DEFAULT_REWRITE_MIN_SIZE: u64 = 64 * 1024 * 1024;
+
+impl AofConfig {
+    pub fn new(path: impl Into<PathBuf>) -> AofConfig {
+        AofConfig {
+            path: path.into(),
+            fsync: Fsync::default(),
+            rewrite_growth: DEFAULT_REWRITE_GROWTH,
+            rewrite_min_size: DEFAULT_REWRITE_MIN_SIZE,
+        }
+    }
+}
+
+/// When the writes appended to the file are flushed to the disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Fsync {
+    /// After every write, which is slow but loses nothing on a crash.
+    Always,
+    /// Once a second, losing at most the last second of writes on a crash.
+    #[default]
+    EverySecond,
+    /// Whenever the operating system sees fit.
+    Never,
+}
+
+/// The rewritten file holds at most this many elements of a collection per
+/// command.
+const REWRITE_CHUNK: usize = 64;
+
+/// A checksummed record starts with this byte, see the
+/// [module documentation](self).
+const RECORD_MARK: u8 = b'#';
+/// The longest line a record starts with: the mark, the length, a space, the
+/// checksum and the line end.
+const MAX_RECORD_HEADER: usize = 1 + 20 + 1 + 8 + 2;
+
+/// An open append-only file, shared by the connections.
+#[derive(Debug, Clone)]
+pub struct AppendOnlyFile {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: AofConfig,
+    /// Held across applying and logging a write, so that writes are logged in
+    /// the order they were applied.
+    writes: Arc<tokio::sync::Mutex<()>>,
+    log: Mutex<Log>,
+    rewriting: AtomicBool,
+}
+
+#[derive(Debug)]
+struct Log {
+    file: File,
+    /// The logical database the last entry worked on.
+    selected: usize,
+    size: u64,
+    /// The size right after the last rewrite, or startup.
+    base_size: u64,
+    /// The entries logged while a rewrite runs, appended to the new file.
+    rewrite: Option<Entries>,
+    /// Whether there are entries which are not synced to the disk yet.
+    dirty: bool,
+}
+
+#[derive(Debug)]
+struct Entries {
+    selected: usize,
+    data: BytesMut,
+}
+
+impl Entries {
+    fn push(&mut self, index: usize, entry: &[u8]) -> Result<()> {
+        select(&mut self.selected, index, &mut self.data)?;
+        self.data.extend_from_slice(entry);
+        Ok(())
+    }
+}
+
+/// Appends a `SELECT` to `dst` if `index` isn't `selected`.
+fn select(selected: &mut usize, index: usize, dst: &mut BytesMut) -> Result<()> {
+    if *selected != index {
+        Select::new(index).into_frame().encode(Protocol::V2, dst)?;
+        *selected = index;
+    }
+    Ok(())
+}
+
+impl AppendOnlyFile {
+    /// Replays the file of `config` on `db`, creating it if it doesn't exist,
+    /// then opens it to log the writes from now on. A truncated last entry,
+    /// say from a crash while it was written, is dropped.
+    pub async fn open<D: Database>(
+        config: AofConfig,
+        db: &D,
+        shared: &Shared,
+        limits: FrameLimits,
+    ) -> Result<AppendOnlyFile> {
+        let data = match fs::read(&config.path) {
+            Ok(data) => Bytes::from(data),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Bytes::new(),
+            Err(err) => Err(err)?,
+        };
+        let (size, replayed) = replay(data, db, shared, limits).await?;
+        info!(path = %config.path.display(), replayed, "loaded the append only file");
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        if file.metadata()?.len() > size {
+            warn!(
+                size,
+                "truncating an incomplete entry of the append only file"
+            );
+            file.set_len(size)?;
+        }
+        let fsync = config.fsync;
+        let inner = Arc::new(Inner {
+            config,
+            writes: Arc::new(tokio::sync::Mutex::new(())),
+            log: Mutex::new(Log {
+                file,
+                // Replaying starts on the first database too.
+                selected: 0,
+                size,
+                base_size: size,
+                rewrite: None,
+                dirty: false,
+            }),
+            rewriting: AtomicBool::new(false),
+        });
+        if fsync == Fsync::EverySecond {
+            spawn_named("aof fsync", sync_every_second(Arc::downgrade(&inner)));
+        }
+        Ok(AppendOnlyFile { inner })
+    }
+
+    /// Waits for the writes being logged, then holds off the others until the
+    /// guard is dropped.
+    pub async fn lock_writes(&self) -> OwnedMutexGuard<()> {
+        self.inner.writes.clone().lock_owned().await
+    }
+
+    /// Logs `request`, a write applied to `db`. It should be called under
+    /// [`AppendOnlyFile::lock_writes`] along with applying the write.
+    pub fn record<D: Database>(&self, db: &D, request: &Frame) -> Result<()> {
+        let mut entry = BytesMut::new();
+        request.encode(Protocol::V2, &mut entry)?;
+        self.append(db, &entry)
+    }
+
+    /// Logs the current value of `key` in `db`. Blocking pops are logged this
+    /// way, see the [module documentation](self).
+    pub fn record_value<D: Database>(&self, db: &D, key: &[u8]) -> Result<()> {
+        let mut entry = BytesMut::new();
+        Del::new(vec![Bytes::copy_from_slice(key)])
+            .into_frame()
+            .encode(Protocol::V2, &mut entry)?;
+        let key = Bytes::copy_from_slice(key);
+        if let Some(value) = db.get(key.clone())? {
+            for frame in restore_frames(&key, &value, deadline(db.ttl(key.clone())?)) {
+                frame.encode(Protocol::V2, &mut entry)?;
+            }
+        }
+        self.append(db, &entry)
+    }
+
+    fn append<D: Database>(&self, db: &D, entry: &[u8]) -> Result<()> {
+        let grown = {
+            let mut log = self.inner.log.lock().unwrap();
+            let mut data = BytesMut::new();
+            select(&mut log.selected, db.index(), &mut data)?;
+            data.extend_from_slice(entry);
+            let mut record = BytesMut::new();
+            push_record(&data, &mut record);
+            log.file.write_all(&record)?;
+            log.size += record.len() as u64;
+            if let Some(rewrite) = &mut log.rewrite {
+                rewrite.push(db.index(), entry)?;
+            }
+            match self.inner.config.fsync {
+                Fsync::Always => log.file.sync_data()?,
+                Fsync::EverySecond => log.dirty = true,
+                Fsync::Never => {}
+            }
+            let growth = self.inner.config.rewrite_growth;
+            growth > 0
+                && log.size >= self.inner.config.rewrite_min_size
+                && log.size >= log.base_size * (100 + growth) / 100
+        };
+        if grown && self.rewrite(db.clone()) {
+            info!("started rewriting the append only file as it grew");
+        }
+        Ok(())
+    }
+
+    /// Starts rewriting the file in the background to the fewest commands
+    /// recreating the keys of every logical database of `db`. Returns false
+    /// if a rewrite is in progress already.
+    pub fn rewrite<D: Database>(&self, db: D) -> bool {
+        if self.inner.rewriting.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        let aof = self.clone();
+        spawn_named("aof rewrite", async move {
+            if let Err(err) = aof.run_rewrite(db).await {
+                error!(cause = %err, "failed to rewrite the append only file");
+                aof.inner.log.lock().unwrap().rewrite = None;
+            }
+            aof.inner.rewriting.store(false, Ordering::SeqCst);
+        });
+        true
+    }
+
+    /// Whether a rewrite is in progress.
+    pub fn is_rewriting(&self) -> bool {
+        self.inner.rewriting.load(Ordering::SeqCst)
+    }
+
+    /// The current size of the file, and its size right after the last
+    /// rewrite, in bytes.
+    pub fn sizes(&self) -> (u64, u64) {
+        let log = self.inner.log.lock().unwrap();
+        (log.size, log.base_size)
+    }
+
+    async fn run_rewrite<D: Database>(&self, db: D) -> Result<()> {
+        let snapshot = {
+            let _writes = self.lock_writes().await;
+            let snapshot = snapshot(&db)?;
+            // The new file ends on the last database it selects.
+            let selected = snapshot.last().map_or(0, |(index, _)| *index);
+            self.inner.log.lock().unwrap().rewrite = Some(Entries {
+                selected,
+                data: BytesMut::new(),
+            });
+            snapshot
+        };
+
+        let path = self.inner.config.path.with_extension("rewrite");
+        let target = path.clone();
+        tokio::task::spawn_blocking(move || write_snapshot(&target, snapshot)).await??;
+
+        let mut log = self.inner.log.lock().unwrap();
+        let entries = log.rewrite.take().expect("a rewrite is in progress");
+        let mut file = OpenOptions::new().append(true).open(&path)?;
+        if !entries.data.is_empty() {
+            let mut record = BytesMut::new();
+            push_record(&entries.data, &mut record);
+            file.write_all(&record)?;
+        }
+        file.sync_all()?;
+        fs::rename(&path, &self.inner.config.path)?;
+        log.size = file.metadata()?.len();
+        log.base_size = log.size;
+        log.selected = entries.selected;
+        log.file = file;
+        log.dirty = false;
+        info!(size = log.size, "rewrote the append only file");
+        Ok(())
+    }
+}
+
+/// Applies the requests of `data` to `db`, returns the size of the complete
+/// ones and their number.
+async fn replay<D: Database>(
+    mut data: Bytes,
+    db: &D,
+    shared: &Shared,
+    limits: FrameLimits,
+) -> Result<(u64, usize)> {
+    if data.is_empty() {
+        return Ok((0, 0));
+    }
+    // The replies go nowhere.
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let mut client = TcpStream::connect(listener.local_addr()?).await?;
+    let (socket, _) = listener.accept().await?;
+    spawn_named("aof replay replies", async move {
+        tokio::io::copy(&mut client, &mut tokio::io::sink()).await
+    });
+    let mut connection = Connection::new(socket);
+
+    let mut db = db.clone();
+    shared.readiness.start_loading(data.len() as u64);
+    let (mut size, mut replayed) = (0, 0);
+    loop {
+        let entry = read_entry(&data, &limits).map_err(|corruption| CorruptedAof {
+            offset: size,
+            corruption,
+        })?;
+        let Some((frames, len)) = entry else {
+            return Ok((size, replayed));
+        };
+        for frame in frames {
+            Command::from_frame(frame)?
+                .apply(&mut connection, &mut db, shared)
+                .await?;
+            replayed += 1;
+        }
+        data.advance(len);
+        size += len as u64;
+        shared.readiness.set_loaded(size);
+        // The connections accepted meanwhile are answered between entries.
+        tokio::task::yield_now().await;
+    }
+}
+
+/// Why an entry of the file can't be replayed.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum Corruption {
+    #[error("the checksum of the record doesn't match")]
+    Checksum,
+    #[error("malformed entry, {0}")]
+    Malformed(String),
+}
+
+/// A file which can't be replayed past `offset`, in bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("corrupted append only file at byte {offset}: {corruption}")]
+pub struct CorruptedAof {
+    pub offset: u64,
+    pub corruption: Corruption,
+}
+
+/// Appends `entry` to `dst` as a checksummed record.
+fn push_record(entry: &[u8], dst: &mut BytesMut) {
+    let header = format!("#{} {:08x}\r\n", entry.len(), crc32c(entry));
+    dst.extend_from_slice(header.as_bytes());
+    dst.extend_from_slice(entry);
+}
+
+/// Reads the entry at the start of `data`, a checksummed record or a request
+/// written without one. Returns its requests and its size, none if it is
+/// incomplete.
+fn read_entry(
+    data: &Bytes,
+    limits: &FrameLimits,
+) -> Result<Option<(Vec<Frame>, usize)>, Corruption> {
+    if data.first() != Some(&RECORD_MARK) {
+        return Ok(read_frame(data, limits)?.map(|(frame, len)| (vec![frame], len)));
+    }
+    let head = &data[..data.len().min(MAX_RECORD_HEADER)];
+    let Some(end) = head.windows(2).position(|window| window == b"\r\n") else {
+        if head.len() == MAX_RECORD_HEADER {
+            Err(Corruption::Malformed(
+                "the record header doesn't end".to_string(),
+            ))?
+        }
+        return Ok(None);
+    };
+    let (len, checksum) = std::str::from_utf8(&head[1..end])
+        .ok()
+        .and_then(|header| header.split_once(' '))
+        .and_then(|(len, checksum)| {
+            Some((
+                len.parse::<usize>().ok()?,
+                u32::from_str_radix(checksum, 16).ok()?,
+            ))
+        })
+        .ok_or_else(|| Corruption::Malformed("invalid record header".to_string()))?;
+    let start = end + 2;
+    if data.len() - start < len {
+        return Ok(None);
+    }
+    let mut body = data.slice(start..start + len);
+    if crc32c(&body) != checksum {
+        Err(Corruption::Checksum)?
+    }
+    let mut frames = vec![];
+    while !body.is_empty() {
+        let Some((frame, len)) = read_frame(&body, limits)? else {
+            Err(Corruption::Malformed(
+                "incomplete request in a record".to_string(),
+            ))?
+        };
+        frames.push(frame);
+        body.advance(len);
+    }
+    Ok(Some((frames, start + len)))
+}
+
+/// Reads the frame at the start of `data`, returns it and its size, none if
+/// it is incomplete.
+fn read_frame(data: &Bytes, limits: &FrameLimits) -> Result<Option<(Frame, usize)>, Corruption> {
+    let malformed = |err: anyhow::Error| Corruption::Malformed(err.to_string());
+    let mut cursor = Cursor::new(&data[..]);
+    if Frame::check(&mut cursor, limits)
+        .map_err(malformed)?
+        .is_none()
+    {
+        return Ok(None);
+    }
+    let len = cursor.position() as usize;
+    let mut src = data.slice(..len);
+    let frame = Frame::parse(&mut src).map_err(malformed)?.unwrap(); // Frame::check guaranteed Some(_)
+    Ok(Some((frame, len)))
+}
+
+/// What [`verify_aof`] found in a file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AofReport {
+    /// The checksummed records.
+    pub records: usize,
+    /// The requests written without checksum.
+    pub unchecked: usize,
+    /// The size of the entries read, in bytes.
+    pub size: u64,
+    /// The size of an incomplete last entry, which is dropped on startup.
+    pub truncated: u64,
+    /// Where the file can't be read past, if it is corrupted.
+    pub corruption: Option<CorruptedAof>,
+}
+
+impl AofReport {
+    /// Whether the file can be loaded.
+    pub fn is_ok(&self) -> bool {
+        self.corruption.is_none()
+    }
+}
+
+impl fmt::Display for AofReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} records, {} unchecked requests, {} bytes",
+            self.records, self.unchecked, self.size
+        )?;
+        if self.truncated > 0 {
+            write!(f, ", {} bytes of an incomplete entry", self.truncated)?;
+        }
+        match &self.corruption {
+            Some(corruption) => write!(f, ", {}", corruption),
+            None => write!(f, ", ok"),
+        }
+    }
+}
+
+/// Checks the entries of the append-only file at `path` without loading it.
+pub fn verify_aof(path: &Path, limits: FrameLimits) -> Result<AofReport> {
+    let mut data = Bytes::from(fs::read(path)?);
+    let mut report = AofReport::default();
+    loop {
+        let checked = data.first() == Some(&RECORD_MARK);
+        match read_entry(&data, &limits) {
+            Ok(Some((_, len))) => {
+                if checked {
+                    report.records += 1;
+                } else {
+                    report.unchecked += 1;
+                }
+                report.size += len as u64;
+                data.advance(len);
+            }
+            Ok(None) => {
+                report.truncated = data.len() as u64;
+                return Ok(report);
+            }
+            Err(corruption) => {
+                report.corruption = Some(CorruptedAof {
+                    offset: report.size,
+                    corruption,
+                });
+                return Ok(report);
+            }
+        }
+    }
+}
+
+/// The keys of every non-empty logical database of `db`, with their TTL.
+type Snapshot = Vec<(usize, Vec<(Bytes, Value, Option<SystemTime>)>)>;
+
+fn snapshot<D: Database>(db: &D) -> Result<Snapshot> {
+    let mut snapshot = vec![];
+    for index in 0..db.databases() {
+        let Some(db) = db.select(index) else {
+            continue;
+        };
+        let mut keys = vec![];
+        for (key, value) in db.scan()? {
+            let expires_at = deadline(db.ttl(key.clone())?);
+            keys.push((key, value, expires_at));
+        }
+        if !keys.is_empty() {
+            snapshot.push((index, keys));
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Writes a record per key.
+fn write_snapshot(path: &Path, snapshot: Snapshot) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut selected = 0;
+    let (mut data, mut record) = (BytesMut::new(), BytesMut::new());
+    for (index, keys) in snapshot {
+        for (key, value, expires_at) in keys {
+            select(&mut selected, index, &mut data)?;
+            for frame in restore_frames(&key, &value, expires_at) {
+                frame.encode(Protocol::V2, &mut data)?;
+            }
+            push_record(&data, &mut record);
+            file.write_all(&record)?;
+            data.clear();
+            record.clear();
+        }
+    }
+    file.into_inner()?.sync_all()?;
+    Ok(())
+}
+
+/// When a key with `ttl` left expires.
+fn deadline(ttl: KeyTtl) -> Option<SystemTime> {
+    match ttl {
+        KeyTtl::Remaining(ttl) => Some(SystemTime::now() + ttl),
+        KeyTtl::Persistent | KeyTtl::Missing => None,
+    }
+}
+
+/// The commands recreating `key` holding `value`, and expiring it at
+/// `expires_at`.
+pub fn restore_frames(key: &Bytes, value: &Value, expires_at: Option<SystemTime>) -> Vec<Frame> {
+    let mut frames = match value {
+        Value::String(string) => vec![Put::new(key, string.clone()).into_frame()],
+        Value::Hash(hash) => {
+            let pairs: Vec<_> = hash.iter().map(|(f, v)| (f.clone(), v.clone())).collect();
+            pairs
+                .chunks(REWRITE_CHUNK)
+                .map(|chunk| HSet::new(key, chunk.to_vec()).into_frame())
+                .collect()
+        }
+        Value::Queue(queue) => {
+            let items: Vec<_> = queue.iter().map(|(item, p)| (p, item.clone())).collect();
+            items
+                .chunks(REWRITE_CHUNK)
+                .map(|chunk| PqAdd::new(key, chunk.to_vec()).into_frame())
+                .collect()
+        }
+        Value::List(list) => {
+            let elements: Vec<_> = list.iter().cloned().collect();
+            elements
+                .chunks(REWRITE_CHUNK)
+                .map(|chunk| Push::new(key, ListEnd::Right, chunk.to_vec()).into_frame())
+                .collect()
+        }
+        Value::Set(set) => {
+            let members: Vec<_> = set.iter().cloned().collect();
+            members
+                .chunks(REWRITE_CHUNK)
+                .map(|chunk| SAdd::new(key, chunk.to_vec()).into_frame())
+                .collect()
+        }
+        Value::SortedSet(set) => {
+            let members: Vec<_> = set
+                .range_by_rank(0, -1)
+                .into_iter()
+                .map(|(member, score)| (score, member.clone()))
+                .collect();
+            members
+                .chunks(REWRITE_CHUNK)
+                .map(|chunk| ZAdd::new(key, chunk.to_vec()).into_frame())
+                .collect()
+        }
+        Value::Stream(stream) => stream
+            .range(StreamId::MIN, StreamId::MAX)
+            .map(|(id, fields)| {
+                let xadd = XAdd::new(key, fields.clone()).with_id(NewId::Exact(*id));
+                xadd.into_frame()
+            })
+            .collect(),
+    };
+    if let Some(at) = expires_at {
+        frames.push(ExpireAt::new(key, at).into_frame());
+    }
+    frames
+}
+
+/// Syncs the entries of the file once a second, until it is dropped.
+async fn sync_every_second(inner: Weak<Inner>) {
+    let mut ticks = time::interval(Duration::from_secs(1));
+    loop {
+        ticks.tick().await;
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        let mut log = inner.log.lock().unwrap();
+        if log.dirty {
+            if let Err(err) = log.file.sync_data() {
+                warn!(cause = %err, "failed to sync the append only file");
+            }
+            log.dirty = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records() {
+        let limits = FrameLimits::default();
+        let mut data = BytesMut::new();
+        let mut entry = BytesMut::new();
+        Select::new(1)
+            .into_frame()
+            .encode(Protocol::V2, &mut entry)
+            .unwrap();
+        Put::new("key", Bytes::from("value"))
+            .into_frame()
+            .encode(Protocol::V2, &mut entry)
+            .unwrap();
+        push_record(&entry, &mut data);
+        let len = data.len();
+        let (frames, read) = read_entry(&data.clone().freeze(), &limits)
+            .unwrap()
+            .unwrap();
+        assert_eq!((frames.len(), read), (2, len));
+
+        // Requests without a record are read too, a request at a time.
+        Put::new("plain", Bytes::from("value"))
+            .into_frame()
+            .encode(Protocol::V2, &mut data)
+            .unwrap();
+        let plain = data.clone().freeze().slice(len..);
+        let (frames, _) = read_entry(&plain, &limits).unwrap().unwrap();
+        assert_eq!(frames.len(), 1);
+
+        let data = data.freeze();
+        assert_eq!(read_entry(&data.slice(..len - 1), &limits), Ok(None));
+        assert_eq!(read_entry(&data.slice(..3), &limits), Ok(None));
+        let mut flipped = data.to_vec();
+        flipped[len - 3] ^= 1;
+        assert_eq!(
+            read_entry(&Bytes::from(flipped), &limits),
+            Err(Corruption::Checksum)
+        );
+        let header = Bytes::from("#12 not-hex\r\n");
+        assert!(matches!(
+            read_entry(&header, &limits),
+            Err(Corruption::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,222 @@
+//! Adaptive sizing of the read buffers of connections
+//!
+//! A connection starts reading [`BufferConfig::initial_size`] bytes at a time,
+//! [`MIN_BUFFER_SIZE`] by default. Frames larger than that raise its read size
+//! to the next power of two, up to [`BufferConfig::max_size`], so that the
+//! following ones arrive in fewer reads. Larger frames still arrive, in more
+//! reads, and the buffer is given back once they are taken off it. Once the
+//! connection has been idle for [`IDLE_SHRINK_AFTER`], it starts over from the
+//! initial size and gives the memory back too.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+pub const MIN_BUFFER_SIZE: usize = 512;
+pub const MAX_BUFFER_SIZE: usize = 64 * 1024;
+pub const IDLE_SHRINK_AFTER: Duration = Duration::from_secs(30);
+
+/// Read sizes are powers of two, each counted in the bucket of its exponent.
+const BUCKETS: usize = usize::BITS as usize;
+
+/// Bounds on the read size of connections. Both are rounded up to powers of
+/// two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferConfig {
+    /// The read size connections start with, and go back to when idle.
+    pub initial_size: usize,
+    /// The read size never grows past this.
+    pub max_size: usize,
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        BufferConfig {
+            initial_size: MIN_BUFFER_SIZE,
+            max_size: MAX_BUFFER_SIZE,
+        }
+    }
+}
+
+impl BufferConfig {
+    /// The smallest and the largest read sizes.
+    fn bounds(&self) -> (usize, usize) {
+        let initial = self.initial_size.max(1).next_power_of_two();
+        (initial, self.max_size.next_power_of_two().max(initial))
+    }
+}
+
+/// How many connections currently read with each size, to check how well the
+/// sizing works for a workload. Reported by `DEBUG BUFFERS`.
+#[derive(Debug, Clone)]
+pub struct BufferSizes {
+    connections: Arc<[AtomicUsize; BUCKETS]>,
+    config: BufferConfig,
+}
+
+impl BufferSizes {
+    pub fn new(config: BufferConfig) -> BufferSizes {
+        BufferSizes {
+            connections: Arc::new(std::array::from_fn(|_| AtomicUsize::new(0))),
+            config,
+        }
+    }
+
+    /// The number of connections for each read size of `config`, smallest
+    /// first.
+    pub fn snapshot(&self) -> Vec<(usize, usize)> {
+        let (min, max) = self.config.bounds();
+        let buckets = min.trailing_zeros() as usize..=max.trailing_zeros() as usize;
+        buckets
+            .map(|bucket| {
+                (
+                    1 << bucket,
+                    self.connections[bucket].load(Ordering::Relaxed),
+                )
+            })
+            .collect()
+    }
+
+    fn bucket(&self, size: usize) -> &AtomicUsize {
+        &self.connections[size.trailing_zeros() as usize]
+    }
+}
+
+/// The read size of one connection.
+#[derive(Debug)]
+pub(crate) struct ReadSize {
+    size: usize,
+    min: usize,
+    max: usize,
+    /// Where this connection is counted, if anywhere.
+    sizes: Option<BufferSizes>,
+}
+
+impl ReadSize {
+    pub(crate) fn new(config: BufferConfig) -> ReadSize {
+        let (min, max) = config.bounds();
+        ReadSize {
+            size: min,
+            min,
+            max,
+            sizes: None,
+        }
+    }
+
+    pub(crate) fn get(&self) -> usize {
+        self.size
+    }
+
+    /// The read size to start over from.
+    pub(crate) fn initial(&self) -> usize {
+        self.min
+    }
+
+    /// The largest read size.
+    pub(crate) fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Bounds the read size by `config` from now on, starting over from its
+    /// initial size.
+    pub(crate) fn configure(&mut self, config: BufferConfig) {
+        (self.min, self.max) = config.bounds();
+        self.reset();
+    }
+
+    /// Counts this connection in `sizes` from now on.
+    pub(crate) fn track(&mut self, sizes: BufferSizes) {
+        sizes.bucket(self.size).fetch_add(1, Ordering::Relaxed);
+        if let Some(previous) = self.sizes.replace(sizes) {
+            previous.bucket(self.size).fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Makes room for frames of `len` bytes.
+    pub(crate) fn fit(&mut self, len: usize) {
+        let size = len.next_power_of_two().clamp(self.min, self.max);
+        if size > self.size {
+            self.set(size);
+        }
+    }
+
+    /// Starts over from the initial size.
+    pub(crate) fn reset(&mut self) {
+        self.set(self.min);
+    }
+
+    fn set(&mut self, size: usize) {
+        if let Some(sizes) = &self.sizes {
+            sizes.bucket(self.size).fetch_sub(1, Ordering::Relaxed);
+            sizes.bucket(size).fetch_add(1, Ordering::Relaxed);
+        }
+        self.size = size;
+    }
+}
+
+impl Drop for ReadSize {
+    fn drop(&mut self) {
+        if let Some(sizes) = &self.sizes {
+            sizes.bucket(self.size).fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_size() {
+        let sizes = BufferSizes::new(BufferConfig::default());
+        let mut first = ReadSize::new(BufferConfig::default());
+        first.track(sizes.clone());
+        let mut second = ReadSize::new(BufferConfig::default());
+        second.track(sizes.clone());
+        first.fit(100);
+        assert_eq!(first.get(), MIN_BUFFER_SIZE);
+        first.fit(3000);
+        assert_eq!(first.get(), 4096);
+        second.fit(1 << 20);
+        assert_eq!(second.get(), MAX_BUFFER_SIZE);
+        first.fit(1000);
+        assert_eq!(first.get(), 4096);
+
+        assert_eq!(counts(&sizes), [(4096, 1), (MAX_BUFFER_SIZE, 1)]);
+        first.reset();
+        drop(second);
+        assert_eq!(counts(&sizes), [(MIN_BUFFER_SIZE, 1)]);
+    }
+
+    #[test]
+    fn test_configured_read_size() {
+        let config = BufferConfig {
+            initial_size: 3000,
+            max_size: 1 << 20,
+        };
+        let sizes = BufferSizes::new(config);
+        assert_eq!(sizes.snapshot().first(), Some(&(4096, 0)));
+        assert_eq!(sizes.snapshot().last(), Some(&(1 << 20, 0)));
+        let mut read_size = ReadSize::new(BufferConfig::default());
+        read_size.track(sizes.clone());
+        read_size.configure(config);
+        assert_eq!(read_size.get(), 4096);
+        read_size.fit(200_000);
+        assert_eq!(read_size.get(), 256 * 1024);
+        read_size.fit(usize::MAX / 2);
+        assert_eq!(read_size.get(), 1 << 20);
+        assert_eq!(counts(&sizes), [(1 << 20, 1)]);
+    }
+
+    fn counts(sizes: &BufferSizes) -> Vec<(usize, usize)> {
+        sizes
+            .snapshot()
+            .into_iter()
+            .filter(|(_, n)| *n > 0)
+            .collect()
+    }
+}
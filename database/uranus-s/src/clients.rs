@@ -0,0 +1,188 @@
+//! The connections served, for operators to look into with `CLIENT`
+//!
+//! Each connection registers when it is accepted and stays listed until it is
+//! closed. Besides its address, the registry keeps what the connection did
+//! last and the name it gave itself with `CLIENT SETNAME`.
+//!
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use tokio::sync::Notify;
+
+/// The connections of the server, listed by `CLIENT LIST`.
+#[derive(Debug, Clone, Default)]
+pub struct Clients {
+    next_id: Arc<AtomicU64>,
+    clients: Arc<Mutex<BTreeMap<u64, ClientInfo>>>,
+}
+
+/// What is known of a connection.
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    /// Unique among the connections of the server, from 1 on.
+    pub id: u64,
+    pub addr: SocketAddr,
+    pub connected_at: Instant,
+    /// When the last command was received, or the connection was accepted.
+    pub active_at: Instant,
+    /// The name of the last command.
+    pub command: Option<&'static str>,
+    pub name: Option<String>,
+    kill: Arc<Notify>,
+}
+
+impl Clients {
+    pub fn new() -> Clients {
+        Clients::default()
+    }
+
+    /// Registers a connection from `addr`, until the returned
+    /// [`ClientHandle`] is dropped.
+    pub fn register(&self, addr: SocketAddr) -> ClientHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let now = Instant::now();
+        let kill = Arc::new(Notify::new());
+        let info = ClientInfo {
+            id,
+            addr,
+            connected_at: now,
+            active_at: now,
+            command: None,
+            name: None,
+            kill: kill.clone(),
+        };
+        self.clients.lock().unwrap().insert(id, info);
+        ClientHandle {
+            clients: self.clone(),
+            id,
+            kill,
+        }
+    }
+
+    /// Every connection, oldest first.
+    pub fn list(&self) -> Vec<ClientInfo> {
+        self.clients.lock().unwrap().values().cloned().collect()
+    }
+
+    /// One line per connection, oldest first, such as
+    /// `id=1 addr=127.0.0.1:50000 name=worker age=12 idle=0 cmd=get`.
+    /// Ages are in seconds.
+    pub fn describe(&self) -> String {
+        let now = Instant::now();
+        let mut lines = String::new();
+        for client in self.list() {
+            let _ = writeln!(
+                lines,
+                "id={} addr={} name={} age={} idle={} cmd={}",
+                client.id,
+                client.addr,
+                client.name.as_deref().unwrap_or(""),
+                now.duration_since(client.connected_at).as_secs(),
+                now.duration_since(client.active_at).as_secs(),
+                client.command.unwrap_or("NULL"),
+            );
+        }
+        lines
+    }
+
+    /// Closes the connection `id` before its next request, returns whether
+    /// there is such a connection.
+    pub fn kill(&self, id: u64) -> bool {
+        match self.clients.lock().unwrap().get(&id) {
+            Some(client) => {
+                client.kill.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn update(&self, id: u64, f: impl FnOnce(&mut ClientInfo)) {
+        if let Some(client) = self.clients.lock().unwrap().get_mut(&id) {
+            f(client);
+        }
+    }
+}
+
+/// The registration of one connection.
+#[derive(Debug)]
+pub struct ClientHandle {
+    clients: Clients,
+    id: u64,
+    kill: Arc<Notify>,
+}
+
+impl ClientHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Records that the connection received `command`.
+    pub fn record(&self, command: &'static str) {
+        self.clients.update(self.id, |client| {
+            client.command = Some(command);
+            client.active_at = Instant::now();
+        });
+    }
+
+    pub fn name(&self) -> Option<String> {
+        let clients = self.clients.clients.lock().unwrap();
+        clients.get(&self.id).and_then(|client| client.name.clone())
+    }
+
+    /// Names the connection, or clears its name if `name` is empty.
+    pub fn set_name(&self, name: String) {
+        let name = (!name.is_empty()).then_some(name);
+        self.clients.update(self.id, |client| client.name = name);
+    }
+
+    /// Completes once the connection is [killed](Clients::kill). It doesn't
+    /// borrow the client, so it can be awaited alongside requests.
+    pub fn killed(&self) -> impl Future<Output = ()> + 'static {
+        let kill = self.kill.clone();
+        async move { kill.notified().await }
+    }
+}
+
+impl Drop for ClientHandle {
+    fn drop(&mut self) {
+        self.clients.clients.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clients() {
+        let clients = Clients::new();
+        let first = clients.register("127.0.0.1:5000".parse().unwrap());
+        let second = clients.register("127.0.0.1:5001".parse().unwrap());
+        assert_eq!((first.id(), second.id()), (1, 2));
+
+        second.set_name("worker".to_string());
+        second.record("get");
+        assert_eq!(second.name(), Some("worker".to_string()));
+        assert_eq!(
+            clients.describe(),
+            "id=1 addr=127.0.0.1:5000 name= age=0 idle=0 cmd=NULL\n\
+             id=2 addr=127.0.0.1:5001 name=worker age=0 idle=0 cmd=get\n"
+        );
+
+        drop(first);
+        assert!(!clients.kill(1));
+        assert!(clients.kill(2));
+        assert_eq!(clients.list().len(), 1);
+    }
+}
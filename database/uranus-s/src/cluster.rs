@@ -0,0 +1,156 @@
+//! Cluster mode: several servers sharing a keyspace
+//!
+//! Each node serves the keys of the [slots](crate::slots) the topology assigns
+//! it, and redirects requests for the other keys:
+//!
+//! - `-MOVED <slot> <addr>` when `addr` serves the slot. Clients should send
+//!   the request there, and the later requests for the slot too.
+//! - `-ASK <slot> <addr>` when the slot is being migrated to `addr` and the
+//!   key is not here anymore. Clients should send that request only to `addr`,
+//!   preceded by `ASKING`, which lets it serve the slot it is importing.
+//!
+//! Commands on several keys must have them in the same slot, say by using a
+//! hash tag, or they fail with `-CROSSSLOT`.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{key_slot, Database, ErrorCode, Frame, Topology};
+
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    /// The address clients reach this node at, as it appears in `topology`.
+    pub myself: String,
+    pub topology: Topology,
+}
+
+impl ClusterConfig {
+    pub fn new(myself: impl ToString, topology: Topology) -> ClusterConfig {
+        ClusterConfig {
+            myself: myself.to_string(),
+            topology,
+        }
+    }
+}
+
+/// Where a slot moves, see `CLUSTER SETSLOT`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlotState {
+    /// The slot is served by the node at this address.
+    Node(String),
+    /// This node serves the slot, whose keys are moving to the node at this
+    /// address.
+    Migrating(String),
+    /// The node at this address serves the slot, whose keys are moving here.
+    Importing(String),
+    /// Neither migrating nor importing the slot anymore.
+    Stable,
+}
+
+/// The view of the cluster of this node, shared by its connections.
+#[derive(Debug, Clone)]
+pub struct ClusterState {
+    myself: Arc<str>,
+    slots: Arc<RwLock<Slots>>,
+}
+
+#[derive(Debug)]
+struct Slots {
+    topology: Topology,
+    /// The slots this node is moving to another, with its address.
+    migrating: HashMap<u16, String>,
+    /// The slots another node is moving here, with its address.
+    importing: HashMap<u16, String>,
+}
+
+impl ClusterState {
+    pub fn new(config: ClusterConfig) -> ClusterState {
+        ClusterState {
+            myself: config.myself.into(),
+            slots: Arc::new(RwLock::new(Slots {
+                topology: config.topology,
+                migrating: HashMap::new(),
+                importing: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn myself(&self) -> &str {
+        &self.myself
+    }
+
+    pub fn topology(&self) -> Topology {
+        self.slots.read().unwrap().topology.clone()
+    }
+
+    pub fn set_slot(&self, slot: u16, state: SlotState) {
+        let mut slots = self.slots.write().unwrap();
+        match state {
+            SlotState::Node(addr) => {
+                slots.topology.assign(slot..=slot, &addr);
+                slots.migrating.remove(&slot);
+                slots.importing.remove(&slot);
+            }
+            SlotState::Migrating(addr) => {
+                slots.migrating.insert(slot, addr);
+            }
+            SlotState::Importing(addr) => {
+                slots.importing.insert(slot, addr);
+            }
+            SlotState::Stable => {
+                slots.migrating.remove(&slot);
+                slots.importing.remove(&slot);
+            }
+        }
+    }
+
+    /// The redirection for a request on `keys`, none if this node serves it.
+    /// `asking` is whether the request follows an `ASKING`.
+    pub fn redirect<D: Database>(
+        &self,
+        keys: &[&[u8]],
+        db: &D,
+        asking: bool,
+    ) -> Result<Option<Frame>> {
+        let Some(first) = keys.first() else {
+            return Ok(None);
+        };
+        let slot = key_slot(first);
+        if keys.iter().any(|key| key_slot(key) != slot) {
+            let err = "Keys in request don't hash to the same slot";
+            return Ok(Some(Frame::error(ErrorCode::CrossSlot, err)));
+        }
+
+        let slots = self.slots.read().unwrap();
+        let redirect = match slots.topology.owner(slot) {
+            Some(owner) if owner == &*self.myself => match slots.migrating.get(&slot) {
+                Some(target) if !all_present(keys, db)? => {
+                    Some(Frame::error(ErrorCode::Ask, format!("{} {}", slot, target)))
+                }
+                _ => None,
+            },
+            _ if asking && slots.importing.contains_key(&slot) => None,
+            Some(owner) => Some(Frame::error(
+                ErrorCode::Moved,
+                format!("{} {}", slot, owner),
+            )),
+            None => Some(Frame::error(ErrorCode::ClusterDown, "Hash slot not served")),
+        };
+        Ok(redirect)
+    }
+}
+
+fn all_present<D: Database>(keys: &[&[u8]], db: &D) -> Result<bool> {
+    for key in keys {
+        let key = Bytes::copy_from_slice(key);
+        if !db.view(key, |value| value.is_some())? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
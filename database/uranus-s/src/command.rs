@@ -1,12 +1,100 @@
-use std::vec;
+use std::{
+    time::{Duration, SystemTime, UNIX_EPOCH},
+    vec,
+};
 
-use crate::{Connection, DBHandle};
+use crate::{
+    Compression, Connection, Database, ErrorCode, EvictionPolicy, Protocol, ServerEvent,
+    SetCondition, Shared, Value, WalSync, LOADING,
+};
 
 use super::Frame;
 use anyhow::Result;
 use bytes::Bytes;
 use thiserror::Error;
 use tracing::debug;
+use uranus_kv::StorageError;
+
+mod hash;
+pub use hash::*;
+
+mod string;
+pub use string::*;
+
+mod cas;
+pub use cas::*;
+
+mod keyspace;
+pub use keyspace::*;
+
+mod keys;
+pub use keys::*;
+
+mod debug;
+pub use debug::*;
+
+mod object;
+pub use object::*;
+
+mod pubsub;
+pub use pubsub::*;
+
+mod queue;
+pub use queue::*;
+
+mod list;
+pub use list::*;
+
+mod history;
+pub use history::*;
+
+mod set;
+pub use set::*;
+
+mod sorted_set;
+pub use sorted_set::*;
+
+mod info;
+pub use info::*;
+
+mod expire;
+pub use expire::*;
+
+mod rename;
+pub use rename::*;
+
+mod dump;
+pub use dump::*;
+
+mod del;
+pub use del::*;
+
+mod aof;
+pub use aof::*;
+
+mod replication;
+pub use replication::*;
+
+mod cluster;
+pub use cluster::*;
+
+mod client;
+pub use client::*;
+
+mod hyperloglog;
+pub use hyperloglog::*;
+
+mod stream;
+pub use stream::*;
+
+mod script;
+pub use script::*;
+
+mod acl;
+pub use acl::*;
+
+pub mod registry;
+pub use registry::{CommandSpec, COMMANDS};
 
 /// [`Command`] is a semantic information atom between client and server.
 #[derive(Debug)]
@@ -14,6 +102,81 @@ pub enum Command {
     Set(Put),
     Get(Get),
     Echo(Echo),
+    Ping(Ping),
+    Ready(Ready),
+    Hello(Hello),
+    Config(Config),
+    HSet(HSet),
+    HGet(HGet),
+    HDel(HDel),
+    HGetAll(HGetAll),
+    Publish(Publish),
+    Subscribe(Subscribe),
+    Unsubscribe(Unsubscribe),
+    PqAdd(PqAdd),
+    PqPop(PqPop),
+    BPqPop(BPqPop),
+    PqPeek(PqPeek),
+    Push(Push),
+    Pop(Pop),
+    BLPop(BLPop),
+    KeepRevs(KeepRevs),
+    GetRev(GetRev),
+    SAdd(SAdd),
+    SRem(SRem),
+    SMembers(SMembers),
+    SIsMember(SIsMember),
+    SetAlgebra(SetAlgebra),
+    SetAlgebraStore(SetAlgebraStore),
+    SInterCard(SInterCard),
+    ZAdd(ZAdd),
+    ZScore(ZScore),
+    ZRange(ZRange),
+    Append(Append),
+    StrLen(StrLen),
+    GetRange(GetRange),
+    Cas(Cas),
+    Select(Select),
+    Flush(Flush),
+    DbSize(DbSize),
+    Keys(Keys),
+    Scan(Scan),
+    Debug(DebugCommand),
+    Object(ObjectCommand),
+    Info(Info),
+    Expire(Expire),
+    ExpireAt(ExpireAt),
+    Ttl(Ttl),
+    Persist(Persist),
+    Rename(Rename),
+    Copy(CopyCommand),
+    Dump(Dump),
+    Restore(Restore),
+    Migrate(Migrate),
+    Del(Del),
+    Unlink(Unlink),
+    DelPrefix(DelPrefix),
+    BgRewriteAof(BgRewriteAof),
+    ReplConf(ReplConf),
+    Wait(Wait),
+    Cluster(Cluster),
+    Asking(Asking),
+    Client(ClientCommand),
+    Auth(Auth),
+    Acl(AclCommand),
+    MSet(MSet),
+    SetRange(SetRange),
+    SetBit(SetBit),
+    GetBit(GetBit),
+    BitCount(BitCount),
+    LPos(LPos),
+    PfAdd(PfAdd),
+    PfCount(PfCount),
+    PfMerge(PfMerge),
+    XAdd(XAdd),
+    XRange(XRange),
+    XRead(XRead),
+    Eval(Eval),
 }
 
 impl Command {
@@ -22,31 +185,416 @@ impl Command {
     /// what client wants to do.
     pub fn from_frame(frame: Frame) -> Result<Command> {
         let mut parser = CommandParser::new(frame)?;
+        let len = parser.remaining();
         let command_name = parser
             .next_string()?
-            .ok_or(CommandParseError::UnexpectedEOF)?
-            .to_lowercase();
-        let command = match command_name.as_str() {
-            "get" => Command::Get(Get::parse_frames(&mut parser)?),
-            "set" => Command::Set(Put::parse_frames(&mut parser)?),
-            "echo" => Command::Echo(Echo::parse_frames(&mut parser)?),
-            _ => Err(CommandParseError::UnknownCommand)?,
-        };
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let spec = CommandSpec::lookup(&command_name).ok_or(CommandParseError::UnknownCommand)?;
+        if !spec.accepts(len) {
+            Err(CommandParseError::WrongArity(spec.name.to_string()))?
+        }
+        spec.check_args(parser.args())?;
+        let command = spec.parse(&mut parser)?;
         parser.exhausted()?;
         Ok(command)
     }
 
-    pub async fn apply(self, dst: &mut Connection, db: &mut DBHandle) -> Result<()> {
+    /// The entry of this command in [`COMMANDS`].
+    pub fn spec(&self) -> &'static CommandSpec {
+        CommandSpec::lookup(self.name()).expect("every command is registered")
+    }
+
+    /// The name of this command, as it is written on the wire.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Set(_) => "set",
+            Command::Get(_) => "get",
+            Command::Echo(_) => "echo",
+            Command::Ping(_) => "ping",
+            Command::Ready(_) => "ready",
+            Command::Hello(_) => "hello",
+            Command::Config(_) => "config",
+            Command::HSet(_) => "hset",
+            Command::HGet(_) => "hget",
+            Command::HDel(_) => "hdel",
+            Command::HGetAll(_) => "hgetall",
+            Command::Publish(_) => "publish",
+            Command::Subscribe(subscribe) => subscribe.name(),
+            Command::Unsubscribe(unsubscribe) => unsubscribe.name(),
+            Command::PqAdd(_) => "pqadd",
+            Command::PqPop(pop) => pop.name(),
+            Command::BPqPop(pop) => pop.name(),
+            Command::PqPeek(peek) => peek.name(),
+            Command::Push(push) => push.name(),
+            Command::Pop(pop) => pop.name(),
+            Command::BLPop(_) => "blpop",
+            Command::KeepRevs(_) => "keeprevs",
+            Command::GetRev(_) => "getrev",
+            Command::SAdd(_) => "sadd",
+            Command::SRem(_) => "srem",
+            Command::SMembers(_) => "smembers",
+            Command::SIsMember(_) => "sismember",
+            Command::SetAlgebra(algebra) => algebra.name(),
+            Command::SetAlgebraStore(store) => store.name(),
+            Command::SInterCard(_) => "sintercard",
+            Command::ZAdd(_) => "zadd",
+            Command::ZScore(_) => "zscore",
+            Command::ZRange(_) => "zrange",
+            Command::Append(_) => "append",
+            Command::StrLen(_) => "strlen",
+            Command::GetRange(_) => "getrange",
+            Command::Cas(_) => "cas",
+            Command::Select(_) => "select",
+            Command::Flush(flush) => flush.name(),
+            Command::DbSize(_) => "dbsize",
+            Command::Keys(_) => "keys",
+            Command::Scan(_) => "scan",
+            Command::Debug(_) => "debug",
+            Command::Object(_) => "object",
+            Command::Info(_) => "info",
+            Command::Expire(_) => "expire",
+            Command::ExpireAt(_) => "expireat",
+            Command::Ttl(ttl) => ttl.name(),
+            Command::Persist(_) => "persist",
+            Command::Rename(_) => "rename",
+            Command::Copy(_) => "copy",
+            Command::Dump(_) => "dump",
+            Command::Restore(_) => "restore",
+            Command::Migrate(_) => "migrate",
+            Command::Del(_) => "del",
+            Command::Unlink(_) => "unlink",
+            Command::DelPrefix(_) => "delprefix",
+            Command::BgRewriteAof(_) => "bgrewriteaof",
+            Command::ReplConf(_) => "replconf",
+            Command::Wait(_) => "wait",
+            Command::Cluster(_) => "cluster",
+            Command::Asking(_) => "asking",
+            Command::Client(_) => "client",
+            Command::Auth(_) => "auth",
+            Command::Acl(_) => "acl",
+            Command::MSet(_) => "mset",
+            Command::SetRange(_) => "setrange",
+            Command::SetBit(_) => "setbit",
+            Command::GetBit(_) => "getbit",
+            Command::BitCount(_) => "bitcount",
+            Command::LPos(_) => "lpos",
+            Command::PfAdd(_) => "pfadd",
+            Command::PfCount(_) => "pfcount",
+            Command::PfMerge(_) => "pfmerge",
+            Command::XAdd(_) => "xadd",
+            Command::XRange(_) => "xrange",
+            Command::XRead(_) => "xread",
+            Command::Eval(eval) => eval.name(),
+        }
+    }
+
+    /// The key this command operates on, if any.
+    pub fn key(&self) -> Option<&[u8]> {
+        match self {
+            Command::Set(set) => Some(&set.key),
+            Command::Get(get) => Some(&get.key),
+            Command::HSet(hset) => Some(&hset.key),
+            Command::HGet(hget) => Some(&hget.key),
+            Command::HDel(hdel) => Some(&hdel.key),
+            Command::HGetAll(hgetall) => Some(&hgetall.key),
+            Command::PqAdd(add) => Some(&add.key),
+            Command::PqPop(pop) => Some(&pop.key),
+            Command::BPqPop(pop) => Some(&pop.key),
+            Command::PqPeek(peek) => Some(&peek.key),
+            Command::Push(push) => Some(&push.key),
+            Command::Pop(pop) => Some(&pop.key),
+            Command::BLPop(blpop) => Some(&blpop.key),
+            Command::KeepRevs(keeprevs) => Some(&keeprevs.key),
+            Command::GetRev(getrev) => Some(&getrev.key),
+            Command::SAdd(sadd) => Some(&sadd.key),
+            Command::SRem(srem) => Some(&srem.key),
+            Command::SMembers(smembers) => Some(&smembers.key),
+            Command::SIsMember(sismember) => Some(&sismember.key),
+            Command::SetAlgebra(algebra) => algebra.keys.first().map(|key| &key[..]),
+            Command::SetAlgebraStore(store) => Some(&store.dst),
+            Command::SInterCard(sintercard) => sintercard.keys.first().map(|key| &key[..]),
+            Command::ZAdd(zadd) => Some(&zadd.key),
+            Command::ZScore(zscore) => Some(&zscore.key),
+            Command::ZRange(zrange) => Some(&zrange.key),
+            Command::Append(append) => Some(&append.key),
+            Command::StrLen(strlen) => Some(&strlen.key),
+            Command::GetRange(getrange) => Some(&getrange.key),
+            Command::Cas(cas) => Some(&cas.key),
+            Command::Expire(expire) => Some(&expire.key),
+            Command::ExpireAt(expire) => Some(&expire.key),
+            Command::Ttl(ttl) => Some(&ttl.key),
+            Command::Object(object) => Some(object.key()),
+            Command::Persist(persist) => Some(&persist.key),
+            Command::Rename(rename) => Some(&rename.src),
+            Command::Copy(copy) => Some(&copy.src),
+            Command::Dump(dump) => Some(&dump.key),
+            Command::Restore(restore) => Some(&restore.key),
+            Command::Migrate(migrate) => Some(&migrate.key),
+            Command::Del(del) => del.keys.first().map(|key| &key[..]),
+            Command::Unlink(unlink) => unlink.keys.first().map(|key| &key[..]),
+            Command::MSet(mset) => mset.pairs.first().map(|(key, _)| &key[..]),
+            Command::SetRange(setrange) => Some(&setrange.key),
+            Command::SetBit(setbit) => Some(&setbit.key),
+            Command::GetBit(getbit) => Some(&getbit.key),
+            Command::BitCount(bitcount) => Some(&bitcount.key),
+            Command::LPos(lpos) => Some(&lpos.key),
+            Command::PfAdd(pfadd) => Some(&pfadd.key),
+            Command::PfCount(pfcount) => pfcount.keys.first().map(|key| &key[..]),
+            Command::PfMerge(pfmerge) => Some(&pfmerge.dst),
+            Command::XAdd(xadd) => Some(&xadd.key),
+            Command::XRange(xrange) => Some(&xrange.key),
+            Command::XRead(xread) => xread.streams.first().map(|(key, _)| &key[..]),
+            Command::Eval(eval) => eval.keys.first().map(|key| &key[..]),
+            Command::Echo(_)
+            | Command::Ping(_)
+            | Command::Ready(_)
+            | Command::Hello(_)
+            | Command::Config(_)
+            | Command::Publish(_)
+            | Command::Subscribe(_)
+            | Command::Unsubscribe(_)
+            | Command::Select(_)
+            | Command::Flush(_)
+            | Command::DbSize(_)
+            | Command::Keys(_)
+            | Command::Scan(_)
+            | Command::DelPrefix(_)
+            | Command::Debug(_)
+            | Command::Info(_)
+            | Command::BgRewriteAof(_)
+            | Command::ReplConf(_)
+            | Command::Wait(_)
+            | Command::Cluster(_)
+            | Command::Asking(_)
+            | Command::Client(_)
+            | Command::Auth(_)
+            | Command::Acl(_) => None,
+        }
+    }
+
+    /// Every key this command works on, which a cluster serves from one slot.
+    pub fn keys(&self) -> Vec<&[u8]> {
+        match self {
+            Command::Rename(rename) => vec![&rename.src[..], &rename.dst[..]],
+            Command::Copy(copy) => vec![&copy.src[..], &copy.dst[..]],
+            Command::Del(del) => del.keys.iter().map(|key| &key[..]).collect(),
+            Command::Unlink(unlink) => unlink.keys.iter().map(|key| &key[..]).collect(),
+            Command::MSet(mset) => mset.pairs.iter().map(|(key, _)| &key[..]).collect(),
+            Command::PfCount(pfcount) => pfcount.keys.iter().map(|key| &key[..]).collect(),
+            Command::PfMerge(pfmerge) => std::iter::once(&pfmerge.dst)
+                .chain(&pfmerge.sources)
+                .map(|key| &key[..])
+                .collect(),
+            Command::SetAlgebra(algebra) => algebra.keys.iter().map(|key| &key[..]).collect(),
+            Command::SetAlgebraStore(store) => std::iter::once(&store.dst)
+                .chain(&store.keys)
+                .map(|key| &key[..])
+                .collect(),
+            Command::SInterCard(sintercard) => sintercard.keys.iter().map(|key| &key[..]).collect(),
+            Command::XRead(xread) => xread.streams.iter().map(|(key, _)| &key[..]).collect(),
+            Command::Eval(eval) => eval.keys.iter().map(|key| &key[..]).collect(),
+            _ => self.key().into_iter().collect(),
+        }
+    }
+
+    /// The request to log for this command, received as `request`, such that
+    /// replaying it has the same effect. `XADD *` is logged with the time it
+    /// parsed, so that its entry gets the same ID again, and TTLs as the time
+    /// they end at, so that a restart doesn't extend them.
+    pub fn replayable(&self, request: Frame) -> Frame {
+        match self {
+            Command::XAdd(xadd) => xadd.clone().into_frame(),
+            Command::Set(put) => match put.ttl {
+                Some(ttl) => put
+                    .clone()
+                    .expiring_at(SystemTime::now() + ttl)
+                    .into_frame(),
+                None => request,
+            },
+            Command::Expire(expire) => {
+                let at = SystemTime::now() + expire.ttl;
+                ExpireAt::new(&expire.key, at).into_frame()
+            }
+            Command::Restore(restore) => match restore.ttl {
+                Some(ttl) => restore
+                    .clone()
+                    .expiring_at(SystemTime::now() + ttl)
+                    .into_frame(),
+                None => request,
+            },
+            _ => request,
+        }
+    }
+
+    /// Whether this command may only run on an authenticated connection.
+    /// `PING` and `READY` may not, so that load balancers and health checks
+    /// can probe the server without credentials, nor `AUTH` which
+    /// authenticates.
+    pub fn requires_auth(&self) -> bool {
+        !matches!(
+            self,
+            Command::Ping(_) | Command::Ready(_) | Command::Auth(_)
+        )
+    }
+
+    /// Whether this command is served while the server loads its data, see
+    /// [`crate::health`].
+    pub fn serves_loading(&self) -> bool {
+        matches!(
+            self,
+            Command::Ping(_)
+                | Command::Info(_)
+                | Command::Ready(_)
+                | Command::Auth(_)
+                | Command::Hello(_)
+        )
+    }
+
+    /// Whether this command puts the connection in subscribed mode, where it
+    /// stays for as long as the client wishes.
+    pub fn is_subscribe(&self) -> bool {
+        matches!(self, Command::Subscribe(_))
+    }
+
+    /// Whether this command may wait indefinitely before replying.
+    pub fn is_blocking(&self) -> bool {
+        self.spec().has(registry::BLOCKING)
+    }
+
+    /// Whether this command changes the database.
+    pub fn is_write(&self) -> bool {
+        self.spec().has(registry::WRITE)
+    }
+
+    /// Whether this command may take more memory, and is refused once
+    /// `maxmemory` is reached and nothing can be evicted. Commands which only
+    /// remove data are always allowed.
+    pub fn may_grow(&self) -> bool {
+        self.spec().has(registry::GROWS)
+    }
+
+    /// Whether this command reads the database without changing it.
+    pub fn is_read(&self) -> bool {
+        self.spec().has(registry::READ)
+    }
+
+    /// `db` is the database handle of the connection, which `SELECT` replaces.
+    pub async fn apply<D: Database>(
+        self,
+        dst: &mut Connection,
+        db: &mut D,
+        shared: &Shared,
+    ) -> Result<()> {
         use Command::*;
 
         match self {
             Echo(echo) => echo.apply(dst).await,
-            Set(set) => set.apply(db, dst).await,
+            Ping(ping) => ping.apply(dst).await,
+            Ready(ready) => ready.apply(dst, shared).await,
+            Set(set) => set.apply(db, dst, shared).await,
             Get(get) => get.apply(db, dst).await,
+            Hello(hello) => hello.apply(dst).await,
+            Config(config) => config.apply(db, dst, shared).await,
+            HSet(hset) => hset.apply(db, dst).await,
+            HGet(hget) => hget.apply(db, dst).await,
+            HDel(hdel) => hdel.apply(db, dst).await,
+            HGetAll(hgetall) => hgetall.apply(db, dst).await,
+            Publish(publish) => publish.apply(dst, shared).await,
+            Subscribe(subscribe) => subscribe.apply(dst, shared).await,
+            Unsubscribe(unsubscribe) => unsubscribe.apply(dst).await,
+            PqAdd(add) => add.apply(db, dst, shared).await,
+            PqPop(pop) => pop.apply(db, dst).await,
+            BPqPop(pop) => pop.apply(db, dst, shared).await,
+            PqPeek(peek) => peek.apply(db, dst).await,
+            Push(push) => push.apply(db, dst, shared).await,
+            Pop(pop) => pop.apply(db, dst).await,
+            BLPop(blpop) => blpop.apply(db, dst, shared).await,
+            KeepRevs(keeprevs) => keeprevs.apply(db, dst, shared).await,
+            GetRev(getrev) => getrev.apply(dst, shared).await,
+            SAdd(sadd) => sadd.apply(db, dst).await,
+            SRem(srem) => srem.apply(db, dst).await,
+            SMembers(smembers) => smembers.apply(db, dst).await,
+            SIsMember(sismember) => sismember.apply(db, dst).await,
+            SetAlgebra(algebra) => algebra.apply(db, dst).await,
+            SetAlgebraStore(store) => store.apply(db, dst).await,
+            SInterCard(sintercard) => sintercard.apply(db, dst).await,
+            ZAdd(zadd) => zadd.apply(db, dst).await,
+            ZScore(zscore) => zscore.apply(db, dst).await,
+            ZRange(zrange) => zrange.apply(db, dst).await,
+            Append(append) => append.apply(db, dst).await,
+            StrLen(strlen) => strlen.apply(db, dst).await,
+            GetRange(getrange) => getrange.apply(db, dst).await,
+            Cas(cas) => cas.apply(db, dst).await,
+            Select(select) => select.apply(db, dst).await,
+            Flush(flush) => flush.apply(db, dst).await,
+            DbSize(dbsize) => dbsize.apply(db, dst).await,
+            Keys(keys) => keys.apply(db, dst).await,
+            Scan(scan) => scan.apply(db, dst).await,
+            Debug(debug) => debug.apply(dst, shared).await,
+            Object(object) => object.apply(db, dst).await,
+            Info(info) => info.apply(db, dst, shared).await,
+            Expire(expire) => expire.apply(db, dst).await,
+            ExpireAt(expire) => expire.apply(db, dst).await,
+            Ttl(ttl) => ttl.apply(db, dst).await,
+            Persist(persist) => persist.apply(db, dst).await,
+            Rename(rename) => rename.apply(db, dst).await,
+            Copy(copy) => copy.apply(db, dst).await,
+            Dump(dump) => dump.apply(db, dst).await,
+            Restore(restore) => restore.apply(db, dst).await,
+            Migrate(migrate) => migrate.apply(db, dst).await,
+            Del(del) => del.apply(db, dst, shared).await,
+            Unlink(unlink) => unlink.apply(db, dst, shared).await,
+            DelPrefix(delprefix) => delprefix.apply(db, dst).await,
+            BgRewriteAof(rewrite) => rewrite.apply(db, dst, shared).await,
+            ReplConf(replconf) => replconf.apply(dst).await,
+            Wait(wait) => wait.apply(dst, shared).await,
+            Cluster(cluster) => cluster.apply(dst, shared).await,
+            Asking(asking) => asking.apply(dst).await,
+            Client(client) => client.apply(dst, shared).await,
+            Auth(auth) => auth.apply(dst, shared).await,
+            Acl(acl) => acl.apply(dst, shared).await,
+            MSet(mset) => mset.apply(db, dst).await,
+            SetRange(setrange) => setrange.apply(db, dst).await,
+            SetBit(setbit) => setbit.apply(db, dst).await,
+            GetBit(getbit) => getbit.apply(db, dst).await,
+            BitCount(bitcount) => bitcount.apply(db, dst).await,
+            LPos(lpos) => lpos.apply(db, dst).await,
+            PfAdd(pfadd) => pfadd.apply(db, dst).await,
+            PfCount(pfcount) => pfcount.apply(db, dst).await,
+            PfMerge(pfmerge) => pfmerge.apply(db, dst).await,
+            XAdd(xadd) => xadd.apply(db, dst, shared).await,
+            XRange(xrange) => xrange.apply(db, dst).await,
+            XRead(xread) => xread.apply(db, dst, shared).await,
+            Eval(eval) => eval.apply(db, dst, shared).await,
         }
     }
 }
 
+/// Commands going through many keys yield to the scheduler after each chunk of
+/// this many, so that they can't hold up the other connections of a worker.
+pub(crate) const YIELD_EVERY: usize = 1024;
+
+/// Runs `f` on the value of `key` like [`Database::view`], counting the lookup
+/// as a keyspace hit or miss on `dst`, as read commands do.
+pub(crate) fn lookup<D: Database, R>(
+    db: &D,
+    dst: &mut Connection,
+    key: Bytes,
+    f: impl FnOnce(Option<&Value>) -> R,
+) -> Result<R> {
+    let (hit, result) = db.view(key, |value| (value.is_some(), f(value)))?;
+    dst.record_lookup(hit);
+    Ok(result)
+}
+
+/// The reply to a command applied to a key holding another type of value.
+pub(crate) fn wrong_type() -> Frame {
+    Frame::error(
+        ErrorCode::WrongType,
+        "Operation against a key holding the wrong kind of value",
+    )
+}
+
 /// This struct parses the command from network frames, remembering current cursor position.
 pub struct CommandParser {
     tokens: vec::IntoIter<Frame>,
@@ -60,6 +608,13 @@ pub enum CommandParseError {
     ArgNotBinary,
     UnexpectedFrame,
     UnknownCommand,
+    UnknownOption(String),
+    /// The command named so got too few or too many arguments.
+    WrongArity(String),
+    /// An argument isn't an integer, see [`ArgType::Int`].
+    NotInteger,
+    /// An argument isn't a number, see [`ArgType::Float`].
+    NotFloat,
 }
 
 impl std::fmt::Display for CommandParseError {
@@ -85,6 +640,16 @@ impl std::fmt::Display for CommandParseError {
             CommandParseError::UnknownCommand => {
                 write!(f, "The command is not implemented in this system.")
             }
+            CommandParseError::UnknownOption(option) => {
+                write!(f, "The command doesn't take an option '{}'.", option)
+            }
+            CommandParseError::WrongArity(name) => {
+                write!(f, "wrong number of arguments for '{}' command", name)
+            }
+            CommandParseError::NotInteger => {
+                write!(f, "value is not an integer or out of range")
+            }
+            CommandParseError::NotFloat => write!(f, "value is not a valid float"),
         }
     }
 }
@@ -101,6 +666,16 @@ impl CommandParser {
         })
     }
 
+    /// The number of frames left.
+    pub fn remaining(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// The frames left, without consuming them.
+    pub fn args(&self) -> &[Frame] {
+        self.tokens.as_slice()
+    }
+
     fn next(&mut self) -> Option<Frame> {
         self.tokens.next()
     }
@@ -142,43 +717,169 @@ impl CommandParser {
 
 /// This command set `key` to hold a value `value`.
 /// if `key` already have a value, that value is overwritten,
-#[derive(Debug)]
+///
+/// With `ID <token>`, the command is applied at most once per token, see
+/// [`IdempotencyCache`](crate::IdempotencyCache). With `EX <seconds>` or
+/// `PX <milliseconds>`, the key expires after that long, and with `EXAT` or
+/// `PXAT` at that Unix time; a time already past deletes it. With `NX`, the key is
+/// only set if it has no value, with `XX` only if it has one; the reply is nil
+/// when it isn't set.
+#[derive(Debug, Clone)]
 pub struct Put {
-    pub key: String,
+    pub key: Bytes,
     pub value: Bytes,
+    pub id: Option<String>,
+    pub ttl: Option<Duration>,
+    pub expires_at: Option<SystemTime>,
+    pub condition: Option<SetCondition>,
 }
 
 impl Put {
-    pub fn new(key: impl ToString, value: Bytes) -> Put {
+    pub fn new(key: impl AsRef<[u8]>, value: Bytes) -> Put {
         Put {
-            key: key.to_string(),
+            key: Bytes::copy_from_slice(key.as_ref()),
             value,
+            id: None,
+            ttl: None,
+            expires_at: None,
+            condition: None,
         }
     }
 
+    /// Attaches an idempotency token to this command.
+    pub fn with_id(mut self, id: impl ToString) -> Put {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    /// Only sets the key if `condition` holds.
+    pub fn with_condition(mut self, condition: SetCondition) -> Put {
+        self.condition = Some(condition);
+        self
+    }
+
+    /// Makes the key expire after `ttl`.
+    pub fn with_ttl(mut self, ttl: Duration) -> Put {
+        self.ttl = Some(ttl);
+        self.expires_at = None;
+        self
+    }
+
+    /// Makes the key expire at `at`.
+    pub fn expiring_at(mut self, at: SystemTime) -> Put {
+        self.ttl = None;
+        self.expires_at = Some(at);
+        self
+    }
+
     pub fn parse_frames(parser: &mut CommandParser) -> Result<Put> {
         let key = parser
-            .next_string()?
+            .next_bytes()?
             .ok_or(CommandParseError::UnexpectedEOF)?;
         let value = parser
             .next_bytes()?
             .ok_or(CommandParseError::UnexpectedEOF)?;
-        Ok(Put { key, value })
+        let mut put = Put::new(key, value);
+        while let Some(option) = parser.next_string()? {
+            match option.to_lowercase().as_str() {
+                "id" => {
+                    let id = parser
+                        .next_string()?
+                        .ok_or(CommandParseError::UnexpectedEOF)?;
+                    put.id = Some(id);
+                }
+                "nx" | "xx" if put.condition.is_some() => {
+                    Err(CommandParseError::UnknownOption(option))?
+                }
+                "nx" => put.condition = Some(SetCondition::Absent),
+                "xx" => put.condition = Some(SetCondition::Present),
+                "ex" | "px" | "exat" | "pxat" => {
+                    let amount = parser
+                        .next_string()?
+                        .ok_or(CommandParseError::UnexpectedEOF)?
+                        .parse::<u64>()?;
+                    put = match option.to_lowercase().as_str() {
+                        "ex" => put.with_ttl(Duration::from_secs(amount)),
+                        "px" => put.with_ttl(Duration::from_millis(amount)),
+                        "exat" => put.expiring_at(UNIX_EPOCH + Duration::from_secs(amount)),
+                        _ => put.expiring_at(from_unix_millis(amount)),
+                    };
+                }
+                _ => Err(CommandParseError::UnknownOption(option))?,
+            }
+        }
+        Ok(put)
     }
 
     /// Consume this command to generate an array frame representation
     pub fn into_frame(self) -> Frame {
-        let frame = vec![
+        let mut frame = vec![
             Frame::Text("set".to_string()),
-            Frame::Text(self.key),
+            Frame::Binary(self.key),
             Frame::Binary(self.value),
         ];
+        if let Some(id) = self.id {
+            frame.push(Frame::Text("id".to_string()));
+            frame.push(Frame::Text(id));
+        }
+        if let Some(ttl) = self.ttl {
+            frame.push(Frame::Text("px".to_string()));
+            frame.push(Frame::Text(ttl.as_millis().to_string()));
+        }
+        if let Some(at) = self.expires_at {
+            frame.push(Frame::Text("pxat".to_string()));
+            frame.push(Frame::Text(unix_millis(at).to_string()));
+        }
+        match self.condition {
+            Some(SetCondition::Absent) => frame.push(Frame::Text("nx".to_string())),
+            Some(SetCondition::Present) => frame.push(Frame::Text("xx".to_string())),
+            None => {}
+        }
         Frame::Array(frame)
     }
 
-    pub async fn apply(self, db: &mut DBHandle, dst: &mut Connection) -> Result<()> {
-        db.put(self.key, self.value)?;
-        let response = Frame::Text("OK".to_string());
+    pub async fn apply<D: Database>(
+        self,
+        db: &D,
+        dst: &mut Connection,
+        shared: &Shared,
+    ) -> Result<()> {
+        let Put {
+            key,
+            value,
+            id,
+            ttl,
+            expires_at,
+            condition,
+        } = self;
+        let write = || -> Result<Frame> {
+            let ttl = match expires_at {
+                Some(at) => match at.duration_since(SystemTime::now()) {
+                    Ok(ttl) => Some(ttl),
+                    Err(_) if condition.is_none() => {
+                        db.delete(key.clone())?;
+                        return Ok(Frame::Text("OK".to_string()));
+                    }
+                    Err(_) => Some(Duration::ZERO),
+                },
+                None => ttl,
+            };
+            let string = Value::String(value.clone());
+            let stored = match (condition, ttl) {
+                (Some(condition), ttl) => db.put_if(key.clone(), string, ttl, condition)?,
+                (None, Some(ttl)) => db.put_with_ttl(key.clone(), string, ttl).map(|()| true)?,
+                (None, None) => db.put(key.clone(), string).map(|()| true)?,
+            };
+            if !stored {
+                return Ok(Frame::Null);
+            }
+            shared.history.record(&key, value);
+            Ok(Frame::Text("OK".to_string()))
+        };
+        let response = match id {
+            Some(id) => shared.idempotency.run_once(&id, &key, write)?,
+            None => write()?,
+        };
         dst.write_frame(&response).await?;
         Ok(())
     }
@@ -187,34 +888,34 @@ impl Put {
 /// If the key does not exists, returns nil. otherwise just normal.
 #[derive(Debug)]
 pub struct Get {
-    pub key: String,
+    pub key: Bytes,
 }
 
 impl Get {
-    pub fn new(key: impl ToString) -> Get {
+    pub fn new(key: impl AsRef<[u8]>) -> Get {
         Get {
-            key: key.to_string(),
+            key: Bytes::copy_from_slice(key.as_ref()),
         }
     }
 
     pub fn parse_frames(parser: &mut CommandParser) -> Result<Get> {
         let key = parser
-            .next_string()?
+            .next_bytes()?
             .ok_or(CommandParseError::UnexpectedEOF)?;
         Ok(Get { key })
     }
 
     pub fn into_frame(self) -> Frame {
-        let frame = vec![Frame::Text("get".to_string()), Frame::Text(self.key)];
+        let frame = vec![Frame::Text("get".to_string()), Frame::Binary(self.key)];
         Frame::Array(frame)
     }
 
-    pub async fn apply(self, db: &DBHandle, dst: &mut Connection) -> Result<()> {
-        let response = if let Some(value) = db.get(self.key)? {
-            Frame::Binary(value)
-        } else {
-            Frame::Null
-        };
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let response = lookup(db, dst, self.key, |value| match value {
+            Some(Value::String(value)) => Frame::Binary(value.clone()),
+            Some(_) => wrong_type(),
+            None => Frame::Null,
+        })?;
         debug!(?response);
         dst.write_frame(&response).await?;
         Ok(())
@@ -251,3 +952,404 @@ impl Echo {
         Frame::Array(frame)
     }
 }
+
+/// `PING [message]` replies with `PONG`, or with `message` if given.
+#[derive(Debug, Default)]
+pub struct Ping {
+    pub message: Option<Bytes>,
+}
+
+impl Ping {
+    pub fn new(message: Option<Bytes>) -> Ping {
+        Ping { message }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Ping> {
+        let message = parser.next_bytes()?;
+        Ok(Ping { message })
+    }
+
+    pub async fn apply(self, dst: &mut Connection) -> Result<()> {
+        let response = match self.message {
+            Some(message) => Frame::Binary(message),
+            None => Frame::Text("PONG".to_string()),
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("ping".to_string())];
+        if let Some(message) = self.message {
+            frame.push(Frame::Binary(message));
+        }
+        Frame::Array(frame)
+    }
+}
+
+/// `READY` replies with OK once the data of the server is loaded, with a
+/// `-LOADING` error before, see [`crate::health`].
+#[derive(Debug, Default)]
+pub struct Ready;
+
+impl Ready {
+    pub fn new() -> Ready {
+        Ready
+    }
+
+    pub fn parse_frames(_parser: &mut CommandParser) -> Result<Ready> {
+        Ok(Ready)
+    }
+
+    pub async fn apply(self, dst: &mut Connection, shared: &Shared) -> Result<()> {
+        let response = if shared.readiness.is_ready() {
+            Frame::Text("OK".to_string())
+        } else {
+            Frame::error(ErrorCode::Loading, LOADING)
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn into_frame(self) -> Frame {
+        Frame::Array(vec![Frame::Text("ready".to_string())])
+    }
+}
+
+/// Negotiates the [`Protocol`] of this connection. Without a version, it only
+/// reports the current one. Replies with the version in use afterwards.
+#[derive(Debug)]
+pub struct Hello {
+    pub version: Option<i64>,
+}
+
+impl Hello {
+    pub fn new(version: Option<i64>) -> Hello {
+        Hello { version }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Hello> {
+        let version = match parser.next_string()? {
+            Some(version) => Some(version.parse::<i64>()?),
+            None => None,
+        };
+        Ok(Hello { version })
+    }
+
+    pub async fn apply(self, dst: &mut Connection) -> Result<()> {
+        let protocol = match self.version {
+            Some(version) => Protocol::from_version(version),
+            None => Some(dst.protocol()),
+        };
+        let response = match protocol {
+            Some(protocol) => {
+                dst.set_protocol(protocol);
+                Frame::Integer(protocol.version())
+            }
+            None => Frame::error(ErrorCode::NoProto, "unsupported protocol version"),
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("hello".to_string())];
+        if let Some(version) = self.version {
+            frame.push(Frame::Text(version.to_string()));
+        }
+        Frame::Array(frame)
+    }
+}
+
+/// `CONFIG GET param` reads a runtime parameter of the server, `CONFIG SET param value`
+/// changes it. Supported parameters:
+///
+/// - `shards`: the shard count of a sharded storage engine.
+/// - `maxmemory`: the approximate memory the keys may take, in bytes, 0 for no
+///   limit.
+/// - `maxmemory-policy`: which keys are evicted past `maxmemory`,
+///   `noeviction`, `allkeys-lru`, `allkeys-lfu` or `allkeys-random`, see
+///   [`EvictionPolicy`].
+/// - `value-compression`: how string values are compressed, `none`, `lz4` or
+///   `zstd` if the server was built with them, see [`Compression`].
+/// - `compression-threshold`: strings shorter than this many bytes are stored
+///   as is.
+/// - `wal-fsync`: when the LSM engine syncs its log to the disk, `always`,
+///   `everysec` or `no`, see [`WalSync`](crate::WalSync).
+/// - `replica-read-only`: `yes` to refuse writes but from the primary, see
+///   [`ServerConfig::replica_read_only`](crate::ServerConfig::replica_read_only).
+/// - `slowlog-log-slower-than`: the microseconds past which commands are
+///   logged as slow, see
+///   [`ServerConfig::slowlog_threshold`](crate::ServerConfig::slowlog_threshold).
+/// - `timeout`: the seconds after which idle connections are closed, 0 for
+///   never, see [`ServerConfig::client_timeout`](crate::ServerConfig::client_timeout).
+/// - `expiry-sweep-interval`: the milliseconds between reclaims of the expired
+///   keys, see
+///   [`ServerConfig::expiry_sweep_interval`](crate::ServerConfig::expiry_sweep_interval).
+/// - `lazyfree-threshold`: values with more elements than this are dropped in
+///   the background, see [`crate::lazyfree`].
+/// - `lazyfree-lazy-user-del`: `yes` for `DEL` to drop values in the
+///   background too, as `UNLINK` does.
+/// - `loglevel`: the filter directives of the log, as `info,uranus_s::aof=debug`,
+///   see [`crate::logging`].
+#[derive(Debug)]
+pub struct Config {
+    pub param: String,
+    /// The new value for `CONFIG SET`, none for `CONFIG GET`.
+    pub value: Option<String>,
+}
+
+impl Config {
+    pub fn get(param: impl ToString) -> Config {
+        Config {
+            param: param.to_string(),
+            value: None,
+        }
+    }
+
+    pub fn set(param: impl ToString, value: impl ToString) -> Config {
+        Config {
+            param: param.to_string(),
+            value: Some(value.to_string()),
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Config> {
+        let action = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .to_lowercase();
+        let param = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let value = match action.as_str() {
+            "get" => None,
+            "set" => Some(
+                parser
+                    .next_string()?
+                    .ok_or(CommandParseError::UnexpectedEOF)?,
+            ),
+            _ => Err(CommandParseError::UnknownCommand)?,
+        };
+        Ok(Config { param, value })
+    }
+
+    /// `CONFIG GET` replies with a `[param, value]` array, empty if the
+    /// parameter doesn't apply to this server. Changes are announced as
+    /// [`ServerEvent::ConfigChanged`].
+    pub async fn apply<D: Database>(
+        self,
+        db: &D,
+        dst: &mut Connection,
+        shared: &Shared,
+    ) -> Result<()> {
+        let param = self.param.to_lowercase();
+        let pair =
+            |value: String| Frame::Array(vec![Frame::Text(param.clone()), Frame::Text(value)]);
+        let response = match (param.as_str(), self.value.clone()) {
+            ("shards", None) => match db.shard_count() {
+                Some(shards) => Frame::Array(vec![
+                    Frame::Text(param.clone()),
+                    Frame::Text(shards.to_string()),
+                ]),
+                None => Frame::Array(vec![]),
+            },
+            ("shards", Some(value)) => match value.parse::<usize>() {
+                Ok(shards) if shards > 0 => match db.reshard(shards) {
+                    Ok(()) => Frame::Text("OK".to_string()),
+                    Err(err) => Frame::error(ErrorCode::Err, err),
+                },
+                _ => Frame::error(ErrorCode::Err, format!("invalid shard count '{}'", value)),
+            },
+            ("maxmemory", None) => match db.memory_limit() {
+                Some(limit) => Frame::Array(vec![
+                    Frame::Text(param.clone()),
+                    Frame::Text(limit.max().to_string()),
+                ]),
+                None => Frame::Array(vec![]),
+            },
+            ("maxmemory", Some(value)) => match (db.memory_limit(), value.parse::<usize>()) {
+                (Some(limit), Ok(max)) => {
+                    limit.set_max(max);
+                    Frame::Text("OK".to_string())
+                }
+                (None, _) => Frame::error(ErrorCode::Err, StorageError::Unsupported),
+                (_, Err(_)) => {
+                    Frame::error(ErrorCode::Err, format!("invalid memory limit '{}'", value))
+                }
+            },
+            ("maxmemory-policy", None) => match db.memory_limit() {
+                Some(limit) => Frame::Array(vec![
+                    Frame::Text(param.clone()),
+                    Frame::Text(limit.policy().to_string()),
+                ]),
+                None => Frame::Array(vec![]),
+            },
+            ("maxmemory-policy", Some(value)) => {
+                match (db.memory_limit(), value.parse::<EvictionPolicy>()) {
+                    (Some(limit), Ok(policy)) => {
+                        limit.set_policy(policy);
+                        Frame::Text("OK".to_string())
+                    }
+                    (None, _) => Frame::error(ErrorCode::Err, StorageError::Unsupported),
+                    (_, Err(err)) => Frame::error(ErrorCode::Err, err),
+                }
+            }
+            ("value-compression", None) => match db.value_compression() {
+                Some(compression) => pair(compression.compression().to_string()),
+                None => Frame::Array(vec![]),
+            },
+            ("value-compression", Some(value)) => {
+                match (db.value_compression(), value.parse::<Compression>()) {
+                    (Some(compression), Ok(algorithm)) => {
+                        compression.set_compression(algorithm);
+                        Frame::Text("OK".to_string())
+                    }
+                    (None, _) => Frame::error(ErrorCode::Err, StorageError::Unsupported),
+                    (_, Err(err)) => Frame::error(ErrorCode::Err, err),
+                }
+            }
+            ("compression-threshold", None) => match db.value_compression() {
+                Some(compression) => pair(compression.threshold().to_string()),
+                None => Frame::Array(vec![]),
+            },
+            ("compression-threshold", Some(value)) => {
+                match (db.value_compression(), value.parse::<usize>()) {
+                    (Some(compression), Ok(threshold)) => {
+                        compression.set_threshold(threshold);
+                        Frame::Text("OK".to_string())
+                    }
+                    (None, _) => Frame::error(ErrorCode::Err, StorageError::Unsupported),
+                    (_, Err(_)) => {
+                        Frame::error(ErrorCode::Err, format!("invalid threshold '{}'", value))
+                    }
+                }
+            }
+            ("wal-fsync", None) => match db.wal_sync() {
+                Some(wal_sync) => pair(wal_sync.get().to_string()),
+                None => Frame::Array(vec![]),
+            },
+            ("wal-fsync", Some(value)) => match (db.wal_sync(), value.parse::<WalSync>()) {
+                (Some(wal_sync), Ok(policy)) => {
+                    wal_sync.set(policy);
+                    Frame::Text("OK".to_string())
+                }
+                (None, _) => Frame::error(ErrorCode::Err, StorageError::Unsupported),
+                (_, Err(err)) => Frame::error(ErrorCode::Err, err),
+            },
+            ("replica-read-only", None) => {
+                let read_only = shared.config.replica_read_only();
+                pair(if read_only { "yes" } else { "no" }.to_string())
+            }
+            ("replica-read-only", Some(value)) => match value.to_lowercase().as_str() {
+                "yes" | "no" => {
+                    shared
+                        .config
+                        .set_replica_read_only(value.eq_ignore_ascii_case("yes"));
+                    Frame::Text("OK".to_string())
+                }
+                _ => Frame::error(ErrorCode::Err, format!("invalid value '{}'", value)),
+            },
+            ("slowlog-log-slower-than", None) => {
+                pair(shared.config.slowlog_threshold().as_micros().to_string())
+            }
+            ("slowlog-log-slower-than", Some(value)) => match value.parse::<u64>() {
+                Ok(micros) => {
+                    let threshold = Duration::from_micros(micros);
+                    shared.config.set_slowlog_threshold(threshold);
+                    Frame::Text("OK".to_string())
+                }
+                Err(_) => Frame::error(ErrorCode::Err, format!("invalid threshold '{}'", value)),
+            },
+            ("timeout", None) => {
+                let timeout = shared.config.client_timeout();
+                pair(timeout.map_or(0, |timeout| timeout.as_secs()).to_string())
+            }
+            ("timeout", Some(value)) => match value.parse::<u64>() {
+                Ok(secs) => {
+                    let timeout = (secs > 0).then(|| Duration::from_secs(secs));
+                    shared.config.set_client_timeout(timeout);
+                    Frame::Text("OK".to_string())
+                }
+                Err(_) => Frame::error(ErrorCode::Err, format!("invalid timeout '{}'", value)),
+            },
+            ("expiry-sweep-interval", None) => pair(
+                shared
+                    .config
+                    .expiry_sweep_interval()
+                    .as_millis()
+                    .to_string(),
+            ),
+            ("expiry-sweep-interval", Some(value)) => match value.parse::<u64>() {
+                Ok(millis) if millis > 0 => {
+                    let interval = Duration::from_millis(millis);
+                    shared.config.set_expiry_sweep_interval(interval);
+                    Frame::Text("OK".to_string())
+                }
+                _ => Frame::error(ErrorCode::Err, format!("invalid interval '{}'", value)),
+            },
+            ("lazyfree-threshold", None) => pair(shared.lazyfree.threshold().to_string()),
+            ("lazyfree-threshold", Some(value)) => match value.parse::<usize>() {
+                Ok(threshold) => {
+                    shared.lazyfree.set_threshold(threshold);
+                    Frame::Text("OK".to_string())
+                }
+                Err(_) => Frame::error(ErrorCode::Err, format!("invalid threshold '{}'", value)),
+            },
+            ("lazyfree-lazy-user-del", None) => {
+                let lazy = shared.lazyfree.lazy_user_del();
+                pair(if lazy { "yes" } else { "no" }.to_string())
+            }
+            ("lazyfree-lazy-user-del", Some(value)) => match value.to_lowercase().as_str() {
+                "yes" | "no" => {
+                    shared
+                        .lazyfree
+                        .set_lazy_user_del(value.eq_ignore_ascii_case("yes"));
+                    Frame::Text("OK".to_string())
+                }
+                _ => Frame::error(ErrorCode::Err, format!("invalid value '{}'", value)),
+            },
+            ("loglevel", None) => match shared.config.log_level() {
+                Some(level) => pair(level.get()),
+                None => Frame::Array(vec![]),
+            },
+            ("loglevel", Some(value)) => match shared.config.log_level() {
+                Some(level) => match level.set(&value) {
+                    Ok(()) => Frame::Text("OK".to_string()),
+                    Err(err) => Frame::error(
+                        ErrorCode::Err,
+                        format!("invalid log level '{}': {}", value, err),
+                    ),
+                },
+                None => Frame::error(ErrorCode::Err, "the log level is not configurable"),
+            },
+            _ => Frame::error(
+                ErrorCode::Err,
+                format!("unknown parameter '{}'", self.param),
+            ),
+        };
+        if let (Some(value), Frame::Text(_)) = (self.value, &response) {
+            shared
+                .events
+                .emit(ServerEvent::ConfigChanged { param, value });
+        }
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("config".to_string())];
+        match self.value {
+            Some(value) => {
+                frame.push(Frame::Text("set".to_string()));
+                frame.push(Frame::Text(self.param));
+                frame.push(Frame::Text(value));
+            }
+            None => {
+                frame.push(Frame::Text("get".to_string()));
+                frame.push(Frame::Text(self.param));
+            }
+        }
+        Frame::Array(frame)
+    }
+}
@@ -0,0 +1,169 @@
+//! Authentication and users, see [`crate::acl`]
+//!
+
+use std::fmt;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{CommandParseError, CommandParser};
+use crate::{Connection, ErrorCode, Frame, Shared, DEFAULT_USER};
+
+/// `AUTH [username] password` binds the connection to `username`, the default
+/// user if not given, if `password` is one of its passwords.
+pub struct Auth {
+    pub username: Option<String>,
+    pub password: String,
+}
+
+// The password is kept out of the logs, see [`crate::Redacted`].
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (f.debug_struct("Auth").field("username", &self.username)).finish_non_exhaustive()
+    }
+}
+
+impl Auth {
+    pub fn new(username: Option<String>, password: impl ToString) -> Auth {
+        Auth {
+            username,
+            password: password.to_string(),
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Auth> {
+        let first = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let auth = match parser.next_string()? {
+            Some(password) => Auth::new(Some(first), password),
+            None => Auth::new(None, first),
+        };
+        Ok(auth)
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("auth".to_string())];
+        frame.extend(self.username.map(Frame::Text));
+        frame.push(Frame::Text(self.password));
+        Frame::Array(frame)
+    }
+
+    pub async fn apply(self, dst: &mut Connection, shared: &Shared) -> Result<()> {
+        let username = self.username.unwrap_or_else(|| DEFAULT_USER.to_string());
+        let response = if shared.acl.authenticate(&username, &self.password) {
+            dst.set_user(username);
+            Frame::Text("OK".to_string())
+        } else {
+            let reply = "invalid username-password pair or user is disabled.";
+            Frame::error(ErrorCode::WrongPass, reply)
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `ACL` subcommands:
+///
+/// - `ACL SETUSER username [rule ...]` applies the rules to the user, creating
+///   it if needed.
+/// - `ACL DELUSER username [username ...]` removes the users, replies with how
+///   many existed. The default user can't be removed.
+/// - `ACL LIST` replies with the rules of each user, see
+///   [`AclUser`](crate::AclUser).
+/// - `ACL WHOAMI` replies with the user of this connection.
+pub enum AclCommand {
+    SetUser(String, Vec<String>),
+    DelUser(Vec<String>),
+    List,
+    WhoAmI,
+}
+
+// The rules, which set passwords, are kept out of the logs.
+impl fmt::Debug for AclCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AclCommand::SetUser(username, _) => {
+                (f.debug_tuple("SetUser").field(username)).finish_non_exhaustive()
+            }
+            AclCommand::DelUser(usernames) => f.debug_tuple("DelUser").field(usernames).finish(),
+            AclCommand::List => write!(f, "List"),
+            AclCommand::WhoAmI => write!(f, "WhoAmI"),
+        }
+    }
+}
+
+impl AclCommand {
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<AclCommand> {
+        let action = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .to_lowercase();
+        let mut rest = vec![];
+        while let Some(arg) = parser.next_string()? {
+            rest.push(arg);
+        }
+        let acl = match action.as_str() {
+            "setuser" if !rest.is_empty() => {
+                let username = rest.remove(0);
+                AclCommand::SetUser(username, rest)
+            }
+            "deluser" if !rest.is_empty() => AclCommand::DelUser(rest),
+            "list" if rest.is_empty() => AclCommand::List,
+            "whoami" if rest.is_empty() => AclCommand::WhoAmI,
+            "setuser" | "deluser" | "list" | "whoami" => {
+                Err(CommandParseError::WrongArity(format!("acl|{}", action)))?
+            }
+            _ => Err(CommandParseError::UnknownOption(action))?,
+        };
+        Ok(acl)
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("acl".to_string())];
+        match self {
+            AclCommand::SetUser(username, rules) => {
+                frame.push(Frame::Text("setuser".to_string()));
+                frame.push(Frame::Text(username));
+                frame.extend(rules.into_iter().map(Frame::Text));
+            }
+            AclCommand::DelUser(usernames) => {
+                frame.push(Frame::Text("deluser".to_string()));
+                frame.extend(usernames.into_iter().map(Frame::Text));
+            }
+            AclCommand::List => frame.push(Frame::Text("list".to_string())),
+            AclCommand::WhoAmI => frame.push(Frame::Text("whoami".to_string())),
+        }
+        Frame::Array(frame)
+    }
+
+    pub async fn apply(self, dst: &mut Connection, shared: &Shared) -> Result<()> {
+        let response = match self {
+            AclCommand::SetUser(username, rules) => match shared.acl.set_user(&username, &rules) {
+                Ok(()) => Frame::Text("OK".to_string()),
+                Err(err) => Frame::error(ErrorCode::Err, err),
+            },
+            AclCommand::DelUser(usernames) => {
+                let deleted = usernames
+                    .iter()
+                    .filter(|username| shared.acl.delete_user(username))
+                    .count();
+                Frame::Integer(deleted as i64)
+            }
+            AclCommand::List => Frame::Array(
+                shared
+                    .acl
+                    .users()
+                    .iter()
+                    .map(|user| Frame::Binary(Bytes::from(user.to_string())))
+                    .collect(),
+            ),
+            AclCommand::WhoAmI => {
+                let user = dst.user().unwrap_or(DEFAULT_USER);
+                Frame::Binary(Bytes::from(user.to_string()))
+            }
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+//! Commands on the append-only file
+//!
+
+use anyhow::Result;
+
+use super::CommandParser;
+use crate::{Connection, Database, ErrorCode, Frame, Shared};
+
+/// `BGREWRITEAOF` compacts the append-only file in the background, see
+/// [`AppendOnlyFile::rewrite`](crate::AppendOnlyFile::rewrite). Replies with
+/// an error if the file is disabled or a rewrite is in progress already.
+#[derive(Debug, Default)]
+pub struct BgRewriteAof;
+
+impl BgRewriteAof {
+    pub fn new() -> BgRewriteAof {
+        BgRewriteAof
+    }
+
+    pub fn parse_frames(_parser: &mut CommandParser) -> Result<BgRewriteAof> {
+        Ok(BgRewriteAof)
+    }
+
+    pub fn into_frame(self) -> Frame {
+        Frame::Array(vec![Frame::Text("bgrewriteaof".to_string())])
+    }
+
+    pub async fn apply<D: Database>(
+        self,
+        db: &D,
+        dst: &mut Connection,
+        shared: &Shared,
+    ) -> Result<()> {
+        let response = match &shared.aof {
+            Some(aof) if aof.rewrite(db.clone()) => {
+                Frame::Text("Background append only file rewriting started".to_string())
+            }
+            Some(_) => Frame::error(
+                ErrorCode::Err,
+                "Background append only file rewriting already in progress",
+            ),
+            None => Frame::error(ErrorCode::Err, "append only file is disabled"),
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
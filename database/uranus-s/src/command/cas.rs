@@ -0,0 +1,66 @@
+//! Compare-and-swap, for optimistic concurrency
+//!
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{CommandParseError, CommandParser};
+use crate::{Connection, Database, Frame, Value};
+
+/// `CAS key expected value` sets the string at `key` to `value` only if it is
+/// currently `expected`. Replies with 1 if it was set, 0 otherwise. Clients
+/// read a key, compute its new value, and retry from the read until `CAS`
+/// succeeds, rather than locking the key.
+#[derive(Debug)]
+pub struct Cas {
+    pub key: Bytes,
+    pub expected: Bytes,
+    pub value: Bytes,
+}
+
+impl Cas {
+    pub fn new(key: impl AsRef<[u8]>, expected: Bytes, value: Bytes) -> Cas {
+        Cas {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            expected,
+            value,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Cas> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let expected = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let value = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(Cas {
+            key,
+            expected,
+            value,
+        })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("cas".to_string()),
+            Frame::Binary(self.key),
+            Frame::Binary(self.expected),
+            Frame::Binary(self.value),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let swapped = db.compare_and_swap(
+            self.key,
+            Value::String(self.expected),
+            Value::String(self.value),
+        )?;
+        dst.write_frame(&Frame::Integer(swapped as i64)).await?;
+        Ok(())
+    }
+}
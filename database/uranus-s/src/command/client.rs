@@ -0,0 +1,97 @@
+//! Commands on the connections of the server, see [`crate::clients`]
+//!
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{CommandParseError, CommandParser};
+use crate::{Connection, ErrorCode, Frame, Shared};
+
+/// `CLIENT` subcommands:
+///
+/// - `CLIENT LIST` replies with a line for each connection, see
+///   [`Clients::describe`](crate::Clients::describe).
+/// - `CLIENT GETNAME` replies with the name of this connection, nil if it has
+///   none.
+/// - `CLIENT SETNAME name` names this connection, an empty name clears it.
+/// - `CLIENT KILL id` closes the connection `id` before its next request.
+#[derive(Debug)]
+pub enum ClientCommand {
+    List,
+    GetName,
+    SetName(String),
+    Kill(u64),
+}
+
+impl ClientCommand {
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<ClientCommand> {
+        let action = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .to_lowercase();
+        let client = match action.as_str() {
+            "list" => ClientCommand::List,
+            "getname" => ClientCommand::GetName,
+            "setname" => ClientCommand::SetName(
+                parser
+                    .next_string()?
+                    .ok_or(CommandParseError::UnexpectedEOF)?,
+            ),
+            "kill" => ClientCommand::Kill(
+                parser
+                    .next_string()?
+                    .ok_or(CommandParseError::UnexpectedEOF)?
+                    .parse::<u64>()?,
+            ),
+            _ => Err(CommandParseError::UnknownOption(action))?,
+        };
+        Ok(client)
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("client".to_string())];
+        match self {
+            ClientCommand::List => frame.push(Frame::Text("list".to_string())),
+            ClientCommand::GetName => frame.push(Frame::Text("getname".to_string())),
+            ClientCommand::SetName(name) => {
+                frame.push(Frame::Text("setname".to_string()));
+                frame.push(Frame::Text(name));
+            }
+            ClientCommand::Kill(id) => {
+                frame.push(Frame::Text("kill".to_string()));
+                frame.push(Frame::Text(id.to_string()));
+            }
+        }
+        Frame::Array(frame)
+    }
+
+    /// Names can't hold spaces, so that `CLIENT LIST` stays easy to parse.
+    pub async fn apply(self, dst: &mut Connection, shared: &Shared) -> Result<()> {
+        let response = match self {
+            ClientCommand::List => Frame::Binary(Bytes::from(shared.clients.describe())),
+            ClientCommand::GetName => match dst.client().and_then(|client| client.name()) {
+                Some(name) => Frame::Binary(Bytes::from(name)),
+                None => Frame::Null,
+            },
+            ClientCommand::SetName(name) if name.chars().any(|c| c <= ' ' || c > '~') => {
+                Frame::error(
+                    ErrorCode::Err,
+                    "Client names cannot contain spaces, newlines or special characters.",
+                )
+            }
+            ClientCommand::SetName(name) => match dst.client() {
+                Some(client) => {
+                    client.set_name(name);
+                    Frame::Text("OK".to_string())
+                }
+                None => Frame::error(ErrorCode::Err, "This connection is not listed"),
+            },
+            ClientCommand::Kill(id) => match shared.clients.kill(id) {
+                true => Frame::Text("OK".to_string()),
+                false => Frame::error(ErrorCode::Err, "No such client"),
+            },
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
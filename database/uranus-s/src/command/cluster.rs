@@ -0,0 +1,150 @@
+//! Commands on cluster mode, see [`crate::cluster`]
+//!
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{CommandParseError, CommandParser};
+use crate::{key_slot, Connection, ErrorCode, Frame, Shared, SlotState, SLOTS};
+
+/// `CLUSTER` subcommands:
+///
+/// - `CLUSTER KEYSLOT key` replies with the slot of `key`.
+/// - `CLUSTER NODES` replies with the nodes known by gossip, see
+///   [`Membership::describe`](crate::Membership::describe).
+/// - `CLUSTER SLOTS` replies with the ranges of slots served by a node, as a
+///   flat array of `start`, `end` and `addr` triples.
+/// - `CLUSTER SETSLOT slot NODE|MIGRATING|IMPORTING addr` and
+///   `CLUSTER SETSLOT slot STABLE` change the view of this node, see
+///   [`SlotState`].
+#[derive(Debug)]
+pub enum Cluster {
+    KeySlot(String),
+    Nodes,
+    Slots,
+    SetSlot(u16, SlotState),
+}
+
+impl Cluster {
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Cluster> {
+        let action = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .to_lowercase();
+        let cluster = match action.as_str() {
+            "keyslot" => Cluster::KeySlot(
+                parser
+                    .next_string()?
+                    .ok_or(CommandParseError::UnexpectedEOF)?,
+            ),
+            "nodes" => Cluster::Nodes,
+            "slots" => Cluster::Slots,
+            "setslot" => {
+                let slot = parser
+                    .next_string()?
+                    .ok_or(CommandParseError::UnexpectedEOF)?
+                    .parse::<u16>()?;
+                let state = parser
+                    .next_string()?
+                    .ok_or(CommandParseError::UnexpectedEOF)?
+                    .to_lowercase();
+                let state = match state.as_str() {
+                    "stable" => SlotState::Stable,
+                    _ => {
+                        let addr = parser
+                            .next_string()?
+                            .ok_or(CommandParseError::UnexpectedEOF)?;
+                        match state.as_str() {
+                            "node" => SlotState::Node(addr),
+                            "migrating" => SlotState::Migrating(addr),
+                            "importing" => SlotState::Importing(addr),
+                            _ => Err(CommandParseError::UnknownOption(state))?,
+                        }
+                    }
+                };
+                Cluster::SetSlot(slot, state)
+            }
+            _ => Err(CommandParseError::UnknownOption(action))?,
+        };
+        Ok(cluster)
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("cluster".to_string())];
+        match self {
+            Cluster::KeySlot(key) => {
+                frame.push(Frame::Text("keyslot".to_string()));
+                frame.push(Frame::Text(key));
+            }
+            Cluster::Nodes => frame.push(Frame::Text("nodes".to_string())),
+            Cluster::Slots => frame.push(Frame::Text("slots".to_string())),
+            Cluster::SetSlot(slot, state) => {
+                frame.push(Frame::Text("setslot".to_string()));
+                frame.push(Frame::Text(slot.to_string()));
+                let (state, addr) = match state {
+                    SlotState::Node(addr) => ("node", Some(addr)),
+                    SlotState::Migrating(addr) => ("migrating", Some(addr)),
+                    SlotState::Importing(addr) => ("importing", Some(addr)),
+                    SlotState::Stable => ("stable", None),
+                };
+                frame.push(Frame::Text(state.to_string()));
+                frame.extend(addr.map(Frame::Text));
+            }
+        }
+        Frame::Array(frame)
+    }
+
+    pub async fn apply(self, dst: &mut Connection, shared: &Shared) -> Result<()> {
+        let response = match (self, &shared.cluster) {
+            (Cluster::KeySlot(key), _) => Frame::Integer(key_slot(key.as_bytes()) as i64),
+            (Cluster::Nodes, _) => match &shared.membership {
+                Some(membership) => Frame::Binary(Bytes::from(membership.describe())),
+                None => Frame::error(ErrorCode::Err, "This instance has gossip disabled"),
+            },
+            (_, None) => Frame::error(ErrorCode::Err, "This instance has cluster support disabled"),
+            (Cluster::Slots, Some(cluster)) => {
+                let topology = cluster.topology();
+                let mut slots = vec![];
+                for (range, addr) in topology.ranges() {
+                    slots.push(Frame::Integer(*range.start() as i64));
+                    slots.push(Frame::Integer(*range.end() as i64));
+                    slots.push(Frame::Binary(Bytes::from(addr.to_string())));
+                }
+                Frame::Array(slots)
+            }
+            (Cluster::SetSlot(slot, _), Some(_)) if slot >= SLOTS => {
+                Frame::error(ErrorCode::Err, "Invalid or out of range slot")
+            }
+            (Cluster::SetSlot(slot, state), Some(cluster)) => {
+                cluster.set_slot(slot, state);
+                Frame::Text("OK".to_string())
+            }
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `ASKING` lets the next command of the connection be served by a node
+/// importing its slot, see [`crate::cluster`]. The handler keeps track of it.
+#[derive(Debug, Default)]
+pub struct Asking;
+
+impl Asking {
+    pub fn new() -> Asking {
+        Asking
+    }
+
+    pub fn parse_frames(_parser: &mut CommandParser) -> Result<Asking> {
+        Ok(Asking)
+    }
+
+    pub fn into_frame(self) -> Frame {
+        Frame::Array(vec![Frame::Text("asking".to_string())])
+    }
+
+    pub async fn apply(self, dst: &mut Connection) -> Result<()> {
+        dst.write_frame(&Frame::Text("OK".to_string())).await?;
+        Ok(())
+    }
+}
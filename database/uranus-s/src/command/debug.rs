@@ -0,0 +1,85 @@
+//! Server debugging commands
+//!
+
+use anyhow::Result;
+
+use super::{CommandParseError, CommandParser};
+use crate::{Connection, Frame, Shared};
+
+/// Subcommands of `DEBUG`:
+///
+/// - `DEBUG TRACE ON|OFF` turns the protocol trace of every connection on or
+///   off, see [`Tracer`](crate::Tracer).
+/// - `DEBUG BUFFERS` replies with how many connections read with each buffer
+///   size, as a flat `[size, connections, ...]` array, see [`crate::buffer`].
+/// - `DEBUG PANIC` panics, which the connection survives with an error reply.
+#[derive(Debug)]
+pub enum DebugCommand {
+    Trace(bool),
+    Buffers,
+    Panic,
+}
+
+impl DebugCommand {
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<DebugCommand> {
+        let subcommand = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .to_lowercase();
+        match subcommand.as_str() {
+            "trace" => {
+                let switch = parser
+                    .next_string()?
+                    .ok_or(CommandParseError::UnexpectedEOF)?
+                    .to_lowercase();
+                match switch.as_str() {
+                    "on" => Ok(DebugCommand::Trace(true)),
+                    "off" => Ok(DebugCommand::Trace(false)),
+                    _ => Err(CommandParseError::UnknownOption(switch))?,
+                }
+            }
+            "buffers" => Ok(DebugCommand::Buffers),
+            "panic" => Ok(DebugCommand::Panic),
+            _ => Err(CommandParseError::UnknownCommand)?,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("debug".to_string())];
+        match self {
+            DebugCommand::Trace(enabled) => {
+                let switch = if enabled { "on" } else { "off" };
+                frame.push(Frame::Text("trace".to_string()));
+                frame.push(Frame::Text(switch.to_string()));
+            }
+            DebugCommand::Buffers => frame.push(Frame::Text("buffers".to_string())),
+            DebugCommand::Panic => frame.push(Frame::Text("panic".to_string())),
+        }
+        Frame::Array(frame)
+    }
+
+    pub async fn apply(self, dst: &mut Connection, shared: &Shared) -> Result<()> {
+        let response = match self {
+            DebugCommand::Trace(enabled) => {
+                shared.tracer.set_enabled(enabled);
+                Frame::Text("OK".to_string())
+            }
+            DebugCommand::Buffers => Frame::Array(
+                shared
+                    .buffer_sizes
+                    .snapshot()
+                    .into_iter()
+                    .flat_map(|(size, connections)| {
+                        [
+                            Frame::Integer(size as i64),
+                            Frame::Integer(connections as i64),
+                        ]
+                    })
+                    .collect(),
+            ),
+            DebugCommand::Panic => panic!("DEBUG PANIC"),
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+//! Removing keys
+//!
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{CommandParseError, CommandParser, YIELD_EVERY};
+use crate::{Connection, Database, Frame, Shared};
+
+/// `DEL key [key ...]` removes the keys, whatever their type. Replies with the
+/// number of keys which existed. Their values are dropped right away, unless
+/// [`LazyFree::lazy_user_del`](crate::LazyFree::lazy_user_del) is set, see
+/// [`Unlink`].
+#[derive(Debug)]
+pub struct Del {
+    pub keys: Vec<Bytes>,
+}
+
+impl Del {
+    pub fn new(keys: Vec<Bytes>) -> Del {
+        Del { keys }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Del> {
+        let mut keys = vec![];
+        while let Some(key) = parser.next_bytes()? {
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            Err(CommandParseError::UnexpectedEOF)?
+        }
+        Ok(Del { keys })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("del".to_string())];
+        frame.extend(self.keys.into_iter().map(Frame::Binary));
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(
+        self,
+        db: &D,
+        dst: &mut Connection,
+        shared: &Shared,
+    ) -> Result<()> {
+        if shared.lazyfree.lazy_user_del() {
+            return Unlink::new(self.keys).apply(db, dst, shared).await;
+        }
+        let mut removed = 0;
+        for key in self.keys {
+            if db.update(key, |value| value.take().is_some())? {
+                removed += 1;
+            }
+        }
+        dst.write_frame(&Frame::Integer(removed)).await?;
+        Ok(())
+    }
+}
+
+/// `UNLINK key [key ...]` removes the keys as `DEL` does, but drops their
+/// values in the background if they are large, see [`crate::lazyfree`].
+/// Replies with the number of keys which existed.
+#[derive(Debug)]
+pub struct Unlink {
+    pub keys: Vec<Bytes>,
+}
+
+impl Unlink {
+    pub fn new(keys: Vec<Bytes>) -> Unlink {
+        Unlink { keys }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Unlink> {
+        let Del { keys } = Del::parse_frames(parser)?;
+        Ok(Unlink { keys })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("unlink".to_string())];
+        frame.extend(self.keys.into_iter().map(Frame::Binary));
+        Frame::Array(frame)
+    }
+
+    /// Takes the values out under the lock of their stripe, then frees them
+    /// once it is released.
+    pub async fn apply<D: Database>(
+        self,
+        db: &D,
+        dst: &mut Connection,
+        shared: &Shared,
+    ) -> Result<()> {
+        let mut removed = 0;
+        for key in self.keys {
+            if let Some(value) = db.update(key, Option::take)? {
+                shared.lazyfree.free(value);
+                removed += 1;
+            }
+        }
+        dst.write_frame(&Frame::Integer(removed)).await?;
+        Ok(())
+    }
+}
+
+/// `DELPREFIX prefix` removes every key starting with `prefix` from the
+/// selected database, such as those of a tenant. Replies with the number of
+/// keys removed. In cluster mode, it only removes the keys of this node.
+#[derive(Debug)]
+pub struct DelPrefix {
+    pub prefix: String,
+}
+
+impl DelPrefix {
+    pub fn new(prefix: impl ToString) -> DelPrefix {
+        DelPrefix {
+            prefix: prefix.to_string(),
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<DelPrefix> {
+        let prefix = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(DelPrefix { prefix })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("delprefix".to_string()),
+            Frame::Text(self.prefix),
+        ];
+        Frame::Array(frame)
+    }
+
+    /// Removes the keys by chunks, yielding in between.
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let keys = db.keys_with_prefix(self.prefix.as_bytes())?;
+        let mut removed = 0;
+        for chunk in keys.chunks(YIELD_EVERY) {
+            for key in chunk {
+                if db.update(key.clone(), |value| value.take().is_some())? {
+                    removed += 1;
+                }
+            }
+            tokio::task::yield_now().await;
+        }
+        dst.write_frame(&Frame::Integer(removed)).await?;
+        Ok(())
+    }
+}
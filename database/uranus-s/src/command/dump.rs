@@ -0,0 +1,288 @@
+//! Serializing keys, to move them to another server
+//!
+
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use tokio::{net::TcpStream, time};
+use uranus_kv::{dump, restore};
+
+use super::{from_unix_millis, lookup, unix_millis, CommandParseError, CommandParser};
+use crate::{Connection, Database, ErrorCode, Frame, KeyTtl, SetCondition};
+
+/// `DUMP key` replies with the value of `key` serialized, see
+/// [`uranus_kv::dump`], or nil if there is no such key. The TTL isn't part of
+/// it.
+#[derive(Debug)]
+pub struct Dump {
+    pub key: Bytes,
+}
+
+impl Dump {
+    pub fn new(key: impl AsRef<[u8]>) -> Dump {
+        Dump {
+            key: Bytes::copy_from_slice(key.as_ref()),
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Dump> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(Dump { key })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![Frame::Text("dump".to_string()), Frame::Binary(self.key)];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let response = lookup(db, dst, self.key, |value| match value {
+            Some(value) => Frame::Binary(dump(value)),
+            None => Frame::Null,
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `RESTORE key ttl blob [REPLACE] [ABSTTL]` recreates `key` from a `DUMP` of
+/// its value, expiring after `ttl` milliseconds, or never if it is 0. With
+/// `ABSTTL`, `ttl` is the Unix time in milliseconds it expires at instead, and
+/// a time already past restores nothing. Without `REPLACE`, it fails with
+/// `BUSYKEY` if `key` exists. A blob which is damaged or of an unknown version
+/// is refused.
+#[derive(Debug, Clone)]
+pub struct Restore {
+    pub key: Bytes,
+    pub ttl: Option<Duration>,
+    pub expires_at: Option<SystemTime>,
+    pub blob: Bytes,
+    pub replace: bool,
+}
+
+impl Restore {
+    pub fn new(key: impl AsRef<[u8]>, ttl: Option<Duration>, blob: Bytes) -> Restore {
+        Restore {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            ttl,
+            expires_at: None,
+            blob,
+            replace: false,
+        }
+    }
+
+    /// Makes the key expire at `at` rather than after its TTL.
+    pub fn expiring_at(mut self, at: SystemTime) -> Restore {
+        self.ttl = None;
+        self.expires_at = Some(at);
+        self
+    }
+
+    /// Overwrites `key` if it exists.
+    pub fn replacing(mut self) -> Restore {
+        self.replace = true;
+        self
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Restore> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let ttl = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<u64>()?;
+        let blob = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let mut restore = Restore::new(key, (ttl > 0).then(|| Duration::from_millis(ttl)), blob);
+        while let Some(option) = parser.next_string()? {
+            match option.to_lowercase().as_str() {
+                "replace" => restore.replace = true,
+                "absttl" if ttl > 0 => restore = restore.expiring_at(from_unix_millis(ttl)),
+                "absttl" => {}
+                _ => Err(CommandParseError::UnknownOption(option))?,
+            }
+        }
+        Ok(restore)
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let ttl = match (self.ttl, self.expires_at) {
+            (_, Some(at)) => unix_millis(at).max(1),
+            (Some(ttl), None) => (ttl.as_millis() as u64).max(1),
+            (None, None) => 0,
+        };
+        let mut frame = vec![
+            Frame::Text("restore".to_string()),
+            Frame::Binary(self.key),
+            Frame::Text(ttl.to_string()),
+            Frame::Binary(self.blob),
+        ];
+        if self.replace {
+            frame.push(Frame::Text("replace".to_string()));
+        }
+        if self.expires_at.is_some() {
+            frame.push(Frame::Text("absttl".to_string()));
+        }
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let value = match restore(self.blob) {
+            Ok(value) => value,
+            Err(err) => {
+                let response = Frame::error(ErrorCode::Err, format!("bad DUMP payload, {}", err));
+                dst.write_frame(&response).await?;
+                return Ok(());
+            }
+        };
+        let key = self.key;
+        let ttl = match self.expires_at {
+            Some(at) => match at.duration_since(SystemTime::now()) {
+                Ok(ttl) => Some(ttl),
+                Err(_) => {
+                    dst.write_frame(&Frame::Text("OK".to_string())).await?;
+                    return Ok(());
+                }
+            },
+            None => self.ttl,
+        };
+        let restored = match (self.replace, ttl) {
+            (false, ttl) => db.put_if(key, value, ttl, SetCondition::Absent)?,
+            (true, Some(ttl)) => db.put_with_ttl(key, value, ttl).map(|()| true)?,
+            (true, None) => db.put(key, value).map(|()| true)?,
+        };
+        let response = if restored {
+            Frame::Text("OK".to_string())
+        } else {
+            Frame::error(ErrorCode::BusyKey, "Target key name already exists")
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `MIGRATE host port key timeout` moves `key` to the server at `host:port`:
+/// it is restored there with its TTL, then deleted here. Connecting and
+/// restoring may take at most `timeout` milliseconds, or indefinitely with a
+/// timeout of 0. Replies with `NOKEY` if there is no such key, and fails if the
+/// target refuses the key, say with `BUSYKEY` as it has it already.
+///
+/// The key is only deleted if it wasn't written to meanwhile, otherwise it is
+/// kept and the command fails, the target having an older value.
+#[derive(Debug)]
+pub struct Migrate {
+    pub host: String,
+    pub port: u16,
+    pub key: Bytes,
+    pub timeout: Option<Duration>,
+}
+
+impl Migrate {
+    pub fn new(
+        host: impl ToString,
+        port: u16,
+        key: impl AsRef<[u8]>,
+        timeout: Option<Duration>,
+    ) -> Migrate {
+        Migrate {
+            host: host.to_string(),
+            port,
+            key: Bytes::copy_from_slice(key.as_ref()),
+            timeout,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Migrate> {
+        let host = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let port = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<u16>()?;
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let timeout = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<u64>()?;
+        let timeout = (timeout > 0).then(|| Duration::from_millis(timeout));
+        Ok(Migrate::new(host, port, key, timeout))
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let timeout = self.timeout.map_or(0, |timeout| timeout.as_millis().max(1));
+        let frame = vec![
+            Frame::Text("migrate".to_string()),
+            Frame::Text(self.host),
+            Frame::Text(self.port.to_string()),
+            Frame::Binary(self.key),
+            Frame::Text(timeout.to_string()),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let key = self.key.clone();
+        let Some(value) = db.get(key.clone())? else {
+            dst.write_frame(&Frame::Text("NOKEY".to_string())).await?;
+            return Ok(());
+        };
+        let ttl = match db.ttl(key.clone())? {
+            KeyTtl::Remaining(ttl) => Some(ttl),
+            _ => None,
+        };
+        let request = Restore::new(&self.key, ttl, dump(&value)).into_frame();
+        let target = format!("{}:{}", self.host, self.port);
+        let reply = match self.timeout {
+            Some(timeout) => time::timeout(timeout, send(&target, &request))
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("timed out"))),
+            None => send(&target, &request).await,
+        };
+
+        let response = match reply {
+            Ok(Frame::Text(ok)) if ok == "OK" => {
+                let deleted = db.update(key, |current| {
+                    let unchanged = current.as_ref() == Some(&value);
+                    if unchanged {
+                        *current = None;
+                    }
+                    unchanged
+                })?;
+                if deleted {
+                    Frame::Text("OK".to_string())
+                } else {
+                    Frame::error(ErrorCode::Err, "the key was written to while migrating")
+                }
+            }
+            Ok(Frame::Error(err)) => Frame::Error(err),
+            Ok(frame) => Frame::error(
+                ErrorCode::IoErr,
+                format!("unexpected reply from {}: {}", target, frame),
+            ),
+            Err(err) => Frame::error(
+                ErrorCode::IoErr,
+                format!("failed to migrate to {}: {}", target, err),
+            ),
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// Sends `request` to the server at `target`, returns its reply.
+async fn send(target: &str, request: &Frame) -> Result<Frame> {
+    let mut connection = Connection::new(TcpStream::connect(target).await?);
+    connection.write_frame(request).await?;
+    connection
+        .read_frame()
+        .await?
+        .ok_or_else(|| anyhow!("the target closed the connection"))
+}
@@ -0,0 +1,226 @@
+//! Expiry of existing keys
+//!
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{CommandParseError, CommandParser};
+use crate::{Connection, Database, Frame, KeyTtl};
+
+/// `EXPIRE key seconds` or `PEXPIRE key milliseconds` makes `key` expire after
+/// that long, replacing its TTL if it had one. A TTL which isn't positive
+/// expires the key right away. Replies with 1, or 0 if there is no such key.
+#[derive(Debug)]
+pub struct Expire {
+    pub key: Bytes,
+    pub ttl: Duration,
+}
+
+impl Expire {
+    pub fn new(key: impl AsRef<[u8]>, ttl: Duration) -> Expire {
+        Expire {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            ttl,
+        }
+    }
+
+    /// Parses `PEXPIRE` if `millis`, `EXPIRE` otherwise.
+    pub fn parse_frames(parser: &mut CommandParser, millis: bool) -> Result<Expire> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let amount = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<i64>()?
+            .max(0) as u64;
+        let ttl = if millis {
+            Duration::from_millis(amount)
+        } else {
+            Duration::from_secs(amount)
+        };
+        Ok(Expire { key, ttl })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("pexpire".to_string()),
+            Frame::Binary(self.key),
+            Frame::Text(self.ttl.as_millis().to_string()),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let expired = db.expire_in(self.key, self.ttl)?;
+        dst.write_frame(&Frame::Integer(expired as i64)).await?;
+        Ok(())
+    }
+}
+
+/// `EXPIREAT key timestamp` or `PEXPIREAT key timestamp` makes `key` expire at
+/// that Unix time, in seconds or milliseconds, replacing its TTL if it had one.
+/// A time already past deletes the key. Replies with 1, or 0 if there is no
+/// such key.
+///
+/// Relative TTLs are logged this way, see the [append-only file](crate::aof).
+#[derive(Debug)]
+pub struct ExpireAt {
+    pub key: Bytes,
+    pub at: SystemTime,
+}
+
+impl ExpireAt {
+    pub fn new(key: impl AsRef<[u8]>, at: SystemTime) -> ExpireAt {
+        ExpireAt {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            at,
+        }
+    }
+
+    /// Parses `PEXPIREAT` if `millis`, `EXPIREAT` otherwise.
+    pub fn parse_frames(parser: &mut CommandParser, millis: bool) -> Result<ExpireAt> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let timestamp = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<i64>()?
+            .max(0) as u64;
+        let at = if millis {
+            from_unix_millis(timestamp)
+        } else {
+            UNIX_EPOCH + Duration::from_secs(timestamp)
+        };
+        Ok(ExpireAt { key, at })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("pexpireat".to_string()),
+            Frame::Binary(self.key),
+            Frame::Text(unix_millis(self.at).to_string()),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let key = self.key;
+        let expired = match self.at.duration_since(SystemTime::now()) {
+            Ok(ttl) => db.expire_in(key, ttl)?,
+            Err(_) => {
+                let exists = db.ttl(key.clone())? != KeyTtl::Missing;
+                if exists {
+                    db.delete(key)?;
+                }
+                exists
+            }
+        };
+        dst.write_frame(&Frame::Integer(expired as i64)).await?;
+        Ok(())
+    }
+}
+
+/// Milliseconds since the Unix epoch at `at`, 0 before it.
+pub fn unix_millis(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// The time `millis` milliseconds after the Unix epoch.
+pub fn from_unix_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+/// `TTL key` replies with the seconds `key` has left to live, rounded, and
+/// `PTTL key` with the milliseconds. The reply is -1 if the key has no TTL, and
+/// -2 if there is no such key.
+#[derive(Debug)]
+pub struct Ttl {
+    pub key: Bytes,
+    pub millis: bool,
+}
+
+impl Ttl {
+    /// `PTTL key`, the finer of the two.
+    pub fn new(key: impl AsRef<[u8]>) -> Ttl {
+        Ttl {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            millis: true,
+        }
+    }
+
+    /// Parses `PTTL` if `millis`, `TTL` otherwise.
+    pub fn parse_frames(parser: &mut CommandParser, millis: bool) -> Result<Ttl> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(Ttl { key, millis })
+    }
+
+    pub fn name(&self) -> &'static str {
+        if self.millis {
+            "pttl"
+        } else {
+            "ttl"
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text(self.name().to_string()),
+            Frame::Binary(self.key),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let ttl = db.ttl(self.key)?;
+        dst.record_lookup(ttl != KeyTtl::Missing);
+        let reply = match ttl {
+            KeyTtl::Missing => -2,
+            KeyTtl::Persistent => -1,
+            KeyTtl::Remaining(remaining) if self.millis => remaining.as_millis() as i64,
+            KeyTtl::Remaining(remaining) => (remaining.as_millis() as i64 + 500) / 1000,
+        };
+        dst.write_frame(&Frame::Integer(reply)).await?;
+        Ok(())
+    }
+}
+
+/// `PERSIST key` removes the TTL of `key`. Replies with 1, or 0 if the key has
+/// no TTL or doesn't exist.
+#[derive(Debug)]
+pub struct Persist {
+    pub key: Bytes,
+}
+
+impl Persist {
+    pub fn new(key: impl AsRef<[u8]>) -> Persist {
+        Persist {
+            key: Bytes::copy_from_slice(key.as_ref()),
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Persist> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(Persist { key })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![Frame::Text("persist".to_string()), Frame::Binary(self.key)];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let persisted = db.persist(self.key)?;
+        dst.write_frame(&Frame::Integer(persisted as i64)).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+//! Commands on hash values, which map fields of a key to values
+//!
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{lookup, wrong_type, CommandParseError, CommandParser};
+use crate::{Connection, Database, Frame, Value};
+
+/// `HSET key field value [field value ...]` sets fields of the hash at `key`,
+/// creating it if needed. Replies with the number of fields added, fields
+/// which only got a new value don't count.
+#[derive(Debug)]
+pub struct HSet {
+    pub key: Bytes,
+    pub pairs: Vec<(Bytes, Bytes)>,
+}
+
+impl HSet {
+    pub fn new(key: impl AsRef<[u8]>, pairs: Vec<(Bytes, Bytes)>) -> HSet {
+        HSet {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            pairs,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<HSet> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let mut pairs = vec![];
+        while let Some(field) = parser.next_bytes()? {
+            let value = parser
+                .next_bytes()?
+                .ok_or(CommandParseError::UnexpectedEOF)?;
+            pairs.push((field, value));
+        }
+        if pairs.is_empty() {
+            Err(CommandParseError::UnexpectedEOF)?
+        }
+        Ok(HSet { key, pairs })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("hset".to_string()), Frame::Binary(self.key)];
+        for (field, value) in self.pairs {
+            frame.push(Frame::Binary(field));
+            frame.push(Frame::Binary(value));
+        }
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let HSet { key, pairs } = self;
+        let response = db.update(key, |value| {
+            match value.get_or_insert_with(|| Value::Hash(HashMap::new())) {
+                Value::Hash(hash) => {
+                    let mut added = 0;
+                    for (field, value) in pairs {
+                        if hash.insert(field, value).is_none() {
+                            added += 1;
+                        }
+                    }
+                    Frame::Integer(added)
+                }
+                _ => wrong_type(),
+            }
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `HGET key field` replies with the value of `field`, or nil.
+#[derive(Debug)]
+pub struct HGet {
+    pub key: Bytes,
+    pub field: Bytes,
+}
+
+impl HGet {
+    pub fn new(key: impl AsRef<[u8]>, field: Bytes) -> HGet {
+        HGet {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            field,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<HGet> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let field = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(HGet { key, field })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("hget".to_string()),
+            Frame::Binary(self.key),
+            Frame::Binary(self.field),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let field = self.field;
+        let response = lookup(db, dst, self.key, |value| match value {
+            Some(Value::Hash(hash)) => match hash.get(&field) {
+                Some(value) => Frame::Binary(value.clone()),
+                None => Frame::Null,
+            },
+            Some(_) => wrong_type(),
+            None => Frame::Null,
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `HDEL key field [field ...]` removes fields from the hash at `key`, and the key
+/// itself once no field is left. Replies with the number of fields removed.
+#[derive(Debug)]
+pub struct HDel {
+    pub key: Bytes,
+    pub fields: Vec<Bytes>,
+}
+
+impl HDel {
+    pub fn new(key: impl AsRef<[u8]>, fields: Vec<Bytes>) -> HDel {
+        HDel {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            fields,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<HDel> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let mut fields = vec![];
+        while let Some(field) = parser.next_bytes()? {
+            fields.push(field);
+        }
+        if fields.is_empty() {
+            Err(CommandParseError::UnexpectedEOF)?
+        }
+        Ok(HDel { key, fields })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("hdel".to_string()), Frame::Binary(self.key)];
+        frame.extend(self.fields.into_iter().map(Frame::Binary));
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let HDel { key, fields } = self;
+        let response = db.update(key, |value| match value {
+            Some(Value::Hash(hash)) => {
+                let removed = fields
+                    .iter()
+                    .filter(|field| hash.remove(*field).is_some())
+                    .count();
+                if hash.is_empty() {
+                    *value = None;
+                }
+                Frame::Integer(removed as i64)
+            }
+            Some(_) => wrong_type(),
+            None => Frame::Integer(0),
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `HGETALL key` replies with every field of the hash at `key` followed by its
+/// value, in a flat array.
+#[derive(Debug)]
+pub struct HGetAll {
+    pub key: Bytes,
+}
+
+impl HGetAll {
+    pub fn new(key: impl AsRef<[u8]>) -> HGetAll {
+        HGetAll {
+            key: Bytes::copy_from_slice(key.as_ref()),
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<HGetAll> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(HGetAll { key })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![Frame::Text("hgetall".to_string()), Frame::Binary(self.key)];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let response = lookup(db, dst, self.key, |value| match value {
+            Some(Value::Hash(hash)) => {
+                let mut pairs = Vec::with_capacity(hash.len() * 2);
+                for (field, value) in hash {
+                    pairs.push(Frame::Binary(field.clone()));
+                    pairs.push(Frame::Binary(value.clone()));
+                }
+                Frame::Array(pairs)
+            }
+            Some(_) => wrong_type(),
+            None => Frame::Array(vec![]),
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
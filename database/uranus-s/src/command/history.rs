@@ -0,0 +1,128 @@
+//! Commands on the history of values, see [`History`](crate::History)
+//!
+
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{CommandParseError, CommandParser};
+use crate::{Connection, Database, ErrorCode, Frame, Shared, Value};
+
+/// `KEEPREVS key depth` keeps the last `depth` values written to `key` by `SET`,
+/// or stops keeping them with a depth of 0. Depth is capped by
+/// [`ServerConfig::max_history_depth`](crate::ServerConfig::max_history_depth).
+#[derive(Debug)]
+pub struct KeepRevs {
+    pub key: Bytes,
+    pub depth: usize,
+}
+
+impl KeepRevs {
+    pub fn new(key: impl AsRef<[u8]>, depth: usize) -> KeepRevs {
+        KeepRevs {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            depth,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<KeepRevs> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let depth = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<usize>()?;
+        Ok(KeepRevs { key, depth })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("keeprevs".to_string()),
+            Frame::Binary(self.key),
+            Frame::Text(self.depth.to_string()),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(
+        self,
+        db: &D,
+        dst: &mut Connection,
+        shared: &Shared,
+    ) -> Result<()> {
+        let max_depth = shared.history.max_depth();
+        let response = if self.depth > max_depth {
+            Frame::error(
+                ErrorCode::Err,
+                format!("depth exceeds the maximum of {}", max_depth),
+            )
+        } else {
+            let key = self.key;
+            let current = db.view(key.clone(), |value| match value {
+                Some(Value::String(value)) => Some(value.clone()),
+                _ => None,
+            })?;
+            shared.history.keep(key, self.depth, current);
+            Frame::Text("OK".to_string())
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `GETREV key back` replies with the value written to `key` `back` writes ago,
+/// 0 being the latest, followed by when it was written in milliseconds since
+/// the Unix epoch. Replies with nil if that revision isn't kept.
+#[derive(Debug)]
+pub struct GetRev {
+    pub key: Bytes,
+    pub back: usize,
+}
+
+impl GetRev {
+    pub fn new(key: impl AsRef<[u8]>, back: usize) -> GetRev {
+        GetRev {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            back,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<GetRev> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let back = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<usize>()?;
+        Ok(GetRev { key, back })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("getrev".to_string()),
+            Frame::Binary(self.key),
+            Frame::Text(self.back.to_string()),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply(self, dst: &mut Connection, shared: &Shared) -> Result<()> {
+        let revision = shared.history.get(&self.key, self.back);
+        dst.record_lookup(revision.is_some());
+        let response = match revision {
+            Some(revision) => {
+                let written_at = revision.written_at.duration_since(UNIX_EPOCH)?;
+                Frame::Array(vec![
+                    Frame::Binary(revision.value),
+                    Frame::Integer(written_at.as_millis() as i64),
+                ])
+            }
+            None => Frame::Null,
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+//! Commands on HyperLogLogs, see [`uranus_kv::hyperloglog`]
+//!
+//! A HyperLogLog is stored as a string, so `GET` and `SET` can copy it around.
+//! Any other string is refused.
+//!
+
+use anyhow::Result;
+use bytes::Bytes;
+use uranus_kv::HyperLogLog;
+
+use super::{CommandParseError, CommandParser};
+use crate::{Connection, Database, ErrorCode, Frame, Value};
+
+/// `PFADD key [element ...]` counts the elements in the HyperLogLog at `key`,
+/// creating it if needed. Replies with 1 if the estimate may have changed, 0
+/// otherwise.
+#[derive(Debug)]
+pub struct PfAdd {
+    pub key: Bytes,
+    pub elements: Vec<Bytes>,
+}
+
+impl PfAdd {
+    pub fn new(key: impl AsRef<[u8]>, elements: Vec<Bytes>) -> PfAdd {
+        PfAdd {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            elements,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<PfAdd> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let mut elements = vec![];
+        while let Some(element) = parser.next_bytes()? {
+            elements.push(element);
+        }
+        Ok(PfAdd { key, elements })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("pfadd".to_string()), Frame::Binary(self.key)];
+        frame.extend(self.elements.into_iter().map(Frame::Binary));
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let elements = self.elements;
+        let response = db.update(self.key, |value| {
+            let (mut hll, mut changed) = match value.as_ref() {
+                Some(value) => match decode(value) {
+                    Some(hll) => (hll, false),
+                    None => return invalid(),
+                },
+                None => (HyperLogLog::new(), true),
+            };
+            for element in &elements {
+                changed |= hll.add(element);
+            }
+            if changed {
+                *value = Some(Value::String(hll.to_bytes()));
+            }
+            Frame::Integer(changed as i64)
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `PFCOUNT key [key ...]` replies with the estimated number of distinct
+/// elements counted by the HyperLogLogs at the keys together. Missing keys
+/// count nothing.
+#[derive(Debug)]
+pub struct PfCount {
+    pub keys: Vec<Bytes>,
+}
+
+impl PfCount {
+    pub fn new(keys: Vec<Bytes>) -> PfCount {
+        PfCount { keys }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<PfCount> {
+        let mut keys = vec![];
+        while let Some(key) = parser.next_bytes()? {
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            Err(CommandParseError::UnexpectedEOF)?
+        }
+        Ok(PfCount { keys })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("pfcount".to_string())];
+        frame.extend(self.keys.into_iter().map(Frame::Binary));
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let response = match union(db, self.keys, |hit| dst.record_lookup(hit))? {
+            Some(hll) => Frame::Integer(hll.count() as i64),
+            None => invalid(),
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `PFMERGE dst [src ...]` makes the HyperLogLog at `dst` count the elements of
+/// those at the sources too, creating it if needed.
+#[derive(Debug)]
+pub struct PfMerge {
+    pub dst: Bytes,
+    pub sources: Vec<Bytes>,
+}
+
+impl PfMerge {
+    pub fn new(dst: impl AsRef<[u8]>, sources: Vec<Bytes>) -> PfMerge {
+        PfMerge {
+            dst: Bytes::copy_from_slice(dst.as_ref()),
+            sources,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<PfMerge> {
+        let dst = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let mut sources = vec![];
+        while let Some(source) = parser.next_bytes()? {
+            sources.push(source);
+        }
+        Ok(PfMerge { dst, sources })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("pfmerge".to_string()), Frame::Binary(self.dst)];
+        frame.extend(self.sources.into_iter().map(Frame::Binary));
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let response = match union(db, self.sources, |_| {})? {
+            Some(sources) => db.update(self.dst, |value| {
+                let mut hll = match value.as_ref() {
+                    Some(value) => match decode(value) {
+                        Some(hll) => hll,
+                        None => return invalid(),
+                    },
+                    None => HyperLogLog::new(),
+                };
+                hll.merge(&sources);
+                *value = Some(Value::String(hll.to_bytes()));
+                Frame::Text("OK".to_string())
+            })?,
+            None => invalid(),
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+fn decode(value: &Value) -> Option<HyperLogLog> {
+    match value {
+        Value::String(string) => HyperLogLog::from_bytes(string),
+        _ => None,
+    }
+}
+
+/// Merges the HyperLogLogs at `keys`, none if one of them isn't a HyperLogLog.
+/// `lookup` is told whether each key read was found.
+fn union<D: Database>(
+    db: &D,
+    keys: Vec<Bytes>,
+    mut lookup: impl FnMut(bool),
+) -> Result<Option<HyperLogLog>> {
+    let mut union = HyperLogLog::new();
+    for key in keys {
+        let merged = db.view(key, |value| {
+            lookup(value.is_some());
+            match value.map(decode) {
+                Some(Some(hll)) => {
+                    union.merge(&hll);
+                    true
+                }
+                Some(None) => false,
+                None => true,
+            }
+        })?;
+        if !merged {
+            return Ok(None);
+        }
+    }
+    Ok(Some(union))
+}
+
+fn invalid() -> Frame {
+    Frame::error(
+        ErrorCode::WrongType,
+        "Key is not a valid HyperLogLog string value.",
+    )
+}
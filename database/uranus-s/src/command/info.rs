@@ -0,0 +1,200 @@
+//! Introspection of the server
+//!
+
+use std::{fmt::Write, time::UNIX_EPOCH};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::CommandParser;
+use crate::{Connection, Database, Frame, Shared};
+
+/// `INFO [section]` replies with statistics of the server, as `field:value`
+/// lines grouped under `# Section` headers, all of them if no section is
+/// given. The sections are:
+///
+/// - `memory`: `used_memory`, the approximate size of the keys in bytes,
+///   `maxmemory` and `maxmemory_policy`, then `value_compression`,
+///   `compressed_values`, the number of values compressed so far, and
+///   `compression_ratio`, how many times smaller they got. Last
+///   `lazyfree_pending_objects` and `lazyfreed_objects`, the values waiting
+///   to be freed in the background and those freed so far, see
+///   [`crate::lazyfree`].
+/// - `stats`: `evicted_keys` and `expired_keys`, the number of keys evicted
+///   and expired so far, `keyspace_hits` and `keyspace_misses`, how many keys
+///   read commands found or not, and the [samples](crate::stats) of these,
+///   the oldest first, as `sample<n>:at=<unix ms>,keys=<n>,hits=<n>,...`.
+/// - `persistence`: `loading`, whether the server is still loading its data,
+///   and while it is, `loading_loaded_bytes`, `loading_total_bytes` and
+///   `loading_loaded_perc`, how far it got. Then `aof_enabled`, and if it is,
+///   `aof_rewrite_in_progress`, `aof_current_size` and `aof_base_size`, the
+///   size of the append-only file after the last rewrite. With the LSM engine,
+///   `sst_blocks_written`, `sst_blocks_read`, `sst_compression_ratio` and
+///   `sst_read_amplification`, see [`TableStats`](crate::TableStats), and
+///   `wal_fsync`, the policy syncing its log, and `wal_fsyncs`, how many
+///   times it was synced so far.
+/// - `commandstats`: for each command called so far,
+///   `cmdstat_<name>:calls=<n>,usec=<n>,usec_per_call=<n>,rejected_calls=<n>,failed_calls=<n>`,
+///   see [`crate::commandstats`].
+/// - `latencystats`: for each command called so far,
+///   `latency_percentiles_usec_<name>:p50=<n>,p99=<n>,p99.9=<n>`.
+///
+/// As in Redis, the last two are long, and only replied with when asked for,
+/// or for `all` and `everything`.
+#[derive(Debug, Default)]
+pub struct Info {
+    pub section: Option<String>,
+}
+
+impl Info {
+    pub fn new() -> Info {
+        Info::default()
+    }
+
+    /// Only replies with `section`.
+    pub fn section(section: impl ToString) -> Info {
+        Info {
+            section: Some(section.to_string()),
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Info> {
+        let section = parser.next_string()?;
+        Ok(Info { section })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("info".to_string())];
+        if let Some(section) = self.section {
+            frame.push(Frame::Text(section));
+        }
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(
+        self,
+        db: &D,
+        dst: &mut Connection,
+        shared: &Shared,
+    ) -> Result<()> {
+        let section = self.section.map(|section| section.to_lowercase());
+        let wanted = |name: &str| match section.as_deref() {
+            None | Some("all") | Some("everything") | Some("default") => true,
+            Some(section) => section == name,
+        };
+        let asked = |name: &str| match section.as_deref() {
+            Some("all") | Some("everything") => true,
+            section => section == Some(name),
+        };
+        let mut info = String::new();
+        let limit = db.memory_limit();
+        if wanted("memory") {
+            info.push_str("# Memory\r\n");
+            if let Some(limit) = &limit {
+                write!(info, "used_memory:{}\r\n", limit.used())?;
+                write!(info, "maxmemory:{}\r\n", limit.max())?;
+                write!(info, "maxmemory_policy:{}\r\n", limit.policy())?;
+            }
+            if let Some(compression) = db.value_compression() {
+                let ratio = compression.ratio();
+                write!(info, "value_compression:{}\r\n", compression.compression())?;
+                write!(info, "compressed_values:{}\r\n", compression.compressed())?;
+                write!(info, "compression_ratio:{:.2}\r\n", ratio)?;
+            }
+            let lazyfree = &shared.lazyfree;
+            write!(info, "lazyfree_pending_objects:{}\r\n", lazyfree.pending())?;
+            write!(info, "lazyfreed_objects:{}\r\n", lazyfree.freed())?;
+        }
+        if wanted("stats") {
+            info.push_str("# Stats\r\n");
+            let evicted = limit.map_or(0, |limit| limit.evicted());
+            write!(info, "evicted_keys:{}\r\n", evicted)?;
+            write!(info, "expired_keys:{}\r\n", shared.stats.expired())?;
+            write!(info, "keyspace_hits:{}\r\n", shared.stats.hits())?;
+            write!(info, "keyspace_misses:{}\r\n", shared.stats.misses())?;
+            for (n, sample) in shared.stats.samples().iter().enumerate() {
+                let at = sample.taken_at.duration_since(UNIX_EPOCH)?.as_millis();
+                write!(
+                    info,
+                    "sample{}:at={},keys={},hits={},misses={},expired={},evicted={}\r\n",
+                    n, at, sample.keys, sample.hits, sample.misses, sample.expired, sample.evicted
+                )?;
+            }
+        }
+        if wanted("persistence") {
+            info.push_str("# Persistence\r\n");
+            let progress = shared.readiness.progress();
+            write!(info, "loading:{}\r\n", progress.is_some() as u8)?;
+            if let Some((loaded, total)) = progress {
+                let percent = match total {
+                    0 => 0.0,
+                    total => loaded as f64 * 100.0 / total as f64,
+                };
+                write!(info, "loading_loaded_bytes:{}\r\n", loaded)?;
+                write!(info, "loading_total_bytes:{}\r\n", total)?;
+                write!(info, "loading_loaded_perc:{:.2}\r\n", percent)?;
+            }
+            write!(info, "aof_enabled:{}\r\n", shared.aof.is_some() as u8)?;
+            if let Some(aof) = &shared.aof {
+                let (size, base_size) = aof.sizes();
+                write!(
+                    info,
+                    "aof_rewrite_in_progress:{}\r\n",
+                    aof.is_rewriting() as u8
+                )?;
+                write!(info, "aof_current_size:{}\r\n", size)?;
+                write!(info, "aof_base_size:{}\r\n", base_size)?;
+            }
+            if let Some(stats) = db.table_stats() {
+                write!(info, "sst_blocks_written:{}\r\n", stats.blocks_written)?;
+                write!(info, "sst_blocks_read:{}\r\n", stats.blocks_read)?;
+                let ratio = stats.compression_ratio();
+                write!(info, "sst_compression_ratio:{:.2}\r\n", ratio)?;
+                let amplification = stats.read_amplification();
+                write!(info, "sst_read_amplification:{:.2}\r\n", amplification)?;
+            }
+            if let Some(wal_sync) = db.wal_sync() {
+                write!(info, "wal_fsync:{}\r\n", wal_sync.get())?;
+                write!(info, "wal_fsyncs:{}\r\n", wal_sync.syncs())?;
+            }
+        }
+        let commands = if asked("commandstats") || asked("latencystats") {
+            shared.commands.snapshot()
+        } else {
+            vec![]
+        };
+        if asked("commandstats") {
+            info.push_str("# Commandstats\r\n");
+            for stat in &commands {
+                write!(
+                    info,
+                    "cmdstat_{}:calls={},usec={},usec_per_call={:.2},rejected_calls={},failed_calls={}\r\n",
+                    stat.name,
+                    stat.calls,
+                    stat.usec,
+                    stat.usec_per_call(),
+                    stat.rejected,
+                    stat.failed
+                )?;
+            }
+        }
+        if asked("latencystats") {
+            info.push_str("# Latencystats\r\n");
+            for stat in commands.iter().filter(|stat| stat.calls > 0) {
+                let percentiles: Vec<String> = stat
+                    .percentiles
+                    .iter()
+                    .map(|(percentile, usec)| format!("p{}={}", percentile, usec))
+                    .collect();
+                write!(
+                    info,
+                    "latency_percentiles_usec_{}:{}\r\n",
+                    stat.name,
+                    percentiles.join(",")
+                )?;
+            }
+        }
+        dst.write_frame(&Frame::Binary(Bytes::from(info))).await?;
+        Ok(())
+    }
+}
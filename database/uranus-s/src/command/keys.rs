@@ -0,0 +1,184 @@
+//! Listing the keys of a database
+//!
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{CommandParseError, CommandParser, YIELD_EVERY};
+use crate::{glob_match, literal_prefix, Connection, Database, Frame};
+
+/// `KEYS pattern` replies with every key matching the glob `pattern`, in
+/// order. See [`glob`](crate::glob) for the syntax. Only the keys starting
+/// with the [literal prefix](literal_prefix) of `pattern` are matched, which
+/// the storage may enumerate without going through the others.
+#[derive(Debug)]
+pub struct Keys {
+    pub pattern: String,
+}
+
+impl Keys {
+    pub fn new(pattern: impl ToString) -> Keys {
+        Keys {
+            pattern: pattern.to_string(),
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Keys> {
+        let pattern = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(Keys { pattern })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![Frame::Text("keys".to_string()), Frame::Text(self.pattern)];
+        Frame::Array(frame)
+    }
+
+    /// Matches the keys by chunks, yielding in between.
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let keys = db.keys_with_prefix(&literal_prefix(self.pattern.as_bytes()))?;
+        let mut matched = vec![];
+        for chunk in keys.chunks(YIELD_EVERY) {
+            let chunk = chunk
+                .iter()
+                .filter(|key| glob_match(self.pattern.as_bytes(), key));
+            matched.extend(chunk.cloned().map(Frame::Binary));
+            tokio::task::yield_now().await;
+        }
+        dst.write_frame(&Frame::Array(matched)).await?;
+        Ok(())
+    }
+}
+
+/// `SCAN cursor [MATCH pattern] [COUNT count]` iterates over the keys, `count`
+/// of them at a time, 10 by default. Start from cursor 0, then pass the
+/// cursor of each reply to the next call, until it is 0 again.
+///
+/// Replies with a flat `[cursor, key, ...]` array. Keys not matching `pattern`
+/// are skipped but count toward `count`, so a reply may hold fewer keys, or
+/// none. Only the keys starting with the literal prefix of `pattern` are
+/// gone through, as for [`Keys`].
+///
+/// As in Redis, a full iteration returns every key present from its start to
+/// its end at least once, whatever is added or removed meanwhile. Keys added
+/// or removed during it may be returned or not. The keys are ordered by their
+/// hash with its bits reversed, and the cursor is the hash of the next one, so
+/// it doesn't depend on the other keys: Redis' reverse binary iteration over
+/// its hash table, with one bucket per hash. Hashes are 64 bits, so keys
+/// sharing one are rare, and returned in the same reply.
+#[derive(Debug)]
+pub struct Scan {
+    pub cursor: u64,
+    pub pattern: Option<String>,
+    pub count: usize,
+}
+
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+impl Scan {
+    pub fn new(cursor: u64) -> Scan {
+        Scan {
+            cursor,
+            pattern: None,
+            count: DEFAULT_SCAN_COUNT,
+        }
+    }
+
+    /// Only returns the keys matching the glob `pattern`.
+    pub fn matching(mut self, pattern: impl ToString) -> Scan {
+        self.pattern = Some(pattern.to_string());
+        self
+    }
+
+    pub fn with_count(mut self, count: usize) -> Scan {
+        self.count = count;
+        self
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Scan> {
+        let cursor = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<u64>()?;
+        let mut scan = Scan::new(cursor);
+        while let Some(option) = parser.next_string()? {
+            match option.to_lowercase().as_str() {
+                "match" => {
+                    let pattern = parser
+                        .next_string()?
+                        .ok_or(CommandParseError::UnexpectedEOF)?;
+                    scan.pattern = Some(pattern);
+                }
+                "count" => {
+                    scan.count = parser
+                        .next_string()?
+                        .ok_or(CommandParseError::UnexpectedEOF)?
+                        .parse::<usize>()?;
+                }
+                _ => Err(CommandParseError::UnknownOption(option))?,
+            }
+        }
+        Ok(scan)
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![
+            Frame::Text("scan".to_string()),
+            Frame::Text(self.cursor.to_string()),
+        ];
+        if let Some(pattern) = self.pattern {
+            frame.push(Frame::Text("match".to_string()));
+            frame.push(Frame::Text(pattern));
+        }
+        frame.push(Frame::Text("count".to_string()));
+        frame.push(Frame::Text(self.count.to_string()));
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let prefix = match &self.pattern {
+            Some(pattern) => literal_prefix(pattern.as_bytes()),
+            None => vec![],
+        };
+        let start = self.cursor.reverse_bits();
+        let mut keys: Vec<(u64, Bytes)> = (db.keys_with_prefix(&prefix)?.into_iter())
+            .map(|key| (scan_position(&key), key))
+            .filter(|(position, _)| *position >= start)
+            .collect();
+        keys.sort_unstable();
+        let mut end = self.count.max(1).min(keys.len());
+        while end < keys.len() && keys[end].0 == keys[end - 1].0 {
+            end += 1;
+        }
+        let next = match keys.get(end) {
+            Some((position, _)) => position.reverse_bits(),
+            None => 0,
+        };
+        let mut response = vec![Frame::Text(next.to_string())];
+        response.extend(
+            keys.into_iter()
+                .take(end)
+                .map(|(_, key)| key)
+                .filter(|key| match &self.pattern {
+                    Some(pattern) => glob_match(pattern.as_bytes(), key),
+                    None => true,
+                })
+                .map(Frame::Binary),
+        );
+        dst.write_frame(&Frame::Array(response)).await?;
+        Ok(())
+    }
+}
+
+/// Where `key` comes in a [`Scan`]: its hash, bits reversed.
+fn scan_position(key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish().reverse_bits()
+}
@@ -0,0 +1,126 @@
+//! Commands on whole logical databases
+//!
+
+use anyhow::Result;
+
+use super::{CommandParseError, CommandParser, YIELD_EVERY};
+use crate::{Connection, Database, ErrorCode, Frame};
+
+/// `SELECT index` switches the connection to the logical database `index`.
+/// Connections start on database 0.
+#[derive(Debug)]
+pub struct Select {
+    pub index: usize,
+}
+
+impl Select {
+    pub fn new(index: usize) -> Select {
+        Select { index }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Select> {
+        let index = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<usize>()?;
+        Ok(Select { index })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("select".to_string()),
+            Frame::Text(self.index.to_string()),
+        ];
+        Frame::Array(frame)
+    }
+
+    /// Replaces `db`, the database handle of the connection, by the selected one.
+    pub async fn apply<D: Database>(self, db: &mut D, dst: &mut Connection) -> Result<()> {
+        let response = match db.select(self.index) {
+            Some(selected) => {
+                *db = selected;
+                Frame::Text("OK".to_string())
+            }
+            None => Frame::error(ErrorCode::Err, "DB index is out of range"),
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `DBSIZE` replies with the number of keys in the selected database.
+#[derive(Debug, Default)]
+pub struct DbSize;
+
+impl DbSize {
+    pub fn new() -> DbSize {
+        DbSize
+    }
+
+    pub fn parse_frames(_parser: &mut CommandParser) -> Result<DbSize> {
+        Ok(DbSize)
+    }
+
+    pub fn into_frame(self) -> Frame {
+        Frame::Array(vec![Frame::Text("dbsize".to_string())])
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let keys = db.len()?;
+        dst.write_frame(&Frame::Integer(keys as i64)).await?;
+        Ok(())
+    }
+}
+
+/// `FLUSHDB` removes every key of the selected database, `FLUSHALL` of all of them.
+///
+/// Keys are removed by chunks, yielding in between, so that flushing a large
+/// database doesn't hold up other connections. Keys written meanwhile are
+/// removed too.
+#[derive(Debug)]
+pub struct Flush {
+    pub all: bool,
+}
+
+impl Flush {
+    pub fn new(all: bool) -> Flush {
+        Flush { all }
+    }
+
+    pub fn parse_frames(_parser: &mut CommandParser, all: bool) -> Result<Flush> {
+        Ok(Flush { all })
+    }
+
+    pub fn name(&self) -> &'static str {
+        if self.all {
+            "flushall"
+        } else {
+            "flushdb"
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        Frame::Array(vec![Frame::Text(self.name().to_string())])
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let databases = if self.all {
+            (0..db.databases())
+                .filter_map(|index| db.select(index))
+                .collect()
+        } else {
+            vec![db.clone()]
+        };
+        for db in databases {
+            for chunk in db.keys()?.chunks(YIELD_EVERY) {
+                for key in chunk {
+                    db.update(key.clone(), |value| *value = None)?;
+                }
+                tokio::task::yield_now().await;
+            }
+            db.flush()?;
+        }
+        dst.write_frame(&Frame::Text("OK".to_string())).await?;
+        Ok(())
+    }
+}
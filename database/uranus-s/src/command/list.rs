@@ -0,0 +1,345 @@
+//! Commands on list values, sequences of elements pushed and popped at both ends
+//!
+
+use std::{collections::VecDeque, time::Duration};
+
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::time::{self, Instant};
+
+use super::{lookup, wrong_type, CommandParseError, CommandParser};
+use crate::{format_double, parse_double, Connection, Database, ErrorCode, Frame, Shared, Value};
+
+/// The end of a list elements are pushed to or popped from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+/// `LPUSH key element [element ...]` and `RPUSH key element [element ...]` push
+/// elements at an end of the list at `key`, creating it if needed. Replies with
+/// the length of the list.
+#[derive(Debug)]
+pub struct Push {
+    pub key: Bytes,
+    pub end: ListEnd,
+    pub elements: Vec<Bytes>,
+}
+
+impl Push {
+    pub fn new(key: impl AsRef<[u8]>, end: ListEnd, elements: Vec<Bytes>) -> Push {
+        Push {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            end,
+            elements,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser, end: ListEnd) -> Result<Push> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let mut elements = vec![];
+        while let Some(element) = parser.next_bytes()? {
+            elements.push(element);
+        }
+        if elements.is_empty() {
+            Err(CommandParseError::UnexpectedEOF)?
+        }
+        Ok(Push { key, end, elements })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.end {
+            ListEnd::Left => "lpush",
+            ListEnd::Right => "rpush",
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![
+            Frame::Text(self.name().to_string()),
+            Frame::Binary(self.key),
+        ];
+        frame.extend(self.elements.into_iter().map(Frame::Binary));
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(
+        self,
+        db: &D,
+        dst: &mut Connection,
+        shared: &Shared,
+    ) -> Result<()> {
+        let Push { key, end, elements } = self;
+        let pushed = elements.len();
+        let response = db.update(key.clone(), |value| {
+            match value.get_or_insert_with(|| Value::List(VecDeque::new())) {
+                Value::List(list) => {
+                    for element in elements {
+                        match end {
+                            ListEnd::Left => list.push_front(element),
+                            ListEnd::Right => list.push_back(element),
+                        }
+                    }
+                    Frame::Integer(list.len() as i64)
+                }
+                _ => wrong_type(),
+            }
+        })?;
+        shared.waiters.wake(&key, pushed);
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `LPOP key` and `RPOP key` take the element at an end of the list at `key`,
+/// or reply with nil if there is none. The key is removed with its last element.
+#[derive(Debug)]
+pub struct Pop {
+    pub key: Bytes,
+    pub end: ListEnd,
+}
+
+impl Pop {
+    pub fn new(key: impl AsRef<[u8]>, end: ListEnd) -> Pop {
+        Pop {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            end,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser, end: ListEnd) -> Result<Pop> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(Pop { key, end })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.end {
+            ListEnd::Left => "lpop",
+            ListEnd::Right => "rpop",
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text(self.name().to_string()),
+            Frame::Binary(self.key),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let response = match pop(db, self.key, self.end)? {
+            Some(Ok(element)) => Frame::Binary(element),
+            Some(Err(wrong_type)) => wrong_type,
+            None => Frame::Null,
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `BLPOP key timeout` takes the first element of the list at `key`, waiting up
+/// to `timeout` seconds for one to be pushed if the list is empty, or forever
+/// with a timeout of 0. Replies with `[key, element]`, or nil once the timeout
+/// is over. Connections blocked on the same key are served in arrival order.
+#[derive(Debug)]
+pub struct BLPop {
+    pub key: Bytes,
+    /// None to wait forever.
+    pub timeout: Option<Duration>,
+}
+
+impl BLPop {
+    pub fn new(key: impl AsRef<[u8]>, timeout: Option<Duration>) -> BLPop {
+        BLPop {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            timeout,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<BLPop> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let timeout = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let timeout = Duration::try_from_secs_f64(parse_double(&timeout)?)?;
+        let timeout = (!timeout.is_zero()).then_some(timeout);
+        Ok(BLPop { key, timeout })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let timeout = self.timeout.unwrap_or_default().as_secs_f64();
+        let frame = vec![
+            Frame::Text("blpop".to_string()),
+            Frame::Binary(self.key),
+            Frame::Text(format_double(timeout)),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(
+        self,
+        db: &D,
+        dst: &mut Connection,
+        shared: &Shared,
+    ) -> Result<()> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let key = self.key;
+        let waiter = shared.waiters.register(key.clone());
+        let response = loop {
+            let pushed = waiter.listen();
+            match pop(db, key.clone(), ListEnd::Left)? {
+                Some(Ok(element)) => {
+                    break Frame::Array(vec![Frame::Binary(key), Frame::Binary(element)])
+                }
+                Some(Err(wrong_type)) => break wrong_type,
+                None => {}
+            }
+            match deadline {
+                Some(deadline) => {
+                    if time::timeout_at(deadline, pushed).await.is_err() {
+                        break Frame::Null;
+                    }
+                }
+                None => pushed.await,
+            }
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `LPOS key element [RANK rank] [COUNT count] [MAXLEN len]` replies with the
+/// index of `element` in the list at `key`, nil if it isn't there. With a
+/// `rank` of n, the search skips the first n - 1 matches, and a negative one
+/// searches from the tail. With `COUNT`, it replies with the indexes of up to
+/// `count` matches, of all of them for 0. With `MAXLEN`, it only looks at
+/// that many elements.
+#[derive(Debug)]
+pub struct LPos {
+    pub key: Bytes,
+    pub element: Bytes,
+    pub rank: i64,
+    pub count: Option<usize>,
+    /// 0 to look at the whole list.
+    pub max_len: usize,
+}
+
+impl LPos {
+    pub fn new(key: impl AsRef<[u8]>, element: Bytes) -> LPos {
+        LPos {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            element,
+            rank: 1,
+            count: None,
+            max_len: 0,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<LPos> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let element = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let mut lpos = LPos::new(key, element);
+        while let Some(option) = parser.next_string()? {
+            let amount = parser
+                .next_string()?
+                .ok_or(CommandParseError::UnexpectedEOF)?;
+            match option.to_lowercase().as_str() {
+                "rank" => lpos.rank = amount.parse()?,
+                "count" => lpos.count = Some(amount.parse()?),
+                "maxlen" => lpos.max_len = amount.parse()?,
+                _ => Err(CommandParseError::UnknownOption(option))?,
+            }
+        }
+        Ok(lpos)
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![
+            Frame::Text("lpos".to_string()),
+            Frame::Binary(self.key),
+            Frame::Binary(self.element),
+            Frame::Text("rank".to_string()),
+            Frame::Text(self.rank.to_string()),
+        ];
+        if let Some(count) = self.count {
+            frame.push(Frame::Text("count".to_string()));
+            frame.push(Frame::Text(count.to_string()));
+        }
+        frame.push(Frame::Text("maxlen".to_string()));
+        frame.push(Frame::Text(self.max_len.to_string()));
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let LPos {
+            key,
+            element,
+            rank,
+            count,
+            max_len,
+        } = self;
+        if rank == 0 {
+            let reply = "RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list";
+            dst.write_frame(&Frame::error(ErrorCode::Err, reply))
+                .await?;
+            return Ok(());
+        }
+        let response = lookup(db, dst, key, |value| {
+            let list = match value {
+                Some(Value::List(list)) => list,
+                Some(_) => return wrong_type(),
+                None if count.is_some() => return Frame::Array(vec![]),
+                None => return Frame::Null,
+            };
+            let max_len = if max_len == 0 { list.len() } else { max_len };
+            let indexes = list.iter().enumerate();
+            let indexes: Box<dyn Iterator<Item = (usize, &Bytes)>> = if rank > 0 {
+                Box::new(indexes.take(max_len))
+            } else {
+                Box::new(indexes.rev().take(max_len))
+            };
+            let mut matches = indexes
+                .filter(|(_, candidate)| **candidate == element)
+                .map(|(index, _)| Frame::Integer(index as i64))
+                .skip(rank.unsigned_abs() as usize - 1);
+            match count {
+                Some(0) => Frame::Array(matches.collect()),
+                Some(count) => Frame::Array(matches.take(count).collect()),
+                None => matches.next().unwrap_or(Frame::Null),
+            }
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// Pops an element from the list at `key`, none if there is no list, or the
+/// error to reply if `key` holds another type.
+fn pop<D: Database>(db: &D, key: Bytes, end: ListEnd) -> Result<Option<Result<Bytes, Frame>>> {
+    db.update(key, |value| match value {
+        Some(Value::List(list)) => {
+            let element = match end {
+                ListEnd::Left => list.pop_front(),
+                ListEnd::Right => list.pop_back(),
+            };
+            if list.is_empty() {
+                *value = None;
+            }
+            element.map(Ok)
+        }
+        Some(_) => Some(Err(wrong_type())),
+        None => None,
+    })
+}
@@ -0,0 +1,78 @@
+//! Inspecting the bookkeeping of keys
+//!
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{CommandParseError, CommandParser};
+use crate::{Connection, Database, ErrorCode, EvictionPolicy, Frame};
+
+/// Subcommands of `OBJECT`:
+///
+/// - `OBJECT FREQ key` replies with the access frequency of `key`, the
+///   logarithmic counter `allkeys-lfu` evicts by, see
+///   [`Storage::frequency`](uranus_kv::Storage::frequency), or null if there
+///   is no such key. It fails unless that policy is in effect, as the counters
+///   are stale otherwise. Reading it doesn't count as a use of the key.
+#[derive(Debug)]
+pub enum ObjectCommand {
+    Freq(Bytes),
+}
+
+impl ObjectCommand {
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<ObjectCommand> {
+        let subcommand = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .to_lowercase();
+        match subcommand.as_str() {
+            "freq" => {
+                let key = parser
+                    .next_bytes()?
+                    .ok_or(CommandParseError::UnexpectedEOF)?;
+                Ok(ObjectCommand::Freq(key))
+            }
+            _ => Err(CommandParseError::UnknownCommand)?,
+        }
+    }
+
+    pub fn key(&self) -> &[u8] {
+        match self {
+            ObjectCommand::Freq(key) => key,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("object".to_string())];
+        match self {
+            ObjectCommand::Freq(key) => {
+                frame.push(Frame::Text("freq".to_string()));
+                frame.push(Frame::Binary(key));
+            }
+        }
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let response = match self {
+            ObjectCommand::Freq(key) => {
+                let policy = db.memory_limit().map(|limit| limit.policy());
+                if policy != Some(EvictionPolicy::Lfu) {
+                    Frame::error(
+                        ErrorCode::Err,
+                        "An LFU maxmemory policy is not selected, access frequency not tracked",
+                    )
+                } else {
+                    let frequency = db.frequency(key)?;
+                    dst.record_lookup(frequency.is_some());
+                    match frequency {
+                        Some(frequency) => Frame::Integer(frequency as i64),
+                        None => Frame::Null,
+                    }
+                }
+            }
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,278 @@
+//! Publish/subscribe commands
+//!
+//! Once a connection subscribes to something, it only listens to messages, and
+//! only accepts commands changing its subscriptions, until none is left.
+//!
+
+use std::collections::HashSet;
+
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use super::{Command, CommandParseError, CommandParser};
+use crate::{glob_match, Connection, ErrorCode, Frame, Message, Shared};
+
+/// `PUBLISH channel message` replies with the number of subscribed connections.
+#[derive(Debug)]
+pub struct Publish {
+    pub channel: String,
+    pub message: Bytes,
+}
+
+impl Publish {
+    pub fn new(channel: impl ToString, message: Bytes) -> Publish {
+        Publish {
+            channel: channel.to_string(),
+            message,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Publish> {
+        let channel = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let message = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(Publish { channel, message })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("publish".to_string()),
+            Frame::Text(self.channel),
+            Frame::Binary(self.message),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply(self, dst: &mut Connection, shared: &Shared) -> Result<()> {
+        let receivers = shared.pubsub.publish(self.channel, self.message);
+        dst.write_frame(&Frame::Integer(receivers as i64)).await?;
+        Ok(())
+    }
+}
+
+/// `SUBSCRIBE channel [channel ...]`, or `PSUBSCRIBE pattern [pattern ...]` when
+/// `patterns` is set, listens to messages published on the given channels, or
+/// on the channels matching the given glob patterns.
+#[derive(Debug)]
+pub struct Subscribe {
+    pub channels: Vec<String>,
+    pub patterns: bool,
+}
+
+impl Subscribe {
+    pub fn channels(channels: Vec<String>) -> Subscribe {
+        Subscribe {
+            channels,
+            patterns: false,
+        }
+    }
+
+    pub fn patterns(patterns: Vec<String>) -> Subscribe {
+        Subscribe {
+            channels: patterns,
+            patterns: true,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser, patterns: bool) -> Result<Subscribe> {
+        let channels = parse_channels(parser)?;
+        if channels.is_empty() {
+            Err(CommandParseError::UnexpectedEOF)?
+        }
+        Ok(Subscribe { channels, patterns })
+    }
+
+    pub fn name(&self) -> &'static str {
+        if self.patterns {
+            "psubscribe"
+        } else {
+            "subscribe"
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text(self.name().to_string())];
+        frame.extend(self.channels.into_iter().map(Frame::Text));
+        Frame::Array(frame)
+    }
+
+    /// Serves the connection in subscribed mode, until it unsubscribes from
+    /// everything or goes away.
+    pub async fn apply(self, dst: &mut Connection, shared: &Shared) -> Result<()> {
+        let mut receiver = shared.pubsub.subscribe();
+        let mut subscriptions = Subscriptions::default();
+        for frame in subscriptions.subscribe(self) {
+            dst.write_frame(&frame).await?;
+        }
+
+        while !subscriptions.is_empty() {
+            tokio::select! {
+                message = receiver.recv() => match message {
+                    Ok(message) => {
+                        for frame in subscriptions.deliveries(&message) {
+                            dst.write_frame(&frame).await?;
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => warn!(skipped, "subscriber lagged behind"),
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                frame = dst.read_frame() => {
+                    let Some(frame) = frame? else {
+                        return Ok(());
+                    };
+                    let replies = match Command::from_frame(frame)? {
+                        Command::Subscribe(subscribe) => subscriptions.subscribe(subscribe),
+                        Command::Unsubscribe(unsubscribe) => subscriptions.unsubscribe(unsubscribe),
+                        command => vec![Frame::error(ErrorCode::Err, format!("'{}' is not allowed while subscribed",
+                            command.name()
+                        ))],
+                    };
+                    for frame in replies {
+                        dst.write_frame(&frame).await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `UNSUBSCRIBE [channel ...]`, or `PUNSUBSCRIBE [pattern ...]` when `patterns`
+/// is set, stops listening to the given channels or patterns, or to all of them
+/// if none is given.
+#[derive(Debug)]
+pub struct Unsubscribe {
+    pub channels: Vec<String>,
+    pub patterns: bool,
+}
+
+impl Unsubscribe {
+    pub fn parse_frames(parser: &mut CommandParser, patterns: bool) -> Result<Unsubscribe> {
+        let channels = parse_channels(parser)?;
+        Ok(Unsubscribe { channels, patterns })
+    }
+
+    pub fn name(&self) -> &'static str {
+        if self.patterns {
+            "punsubscribe"
+        } else {
+            "unsubscribe"
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text(self.name().to_string())];
+        frame.extend(self.channels.into_iter().map(Frame::Text));
+        Frame::Array(frame)
+    }
+
+    /// Outside subscribed mode there's nothing to unsubscribe from.
+    pub async fn apply(self, dst: &mut Connection) -> Result<()> {
+        for frame in Subscriptions::default().unsubscribe(self) {
+            dst.write_frame(&frame).await?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_channels(parser: &mut CommandParser) -> Result<Vec<String>> {
+    let mut channels = vec![];
+    while let Some(channel) = parser.next_string()? {
+        channels.push(channel);
+    }
+    Ok(channels)
+}
+
+/// The channels and patterns a connection listens to.
+#[derive(Debug, Default)]
+struct Subscriptions {
+    channels: HashSet<String>,
+    patterns: HashSet<String>,
+}
+
+impl Subscriptions {
+    fn is_empty(&self) -> bool {
+        self.channels.is_empty() && self.patterns.is_empty()
+    }
+
+    fn count(&self) -> i64 {
+        (self.channels.len() + self.patterns.len()) as i64
+    }
+
+    fn set(&mut self, patterns: bool) -> &mut HashSet<String> {
+        if patterns {
+            &mut self.patterns
+        } else {
+            &mut self.channels
+        }
+    }
+
+    /// Returns a confirmation for every new subscription.
+    fn subscribe(&mut self, subscribe: Subscribe) -> Vec<Frame> {
+        let name = subscribe.name();
+        let mut replies = vec![];
+        for channel in subscribe.channels {
+            self.set(subscribe.patterns).insert(channel.clone());
+            replies.push(self.confirmation(name, Frame::Text(channel)));
+        }
+        replies
+    }
+
+    /// Returns a confirmation for every subscription removed.
+    fn unsubscribe(&mut self, unsubscribe: Unsubscribe) -> Vec<Frame> {
+        let name = unsubscribe.name();
+        let set = self.set(unsubscribe.patterns);
+        let channels = if unsubscribe.channels.is_empty() {
+            set.drain().collect()
+        } else {
+            for channel in &unsubscribe.channels {
+                set.remove(channel);
+            }
+            unsubscribe.channels
+        };
+        if channels.is_empty() {
+            return vec![self.confirmation(name, Frame::Null)];
+        }
+        channels
+            .into_iter()
+            .map(|channel| self.confirmation(name, Frame::Text(channel)))
+            .collect()
+    }
+
+    fn confirmation(&self, kind: &str, channel: Frame) -> Frame {
+        Frame::Array(vec![
+            Frame::Text(kind.to_string()),
+            channel,
+            Frame::Integer(self.count()),
+        ])
+    }
+
+    /// Returns the frames delivering `message` to this connection: one if it
+    /// listens to the channel, and one per matching pattern.
+    fn deliveries(&self, message: &Message) -> Vec<Frame> {
+        let mut frames = vec![];
+        if self.channels.contains(&message.channel) {
+            frames.push(Frame::Array(vec![
+                Frame::Text("message".to_string()),
+                Frame::Text(message.channel.clone()),
+                Frame::Binary(message.payload.clone()),
+            ]));
+        }
+        for pattern in &self.patterns {
+            if glob_match(pattern.as_bytes(), message.channel.as_bytes()) {
+                frames.push(Frame::Array(vec![
+                    Frame::Text("pmessage".to_string()),
+                    Frame::Text(pattern.clone()),
+                    Frame::Text(message.channel.clone()),
+                    Frame::Binary(message.payload.clone()),
+                ]));
+            }
+        }
+        frames
+    }
+}
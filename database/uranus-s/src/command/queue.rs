@@ -0,0 +1,317 @@
+//! Commands on priority queue values, for job schedulers and the like
+//!
+//! Items are taken from the low (`min`) or the high (`max`) end of a queue, and
+//! each popped or peeked item is replied followed by its priority.
+//!
+
+use std::time::Duration;
+
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::time::{self, Instant};
+
+use super::{lookup, wrong_type, CommandParseError, CommandParser};
+use crate::{
+    format_double, parse_double, Connection, Database, Frame, PriorityQueue, Shared, Value,
+};
+
+/// The end of a queue an item is taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueEnd {
+    /// The item of the lowest priority.
+    Min,
+    /// The item of the highest priority.
+    Max,
+}
+
+impl QueueEnd {
+    fn pop(self, queue: &mut PriorityQueue) -> Option<(Bytes, f64)> {
+        match self {
+            QueueEnd::Min => queue.pop_min(),
+            QueueEnd::Max => queue.pop_max(),
+        }
+    }
+
+    fn peek(self, queue: &PriorityQueue) -> Option<(&Bytes, f64)> {
+        match self {
+            QueueEnd::Min => queue.peek_min(),
+            QueueEnd::Max => queue.peek_max(),
+        }
+    }
+}
+
+/// `PQADD key priority item [priority item ...]` pushes items on the queue at
+/// `key`, creating it if needed. Replies with the length of the queue.
+#[derive(Debug)]
+pub struct PqAdd {
+    pub key: Bytes,
+    pub items: Vec<(f64, Bytes)>,
+}
+
+impl PqAdd {
+    pub fn new(key: impl AsRef<[u8]>, items: Vec<(f64, Bytes)>) -> PqAdd {
+        PqAdd {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            items,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<PqAdd> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let mut items = vec![];
+        while let Some(priority) = parser.next_string()? {
+            let item = parser
+                .next_bytes()?
+                .ok_or(CommandParseError::UnexpectedEOF)?;
+            items.push((parse_double(&priority)?, item));
+        }
+        if items.is_empty() {
+            Err(CommandParseError::UnexpectedEOF)?
+        }
+        Ok(PqAdd { key, items })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("pqadd".to_string()), Frame::Binary(self.key)];
+        for (priority, item) in self.items {
+            frame.push(Frame::Text(format_double(priority)));
+            frame.push(Frame::Binary(item));
+        }
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(
+        self,
+        db: &D,
+        dst: &mut Connection,
+        shared: &Shared,
+    ) -> Result<()> {
+        let PqAdd { key, items } = self;
+        let pushed = items.len();
+        let response = db.update(key.clone(), |value| {
+            match value.get_or_insert_with(|| Value::Queue(PriorityQueue::new())) {
+                Value::Queue(queue) => {
+                    for (priority, item) in items {
+                        queue.push(priority, item);
+                    }
+                    Frame::Integer(queue.len() as i64)
+                }
+                _ => wrong_type(),
+            }
+        })?;
+        shared.waiters.wake(&key, pushed);
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `PQPOPMIN key [count]` and `PQPOPMAX key [count]` take up to `count` items,
+/// one by default, from an end of the queue at `key`. The key is removed with
+/// its last item.
+#[derive(Debug)]
+pub struct PqPop {
+    pub key: Bytes,
+    pub end: QueueEnd,
+    pub count: usize,
+}
+
+impl PqPop {
+    pub fn new(key: impl AsRef<[u8]>, end: QueueEnd, count: usize) -> PqPop {
+        PqPop {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            end,
+            count,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser, end: QueueEnd) -> Result<PqPop> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let count = match parser.next_string()? {
+            Some(count) => count.parse::<usize>()?,
+            None => 1,
+        };
+        Ok(PqPop { key, end, count })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.end {
+            QueueEnd::Min => "pqpopmin",
+            QueueEnd::Max => "pqpopmax",
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text(self.name().to_string()),
+            Frame::Binary(self.key),
+            Frame::Text(self.count.to_string()),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let response = pop(db, self.key, self.end, self.count)?;
+        dst.write_frame(&response.unwrap_or(Frame::Array(vec![])))
+            .await?;
+        Ok(())
+    }
+}
+
+/// `BPQPOPMIN key timeout` and `BPQPOPMAX key timeout` take one item from an end
+/// of the queue at `key`, waiting up to `timeout` seconds for one to be pushed
+/// if the queue is empty, or forever with a timeout of 0. Replies with nil
+/// once the timeout is over.
+#[derive(Debug)]
+pub struct BPqPop {
+    pub key: Bytes,
+    pub end: QueueEnd,
+    /// None to wait forever.
+    pub timeout: Option<Duration>,
+}
+
+impl BPqPop {
+    pub fn new(key: impl AsRef<[u8]>, end: QueueEnd, timeout: Option<Duration>) -> BPqPop {
+        BPqPop {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            end,
+            timeout,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser, end: QueueEnd) -> Result<BPqPop> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let timeout = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let timeout = Duration::try_from_secs_f64(parse_double(&timeout)?)?;
+        let timeout = (!timeout.is_zero()).then_some(timeout);
+        Ok(BPqPop { key, end, timeout })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.end {
+            QueueEnd::Min => "bpqpopmin",
+            QueueEnd::Max => "bpqpopmax",
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let timeout = self.timeout.unwrap_or_default().as_secs_f64();
+        let frame = vec![
+            Frame::Text(self.name().to_string()),
+            Frame::Binary(self.key),
+            Frame::Text(format_double(timeout)),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(
+        self,
+        db: &D,
+        dst: &mut Connection,
+        shared: &Shared,
+    ) -> Result<()> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let key = self.key;
+        let waiter = shared.waiters.register(key.clone());
+        let response = loop {
+            let pushed = waiter.listen();
+            if let Some(response) = pop(db, key.clone(), self.end, 1)? {
+                break response;
+            }
+            match deadline {
+                Some(deadline) => {
+                    if time::timeout_at(deadline, pushed).await.is_err() {
+                        break Frame::Null;
+                    }
+                }
+                None => pushed.await,
+            }
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `PQPEEKMIN key` and `PQPEEKMAX key` reply with the item at an end of the
+/// queue at `key` without taking it, or nil.
+#[derive(Debug)]
+pub struct PqPeek {
+    pub key: Bytes,
+    pub end: QueueEnd,
+}
+
+impl PqPeek {
+    pub fn new(key: impl AsRef<[u8]>, end: QueueEnd) -> PqPeek {
+        PqPeek {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            end,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser, end: QueueEnd) -> Result<PqPeek> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(PqPeek { key, end })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.end {
+            QueueEnd::Min => "pqpeekmin",
+            QueueEnd::Max => "pqpeekmax",
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text(self.name().to_string()),
+            Frame::Binary(self.key),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let end = self.end;
+        let response = lookup(db, dst, self.key, |value| match value {
+            Some(Value::Queue(queue)) => match end.peek(queue) {
+                Some((item, priority)) => {
+                    Frame::Array(vec![Frame::Binary(item.clone()), Frame::Double(priority)])
+                }
+                None => Frame::Null,
+            },
+            Some(_) => wrong_type(),
+            None => Frame::Null,
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// Pops up to `count` items from the queue at `key`, none if there is no queue.
+fn pop<D: Database>(db: &D, key: Bytes, end: QueueEnd, count: usize) -> Result<Option<Frame>> {
+    db.update(key, |value| match value {
+        Some(Value::Queue(queue)) => {
+            let mut popped = vec![];
+            for _ in 0..count {
+                let Some((item, priority)) = end.pop(queue) else {
+                    break;
+                };
+                popped.push(Frame::Binary(item));
+                popped.push(Frame::Double(priority));
+            }
+            if queue.is_empty() {
+                *value = None;
+            }
+            Some(Frame::Array(popped))
+        }
+        Some(_) => Some(wrong_type()),
+        None => None,
+    })
+}
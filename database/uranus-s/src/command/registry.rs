@@ -0,0 +1,520 @@
+//! Every command the server understands, by name
+//!
+//! [`Command::from_frame`] looks the name of a request up in [`COMMANDS`],
+//! checks its arity and the [types](ArgType) of its arguments, and hands them
+//! to the parser registered with it. Requests failing the checks get the same
+//! error replies, whichever command they are for.
+//! The [flags](CommandSpec::flags) of the entry tell the handler how to treat
+//! the command. Besides its [`Command`] variant, which [`Command::name`] and
+//! [`Command::apply`] match on, a new command only needs an entry here.
+//!
+
+use std::{collections::HashMap, sync::OnceLock};
+
+use anyhow::Result;
+
+use crate::parse_double;
+
+use super::*;
+use ArgType::*;
+
+/// The command changes the database.
+pub const WRITE: u8 = 1 << 0;
+/// The command reads the database without changing it.
+pub const READ: u8 = 1 << 1;
+/// The command may take more memory, see [`Command::may_grow`].
+pub const GROWS: u8 = 1 << 2;
+/// The command may wait indefinitely before replying.
+pub const BLOCKING: u8 = 1 << 3;
+/// The command administers the server. This flag and the next ones only group
+/// commands into the categories of [`crate::acl`].
+pub const ADMIN: u8 = 1 << 4;
+/// The command publishes or subscribes to channels.
+pub const PUBSUB: u8 = 1 << 5;
+/// The command runs scripts.
+pub const SCRIPTING: u8 = 1 << 6;
+
+/// What an argument must be for a request to be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgType {
+    /// Any bytes, such as a key or a value.
+    Any,
+    /// A decimal integer, such as a count or an offset, failing with
+    /// [`CommandParseError::NotInteger`] otherwise.
+    Int,
+    /// A decimal number, `inf` and `-inf` included, failing with
+    /// [`CommandParseError::NotFloat`] otherwise.
+    Float,
+}
+
+impl ArgType {
+    /// Checks `arg`. Frames other than strings are left to the parser.
+    pub fn check(self, arg: &Frame) -> Result<(), CommandParseError> {
+        let text = match arg {
+            Frame::Text(text) => text.as_bytes(),
+            Frame::Binary(binary) => binary,
+            _ => return Ok(()),
+        };
+        let text = std::str::from_utf8(text).unwrap_or_default();
+        match self {
+            ArgType::Any => Ok(()),
+            ArgType::Int if text.parse::<i64>().is_err() => Err(CommandParseError::NotInteger),
+            ArgType::Float if parse_double(text).is_err() => Err(CommandParseError::NotFloat),
+            ArgType::Int | ArgType::Float => Ok(()),
+        }
+    }
+}
+
+/// How a command is parsed and handled.
+#[derive(Debug)]
+pub struct CommandSpec {
+    /// The name on the wire, in lower case.
+    pub name: &'static str,
+    /// The number of frames of a request, the name included. Negative for at
+    /// least that many, as some arguments are optional or repeated.
+    pub arity: i32,
+    /// [`WRITE`], [`READ`], [`GROWS`], [`BLOCKING`] and the categories of
+    /// [`ADMIN`], [`PUBSUB`] and [`SCRIPTING`] as they apply.
+    pub flags: u8,
+    /// The types of the first arguments after the name. The later ones are
+    /// of the types of `repeated`, over and over, or unchecked without them.
+    pub args: &'static [ArgType],
+    pub repeated: &'static [ArgType],
+    parse: fn(&mut CommandParser) -> Result<Command>,
+}
+
+impl CommandSpec {
+    const fn new(
+        name: &'static str,
+        arity: i32,
+        flags: u8,
+        parse: fn(&mut CommandParser) -> Result<Command>,
+    ) -> CommandSpec {
+        CommandSpec {
+            name,
+            arity,
+            flags,
+            args: &[],
+            repeated: &[],
+            parse,
+        }
+    }
+
+    const fn args(self, args: &'static [ArgType]) -> CommandSpec {
+        CommandSpec { args, ..self }
+    }
+
+    /// Repeats the types of `repeated` after those of the first arguments,
+    /// for commands taking any number of pairs, as `ZADD`.
+    const fn repeated(self, repeated: &'static [ArgType]) -> CommandSpec {
+        CommandSpec { repeated, ..self }
+    }
+
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Whether a request of `len` frames, the name included, is well formed.
+    pub fn accepts(&self, len: usize) -> bool {
+        match usize::try_from(self.arity) {
+            Ok(arity) => len == arity,
+            Err(_) => len >= self.arity.unsigned_abs() as usize,
+        }
+    }
+
+    /// Checks the types of `args`, the arguments of a request after its name.
+    pub fn check_args(&self, args: &[Frame]) -> Result<(), CommandParseError> {
+        for (i, arg) in args.iter().enumerate() {
+            let ty = match (self.args.get(i), self.repeated.len()) {
+                (Some(ty), _) => *ty,
+                (None, 0) => break,
+                (None, n) => self.repeated[(i - self.args.len()) % n],
+            };
+            ty.check(arg)?;
+        }
+        Ok(())
+    }
+
+    /// Parses the arguments of a request, which follow its name in `parser`.
+    pub fn parse(&self, parser: &mut CommandParser) -> Result<Command> {
+        (self.parse)(parser)
+    }
+
+    /// The entry of the command named `name`, in any case.
+    pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+        static INDEX: OnceLock<HashMap<&'static str, &'static CommandSpec>> = OnceLock::new();
+        let index = INDEX.get_or_init(|| COMMANDS.iter().map(|spec| (spec.name, spec)).collect());
+        index
+            .get(name)
+            .or_else(|| index.get(name.to_lowercase().as_str()))
+            .copied()
+    }
+}
+
+pub static COMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("get", 2, READ, |p| Ok(Command::Get(Get::parse_frames(p)?))),
+    CommandSpec::new("set", -3, WRITE | GROWS, |p| {
+        Ok(Command::Set(Put::parse_frames(p)?))
+    }),
+    CommandSpec::new("echo", 2, 0, |p| Ok(Command::Echo(Echo::parse_frames(p)?))),
+    CommandSpec::new("ping", -1, 0, |p| Ok(Command::Ping(Ping::parse_frames(p)?))),
+    CommandSpec::new("ready", 1, 0, |p| {
+        Ok(Command::Ready(Ready::parse_frames(p)?))
+    }),
+    CommandSpec::new("hello", -1, 0, |p| {
+        Ok(Command::Hello(Hello::parse_frames(p)?))
+    }),
+    CommandSpec::new("config", -3, ADMIN, |p| {
+        Ok(Command::Config(Config::parse_frames(p)?))
+    }),
+    CommandSpec::new("hset", -4, WRITE | GROWS, |p| {
+        Ok(Command::HSet(HSet::parse_frames(p)?))
+    }),
+    CommandSpec::new("hget", 3, READ, |p| {
+        Ok(Command::HGet(HGet::parse_frames(p)?))
+    }),
+    CommandSpec::new("hdel", -3, WRITE, |p| {
+        Ok(Command::HDel(HDel::parse_frames(p)?))
+    }),
+    CommandSpec::new("hgetall", 2, READ, |p| {
+        Ok(Command::HGetAll(HGetAll::parse_frames(p)?))
+    }),
+    CommandSpec::new("publish", 3, PUBSUB, |p| {
+        Ok(Command::Publish(Publish::parse_frames(p)?))
+    }),
+    CommandSpec::new("subscribe", -2, BLOCKING | PUBSUB, |p| {
+        Ok(Command::Subscribe(Subscribe::parse_frames(p, false)?))
+    }),
+    CommandSpec::new("psubscribe", -2, BLOCKING | PUBSUB, |p| {
+        Ok(Command::Subscribe(Subscribe::parse_frames(p, true)?))
+    }),
+    CommandSpec::new("unsubscribe", -1, PUBSUB, |p| {
+        Ok(Command::Unsubscribe(Unsubscribe::parse_frames(p, false)?))
+    }),
+    CommandSpec::new("punsubscribe", -1, PUBSUB, |p| {
+        Ok(Command::Unsubscribe(Unsubscribe::parse_frames(p, true)?))
+    }),
+    CommandSpec::new("pqadd", -4, WRITE | GROWS, |p| {
+        Ok(Command::PqAdd(PqAdd::parse_frames(p)?))
+    })
+    .args(&[Any])
+    .repeated(&[Float, Any]),
+    CommandSpec::new("pqpopmin", -2, WRITE, |p| {
+        Ok(Command::PqPop(PqPop::parse_frames(p, QueueEnd::Min)?))
+    })
+    .args(&[Any, Int]),
+    CommandSpec::new("pqpopmax", -2, WRITE, |p| {
+        Ok(Command::PqPop(PqPop::parse_frames(p, QueueEnd::Max)?))
+    })
+    .args(&[Any, Int]),
+    CommandSpec::new("bpqpopmin", 3, WRITE | BLOCKING, |p| {
+        Ok(Command::BPqPop(BPqPop::parse_frames(p, QueueEnd::Min)?))
+    })
+    .args(&[Any, Float]),
+    CommandSpec::new("bpqpopmax", 3, WRITE | BLOCKING, |p| {
+        Ok(Command::BPqPop(BPqPop::parse_frames(p, QueueEnd::Max)?))
+    })
+    .args(&[Any, Float]),
+    CommandSpec::new("pqpeekmin", 2, READ, |p| {
+        Ok(Command::PqPeek(PqPeek::parse_frames(p, QueueEnd::Min)?))
+    }),
+    CommandSpec::new("pqpeekmax", 2, READ, |p| {
+        Ok(Command::PqPeek(PqPeek::parse_frames(p, QueueEnd::Max)?))
+    }),
+    CommandSpec::new("lpush", -3, WRITE | GROWS, |p| {
+        Ok(Command::Push(Push::parse_frames(p, ListEnd::Left)?))
+    }),
+    CommandSpec::new("rpush", -3, WRITE | GROWS, |p| {
+        Ok(Command::Push(Push::parse_frames(p, ListEnd::Right)?))
+    }),
+    CommandSpec::new("lpop", 2, WRITE, |p| {
+        Ok(Command::Pop(Pop::parse_frames(p, ListEnd::Left)?))
+    }),
+    CommandSpec::new("rpop", 2, WRITE, |p| {
+        Ok(Command::Pop(Pop::parse_frames(p, ListEnd::Right)?))
+    }),
+    CommandSpec::new("lpos", -3, READ, |p| {
+        Ok(Command::LPos(LPos::parse_frames(p)?))
+    }),
+    CommandSpec::new("blpop", 3, WRITE | BLOCKING, |p| {
+        Ok(Command::BLPop(BLPop::parse_frames(p)?))
+    })
+    .args(&[Any, Float]),
+    CommandSpec::new("keeprevs", 3, WRITE, |p| {
+        Ok(Command::KeepRevs(KeepRevs::parse_frames(p)?))
+    })
+    .args(&[Any, Int]),
+    CommandSpec::new("getrev", 3, READ, |p| {
+        Ok(Command::GetRev(GetRev::parse_frames(p)?))
+    })
+    .args(&[Any, Int]),
+    CommandSpec::new("sadd", -3, WRITE | GROWS, |p| {
+        Ok(Command::SAdd(SAdd::parse_frames(p)?))
+    }),
+    CommandSpec::new("srem", -3, WRITE, |p| {
+        Ok(Command::SRem(SRem::parse_frames(p)?))
+    }),
+    CommandSpec::new("smembers", 2, READ, |p| {
+        Ok(Command::SMembers(SMembers::parse_frames(p)?))
+    }),
+    CommandSpec::new("sismember", 3, READ, |p| {
+        Ok(Command::SIsMember(SIsMember::parse_frames(p)?))
+    }),
+    CommandSpec::new("sinter", -2, READ, |p| {
+        let algebra = SetAlgebra::parse_frames(p, SetOperation::Inter)?;
+        Ok(Command::SetAlgebra(algebra))
+    }),
+    CommandSpec::new("sunion", -2, READ, |p| {
+        let algebra = SetAlgebra::parse_frames(p, SetOperation::Union)?;
+        Ok(Command::SetAlgebra(algebra))
+    }),
+    CommandSpec::new("sdiff", -2, READ, |p| {
+        let algebra = SetAlgebra::parse_frames(p, SetOperation::Diff)?;
+        Ok(Command::SetAlgebra(algebra))
+    }),
+    CommandSpec::new("sinterstore", -3, WRITE | GROWS, |p| {
+        let store = SetAlgebraStore::parse_frames(p, SetOperation::Inter)?;
+        Ok(Command::SetAlgebraStore(store))
+    }),
+    CommandSpec::new("sunionstore", -3, WRITE | GROWS, |p| {
+        let store = SetAlgebraStore::parse_frames(p, SetOperation::Union)?;
+        Ok(Command::SetAlgebraStore(store))
+    }),
+    CommandSpec::new("sdiffstore", -3, WRITE | GROWS, |p| {
+        let store = SetAlgebraStore::parse_frames(p, SetOperation::Diff)?;
+        Ok(Command::SetAlgebraStore(store))
+    }),
+    CommandSpec::new("sintercard", -3, READ, |p| {
+        Ok(Command::SInterCard(SInterCard::parse_frames(p)?))
+    })
+    .args(&[Int, Any]),
+    CommandSpec::new("zadd", -4, WRITE | GROWS, |p| {
+        Ok(Command::ZAdd(ZAdd::parse_frames(p)?))
+    })
+    .args(&[Any])
+    .repeated(&[Float, Any]),
+    CommandSpec::new("zscore", 3, READ, |p| {
+        Ok(Command::ZScore(ZScore::parse_frames(p)?))
+    }),
+    CommandSpec::new("zrange", -4, READ, |p| {
+        Ok(Command::ZRange(ZRange::parse_frames(p)?))
+    }),
+    CommandSpec::new("append", 3, WRITE | GROWS, |p| {
+        Ok(Command::Append(Append::parse_frames(p)?))
+    }),
+    CommandSpec::new("strlen", 2, READ, |p| {
+        Ok(Command::StrLen(StrLen::parse_frames(p)?))
+    }),
+    CommandSpec::new("getrange", 4, READ, |p| {
+        Ok(Command::GetRange(GetRange::parse_frames(p)?))
+    })
+    .args(&[Any, Int, Int]),
+    CommandSpec::new("mset", -3, WRITE | GROWS, |p| {
+        Ok(Command::MSet(MSet::parse_frames(p)?))
+    }),
+    CommandSpec::new("setrange", 4, WRITE | GROWS, |p| {
+        Ok(Command::SetRange(SetRange::parse_frames(p)?))
+    })
+    .args(&[Any, Int, Any]),
+    CommandSpec::new("setbit", 4, WRITE | GROWS, |p| {
+        Ok(Command::SetBit(SetBit::parse_frames(p)?))
+    })
+    .args(&[Any, Int, Int]),
+    CommandSpec::new("getbit", 3, READ, |p| {
+        Ok(Command::GetBit(GetBit::parse_frames(p)?))
+    })
+    .args(&[Any, Int]),
+    CommandSpec::new("bitcount", -2, READ, |p| {
+        Ok(Command::BitCount(BitCount::parse_frames(p)?))
+    })
+    .args(&[Any, Int, Int]),
+    CommandSpec::new("pfadd", -2, WRITE | GROWS, |p| {
+        Ok(Command::PfAdd(PfAdd::parse_frames(p)?))
+    }),
+    CommandSpec::new("pfcount", -2, READ, |p| {
+        Ok(Command::PfCount(PfCount::parse_frames(p)?))
+    }),
+    CommandSpec::new("pfmerge", -2, WRITE | GROWS, |p| {
+        Ok(Command::PfMerge(PfMerge::parse_frames(p)?))
+    }),
+    CommandSpec::new("xadd", -5, WRITE | GROWS, |p| {
+        Ok(Command::XAdd(XAdd::parse_frames(p)?))
+    }),
+    CommandSpec::new("xrange", -4, READ, |p| {
+        Ok(Command::XRange(XRange::parse_frames(p)?))
+    }),
+    CommandSpec::new("xread", -4, READ | BLOCKING, |p| {
+        Ok(Command::XRead(XRead::parse_frames(p)?))
+    }),
+    CommandSpec::new("eval", -3, WRITE | GROWS | SCRIPTING, |p| {
+        Ok(Command::Eval(Eval::parse_frames(p, false)?))
+    }),
+    CommandSpec::new("evalsha", -3, WRITE | GROWS | SCRIPTING, |p| {
+        Ok(Command::Eval(Eval::parse_frames(p, true)?))
+    }),
+    CommandSpec::new("cas", 4, WRITE | GROWS, |p| {
+        Ok(Command::Cas(Cas::parse_frames(p)?))
+    }),
+    CommandSpec::new("select", 2, 0, |p| {
+        Ok(Command::Select(Select::parse_frames(p)?))
+    })
+    .args(&[Int]),
+    CommandSpec::new("flushdb", 1, WRITE, |p| {
+        Ok(Command::Flush(Flush::parse_frames(p, false)?))
+    }),
+    CommandSpec::new("flushall", 1, WRITE, |p| {
+        Ok(Command::Flush(Flush::parse_frames(p, true)?))
+    }),
+    CommandSpec::new("dbsize", 1, READ, |p| {
+        Ok(Command::DbSize(DbSize::parse_frames(p)?))
+    }),
+    CommandSpec::new("keys", 2, READ, |p| {
+        Ok(Command::Keys(Keys::parse_frames(p)?))
+    }),
+    CommandSpec::new("scan", -2, READ, |p| {
+        Ok(Command::Scan(Scan::parse_frames(p)?))
+    }),
+    CommandSpec::new("debug", -2, ADMIN, |p| {
+        Ok(Command::Debug(DebugCommand::parse_frames(p)?))
+    }),
+    CommandSpec::new("object", -2, READ, |p| {
+        Ok(Command::Object(ObjectCommand::parse_frames(p)?))
+    }),
+    CommandSpec::new("info", -1, 0, |p| Ok(Command::Info(Info::parse_frames(p)?))),
+    CommandSpec::new("expire", 3, WRITE, |p| {
+        Ok(Command::Expire(Expire::parse_frames(p, false)?))
+    })
+    .args(&[Any, Int]),
+    CommandSpec::new("pexpire", 3, WRITE, |p| {
+        Ok(Command::Expire(Expire::parse_frames(p, true)?))
+    })
+    .args(&[Any, Int]),
+    CommandSpec::new("expireat", 3, WRITE, |p| {
+        Ok(Command::ExpireAt(ExpireAt::parse_frames(p, false)?))
+    })
+    .args(&[Any, Int]),
+    CommandSpec::new("pexpireat", 3, WRITE, |p| {
+        Ok(Command::ExpireAt(ExpireAt::parse_frames(p, true)?))
+    })
+    .args(&[Any, Int]),
+    CommandSpec::new("ttl", 2, READ, |p| {
+        Ok(Command::Ttl(Ttl::parse_frames(p, false)?))
+    }),
+    CommandSpec::new("pttl", 2, READ, |p| {
+        Ok(Command::Ttl(Ttl::parse_frames(p, true)?))
+    }),
+    CommandSpec::new("persist", 2, WRITE, |p| {
+        Ok(Command::Persist(Persist::parse_frames(p)?))
+    }),
+    CommandSpec::new("rename", 3, WRITE, |p| {
+        Ok(Command::Rename(Rename::parse_frames(p)?))
+    }),
+    CommandSpec::new("copy", -3, WRITE | GROWS, |p| {
+        Ok(Command::Copy(CopyCommand::parse_frames(p)?))
+    }),
+    CommandSpec::new("dump", 2, READ, |p| {
+        Ok(Command::Dump(Dump::parse_frames(p)?))
+    }),
+    CommandSpec::new("restore", -4, WRITE | GROWS, |p| {
+        Ok(Command::Restore(Restore::parse_frames(p)?))
+    })
+    .args(&[Any, Int, Any]),
+    // Waits on the target, so it's logged as the value of its key once done.
+    CommandSpec::new("migrate", 5, WRITE | BLOCKING, |p| {
+        Ok(Command::Migrate(Migrate::parse_frames(p)?))
+    })
+    .args(&[Any, Int, Any, Int]),
+    CommandSpec::new("del", -2, WRITE, |p| {
+        Ok(Command::Del(Del::parse_frames(p)?))
+    }),
+    CommandSpec::new("unlink", -2, WRITE, |p| {
+        Ok(Command::Unlink(Unlink::parse_frames(p)?))
+    }),
+    CommandSpec::new("delprefix", 2, WRITE, |p| {
+        Ok(Command::DelPrefix(DelPrefix::parse_frames(p)?))
+    }),
+    CommandSpec::new("bgrewriteaof", 1, ADMIN, |p| {
+        Ok(Command::BgRewriteAof(BgRewriteAof::parse_frames(p)?))
+    }),
+    CommandSpec::new("replconf", 3, ADMIN, |p| {
+        Ok(Command::ReplConf(ReplConf::parse_frames(p)?))
+    }),
+    CommandSpec::new("wait", 3, BLOCKING, |p| {
+        Ok(Command::Wait(Wait::parse_frames(p)?))
+    })
+    .args(&[Int, Int]),
+    CommandSpec::new("cluster", -2, ADMIN, |p| {
+        Ok(Command::Cluster(Cluster::parse_frames(p)?))
+    }),
+    CommandSpec::new("asking", 1, 0, |p| {
+        Ok(Command::Asking(Asking::parse_frames(p)?))
+    }),
+    CommandSpec::new("client", -2, ADMIN, |p| {
+        Ok(Command::Client(ClientCommand::parse_frames(p)?))
+    }),
+    CommandSpec::new("auth", -2, 0, |p| Ok(Command::Auth(Auth::parse_frames(p)?))),
+    CommandSpec::new("acl", -2, ADMIN, |p| {
+        Ok(Command::Acl(AclCommand::parse_frames(p)?))
+    }),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let mut names: Vec<_> = COMMANDS.iter().map(|spec| spec.name).collect();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), COMMANDS.len());
+
+        let spec = CommandSpec::lookup("GET").unwrap();
+        assert_eq!(spec.name, "get");
+        assert!(spec.accepts(2) && !spec.accepts(3));
+        let spec = CommandSpec::lookup("del").unwrap();
+        assert!(!spec.accepts(1) && spec.accepts(2) && spec.accepts(5));
+        assert!(CommandSpec::lookup("nosuchcommand").is_none());
+    }
+
+    #[test]
+    fn test_from_frame() {
+        let request = |words: &[&str]| {
+            Frame::Array(
+                words
+                    .iter()
+                    .map(|word| Frame::Text(word.to_string()))
+                    .collect(),
+            )
+        };
+        let command = Command::from_frame(request(&["SET", "key", "value", "NX"])).unwrap();
+        assert!(command.is_write() && command.may_grow() && !command.is_read());
+        let command = Command::from_frame(request(&["pqpopmax", "queue"])).unwrap();
+        assert_eq!(command.name(), "pqpopmax");
+        assert!(command.is_write() && !command.may_grow());
+        let command = Command::from_frame(request(&["keeprevs", "key", "3"])).unwrap();
+        assert!(command.is_write() && !command.is_read());
+
+        let err = Command::from_frame(request(&["get", "a", "b"])).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CommandParseError>(),
+            Some(CommandParseError::WrongArity(name)) if name == "get"
+        ));
+        let err = Command::from_frame(request(&["getrange", "key", "0", "x"])).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CommandParseError>(),
+            Some(CommandParseError::NotInteger)
+        ));
+        let err = Command::from_frame(request(&["zadd", "z", "1", "a", "two", "b"])).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CommandParseError>(),
+            Some(CommandParseError::NotFloat)
+        ));
+        assert!(Command::from_frame(request(&["zadd", "z", "1", "a", "-inf", "b"])).is_ok());
+        let err = Command::from_frame(request(&["nosuchcommand"])).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CommandParseError>(),
+            Some(CommandParseError::UnknownCommand)
+        ));
+    }
+}
@@ -0,0 +1,124 @@
+//! Moving and copying keys
+//!
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{CommandParseError, CommandParser};
+use crate::{Connection, Database, ErrorCode, Frame};
+
+/// `RENAME src dst` moves the value of `src` to `dst` with its TTL,
+/// overwriting `dst`. Both happen at once for other connections. Replies with
+/// an error if there is no `src`.
+#[derive(Debug)]
+pub struct Rename {
+    pub src: Bytes,
+    pub dst: Bytes,
+}
+
+impl Rename {
+    pub fn new(src: impl AsRef<[u8]>, dst: impl AsRef<[u8]>) -> Rename {
+        Rename {
+            src: Bytes::copy_from_slice(src.as_ref()),
+            dst: Bytes::copy_from_slice(dst.as_ref()),
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Rename> {
+        let src = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let dst = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(Rename { src, dst })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("rename".to_string()),
+            Frame::Binary(self.src),
+            Frame::Binary(self.dst),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let response = if db.rename(self.src, self.dst)? {
+            Frame::Text("OK".to_string())
+        } else {
+            Frame::error(ErrorCode::Err, "no such key")
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `COPY src dst [REPLACE]` copies the value of `src` to `dst` with its TTL.
+/// Without `REPLACE`, an existing `dst` is left alone. Replies with 1 if the
+/// value was copied, 0 otherwise.
+#[derive(Debug)]
+pub struct CopyCommand {
+    pub src: Bytes,
+    pub dst: Bytes,
+    pub replace: bool,
+}
+
+impl CopyCommand {
+    pub fn new(src: impl AsRef<[u8]>, dst: impl AsRef<[u8]>) -> CopyCommand {
+        CopyCommand {
+            src: Bytes::copy_from_slice(src.as_ref()),
+            dst: Bytes::copy_from_slice(dst.as_ref()),
+            replace: false,
+        }
+    }
+
+    /// Overwrites `dst` if it exists.
+    pub fn replacing(mut self) -> CopyCommand {
+        self.replace = true;
+        self
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<CopyCommand> {
+        let src = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let dst = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let mut copy = CopyCommand::new(src, dst);
+        while let Some(option) = parser.next_string()? {
+            match option.to_lowercase().as_str() {
+                "replace" => copy.replace = true,
+                _ => Err(CommandParseError::UnknownOption(option))?,
+            }
+        }
+        Ok(copy)
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![
+            Frame::Text("copy".to_string()),
+            Frame::Binary(self.src),
+            Frame::Binary(self.dst),
+        ];
+        if self.replace {
+            frame.push(Frame::Text("replace".to_string()));
+        }
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        if self.src == self.dst {
+            let response = Frame::error(
+                ErrorCode::Err,
+                "source and destination objects are the same",
+            );
+            dst.write_frame(&response).await?;
+            return Ok(());
+        }
+        let copied = db.copy(self.src, self.dst, self.replace)?;
+        dst.write_frame(&Frame::Integer(copied as i64)).await?;
+        Ok(())
+    }
+}
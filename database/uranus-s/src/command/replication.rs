@@ -0,0 +1,113 @@
+//! Commands on replication, see [`crate::shadow`]
+//!
+
+use std::{fmt, time::Duration};
+
+use anyhow::Result;
+
+use super::{CommandParseError, CommandParser};
+use crate::{Connection, Frame, Shared};
+
+/// `REPLCONF PRIMARY secret` marks the connection as the link of the primary
+/// of this server, whose writes a read-only replica accepts. It is refused
+/// unless `secret` is the
+/// [`ServerConfig::replication_secret`](crate::ServerConfig::replication_secret)
+/// of this server.
+pub struct ReplConf {
+    pub secret: String,
+}
+
+// The secret is kept out of the logs.
+impl fmt::Debug for ReplConf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplConf").finish_non_exhaustive()
+    }
+}
+
+impl ReplConf {
+    pub fn new(secret: impl ToString) -> ReplConf {
+        ReplConf {
+            secret: secret.to_string(),
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<ReplConf> {
+        let option = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        if !option.eq_ignore_ascii_case("primary") {
+            Err(CommandParseError::UnknownOption(option))?
+        }
+        let secret = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(ReplConf { secret })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("replconf".to_string()),
+            Frame::Text("primary".to_string()),
+            Frame::Text(self.secret),
+        ];
+        Frame::Array(frame)
+    }
+
+    /// The secret is checked and the connection marked by the handler, this
+    /// only acknowledges it.
+    pub async fn apply(self, dst: &mut Connection) -> Result<()> {
+        dst.write_frame(&Frame::Text("OK".to_string())).await?;
+        Ok(())
+    }
+}
+
+/// `WAIT numreplicas timeout` waits until `numreplicas` replicas have applied
+/// the writes mirrored so far, for at most `timeout` milliseconds, or
+/// indefinitely with a timeout of 0. Replies with the number of replicas
+/// which did, which is at most 1 as a server mirrors to one replica.
+#[derive(Debug)]
+pub struct Wait {
+    pub replicas: usize,
+    /// None to wait forever.
+    pub timeout: Option<Duration>,
+}
+
+impl Wait {
+    pub fn new(replicas: usize, timeout: Option<Duration>) -> Wait {
+        Wait { replicas, timeout }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Wait> {
+        let replicas = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<usize>()?;
+        let timeout = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<u64>()?;
+        let timeout = (timeout > 0).then(|| Duration::from_millis(timeout));
+        Ok(Wait { replicas, timeout })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let timeout = self.timeout.map_or(0, |timeout| timeout.as_millis());
+        let frame = vec![
+            Frame::Text("wait".to_string()),
+            Frame::Text(self.replicas.to_string()),
+            Frame::Text(timeout.to_string()),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply(self, dst: &mut Connection, shared: &Shared) -> Result<()> {
+        let acked = match &shared.shadow {
+            // Waiting for no replica merely counts those which caught up.
+            Some(shadow) if self.replicas == 0 => shadow.wait_acked(Some(Duration::ZERO)).await,
+            Some(shadow) => shadow.wait_acked(self.timeout).await,
+            None => false,
+        };
+        dst.write_frame(&Frame::Integer(acked as i64)).await?;
+        Ok(())
+    }
+}
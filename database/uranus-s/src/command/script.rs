@@ -0,0 +1,105 @@
+//! Running scripts, see [`crate::scripting`]
+//!
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{CommandParseError, CommandParser};
+use crate::{eval_script, Connection, Database, ErrorCode, Frame, Shared};
+
+/// The script `EVAL` runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Script {
+    Source(String),
+    /// The digest of a script cached before, see
+    /// [`Scripts::digest`](crate::Scripts::digest).
+    Digest(String),
+}
+
+/// `EVAL script numkeys [key ...] [arg ...]` runs the Lua `script` with the
+/// keys in `KEYS` and the other arguments in `ARGV`, and replies with what it
+/// returns. No other command runs meanwhile. The script is cached, so that
+/// `EVALSHA digest numkeys [key ...] [arg ...]` runs it again by the SHA1
+/// digest of its source.
+#[derive(Debug)]
+pub struct Eval {
+    pub script: Script,
+    pub keys: Vec<Bytes>,
+    pub args: Vec<Bytes>,
+}
+
+impl Eval {
+    pub fn new(script: Script, keys: Vec<Bytes>, args: Vec<Bytes>) -> Eval {
+        Eval { script, keys, args }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser, by_digest: bool) -> Result<Eval> {
+        let script = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let script = if by_digest {
+            Script::Digest(script)
+        } else {
+            Script::Source(script)
+        };
+        let numkeys: usize = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse()?;
+        if numkeys > parser.remaining() {
+            Err(CommandParseError::UnexpectedEOF)?
+        }
+        let mut keys = vec![];
+        for _ in 0..numkeys {
+            keys.push(
+                parser
+                    .next_bytes()?
+                    .ok_or(CommandParseError::UnexpectedEOF)?,
+            );
+        }
+        let mut args = vec![];
+        while let Some(arg) = parser.next_bytes()? {
+            args.push(arg);
+        }
+        Ok(Eval { script, keys, args })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.script {
+            Script::Source(_) => "eval",
+            Script::Digest(_) => "evalsha",
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text(self.name().to_string())];
+        match self.script {
+            Script::Source(script) | Script::Digest(script) => frame.push(Frame::Text(script)),
+        }
+        frame.push(Frame::Text(self.keys.len().to_string()));
+        frame.extend(self.keys.into_iter().map(Frame::Binary));
+        frame.extend(self.args.into_iter().map(Frame::Binary));
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(
+        self,
+        db: &D,
+        dst: &mut Connection,
+        shared: &Shared,
+    ) -> Result<()> {
+        let source = match self.script {
+            Script::Source(source) => Some(shared.scripts.load(source)),
+            Script::Digest(digest) => shared.scripts.get(&digest),
+        };
+        let response = match source {
+            Some(source) => {
+                let user = dst.user();
+                eval_script(source, self.keys, self.args, user, db, shared).await?
+            }
+            None => Frame::error(ErrorCode::NoScript, "No matching script. Please use EVAL."),
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,471 @@
+//! Commands on set values, unordered collections of distinct members
+//!
+
+use std::collections::HashSet;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{lookup, wrong_type, CommandParseError, CommandParser};
+use crate::{Connection, Database, Frame, Value};
+
+/// `SADD key member [member ...]` adds members to the set at `key`, creating it
+/// if needed. Replies with the number of members added, members already there
+/// don't count.
+#[derive(Debug)]
+pub struct SAdd {
+    pub key: Bytes,
+    pub members: Vec<Bytes>,
+}
+
+impl SAdd {
+    pub fn new(key: impl AsRef<[u8]>, members: Vec<Bytes>) -> SAdd {
+        SAdd {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            members,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<SAdd> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let members = members(parser)?;
+        Ok(SAdd { key, members })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("sadd".to_string()), Frame::Binary(self.key)];
+        frame.extend(self.members.into_iter().map(Frame::Binary));
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let SAdd { key, members } = self;
+        let response = db.update(key, |value| {
+            match value.get_or_insert_with(|| Value::Set(HashSet::new())) {
+                Value::Set(set) => {
+                    let added = members
+                        .into_iter()
+                        .filter(|member| set.insert(member.clone()))
+                        .count();
+                    Frame::Integer(added as i64)
+                }
+                _ => wrong_type(),
+            }
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `SREM key member [member ...]` removes members from the set at `key`, and the
+/// key itself once no member is left. Replies with the number of members removed.
+#[derive(Debug)]
+pub struct SRem {
+    pub key: Bytes,
+    pub members: Vec<Bytes>,
+}
+
+impl SRem {
+    pub fn new(key: impl AsRef<[u8]>, members: Vec<Bytes>) -> SRem {
+        SRem {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            members,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<SRem> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let members = members(parser)?;
+        Ok(SRem { key, members })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("srem".to_string()), Frame::Binary(self.key)];
+        frame.extend(self.members.into_iter().map(Frame::Binary));
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let SRem { key, members } = self;
+        let response = db.update(key, |value| match value {
+            Some(Value::Set(set)) => {
+                let removed = members.iter().filter(|member| set.remove(*member)).count();
+                if set.is_empty() {
+                    *value = None;
+                }
+                Frame::Integer(removed as i64)
+            }
+            Some(_) => wrong_type(),
+            None => Frame::Integer(0),
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `SMEMBERS key` replies with every member of the set at `key`, in no
+/// particular order.
+#[derive(Debug)]
+pub struct SMembers {
+    pub key: Bytes,
+}
+
+impl SMembers {
+    pub fn new(key: impl AsRef<[u8]>) -> SMembers {
+        SMembers {
+            key: Bytes::copy_from_slice(key.as_ref()),
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<SMembers> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(SMembers { key })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![Frame::Text("smembers".to_string()), Frame::Binary(self.key)];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let response = lookup(db, dst, self.key, |value| match value {
+            Some(Value::Set(set)) => Frame::Array(set.iter().cloned().map(Frame::Binary).collect()),
+            Some(_) => wrong_type(),
+            None => Frame::Array(vec![]),
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `SISMEMBER key member` replies with 1 if `member` belongs to the set at
+/// `key`, 0 otherwise.
+#[derive(Debug)]
+pub struct SIsMember {
+    pub key: Bytes,
+    pub member: Bytes,
+}
+
+impl SIsMember {
+    pub fn new(key: impl AsRef<[u8]>, member: Bytes) -> SIsMember {
+        SIsMember {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            member,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<SIsMember> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let member = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(SIsMember { key, member })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("sismember".to_string()),
+            Frame::Binary(self.key),
+            Frame::Binary(self.member),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let member = self.member;
+        let response = lookup(db, dst, self.key, |value| match value {
+            Some(Value::Set(set)) => Frame::Integer(set.contains(&member) as i64),
+            Some(_) => wrong_type(),
+            None => Frame::Integer(0),
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// How [`SetAlgebra`] and [`SetAlgebraStore`] combine their sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOperation {
+    /// The members of every set.
+    Inter,
+    /// The members of any set.
+    Union,
+    /// The members of the first set in none of the others.
+    Diff,
+}
+
+impl SetOperation {
+    fn name(self) -> &'static str {
+        match self {
+            SetOperation::Inter => "sinter",
+            SetOperation::Union => "sunion",
+            SetOperation::Diff => "sdiff",
+        }
+    }
+}
+
+/// `SINTER key [key ...]`, `SUNION key [key ...]` and `SDIFF key [key ...]`
+/// reply with the members of the sets at the keys combined by
+/// [`SetOperation`], in no particular order. Missing keys are empty sets.
+#[derive(Debug)]
+pub struct SetAlgebra {
+    pub op: SetOperation,
+    pub keys: Vec<Bytes>,
+}
+
+impl SetAlgebra {
+    pub fn new(op: SetOperation, keys: Vec<Bytes>) -> SetAlgebra {
+        SetAlgebra { op, keys }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser, op: SetOperation) -> Result<SetAlgebra> {
+        Ok(SetAlgebra {
+            op,
+            keys: keys(parser)?,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.op.name()
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text(self.name().to_string())];
+        frame.extend(self.keys.into_iter().map(Frame::Binary));
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let response = match combine(db, self.op, &self.keys, |hit| dst.record_lookup(hit))? {
+            Some(members) => Frame::Array(members.into_iter().map(Frame::Binary).collect()),
+            None => wrong_type(),
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `SINTERSTORE destination key [key ...]`, `SUNIONSTORE destination key [key
+/// ...]` and `SDIFFSTORE destination key [key ...]` store the members
+/// [`SetAlgebra`] would reply with as the set at `destination`, replacing its
+/// value, or remove it if there are none. Replies with their number.
+#[derive(Debug)]
+pub struct SetAlgebraStore {
+    pub op: SetOperation,
+    pub dst: Bytes,
+    pub keys: Vec<Bytes>,
+}
+
+impl SetAlgebraStore {
+    pub fn new(op: SetOperation, dst: impl AsRef<[u8]>, keys: Vec<Bytes>) -> SetAlgebraStore {
+        SetAlgebraStore {
+            op,
+            dst: Bytes::copy_from_slice(dst.as_ref()),
+            keys,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser, op: SetOperation) -> Result<SetAlgebraStore> {
+        let dst = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(SetAlgebraStore {
+            op,
+            dst,
+            keys: keys(parser)?,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.op {
+            SetOperation::Inter => "sinterstore",
+            SetOperation::Union => "sunionstore",
+            SetOperation::Diff => "sdiffstore",
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![
+            Frame::Text(self.name().to_string()),
+            Frame::Binary(self.dst),
+        ];
+        frame.extend(self.keys.into_iter().map(Frame::Binary));
+        Frame::Array(frame)
+    }
+
+    /// Like [`PfMerge`](super::PfMerge), the sets are read then the result
+    /// written, not atomically.
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let response = match combine(db, self.op, &self.keys, |_| {})? {
+            Some(members) => {
+                let stored = members.len();
+                db.update(self.dst, |value| {
+                    *value = (!members.is_empty()).then_some(Value::Set(members));
+                })?;
+                Frame::Integer(stored as i64)
+            }
+            None => wrong_type(),
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `SINTERCARD numkeys key [key ...] [LIMIT limit]` replies with the number of
+/// members of the intersection of the sets at the keys, as [`SetAlgebra`]
+/// computes it, but at most `limit` if it isn't 0.
+#[derive(Debug)]
+pub struct SInterCard {
+    pub keys: Vec<Bytes>,
+    pub limit: Option<usize>,
+}
+
+impl SInterCard {
+    pub fn new(keys: Vec<Bytes>, limit: Option<usize>) -> SInterCard {
+        SInterCard { keys, limit }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<SInterCard> {
+        let numkeys = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<usize>()?;
+        let mut keys = Vec::with_capacity(numkeys.min(parser.remaining()));
+        for _ in 0..numkeys {
+            let key = parser
+                .next_bytes()?
+                .ok_or(CommandParseError::UnexpectedEOF)?;
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            Err(CommandParseError::UnexpectedEOF)?
+        }
+        let mut limit = None;
+        while let Some(option) = parser.next_string()? {
+            match option.to_lowercase().as_str() {
+                "limit" => {
+                    let max = parser
+                        .next_string()?
+                        .ok_or(CommandParseError::UnexpectedEOF)?
+                        .parse::<usize>()?;
+                    limit = (max > 0).then_some(max);
+                }
+                _ => Err(CommandParseError::UnknownOption(option))?,
+            }
+        }
+        Ok(SInterCard { keys, limit })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![
+            Frame::Text("sintercard".to_string()),
+            Frame::Text(self.keys.len().to_string()),
+        ];
+        frame.extend(self.keys.into_iter().map(Frame::Binary));
+        if let Some(limit) = self.limit {
+            frame.push(Frame::Text("limit".to_string()));
+            frame.push(Frame::Text(limit.to_string()));
+        }
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let lookup = |hit| dst.record_lookup(hit);
+        let response = match combine(db, SetOperation::Inter, &self.keys, lookup)? {
+            Some(members) => {
+                let count = self
+                    .limit
+                    .map_or(members.len(), |limit| limit.min(members.len()));
+                Frame::Integer(count as i64)
+            }
+            None => wrong_type(),
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// The members of the sets at `keys` combined by `op`, none if one of the
+/// keys holds another type. `lookup` is told whether each key read was found.
+///
+/// Intersections start from the smallest set and check its members against
+/// the others by increasing size, stopping as soon as none is left, so that
+/// they take time in the size of the smallest set rather than of the largest.
+fn combine<D: Database>(
+    db: &D,
+    op: SetOperation,
+    keys: &[Bytes],
+    mut lookup: impl FnMut(bool),
+) -> Result<Option<HashSet<Bytes>>> {
+    let mut sets = Vec::with_capacity(keys.len());
+    for key in keys {
+        let len = db.view(key.clone(), |value| {
+            lookup(value.is_some());
+            match value {
+                Some(Value::Set(set)) => Some(set.len()),
+                Some(_) => None,
+                None => Some(0),
+            }
+        })?;
+        match len {
+            Some(len) => sets.push((key, len)),
+            None => return Ok(None),
+        }
+    }
+    if op == SetOperation::Inter {
+        sets.sort_by_key(|(_, len)| *len);
+    }
+    let mut members = HashSet::new();
+    let empty = HashSet::new();
+    for (n, (key, _)) in sets.into_iter().enumerate() {
+        db.view(key.clone(), |value| {
+            let set = match value {
+                Some(Value::Set(set)) => set,
+                // Written since, as the sets aren't read atomically.
+                _ => &empty,
+            };
+            match op {
+                _ if n == 0 => members = set.clone(),
+                SetOperation::Inter => members.retain(|member| set.contains(member)),
+                SetOperation::Union => members.extend(set.iter().cloned()),
+                SetOperation::Diff => members.retain(|member| !set.contains(member)),
+            }
+        })?;
+        if members.is_empty() && op != SetOperation::Union {
+            break;
+        }
+    }
+    Ok(Some(members))
+}
+
+/// Parses the keys left, at least one.
+fn keys(parser: &mut CommandParser) -> Result<Vec<Bytes>> {
+    let mut keys = vec![];
+    while let Some(key) = parser.next_bytes()? {
+        keys.push(key);
+    }
+    if keys.is_empty() {
+        Err(CommandParseError::UnexpectedEOF)?
+    }
+    Ok(keys)
+}
+
+/// Parses the members left, at least one.
+fn members(parser: &mut CommandParser) -> Result<Vec<Bytes>> {
+    let mut members = vec![];
+    while let Some(member) = parser.next_bytes()? {
+        members.push(member);
+    }
+    if members.is_empty() {
+        Err(CommandParseError::UnexpectedEOF)?
+    }
+    Ok(members)
+}
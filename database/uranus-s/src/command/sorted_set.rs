@@ -0,0 +1,234 @@
+//! Commands on sorted set values, members ordered by a score, for leaderboards
+//! and the like
+//!
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{lookup, wrong_type, CommandParseError, CommandParser};
+use crate::{format_double, parse_double, Connection, Database, Frame, SortedSet, Value};
+
+/// `ZADD key score member [score member ...]` sets the score of members of the
+/// sorted set at `key`, creating it if needed. Replies with the number of
+/// members added, members which only got a new score don't count.
+#[derive(Debug)]
+pub struct ZAdd {
+    pub key: Bytes,
+    pub members: Vec<(f64, Bytes)>,
+}
+
+impl ZAdd {
+    pub fn new(key: impl AsRef<[u8]>, members: Vec<(f64, Bytes)>) -> ZAdd {
+        ZAdd {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            members,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<ZAdd> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let mut members = vec![];
+        while let Some(score) = parser.next_string()? {
+            let member = parser
+                .next_bytes()?
+                .ok_or(CommandParseError::UnexpectedEOF)?;
+            members.push((parse_double(&score)?, member));
+        }
+        if members.is_empty() {
+            Err(CommandParseError::UnexpectedEOF)?
+        }
+        Ok(ZAdd { key, members })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("zadd".to_string()), Frame::Binary(self.key)];
+        for (score, member) in self.members {
+            frame.push(Frame::Text(format_double(score)));
+            frame.push(Frame::Binary(member));
+        }
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let ZAdd { key, members } = self;
+        let response = db.update(key, |value| {
+            match value.get_or_insert_with(|| Value::SortedSet(SortedSet::new())) {
+                Value::SortedSet(set) => {
+                    let added = members
+                        .into_iter()
+                        .filter(|(score, member)| set.insert(member.clone(), *score))
+                        .count();
+                    Frame::Integer(added as i64)
+                }
+                _ => wrong_type(),
+            }
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `ZSCORE key member` replies with the score of `member`, or nil.
+#[derive(Debug)]
+pub struct ZScore {
+    pub key: Bytes,
+    pub member: Bytes,
+}
+
+impl ZScore {
+    pub fn new(key: impl AsRef<[u8]>, member: Bytes) -> ZScore {
+        ZScore {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            member,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<ZScore> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let member = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(ZScore { key, member })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("zscore".to_string()),
+            Frame::Binary(self.key),
+            Frame::Binary(self.member),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let member = self.member;
+        let response = lookup(db, dst, self.key, |value| match value {
+            Some(Value::SortedSet(set)) => match set.score(&member) {
+                Some(score) => Frame::Double(score),
+                None => Frame::Null,
+            },
+            Some(_) => wrong_type(),
+            None => Frame::Null,
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// Which members a [`ZRange`] replies with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZRangeBy {
+    /// Ranked from the first to the second included, negative ranks counting
+    /// from the end.
+    Rank(i64, i64),
+    /// Scored from the first to the second included.
+    Score(f64, f64),
+}
+
+/// `ZRANGE key start stop [BYSCORE] [WITHSCORES]` replies with members of the
+/// sorted set at `key` in score order, ranked from `start` to `stop`, or scored
+/// from `start` to `stop` with `BYSCORE`. `WITHSCORES` follows each member by
+/// its score.
+#[derive(Debug)]
+pub struct ZRange {
+    pub key: Bytes,
+    pub by: ZRangeBy,
+    pub with_scores: bool,
+}
+
+impl ZRange {
+    pub fn new(key: impl AsRef<[u8]>, by: ZRangeBy) -> ZRange {
+        ZRange {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            by,
+            with_scores: false,
+        }
+    }
+
+    pub fn with_scores(mut self) -> ZRange {
+        self.with_scores = true;
+        self
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<ZRange> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let start = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let stop = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let mut by_score = false;
+        let mut with_scores = false;
+        while let Some(option) = parser.next_string()? {
+            match option.to_lowercase().as_str() {
+                "byscore" => by_score = true,
+                "withscores" => with_scores = true,
+                _ => Err(CommandParseError::UnknownOption(option))?,
+            }
+        }
+        let by = if by_score {
+            ZRangeBy::Score(parse_double(&start)?, parse_double(&stop)?)
+        } else {
+            ZRangeBy::Rank(start.parse()?, stop.parse()?)
+        };
+        Ok(ZRange {
+            key,
+            by,
+            with_scores,
+        })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("zrange".to_string()), Frame::Binary(self.key)];
+        match self.by {
+            ZRangeBy::Rank(start, stop) => {
+                frame.push(Frame::Text(start.to_string()));
+                frame.push(Frame::Text(stop.to_string()));
+            }
+            ZRangeBy::Score(min, max) => {
+                frame.push(Frame::Text(format_double(min)));
+                frame.push(Frame::Text(format_double(max)));
+                frame.push(Frame::Text("byscore".to_string()));
+            }
+        }
+        if self.with_scores {
+            frame.push(Frame::Text("withscores".to_string()));
+        }
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let ZRange {
+            key,
+            by,
+            with_scores,
+        } = self;
+        let response = lookup(db, dst, key, |value| match value {
+            Some(Value::SortedSet(set)) => {
+                let range = match by {
+                    ZRangeBy::Rank(start, stop) => set.range_by_rank(start, stop),
+                    ZRangeBy::Score(min, max) => set.range_by_score(min, max),
+                };
+                let mut members = vec![];
+                for (member, score) in range {
+                    members.push(Frame::Binary(member.clone()));
+                    if with_scores {
+                        members.push(Frame::Double(score));
+                    }
+                }
+                Frame::Array(members)
+            }
+            Some(_) => wrong_type(),
+            None => Frame::Array(vec![]),
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,429 @@
+//! Commands on streams, see [`uranus_kv::stream`]
+//!
+//! Frames can't nest, so replies list entries in a row: the ID of each entry,
+//! the number of its fields, then every field followed by its value.
+//!
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::time::{self, Instant};
+use uranus_kv::{Stream, StreamFields, StreamId};
+
+use super::{lookup, wrong_type, CommandParseError, CommandParser};
+use crate::{wake_any, Connection, Database, ErrorCode, Frame, Shared, Value, Waiter};
+
+const ID_ZERO: &str = "The ID specified in XADD must be greater than 0-0";
+const ID_TOO_SMALL: &str =
+    "The ID specified in XADD is equal or smaller than the target stream top item";
+const IDS_EXHAUSTED: &str =
+    "The stream has exhausted the last possible ID, unable to add more items";
+
+/// The ID of an entry to add.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NewId {
+    /// `ms-*`, the next ID of `ms` or of the last entry if that is later. `*`
+    /// is parsed as the current time, so that the request replays the same.
+    Time(u64),
+    /// `ms-seq`, which must be greater than the ID of the last entry.
+    Exact(StreamId),
+}
+
+/// `XADD key id field value [field value ...]` appends an entry to the stream
+/// at `key`, creating it if needed. Replies with the ID of the entry.
+#[derive(Debug, Clone)]
+pub struct XAdd {
+    pub key: Bytes,
+    pub id: NewId,
+    pub fields: StreamFields,
+}
+
+impl XAdd {
+    /// Adds an entry under an ID made from the current time.
+    pub fn new(key: impl AsRef<[u8]>, fields: StreamFields) -> XAdd {
+        XAdd {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            id: NewId::Time(now_ms()),
+            fields,
+        }
+    }
+
+    pub fn with_id(mut self, id: NewId) -> XAdd {
+        self.id = id;
+        self
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<XAdd> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let id = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let id = match id.strip_suffix("-*") {
+            _ if id == "*" => NewId::Time(now_ms()),
+            Some(ms) => NewId::Time(ms.parse()?),
+            None => NewId::Exact(id.parse()?),
+        };
+        let mut fields = vec![];
+        while let Some(field) = parser.next_bytes()? {
+            let value = parser
+                .next_bytes()?
+                .ok_or(CommandParseError::UnexpectedEOF)?;
+            fields.push((field, value));
+        }
+        if fields.is_empty() {
+            Err(CommandParseError::UnexpectedEOF)?
+        }
+        Ok(XAdd { key, id, fields })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let id = match self.id {
+            NewId::Time(ms) => format!("{}-*", ms),
+            NewId::Exact(id) => id.to_string(),
+        };
+        let mut frame = vec![
+            Frame::Text("xadd".to_string()),
+            Frame::Binary(self.key),
+            Frame::Text(id),
+        ];
+        for (field, value) in self.fields {
+            frame.push(Frame::Binary(field));
+            frame.push(Frame::Binary(value));
+        }
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(
+        self,
+        db: &D,
+        dst: &mut Connection,
+        shared: &Shared,
+    ) -> Result<()> {
+        let XAdd { key, id, fields } = self;
+        let response = db.update(key.clone(), |value| {
+            let mut stream = match value.take() {
+                Some(Value::Stream(stream)) => stream,
+                None => Stream::new(),
+                Some(other) => {
+                    *value = Some(other);
+                    return wrong_type();
+                }
+            };
+            let id = match id {
+                NewId::Time(ms) => stream.next_id(ms),
+                NewId::Exact(id) => Some(id),
+            };
+            let response = match id {
+                Some(StreamId::MIN) => Frame::error(ErrorCode::Err, ID_ZERO),
+                Some(id) if id > stream.last_id() => {
+                    stream.add(id, fields);
+                    Frame::Binary(Bytes::from(id.to_string()))
+                }
+                Some(_) => Frame::error(ErrorCode::Err, ID_TOO_SMALL),
+                None => Frame::error(ErrorCode::Err, IDS_EXHAUSTED),
+            };
+            if !stream.is_empty() {
+                *value = Some(Value::Stream(stream));
+            }
+            response
+        })?;
+        shared.waiters.wake_all(&key);
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `XRANGE key start end [COUNT count]` replies with the entries of the stream
+/// at `key` from `start` to `end` included, up to `count` of them. `-` and `+`
+/// stand for the first and last IDs, and a time alone for all of its IDs.
+#[derive(Debug)]
+pub struct XRange {
+    pub key: Bytes,
+    pub start: StreamId,
+    pub end: StreamId,
+    pub count: Option<usize>,
+}
+
+impl XRange {
+    pub fn new(key: impl AsRef<[u8]>, start: StreamId, end: StreamId) -> XRange {
+        XRange {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            start,
+            end,
+            count: None,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<XRange> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let start = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let end = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let start = match start.as_str() {
+            "-" => StreamId::MIN,
+            start => start.parse()?,
+        };
+        let end = match end.as_str() {
+            "+" => StreamId::MAX,
+            end if !end.contains('-') => StreamId::new(end.parse()?, u64::MAX),
+            end => end.parse()?,
+        };
+        let mut xrange = XRange::new(key, start, end);
+        while let Some(option) = parser.next_string()? {
+            match option.to_lowercase().as_str() {
+                "count" => {
+                    let count = parser
+                        .next_string()?
+                        .ok_or(CommandParseError::UnexpectedEOF)?;
+                    xrange.count = Some(count.parse()?);
+                }
+                _ => Err(CommandParseError::UnknownOption(option))?,
+            }
+        }
+        Ok(xrange)
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![
+            Frame::Text("xrange".to_string()),
+            Frame::Binary(self.key),
+            Frame::Text(self.start.to_string()),
+            Frame::Text(self.end.to_string()),
+        ];
+        if let Some(count) = self.count {
+            frame.push(Frame::Text("count".to_string()));
+            frame.push(Frame::Text(count.to_string()));
+        }
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let (start, end) = (self.start, self.end);
+        let count = self.count.unwrap_or(usize::MAX);
+        let response = lookup(db, dst, self.key, |value| match value {
+            Some(Value::Stream(stream)) => {
+                let mut entries = vec![];
+                for (id, fields) in stream.range(start, end).take(count) {
+                    push_entry(&mut entries, id, fields);
+                }
+                Frame::Array(entries)
+            }
+            Some(_) => wrong_type(),
+            None => Frame::Array(vec![]),
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// Where `XREAD` starts reading a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XReadFrom {
+    /// After the given ID.
+    After(StreamId),
+    /// `$`, after the last entry when the command arrives.
+    Last,
+}
+
+/// `XREAD [COUNT count] [BLOCK ms] STREAMS key [key ...] id [id ...]` replies
+/// with the entries after each ID of the stream at the matching key, up to
+/// `count` of them per stream, each preceded by its key. With `BLOCK`, it
+/// waits up to `ms` milliseconds for an entry if there is none, or forever
+/// with 0. Replies with nil if there is no entry.
+#[derive(Debug)]
+pub struct XRead {
+    pub streams: Vec<(Bytes, XReadFrom)>,
+    pub count: Option<usize>,
+    /// Whether to wait for an entry if there is none.
+    pub block: bool,
+    /// How long to wait, None to wait forever.
+    pub timeout: Option<Duration>,
+}
+
+impl XRead {
+    pub fn new(streams: Vec<(Bytes, XReadFrom)>) -> XRead {
+        XRead {
+            streams,
+            count: None,
+            block: false,
+            timeout: None,
+        }
+    }
+
+    /// Waits up to `timeout` for an entry, or forever with none.
+    pub fn blocking(mut self, timeout: Option<Duration>) -> XRead {
+        self.block = true;
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<XRead> {
+        let mut xread = XRead::new(vec![]);
+        loop {
+            let option = parser
+                .next_string()?
+                .ok_or(CommandParseError::UnexpectedEOF)?;
+            let option = option.to_lowercase();
+            if option == "streams" {
+                break;
+            }
+            let amount = parser
+                .next_string()?
+                .ok_or(CommandParseError::UnexpectedEOF)?;
+            match option.as_str() {
+                "count" => xread.count = Some(amount.parse()?),
+                "block" => {
+                    let timeout = Duration::from_millis(amount.parse()?);
+                    xread = xread.blocking((!timeout.is_zero()).then_some(timeout));
+                }
+                _ => Err(CommandParseError::UnknownOption(option))?,
+            }
+        }
+        let mut args = vec![];
+        while let Some(arg) = parser.next_bytes()? {
+            args.push(arg);
+        }
+        if args.is_empty() || args.len() % 2 != 0 {
+            Err(CommandParseError::UnexpectedEOF)?
+        }
+        let ids = args.split_off(args.len() / 2);
+        for (key, id) in args.into_iter().zip(ids) {
+            let from = match std::str::from_utf8(&id)? {
+                "$" => XReadFrom::Last,
+                id => XReadFrom::After(id.parse()?),
+            };
+            xread.streams.push((key, from));
+        }
+        Ok(xread)
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("xread".to_string())];
+        if let Some(count) = self.count {
+            frame.push(Frame::Text("count".to_string()));
+            frame.push(Frame::Text(count.to_string()));
+        }
+        if self.block {
+            let timeout = self.timeout.unwrap_or_default().as_millis();
+            frame.push(Frame::Text("block".to_string()));
+            frame.push(Frame::Text(timeout.to_string()));
+        }
+        frame.push(Frame::Text("streams".to_string()));
+        let (keys, ids): (Vec<_>, Vec<_>) = self.streams.into_iter().unzip();
+        frame.extend(keys.into_iter().map(Frame::Binary));
+        frame.extend(ids.into_iter().map(|from| match from {
+            XReadFrom::After(id) => Frame::Text(id.to_string()),
+            XReadFrom::Last => Frame::Text("$".to_string()),
+        }));
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(
+        self,
+        db: &D,
+        dst: &mut Connection,
+        shared: &Shared,
+    ) -> Result<()> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut streams = vec![];
+        for (key, from) in self.streams {
+            let after = match from {
+                XReadFrom::After(id) => id,
+                XReadFrom::Last => db.view(key.clone(), |value| match value {
+                    Some(Value::Stream(stream)) => stream.last_id(),
+                    _ => StreamId::MIN,
+                })?,
+            };
+            streams.push((key, after));
+        }
+        let waiters: Vec<_> = if self.block {
+            let keys = streams.iter().map(|(key, _)| key.clone());
+            keys.map(|key| shared.waiters.register(key)).collect()
+        } else {
+            vec![]
+        };
+        // The keys are looked up once, rather than again on each wake.
+        let mut looked_up = false;
+        let response = loop {
+            let added = waiters.iter().map(Waiter::listen).collect();
+            let lookup = |hit| {
+                if !looked_up {
+                    dst.record_lookup(hit);
+                }
+            };
+            let read = read(db, &streams, self.count, lookup)?;
+            looked_up = true;
+            if let Some(response) = read {
+                break response;
+            }
+            if !self.block {
+                break Frame::Null;
+            }
+            match deadline {
+                Some(deadline) => {
+                    if time::timeout_at(deadline, wake_any(added)).await.is_err() {
+                        break Frame::Null;
+                    }
+                }
+                None => wake_any(added).await,
+            }
+        };
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// The reply of `XREAD` for the entries of `streams` after their IDs, none if
+/// there are none. `lookup` is told whether each key read was found.
+fn read<D: Database>(
+    db: &D,
+    streams: &[(Bytes, StreamId)],
+    count: Option<usize>,
+    mut lookup: impl FnMut(bool),
+) -> Result<Option<Frame>> {
+    let mut entries = vec![];
+    for (key, after) in streams {
+        let error = db.view(key.clone(), |value| {
+            lookup(value.is_some());
+            match value {
+                Some(Value::Stream(stream)) => {
+                    for (id, fields) in stream.after(*after).take(count.unwrap_or(usize::MAX)) {
+                        entries.push(Frame::Binary(key.clone()));
+                        push_entry(&mut entries, id, fields);
+                    }
+                    None
+                }
+                Some(_) => Some(wrong_type()),
+                None => None,
+            }
+        })?;
+        if error.is_some() {
+            return Ok(error);
+        }
+    }
+    Ok((!entries.is_empty()).then_some(Frame::Array(entries)))
+}
+
+fn push_entry(entries: &mut Vec<Frame>, id: &StreamId, fields: &StreamFields) {
+    entries.push(Frame::Binary(Bytes::from(id.to_string())));
+    entries.push(Frame::Integer(fields.len() as i64));
+    for (field, value) in fields {
+        entries.push(Frame::Binary(field.clone()));
+        entries.push(Frame::Binary(value.clone()));
+    }
+}
+
+fn now_ms() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    since_epoch.as_millis() as u64
+}
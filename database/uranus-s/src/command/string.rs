@@ -0,0 +1,528 @@
+//! Commands working on string values in place
+//!
+
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+
+use super::{lookup, wrong_type, CommandParseError, CommandParser};
+use crate::{Connection, Database, ErrorCode, Frame, Value};
+
+/// `APPEND key value` appends `value` to the string at `key`, creating it if
+/// needed. Replies with the length of the string afterwards.
+#[derive(Debug)]
+pub struct Append {
+    pub key: Bytes,
+    pub value: Bytes,
+}
+
+impl Append {
+    pub fn new(key: impl AsRef<[u8]>, value: Bytes) -> Append {
+        Append {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            value,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<Append> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let value = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(Append { key, value })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("append".to_string()),
+            Frame::Binary(self.key),
+            Frame::Binary(self.value),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let Append { key, value: suffix } = self;
+        let response = db.update(key, |value| {
+            match value.get_or_insert_with(|| Value::String(Bytes::new())) {
+                Value::String(string) => {
+                    let mut appended = BytesMut::with_capacity(string.len() + suffix.len());
+                    appended.extend_from_slice(string);
+                    appended.extend_from_slice(&suffix);
+                    *string = appended.freeze();
+                    Frame::Integer(string.len() as i64)
+                }
+                _ => wrong_type(),
+            }
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `MSET key value [key value ...]` sets every key to its value, making them
+/// persistent. Other connections see all of them set at once, or none.
+/// Replies with OK.
+#[derive(Debug)]
+pub struct MSet {
+    pub pairs: Vec<(Bytes, Bytes)>,
+}
+
+impl MSet {
+    pub fn new(pairs: Vec<(Bytes, Bytes)>) -> MSet {
+        MSet { pairs }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<MSet> {
+        let mut pairs = vec![];
+        while let Some(key) = parser.next_bytes()? {
+            let value = parser
+                .next_bytes()?
+                .ok_or_else(|| CommandParseError::WrongArity("mset".to_string()))?;
+            pairs.push((key, value));
+        }
+        if pairs.is_empty() {
+            Err(CommandParseError::UnexpectedEOF)?
+        }
+        Ok(MSet { pairs })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("mset".to_string())];
+        for (key, value) in self.pairs {
+            frame.push(Frame::Binary(key));
+            frame.push(Frame::Binary(value));
+        }
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let pairs = self.pairs.into_iter();
+        db.put_many(
+            pairs
+                .map(|(key, value)| (key, Value::String(value)))
+                .collect(),
+        )?;
+        dst.write_frame(&Frame::Text("OK".to_string())).await?;
+        Ok(())
+    }
+}
+
+/// `STRLEN key` replies with the length of the string at `key`, 0 if there is none.
+#[derive(Debug)]
+pub struct StrLen {
+    pub key: Bytes,
+}
+
+impl StrLen {
+    pub fn new(key: impl AsRef<[u8]>) -> StrLen {
+        StrLen {
+            key: Bytes::copy_from_slice(key.as_ref()),
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<StrLen> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(StrLen { key })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![Frame::Text("strlen".to_string()), Frame::Binary(self.key)];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let response = lookup(db, dst, self.key, |value| match value {
+            Some(Value::String(string)) => Frame::Integer(string.len() as i64),
+            Some(_) => wrong_type(),
+            None => Frame::Integer(0),
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `GETRANGE key start end` replies with the bytes of the string at `key` from
+/// offset `start` to `end` included. Negative offsets count from the end, -1
+/// being the last byte. Out of range offsets are clamped to the string.
+#[derive(Debug)]
+pub struct GetRange {
+    pub key: Bytes,
+    pub start: i64,
+    pub end: i64,
+}
+
+impl GetRange {
+    pub fn new(key: impl AsRef<[u8]>, start: i64, end: i64) -> GetRange {
+        GetRange {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            start,
+            end,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<GetRange> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let start = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<i64>()?;
+        let end = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<i64>()?;
+        Ok(GetRange { key, start, end })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("getrange".to_string()),
+            Frame::Binary(self.key),
+            Frame::Text(self.start.to_string()),
+            Frame::Text(self.end.to_string()),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let (start, end) = (self.start, self.end);
+        let response = lookup(db, dst, self.key, |value| match value {
+            Some(Value::String(string)) => Frame::Binary(substring(string, start, end)),
+            Some(_) => wrong_type(),
+            None => Frame::Binary(Bytes::new()),
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+fn substring(string: &Bytes, start: i64, end: i64) -> Bytes {
+    match range(string.len(), start, end) {
+        Some((start, end)) => string.slice(start..=end),
+        None => Bytes::new(),
+    }
+}
+
+/// The offsets from `start` to `end` included within `len`, negative ones
+/// counting from the end, none if the range is empty.
+fn range(len: usize, start: i64, end: i64) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let end = if end < 0 { len + end } else { end }.min(len - 1);
+    (start <= end).then_some((start as usize, end as usize))
+}
+
+/// The longest string `SETRANGE` and `SETBIT` may grow a value to, in bytes.
+pub const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+fn too_long() -> Frame {
+    Frame::error(
+        ErrorCode::Err,
+        "string exceeds maximum allowed size (proto-max-bulk-len)",
+    )
+}
+
+/// `SETRANGE key offset value` overwrites the string at `key` with `value` from
+/// `offset` on, padding it with zero bytes if it is shorter. Replies with the
+/// length of the string afterwards. An empty `value` creates no key.
+#[derive(Debug)]
+pub struct SetRange {
+    pub key: Bytes,
+    pub offset: usize,
+    pub value: Bytes,
+}
+
+impl SetRange {
+    pub fn new(key: impl AsRef<[u8]>, offset: usize, value: Bytes) -> SetRange {
+        SetRange {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            offset,
+            value,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<SetRange> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let offset = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<usize>()?;
+        let value = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        Ok(SetRange { key, offset, value })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("setrange".to_string()),
+            Frame::Binary(self.key),
+            Frame::Text(self.offset.to_string()),
+            Frame::Binary(self.value),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let SetRange { key, offset, value } = self;
+        let end = offset.saturating_add(value.len());
+        let response = db.update(key, |stored| match stored {
+            Some(Value::String(string)) if value.is_empty() => Frame::Integer(string.len() as i64),
+            None if value.is_empty() => Frame::Integer(0),
+            Some(Value::String(_)) | None if end > MAX_STRING_LEN => too_long(),
+            Some(Value::String(_)) | None => {
+                let string = match stored.take() {
+                    Some(Value::String(string)) => string,
+                    _ => Bytes::new(),
+                };
+                let mut written = BytesMut::from(&string[..]);
+                if written.len() < end {
+                    written.resize(end, 0);
+                }
+                written[offset..end].copy_from_slice(&value);
+                let len = written.len();
+                *stored = Some(Value::String(written.freeze()));
+                Frame::Integer(len as i64)
+            }
+            Some(_) => wrong_type(),
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `SETBIT key offset 0|1` sets the bit at `offset` of the string at `key`,
+/// growing it with zero bytes as needed. Bit 0 is the most significant bit of
+/// the first byte. Replies with the previous value of the bit.
+#[derive(Debug)]
+pub struct SetBit {
+    pub key: Bytes,
+    pub offset: u64,
+    pub bit: bool,
+}
+
+impl SetBit {
+    pub fn new(key: impl AsRef<[u8]>, offset: u64, bit: bool) -> SetBit {
+        SetBit {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            offset,
+            bit,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<SetBit> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let offset = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<u64>()?;
+        let bit = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let bit = match bit.as_str() {
+            "0" => false,
+            "1" => true,
+            _ => Err(CommandParseError::UnknownOption(bit))?,
+        };
+        Ok(SetBit { key, offset, bit })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("setbit".to_string()),
+            Frame::Binary(self.key),
+            Frame::Text(self.offset.to_string()),
+            Frame::Text(if self.bit { "1" } else { "0" }.to_string()),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let SetBit { key, offset, bit } = self;
+        let byte = (offset / 8) as usize;
+        let mask = 0x80u8 >> (offset % 8);
+        let response = db.update(key, |stored| match stored {
+            Some(Value::String(_)) | None if byte >= MAX_STRING_LEN => too_long(),
+            Some(Value::String(_)) | None => {
+                let string = match stored.take() {
+                    Some(Value::String(string)) => string,
+                    _ => Bytes::new(),
+                };
+                let mut written = BytesMut::from(&string[..]);
+                if written.len() <= byte {
+                    written.resize(byte + 1, 0);
+                }
+                let previous = written[byte] & mask != 0;
+                if bit {
+                    written[byte] |= mask;
+                } else {
+                    written[byte] &= !mask;
+                }
+                *stored = Some(Value::String(written.freeze()));
+                Frame::Integer(previous as i64)
+            }
+            Some(_) => wrong_type(),
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+/// `GETBIT key offset` replies with the bit at `offset` of the string at `key`,
+/// 0 past its end. Bits are numbered as by [`SetBit`].
+#[derive(Debug)]
+pub struct GetBit {
+    pub key: Bytes,
+    pub offset: u64,
+}
+
+impl GetBit {
+    pub fn new(key: impl AsRef<[u8]>, offset: u64) -> GetBit {
+        GetBit {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            offset,
+        }
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<GetBit> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let offset = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?
+            .parse::<u64>()?;
+        Ok(GetBit { key, offset })
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let frame = vec![
+            Frame::Text("getbit".to_string()),
+            Frame::Binary(self.key),
+            Frame::Text(self.offset.to_string()),
+        ];
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let offset = self.offset;
+        let response = lookup(db, dst, self.key, |value| match value {
+            Some(Value::String(string)) => Frame::Integer(bit_at(string, offset) as i64),
+            Some(_) => wrong_type(),
+            None => Frame::Integer(0),
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+fn bit_at(string: &[u8], offset: u64) -> bool {
+    let byte = usize::try_from(offset / 8).unwrap_or(usize::MAX);
+    string
+        .get(byte)
+        .is_some_and(|byte| byte & (0x80 >> (offset % 8)) != 0)
+}
+
+/// What the offsets of a [`BitCount`] range count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitUnit {
+    Byte,
+    Bit,
+}
+
+/// `BITCOUNT key [start end [BYTE|BIT]]` replies with the number of bits set
+/// in the string at `key`, within the bytes or bits from `start` to `end`
+/// included if given. Offsets are taken as by [`GetRange`].
+#[derive(Debug)]
+pub struct BitCount {
+    pub key: Bytes,
+    pub range: Option<(i64, i64, BitUnit)>,
+}
+
+impl BitCount {
+    pub fn new(key: impl AsRef<[u8]>) -> BitCount {
+        BitCount {
+            key: Bytes::copy_from_slice(key.as_ref()),
+            range: None,
+        }
+    }
+
+    /// Only counts the bits from `start` to `end`, in `unit`s.
+    pub fn with_range(mut self, start: i64, end: i64, unit: BitUnit) -> BitCount {
+        self.range = Some((start, end, unit));
+        self
+    }
+
+    pub fn parse_frames(parser: &mut CommandParser) -> Result<BitCount> {
+        let key = parser
+            .next_bytes()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let mut count = BitCount::new(key);
+        let Some(start) = parser.next_string()? else {
+            return Ok(count);
+        };
+        let end = parser
+            .next_string()?
+            .ok_or(CommandParseError::UnexpectedEOF)?;
+        let unit = match parser.next_string()? {
+            None => BitUnit::Byte,
+            Some(unit) => match unit.to_lowercase().as_str() {
+                "byte" => BitUnit::Byte,
+                "bit" => BitUnit::Bit,
+                _ => Err(CommandParseError::UnknownOption(unit))?,
+            },
+        };
+        count.range = Some((start.parse()?, end.parse()?, unit));
+        Ok(count)
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = vec![Frame::Text("bitcount".to_string()), Frame::Binary(self.key)];
+        if let Some((start, end, unit)) = self.range {
+            frame.push(Frame::Text(start.to_string()));
+            frame.push(Frame::Text(end.to_string()));
+            let unit = match unit {
+                BitUnit::Byte => "byte",
+                BitUnit::Bit => "bit",
+            };
+            frame.push(Frame::Text(unit.to_string()));
+        }
+        Frame::Array(frame)
+    }
+
+    pub async fn apply<D: Database>(self, db: &D, dst: &mut Connection) -> Result<()> {
+        let range = self.range;
+        let response = lookup(db, dst, self.key, |value| match value {
+            Some(Value::String(string)) => Frame::Integer(count_bits(string, range) as i64),
+            Some(_) => wrong_type(),
+            None => Frame::Integer(0),
+        })?;
+        dst.write_frame(&response).await?;
+        Ok(())
+    }
+}
+
+fn count_bits(string: &[u8], span: Option<(i64, i64, BitUnit)>) -> u64 {
+    match span {
+        None => string.iter().map(|byte| byte.count_ones() as u64).sum(),
+        Some((start, end, BitUnit::Byte)) => match range(string.len(), start, end) {
+            Some((start, end)) => count_bits(&string[start..=end], None),
+            None => 0,
+        },
+        Some((start, end, BitUnit::Bit)) => match range(string.len() * 8, start, end) {
+            Some((start, end)) => (start..=end)
+                .filter(|&offset| bit_at(string, offset as u64))
+                .count() as u64,
+            None => 0,
+        },
+    }
+}
@@ -0,0 +1,317 @@
+//! Statistics of the commands served
+//!
+//! Handlers count the calls of each command, those failing with an error
+//! reply, those rejected before they ran, say by the ACL or while loading,
+//! and how long they took. `INFO commandstats` reports the counters of the
+//! commands called so far, `INFO latencystats` the percentiles of their
+//! latencies, and so does the `/metrics` endpoint of the
+//! [probes](crate::health), so that operators see which commands dominate.
+//!
+//! Latencies are counted in a histogram of microseconds with 8 buckets per
+//! power of two, so percentiles are within 12.5% of the exact ones.
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+
+use crate::COMMANDS;
+
+/// The percentiles reported, as `p50`, `p99` and `p99.9`.
+pub const PERCENTILES: &[f64] = &[50.0, 99.0, 99.9];
+
+/// The counters of every command, shared by all connections.
+#[derive(Debug, Clone)]
+pub struct CommandStats {
+    commands: Arc<HashMap<&'static str, Counters>>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    calls: AtomicU64,
+    failed: AtomicU64,
+    rejected: AtomicU64,
+    usec: AtomicU64,
+    /// Allocated on the first call, most commands never being called.
+    latencies: OnceLock<Histogram>,
+}
+
+/// The statistics of a command at some point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandStat {
+    pub name: &'static str,
+    /// The calls which ran, failed ones included, but not rejected ones.
+    pub calls: u64,
+    /// The calls which ran but replied with an error.
+    pub failed: u64,
+    /// The calls refused before they ran.
+    pub rejected: u64,
+    /// The time the calls took, in microseconds.
+    pub usec: u64,
+    /// The [`PERCENTILES`] of the latencies of the calls, in microseconds.
+    pub percentiles: Vec<(f64, u64)>,
+}
+
+impl CommandStat {
+    pub fn usec_per_call(&self) -> f64 {
+        match self.calls {
+            0 => 0.0,
+            calls => self.usec as f64 / calls as f64,
+        }
+    }
+}
+
+impl Default for CommandStats {
+    fn default() -> Self {
+        CommandStats::new()
+    }
+}
+
+impl CommandStats {
+    pub fn new() -> CommandStats {
+        let commands = COMMANDS
+            .iter()
+            .map(|spec| (spec.name, Counters::default()))
+            .collect();
+        CommandStats {
+            commands: Arc::new(commands),
+        }
+    }
+
+    /// Counts a call of the command named `name`, which took `elapsed`, and
+    /// replied with an error if `failed`.
+    pub fn record(&self, name: &str, elapsed: Duration, failed: bool) {
+        let Some(counters) = self.commands.get(name) else {
+            return;
+        };
+        let usec = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        counters.calls.fetch_add(1, Ordering::Relaxed);
+        counters.usec.fetch_add(usec, Ordering::Relaxed);
+        if failed {
+            counters.failed.fetch_add(1, Ordering::Relaxed);
+        }
+        counters
+            .latencies
+            .get_or_init(Histogram::default)
+            .record(usec);
+    }
+
+    /// Counts a call of the command named `name` refused before it ran.
+    pub fn record_rejected(&self, name: &str) {
+        if let Some(counters) = self.commands.get(name) {
+            counters.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The statistics of the commands called or rejected so far, by name.
+    pub fn snapshot(&self) -> Vec<CommandStat> {
+        let mut stats: Vec<CommandStat> = self
+            .commands
+            .iter()
+            .filter_map(|(name, counters)| {
+                let calls = counters.calls.load(Ordering::Relaxed);
+                let rejected = counters.rejected.load(Ordering::Relaxed);
+                if calls == 0 && rejected == 0 {
+                    return None;
+                }
+                let percentiles = match counters.latencies.get() {
+                    Some(latencies) => latencies.percentiles(PERCENTILES),
+                    None => PERCENTILES.iter().map(|p| (*p, 0)).collect(),
+                };
+                Some(CommandStat {
+                    name,
+                    calls,
+                    failed: counters.failed.load(Ordering::Relaxed),
+                    rejected,
+                    usec: counters.usec.load(Ordering::Relaxed),
+                    percentiles,
+                })
+            })
+            .collect();
+        stats.sort_unstable_by_key(|stat| stat.name);
+        stats
+    }
+
+    /// The statistics in the Prometheus text format, served by the `/metrics`
+    /// endpoint of the [probes](crate::health).
+    pub fn prometheus(&self) -> String {
+        let stats = self.snapshot();
+        let mut text = String::new();
+        type Field = fn(&CommandStat) -> u64;
+        let counters: [(&str, &str, Field); 4] = [
+            ("calls", "Calls of the command which ran.", |s| s.calls),
+            ("failed_calls", "Calls replying with an error.", |s| {
+                s.failed
+            }),
+            ("rejected_calls", "Calls refused before running.", |s| {
+                s.rejected
+            }),
+            ("usec", "Microseconds spent running the command.", |s| {
+                s.usec
+            }),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(text, "# HELP uranus_command_{}_total {}", name, help);
+            let _ = writeln!(text, "# TYPE uranus_command_{}_total counter", name);
+            for stat in &stats {
+                let _ = writeln!(
+                    text,
+                    "uranus_command_{}_total{{command=\"{}\"}} {}",
+                    name,
+                    stat.name,
+                    value(stat)
+                );
+            }
+        }
+        let _ = writeln!(
+            text,
+            "# HELP uranus_command_latency_usec Percentiles of the latency of the command."
+        );
+        let _ = writeln!(text, "# TYPE uranus_command_latency_usec gauge");
+        for stat in &stats {
+            for (percentile, usec) in &stat.percentiles {
+                let _ = writeln!(
+                    text,
+                    "uranus_command_latency_usec{{command=\"{}\",quantile=\"{}\"}} {}",
+                    stat.name,
+                    quantile(*percentile),
+                    usec
+                );
+            }
+        }
+        text
+    }
+}
+
+/// `percentile` as a fraction, as `0.999` rather than `0.9990000000000001`.
+fn quantile(percentile: f64) -> String {
+    let quantile = format!("{:.6}", percentile / 100.0);
+    quantile
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Values under this many microseconds have a bucket each, larger ones share
+/// one of the 8 buckets of their power of two.
+const EXACT: u64 = 8;
+/// Latencies are capped to 2^40 microseconds, about 12 days.
+const MAX_POWER: u32 = 40;
+const BUCKETS: usize = ((MAX_POWER - 2) * EXACT as u32) as usize;
+
+#[derive(Debug)]
+struct Histogram {
+    buckets: Box<[AtomicU64]>,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+}
+
+impl Histogram {
+    fn record(&self, usec: u64) {
+        self.buckets[bucket(usec)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The largest value of the bucket holding each of `percentiles`.
+    fn percentiles(&self, percentiles: &[f64]) -> Vec<(f64, u64)> {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect();
+        let total: u64 = counts.iter().sum();
+        percentiles
+            .iter()
+            .map(|&percentile| {
+                let rank = ((percentile / 100.0 * total as f64).ceil() as u64).max(1);
+                let mut seen = 0;
+                let index = counts
+                    .iter()
+                    .position(|count| {
+                        seen += count;
+                        seen >= rank
+                    })
+                    .unwrap_or(0);
+                (percentile, bucket_max(index))
+            })
+            .collect()
+    }
+}
+
+fn bucket(usec: u64) -> usize {
+    let usec = usec.min((1 << MAX_POWER) - 1);
+    if usec < EXACT {
+        return usec as usize;
+    }
+    let power = 63 - usec.leading_zeros();
+    let offset = (usec >> (power - 3)) & (EXACT - 1);
+    ((power - 2) as u64 * EXACT + offset) as usize
+}
+
+fn bucket_max(index: usize) -> u64 {
+    let index = index as u64;
+    if index < EXACT {
+        return index;
+    }
+    let power = index / EXACT + 2;
+    let offset = index % EXACT;
+    let width = 1 << (power - 3);
+    ((EXACT + offset) << (power - 3)) + width - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets() {
+        for usec in [0, 1, 7, 8, 15, 16, 100, 1000, 123_456, 1 << 39] {
+            let index = bucket(usec);
+            assert!(bucket_max(index) >= usec, "{}", usec);
+            assert!(bucket_max(index) as f64 <= usec as f64 * 1.125 + 1.0);
+            if index > 0 {
+                assert!(bucket_max(index - 1) < usec, "{}", usec);
+            }
+        }
+        assert_eq!(bucket(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn test_command_stats() {
+        let stats = CommandStats::new();
+        for usec in 1..=100 {
+            stats.record("get", Duration::from_micros(usec), usec % 10 == 0);
+        }
+        stats.record_rejected("set");
+        stats.record("nosuchcommand", Duration::ZERO, false);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        let get = &snapshot[0];
+        assert_eq!(
+            (get.name, get.calls, get.failed, get.rejected),
+            ("get", 100, 10, 0)
+        );
+        assert_eq!(get.usec, 5050);
+        assert_eq!(get.usec_per_call(), 50.5);
+        assert_eq!(get.percentiles, [(50.0, 51), (99.0, 103), (99.9, 103)]);
+        let set = &snapshot[1];
+        assert_eq!((set.calls, set.rejected, set.usec_per_call()), (0, 1, 0.0));
+
+        let metrics = stats.prometheus();
+        assert!(metrics.contains("uranus_command_calls_total{command=\"get\"} 100\n"));
+        assert!(metrics.contains("uranus_command_rejected_calls_total{command=\"set\"} 1\n"));
+        assert!(metrics.contains("{command=\"get\",quantile=\"0.5\"} 51\n"));
+        assert!(metrics.contains("{command=\"get\",quantile=\"0.999\"} 103\n"));
+    }
+}
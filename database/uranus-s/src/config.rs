@@ -0,0 +1,255 @@
+//! Server configuration
+//!
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    AclUser, AofConfig, BufferConfig, ClusterConfig, Compression, EvictionPolicy, FrameLimits,
+    GossipConfig, HealthConfig, KeyspaceEvents, LazyFreeConfig, LogLevel, ShadowConfig,
+    StatsConfig, StorageEngine, TraceConfig, WalSync, DEFAULT_COMPRESSION_THRESHOLD,
+    DEFAULT_DATABASES,
+};
+
+/// Tunables of a uranus server. Pass it to [`crate::run_with_config`], or use
+/// [`crate::run`] to start with the defaults.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Commands running longer than this are reported by the slow command log.
+    /// It can be changed at runtime by `CONFIG SET slowlog-log-slower-than
+    /// <microseconds>`.
+    pub slowlog_threshold: Duration,
+    /// Connections idle for longer than this are closed, none for never. It can
+    /// be changed at runtime by `CONFIG SET timeout <seconds>`, 0 for never.
+    pub client_timeout: Option<Duration>,
+    /// Serve a sharded in-memory engine with this many shards instead of a single
+    /// hash map. It can be changed at runtime by `CONFIG SET shards <n>`.
+    pub shards: Option<usize>,
+    /// How long idempotency tokens of writes are remembered.
+    pub idempotency_ttl: Duration,
+    /// At most this many idempotency tokens are remembered.
+    pub idempotency_capacity: usize,
+    /// Mirror a sample of the commands to a secondary server.
+    pub shadow: Option<ShadowConfig>,
+    /// Announce writes, expiries and evictions on keyspace and keyevent
+    /// channels, as these say, see [`KeyspaceEvents`]. None by default.
+    pub keyspace_events: KeyspaceEvents,
+    /// Keys keep at most this many revisions, see [`History`](crate::History).
+    pub max_history_depth: usize,
+    /// How often memory held by expired keys is reclaimed. Expired keys are
+    /// hidden from commands right away regardless. It can be changed at runtime
+    /// by `CONFIG SET expiry-sweep-interval <milliseconds>`.
+    pub expiry_sweep_interval: Duration,
+    /// The number of logical databases, which connections pick with `SELECT`.
+    pub databases: usize,
+    /// The engine keeping the keys. `shards` only applies to the in-memory
+    /// one.
+    pub storage: StorageEngine,
+    /// Where connections dump their traffic when tracing is turned on, see
+    /// [`Tracer`](crate::Tracer).
+    pub trace: TraceConfig,
+    /// Clients sending larger frames get a protocol error and are disconnected.
+    pub frame_limits: FrameLimits,
+    /// How much connections read at once, see [`crate::buffer`].
+    pub buffers: BufferConfig,
+    /// How the keyspace is sampled for `INFO stats`, see [`crate::stats`].
+    pub stats: StatsConfig,
+    /// The approximate memory the keys may take, in bytes, 0 for no limit.
+    /// Writes past it evict keys by `eviction_policy`, or fail if it is
+    /// [`EvictionPolicy::NoEviction`].
+    pub max_memory: usize,
+    pub eviction_policy: EvictionPolicy,
+    /// Compress the string values of at least `compression_threshold` bytes,
+    /// see [`Compressed`](uranus_kv::Compressed). lz4 and zstd are behind the
+    /// `lz4` and `zstd` features. Both can be changed at runtime by `CONFIG
+    /// SET value-compression <none|lz4|zstd>` and `CONFIG SET
+    /// compression-threshold <bytes>`.
+    pub compression: Compression,
+    pub compression_threshold: usize,
+    /// When the LSM engine syncs its write-ahead log, see [`WalSync`]: once a
+    /// second by default. It can be changed at runtime by `CONFIG SET
+    /// wal-fsync <always|everysec|no>`.
+    pub wal_sync: WalSync,
+    /// Which deleted values are dropped in the background, see
+    /// [`crate::lazyfree`].
+    pub lazyfree: LazyFreeConfig,
+    /// Log the writes to an append-only file, replayed on startup, see
+    /// [`AppendOnlyFile`](crate::AppendOnlyFile).
+    pub aof: Option<AofConfig>,
+    /// Refuse writes with `-READONLY`, but from the link of the primary, as a
+    /// replica does, see [`ReplConf`](crate::ReplConf). It can be changed at
+    /// runtime by `CONFIG SET replica-read-only no`, say to promote a replica.
+    pub replica_read_only: bool,
+    /// The secret the primary gives in `REPLCONF PRIMARY` for its link to be
+    /// trusted, see [`ReplConf`](crate::ReplConf). Without one, no connection
+    /// is.
+    pub replication_secret: Option<String>,
+    /// Serve only the keys of the slots this node owns, redirecting requests
+    /// for the others, see [`crate::cluster`].
+    pub cluster: Option<ClusterConfig>,
+    /// Exchange the view of the cluster with the other nodes, see
+    /// [`crate::gossip`].
+    pub gossip: Option<GossipConfig>,
+    /// Scripts run by `EVAL` are aborted after this long, as no other command
+    /// runs meanwhile, see [`crate::scripting`].
+    pub script_time_limit: Duration,
+    /// The users connections may authenticate as, besides the default one
+    /// which a user of that name replaces, see [`crate::acl`]. More can be
+    /// added at runtime by `ACL SETUSER`.
+    pub acl: Vec<AclUser>,
+    /// The filter of the log installed by [`init_logging`](crate::init_logging),
+    /// which `CONFIG SET loglevel <directives>` changes at runtime. Without it,
+    /// the log level can't be changed.
+    pub log_level: Option<LogLevel>,
+    /// Serve liveness and readiness probes over HTTP, see [`crate::health`].
+    pub health: Option<HealthConfig>,
+    /// Make the connections accepted fail as these say, behind the `faults`
+    /// feature, see [`crate::fault`].
+    #[cfg(feature = "faults")]
+    pub faults: Option<crate::Faults>,
+}
+
+const DEFAULT_SLOWLOG_THRESHOLD: Duration = Duration::from_millis(10);
+const DEFAULT_IDEMPOTENCY_TTL: Duration = Duration::from_secs(60);
+const DEFAULT_IDEMPOTENCY_CAPACITY: usize = 10_000;
+const DEFAULT_MAX_HISTORY_DEPTH: usize = 100;
+const DEFAULT_EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_millis(100);
+const DEFAULT_SCRIPT_TIME_LIMIT: Duration = Duration::from_secs(5);
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            slowlog_threshold: DEFAULT_SLOWLOG_THRESHOLD,
+            client_timeout: None,
+            shards: None,
+            idempotency_ttl: DEFAULT_IDEMPOTENCY_TTL,
+            idempotency_capacity: DEFAULT_IDEMPOTENCY_CAPACITY,
+            shadow: None,
+            keyspace_events: KeyspaceEvents::none(),
+            max_history_depth: DEFAULT_MAX_HISTORY_DEPTH,
+            expiry_sweep_interval: DEFAULT_EXPIRY_SWEEP_INTERVAL,
+            databases: DEFAULT_DATABASES,
+            storage: StorageEngine::default(),
+            trace: TraceConfig::default(),
+            frame_limits: FrameLimits::default(),
+            buffers: BufferConfig::default(),
+            stats: StatsConfig::default(),
+            max_memory: 0,
+            eviction_policy: EvictionPolicy::default(),
+            compression: Compression::None,
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            wal_sync: WalSync::default(),
+            lazyfree: LazyFreeConfig::default(),
+            aof: None,
+            replica_read_only: false,
+            replication_secret: None,
+            cluster: None,
+            gossip: None,
+            script_time_limit: DEFAULT_SCRIPT_TIME_LIMIT,
+            acl: vec![],
+            log_level: None,
+            health: None,
+            #[cfg(feature = "faults")]
+            faults: None,
+        }
+    }
+}
+
+/// The tunables of a [`ServerConfig`] which `CONFIG SET` changes at runtime,
+/// shared by the subsystems consulting them. The others only apply at startup.
+/// The memory limit and the compression of values are kept by the database,
+/// see [`Database::memory_limit`](crate::Database::memory_limit) and
+/// [`Database::value_compression`](crate::Database::value_compression).
+#[derive(Debug, Clone)]
+pub struct RuntimeConfig {
+    inner: Arc<Tunables>,
+}
+
+#[derive(Debug)]
+struct Tunables {
+    slowlog_threshold_us: AtomicU64,
+    /// In milliseconds, 0 for none.
+    client_timeout_ms: AtomicU64,
+    expiry_sweep_interval_ms: AtomicU64,
+    replica_read_only: AtomicBool,
+    log_level: Option<LogLevel>,
+}
+
+impl RuntimeConfig {
+    pub fn new(config: &ServerConfig) -> RuntimeConfig {
+        let client_timeout = config
+            .client_timeout
+            .map_or(0, |timeout| timeout.as_millis());
+        RuntimeConfig {
+            inner: Arc::new(Tunables {
+                slowlog_threshold_us: AtomicU64::new(config.slowlog_threshold.as_micros() as u64),
+                client_timeout_ms: AtomicU64::new(client_timeout as u64),
+                expiry_sweep_interval_ms: AtomicU64::new(
+                    config.expiry_sweep_interval.as_millis() as u64
+                ),
+                replica_read_only: AtomicBool::new(config.replica_read_only),
+                log_level: config.log_level.clone(),
+            }),
+        }
+    }
+
+    /// See [`ServerConfig::slowlog_threshold`].
+    pub fn slowlog_threshold(&self) -> Duration {
+        Duration::from_micros(self.inner.slowlog_threshold_us.load(Ordering::Relaxed))
+    }
+
+    pub fn set_slowlog_threshold(&self, threshold: Duration) {
+        let threshold = threshold.as_micros() as u64;
+        self.inner
+            .slowlog_threshold_us
+            .store(threshold, Ordering::Relaxed);
+    }
+
+    /// See [`ServerConfig::client_timeout`].
+    pub fn client_timeout(&self) -> Option<Duration> {
+        match self.inner.client_timeout_ms.load(Ordering::Relaxed) {
+            0 => None,
+            millis => Some(Duration::from_millis(millis)),
+        }
+    }
+
+    pub fn set_client_timeout(&self, timeout: Option<Duration>) {
+        let timeout = timeout.map_or(0, |timeout| timeout.as_millis().max(1) as u64);
+        self.inner
+            .client_timeout_ms
+            .store(timeout, Ordering::Relaxed);
+    }
+
+    /// See [`ServerConfig::expiry_sweep_interval`].
+    pub fn expiry_sweep_interval(&self) -> Duration {
+        Duration::from_millis(self.inner.expiry_sweep_interval_ms.load(Ordering::Relaxed))
+    }
+
+    pub fn set_expiry_sweep_interval(&self, interval: Duration) {
+        let interval = interval.as_millis().max(1) as u64;
+        self.inner
+            .expiry_sweep_interval_ms
+            .store(interval, Ordering::Relaxed);
+    }
+
+    /// See [`ServerConfig::replica_read_only`].
+    pub fn replica_read_only(&self) -> bool {
+        self.inner.replica_read_only.load(Ordering::SeqCst)
+    }
+
+    pub fn set_replica_read_only(&self, read_only: bool) {
+        self.inner
+            .replica_read_only
+            .store(read_only, Ordering::SeqCst);
+    }
+
+    /// See [`ServerConfig::log_level`].
+    pub fn log_level(&self) -> Option<&LogLevel> {
+        self.inner.log_level.as_ref()
+    }
+}
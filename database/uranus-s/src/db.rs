@@ -1,34 +1,1438 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    iter,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+use crate::{spawn_named, Batch, BatchOp, Snapshot};
 use anyhow::Result;
 use bytes::Bytes;
-use uranus_kv::{StdHashKV, Storage};
+use tokio::{
+    runtime::{Handle, RuntimeFlavor},
+    task,
+};
+use uranus_kv::{
+    sharded::ShardedKV, Accounted, Compressed, Expiries, LsmConfig, LsmKV, StdHashKV, Storage,
+    StorageError,
+};
 
+pub use uranus_kv::{
+    Compression, EvictionPolicy, MemoryLimit, PriorityQueue, SortedSet, Stream, StreamFields,
+    StreamId, TableStats, Value, ValueCompression, WalSync, WalSyncPolicy, WriteStall,
+    DEFAULT_COMPRESSION_THRESHOLD,
+};
+
+/// When a conditional write goes through, see [`Database::put_if`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetCondition {
+    /// Only if the key has no value, `NX`.
+    Absent,
+    /// Only if the key has a value, `XX`.
+    Present,
+}
+
+/// How long a key has left to live, see [`Database::ttl`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyTtl {
+    /// There is no such key.
+    Missing,
+    /// The key has no expiry.
+    Persistent,
+    Remaining(Duration),
+}
+
+/// [`Database`] is everything the server needs from its storage. [`Handler`](crate::Handler)
+/// is generic over it, so embedders can serve their own backend, or a mock in tests.
+///
+/// A database is shared by all connections: it is cloned into every handler, and
+/// clones must observe each other's writes.
+pub trait Database: Clone + Send + Sync + 'static {
+    fn get(&self, key: Bytes) -> Result<Option<Value>>;
+    fn put(&self, key: Bytes, value: Value) -> Result<()>;
+    fn delete(&self, key: Bytes) -> Result<()>;
+    /// Returns every key-value pair currently stored, in no particular order.
+    fn scan(&self) -> Result<Vec<(Bytes, Value)>>;
+
+    /// Returns every key currently stored, in no particular order.
+    fn keys(&self) -> Result<Vec<Bytes>> {
+        Ok(self.scan()?.into_iter().map(|(key, _)| key).collect())
+    }
+
+    /// Every key starting with `prefix`, in order, see
+    /// [`Storage::keys_with_prefix`].
+    fn keys_with_prefix(&self, prefix: &[u8]) -> Result<Vec<Bytes>> {
+        let mut keys = self.keys()?;
+        keys.retain(|key| key.starts_with(prefix));
+        keys.sort_unstable();
+        Ok(keys)
+    }
+
+    /// The number of keys currently stored.
+    fn len(&self) -> Result<usize> {
+        Ok(self.scan()?.len())
+    }
+
+    fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Whether `key` is stored. Backends evicting keys by their use shouldn't
+    /// count it as one, see [`Storage::contains`].
+    fn contains(&self, key: Bytes) -> Result<bool> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Runs `f` on the value of `key`, which it may modify, replace, or remove by
+    /// leaving `None`. Commands use it for read-modify-write cycles, so backends
+    /// shared by several connections should make it atomic. The default
+    /// implementation merely reads then writes back.
+    fn update<R>(&self, key: Bytes, f: impl FnOnce(&mut Option<Value>) -> R) -> Result<R> {
+        let mut value = self.get(key.clone())?;
+        let existed = value.is_some();
+        let result = f(&mut value);
+        match value {
+            Some(value) => self.put(key, value)?,
+            None if existed => self.delete(key)?,
+            None => {}
+        }
+        Ok(result)
+    }
+
+    /// Runs `f` on the value of `key`, which it only reads. The default
+    /// implementation runs it on a copy, as [`Database::get`] returns.
+    fn view<R>(&self, key: Bytes, f: impl FnOnce(Option<&Value>) -> R) -> Result<R> {
+        let value = self.get(key)?;
+        Ok(f(value.as_ref()))
+    }
+
+    /// The number of shards of the storage engine, if it is sharded.
+    fn shard_count(&self) -> Option<usize> {
+        None
+    }
+
+    /// Changes the number of shards of the storage engine. Keys are moved in the
+    /// background, and stay readable meanwhile.
+    fn reshard(&self, _shards: usize) -> Result<()> {
+        Err(StorageError::Unsupported)?
+    }
+
+    /// Stores `value` under `key` for `ttl`, after which the key disappears.
+    /// Other updates of the key keep its TTL, a later [`Database::put`] drops it.
+    fn put_with_ttl(&self, _key: Bytes, _value: Value, _ttl: Duration) -> Result<()> {
+        Err(StorageError::Unsupported)?
+    }
+
+    /// Makes the existing `key` expire after `ttl`, replacing its TTL if it had
+    /// one. Returns whether there is such a key.
+    fn expire_in(&self, _key: Bytes, _ttl: Duration) -> Result<bool> {
+        Err(StorageError::Unsupported)?
+    }
+
+    /// Makes `key` persistent, returns whether it had a TTL.
+    fn persist(&self, _key: Bytes) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// How long `key` has left to live.
+    fn ttl(&self, key: Bytes) -> Result<KeyTtl> {
+        Ok(match self.get(key)? {
+            Some(_) => KeyTtl::Persistent,
+            None => KeyTtl::Missing,
+        })
+    }
+
+    /// Stores every pair, making the keys persistent. Backends shared by
+    /// several connections should make it atomic, the default implementation
+    /// merely stores them one after the other.
+    fn put_many(&self, pairs: Vec<(Bytes, Value)>) -> Result<()> {
+        for (key, value) in pairs {
+            self.put(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the writes of `batch` in order. Backends shared by several
+    /// connections should make it atomic, the default implementation merely
+    /// applies them one after the other.
+    fn write_batch(&self, batch: Batch) -> Result<()> {
+        for op in batch {
+            match op {
+                BatchOp::Put(key, value) => self.put(key, value)?,
+                BatchOp::Delete(key) => self.delete(key)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// A copy of every key, see [`Snapshot`]. Backends shared by several
+    /// connections should take it atomically, the default implementation
+    /// merely [scans](Database::scan) them.
+    fn snapshot(&self) -> Result<Snapshot> {
+        Ok(self.scan()?.into_iter().collect())
+    }
+
+    /// Makes the writes so far durable, see [`Storage::sync`].
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Moves the value of `src` to `dst`, overwriting it. Returns whether there
+    /// was such a key. Backends shared by several connections should make it
+    /// atomic, the default implementation doesn't, and drops the TTL.
+    fn rename(&self, src: Bytes, dst: Bytes) -> Result<bool> {
+        let Some(value) = self.get(src.clone())? else {
+            return Ok(false);
+        };
+        self.delete(src)?;
+        self.put(dst, value)?;
+        Ok(true)
+    }
+
+    /// Copies the value of `src` to `dst`, unless `dst` exists and not
+    /// `replace`. Returns whether it was copied. Like [`Database::rename`], the
+    /// default implementation is neither atomic nor keeps the TTL.
+    fn copy(&self, src: Bytes, dst: Bytes, replace: bool) -> Result<bool> {
+        if !replace && self.get(dst.clone())?.is_some() {
+            return Ok(false);
+        }
+        let Some(value) = self.get(src)? else {
+            return Ok(false);
+        };
+        self.put(dst, value)?;
+        Ok(true)
+    }
+
+    /// Stores `value` under `key`, for `ttl` if given, only if `condition` holds.
+    /// Returns whether it was stored. Backends shared by several connections
+    /// should make the check and the write atomic, the default implementation
+    /// doesn't.
+    fn put_if(
+        &self,
+        key: Bytes,
+        value: Value,
+        ttl: Option<Duration>,
+        condition: SetCondition,
+    ) -> Result<bool> {
+        let present = self.get(key.clone())?.is_some();
+        if present != (condition == SetCondition::Present) {
+            return Ok(false);
+        }
+        match ttl {
+            Some(ttl) => self.put_with_ttl(key, value, ttl)?,
+            None => self.put(key, value)?,
+        }
+        Ok(true)
+    }
+
+    /// Replaces the value of `key` by `new` only if it is `expected`, returns
+    /// whether it was replaced.
+    fn compare_and_swap(&self, key: Bytes, expected: Value, new: Value) -> Result<bool> {
+        self.update(key, |value| {
+            if value.as_ref() != Some(&expected) {
+                return false;
+            }
+            *value = Some(new);
+            true
+        })
+    }
+
+    /// Reclaims the expired keys, returns them. The server calls it
+    /// periodically.
+    fn expire(&self) -> Result<Vec<Bytes>> {
+        Ok(vec![])
+    }
+
+    /// The number of logical databases, numbered from 0.
+    fn databases(&self) -> usize {
+        1
+    }
+
+    /// A handle on the logical database `index`, none if there is no such
+    /// database. Handles on the same database observe each other's writes.
+    fn select(&self, index: usize) -> Option<Self> {
+        (index == 0).then(|| self.clone())
+    }
+
+    /// The index of the logical database this handle works on.
+    fn index(&self) -> usize {
+        0
+    }
+
+    /// Removes every key of this logical database.
+    fn flush(&self) -> Result<()> {
+        for (key, _) in self.scan()? {
+            self.delete(key)?;
+        }
+        Ok(())
+    }
+
+    /// Evicts keys until the memory they take is back under the limit, returns
+    /// them. Fails with [`StorageError::OutOfMemory`] if that isn't possible.
+    /// The server calls it before every write.
+    fn make_room(&self) -> Result<Vec<Bytes>> {
+        Ok(vec![])
+    }
+
+    /// The memory limit of the storage, if it has one. It can be changed at
+    /// runtime.
+    fn memory_limit(&self) -> Option<Arc<MemoryLimit>> {
+        None
+    }
+
+    /// How often `key` is used, see [`Storage::frequency`], if there is such a
+    /// key and the storage counts it. Reading it isn't a use.
+    fn frequency(&self, _key: Bytes) -> Result<Option<u8>> {
+        Ok(None)
+    }
+
+    /// How writes to this logical database should be held back, see
+    /// [`Storage::write_stall`]. The server checks it before every write.
+    fn write_stall(&self) -> Result<WriteStall> {
+        Ok(WriteStall::None)
+    }
+
+    /// How the tables of the storage fare, see [`Storage::table_stats`], if
+    /// it keeps its keys in tables.
+    fn table_stats(&self) -> Option<TableStats> {
+        None
+    }
+
+    /// Does the work the writes left for later, see [`Storage::compact`]. The
+    /// server calls it periodically.
+    fn compact(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// How the storage compresses string values, if it does. It can be
+    /// changed at runtime.
+    fn value_compression(&self) -> Option<Arc<ValueCompression>> {
+        None
+    }
+
+    /// When the storage syncs its write-ahead log, if it has one. It can be
+    /// changed at runtime.
+    fn wal_sync(&self) -> Option<Arc<WalSyncPolicy>> {
+        None
+    }
+
+    /// Removes every key of all logical databases.
+    fn flush_all(&self) -> Result<()> {
+        for index in 0..self.databases() {
+            if let Some(db) = self.select(index) {
+                db.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The default [`Database`], [`Storage`] engines behind read-write locks for
+/// each of its logical databases. A handle works on the database it
+/// [selected](Database::select), the first one initially.
+///
+/// The keys of an in-memory database are split by their hash over
+/// [`DEFAULT_STRIPES`] engines, each locked on its own, so that commands on
+/// keys of different stripes don't wait for each other. Reads only share the
+/// lock of their stripe, so they don't wait for each other either, but for
+/// the writes of the stripe. Commands on several
+/// keys lock their stripes in order, and those on the whole database all of
+/// them, so that two commands never wait for each other. Engines which
+/// [block](Storage::blocks) have a single stripe, their data being laid out on
+/// disk.
+///
+/// Engines expire keys themselves when they [support it](Storage::supports_ttl).
+/// For the others, the handle keeps the deadlines aside, drops expired keys
+/// when they are accessed, and sweeps the rest in [`Database::expire`].
+///
+/// The memory of the keys is accounted for across all logical databases, see
+/// [`DBHandle::limit_memory`], after their string values are compressed, see
+/// [`DBHandle::compress_values`].
 #[derive(Debug, Clone)]
 pub struct DBHandle {
-    storage: Arc<Mutex<dyn Storage + Send + Sync>>,
+    keyspaces: Arc<[Keyspace]>,
+    selected: usize,
+    memory: Arc<MemoryLimit>,
+    compression: Arc<ValueCompression>,
+    /// Shared by the engines keeping a log.
+    wal_sync: Option<Arc<WalSyncPolicy>>,
+}
+
+/// One logical database, locked independently of the others.
+#[derive(Debug)]
+struct Keyspace {
+    stripes: Box<[Stripe]>,
+    /// Whether the engines [block](Storage::blocks).
+    blocks: bool,
+}
+
+/// The keys of a logical database whose hash falls on it, in an engine of
+/// their own.
+#[derive(Debug)]
+struct Stripe {
+    /// Shared by reads, which the engines serve from `&self`.
+    storage: RwLock<Box<Engine>>,
+    /// Deadlines of the keys of engines without native TTL, always locked
+    /// after `storage`.
+    expiries: Mutex<Expiries>,
+    blocks: bool,
+}
+
+type Engine = dyn Storage + Send + Sync;
+
+impl Keyspace {
+    fn new(engines: Vec<Box<Engine>>) -> Keyspace {
+        let stripes: Box<[Stripe]> = engines
+            .into_iter()
+            .map(|storage| Stripe {
+                blocks: storage.blocks(),
+                storage: RwLock::new(storage),
+                expiries: Mutex::new(Expiries::new()),
+            })
+            .collect();
+        Keyspace {
+            blocks: stripes.iter().any(|stripe| stripe.blocks),
+            stripes,
+        }
+    }
+
+    /// The stripe holding `key`.
+    fn stripe(&self, key: &[u8]) -> &Stripe {
+        &self.stripes[self.stripe_index(key)]
+    }
+
+    fn stripe_index(&self, key: &[u8]) -> usize {
+        if self.stripes.len() == 1 {
+            return 0;
+        }
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.stripes.len() as u64) as usize
+    }
+
+    /// Runs `f` with the stripes of `keys` locked, see [`Keyspace::lock`].
+    fn run_keys<R>(&self, keys: &[&[u8]], f: impl FnOnce(&mut Locked) -> R) -> R {
+        let indices = keys.iter().map(|key| self.stripe_index(key)).collect();
+        blocking(self.blocks, || f(&mut self.lock(indices)))
+    }
+
+    /// Runs `f` with every stripe locked, see [`Keyspace::lock`].
+    fn run_all<R>(&self, f: impl FnOnce(&mut Locked) -> R) -> R {
+        let indices = (0..self.stripes.len()).collect();
+        blocking(self.blocks, || f(&mut self.lock(indices)))
+    }
+
+    /// Runs `f` with every stripe locked for reading, in the same order as
+    /// [`Keyspace::lock`].
+    fn read_all<R>(&self, f: impl FnOnce(Vec<(&Stripe, &Engine)>) -> R) -> R {
+        blocking(self.blocks, || {
+            let guards: Vec<_> = (self.stripes.iter())
+                .map(|stripe| (stripe, stripe.lock_read()))
+                .collect();
+            f(guards
+                .iter()
+                .map(|(stripe, db)| (*stripe, &***db))
+                .collect())
+        })
+    }
+
+    /// Runs `f` on each stripe in turn, only locking one at a time.
+    fn each(&self, mut f: impl FnMut(&Stripe, &mut Engine) -> Result<()>) -> Result<()> {
+        for stripe in self.stripes.iter() {
+            stripe.run(|db| f(stripe, db))?;
+        }
+        Ok(())
+    }
+
+    /// Locks the stripes `indices` in the order of their index, which is what
+    /// keeps commands locking several of them from waiting for each other.
+    fn lock(&self, mut indices: Vec<usize>) -> Locked<'_> {
+        indices.sort_unstable();
+        indices.dedup();
+        let stripes = indices
+            .into_iter()
+            .map(|index| {
+                let stripe = &self.stripes[index];
+                (index, stripe, stripe.lock_write())
+            })
+            .collect();
+        Locked {
+            keyspace: self,
+            stripes,
+        }
+    }
+}
+
+/// Stripes of a keyspace locked together, see [`Keyspace::lock`].
+struct Locked<'a> {
+    keyspace: &'a Keyspace,
+    stripes: Vec<(usize, &'a Stripe, RwLockWriteGuard<'a, Box<Engine>>)>,
+}
+
+impl<'a> Locked<'a> {
+    /// The stripe of `key` and its engine. It has to be one of those locked.
+    fn stripe(&mut self, key: &[u8]) -> (&'a Stripe, &mut Engine) {
+        let index = self.keyspace.stripe_index(key);
+        let (_, stripe, guard) = (self.stripes.iter_mut())
+            .find(|(locked, _, _)| *locked == index)
+            .expect("the stripe of the key is locked");
+        (*stripe, &mut ***guard)
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = (&'a Stripe, &mut Engine)> {
+        (self.stripes.iter_mut()).map(|(_, stripe, guard)| (*stripe, &mut ***guard))
+    }
+
+    /// Evicts a key chosen by `policy`: from the stripe holding the least
+    /// recently used key under [`EvictionPolicy::Lru`], the least frequently
+    /// used one under [`EvictionPolicy::Lfu`], from the first stripe having
+    /// one from a random start otherwise.
+    fn evict(&mut self, policy: EvictionPolicy) -> Option<Bytes> {
+        let mut stripes: Vec<_> = self.iter_mut().collect();
+        if policy == EvictionPolicy::Lru {
+            stripes.sort_by_key(|(_, db)| db.least_recent_use().unwrap_or(u64::MAX));
+        } else if policy == EvictionPolicy::Lfu {
+            stripes.sort_by_key(|(_, db)| db.least_frequent_use().unwrap_or(u8::MAX));
+        } else if !stripes.is_empty() {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let start = nanos.subsec_nanos() as usize % stripes.len();
+            stripes.rotate_left(start);
+        }
+        for (stripe, db) in stripes {
+            if let Some(key) = db.evict(policy) {
+                stripe.expiries().clear(&key);
+                return Some(key);
+            }
+        }
+        None
+    }
+}
+
+impl Stripe {
+    /// Locks the engine for writing. A command panicking under the lock
+    /// poisons it, which is ignored as the server carries on, see
+    /// [`DBHandle::update`].
+    fn lock_write(&self) -> RwLockWriteGuard<'_, Box<Engine>> {
+        self.storage.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_read(&self) -> RwLockReadGuard<'_, Box<Engine>> {
+        self.storage.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Like [`Stripe::lock_write`], for the deadlines of the keys.
+    fn expiries(&self) -> MutexGuard<'_, Expiries> {
+        self.expiries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Runs `f` on the engine locked for writing, off the event loop if it
+    /// blocks. The lock is taken there too, as waiting for it may take as long.
+    fn run<R>(&self, f: impl FnOnce(&mut Engine) -> R) -> R {
+        blocking(self.blocks, || f(&mut **self.lock_write()))
+    }
+
+    /// Like [`Stripe::run`], but only sharing the lock with other reads.
+    fn read<R>(&self, f: impl FnOnce(&Engine) -> R) -> R {
+        blocking(self.blocks, || f(&**self.lock_read()))
+    }
+
+    /// Stores `value` under `key` in `db`, the locked engine of the stripe,
+    /// until `expires_at` if given, natively or not.
+    fn store(
+        &self,
+        db: &mut Engine,
+        key: Bytes,
+        value: Value,
+        expires_at: Option<SystemTime>,
+    ) -> Result<()> {
+        let mut expiries = self.expiries();
+        let Some(expires_at) = expires_at else {
+            expiries.clear(&key);
+            return db.put(key, value);
+        };
+        if db.supports_ttl() {
+            db.put_with_ttl(key, value, expires_at)
+        } else {
+            db.put(key.clone(), value)?;
+            expiries.set(key, expires_at);
+            Ok(())
+        }
+    }
+
+    /// When `key` expires, natively or not.
+    fn expires_at(&self, db: &Engine, key: &Bytes) -> Option<SystemTime> {
+        db.expires_at(key).or_else(|| self.expiries().get(key))
+    }
+
+    /// Whether `key` has expired, for engines without native TTL. Reads skip
+    /// such keys, leaving them to the next write or sweep.
+    fn is_expired(&self, key: &Bytes) -> bool {
+        self.expiries().is_expired(key, SystemTime::now())
+    }
+
+    /// Drops `key` if it has expired, for engines without native TTL.
+    fn purge_expired(&self, db: &mut Engine, key: &Bytes) -> Result<()> {
+        let mut expiries = self.expiries();
+        if expiries.is_expired(key, SystemTime::now()) {
+            expiries.clear(key);
+            db.remove(key.clone())?;
+        }
+        Ok(())
+    }
+}
+
+/// When a key stored now for `ttl` expires.
+fn deadline(ttl: Option<Duration>) -> Option<SystemTime> {
+    ttl.map(|ttl| SystemTime::now() + ttl)
+}
+
+/// Runs `f`, on a worker thread if it `blocks`, see [`run_blocking`].
+fn blocking<R>(blocks: bool, f: impl FnOnce() -> R) -> R {
+    if blocks {
+        run_blocking(f)
+    } else {
+        f()
+    }
+}
+
+/// Runs `f` on a worker thread that the runtime replaces in the meantime, so
+/// the tasks queued on it keep going. Outside of a multi-threaded runtime,
+/// there is no other thread to hand them to and `f` runs as is.
+fn run_blocking<R>(f: impl FnOnce() -> R) -> R {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
+/// The number of logical databases of [`DBHandle::new`] and [`DBHandle::sharded`].
+pub const DEFAULT_DATABASES: usize = 16;
+
+/// The number of stripes of the in-memory logical databases of a [`DBHandle`].
+pub const DEFAULT_STRIPES: usize = 16;
+
+/// The engine keeping the keys of a [`DBHandle`], chosen at startup.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StorageEngine {
+    /// [`StdHashKV`], or [`ShardedKV`] when sharded. Keys live in memory only.
+    #[default]
+    Memory,
+    /// [`LsmKV`], keeping logical database `i` in `data_dir/i` across restarts.
+    /// Its tables are merged in the background, writes being held back when
+    /// the merges fall behind, see [`WriteStall`].
+    Lsm { data_dir: PathBuf },
 }
 
 impl DBHandle {
-    pub fn new() -> DBHandle {
-        DBHandle {
-            storage: Arc::new(Mutex::new(StdHashKV::new())),
+    /// A database on `storage`, opening the data it holds already.
+    pub fn new(storage: &StorageEngine) -> Result<DBHandle> {
+        DBHandle::open(DEFAULT_DATABASES, None, storage)
+    }
+
+    /// A database with `databases` logical databases on `storage`. Only the
+    /// in-memory engines have `shards`.
+    pub fn open(
+        databases: usize,
+        shards: Option<usize>,
+        storage: &StorageEngine,
+    ) -> Result<DBHandle> {
+        match storage {
+            StorageEngine::Memory => Ok(DBHandle::with_databases(databases, shards)),
+            StorageEngine::Lsm { data_dir } => {
+                let config = LsmConfig {
+                    background_merges: true,
+                    ..LsmConfig::new(data_dir)
+                };
+                DBHandle::with_lsm(databases, &config)
+            }
+        }
+    }
+
+    /// A database with `databases` logical databases on [`LsmKV`] engines
+    /// configured by `config`, keeping logical database `i` in `config.dir/i`.
+    /// They share [`LsmConfig::wal_sync`].
+    pub fn with_lsm(databases: usize, config: &LsmConfig) -> Result<DBHandle> {
+        let db = DBHandle::build(databases, |index, layers| {
+            let config = LsmConfig {
+                dir: config.dir.join(index.to_string()),
+                ..config.clone()
+            };
+            Ok(vec![layers.wrap(LsmKV::open(config)?)?])
+        })?;
+        Ok(DBHandle {
+            wal_sync: Some(config.wal_sync.clone()),
+            ..db
+        })
+    }
+
+    /// A database on [`ShardedKV`] engines with `shards` shards.
+    pub fn sharded(shards: usize) -> DBHandle {
+        DBHandle::with_databases(DEFAULT_DATABASES, Some(shards))
+    }
+
+    /// A database with `databases` logical databases, on [`ShardedKV`] engines
+    /// with `shards` shards if given, on [`StdHashKV`] ones otherwise.
+    pub fn with_databases(databases: usize, shards: Option<usize>) -> DBHandle {
+        DBHandle::build(databases, |_, layers| {
+            (0..DEFAULT_STRIPES)
+                .map(|_| match shards {
+                    Some(shards) => layers.wrap(ShardedKV::new(shards)),
+                    None => layers.wrap(StdHashKV::new()),
+                })
+                .collect()
+        })
+        .expect("an empty engine can be scanned")
+    }
+
+    /// A database with `databases` logical databases, on the engines `engine`
+    /// makes for each index, each a single stripe. Engines which
+    /// [block](Storage::blocks), such as disk-backed ones, are run off the
+    /// event loop.
+    pub fn with_storage<S, F>(databases: usize, mut engine: F) -> DBHandle
+    where
+        S: Storage + Send + Sync + 'static,
+        F: FnMut(usize) -> S,
+    {
+        DBHandle::build(databases, |index, layers| {
+            Ok(vec![layers.wrap(engine(index))?])
+        })
+        .expect("an engine can be scanned")
+    }
+
+    fn build(
+        databases: usize,
+        mut stripes: impl FnMut(usize, &Layers) -> Result<Vec<Box<Engine>>>,
+    ) -> Result<DBHandle> {
+        let layers = Layers {
+            memory: Arc::new(MemoryLimit::default()),
+            compression: Arc::new(ValueCompression::new(
+                Compression::None,
+                DEFAULT_COMPRESSION_THRESHOLD,
+            )),
+        };
+        let keyspaces = (0..databases.max(1))
+            .map(|index| Ok(Keyspace::new(stripes(index, &layers)?)))
+            .collect::<Result<_>>()?;
+        Ok(DBHandle {
+            keyspaces,
+            selected: 0,
+            memory: layers.memory,
+            compression: layers.compression,
+            wal_sync: None,
+        })
+    }
+
+    /// Bounds the memory of the keys to `max` bytes, evicting them by `policy`
+    /// past it. There is no limit by default.
+    pub fn limit_memory(self, max: usize, policy: EvictionPolicy) -> DBHandle {
+        self.memory.set_max(max);
+        self.memory.set_policy(policy);
+        self
+    }
+
+    /// Compresses the string values of at least `threshold` bytes by
+    /// `compression`. Values are stored as is by default.
+    pub fn compress_values(self, compression: Compression, threshold: usize) -> DBHandle {
+        self.compression.set_compression(compression);
+        self.compression.set_threshold(threshold);
+        self
+    }
+
+    /// Syncs the logs of the engines by `policy`, if they keep one.
+    /// [`WalSync::EverySecond`] by default.
+    pub fn sync_wal(self, policy: WalSync) -> DBHandle {
+        if let Some(wal_sync) = &self.wal_sync {
+            wal_sync.set(policy);
+        }
+        self
+    }
+
+    /// Syncs the database, see [`Database::sync`], and drops this handle. The
+    /// engines are closed once all the clones of the handle are dropped.
+    pub fn close(self) -> Result<()> {
+        self.sync()
+    }
+
+    fn keyspace(&self) -> &Keyspace {
+        &self.keyspaces[self.selected]
+    }
+
+    /// The stripe of `key` in the selected database.
+    fn stripe(&self, key: &[u8]) -> &Stripe {
+        self.keyspace().stripe(key)
+    }
+}
+
+/// What every engine of a [`DBHandle`] is wrapped in, shared by all of them.
+struct Layers {
+    memory: Arc<MemoryLimit>,
+    compression: Arc<ValueCompression>,
+}
+
+impl Layers {
+    /// `engine` with the memory of its keys accounted for, including those it
+    /// holds already, and its string values compressed. The memory accounted
+    /// for is that of the compressed values.
+    fn wrap<S>(&self, engine: S) -> Result<Box<Engine>>
+    where
+        S: Storage + Send + Sync + 'static,
+    {
+        let accounted = Accounted::new(engine, self.memory.clone())?;
+        Ok(Box::new(Compressed::new(
+            accounted,
+            self.compression.clone(),
+        )))
+    }
+}
+
+impl Database for DBHandle {
+    fn get(&self, key: Bytes) -> Result<Option<Value>> {
+        let stripe = self.stripe(&key);
+        stripe.read(|db| {
+            if stripe.is_expired(&key) {
+                return Ok(None);
+            }
+            db.get(key)
+        })
+    }
+
+    fn put(&self, key: Bytes, value: Value) -> Result<()> {
+        let stripe = self.stripe(&key);
+        stripe.run(|db| stripe.store(db, key, value, None))
+    }
+
+    fn delete(&self, key: Bytes) -> Result<()> {
+        let stripe = self.stripe(&key);
+        stripe.run(|db| {
+            stripe.purge_expired(db, &key)?;
+            stripe.expiries().clear(&key);
+            db.delete(key)
+        })
+    }
+
+    fn scan(&self) -> Result<Vec<(Bytes, Value)>> {
+        self.keyspace().read_all(|stripes| {
+            let now = SystemTime::now();
+            let mut pairs = vec![];
+            for (stripe, db) in stripes {
+                let expiries = stripe.expiries();
+                let mut stripe_pairs = db.scan()?;
+                stripe_pairs.retain(|(key, _)| !expiries.is_expired(key, now));
+                pairs.append(&mut stripe_pairs);
+            }
+            Ok(pairs)
+        })
+    }
+
+    fn keys(&self) -> Result<Vec<Bytes>> {
+        self.keyspace().read_all(|stripes| {
+            let now = SystemTime::now();
+            let mut keys = vec![];
+            for (stripe, db) in stripes {
+                let expiries = stripe.expiries();
+                let mut stripe_keys = db.keys()?;
+                stripe_keys.retain(|key| !expiries.is_expired(key, now));
+                keys.append(&mut stripe_keys);
+            }
+            Ok(keys)
+        })
+    }
+
+    fn keys_with_prefix(&self, prefix: &[u8]) -> Result<Vec<Bytes>> {
+        self.keyspace().read_all(|stripes| {
+            let now = SystemTime::now();
+            let mut keys = vec![];
+            for (stripe, db) in stripes {
+                let expiries = stripe.expiries();
+                let mut stripe_keys = db.keys_with_prefix(prefix)?;
+                stripe_keys.retain(|key| !expiries.is_expired(key, now));
+                keys.append(&mut stripe_keys);
+            }
+            keys.sort_unstable();
+            Ok(keys)
+        })
+    }
+
+    /// Keys expired but not purged yet are not counted.
+    fn len(&self) -> Result<usize> {
+        self.keyspace().read_all(|stripes| {
+            let now = SystemTime::now();
+            let mut len = 0;
+            for (stripe, db) in stripes {
+                let expiries = stripe.expiries();
+                len += db.len()?.saturating_sub(expiries.count_expired(now));
+            }
+            Ok(len)
+        })
+    }
+
+    /// The lock is held throughout. In memory, the value is taken out of the
+    /// storage and put back, so `f` works on it in place. It is put back even
+    /// if `f` panics, as the server carries on. Engines which block write each
+    /// change to disk, so `f` works on a copy there, written back only if `f`
+    /// changed it.
+    fn update<R>(&self, key: Bytes, f: impl FnOnce(&mut Option<Value>) -> R) -> Result<R> {
+        let stripe = self.stripe(&key);
+        stripe.run(|db| {
+            stripe.purge_expired(db, &key)?;
+            if stripe.blocks {
+                let value = db.get(key.clone())?;
+                let mut updated = value.clone();
+                let result = f(&mut updated);
+                if updated != value {
+                    match updated {
+                        Some(updated) => {
+                            let expires_at = stripe.expires_at(db, &key);
+                            stripe.store(db, key, updated, expires_at)?;
+                        }
+                        None => {
+                            db.delete(key.clone())?;
+                            stripe.expiries().clear(&key);
+                        }
+                    }
+                }
+                return Ok(result);
+            }
+            let expires_at = db.expires_at(&key);
+            let mut value = db.remove(key.clone())?;
+            let result = panic::catch_unwind(AssertUnwindSafe(|| f(&mut value)));
+            let stored = match (value, expires_at) {
+                (Some(value), Some(expires_at)) => db.put_with_ttl(key, value, expires_at),
+                (Some(value), None) => db.put(key, value),
+                (None, _) => {
+                    stripe.expiries().clear(&key);
+                    Ok(())
+                }
+            };
+            let result = result.unwrap_or_else(|panic| panic::resume_unwind(panic));
+            stored?;
+            Ok(result)
+        })
+    }
+
+    /// Shares the lock with other reads. `f` reads a copy of the value, as
+    /// [`Storage::get`] returns.
+    fn view<R>(&self, key: Bytes, f: impl FnOnce(Option<&Value>) -> R) -> Result<R> {
+        let stripe = self.stripe(&key);
+        stripe.read(|db| {
+            if stripe.is_expired(&key) {
+                return Ok(f(None));
+            }
+            let value = db.get(key)?;
+            Ok(f(value.as_ref()))
+        })
+    }
+
+    fn shard_count(&self) -> Option<usize> {
+        self.keyspace().stripes[0].read(|db| db.shard_count())
+    }
+
+    /// Reshards every stripe of every logical database. The keys are moved by
+    /// a background task, taking the lock for one shard at a time so other
+    /// connections are served in between.
+    fn reshard(&self, shards: usize) -> Result<()> {
+        for keyspace in self.keyspaces.iter() {
+            keyspace.each(|_, db| db.reshard(shards))?;
+        }
+        let keyspaces = self.keyspaces.clone();
+        spawn_named("reshard", async move {
+            let stripes = keyspaces
+                .iter()
+                .flat_map(|keyspace| keyspace.stripes.iter());
+            for stripe in stripes {
+                loop {
+                    let more = stripe.run(|db| db.migrate_step());
+                    if !more {
+                        break;
+                    }
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn put_with_ttl(&self, key: Bytes, value: Value, ttl: Duration) -> Result<()> {
+        let stripe = self.stripe(&key);
+        stripe.run(|db| stripe.store(db, key, value, deadline(Some(ttl))))
+    }
+
+    /// The value is taken out and stored again with its new TTL.
+    fn expire_in(&self, key: Bytes, ttl: Duration) -> Result<bool> {
+        let stripe = self.stripe(&key);
+        stripe.run(|db| {
+            stripe.purge_expired(db, &key)?;
+            let Some(value) = db.remove(key.clone())? else {
+                return Ok(false);
+            };
+            stripe.store(db, key, value, deadline(Some(ttl)))?;
+            Ok(true)
+        })
+    }
+
+    fn persist(&self, key: Bytes) -> Result<bool> {
+        let stripe = self.stripe(&key);
+        stripe.run(|db| {
+            stripe.purge_expired(db, &key)?;
+            if stripe.expires_at(db, &key).is_none() {
+                return Ok(false);
+            }
+            let Some(value) = db.remove(key.clone())? else {
+                return Ok(false);
+            };
+            stripe.store(db, key, value, None)?;
+            Ok(true)
+        })
+    }
+
+    fn ttl(&self, key: Bytes) -> Result<KeyTtl> {
+        let stripe = self.stripe(&key);
+        stripe.read(|db| {
+            if stripe.is_expired(&key) || db.get(key.clone())?.is_none() {
+                return Ok(KeyTtl::Missing);
+            }
+            Ok(match stripe.expires_at(db, &key) {
+                Some(expires_at) => {
+                    let remaining = expires_at.duration_since(SystemTime::now());
+                    KeyTtl::Remaining(remaining.unwrap_or_default())
+                }
+                None => KeyTtl::Persistent,
+            })
+        })
+    }
+
+    /// Under the locks of both stripes, and moves the TTL along. Within a
+    /// stripe, it relies on the engine.
+    fn rename(&self, src: Bytes, dst: Bytes) -> Result<bool> {
+        self.keyspace().run_keys(&[&src, &dst], |locked| {
+            let (src_stripe, db) = locked.stripe(&src);
+            src_stripe.purge_expired(db, &src)?;
+            let (dst_stripe, db) = locked.stripe(&dst);
+            dst_stripe.purge_expired(db, &dst)?;
+            if std::ptr::eq(src_stripe, dst_stripe) {
+                let mut expiries = src_stripe.expiries();
+                if !db.rename(src.clone(), dst.clone())? {
+                    return Ok(false);
+                }
+                let expires_at = expiries.get(&src);
+                expiries.clear(&src);
+                match expires_at {
+                    Some(expires_at) => expiries.set(dst.clone(), expires_at),
+                    None => expiries.clear(&dst),
+                }
+                return Ok(true);
+            }
+            let (_, db) = locked.stripe(&src);
+            let expires_at = src_stripe.expires_at(db, &src);
+            let Some(value) = db.remove(src.clone())? else {
+                return Ok(false);
+            };
+            src_stripe.expiries().clear(&src);
+            let (_, db) = locked.stripe(&dst);
+            dst_stripe.store(db, dst.clone(), value, expires_at)?;
+            Ok(true)
+        })
+    }
+
+    /// Under the locks of both stripes, and copies the TTL along. Within a
+    /// stripe, it relies on the engine.
+    fn copy(&self, src: Bytes, dst: Bytes, replace: bool) -> Result<bool> {
+        self.keyspace().run_keys(&[&src, &dst], |locked| {
+            let (src_stripe, db) = locked.stripe(&src);
+            src_stripe.purge_expired(db, &src)?;
+            let (dst_stripe, db) = locked.stripe(&dst);
+            dst_stripe.purge_expired(db, &dst)?;
+            if std::ptr::eq(src_stripe, dst_stripe) {
+                let mut expiries = src_stripe.expiries();
+                if !db.copy(src.clone(), dst.clone(), replace)? {
+                    return Ok(false);
+                }
+                match expiries.get(&src) {
+                    Some(expires_at) => expiries.set(dst.clone(), expires_at),
+                    None => expiries.clear(&dst),
+                }
+                return Ok(true);
+            }
+            if !replace && db.get(dst.clone())?.is_some() {
+                return Ok(false);
+            }
+            let (_, db) = locked.stripe(&src);
+            let expires_at = src_stripe.expires_at(db, &src);
+            let Some(value) = db.get(src.clone())? else {
+                return Ok(false);
+            };
+            let (_, db) = locked.stripe(&dst);
+            dst_stripe.store(db, dst.clone(), value, expires_at)?;
+            Ok(true)
+        })
+    }
+
+    /// Under the locks of the stripes of all the keys.
+    fn put_many(&self, pairs: Vec<(Bytes, Value)>) -> Result<()> {
+        let keys: Vec<&[u8]> = pairs.iter().map(|(key, _)| &key[..]).collect();
+        self.keyspace().run_keys(&keys, |locked| {
+            for (key, value) in pairs.iter().cloned() {
+                let (stripe, db) = locked.stripe(&key);
+                stripe.store(db, key, value, None)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Under the locks of the stripes of all the keys.
+    fn write_batch(&self, batch: Batch) -> Result<()> {
+        let keys: Vec<&[u8]> = batch.ops().iter().map(|op| &op.key()[..]).collect();
+        self.keyspace().run_keys(&keys, |locked| {
+            for op in batch.ops().iter().cloned() {
+                let (stripe, db) = locked.stripe(op.key());
+                match op {
+                    BatchOp::Put(key, value) => stripe.store(db, key, value, None)?,
+                    BatchOp::Delete(key) => {
+                        stripe.purge_expired(db, &key)?;
+                        stripe.expiries().clear(&key);
+                        db.delete(key)?;
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Syncs every logical database, a stripe at a time.
+    fn sync(&self) -> Result<()> {
+        for keyspace in self.keyspaces.iter() {
+            keyspace.each(|_, db| db.sync())?;
+        }
+        Ok(())
+    }
+
+    /// Relies on the compare-and-swap of the engine, under the lock.
+    fn compare_and_swap(&self, key: Bytes, expected: Value, new: Value) -> Result<bool> {
+        let stripe = self.stripe(&key);
+        stripe.run(|db| {
+            stripe.purge_expired(db, &key)?;
+            db.compare_and_swap(key, &expected, new)
+        })
+    }
+
+    /// The check and the write happen under the lock.
+    fn put_if(
+        &self,
+        key: Bytes,
+        value: Value,
+        ttl: Option<Duration>,
+        condition: SetCondition,
+    ) -> Result<bool> {
+        let stripe = self.stripe(&key);
+        stripe.run(|db| {
+            stripe.purge_expired(db, &key)?;
+            let present = db.get(key.clone())?.is_some();
+            if present != (condition == SetCondition::Present) {
+                return Ok(false);
+            }
+            stripe.store(db, key, value, deadline(ttl))?;
+            Ok(true)
+        })
+    }
+
+    /// Sweeps every logical database, a stripe at a time.
+    fn expire(&self) -> Result<Vec<Bytes>> {
+        let now = SystemTime::now();
+        let mut expired = vec![];
+        for keyspace in self.keyspaces.iter() {
+            keyspace.each(|stripe, db| {
+                expired.extend(db.expire(now));
+                for key in stripe.expiries().take_expired(now) {
+                    if db.remove(key.clone())?.is_some() {
+                        expired.push(key);
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        Ok(expired)
+    }
+
+    fn databases(&self) -> usize {
+        self.keyspaces.len()
+    }
+
+    fn select(&self, index: usize) -> Option<Self> {
+        (index < self.keyspaces.len()).then(|| DBHandle {
+            keyspaces: self.keyspaces.clone(),
+            selected: index,
+            memory: self.memory.clone(),
+            compression: self.compression.clone(),
+            wal_sync: self.wal_sync.clone(),
+        })
+    }
+
+    fn index(&self) -> usize {
+        self.selected
+    }
+
+    /// Evicts from the selected database first, then from the others.
+    fn make_room(&self) -> Result<Vec<Bytes>> {
+        let mut evicted = vec![];
+        let policy = self.memory.policy();
+        let others = (0..self.keyspaces.len()).filter(|&index| index != self.selected);
+        for index in iter::once(self.selected).chain(others) {
+            if !self.memory.exceeded() {
+                return Ok(evicted);
+            }
+            self.keyspaces[index].run_all(|locked| {
+                while self.memory.exceeded() {
+                    let Some(key) = locked.evict(policy) else {
+                        break;
+                    };
+                    evicted.push(key);
+                }
+            });
+        }
+        if self.memory.exceeded() {
+            Err(StorageError::OutOfMemory)?
         }
+        Ok(evicted)
+    }
+
+    fn memory_limit(&self) -> Option<Arc<MemoryLimit>> {
+        Some(self.memory.clone())
+    }
+
+    fn contains(&self, key: Bytes) -> Result<bool> {
+        let stripe = self.stripe(&key);
+        stripe.read(|db| Ok(!stripe.is_expired(&key) && db.contains(key)?))
     }
 
-    pub fn get(&self, key: impl Into<Bytes>) -> Result<Option<Bytes>> {
-        let db = self.storage.lock().unwrap();
-        db.get(key.into())
+    fn frequency(&self, key: Bytes) -> Result<Option<u8>> {
+        let stripe = self.stripe(&key);
+        stripe.read(|db| {
+            if stripe.is_expired(&key) {
+                return Ok(None);
+            }
+            Ok(db.frequency(&key))
+        })
     }
 
-    pub fn put(&self, key: impl Into<Bytes>, value: impl Into<Bytes>) -> Result<()> {
-        let mut db = self.storage.lock().unwrap();
-        db.put(key.into(), value.into())
+    /// The most held back of the stripes.
+    fn write_stall(&self) -> Result<WriteStall> {
+        let stripes = self.keyspace().stripes.iter();
+        let stalls = stripes.map(|stripe| stripe.read(|db| db.write_stall()));
+        Ok(stalls.max().unwrap_or(WriteStall::None))
+    }
+
+    /// The sum over the stripes of every logical database.
+    fn table_stats(&self) -> Option<TableStats> {
+        let stripes = self
+            .keyspaces
+            .iter()
+            .flat_map(|keyspace| keyspace.stripes.iter());
+        let stats = stripes.filter_map(|stripe| stripe.read(|db| db.table_stats()));
+        stats.reduce(|sum, stats| sum + stats)
+    }
+
+    /// Compacts every logical database.
+    fn compact(&self) -> Result<()> {
+        for keyspace in self.keyspaces.iter() {
+            keyspace.each(|_, db| db.compact())?;
+        }
+        Ok(())
+    }
+
+    fn value_compression(&self) -> Option<Arc<ValueCompression>> {
+        Some(self.compression.clone())
+    }
+
+    fn wal_sync(&self) -> Option<Arc<WalSyncPolicy>> {
+        self.wal_sync.clone()
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.keyspace().run_all(|locked| {
+            for (stripe, db) in locked.iter_mut() {
+                db.clear()?;
+                *stripe.expiries() = Expiries::new();
+            }
+            Ok(())
+        })
     }
 }
 
 impl Default for DBHandle {
     fn default() -> Self {
-        Self::new()
+        DBHandle::with_databases(DEFAULT_DATABASES, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread};
+
+    use super::*;
+
+    /// A command panicking under the lock of a stripe neither loses the key
+    /// it updates nor leaves the stripe unusable.
+    #[test]
+    fn test_panic_under_lock() {
+        let db = DBHandle::default();
+        let key = Bytes::from("key");
+        let value = Value::String(Bytes::from("value"));
+        db.put(key.clone(), value.clone()).unwrap();
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+            db.update(key.clone(), |_| panic!("a bug in a command"))
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(db.get(key.clone()).unwrap(), Some(value));
+        db.put(key.clone(), Value::String(Bytes::from("other")))
+            .unwrap();
+        assert_eq!(db.ttl(key.clone()).unwrap(), KeyTtl::Persistent);
+        assert!(db.view(key, |value| value.is_some()).unwrap());
+    }
+
+    /// Updates which leave the value as it was write nothing to disk.
+    #[test]
+    fn test_unchanged_update() {
+        let dir = std::env::temp_dir().join(format!("uranus-update-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = DBHandle::with_lsm(1, &LsmConfig::new(&dir)).unwrap();
+        let log = dir.join("0").join("wal.log");
+        let logged = || std::fs::metadata(&log).unwrap().len();
+        let key = Bytes::from("key");
+        db.put(key.clone(), Value::String(Bytes::from("value")))
+            .unwrap();
+        let before = logged();
+
+        let found = db.update(key.clone(), |value| value.is_some()).unwrap();
+        assert!(found);
+        db.update(Bytes::from("missing"), |_| ()).unwrap();
+        assert_eq!(logged(), before);
+
+        db.update(key.clone(), |value| *value = None).unwrap();
+        assert!(logged() > before);
+        assert_eq!(db.get(key).unwrap(), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Renames and `MSET`s over keys of every stripe, locking them in
+    /// opposite orders, while a reader checks each `MSET` is seen whole.
+    #[test]
+    fn test_striped_locking() {
+        let db = DBHandle::default();
+        let keys: Vec<Bytes> = (0..32)
+            .map(|i| Bytes::from(format!("mset:{}", i)))
+            .collect();
+        db.put(Bytes::from("a"), Value::String(Bytes::from("value")))
+            .unwrap();
+
+        let (done, finished) = mpsc::channel();
+        let mut workers = vec![];
+        for writer in 0..4 {
+            let (db, keys, done) = (db.clone(), keys.clone(), done.clone());
+            workers.push(thread::spawn(move || {
+                for round in 0..200 {
+                    let value = Value::String(Bytes::from(format!("{}:{}", writer, round)));
+                    let mut pairs: Vec<_> = keys
+                        .iter()
+                        .map(|key| (key.clone(), value.clone()))
+                        .collect();
+                    if writer % 2 == 1 {
+                        pairs.reverse();
+                    }
+                    db.put_many(pairs).unwrap();
+                }
+                done.send(()).unwrap();
+            }));
+        }
+        for (src, dst) in [("a", "b"), ("b", "a")] {
+            let (db, done) = (db.clone(), done.clone());
+            workers.push(thread::spawn(move || {
+                for _ in 0..1000 {
+                    db.rename(Bytes::from(src), Bytes::from(dst)).unwrap();
+                }
+                done.send(()).unwrap();
+            }));
+        }
+        {
+            let (db, done) = (db.clone(), done.clone());
+            workers.push(thread::spawn(move || {
+                for _ in 0..200 {
+                    let pairs = db.scan().unwrap();
+                    let mut values = pairs
+                        .iter()
+                        .filter(|(key, _)| key.starts_with(b"mset:"))
+                        .map(|(_, value)| value);
+                    if let Some(first) = values.next() {
+                        assert!(values.all(|value| value == first));
+                    }
+                }
+                done.send(()).unwrap();
+            }));
+        }
+
+        // A deadlock would hang the workers rather than fail them.
+        for _ in 0..workers.len() {
+            finished
+                .recv_timeout(Duration::from_secs(60))
+                .expect("the workers deadlocked");
+        }
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        // The renamed value is always under exactly one of its keys.
+        let renamed = [Bytes::from("a"), Bytes::from("b")];
+        let present = renamed
+            .iter()
+            .filter(|key| db.get((*key).clone()).unwrap().is_some());
+        assert_eq!(present.count(), 1);
+        assert_eq!(db.len().unwrap(), keys.len() + 1);
+    }
+
+    /// Counts up from several threads while others read the count: no
+    /// increment is lost, and no read goes back in time.
+    #[test]
+    fn test_concurrent_reads_and_writes() {
+        let db = DBHandle::default();
+        let counter = Bytes::from("counter");
+        let count = |value: Option<&Value>| match value {
+            Some(Value::String(count)) => std::str::from_utf8(count).unwrap().parse().unwrap(),
+            _ => 0u64,
+        };
+
+        let mut writers = vec![];
+        for _ in 0..4 {
+            let (db, counter) = (db.clone(), counter.clone());
+            writers.push(thread::spawn(move || {
+                for _ in 0..500 {
+                    db.update(counter.clone(), |value| {
+                        let next = count(value.as_ref()) + 1;
+                        *value = Some(Value::String(Bytes::from(next.to_string())));
+                    })
+                    .unwrap();
+                }
+            }));
+        }
+        let mut readers = vec![];
+        for _ in 0..4 {
+            let (db, counter) = (db.clone(), counter.clone());
+            readers.push(thread::spawn(move || {
+                let mut last = 0;
+                while last < 2000 {
+                    let seen = count(db.get(counter.clone()).unwrap().as_ref());
+                    assert!(seen >= last, "read {} after {}", seen, last);
+                    last = seen;
+                }
+            }));
+        }
+        for worker in writers.into_iter().chain(readers) {
+            worker.join().unwrap();
+        }
+        assert_eq!(count(db.get(counter).unwrap().as_ref()), 2000);
     }
 }
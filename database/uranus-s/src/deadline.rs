@@ -0,0 +1,82 @@
+//! Latency budgets of commands
+//!
+//! A client may prefix a command with `DEADLINE <ms>`, the time it is still
+//! willing to wait for the command to start. Commands queue behind the ones
+//! sent before them on the same connection; once the budget is spent, doing
+//! the work is useless, so the server replies with a `-DEADLINE` error at once.
+//!
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::{CommandParseError, ErrorCode, Frame};
+
+const PREFIX: &str = "deadline";
+
+/// Prefixes `command` with a latency budget of `budget`.
+pub fn with_deadline(budget: Duration, command: Frame) -> Frame {
+    let Frame::Array(command) = command else {
+        return command;
+    };
+    let mut frame = vec![
+        Frame::Text(PREFIX.to_string()),
+        Frame::Text(budget.as_millis().to_string()),
+    ];
+    frame.extend(command);
+    Frame::Array(frame)
+}
+
+/// Strips the `DEADLINE <ms>` prefix of a command, returning its budget too.
+pub fn split_deadline(frame: Frame) -> Result<(Option<Duration>, Frame)> {
+    let Frame::Array(mut parts) = frame else {
+        return Ok((None, frame));
+    };
+    let prefixed = match parts.first() {
+        Some(Frame::Text(name)) => name.eq_ignore_ascii_case(PREFIX),
+        Some(Frame::Binary(name)) => name.eq_ignore_ascii_case(PREFIX.as_bytes()),
+        _ => false,
+    };
+    if !prefixed {
+        return Ok((None, Frame::Array(parts)));
+    }
+    if parts.len() < 3 {
+        Err(CommandParseError::UnexpectedEOF)?
+    }
+    let command = parts.split_off(2);
+    let millis = match &parts[1] {
+        Frame::Text(millis) => millis.parse::<u64>()?,
+        Frame::Binary(millis) => std::str::from_utf8(millis)?.parse::<u64>()?,
+        _ => Err(CommandParseError::ArgNotText)?,
+    };
+    Ok((Some(Duration::from_millis(millis)), Frame::Array(command)))
+}
+
+/// The reply to a command whose budget was spent before it could start.
+pub fn deadline_exceeded(budget: Duration) -> Frame {
+    Frame::error(
+        ErrorCode::Deadline,
+        format!("command could not start within {}ms", budget.as_millis()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deadline_round_trip() {
+        let command = Frame::Array(vec![
+            Frame::Text("get".to_string()),
+            Frame::Text("key".to_string()),
+        ]);
+        let prefixed = with_deadline(Duration::from_millis(25), command.clone());
+        let (budget, stripped) = split_deadline(prefixed).unwrap();
+        assert_eq!(budget, Some(Duration::from_millis(25)));
+        assert_eq!(stripped, command);
+
+        let (budget, stripped) = split_deadline(command.clone()).unwrap();
+        assert_eq!(budget, None);
+        assert_eq!(stripped, command);
+    }
+}
@@ -0,0 +1,151 @@
+//! Embedding the database in an application
+//!
+//! A [`DBHandle`](crate::DBHandle) is usable on its own, without a server in
+//! front of it, the way the server uses it: commands are applied to it
+//! through the [`Database`](crate::Database) trait.
+//!
+//! ```ignore
+//! let db = DBHandle::new(&StorageEngine::Lsm { data_dir: "data".into() })?;
+//! db.put(Bytes::from("greeting"), Value::String(Bytes::from("hello")))?;
+//! let mut batch = Batch::new();
+//! batch.put("a", Value::String(Bytes::from("1"))).delete("greeting");
+//! db.write_batch(batch)?;
+//! for (key, value) in db.snapshot()?.iter() {
+//!     println!("{:?} = {:?}", key, value);
+//! }
+//! db.close()?;
+//! ```
+//!
+//! A [`Batch`] is written atomically: reads see all of its writes or none of
+//! them. A [`Snapshot`] is a copy of a logical database at one point in time,
+//! which later writes leave as it is.
+
+use std::collections::{btree_map, BTreeMap};
+
+use bytes::Bytes;
+
+use crate::Value;
+
+/// A write of a [`Batch`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum BatchOp {
+    /// Stores the value, making the key persistent.
+    Put(Bytes, Value),
+    Delete(Bytes),
+}
+
+impl BatchOp {
+    /// The key written.
+    pub fn key(&self) -> &Bytes {
+        match self {
+            BatchOp::Put(key, _) | BatchOp::Delete(key) => key,
+        }
+    }
+}
+
+/// Writes applied together, in order, by
+/// [`Database::write_batch`](crate::Database::write_batch).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Batch {
+    ops: Vec<BatchOp>,
+}
+
+impl Batch {
+    pub fn new() -> Batch {
+        Batch::default()
+    }
+
+    pub fn put(&mut self, key: impl Into<Bytes>, value: Value) -> &mut Batch {
+        self.ops.push(BatchOp::Put(key.into(), value));
+        self
+    }
+
+    pub fn delete(&mut self, key: impl Into<Bytes>) -> &mut Batch {
+        self.ops.push(BatchOp::Delete(key.into()));
+        self
+    }
+
+    pub fn ops(&self) -> &[BatchOp] {
+        &self.ops
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+impl IntoIterator for Batch {
+    type Item = BatchOp;
+    type IntoIter = std::vec::IntoIter<BatchOp>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ops.into_iter()
+    }
+}
+
+/// The keys of a logical database at one point in time, in order, see
+/// [`Database::snapshot`](crate::Database::snapshot). Keys expiring after it
+/// was taken are still in it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    entries: BTreeMap<Bytes, Value>,
+}
+
+impl Snapshot {
+    pub fn get(&self, key: &[u8]) -> Option<&Value> {
+        self.entries.get(key)
+    }
+
+    /// Every key and its value, in order.
+    pub fn iter(&self) -> btree_map::Iter<'_, Bytes, Value> {
+        self.entries.iter()
+    }
+
+    /// The keys starting with `prefix` and their values, in order.
+    pub fn scan_prefix<'a>(
+        &'a self,
+        prefix: &'a [u8],
+    ) -> impl Iterator<Item = (&'a Bytes, &'a Value)> + 'a {
+        self.entries
+            .range(Bytes::copy_from_slice(prefix)..)
+            .take_while(move |(key, _)| key.starts_with(prefix))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl FromIterator<(Bytes, Value)> for Snapshot {
+    fn from_iter<I: IntoIterator<Item = (Bytes, Value)>>(pairs: I) -> Snapshot {
+        Snapshot {
+            entries: pairs.into_iter().collect(),
+        }
+    }
+}
+
+impl IntoIterator for Snapshot {
+    type Item = (Bytes, Value);
+    type IntoIter = btree_map::IntoIter<Bytes, Value>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Snapshot {
+    type Item = (&'a Bytes, &'a Value);
+    type IntoIter = btree_map::Iter<'a, Bytes, Value>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
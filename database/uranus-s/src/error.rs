@@ -0,0 +1,170 @@
+//! Error replies
+//!
+//! Every error reply starts with a code, an upper case word telling clients
+//! what went wrong without parsing the rest of the message, as in `-WRONGTYPE
+//! Operation against a key holding the wrong kind of value`. [`ErrorCode`]
+//! lists those the server sends, and [`error_reply`] picks the code of an
+//! internal error by its type.
+
+use std::{fmt, str::FromStr};
+
+use uranus_kv::StorageError;
+
+use crate::{AclDenied, Frame};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// Any error without a code of its own, such as a syntax error.
+    Err,
+    /// The key holds another type of value than the command works on.
+    WrongType,
+    /// The connection has to authenticate first.
+    NoAuth,
+    /// `AUTH` was given the wrong password, or a disabled user, or `REPLCONF
+    /// PRIMARY` the wrong secret.
+    WrongPass,
+    /// The user may not run the command, or access its keys.
+    NoPerm,
+    /// A write was sent to a read-only replica.
+    ReadOnly,
+    /// The memory limit is reached and no key could be evicted.
+    Oom,
+    /// Writes are stopped until the storage catches up, see
+    /// [`WriteStall`](crate::WriteStall).
+    Stalled,
+    NoProto,
+    NoScript,
+    /// `RESTORE` would overwrite a key.
+    BusyKey,
+    /// Another server couldn't be reached.
+    IoErr,
+    /// The command couldn't start within its deadline.
+    Deadline,
+    /// The slot of the key is served by another node, see
+    /// [cluster mode](crate::cluster).
+    Moved,
+    /// The slot of the key is being migrated to another node.
+    Ask,
+    /// The keys of the command are in several slots.
+    CrossSlot,
+    /// The slot of the key isn't served by any node.
+    ClusterDown,
+    /// The data is still being loaded, see [`crate::health`].
+    Loading,
+}
+
+const CODES: &[(ErrorCode, &str)] = &[
+    (ErrorCode::Err, "ERR"),
+    (ErrorCode::WrongType, "WRONGTYPE"),
+    (ErrorCode::NoAuth, "NOAUTH"),
+    (ErrorCode::WrongPass, "WRONGPASS"),
+    (ErrorCode::NoPerm, "NOPERM"),
+    (ErrorCode::ReadOnly, "READONLY"),
+    (ErrorCode::Oom, "OOM"),
+    (ErrorCode::Stalled, "STALLED"),
+    (ErrorCode::NoProto, "NOPROTO"),
+    (ErrorCode::NoScript, "NOSCRIPT"),
+    (ErrorCode::BusyKey, "BUSYKEY"),
+    (ErrorCode::IoErr, "IOERR"),
+    (ErrorCode::Deadline, "DEADLINE"),
+    (ErrorCode::Moved, "MOVED"),
+    (ErrorCode::Ask, "ASK"),
+    (ErrorCode::CrossSlot, "CROSSSLOT"),
+    (ErrorCode::ClusterDown, "CLUSTERDOWN"),
+    (ErrorCode::Loading, "LOADING"),
+];
+
+impl ErrorCode {
+    /// The code as it is written on the wire.
+    pub fn as_str(self) -> &'static str {
+        CODES
+            .iter()
+            .find(|(code, _)| *code == self)
+            .map(|(_, name)| *name)
+            .expect("every code has a name")
+    }
+
+    /// Splits an error reply into its code and message. Replies not starting
+    /// with a known code, as scripts may send, are generic errors with the
+    /// whole reply as their message.
+    pub fn split(reply: &str) -> (ErrorCode, &str) {
+        let (code, message) = reply.split_once(' ').unwrap_or((reply, ""));
+        match code.parse() {
+            Ok(code) => (code, message),
+            Err(_) => (ErrorCode::Err, reply),
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ErrorCode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<ErrorCode> {
+        CODES
+            .iter()
+            .find(|(_, name)| *name == s)
+            .map(|(code, _)| *code)
+            .ok_or_else(|| anyhow::anyhow!("unknown error code '{}'", s))
+    }
+}
+
+impl Frame {
+    /// The error reply with `code` and `message`.
+    pub fn error(code: ErrorCode, message: impl fmt::Display) -> Frame {
+        Frame::Error(format!("{} {}", code, message))
+    }
+}
+
+/// The code of the reply to `err`, by its type. Parse errors, like most
+/// others, are generic.
+pub fn error_code(err: &anyhow::Error) -> ErrorCode {
+    if let Some(denied) = err.downcast_ref::<AclDenied>() {
+        return match denied {
+            AclDenied::NoAuth => ErrorCode::NoAuth,
+            AclDenied::Command { .. } | AclDenied::Key => ErrorCode::NoPerm,
+        };
+    }
+    match err.downcast_ref::<StorageError>() {
+        Some(StorageError::OutOfMemory) => ErrorCode::Oom,
+        _ => ErrorCode::Err,
+    }
+}
+
+/// The error reply to `err`, see [`error_code`].
+pub fn error_reply(err: &anyhow::Error) -> Frame {
+    Frame::error(error_code(err), err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CommandParseError;
+
+    #[test]
+    fn test_error_codes() {
+        for &(code, name) in CODES {
+            assert_eq!(code.as_str(), name);
+            assert_eq!(name.parse::<ErrorCode>().unwrap(), code);
+        }
+        let (code, message) = ErrorCode::split("MOVED 3999 127.0.0.1:6381");
+        assert_eq!((code, message), (ErrorCode::Moved, "3999 127.0.0.1:6381"));
+        assert_eq!(ErrorCode::split("oops"), (ErrorCode::Err, "oops"));
+        assert_eq!(ErrorCode::split("NOSCRIPT"), (ErrorCode::NoScript, ""));
+
+        let err = anyhow::Error::from(AclDenied::NoAuth);
+        assert_eq!(
+            error_reply(&err),
+            Frame::Error("NOAUTH Authentication required.".to_string())
+        );
+        let err = anyhow::Error::from(StorageError::OutOfMemory);
+        assert_eq!(error_code(&err), ErrorCode::Oom);
+        let err = anyhow::Error::from(CommandParseError::UnknownCommand);
+        assert_eq!(error_code(&err), ErrorCode::Err);
+    }
+}
@@ -0,0 +1,243 @@
+//! The internal event bus
+//!
+//! Subsystems announce what happens in the server as [`ServerEvent`]s, and the
+//! ones interested subscribe, instead of calling each other: the handler
+//! doesn't know who cares about writes, and new consumers plug in by
+//! subscribing. Keyspace notifications are the first consumer, see
+//! [`notify_keyspace`].
+//!
+//! Events are delivered asynchronously and best effort: a subscriber falling
+//! more than a buffer behind misses the oldest events.
+
+use std::{fmt, net::SocketAddr, str::FromStr};
+
+use bytes::Bytes;
+use thiserror::Error;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+use crate::{PubSub, KEYEVENT_PREFIX, KEYSPACE_PREFIX};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerEvent {
+    /// `command` changed `key`.
+    KeyWritten { key: Bytes, command: &'static str },
+    /// `key` reached its TTL and was reclaimed.
+    KeyExpired { key: Bytes },
+    /// `key` was evicted to stay under `maxmemory`.
+    KeyEvicted { key: Bytes },
+    /// A client connected from `peer`.
+    ClientConnected { peer: SocketAddr },
+    /// `CONFIG SET param value` was applied.
+    ConfigChanged { param: String, value: String },
+}
+
+const CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<ServerEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        EventBus { sender }
+    }
+
+    /// Announces `event` to the current subscribers, if any.
+    pub fn emit(&self, event: ServerEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    /// Receives the events emitted from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerEvent> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Which keyspace notifications are published, parsed from flags as the
+/// `notify-keyspace-events` of Redis:
+///
+/// - `K` publishes on the keyspace channels, see [`KEYSPACE_PREFIX`], and `E`
+///   on the keyevent channels, see [`KEYEVENT_PREFIX`];
+/// - `w` announces the writes of commands, `x` the keys expired and `e` the
+///   keys evicted, `A` all of them.
+///
+/// Nothing is published unless a kind of channel and a class of events are
+/// given, as with `KEA` for all of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyspaceEvents {
+    flags: u8,
+}
+
+const KEYSPACE: u8 = 1 << 0;
+const KEYEVENT: u8 = 1 << 1;
+const WRITTEN: u8 = 1 << 2;
+const EXPIRED: u8 = 1 << 3;
+const EVICTED: u8 = 1 << 4;
+const CLASSES: u8 = WRITTEN | EXPIRED | EVICTED;
+
+const FLAGS: &[(char, u8)] = &[
+    ('K', KEYSPACE),
+    ('E', KEYEVENT),
+    ('w', WRITTEN),
+    ('x', EXPIRED),
+    ('e', EVICTED),
+];
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("invalid keyspace event flag '{0}'")]
+pub struct InvalidKeyspaceEvents(pub char);
+
+impl KeyspaceEvents {
+    /// No notifications at all, the default.
+    pub fn none() -> KeyspaceEvents {
+        KeyspaceEvents::default()
+    }
+
+    /// Whether anything is published at all.
+    pub fn is_enabled(self) -> bool {
+        self.flags & (KEYSPACE | KEYEVENT) != 0 && self.flags & CLASSES != 0
+    }
+
+    /// The channels announcing `event`, and what is published on them, none
+    /// if its class is off.
+    fn notifications(self, event: ServerEvent) -> Vec<(String, Bytes)> {
+        let (key, op, class) = match event {
+            ServerEvent::KeyWritten { key, command } => (key, command, WRITTEN),
+            ServerEvent::KeyExpired { key } => (key, "expired", EXPIRED),
+            ServerEvent::KeyEvicted { key } => (key, "evicted", EVICTED),
+            _ => return vec![],
+        };
+        if self.flags & class == 0 {
+            return vec![];
+        }
+        let mut notifications = vec![];
+        if self.flags & KEYSPACE != 0 {
+            let channel = format!("{}{}", KEYSPACE_PREFIX, String::from_utf8_lossy(&key));
+            notifications.push((channel, Bytes::from(op)));
+        }
+        if self.flags & KEYEVENT != 0 {
+            notifications.push((format!("{}{}", KEYEVENT_PREFIX, op), key));
+        }
+        notifications
+    }
+}
+
+impl FromStr for KeyspaceEvents {
+    type Err = InvalidKeyspaceEvents;
+
+    fn from_str(flags: &str) -> Result<Self, Self::Err> {
+        let mut events = KeyspaceEvents::none();
+        for flag in flags.chars() {
+            events.flags |= match flag {
+                'A' => CLASSES,
+                flag => FLAGS
+                    .iter()
+                    .find(|(name, _)| *name == flag)
+                    .map(|(_, bit)| *bit)
+                    .ok_or(InvalidKeyspaceEvents(flag))?,
+            };
+        }
+        Ok(events)
+    }
+}
+
+impl fmt::Display for KeyspaceEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, bit) in FLAGS {
+            if self.flags & bit != 0 {
+                write!(f, "{}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Publishes the keyspace notifications of `classes`: for each write, the
+/// command name on `__keyspace@0__:key` and the key on
+/// `__keyevent@0__:command`, for each expiry and eviction `expired` and
+/// `evicted` likewise. Runs until the bus is dropped.
+pub async fn notify_keyspace(
+    mut events: broadcast::Receiver<ServerEvent>,
+    pubsub: PubSub,
+    classes: KeyspaceEvents,
+) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                warn!(missed, "keyspace notifications fell behind");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        for (channel, payload) in classes.notifications(event) {
+            pubsub.publish(channel, payload);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_notify_keyspace() {
+        let bus = EventBus::new();
+        let pubsub = PubSub::new();
+        let mut messages = pubsub.subscribe();
+        let classes = "KA".parse().unwrap();
+        tokio::spawn(notify_keyspace(bus.subscribe(), pubsub.clone(), classes));
+
+        bus.emit(ServerEvent::ClientConnected {
+            peer: "127.0.0.1:4242".parse().unwrap(),
+        });
+        bus.emit(ServerEvent::KeyWritten {
+            key: Bytes::from("user:1"),
+            command: "set",
+        });
+        bus.emit(ServerEvent::KeyExpired {
+            key: Bytes::from("session"),
+        });
+
+        let message = messages.recv().await.unwrap();
+        assert_eq!(message.channel, "__keyspace@0__:user:1");
+        assert_eq!(message.payload, Bytes::from("set"));
+        let message = messages.recv().await.unwrap();
+        assert_eq!(message.channel, "__keyspace@0__:session");
+        assert_eq!(message.payload, Bytes::from("expired"));
+    }
+
+    #[test]
+    fn test_keyspace_events() {
+        let events: KeyspaceEvents = "EAK".parse().unwrap();
+        assert_eq!(events.to_string(), "KEwxe");
+        assert!(events.is_enabled());
+        assert!(!"K".parse::<KeyspaceEvents>().unwrap().is_enabled());
+        assert!(!"x".parse::<KeyspaceEvents>().unwrap().is_enabled());
+        assert!(!KeyspaceEvents::none().is_enabled());
+        assert_eq!(
+            "Kq".parse::<KeyspaceEvents>(),
+            Err(InvalidKeyspaceEvents('q'))
+        );
+
+        let evicted = ServerEvent::KeyEvicted {
+            key: Bytes::from("cached"),
+        };
+        let events: KeyspaceEvents = "Ee".parse().unwrap();
+        assert_eq!(
+            events.notifications(evicted.clone()),
+            [("__keyevent@0__:evicted".to_string(), Bytes::from("cached"))]
+        );
+        let events: KeyspaceEvents = "KEx".parse().unwrap();
+        assert!(events.notifications(evicted).is_empty());
+    }
+}
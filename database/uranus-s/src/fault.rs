@@ -0,0 +1,301 @@
+//! Injecting network faults, for tests
+//!
+//! Behind the `faults` feature, a [`Connection`](crate::Connection) can be
+//! spoken over a [`Faulty`] transport, which delays reads and writes, cuts
+//! writes short and drops the connection, as often as its [`Faults`] say.
+//! Servers wrap the connections they accept with
+//! [`ServerConfig::faults`](crate::ServerConfig::faults), clients theirs with
+//! [`Connection::with_faults`](crate::Connection::with_faults):
+//!
+//! ```ignore
+//! let faults = Faults::new(7).with_partial_writes(0.5).with_disconnects(0.01);
+//! let socket = TcpStream::connect(addr).await?;
+//! let mut client = uranus_c::Client::with_connection(Connection::with_faults(socket, faults));
+//! ```
+//!
+//! The faults are drawn from a seed, one draw per read or write, so that the
+//! same seed and the same traffic see the same faults, run after run. Over
+//! TCP, how the bytes are split into reads varies, which in-process pipes,
+//! see [`test_util`](crate::test_util), don't.
+
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{self, Sleep},
+};
+
+/// How often a [`Faulty`] transport fails, see the [module documentation](self).
+/// Rates are the shares of reads or writes affected, from 0 for none to 1 for
+/// all of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Faults {
+    pub seed: u64,
+    /// How long delayed reads and writes are held.
+    pub latency: Duration,
+    pub latency_rate: f64,
+    /// Writes cut short write at least a byte, but not all of them.
+    pub partial_write_rate: f64,
+    /// Once a read or write drops the connection, it fails, as do all later
+    /// ones.
+    pub disconnect_rate: f64,
+}
+
+impl Faults {
+    /// No faults at all, until more are asked for.
+    pub fn new(seed: u64) -> Faults {
+        Faults {
+            seed,
+            ..Faults::default()
+        }
+    }
+
+    pub fn with_latency(self, latency: Duration, rate: f64) -> Faults {
+        Faults {
+            latency,
+            latency_rate: rate,
+            ..self
+        }
+    }
+
+    pub fn with_partial_writes(self, rate: f64) -> Faults {
+        Faults {
+            partial_write_rate: rate,
+            ..self
+        }
+    }
+
+    pub fn with_disconnects(self, rate: f64) -> Faults {
+        Faults {
+            disconnect_rate: rate,
+            ..self
+        }
+    }
+
+    /// The same faults, drawn from another seed, as for the `n`th connection
+    /// of a server.
+    pub fn reseed(self, n: u64) -> Faults {
+        Faults {
+            seed: self.seed ^ n.wrapping_mul(0x9E37_79B9_7F4A_7C15),
+            ..self
+        }
+    }
+}
+
+/// A transport failing as its [`Faults`] say, see the [module
+/// documentation](self).
+#[derive(Debug)]
+pub struct Faulty<T> {
+    inner: T,
+    faults: Faults,
+    /// xorshift64 state.
+    rng: u64,
+    read: Op,
+    write: Op,
+    /// Set once the connection dropped.
+    disconnected: bool,
+}
+
+/// The faults drawn for the read or write in progress, until it completes.
+#[derive(Debug, Default)]
+struct Op {
+    drawn: bool,
+    delay: Option<Pin<Box<Sleep>>>,
+    /// How much a write cut short writes at most.
+    limit: Option<usize>,
+}
+
+impl<T> Faulty<T> {
+    pub fn new(inner: T, faults: Faults) -> Faulty<T> {
+        Faulty {
+            inner,
+            faults,
+            // Spreads nearby seeds apart. xorshift64 is stuck at 0.
+            rng: faults.seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+            read: Op::default(),
+            write: Op::default(),
+            disconnected: false,
+        }
+    }
+
+    pub fn faults(&self) -> Faults {
+        self.faults
+    }
+
+    /// Whether an injected fault dropped the connection.
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected
+    }
+
+    /// A draw between 0 and 1.
+    fn draw(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Draws the faults of a read or write of `len` bytes, unless they were
+    /// drawn already. Fails if the connection is dropped.
+    fn start(&mut self, write: bool, len: usize) -> io::Result<()> {
+        if self.disconnected {
+            return Err(disconnected());
+        }
+        let drawn = if write {
+            self.write.drawn
+        } else {
+            self.read.drawn
+        };
+        if drawn {
+            return Ok(());
+        }
+        if self.draw() < self.faults.disconnect_rate {
+            self.disconnected = true;
+            return Err(disconnected());
+        }
+        let delay = (self.draw() < self.faults.latency_rate)
+            .then(|| Box::pin(time::sleep(self.faults.latency)));
+        let limit = (write && len > 1 && self.draw() < self.faults.partial_write_rate)
+            .then(|| 1 + (self.draw() * (len - 1) as f64) as usize);
+        let op = if write {
+            &mut self.write
+        } else {
+            &mut self.read
+        };
+        *op = Op {
+            drawn: true,
+            delay,
+            limit,
+        };
+        Ok(())
+    }
+}
+
+fn disconnected() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, "injected disconnect")
+}
+
+/// Waits out the delay of `op`, if any.
+fn poll_delay(op: &mut Op, cx: &mut Context<'_>) -> Poll<()> {
+    if let Some(delay) = &mut op.delay {
+        ready!(delay.as_mut().poll(cx));
+        op.delay = None;
+    }
+    Poll::Ready(())
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Faulty<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.start(false, buf.remaining())?;
+        ready!(poll_delay(&mut this.read, cx));
+        let read = ready!(Pin::new(&mut this.inner).poll_read(cx, buf));
+        this.read = Op::default();
+        Poll::Ready(read)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Faulty<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.start(true, buf.len())?;
+        ready!(poll_delay(&mut this.write, cx));
+        let len = this
+            .write
+            .limit
+            .map_or(buf.len(), |limit| limit.min(buf.len()));
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..len]));
+        this.write = Op::default();
+        Poll::Ready(written)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.disconnected {
+            return Poll::Ready(Err(disconnected()));
+        }
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    /// The sizes of the writes of `data`, and whether the connection dropped.
+    async fn writes(faults: Faults, data: &[u8]) -> (Vec<usize>, bool) {
+        let (client, _server) = duplex(1024);
+        let mut faulty = Faulty::new(client, faults);
+        let mut sizes = vec![];
+        let mut data = data;
+        while !data.is_empty() {
+            match faulty.write(data).await {
+                Ok(written) => {
+                    sizes.push(written);
+                    data = &data[written..];
+                }
+                Err(_) => return (sizes, true),
+            }
+        }
+        (sizes, false)
+    }
+
+    #[tokio::test]
+    async fn test_seeded_schedule() {
+        let data = [0u8; 200];
+        let faults = Faults::new(3).with_partial_writes(1.0);
+        let (sizes, disconnected) = writes(faults, &data).await;
+        assert!(!disconnected);
+        assert!(sizes.len() > 1, "{:?}", sizes);
+        assert_eq!(sizes.iter().sum::<usize>(), data.len());
+        assert_eq!(writes(faults, &data).await, (sizes.clone(), false));
+        assert_ne!(writes(faults.reseed(1), &data).await.0, sizes);
+
+        assert_eq!(writes(Faults::new(3), &data).await, (vec![200], false));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect() {
+        let (client, mut server) = duplex(1024);
+        let mut faulty = Faulty::new(client, Faults::new(1).with_disconnects(1.0));
+        let err = faulty.write_all(b"ping").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert!(faulty.is_disconnected());
+        assert!(faulty.flush().await.is_err());
+        drop(faulty);
+        let mut received = vec![];
+        assert_eq!(server.read_to_end(&mut received).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_latency() {
+        let (client, mut server) = duplex(1024);
+        let latency = Duration::from_millis(20);
+        let mut faulty = Faulty::new(client, Faults::new(1).with_latency(latency, 1.0));
+        let start = std::time::Instant::now();
+        faulty.write_all(b"ping").await.unwrap();
+        assert!(start.elapsed() >= latency);
+        let mut ping = [0; 4];
+        server.read_exact(&mut ping).await.unwrap();
+        assert_eq!(&ping, b"ping");
+    }
+}
@@ -0,0 +1,292 @@
+//! Glob patterns, matching channels for `PSUBSCRIBE` and keys for `KEYS` and `SCAN`
+//!
+//! - `*` matches any run of bytes, possibly empty.
+//! - `?` matches any single byte.
+//! - `[abc]` matches one of the listed bytes, `[a-z]` one in the range, and
+//!   `[^abc]` one not listed. A `[` without its `]` is literal.
+//! - `\` makes the next byte literal, as in `\*`.
+
+/// Matches `text` against a glob `pattern`.
+///
+/// Runs in `O(pattern × text)` at worst: on a mismatch it only backtracks to
+/// the last `*`, earlier ones never need to match more.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // The pattern after the last `*`, and where in the text it is tried next.
+    let mut retry: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            retry = Some((p, t));
+            continue;
+        }
+        if let Some((len, true)) = match_one(&pattern[p..], text[t]) {
+            p += len;
+            t += 1;
+            continue;
+        }
+        match retry {
+            Some((after_star, start)) => {
+                p = after_star;
+                t = start + 1;
+                retry = Some((after_star, t));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// The bytes every text matching `pattern` starts with, such as `user:` for
+/// `user:*`, so that only the keys with this prefix need to be matched.
+pub fn literal_prefix(pattern: &[u8]) -> Vec<u8> {
+    let mut prefix = vec![];
+    let mut i = 0;
+    while let Some(&c) = pattern.get(i) {
+        match c {
+            b'*' | b'?' | b'[' => break,
+            b'\\' if i + 1 < pattern.len() => {
+                prefix.push(pattern[i + 1]);
+                i += 2;
+            }
+            literal => {
+                prefix.push(literal);
+                i += 1;
+            }
+        }
+    }
+    prefix
+}
+
+/// `text` as a pattern matching only itself, its special bytes made literal.
+pub fn glob_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Matches `c` against the element at the start of `pattern`, which must not
+/// be `*`. Returns the length of that element and whether it matches, or none
+/// at the end of the pattern.
+fn match_one(pattern: &[u8], c: u8) -> Option<(usize, bool)> {
+    match *pattern.first()? {
+        b'?' => Some((1, true)),
+        b'\\' if pattern.len() > 1 => Some((2, pattern[1] == c)),
+        b'[' => match match_class(pattern, c) {
+            Some(matched) => Some(matched),
+            None => Some((1, c == b'[')),
+        },
+        literal => Some((1, literal == c)),
+    }
+}
+
+/// Matches `c` against the class at the start of `pattern`, none if it isn't
+/// closed.
+fn match_class(pattern: &[u8], c: u8) -> Option<(usize, bool)> {
+    let mut i = 1;
+    let negated = pattern.get(i) == Some(&b'^');
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    loop {
+        let mut low = *pattern.get(i)?;
+        match low {
+            b']' => return Some((i + 1, matched != negated)),
+            b'\\' => {
+                i += 1;
+                low = *pattern.get(i)?;
+            }
+            _ => {}
+        }
+        i += 1;
+        let mut high = low;
+        if pattern.get(i) == Some(&b'-') && !matches!(pattern.get(i + 1), None | Some(b']')) {
+            high = pattern[i + 1];
+            if high == b'\\' {
+                high = *pattern.get(i + 2)?;
+                i += 1;
+            }
+            i += 2;
+        }
+        let (low, high) = (low.min(high), low.max(high));
+        matched |= (low..=high).contains(&c);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"user:*", b"user:1"));
+        assert!(glob_match(b"user:*", b"user:"));
+        assert!(glob_match(b"h?llo", b"hello"));
+        assert!(glob_match(b"*", b""));
+        assert!(!glob_match(b"user:*", b"users"));
+        assert!(!glob_match(b"h?llo", b"hllo"));
+    }
+
+    #[test]
+    fn test_literal_prefix() {
+        assert_eq!(literal_prefix(b"user:*"), b"user:");
+        assert_eq!(literal_prefix(b"user:1"), b"user:1");
+        assert_eq!(literal_prefix(b"h?llo"), b"h");
+        assert_eq!(literal_prefix(b"[ab]*"), b"");
+        assert_eq!(literal_prefix(b"a\\*b*"), b"a*b");
+        assert_eq!(literal_prefix(b"a\\"), b"a\\");
+
+        let escaped = glob_escape("t[1]*?\\");
+        assert_eq!(literal_prefix(escaped.as_bytes()), b"t[1]*?\\");
+        assert!(glob_match(escaped.as_bytes(), b"t[1]*?\\"));
+        assert!(!glob_match(escaped.as_bytes(), b"t1xy\\"));
+    }
+
+    #[test]
+    fn test_classes() {
+        assert!(glob_match(b"h[ae]llo", b"hello"));
+        assert!(glob_match(b"h[ae]llo", b"hallo"));
+        assert!(!glob_match(b"h[ae]llo", b"hillo"));
+        assert!(glob_match(b"h[^e]llo", b"hallo"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"h[a-c]llo", b"hbllo"));
+        assert!(!glob_match(b"h[a-c]llo", b"hdllo"));
+        assert!(glob_match(b"[", b"["));
+        assert!(glob_match(b"a[b", b"a[b"));
+        assert!(!glob_match(b"[]]", b"]"));
+        assert!(glob_match(b"[\\]]", b"]"));
+        assert!(glob_match(b"\\*", b"*"));
+        assert!(!glob_match(b"\\*", b"a"));
+        assert!(glob_match(b"*[0-9]", b"key7"));
+    }
+
+    /// The pattern parsed into its elements, for the reference matcher.
+    #[derive(Debug)]
+    enum Token {
+        Star,
+        Any,
+        Class(Vec<(u8, u8)>, bool),
+    }
+
+    fn tokenize(pattern: &[u8]) -> Vec<Token> {
+        let mut tokens = vec![];
+        let mut i = 0;
+        while i < pattern.len() {
+            let (token, len) = match pattern[i] {
+                b'*' => (Token::Star, 1),
+                b'?' => (Token::Any, 1),
+                b'\\' if i + 1 < pattern.len() => (literal(pattern[i + 1]), 2),
+                b'[' => match class(&pattern[i..]) {
+                    Some(parsed) => parsed,
+                    None => (literal(b'['), 1),
+                },
+                c => (literal(c), 1),
+            };
+            tokens.push(token);
+            i += len;
+        }
+        tokens
+    }
+
+    fn literal(c: u8) -> Token {
+        Token::Class(vec![(c, c)], false)
+    }
+
+    /// Parses the class at the start of `pattern`, with its length.
+    fn class(pattern: &[u8]) -> Option<(Token, usize)> {
+        // The bytes up to the closing bracket, and whether each was escaped.
+        let mut items = vec![];
+        let mut i = 1;
+        loop {
+            match *pattern.get(i)? {
+                b'\\' => {
+                    items.push((*pattern.get(i + 1)?, true));
+                    i += 2;
+                }
+                b']' => break,
+                c => {
+                    items.push((c, false));
+                    i += 1;
+                }
+            }
+        }
+        let negated = items.first() == Some(&(b'^', false));
+        let items = &items[negated as usize..];
+        let mut ranges = vec![];
+        let mut j = 0;
+        while j < items.len() {
+            if items.get(j + 1) == Some(&(b'-', false)) && j + 2 < items.len() {
+                let (low, high) = (items[j].0, items[j + 2].0);
+                ranges.push((low.min(high), low.max(high)));
+                j += 3;
+            } else {
+                ranges.push((items[j].0, items[j].0));
+                j += 1;
+            }
+        }
+        Some((Token::Class(ranges, negated), i + 1))
+    }
+
+    fn reference(tokens: &[Token], text: &[u8]) -> bool {
+        match tokens.split_first() {
+            None => text.is_empty(),
+            Some((Token::Star, rest)) => {
+                (0..=text.len()).any(|skip| reference(rest, &text[skip..]))
+            }
+            Some((_, _)) if text.is_empty() => false,
+            Some((Token::Any, rest)) => reference(rest, &text[1..]),
+            Some((Token::Class(ranges, negated), rest)) => {
+                let matched = ranges
+                    .iter()
+                    .any(|(low, high)| (*low..=*high).contains(&text[0]));
+                matched != *negated && reference(rest, &text[1..])
+            }
+        }
+    }
+
+    /// xorshift64, enough to draw reproducible cases without a dependency.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+
+        fn string(&mut self, pieces: &[&str], max: usize) -> Vec<u8> {
+            let len = self.below(max + 1);
+            (0..len)
+                .flat_map(|_| pieces[self.below(pieces.len())].bytes())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_matches_reference() {
+        let pattern_pieces = [
+            "a", "b", "c", "-", "*", "*", "?", "[ab]", "[^a]", "[a-b]", "[c-a]", "\\*", "\\[", "[",
+            "]", "[\\]]",
+        ];
+        let text_pieces = ["a", "b", "c", "-", "*", "[", "]", "d"];
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        for _ in 0..20_000 {
+            let pattern = rng.string(&pattern_pieces, 6);
+            let text = rng.string(&text_pieces, 8);
+            assert_eq!(
+                glob_match(&pattern, &text),
+                reference(&tokenize(&pattern), &text),
+                "pattern {:?} text {:?}",
+                String::from_utf8_lossy(&pattern),
+                String::from_utf8_lossy(&text),
+            );
+        }
+    }
+}
@@ -0,0 +1,442 @@
+//! Gossip-based membership of cluster nodes
+//!
+//! Besides the port serving clients, each node listens on a gossip port. Every
+//! [`GossipConfig::interval`], it bumps its heartbeat and exchanges its view of
+//! the cluster with a few peers: the id, address, health and slots of every
+//! node it knows. Both sides merge the view of the other, keeping the latest
+//! heartbeat of each node, so that nodes learn about each other through the
+//! seeds alone. A peer whose heartbeat hasn't moved for
+//! [`GossipConfig::fail_timeout`] is deemed failed, until it moves again.
+//!
+//! `CLUSTER NODES` lists the view of a node, see [`Membership::describe`].
+//!
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    ops::RangeInclusive,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time,
+};
+use tracing::{debug, info, warn};
+
+use crate::{spawn_named, ClusterState, Connection, Frame};
+
+/// The number of peers a node gossips with per round.
+const FANOUT: usize = 3;
+
+/// Frames can't nest, so gossip messages list the fields of every node in a
+/// row, this many per node.
+const NODE_FIELDS: usize = 6;
+
+#[derive(Debug, Clone)]
+pub struct GossipConfig {
+    /// Identifies the node in the cluster.
+    pub node_id: String,
+    /// The address of the gossip port, which peers reach it at.
+    pub addr: String,
+    /// The gossip addresses of nodes to join the cluster through.
+    pub seeds: Vec<String>,
+    /// How often views are exchanged.
+    pub interval: Duration,
+    /// Peers whose heartbeat hasn't moved for this long are deemed failed.
+    pub fail_timeout: Duration,
+}
+
+impl GossipConfig {
+    /// Gossips on `addr` under an id made up from it and the current time.
+    pub fn new<I>(addr: impl ToString, seeds: I) -> GossipConfig
+    where
+        I: IntoIterator,
+        I::Item: ToString,
+    {
+        let addr = addr.to_string();
+        let mut hasher = DefaultHasher::new();
+        addr.hash(&mut hasher);
+        SystemTime::now().hash(&mut hasher);
+        std::process::id().hash(&mut hasher);
+        GossipConfig {
+            node_id: format!("{:016x}", hasher.finish()),
+            addr,
+            seeds: seeds.into_iter().map(|seed| seed.to_string()).collect(),
+            interval: Duration::from_millis(100),
+            fail_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// What a node tells about itself to its peers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeInfo {
+    pub id: String,
+    /// Where clients reach the node.
+    pub addr: String,
+    pub gossip_addr: String,
+    /// Bumped by the node every round. It starts from the time the node
+    /// started, in milliseconds, so that it keeps growing across restarts.
+    pub heartbeat: u64,
+    /// The slots the node serves.
+    pub slots: Vec<RangeInclusive<u16>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    Ok,
+    Failed,
+}
+
+/// The view of the cluster of this node, shared by its connections.
+#[derive(Debug, Clone)]
+pub struct Membership {
+    config: Arc<GossipConfig>,
+    view: Arc<Mutex<View>>,
+}
+
+#[derive(Debug)]
+struct View {
+    myself: NodeInfo,
+    peers: HashMap<String, Peer>,
+    rounds: usize,
+}
+
+#[derive(Debug)]
+struct Peer {
+    info: NodeInfo,
+    /// When its heartbeat last moved.
+    updated: Instant,
+}
+
+impl Membership {
+    /// The membership of a node reached by clients at `addr`, knowing no peer
+    /// yet.
+    pub fn new(config: GossipConfig, addr: impl ToString) -> Membership {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let myself = NodeInfo {
+            id: config.node_id.clone(),
+            addr: addr.to_string(),
+            gossip_addr: config.addr.clone(),
+            heartbeat: started.as_millis() as u64,
+            slots: vec![],
+        };
+        Membership {
+            config: Arc::new(config),
+            view: Arc::new(Mutex::new(View {
+                myself,
+                peers: HashMap::new(),
+                rounds: 0,
+            })),
+        }
+    }
+
+    /// Listens to the gossip port and spawns the tasks gossiping on it, so it
+    /// must be called within a tokio runtime. The slots of this node are
+    /// taken from `cluster`.
+    pub async fn start(&self, cluster: Option<ClusterState>) -> Result<()> {
+        let listener = TcpListener::bind(&self.config.addr).await?;
+        info!(addr = %self.config.addr, "gossip started");
+        spawn_named("gossip listener", self.clone().listen(listener));
+        spawn_named("gossip", self.clone().gossip(cluster));
+        Ok(())
+    }
+
+    /// The nodes known, this one first.
+    pub fn nodes(&self) -> Vec<(NodeInfo, Health)> {
+        let view = self.view.lock().unwrap();
+        let mut peers: Vec<&Peer> = view.peers.values().collect();
+        peers.sort_by(|a, b| a.info.id.cmp(&b.info.id));
+        let mut nodes = vec![(view.myself.clone(), Health::Ok)];
+        for peer in peers {
+            nodes.push((peer.info.clone(), self.health(peer)));
+        }
+        nodes
+    }
+
+    /// One line per node known, this one first, like
+    /// `<id> <addr> <gossip addr> <flags> <slots>...`, where the flags are
+    /// `myself`, `ok` or `fail`, and slots are ranges like `0-5460`.
+    pub fn describe(&self) -> String {
+        let mut nodes = String::new();
+        for (i, (node, health)) in self.nodes().into_iter().enumerate() {
+            let flags = match health {
+                _ if i == 0 => "myself",
+                Health::Ok => "ok",
+                Health::Failed => "fail",
+            };
+            let line = format!("{} {} {} {}", node.id, node.addr, node.gossip_addr, flags);
+            nodes.push_str(&line);
+            for range in &node.slots {
+                nodes.push(' ');
+                nodes.push_str(&format_range(range));
+            }
+            nodes.push('\n');
+        }
+        nodes
+    }
+
+    fn health(&self, peer: &Peer) -> Health {
+        if peer.updated.elapsed() > self.config.fail_timeout {
+            Health::Failed
+        } else {
+            Health::Ok
+        }
+    }
+
+    /// Serves the peers gossiping with this node.
+    async fn listen(self, listener: TcpListener) {
+        loop {
+            let (socket, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!(cause = %err, "failed to accept a peer");
+                    continue;
+                }
+            };
+            let membership = self.clone();
+            spawn_named(&format!("gossip peer {}", peer), async move {
+                if let Err(err) = membership.reply(socket).await {
+                    debug!(cause = %err, "failed to gossip with a peer");
+                }
+            });
+        }
+    }
+
+    /// Merges the view of a peer, replying with the view of this node.
+    async fn reply(&self, socket: TcpStream) -> Result<()> {
+        let mut connection = Connection::new(socket);
+        while let Some(frame) = connection.read_frame().await? {
+            self.merge(parse_message(frame)?);
+            connection.write_frame(&self.message()).await?;
+        }
+        Ok(())
+    }
+
+    /// Exchanges views with a few peers every round.
+    async fn gossip(self, cluster: Option<ClusterState>) {
+        let mut ticks = time::interval(self.config.interval);
+        loop {
+            ticks.tick().await;
+            for target in self.round(cluster.as_ref()) {
+                let exchange = time::timeout(self.config.interval, self.exchange(&target));
+                match exchange.await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => debug!(%target, cause = %err, "failed to gossip"),
+                    Err(_) => debug!(%target, "gossip timed out"),
+                }
+            }
+        }
+    }
+
+    async fn exchange(&self, target: &str) -> Result<()> {
+        let mut connection = Connection::new(TcpStream::connect(target).await?);
+        connection.write_frame(&self.message()).await?;
+        let frame = connection
+            .read_frame()
+            .await?
+            .ok_or_else(|| anyhow!("the peer closed the connection"))?;
+        self.merge(parse_message(frame)?);
+        Ok(())
+    }
+
+    /// Bumps the heartbeat of this node, and picks the peers to gossip with
+    /// in turn among the seeds and the nodes known.
+    fn round(&self, cluster: Option<&ClusterState>) -> Vec<String> {
+        let mut view = self.view.lock().unwrap();
+        view.myself.heartbeat += 1;
+        if let Some(cluster) = cluster {
+            view.myself.slots = cluster
+                .topology()
+                .ranges()
+                .into_iter()
+                .filter(|(_, owner)| *owner == cluster.myself())
+                .map(|(range, _)| range)
+                .collect();
+        }
+
+        let mut targets: Vec<String> = self.config.seeds.clone();
+        targets.extend(
+            view.peers
+                .values()
+                .map(|peer| peer.info.gossip_addr.clone()),
+        );
+        targets.retain(|target| *target != self.config.addr);
+        targets.sort();
+        targets.dedup();
+        if targets.is_empty() {
+            return targets;
+        }
+        let start = view.rounds * FANOUT % targets.len();
+        view.rounds += 1;
+        targets
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(FANOUT.min(targets.len()))
+            .cloned()
+            .collect()
+    }
+
+    fn message(&self) -> Frame {
+        let mut message = vec![Frame::Text("gossip".to_string())];
+        for (node, health) in self.nodes() {
+            let slots: Vec<String> = node.slots.iter().map(format_range).collect();
+            let health = match health {
+                Health::Ok => "ok",
+                Health::Failed => "fail",
+            };
+            message.extend([
+                Frame::Text(node.id),
+                Frame::Text(node.addr),
+                Frame::Text(node.gossip_addr),
+                Frame::Integer(node.heartbeat as i64),
+                Frame::Text(health.to_string()),
+                Frame::Text(slots.join(" ")),
+            ]);
+        }
+        Frame::Array(message)
+    }
+
+    /// Keeps the latest heartbeat of each node. Nodes reported failed are
+    /// only learned about once their heartbeat moves.
+    fn merge(&self, nodes: Vec<(NodeInfo, Health)>) {
+        let mut view = self.view.lock().unwrap();
+        for (node, health) in nodes {
+            if node.id == view.myself.id {
+                continue;
+            }
+            match view.peers.get_mut(&node.id) {
+                Some(peer) if node.heartbeat > peer.info.heartbeat => {
+                    peer.info = node;
+                    peer.updated = Instant::now();
+                }
+                Some(_) => {}
+                None if health == Health::Ok => {
+                    debug!(id = %node.id, addr = %node.addr, "discovered a node");
+                    let peer = Peer {
+                        info: node,
+                        updated: Instant::now(),
+                    };
+                    view.peers.insert(peer.info.id.clone(), peer);
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+fn format_range(range: &RangeInclusive<u16>) -> String {
+    if range.start() == range.end() {
+        range.start().to_string()
+    } else {
+        format!("{}-{}", range.start(), range.end())
+    }
+}
+
+fn parse_range(range: &str) -> Option<RangeInclusive<u16>> {
+    match range.split_once('-') {
+        Some((start, end)) => Some(start.parse().ok()?..=end.parse().ok()?),
+        None => {
+            let slot = range.parse().ok()?;
+            Some(slot..=slot)
+        }
+    }
+}
+
+fn parse_message(frame: Frame) -> Result<Vec<(NodeInfo, Health)>> {
+    let malformed = || anyhow!("malformed gossip message");
+    let Frame::Array(frames) = frame else {
+        return Err(malformed());
+    };
+    let Some((Frame::Text(kind), nodes)) = frames.split_first() else {
+        return Err(malformed());
+    };
+    if kind != "gossip" || nodes.len() % NODE_FIELDS != 0 {
+        return Err(malformed());
+    }
+    nodes
+        .chunks(NODE_FIELDS)
+        .map(|fields| parse_node(fields).ok_or_else(malformed))
+        .collect()
+}
+
+fn parse_node(fields: &[Frame]) -> Option<(NodeInfo, Health)> {
+    let [id, addr, gossip_addr, heartbeat, health, slots] = fields else {
+        return None;
+    };
+    let text = |frame: &Frame| match frame {
+        Frame::Text(text) => Some(text.clone()),
+        _ => None,
+    };
+    let Frame::Integer(heartbeat) = heartbeat else {
+        return None;
+    };
+    let health = match text(health)?.as_str() {
+        "ok" => Health::Ok,
+        _ => Health::Failed,
+    };
+    let slots = text(slots)?
+        .split_whitespace()
+        .map(parse_range)
+        .collect::<Option<_>>()?;
+    let node = NodeInfo {
+        id: text(id)?,
+        addr: text(addr)?,
+        gossip_addr: text(gossip_addr)?,
+        heartbeat: *heartbeat as u64,
+        slots,
+    };
+    Some((node, health))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, heartbeat: u64) -> NodeInfo {
+        NodeInfo {
+            id: id.to_string(),
+            addr: format!("{}:6379", id),
+            gossip_addr: format!("{}:16379", id),
+            heartbeat,
+            slots: vec![0..=99, 100..=100],
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut config = GossipConfig::new("a:16379", ["b:16379"]);
+        config.fail_timeout = Duration::from_millis(50);
+        let membership = Membership::new(config, "a:6379");
+        let message = parse_message(membership.message()).unwrap();
+        assert_eq!(message.len(), 1);
+
+        membership.merge(vec![
+            (node("b", 10), Health::Ok),
+            (node("c", 5), Health::Failed),
+        ]);
+        let nodes = membership.nodes();
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[1], (node("b", 10), Health::Ok));
+        assert!(membership
+            .describe()
+            .ends_with("b b:6379 b:16379 ok 0-99 100\n"));
+
+        std::thread::sleep(Duration::from_millis(60));
+        membership.merge(vec![(node("b", 9), Health::Ok)]);
+        assert_eq!(membership.nodes()[1].1, Health::Failed);
+        membership.merge(vec![(node("b", 11), Health::Ok)]);
+        assert_eq!(membership.nodes()[1].1, Health::Ok);
+
+        let round = membership.round(None);
+        assert_eq!(round, vec!["b:16379".to_string()]);
+        let message = parse_message(membership.message()).unwrap();
+        assert_eq!(message[1].0, node("b", 11));
+    }
+}
@@ -0,0 +1,199 @@
+//! Liveness and readiness probes
+//!
+//! A server is live as long as it answers, which `PING` checks, and ready once
+//! its data is loaded, which `READY` checks: it replies `+OK`, or `-LOADING`
+//! while the append-only file is being replayed. Connections are accepted
+//! while it loads, but only `PING`, `INFO`, `READY`, `AUTH` and `HELLO` are
+//! served, the other commands being answered with `-LOADING` too. How much of
+//! the file is replayed is reported by `INFO persistence`, see
+//! [`Readiness::progress`]. Orchestrators probing over
+//! HTTP rather than the wire protocol can be served on a port of their own,
+//! see [`HealthConfig`], where:
+//!
+//! - `GET /livez` replies `200 OK` whenever the server runs;
+//! - `GET /readyz` replies `200 OK` once it is ready, `503 Service Unavailable`
+//!   before;
+//! - `GET /metrics` replies with the [statistics of the
+//!   commands](crate::commandstats) in the Prometheus text format.
+
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
+
+use anyhow::Result;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, info, warn};
+
+use crate::{spawn_named, CommandStats};
+
+/// The address of the HTTP probes, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct HealthConfig {
+    pub addr: String,
+}
+
+/// The reply to the commands sent while the server loads its data.
+pub(crate) const LOADING: &str = "the server is loading its data";
+
+/// Whether the server is ready to serve its data, shared by the connections
+/// and the probes.
+#[derive(Debug, Clone, Default)]
+pub struct Readiness {
+    state: Arc<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    ready: AtomicBool,
+    /// The bytes of data replayed so far, out of `total`.
+    loaded: AtomicU64,
+    total: AtomicU64,
+}
+
+impl Readiness {
+    /// A server not ready yet.
+    pub fn new() -> Readiness {
+        Readiness::default()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.state.ready.load(Ordering::SeqCst)
+    }
+
+    pub fn set_ready(&self) {
+        self.state.ready.store(true, Ordering::SeqCst);
+    }
+
+    /// Starts loading `total` bytes of data.
+    pub fn start_loading(&self, total: u64) {
+        self.state.loaded.store(0, Ordering::Relaxed);
+        self.state.total.store(total, Ordering::Relaxed);
+    }
+
+    /// Records that the first `loaded` bytes of the data are replayed.
+    pub fn set_loaded(&self, loaded: u64) {
+        self.state.loaded.store(loaded, Ordering::Relaxed);
+    }
+
+    /// The bytes of data loaded so far and how many there are in all, while
+    /// the server is not ready.
+    pub fn progress(&self) -> Option<(u64, u64)> {
+        let loaded = self.state.loaded.load(Ordering::Relaxed);
+        let total = self.state.total.load(Ordering::Relaxed);
+        (!self.is_ready()).then_some((loaded, total))
+    }
+
+    /// Binds the HTTP probes to `config.addr` and serves them in the
+    /// background, so it must be called within a tokio runtime. `commands`
+    /// are served as the metrics.
+    pub async fn serve(&self, config: &HealthConfig, commands: CommandStats) -> Result<()> {
+        let listener = TcpListener::bind(&config.addr).await?;
+        info!(addr = %config.addr, "health probes started");
+        spawn_named("health probes", self.clone().listen(listener, commands));
+        Ok(())
+    }
+
+    async fn listen(self, listener: TcpListener, commands: CommandStats) {
+        loop {
+            let socket = match listener.accept().await {
+                Ok((socket, _)) => socket,
+                Err(err) => {
+                    warn!(cause = %err, "failed to accept a probe");
+                    continue;
+                }
+            };
+            let readiness = self.clone();
+            let commands = commands.clone();
+            spawn_named("health probe", async move {
+                if let Err(err) = readiness.reply(socket, &commands).await {
+                    debug!(cause = %err, "failed to answer a probe");
+                }
+            });
+        }
+    }
+
+    /// Answers a single request, then closes the connection. Only its request
+    /// line matters, the headers are skipped.
+    async fn reply(&self, socket: TcpStream, commands: &CommandStats) -> Result<()> {
+        let mut socket = BufReader::new(socket);
+        let mut request = String::new();
+        socket.read_line(&mut request).await?;
+        loop {
+            let mut header = String::new();
+            if socket.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+                break;
+            }
+        }
+        let mut words = request.split_whitespace();
+        let (status, body) = match (words.next(), words.next()) {
+            (Some("GET"), Some("/livez")) => ("200 OK", None),
+            (Some("GET"), Some("/readyz")) if self.is_ready() => ("200 OK", None),
+            (Some("GET"), Some("/readyz")) => ("503 Service Unavailable", None),
+            (Some("GET"), Some("/metrics")) => ("200 OK", Some(commands.prometheus())),
+            _ => ("404 Not Found", None),
+        };
+        let body = body.unwrap_or_else(|| format!("{}\n", status));
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        socket.get_mut().write_all(response.as_bytes()).await?;
+        socket.get_mut().shutdown().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let response = fetch(addr, path).await;
+        response.lines().next().unwrap().to_string()
+    }
+
+    async fn fetch(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut socket = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        socket.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_probes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let readiness = Readiness::new();
+        let commands = CommandStats::new();
+        tokio::spawn(readiness.clone().listen(listener, commands.clone()));
+
+        assert_eq!(get(addr, "/livez").await, "HTTP/1.1 200 OK");
+        assert_eq!(
+            get(addr, "/readyz").await,
+            "HTTP/1.1 503 Service Unavailable"
+        );
+        readiness.start_loading(100);
+        readiness.set_loaded(40);
+        assert_eq!(readiness.progress(), Some((40, 100)));
+        readiness.set_ready();
+        assert_eq!(readiness.progress(), None);
+        assert_eq!(get(addr, "/readyz").await, "HTTP/1.1 200 OK");
+        assert_eq!(get(addr, "/nosuchprobe").await, "HTTP/1.1 404 Not Found");
+
+        commands.record("get", std::time::Duration::from_micros(10), false);
+        let metrics = fetch(addr, "/metrics").await;
+        assert!(metrics.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(metrics.contains("\r\n\r\n# HELP uranus_command_calls_total"));
+        assert!(metrics.contains("uranus_command_calls_total{command=\"get\"} 1\n"));
+    }
+}
@@ -0,0 +1,96 @@
+//! Bounded history of values
+//!
+//! Keys can opt in to keeping their last few values, say configuration keys
+//! that need auditing. Every value written by `SET` becomes a new revision, and
+//! the oldest one is pruned once a key holds more than its depth.
+//!
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use bytes::Bytes;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revision {
+    pub value: Bytes,
+    pub written_at: SystemTime,
+}
+
+/// Revisions of the keys keeping some, shared by all connections. A key keeps
+/// at most `max_depth` revisions.
+#[derive(Debug, Clone)]
+pub struct History {
+    keys: Arc<Mutex<HashMap<Bytes, Revisions>>>,
+    max_depth: usize,
+}
+
+#[derive(Debug)]
+struct Revisions {
+    depth: usize,
+    /// The latest revision first.
+    revisions: VecDeque<Revision>,
+}
+
+impl Revisions {
+    fn prune(&mut self) {
+        self.revisions.truncate(self.depth);
+    }
+}
+
+impl History {
+    pub fn new(max_depth: usize) -> History {
+        History {
+            keys: Arc::new(Mutex::new(HashMap::new())),
+            max_depth,
+        }
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Keeps the last `depth` revisions of `key` from now on, or forgets them
+    /// all if `depth` is 0. `current` starts the history of a key not keeping
+    /// any yet.
+    pub fn keep(&self, key: Bytes, depth: usize, current: Option<Bytes>) {
+        let depth = depth.min(self.max_depth);
+        let mut keys = self.keys.lock().unwrap();
+        if depth == 0 {
+            keys.remove(&key);
+            return;
+        }
+        let revisions = keys.entry(key).or_insert_with(|| Revisions {
+            depth,
+            revisions: current
+                .map(|value| Revision {
+                    value,
+                    written_at: SystemTime::now(),
+                })
+                .into_iter()
+                .collect(),
+        });
+        revisions.depth = depth;
+        revisions.prune();
+    }
+
+    /// Records a value written to `key`, if it keeps revisions.
+    pub fn record(&self, key: &Bytes, value: Bytes) {
+        let mut keys = self.keys.lock().unwrap();
+        if let Some(revisions) = keys.get_mut(key) {
+            revisions.revisions.push_front(Revision {
+                value,
+                written_at: SystemTime::now(),
+            });
+            revisions.prune();
+        }
+    }
+
+    /// The revision of `key` written `back` writes ago, 0 being the latest.
+    pub fn get(&self, key: &Bytes, back: usize) -> Option<Revision> {
+        let keys = self.keys.lock().unwrap();
+        keys.get(key)?.revisions.get(back).cloned()
+    }
+}
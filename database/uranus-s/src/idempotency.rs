@@ -0,0 +1,201 @@
+//! Idempotency tokens of write commands
+//!
+//! A client retrying a write after a timeout can't know whether the first
+//! attempt was applied. Writes carrying a token are applied at most once
+//! while the token is remembered, duplicates get the reply of the first one.
+//!
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{ErrorCode, Frame};
+
+const TOKEN_IN_USE: &str = "a write with this idempotency token is in progress";
+const TOKEN_REUSED: &str = "the idempotency token was used for another key";
+
+/// Recently seen tokens, shared by all connections. A token is forgotten after
+/// `ttl`, or earlier when more than `capacity` tokens are remembered.
+#[derive(Debug, Clone)]
+pub struct IdempotencyCache {
+    inner: Arc<Mutex<SeenTokens>>,
+}
+
+#[derive(Debug)]
+struct SeenTokens {
+    tokens: HashMap<String, Seen>,
+    /// Tokens in the order they were seen, for expiry.
+    order: VecDeque<(Instant, String)>,
+    ttl: Duration,
+    capacity: usize,
+}
+
+/// A token, and the write it was seen with.
+#[derive(Debug)]
+struct Seen {
+    seen_at: Instant,
+    key: Bytes,
+    /// The reply of the write, once it is applied.
+    reply: Option<Frame>,
+}
+
+impl IdempotencyCache {
+    pub fn new(ttl: Duration, capacity: usize) -> IdempotencyCache {
+        IdempotencyCache {
+            inner: Arc::new(Mutex::new(SeenTokens {
+                tokens: HashMap::new(),
+                order: VecDeque::new(),
+                ttl,
+                capacity,
+            })),
+        }
+    }
+
+    /// Runs `write` of `key` and remembers its reply under `token`, unless the
+    /// token was already seen, then the remembered reply is returned without
+    /// running it. The token is reserved while `write` runs, without holding
+    /// the lock of the cache, so a duplicate meanwhile is refused. So is a
+    /// token seen with another key. Failed writes aren't remembered, so they
+    /// can be retried.
+    pub fn run_once(
+        &self,
+        token: &str,
+        key: &Bytes,
+        write: impl FnOnce() -> Result<Frame>,
+    ) -> Result<Frame> {
+        {
+            let mut seen = self.lock();
+            let now = Instant::now();
+            seen.forget_expired(now);
+            match seen.tokens.get(token) {
+                Some(seen) if seen.key != key => {
+                    return Ok(Frame::error(ErrorCode::Err, TOKEN_REUSED))
+                }
+                Some(Seen {
+                    reply: Some(reply), ..
+                }) => return Ok(reply.clone()),
+                Some(_) => return Ok(Frame::error(ErrorCode::Err, TOKEN_IN_USE)),
+                None => seen.reserve(token.to_string(), key.clone(), now),
+            }
+        }
+        let mut reservation = Reservation {
+            cache: self,
+            token,
+            reply: None,
+        };
+        let reply = write()?;
+        reservation.reply = Some(reply.clone());
+        Ok(reply)
+    }
+
+    /// Nothing panics under the lock, but a poisoned one would still be
+    /// consistent.
+    fn lock(&self) -> MutexGuard<'_, SeenTokens> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A token reserved by [`IdempotencyCache::run_once`]. Once dropped, the reply
+/// is remembered, or the token released if the write failed or panicked.
+struct Reservation<'a> {
+    cache: &'a IdempotencyCache,
+    token: &'a str,
+    reply: Option<Frame>,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let mut seen = self.cache.lock();
+        match self.reply.take() {
+            Some(reply) => {
+                if let Some(seen) = seen.tokens.get_mut(self.token) {
+                    seen.reply = Some(reply);
+                }
+            }
+            None => {
+                seen.tokens.remove(self.token);
+            }
+        }
+    }
+}
+
+impl SeenTokens {
+    fn forget_expired(&mut self, now: Instant) {
+        while let Some((seen_at, _)) = self.order.front() {
+            if now.duration_since(*seen_at) < self.ttl && self.order.len() <= self.capacity {
+                break;
+            }
+            if let Some((seen_at, token)) = self.order.pop_front() {
+                // The token may have been released and seen again since.
+                if self
+                    .tokens
+                    .get(&token)
+                    .is_some_and(|seen| seen.seen_at == seen_at)
+                {
+                    self.tokens.remove(&token);
+                }
+            }
+        }
+    }
+
+    fn reserve(&mut self, token: String, key: Bytes, now: Instant) {
+        self.order.push_back((now, token.clone()));
+        let seen = Seen {
+            seen_at: now,
+            key,
+            reply: None,
+        };
+        self.tokens.insert(token, seen);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn test_run_once() {
+        let cache = IdempotencyCache::new(Duration::from_secs(60), 16);
+        let key = Bytes::from("key");
+        let ok = || Ok(Frame::Text("OK".to_string()));
+        let is_error = |reply: Frame| matches!(reply, Frame::Error(_));
+
+        // A duplicate of a running write is refused, without waiting for it.
+        let (started, wait_started) = mpsc::channel();
+        let (finish, wait_finish) = mpsc::channel::<()>();
+        let running = {
+            let (cache, key) = (cache.clone(), key.clone());
+            std::thread::spawn(move || {
+                cache.run_once("token", &key, || {
+                    started.send(()).unwrap();
+                    wait_finish.recv().unwrap();
+                    ok()
+                })
+            })
+        };
+        wait_started.recv().unwrap();
+        assert!(is_error(cache.run_once("token", &key, ok).unwrap()));
+        finish.send(()).unwrap();
+        assert_eq!(running.join().unwrap().unwrap(), ok().unwrap());
+
+        // Once applied, duplicates get its reply, and the token can't be used
+        // for another key.
+        let duplicate = cache.run_once("token", &key, || panic!("applied twice"));
+        assert_eq!(duplicate.unwrap(), ok().unwrap());
+        let other = cache.run_once("token", &Bytes::from("other"), ok).unwrap();
+        assert!(is_error(other));
+
+        // Failed writes release the token.
+        let failed = cache.run_once("retried", &key, || Err(anyhow::anyhow!("failed")));
+        assert!(failed.is_err());
+        let retried = cache.run_once("retried", &key, || Ok(Frame::Integer(1)));
+        assert_eq!(retried.unwrap(), Frame::Integer(1));
+    }
+}
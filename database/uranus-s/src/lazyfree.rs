@@ -0,0 +1,184 @@
+//! Freeing large values in the background
+//!
+//! Dropping a value takes as long as freeing each of its elements, which for
+//! a collection of millions of them stalls the other connections waiting on
+//! the lock of its stripe. `UNLINK` rather takes the values of its keys out
+//! under the lock and hands them to [`LazyFree::free`] once it is released,
+//! which drops those with more elements than the threshold on a background
+//! task, the others right away. `DEL` does the same if
+//! [`LazyFreeConfig::lazy_user_del`] is set, as Redis does with
+//! `lazyfree-lazy-user-del`. Both can be changed at runtime by `CONFIG SET
+//! lazyfree-threshold <elements>` and `CONFIG SET lazyfree-lazy-user-del
+//! <yes|no>`.
+//!
+//! `INFO memory` reports `lazyfree_pending_objects`, the values handed over
+//! but not dropped yet, and `lazyfreed_objects`, those dropped so far.
+
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::{spawn_named, Value};
+
+#[derive(Debug, Clone, Copy)]
+pub struct LazyFreeConfig {
+    /// Values with more elements than this are dropped in the background,
+    /// see [`free_effort`].
+    pub threshold: usize,
+    /// Whether `DEL` frees in the background too, as `UNLINK` does.
+    pub lazy_user_del: bool,
+}
+
+/// As Redis' `LAZYFREE_THRESHOLD`.
+pub const DEFAULT_LAZYFREE_THRESHOLD: usize = 64;
+
+impl Default for LazyFreeConfig {
+    fn default() -> Self {
+        LazyFreeConfig {
+            threshold: DEFAULT_LAZYFREE_THRESHOLD,
+            lazy_user_del: false,
+        }
+    }
+}
+
+/// Drops large values in the background, shared by all connections.
+#[derive(Debug, Clone)]
+pub struct LazyFree {
+    inner: Arc<Inner>,
+    values: UnboundedSender<Value>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    threshold: AtomicUsize,
+    lazy_user_del: AtomicBool,
+    pending: AtomicU64,
+    freed: AtomicU64,
+}
+
+impl LazyFree {
+    /// Starts the task dropping the values, so it must be called within a
+    /// tokio runtime.
+    pub fn new(config: LazyFreeConfig) -> LazyFree {
+        let inner = Arc::new(Inner {
+            threshold: AtomicUsize::new(config.threshold),
+            lazy_user_del: AtomicBool::new(config.lazy_user_del),
+            pending: AtomicU64::new(0),
+            freed: AtomicU64::new(0),
+        });
+        let (values, received) = mpsc::unbounded_channel();
+        spawn_named("lazy free", drop_values(received, inner.clone()));
+        LazyFree { inner, values }
+    }
+
+    /// Drops `value`, in the background if it is larger than the threshold.
+    /// It must not be called with the lock of a stripe held.
+    pub fn free(&self, value: Value) {
+        if free_effort(&value) <= self.threshold() {
+            return;
+        }
+        self.inner.pending.fetch_add(1, Ordering::Relaxed);
+        if let Err(unsent) = self.values.send(value) {
+            // The runtime is shutting down, the value is dropped here.
+            self.inner.pending.fetch_sub(1, Ordering::Relaxed);
+            drop(unsent);
+        }
+    }
+
+    /// See [`LazyFreeConfig::threshold`].
+    pub fn threshold(&self) -> usize {
+        self.inner.threshold.load(Ordering::Relaxed)
+    }
+
+    pub fn set_threshold(&self, threshold: usize) {
+        self.inner.threshold.store(threshold, Ordering::Relaxed);
+    }
+
+    /// See [`LazyFreeConfig::lazy_user_del`].
+    pub fn lazy_user_del(&self) -> bool {
+        self.inner.lazy_user_del.load(Ordering::Relaxed)
+    }
+
+    pub fn set_lazy_user_del(&self, lazy: bool) {
+        self.inner.lazy_user_del.store(lazy, Ordering::Relaxed);
+    }
+
+    /// The values handed to the background task not dropped yet.
+    pub fn pending(&self) -> u64 {
+        self.inner.pending.load(Ordering::Relaxed)
+    }
+
+    /// The values the background task dropped so far.
+    pub fn freed(&self) -> u64 {
+        self.inner.freed.load(Ordering::Relaxed)
+    }
+}
+
+/// How many allocations dropping `value` frees, as Redis' `lazyfreeGetFreeEffort`:
+/// 1 for a string, the number of elements of a collection.
+pub fn free_effort(value: &Value) -> usize {
+    match value {
+        Value::String(_) => 1,
+        Value::Hash(hash) => hash.len(),
+        Value::Queue(queue) => queue.len(),
+        Value::List(list) => list.len(),
+        Value::Set(set) => set.len(),
+        Value::SortedSet(set) => set.len(),
+        Value::Stream(stream) => stream.len(),
+    }
+}
+
+async fn drop_values(mut values: UnboundedReceiver<Value>, inner: Arc<Inner>) {
+    while let Some(value) = values.recv().await {
+        drop(value);
+        inner.pending.fetch_sub(1, Ordering::Relaxed);
+        inner.freed.fetch_add(1, Ordering::Relaxed);
+        tokio::task::yield_now().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Duration};
+
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn set(len: usize) -> Value {
+        Value::Set(
+            (0..len)
+                .map(|n| Bytes::from(n.to_string()))
+                .collect::<HashSet<_>>(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_lazy_free() {
+        let lazyfree = LazyFree::new(LazyFreeConfig {
+            threshold: 10,
+            ..LazyFreeConfig::default()
+        });
+        assert_eq!(free_effort(&Value::String(Bytes::from("value"))), 1);
+        assert_eq!(free_effort(&set(10)), 10);
+
+        lazyfree.free(set(10));
+        lazyfree.free(set(11));
+        lazyfree.free(set(1000));
+        for _ in 0..100 {
+            if lazyfree.freed() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!((lazyfree.freed(), lazyfree.pending()), (2, 0));
+
+        lazyfree.set_threshold(usize::MAX);
+        lazyfree.free(set(1000));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(lazyfree.freed(), 2);
+    }
+}
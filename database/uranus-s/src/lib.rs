@@ -1,34 +1,272 @@
 //! Uranus server library & Client-Server interface
 //!
 
+pub mod acl;
+pub use acl::*;
+
+pub mod aof;
+pub use aof::*;
+
+pub mod buffer;
+pub use buffer::*;
+
+pub mod clients;
+pub use clients::*;
+
+pub mod cluster;
+pub use cluster::*;
+
 pub mod command;
 pub use command::*;
 
+pub mod commandstats;
+pub use commandstats::*;
+
 pub mod db;
 pub use db::*;
 
-use std::{io::Cursor, time::Duration};
+pub mod deadline;
+pub use deadline::*;
+
+pub mod embedded;
+pub use embedded::*;
+
+pub mod error;
+pub use error::*;
+
+pub mod config;
+pub use config::*;
+
+pub mod events;
+pub use events::*;
+
+#[cfg(feature = "faults")]
+pub mod fault;
+#[cfg(feature = "faults")]
+pub use fault::*;
+
+pub mod glob;
+pub use glob::*;
+
+pub mod gossip;
+pub use gossip::*;
+
+pub mod health;
+pub use health::*;
+
+pub mod history;
+pub use history::*;
+
+pub mod idempotency;
+pub use idempotency::*;
+
+pub mod lazyfree;
+pub use lazyfree::*;
+
+pub mod logging;
+pub use logging::*;
+
+pub mod pubsub;
+pub use pubsub::*;
+
+pub mod scripting;
+pub use scripting::*;
+
+pub mod shadow;
+pub use shadow::*;
+
+pub mod shared;
+pub use shared::*;
+
+pub mod slots;
+pub use slots::*;
+
+pub mod stats;
+pub use stats::*;
+
+pub mod tasks;
+pub use tasks::*;
+
+pub mod test_util;
+
+pub mod trace;
+pub use trace::*;
+
+pub mod waiters;
+pub use waiters::*;
+
+use std::{
+    future::{poll_fn, Future},
+    io::Cursor,
+    net::SocketAddr,
+    panic::{catch_unwind, AssertUnwindSafe},
+    pin::pin,
+    sync::{Arc, OnceLock},
+    task::Poll,
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, Result};
-use bytes::{Buf, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, BufWriter},
-    net::{TcpListener, TcpStream},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter, DuplexStream},
+    net::TcpListener,
+    sync::mpsc,
     time,
 };
-use tracing::{debug, error, info};
+use tracing::{debug, error, field, info, info_span, trace, warn, Instrument};
+use uranus_kv::StorageError;
 
 pub async fn run(listener: TcpListener) {
-    let mut server = Listener {
-        listener,
-        db: DBHandle::new(),
+    run_with_config(listener, ServerConfig::default()).await
+}
+
+pub async fn run_with_config(listener: TcpListener, config: ServerConfig) {
+    let db = match DBHandle::open(config.databases, config.shards, &config.storage) {
+        Ok(db) => db
+            .limit_memory(config.max_memory, config.eviction_policy)
+            .compress_values(config.compression, config.compression_threshold)
+            .sync_wal(config.wal_sync),
+        Err(err) => {
+            error!(cause = %err, "failed to open the storage");
+            return;
+        }
     };
+    run_with_database(listener, config, db).await
+}
+
+/// Serves `db` instead of the default [`DBHandle`].
+///
+/// Connections are accepted as soon as the server starts, and answered with
+/// `-LOADING` while the append-only file is replayed, see [`crate::health`].
+pub async fn run_with_database<D: Database>(listener: TcpListener, config: ServerConfig, db: D) {
+    let local_addr = listener.local_addr();
+    serve(Incoming::Tcp(listener), local_addr, config, db).await
+}
 
-    tokio::select! {
+/// Serves `db` to the connections of `incoming`. The server can only be
+/// reached at `local_addr` when it listens to a port.
+async fn serve<D: Database>(
+    incoming: Incoming,
+    local_addr: std::io::Result<SocketAddr>,
+    config: ServerConfig,
+    db: D,
+) {
+    let shared = Shared::new(&config);
+    if let Some(health) = &config.health {
+        if let Err(err) = shared
+            .readiness
+            .serve(health, shared.commands.clone())
+            .await
+        {
+            error!(cause = %err, "failed to listen to the health port");
+            return;
+        }
+    }
+    let loaded = Arc::new(OnceLock::new());
+    let mut server = Listener {
+        incoming,
+        db: db.clone(),
+        shared: shared.clone(),
+        config: config.clone(),
+        loading: Some(loaded.clone()),
+        #[cfg(feature = "faults")]
+        accepted: 0,
+    };
+    // Loading first, so that a server with nothing to load is ready before it
+    // accepts a connection.
+    let shared = tokio::select! {
+        biased;
+        shared = load(&config, &db, shared, local_addr) => match shared {
+            Some(shared) => shared,
+            None => return,
+        },
         res = server.run() => {
             if let Err(err) = res {
                 error!(cause = %err, "failed to accept");
             }
+            return;
+        }
+    };
+    // Loading connections switch over to the loaded state on their next
+    // command, see [`Handler::loading`].
+    let _ = loaded.set(shared.clone());
+    shared.readiness.set_ready();
+    info!("uranus loaded its data");
+    let sweep = sweep_expired(db.clone(), shared.config.clone(), shared.events.clone());
+    spawn_named("expiry sweep", sweep);
+    spawn_named("compaction", compact_storage(db.clone()));
+    let sample = sample_stats(shared.stats.clone(), db.clone(), shared.events.subscribe());
+    spawn_named("stats sampler", sample);
+    server.shared = shared;
+    server.loading = None;
+
+    if let Err(err) = server.run().await {
+        error!(cause = %err, "failed to accept");
+    }
+}
+
+/// Replays the append-only file into `db` and joins the cluster, as
+/// `config` says, returning the state the server is served with afterwards.
+async fn load<D: Database>(
+    config: &ServerConfig,
+    db: &D,
+    mut shared: Shared,
+    local_addr: std::io::Result<SocketAddr>,
+) -> Option<Shared> {
+    if let Some(aof) = &config.aof {
+        match AppendOnlyFile::open(aof.clone(), db, &shared, config.frame_limits).await {
+            Ok(aof) => shared.aof = Some(aof),
+            Err(err) => {
+                error!(cause = %err, "failed to load the append only file");
+                return None;
+            }
+        }
+    }
+    if let Some(gossip) = &config.gossip {
+        let addr = match (&config.cluster, local_addr) {
+            (Some(cluster), _) => cluster.myself.clone(),
+            (None, Ok(addr)) => addr.to_string(),
+            (None, Err(err)) => {
+                error!(cause = %err, "failed to get the listening address");
+                return None;
+            }
+        };
+        let membership = Membership::new(gossip.clone(), addr);
+        if let Err(err) = membership.start(shared.cluster.clone()).await {
+            error!(cause = %err, "failed to listen to the gossip port");
+            return None;
+        }
+        shared.membership = Some(membership);
+    }
+    Some(shared)
+}
+
+/// Periodically reclaims the keys of `db` which have expired, announcing them
+/// on `events`.
+async fn sweep_expired<D: Database>(db: D, config: RuntimeConfig, events: EventBus) {
+    loop {
+        time::sleep(config.expiry_sweep_interval()).await;
+        match db.expire() {
+            Ok(expired) if expired.is_empty() => {}
+            Ok(expired) => {
+                debug!(expired = expired.len(), "swept expired keys");
+                for key in expired {
+                    events.emit(ServerEvent::KeyExpired { key });
+                }
+            }
+            Err(err) => warn!(cause = %err, "failed to sweep expired keys"),
+        }
+    }
+}
+
+/// Periodically does the work the writes to `db` left for later, such as
+/// merging tables, see [`Database::compact`].
+async fn compact_storage<D: Database>(db: D) {
+    loop {
+        time::sleep(COMPACTION_INTERVAL).await;
+        if let Err(err) = db.compact() {
+            warn!(cause = %err, "failed to compact the storage");
         }
     }
 }
@@ -36,36 +274,74 @@ pub async fn run(listener: TcpListener) {
 /// [`Listener`] listens a port, waiting for connections. Established connection is served by
 /// [`Handler`].
 #[derive(Debug)]
-struct Listener {
-    listener: TcpListener,
-    db: DBHandle,
+struct Listener<D: Database> {
+    incoming: Incoming,
+    db: D,
+    shared: Shared,
+    config: ServerConfig,
+    /// Set while the server loads its data, see [`Handler::loading`].
+    loading: Option<Arc<OnceLock<Shared>>>,
+    /// How many connections were accepted, each drawing its faults from a
+    /// seed of its own.
+    #[cfg(feature = "faults")]
+    accepted: u64,
 }
 
-impl Listener {
+impl<D: Database> Listener<D> {
     async fn run(&mut self) -> Result<()> {
-        info!("uranus started to serve requests");
+        if self.loading.is_none() {
+            info!("uranus started to serve requests");
+        }
 
         loop {
-            let socket = self.accept().await?;
+            let (mut connection, peer) = self.accept().await?;
+            self.shared
+                .events
+                .emit(ServerEvent::ClientConnected { peer });
 
+            connection.set_trace(self.shared.tracer.connection(peer));
+            connection.set_buffer_config(self.config.buffers);
+            connection.track_buffer_sizes(self.shared.buffer_sizes.clone());
+            connection.set_flush_policy(FlushPolicy::Pipelined);
+            connection.set_limits(self.config.frame_limits);
+            connection.set_client(self.shared.clients.register(peer));
             let mut handler = Handler {
-                connection: Connection::new(socket),
+                connection,
                 database: self.db.clone(),
+                shared: self.shared.clone(),
+                primary_link: false,
+                asking: false,
+                loading: self.loading.clone(),
             };
 
-            tokio::spawn(async move {
-                if let Err(err) = handler.run().await {
-                    error!(cause = ?err, "connection error");
+            let span = info_span!("connection", %peer);
+            spawn_named(
+                &format!("connection {}", peer),
+                async move {
+                    if let Err(err) = handler.run().await {
+                        error!(cause = ?err, "connection error");
+                    }
                 }
-            });
+                .instrument(span),
+            );
         }
     }
 
-    async fn accept(&mut self) -> Result<TcpStream> {
+    async fn accept(&mut self) -> Result<(Connection, SocketAddr)> {
+        let listener = match &mut self.incoming {
+            Incoming::Tcp(listener) => listener,
+            Incoming::Channel(connections) => {
+                let (stream, peer) = connections
+                    .recv()
+                    .await
+                    .ok_or_else(|| anyhow!("the in-process server was dropped"))?;
+                return Ok((self.connection(stream), peer));
+            }
+        };
         let mut backoff = 1;
         loop {
-            match self.listener.accept().await {
-                Ok((socket, _)) => return Ok(socket),
+            match listener.accept().await {
+                Ok((socket, peer)) => return Ok((self.connection(socket), peer)),
                 Err(err) => {
                     if backoff > 64 {
                         return Err(err.into());
@@ -77,18 +353,86 @@ impl Listener {
             backoff *= 2;
         }
     }
+
+    /// A connection over `socket`, failing as [`ServerConfig::faults`] say.
+    fn connection(&mut self, socket: impl Transport + 'static) -> Connection {
+        #[cfg(feature = "faults")]
+        if let Some(faults) = self.config.faults {
+            self.accepted += 1;
+            return Connection::with_faults(socket, faults.reseed(self.accepted));
+        }
+        Connection::new(socket)
+    }
+}
+
+/// Where the connections of a [`Listener`] come from.
+#[derive(Debug)]
+enum Incoming {
+    Tcp(TcpListener),
+    /// Connections made in-process, see [`test_util`].
+    Channel(mpsc::UnboundedReceiver<(DuplexStream, SocketAddr)>),
 }
 
-pub struct Handler {
+pub struct Handler<D: Database = DBHandle> {
     connection: Connection,
-    database: DBHandle,
+    /// The database selected by the connection, see [`Select`].
+    database: D,
+    shared: Shared,
+    /// Whether this is the link of the primary of this server, see [`ReplConf`].
+    primary_link: bool,
+    /// Whether the previous command was [`Asking`].
+    asking: bool,
+    /// Set while the server loads its data, to the state it is served with
+    /// once loaded. Until then, only the commands which
+    /// [serve loading](Command::serves_loading) are applied.
+    loading: Option<Arc<OnceLock<Shared>>>,
 }
 
-impl Handler {
+impl<D: Database> Handler<D> {
     async fn run(&mut self) -> Result<()> {
+        let result = self.serve().await;
+        // Replies held for a pipeline are still due when a later request fails.
+        let flushed = self.connection.flush().await;
+        result.and(flushed)
+    }
+
+    async fn serve(&mut self) -> Result<()> {
         loop {
+            let killed = self.connection.client().map(ClientHandle::killed);
+            // The link of the primary stays open however long it has nothing to mirror.
+            let idle_timeout = self
+                .shared
+                .config
+                .client_timeout()
+                .filter(|_| !self.primary_link);
             let frame = tokio::select! {
-                res = self.connection.read_frame() => res?
+                res = self.connection.read_frame() => res,
+                Some(()) = async {
+                    killed?.await;
+                    Some(())
+                } => {
+                    debug!("connection killed");
+                    return Ok(());
+                }
+                Some(()) = async {
+                    time::sleep(idle_timeout?).await;
+                    Some(())
+                } => {
+                    debug!("closing an idle connection");
+                    return Ok(());
+                }
+            };
+            let frame = match frame {
+                Err(err) => {
+                    if let Some(limit) = err.downcast_ref::<FrameError>().filter(|e| e.is_limit()) {
+                        // The rest of the frame can't be skipped, so the connection is closed.
+                        let reply =
+                            Frame::error(ErrorCode::Err, format!("Protocol error: {}", limit));
+                        self.connection.write_frame(&reply).await?;
+                    }
+                    return Err(err);
+                }
+                Ok(frame) => frame,
             };
 
             let frame = match frame {
@@ -96,125 +440,778 @@ impl Handler {
                 None => return Ok(()),
             };
 
-            info!("received a frame {:?}", frame);
+            let received_at = self.connection.received_at();
+            let (budget, frame) = match split_deadline(frame) {
+                Ok(split) => split,
+                Err(err) => {
+                    // Like a malformed command, it was read whole.
+                    self.connection.write_frame(&error_reply(&err)).await?;
+                    continue;
+                }
+            };
+            trace!(?budget, frame = ?Redacted(&frame), "received a frame");
+            if let Some(budget) = budget {
+                if received_at.elapsed() > budget {
+                    debug!(?budget, "deadline exceeded");
+                    self.connection
+                        .write_frame(&deadline_exceeded(budget))
+                        .await?;
+                    continue;
+                }
+            }
 
-            let cmd = Command::from_frame(frame)?;
+            if let Some(shared) = self.loading.as_ref().and_then(|loaded| loaded.get()) {
+                self.shared = shared.clone();
+                self.loading = None;
+            }
+            let logged = self.shared.shadow.is_some() || self.shared.aof.is_some();
+            let request = logged.then(|| frame.clone());
+            let cmd = match Command::from_frame(frame) {
+                Ok(cmd) => cmd,
+                Err(err) => {
+                    // The request was read whole, so the next ones can still be
+                    // served.
+                    self.connection.write_frame(&error_reply(&err)).await?;
+                    continue;
+                }
+            };
+            let request = request.map(|request| cmd.replayable(request));
             debug!(?cmd);
+            if let Some(client) = self.connection.client() {
+                client.record(cmd.name());
+            }
+            if self.loading.is_some() && !cmd.serves_loading() {
+                let reply = Frame::error(ErrorCode::Loading, LOADING);
+                self.reject(&cmd, &reply).await?;
+                continue;
+            }
+            if let Err(denied) = self.shared.acl.check(self.connection.user(), &cmd) {
+                self.reject(&cmd, &error_reply(&denied.into())).await?;
+                continue;
+            }
+            if let Command::ReplConf(replconf) = &cmd {
+                // Any client could bypass -READONLY otherwise.
+                if self.shared.replication_secret.as_ref() != Some(&replconf.secret) {
+                    let reply = Frame::error(ErrorCode::WrongPass, BAD_REPLICATION_SECRET);
+                    self.reject(&cmd, &reply).await?;
+                    continue;
+                }
+                self.primary_link = true;
+            }
+            let asking = std::mem::replace(&mut self.asking, matches!(cmd, Command::Asking(_)));
+            // The primary link of a replica mirrors whatever its primary serves.
+            if let Some(cluster) = self.shared.cluster.as_ref().filter(|_| !self.primary_link) {
+                if let Some(redirect) = cluster.redirect(&cmd.keys(), &self.database, asking)? {
+                    self.reject(&cmd, &redirect).await?;
+                    continue;
+                }
+            }
+            let read_only = self.shared.config.replica_read_only() && !self.primary_link;
+            if read_only && cmd.is_write() {
+                let reply = Frame::error(ErrorCode::ReadOnly, READ_ONLY);
+                self.reject(&cmd, &reply).await?;
+                continue;
+            }
+            if (cmd.is_write() && !self.pace_write().await?)
+                || (cmd.may_grow() && !self.make_room().await?)
+            {
+                self.shared.commands.record_rejected(cmd.name());
+                continue;
+            }
+            if let (Some(shadow), Some(frame)) = (&self.shared.shadow, &request) {
+                if shadow.sample(&cmd) {
+                    shadow.mirror(frame.clone());
+                }
+            }
 
-            cmd.apply(&mut self.connection, &mut self.database).await?;
+            let span = info_span!(
+                "command",
+                name = cmd.name(),
+                key = cmd.key().map(|key| field::display(key.escape_ascii()))
+            );
+            let name = cmd.name();
+            let slowlog = !cmd.is_subscribe();
+            let written = cmd
+                .is_write()
+                .then(|| cmd.key().map(Bytes::copy_from_slice))
+                .flatten();
+            let aof = self.shared.aof.clone().filter(|_| cmd.is_write());
+            let blocking = cmd.is_blocking();
+            if blocking {
+                self.connection.flush().await?;
+            }
+            // Scripts run alone, see `scripting`. Blocking commands would hold
+            // them up for as long as they wait.
+            let script = matches!(cmd, Command::Eval(_));
+            let running = if blocking {
+                None
+            } else {
+                Some(self.shared.scripts.lock(script).await)
+            };
+            // Blocking writes log the value of their key instead, see `aof`.
+            let serialized = match &aof {
+                Some(aof) if !blocking => Some(aof.lock_writes().await),
+                _ => None,
+            };
+            // The reply would acknowledge a write a crash could still lose.
+            if serialized.is_some() {
+                self.connection.hold();
+            }
+            let errors = self.connection.errors_written();
+            let (hits, misses) = self.connection.lookups();
+            let start = Instant::now();
+            let apply = cmd
+                .apply(&mut self.connection, &mut self.database, &self.shared)
+                .instrument(span.clone());
+            let result = match catch_panic(apply).await {
+                Ok(result) => result,
+                Err(cause) => {
+                    // What the command changed stays, but isn't logged:
+                    // replaying it would panic again.
+                    error!(%cause, command = name, "command panicked");
+                    self.trace_outcome(&span, start.elapsed(), false, slowlog);
+                    self.shared.commands.record(name, start.elapsed(), true);
+                    drop(serialized);
+                    drop(running);
+                    self.connection.release().await?;
+                    self.connection.write_frame(&internal_error()).await?;
+                    continue;
+                }
+            };
+            let elapsed = start.elapsed();
+            self.trace_outcome(&span, elapsed, result.is_ok(), slowlog);
+            // Subscriptions last until unsubscribed, which isn't latency.
+            let elapsed = if slowlog { elapsed } else { Duration::ZERO };
+            let failed = result.is_err() || self.connection.errors_written() > errors;
+            self.shared.commands.record(name, elapsed, failed);
+            let (found, missed) = self.connection.lookups();
+            self.shared
+                .stats
+                .record_lookups(found - hits, missed - misses);
+            if let Some(aof) = &aof {
+                if blocking {
+                    let _serialized = aof.lock_writes().await;
+                    if let Some(key) = &written {
+                        aof.record_value(&self.database, key)?;
+                    }
+                } else if let Some(request) = &request {
+                    aof.record(&self.database, request)?;
+                }
+            }
+            drop(serialized);
+            drop(running);
+            self.connection.release().await?;
+            result?;
+
+            if let Some(key) = written {
+                let event = ServerEvent::KeyWritten { key, command: name };
+                self.shared.events.emit(event);
+            }
+        }
+    }
+
+    /// Replies `reply` to `cmd`, refused before it ran.
+    async fn reject(&mut self, cmd: &Command, reply: &Frame) -> Result<()> {
+        self.shared.commands.record_rejected(cmd.name());
+        self.connection.write_frame(reply).await
+    }
+
+    /// Evicts keys if the memory limit is exceeded. Returns false, having
+    /// replied with an error, if it can't be brought under it.
+    async fn make_room(&mut self) -> Result<bool> {
+        match self.database.make_room() {
+            Ok(evicted) => {
+                for key in evicted {
+                    self.shared.events.emit(ServerEvent::KeyEvicted { key });
+                }
+                Ok(true)
+            }
+            Err(err) => match err.downcast_ref::<StorageError>() {
+                Some(StorageError::OutOfMemory) => {
+                    let reply = error_reply(&err);
+                    self.connection.write_frame(&reply).await?;
+                    Ok(false)
+                }
+                _ => Err(err),
+            },
         }
     }
+
+    /// Holds writes back while the storage catches up with its background
+    /// work: slowed down by [`WRITE_SLOWDOWN`] each, or refused. Returns false,
+    /// having replied with an error, if the write is refused.
+    async fn pace_write(&mut self) -> Result<bool> {
+        match self.database.write_stall()? {
+            WriteStall::None => Ok(true),
+            WriteStall::Slowdown => {
+                time::sleep(WRITE_SLOWDOWN).await;
+                Ok(true)
+            }
+            WriteStall::Stop => {
+                let reply = Frame::error(ErrorCode::Stalled, WRITES_STOPPED);
+                self.connection.write_frame(&reply).await?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Reports how long a command took, and flags it in the slow command log
+    /// if it exceeds the configured threshold.
+    fn trace_outcome(&self, span: &tracing::Span, elapsed: Duration, ok: bool, slowlog: bool) {
+        let outcome = if ok { "ok" } else { "error" };
+        let elapsed_us = elapsed.as_micros() as u64;
+        span.in_scope(|| {
+            debug!(elapsed_us, outcome, "command finished");
+            if slowlog && elapsed >= self.shared.config.slowlog_threshold() {
+                warn!(elapsed_us, outcome, "slow command");
+            }
+        });
+    }
 }
 
+/// Polls `future` to completion, catching its panic if it does as the message
+/// of the panic, so that a bug in a command doesn't take its connection down.
+async fn catch_panic<F: Future>(future: F) -> Result<F::Output, String> {
+    let mut future = pin!(future);
+    poll_fn(
+        |cx| match catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+            Ok(poll) => poll.map(Ok),
+            Err(panic) => {
+                let cause = match panic.downcast::<String>() {
+                    Ok(message) => *message,
+                    Err(panic) => match panic.downcast::<&str>() {
+                        Ok(message) => message.to_string(),
+                        Err(_) => "unknown cause".to_string(),
+                    },
+                };
+                Poll::Ready(Err(cause))
+            }
+        },
+    )
+    .await
+}
+
+/// The reply to a command which panicked.
+fn internal_error() -> Frame {
+    Frame::error(ErrorCode::Err, "internal error")
+}
+
+/// How long each write is delayed while the storage asks for a slowdown.
+const WRITE_SLOWDOWN: Duration = Duration::from_millis(1);
+
+const WRITES_STOPPED: &str = "writes are stopped until the storage catches up";
+
+/// How often the storage does the work its writes left for later.
+const COMPACTION_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The reply to writes sent to a read-only replica, but by its primary.
+const READ_ONLY: &str = "You can't write against a read only replica.";
+
+/// The reply to a `REPLCONF PRIMARY` without the secret of the server.
+const BAD_REPLICATION_SECRET: &str = "invalid replication secret";
+
+/// A byte stream a [`Connection`] is spoken over: a TCP socket, or an
+/// in-process pipe, see [`test_util`].
+pub trait Transport: AsyncRead + AsyncWrite + Send + Sync + Unpin + std::fmt::Debug {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Sync + Unpin + std::fmt::Debug> Transport for T {}
+
 #[derive(Debug)]
 pub struct Connection {
-    stream: BufWriter<TcpStream>,
+    stream: BufWriter<Box<dyn Transport>>,
     buffer: BytesMut,
+    /// How much to read from the socket at once, see [`buffer`].
+    read_size: ReadSize,
+    protocol: Protocol,
+    /// When the last read from the socket completed.
+    received_at: Instant,
+    /// Where the bytes read and written are dumped, see [`Tracer`].
+    trace: Option<ConnectionTrace>,
+    /// Frames encoded but not sent yet.
+    output: BytesMut,
+    limits: FrameLimits,
+    /// How far the frame at the front of the buffer was checked.
+    check: FrameCheck,
+    flush_policy: FlushPolicy,
+    /// Whether frames are held whatever the flush policy, see
+    /// [`Connection::hold`].
+    held: bool,
+    /// The registration of a connection served by the server, see [`Clients`].
+    client: Option<ClientHandle>,
+    /// The user bound by `AUTH`, see [`Acl`].
+    user: Option<String>,
+    /// How many error replies were written, see [`CommandStats`].
+    errors: u64,
+    /// How many keys read commands found, and missed, see [`Stats`].
+    hits: u64,
+    misses: u64,
+}
+
+/// The start of a frame, see [`Connection::read_head`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameHead {
+    /// A whole frame, other than a bulk string.
+    Frame(Frame),
+    /// The header of a bulk string of this many bytes, whose body is read by
+    /// [`Connection::read_chunk`].
+    Bulk(usize),
 }
 
-const BUFFER_SIZE: usize = 4 * 1024;
+/// When the frames written on a [`Connection`] are sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Each frame is sent as soon as it is written.
+    #[default]
+    Immediate,
+    /// While a client pipelines requests, the replies are held and sent
+    /// together once no further request is buffered, or once
+    /// [`MAX_PENDING_OUTPUT`] bytes are held. Blocking commands must
+    /// [`flush`](Connection::flush) before they wait.
+    Pipelined,
+}
+
+/// Replies held under [`FlushPolicy::Pipelined`] are sent once they take this
+/// many bytes.
+pub const MAX_PENDING_OUTPUT: usize = 64 * 1024;
+
+/// The wire encoding spoken on a connection, negotiated by [`Hello`].
+///
+/// Both versions share text, error, integer, binary and array frames. Version 3
+/// additionally has native double (`,1.5`) and null (`_`) frames; version 2 sends
+/// doubles as binary frames holding their text form, and nulls as `$-1`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    V2,
+    V3,
+}
+
+impl Protocol {
+    pub fn from_version(version: i64) -> Option<Protocol> {
+        match version {
+            2 => Some(Protocol::V2),
+            3 => Some(Protocol::V3),
+            _ => None,
+        }
+    }
+
+    pub fn version(self) -> i64 {
+        match self {
+            Protocol::V2 => 2,
+            Protocol::V3 => 3,
+        }
+    }
+}
 
 impl Connection {
-    pub fn new(socket: TcpStream) -> Connection {
+    pub fn new(socket: impl Transport + 'static) -> Connection {
         Connection {
-            stream: BufWriter::new(socket),
-            buffer: BytesMut::with_capacity(BUFFER_SIZE),
+            stream: BufWriter::new(Box::new(socket)),
+            buffer: BytesMut::with_capacity(MIN_BUFFER_SIZE),
+            read_size: ReadSize::new(BufferConfig::default()),
+            protocol: Protocol::default(),
+            received_at: Instant::now(),
+            trace: None,
+            output: BytesMut::new(),
+            flush_policy: FlushPolicy::default(),
+            held: false,
+            limits: FrameLimits::default(),
+            check: FrameCheck::default(),
+            client: None,
+            user: None,
+            errors: 0,
+            hits: 0,
+            misses: 0,
         }
     }
 
+    /// A connection over `socket`, failing as `faults` say, see [`fault`].
+    #[cfg(feature = "faults")]
+    pub fn with_faults(socket: impl Transport + 'static, faults: Faults) -> Connection {
+        Connection::new(Faulty::new(socket, faults))
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// When the last frame read had fully arrived, or a bit later. Frames sent
+    /// together share this instant, so it tells how long a frame queued
+    /// behind the previous ones.
+    pub fn received_at(&self) -> Instant {
+        self.received_at
+    }
+
+    /// Dumps the traffic of this connection while `trace` is enabled.
+    pub fn set_trace(&mut self, trace: ConnectionTrace) {
+        self.trace = Some(trace);
+    }
+
+    /// Sizes the read buffer within `config` from now on.
+    pub fn set_buffer_config(&mut self, config: BufferConfig) {
+        self.read_size.configure(config);
+    }
+
+    /// Counts this connection in `sizes` by the size of its read buffer.
+    pub fn track_buffer_sizes(&mut self, sizes: BufferSizes) {
+        self.read_size.track(sizes);
+    }
+
+    /// Rejects the frames read from now on which break `limits`.
+    pub fn set_limits(&mut self, limits: FrameLimits) {
+        self.limits = limits;
+    }
+
+    /// Lists this connection with the clients of the server until it is dropped.
+    pub fn set_client(&mut self, client: ClientHandle) {
+        self.client = Some(client);
+    }
+
+    pub fn client(&self) -> Option<&ClientHandle> {
+        self.client.as_ref()
+    }
+
+    /// Acts as `user` from now on, see [`Acl::check`].
+    pub fn set_user(&mut self, user: String) {
+        self.user = Some(user);
+    }
+
+    /// The user authenticated on this connection, none until it does.
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// How many error replies were written on this connection so far.
+    pub fn errors_written(&self) -> u64 {
+        self.errors
+    }
+
+    /// Counts a lookup of a key by a read command, which found it if `hit`.
+    pub fn record_lookup(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+    }
+
+    /// How many keys read commands found and missed on this connection so far.
+    pub fn lookups(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.flush_policy = policy;
+    }
+
+    /// Changes how frames are written from now on. Reading accepts both encodings.
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
     pub async fn read_frame(&mut self) -> Result<Option<Frame>> {
         loop {
             if let Some(frame) = self.parse_frame()? {
                 return Ok(Some(frame));
             }
-            if 0 == self.stream.read_buf(&mut self.buffer).await? {
-                if self.buffer.is_empty() {
-                    return Ok(None);
-                }
-                return Err(anyhow!("connection reset by peer"));
+            // A frame larger than the read size is arriving, read more at once.
+            self.read_size.fit(self.buffer.len() + 1);
+            if !self.fill().await? {
+                return Ok(None);
             }
         }
     }
 
-    /// [`write_frame`] can't deal with recursions
-    pub async fn write_frame(&mut self, frame: &Frame) -> Result<()> {
-        match frame {
-            Frame::Array(val) => {
-                self.stream.write_u8(b'*').await?;
-                self.write_decimal(val.len() as u64).await?;
-                for entry in val {
-                    self.write_scalar(entry).await?;
+    /// Like [`Connection::read_frame`], but only reads the header of a bulk
+    /// string, so that its body can be read in chunks as it arrives rather
+    /// than buffered whole. The body isn't subject to the frame limits.
+    pub async fn read_head(&mut self) -> Result<Option<FrameHead>> {
+        loop {
+            if let Some(len) = self.parse_bulk_header()? {
+                return Ok(Some(FrameHead::Bulk(len)));
+            }
+            if let Some(frame) = self.parse_frame()? {
+                return Ok(Some(FrameHead::Frame(frame)));
+            }
+            if !self.fill().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Reads the next part of the body of a bulk string whose header
+    /// [`Connection::read_head`] returned, with `remaining` bytes of it left:
+    /// what is buffered already, or else what arrives next. The CRLF ending
+    /// the body is consumed along with its last part.
+    pub async fn read_chunk(&mut self, remaining: usize) -> Result<Bytes> {
+        loop {
+            let buffered = self.buffer.len().min(remaining);
+            if buffered < remaining && buffered > 0 {
+                return Ok(self.buffer.split_to(buffered).freeze());
+            }
+            if buffered == remaining && self.buffer.len() >= remaining + 2 {
+                let chunk = self.buffer.split_to(remaining).freeze();
+                if &self.buffer[..2] != b"\r\n" {
+                    return Err(anyhow!("bulk string not ended by CRLF"));
                 }
+                self.buffer.advance(2);
+                return Ok(chunk);
             }
-            _ => self.write_scalar(frame).await?,
+            if !self.fill().await? {
+                Err(FrameError::Incomplete)?
+            }
+        }
+    }
+
+    /// Consumes the header of the bulk string at the front of the buffer, if
+    /// it has arrived, and returns its length. Null bulk strings are left to
+    /// [`Connection::parse_frame`].
+    fn parse_bulk_header(&mut self) -> Result<Option<usize>> {
+        if self.buffer.first() != Some(&b'$') {
+            return Ok(None);
+        }
+        let Some(end) = self.buffer.windows(2).position(|window| window == b"\r\n") else {
+            return Ok(None);
         };
-        self.stream.flush().await?; // note: the '?' cast io::Error to anyhow::Error
-        Ok(())
+        let len = std::str::from_utf8(&self.buffer[1..end])?.parse::<i64>()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        let len = len as usize;
+        self.buffer.advance(end + 2);
+        self.check = FrameCheck::default();
+        Ok(Some(len))
     }
 
-    pub async fn write_scalar(&mut self, frame: &Frame) -> Result<()> {
-        match frame {
-            Frame::Text(s) => {
-                self.stream.write_u8(b'+').await?;
-                self.stream.write_all(s.as_bytes()).await?;
+    /// Reads more into the buffer. Returns false if the peer closed the
+    /// connection, failing if it did in the middle of a frame.
+    async fn fill(&mut self) -> Result<bool> {
+        let read = self.read_more().await?;
+        if 0 == read {
+            if self.buffer.is_empty() {
+                return Ok(false);
             }
-            Frame::Error(err) => {
-                self.stream.write_u8(b'-').await?;
-                self.stream.write_all(err.as_bytes()).await?;
+            return Err(anyhow!("connection reset by peer"));
+        }
+        self.received_at = Instant::now();
+        if let Some(trace) = &mut self.trace {
+            let start = self.buffer.len() - read;
+            trace.record(Direction::Inbound, &self.buffer[start..]);
+        }
+        Ok(true)
+    }
+
+    /// Reads at most the read size from the socket. When the connection is
+    /// idle for long, the read size is reset and the buffer shrunk meanwhile.
+    ///
+    /// A bulk string larger than the largest read size is read into a buffer
+    /// of its exact size instead, which the value then keeps alive, rather
+    /// than one doubled over and over as it arrives.
+    async fn read_more(&mut self) -> Result<usize> {
+        loop {
+            let idle = self.buffer.is_empty() && self.read_size.get() > self.read_size.initial();
+            let awaited = self.check.awaited();
+            if awaited > self.read_size.max() && awaited > self.buffer.len() {
+                self.buffer.reserve(awaited - self.buffer.len());
+            } else {
+                self.buffer.reserve(self.read_size.get());
             }
-            Frame::Binary(bin) => {
-                let len = bin.len();
+            if !idle {
+                return Ok(self.stream.read_buf(&mut self.buffer).await?);
+            }
+            match time::timeout(IDLE_SHRINK_AFTER, self.stream.read_buf(&mut self.buffer)).await {
+                Ok(read) => return Ok(read?),
+                Err(_) => {
+                    debug!(read_size = self.read_size.get(), "shrinking an idle buffer");
+                    self.read_size.reset();
+                    self.shrink_buffer();
+                }
+            }
+        }
+    }
 
-                self.stream.write_u8(b'$').await?;
-                self.write_decimal(len as u64).await?;
-                self.stream.write_all(bin).await?;
+    /// Sends `frame`, or holds it under [`FlushPolicy::Pipelined`] while
+    /// further requests are buffered. Arrays can't nest.
+    pub async fn write_frame(&mut self, frame: &Frame) -> Result<()> {
+        self.queue_frame(frame)?;
+        if !self.held && !self.pipelining() {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Whether the frames queued may wait for the replies to the requests
+    /// buffered, see [`FlushPolicy::Pipelined`].
+    fn pipelining(&mut self) -> bool {
+        self.flush_policy == FlushPolicy::Pipelined
+            && self.output.len() < MAX_PENDING_OUTPUT
+            && self.has_buffered_frame()
+    }
+
+    /// Holds the frames written from now on until [`Connection::release`], as
+    /// the reply to a write isn't sent before the write is logged.
+    pub fn hold(&mut self) {
+        self.held = true;
+    }
+
+    /// Sends the frames held since [`Connection::hold`], unless they may wait
+    /// as the flush policy says.
+    pub async fn release(&mut self) -> Result<()> {
+        self.held = false;
+        if !self.output.is_empty() && !self.pipelining() {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Sends the array `entries`, but for its entry `at`, a bulk string of
+    /// `len` bytes read from `body` as it is sent rather than held whole.
+    /// Fails if `body` ends short, having sent part of the frame.
+    pub async fn write_streamed<R: AsyncRead + Unpin + ?Sized>(
+        &mut self,
+        entries: &[Frame],
+        at: usize,
+        len: usize,
+        body: &mut R,
+    ) -> Result<()> {
+        self.output.put_u8(b'*');
+        encode_decimal(&mut self.output, entries.len() as u64);
+        for entry in &entries[..at] {
+            entry.encode(self.protocol, &mut self.output)?;
+        }
+        self.output.put_u8(b'$');
+        encode_decimal(&mut self.output, len as u64);
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk = remaining.min(self.read_size.get());
+            let mut reading = (&mut *body).take(chunk as u64);
+            let read = reading.read_buf(&mut self.output).await?;
+            if read == 0 {
+                self.flush().await?;
+                return Err(anyhow!("the body ended {} bytes short", remaining));
             }
-            Frame::Null => todo!(),
-            Frame::Array(_) => Err(FrameError::Recursive)?,
+            remaining -= read;
+            self.flush().await?;
+        }
+        self.output.put_slice(b"\r\n");
+        for entry in &entries[at + 1..] {
+            entry.encode(self.protocol, &mut self.output)?;
+        }
+        self.flush().await
+    }
+
+    /// Encodes `frame` at the end of the output, sent by the next [`flush`].
+    ///
+    /// [`flush`]: Connection::flush
+    pub fn queue_frame(&mut self, frame: &Frame) -> Result<()> {
+        if let Frame::Error(_) = frame {
+            self.errors += 1;
+        }
+        frame.encode(self.protocol, &mut self.output)
+    }
+
+    /// Sends the frames queued so far at once.
+    pub async fn flush(&mut self) -> Result<()> {
+        if self.output.is_empty() {
+            return Ok(());
+        }
+        if let Some(trace) = &mut self.trace {
+            trace.capture(&self.output);
+        }
+        self.stream.write_all(&self.output).await?;
+        self.stream.flush().await?; // note: the '?' cast io::Error to anyhow::Error
+        self.output.clear();
+        if let Some(trace) = &mut self.trace {
+            trace.flush_outbound();
         }
-        self.write_crlf().await?;
         Ok(())
     }
 
+    /// Whether a whole request is waiting in the read buffer already.
+    fn has_buffered_frame(&mut self) -> bool {
+        if self.is_inline() {
+            return self.buffer.contains(&b'\n');
+        }
+        // Once complete, the frame is checked over again by the next read.
+        matches!(self.check.advance(&self.buffer, &self.limits), Ok(Some(_)))
+    }
+
+    /// Whether the request at the front of the buffer is an inline command.
+    fn is_inline(&self) -> bool {
+        self.buffer
+            .first()
+            .is_some_and(|byte| !FRAME_MARKERS.contains(byte))
+    }
+
     fn parse_frame(&mut self) -> Result<Option<Frame>> {
-        let mut buf = Cursor::new(&self.buffer[..]);
-        match Frame::check(&mut buf) {
-            Ok(None) => Ok(None),
-            Ok(Some(())) => {
-                let len = buf.position() as usize;
-                buf.set_position(0);
-                let frame = Frame::parse(&mut buf)?.unwrap(); // Frame::check guaranteed Some(_)
-                self.buffer.advance(len);
+        while self.is_inline() {
+            if let Some(frame) = self.parse_inline()? {
+                return Ok(Some(frame));
+            }
+            if !self.buffer.contains(&b'\n') {
+                return Ok(None);
+            }
+        }
+        match self.check.advance(&self.buffer, &self.limits)? {
+            None => Ok(None),
+            Some(len) => {
+                self.read_size.fit(len);
+                let mut src = self.buffer.split_to(len).freeze();
+                if len > self.read_size.max() {
+                    // The buffer grew for this frame alone.
+                    self.shrink_buffer();
+                }
+                let frame = Frame::parse(&mut src)?.ok_or(FrameError::Incomplete)?;
                 Ok(Some(frame))
             }
-            Err(e) => Err(e),
         }
     }
 
-    async fn write_crlf(&mut self) -> Result<()> {
-        self.stream.write_all(b"\r\n").await?;
-        Ok(())
+    /// Moves what is buffered to a buffer of the read size, giving back the
+    /// memory of the current one once no frame refers to it.
+    fn shrink_buffer(&mut self) {
+        let mut buffer = BytesMut::with_capacity(self.read_size.get().max(self.buffer.len()));
+        buffer.extend_from_slice(&self.buffer);
+        self.buffer = buffer;
     }
 
-    async fn write_decimal(&mut self, val: u64) -> Result<()> {
-        use std::io::Write;
-
-        let mut buf = [0u8; 20];
-        let mut buf = Cursor::new(&mut buf[..]);
-        write!(&mut buf, "{}", val)?;
-        let pos = buf.position() as usize;
-        self.stream.write_all(&buf.get_ref()[..pos]).await?;
-        self.write_crlf().await?;
-        Ok(())
+    /// Splits the line at the front of the buffer on whitespace, as typed in
+    /// a telnet session, into an array of bulk strings. There is no quoting.
+    /// A blank line is consumed, but yields no frame.
+    fn parse_inline(&mut self) -> Result<Option<Frame>> {
+        let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') else {
+            if self.buffer.len() > self.limits.max_frame_size {
+                Err(FrameError::FrameTooLarge(self.limits.max_frame_size))?
+            }
+            return Ok(None);
+        };
+        self.read_size.fit(end + 1);
+        let line = self.buffer.split_to(end + 1);
+        let words: Vec<Frame> = line
+            .as_ref()
+            .split(|byte| byte.is_ascii_whitespace())
+            .filter(|word| !word.is_empty())
+            .map(|word| Frame::Binary(Bytes::copy_from_slice(word)))
+            .collect();
+        if words.len() > self.limits.max_array_len {
+            Err(FrameError::ArrayTooLong(words.len() as u64))?
+        }
+        Ok((!words.is_empty()).then_some(Frame::Array(words)))
     }
 }
 
+/// The bytes frames start with. A request starting with any other is an inline
+/// command, a line of words such as `GET foo`.
+const FRAME_MARKERS: &[u8] = b"+-:,_*$";
+
 /// [`Frame`] is a transmission atom between client and server. A command typically
 /// consists of many frames. Command may arrange them to arrays.
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Frame {
     Text(String),
     Error(String),
+    Integer(i64),
+    Double(f64),
     Binary(bytes::Bytes),
     Array(Vec<Frame>),
     Null,
@@ -226,81 +1223,336 @@ pub enum FrameError {
     Incomplete,
     #[error("Uranus wire protocol doesn't support recursive array types")]
     Recursive,
+    #[error("bulk string of {0} bytes exceeds the limit")]
+    BulkTooLong(i64),
+    #[error("array of {0} elements exceeds the limit")]
+    ArrayTooLong(u64),
+    #[error("frame exceeds the limit of {0} bytes")]
+    FrameTooLarge(usize),
+    #[error("frames can't start with byte {0:#04x}")]
+    UnknownMarker(u8),
 }
 
-impl Frame {
-    pub fn check(src: &mut Cursor<&[u8]>) -> Result<Option<()>> {
-        match get_u8_bump(src) {
-            Some(b'+') => Ok(get_line_bump(src).map(|_| ())),
-            Some(b'-') => Ok(get_line_bump(src).map(|_| ())),
-            Some(b'*') => {
-                let len = get_decimal_bump(src)?;
+impl FrameError {
+    /// Whether the frame breaks one of the [`FrameLimits`].
+    pub fn is_limit(&self) -> bool {
+        matches!(
+            self,
+            FrameError::BulkTooLong(_) | FrameError::ArrayTooLong(_) | FrameError::FrameTooLarge(_)
+        )
+    }
+}
 
-                for _ in 0..len {
-                    Frame::check(src)?;
-                }
+/// Bounds on the frames a connection accepts, checked from the headers before
+/// the data arrives, so that a client can't make the server buffer without end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameLimits {
+    /// The longest bulk string, in bytes.
+    pub max_bulk_len: usize,
+    /// The most elements in an array.
+    pub max_array_len: usize,
+    /// The largest frame, headers and elements included, in bytes.
+    pub max_frame_size: usize,
+}
 
-                Ok(Some(()))
-            }
-            Some(b'$') => {
-                let len: usize = get_decimal_bump(src)?.try_into()?;
-                skip(src, len + 2)?;
-                Ok(Some(()))
-            }
-            None => Ok(None),
-            _ => unimplemented!(),
+impl Default for FrameLimits {
+    fn default() -> Self {
+        FrameLimits {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_array_len: 1024 * 1024,
+            max_frame_size: 512 * 1024 * 1024,
         }
     }
+}
+
+impl Frame {
+    /// Finds whether the frame at the front of `src` has fully arrived, and
+    /// moves past it if so. Fails as soon as the headers received show that
+    /// the frame breaks `limits`. Connections keep a [`FrameCheck`] instead,
+    /// rather than start over as each read adds to the frame.
+    pub fn check(src: &mut Cursor<&[u8]>, limits: &FrameLimits) -> Result<Option<()>> {
+        let start = src.position() as usize;
+        let checked = FrameCheck::default().advance(&src.get_ref()[start..], limits)?;
+        Ok(checked.map(|len| src.set_position((start + len) as u64)))
+    }
 
-    pub fn parse(src: &mut Cursor<&[u8]>) -> Result<Option<Frame>> {
-        match get_u8_bump(src) {
-            Some(b'+') => {
-                if let Some(line) = get_line_bump(src).map(|x| x.to_vec()) {
-                    let string = String::from_utf8(line)?;
+    /// Decodes the frame at the front of `src`, which [`Frame::check`] found
+    /// complete. Large bulk payloads are slices of `src` rather than copies.
+    /// Malformed input is an error, never a panic.
+    pub fn parse(src: &mut Bytes) -> Result<Option<Frame>> {
+        if !src.has_remaining() {
+            return Ok(None);
+        }
+        match src.get_u8() {
+            b'+' => {
+                if let Some(line) = split_line(src) {
+                    let string = std::str::from_utf8(&line)?.to_owned();
                     Ok(Some(Frame::Text(string)))
                 } else {
                     Ok(None)
                 }
             }
-            Some(b'-') => {
-                let line = get_line_bump(src).ok_or(FrameError::Incomplete)?.to_vec();
-                let string = String::from_utf8(line)?;
+            b'-' => {
+                let line = split_line(src).ok_or(FrameError::Incomplete)?;
+                let string = std::str::from_utf8(&line)?.to_owned();
 
                 Ok(Some(Frame::Error(string)))
             }
-            Some(b'*') => {
-                let len = get_decimal_bump(src)?.try_into()?;
-                let mut out = Vec::with_capacity(len);
+            b':' => Ok(Some(Frame::Integer(split_signed(src)?))),
+            b',' => {
+                let line = split_line(src).ok_or(FrameError::Incomplete)?;
+                let double = parse_double(std::str::from_utf8(&line)?)?;
+                Ok(Some(Frame::Double(double)))
+            }
+            b'_' => {
+                split_line(src).ok_or(FrameError::Incomplete)?;
+                Ok(Some(Frame::Null))
+            }
+            b'*' => {
+                let len = split_decimal(src)?.try_into()?;
+                // Entries take 3 bytes at least, the header can't be trusted further.
+                let mut out = Vec::with_capacity(std::cmp::min(len, src.remaining() / 3));
 
                 for _ in 0..len {
-                    out.push(Frame::parse(src)?.unwrap());
+                    if src.first() == Some(&b'*') {
+                        Err(FrameError::Recursive)?
+                    }
+                    out.push(Frame::parse(src)?.ok_or(FrameError::Incomplete)?);
                 }
 
                 Ok(Some(Frame::Array(out)))
             }
-            Some(b'$') => {
-                let len = get_decimal_bump(src)?.try_into()?;
-                let n = len + 2;
+            b'$' => {
+                let len = split_signed(src)?;
+                if len < 0 {
+                    return Ok(Some(Frame::Null));
+                }
+                let len = len as usize;
 
-                if src.remaining() < n {
+                if src.remaining() < len + 2 {
                     return Err(FrameError::Incomplete)?;
                 }
 
-                let data = bytes::Bytes::copy_from_slice(&src.chunk()[..len]);
-                skip(src, n)?;
+                let data = if len < ZERO_COPY_MIN {
+                    Bytes::copy_from_slice(&src[..len])
+                } else {
+                    src.slice(..len)
+                };
+                src.advance(len + 2);
                 Ok(Some(Frame::Binary(data)))
             }
+            marker => Err(FrameError::UnknownMarker(marker))?,
+        }
+    }
+
+    /// Appends the encoding of this frame in `protocol` to `dst`. Arrays
+    /// can't nest.
+    pub fn encode(&self, protocol: Protocol, dst: &mut BytesMut) -> Result<()> {
+        match self {
+            Frame::Array(val) => {
+                dst.put_u8(b'*');
+                encode_decimal(dst, val.len() as u64);
+                for entry in val {
+                    entry.encode_scalar(protocol, dst)?;
+                }
+            }
+            _ => self.encode_scalar(protocol, dst)?,
+        };
+        Ok(())
+    }
+
+    fn encode_scalar(&self, protocol: Protocol, dst: &mut BytesMut) -> Result<()> {
+        match self {
+            Frame::Text(s) => {
+                dst.put_u8(b'+');
+                encode_line(dst, s);
+            }
+            Frame::Error(err) => {
+                dst.put_u8(b'-');
+                encode_line(dst, err);
+            }
+            Frame::Binary(bin) => {
+                dst.put_u8(b'$');
+                encode_decimal(dst, bin.len() as u64);
+                dst.put_slice(bin);
+            }
+            Frame::Integer(val) => {
+                dst.put_u8(b':');
+                dst.put_slice(val.to_string().as_bytes());
+            }
+            Frame::Double(val) => {
+                let text = format_double(*val);
+                if protocol == Protocol::V3 {
+                    dst.put_u8(b',');
+                    dst.put_slice(text.as_bytes());
+                } else {
+                    dst.put_u8(b'$');
+                    encode_decimal(dst, text.len() as u64);
+                    dst.put_slice(text.as_bytes());
+                }
+            }
+            Frame::Null => {
+                if protocol == Protocol::V3 {
+                    dst.put_u8(b'_');
+                } else {
+                    dst.put_slice(b"$-1");
+                }
+            }
+            Frame::Array(_) => Err(FrameError::Recursive)?,
+        }
+        dst.put_slice(b"\r\n");
+        Ok(())
+    }
+}
+
+/// Writes the text of a simple string or an error, which may hold text of
+/// the client. A line break would end it early, so they are written as spaces.
+fn encode_line(dst: &mut BytesMut, line: &str) {
+    if !line.contains(['\r', '\n']) {
+        dst.put_slice(line.as_bytes());
+        return;
+    }
+    dst.extend(line.bytes().map(|b| match b {
+        b'\r' | b'\n' => b' ',
+        b => b,
+    }));
+}
+
+fn encode_decimal(dst: &mut BytesMut, val: u64) {
+    dst.put_slice(val.to_string().as_bytes());
+    dst.put_slice(b"\r\n");
+}
+
+/// A resumable [`Frame::check`]. It remembers how far the frame at the front
+/// of a buffer was found well formed, so that the bytes read next are checked
+/// on their own instead of along with all those before them. Large arrays and
+/// long lines arriving in many small reads are thus checked in linear time.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FrameCheck {
+    /// The length of the array header and entries which have fully arrived.
+    checked: usize,
+    /// The entries of the array still to come, none before its header or
+    /// when the frame isn't an array.
+    entries: Option<u64>,
+    /// Where the search for the end of the pending line resumes.
+    scanned: usize,
+    /// How long the frame is at least, once the header of a bulk string
+    /// whose body is still arriving shows it.
+    awaited: usize,
+}
+
+impl FrameCheck {
+    /// Goes on checking the frame at the front of `buf`, which holds what it
+    /// held on the previous call and maybe more. Returns the length of the
+    /// frame once it has fully arrived, and starts over for the next one.
+    /// Fails as soon as the headers received show that the frame breaks
+    /// `limits`.
+    pub fn advance(&mut self, buf: &[u8], limits: &FrameLimits) -> Result<Option<usize>> {
+        match self.advance_entries(buf, limits)? {
+            Some(len) => {
+                *self = FrameCheck::default();
+                Ok(Some(len))
+            }
+            // Say, a line which doesn't end.
+            None if buf.len() > limits.max_frame_size => {
+                Err(FrameError::FrameTooLarge(limits.max_frame_size))?
+            }
             None => Ok(None),
-            _ => unimplemented!(),
+        }
+    }
+
+    /// How many bytes the frame being checked is known to take, 0 until the
+    /// header of a bulk string in it has arrived.
+    pub fn awaited(&self) -> usize {
+        self.awaited
+    }
+
+    fn advance_entries(&mut self, buf: &[u8], limits: &FrameLimits) -> Result<Option<usize>> {
+        loop {
+            match (self.entries, buf.get(self.checked)) {
+                (Some(0), _) => return Ok(Some(self.checked)),
+                (_, None) => return Ok(None),
+                (Some(_), Some(b'*')) => Err(FrameError::Recursive)?,
+                (Some(left), Some(_)) => match self.scalar(buf, self.checked, limits)? {
+                    Some(end) => {
+                        self.checked = end;
+                        self.entries = Some(left - 1);
+                    }
+                    None => return Ok(None),
+                },
+                (None, Some(b'*')) => {
+                    let Some((line, end)) = self.line(buf, self.checked + 1) else {
+                        return Ok(None);
+                    };
+                    let len = std::str::from_utf8(line)?.parse::<u64>()?;
+                    if len > limits.max_array_len as u64 {
+                        Err(FrameError::ArrayTooLong(len))?
+                    }
+                    self.checked = end;
+                    self.entries = Some(len);
+                }
+                (None, Some(_)) => return self.scalar(buf, self.checked, limits),
+            }
+        }
+    }
+
+    /// Checks the frame starting at `at`, which isn't an array. Returns where
+    /// it ends once it has fully arrived.
+    fn scalar(&mut self, buf: &[u8], at: usize, limits: &FrameLimits) -> Result<Option<usize>> {
+        match buf[at] {
+            b'+' | b'-' | b':' | b',' | b'_' => Ok(self.line(buf, at + 1).map(|(_, end)| end)),
+            b'$' => {
+                let Some((line, end)) = self.line(buf, at + 1) else {
+                    return Ok(None);
+                };
+                let len = std::str::from_utf8(line)?.parse::<i64>()?;
+                if len > limits.max_bulk_len as i64 {
+                    Err(FrameError::BulkTooLong(len))?
+                }
+                if len < 0 {
+                    return Ok(Some(end));
+                }
+                let end = end + len as usize + 2;
+                if end > limits.max_frame_size {
+                    Err(FrameError::FrameTooLarge(limits.max_frame_size))?
+                }
+                self.awaited = end;
+                Ok((buf.len() >= end).then_some(end))
+            }
+            marker => Err(FrameError::UnknownMarker(marker))?,
+        }
+    }
+
+    /// Finds the line starting at `start`. Returns it without its CRLF,
+    /// along with where the next line starts.
+    fn line<'a>(&mut self, buf: &'a [u8], start: usize) -> Option<(&'a [u8], usize)> {
+        let from = self.scanned.max(start);
+        match buf[from..].windows(2).position(|pair| pair == b"\r\n") {
+            Some(offset) => {
+                self.scanned = 0;
+                Some((&buf[start..from + offset], from + offset + 2))
+            }
+            None => {
+                // The CR of a CRLF may have arrived alone.
+                self.scanned = buf.len().saturating_sub(1).max(start);
+                None
+            }
         }
     }
 }
 
+/// Bulk payloads at least this large are sliced out of the read buffer instead
+/// of copied. A slice keeps the whole buffer alive for as long as the value is
+/// stored, which is only worth it when the copy would be expensive.
+pub const ZERO_COPY_MIN: usize = 16 * 1024;
+
 impl std::fmt::Display for Frame {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Frame::Text(txt) => std::fmt::Display::fmt(&txt, f),
             Frame::Error(err) => write!(f, "error: {}", err),
+            Frame::Integer(val) => write!(f, "(integer) {}", val),
+            Frame::Double(val) => write!(f, "(double) {}", format_double(*val)),
             Frame::Binary(binary) => std::fmt::LowerHex::fmt(&binary, f),
             Frame::Array(parts) => {
                 for (i, part) in parts.iter().enumerate() {
@@ -317,53 +1569,381 @@ impl std::fmt::Display for Frame {
     }
 }
 
-fn get_line_bump<'a>(src: &mut Cursor<&'a [u8]>) -> Option<&'a [u8]> {
-    let start = src.position() as usize;
-    let end = src.get_ref().len() - 1;
-    for i in start..end {
-        if src.get_ref()[i] == b'\r' && src.get_ref()[i + 1] == b'\n' {
-            src.set_position((i + 2) as u64);
-            return Some(&src.get_ref()[start..i]);
-        }
-    }
-    None
+/// Splits the line at the front of `src` off, without its CRLF.
+fn split_line(src: &mut Bytes) -> Option<Bytes> {
+    let end = src.windows(2).position(|pair| pair == b"\r\n")?;
+    let line = src.split_to(end);
+    src.advance(2);
+    Some(line)
 }
 
-fn get_u8_bump(src: &mut Cursor<&[u8]>) -> Option<u8> {
-    if !src.has_remaining() {
-        return None;
-    }
-    Some(src.get_u8())
+fn split_decimal(src: &mut Bytes) -> Result<u64> {
+    let line = split_line(src).ok_or(FrameError::Incomplete)?;
+    Ok(std::str::from_utf8(&line)?.parse::<u64>()?)
 }
 
-fn skip(src: &mut Cursor<&[u8]>, n: usize) -> Result<()> {
-    if src.remaining() < n {
-        Err(FrameError::Incomplete)?
-    }
+fn split_signed(src: &mut Bytes) -> Result<i64> {
+    let line = split_line(src).ok_or(FrameError::Incomplete)?;
+    Ok(std::str::from_utf8(&line)?.parse::<i64>()?)
+}
 
-    src.advance(n);
-    Ok(())
+/// Formats a double the way it travels on the wire: `inf`, `-inf` and `nan` for
+/// the special values, otherwise the shortest text that parses back to exactly
+/// the same value, e.g. `1.5`, `1.0` or `1e300`.
+pub fn format_double(val: f64) -> String {
+    if val.is_nan() {
+        "nan".to_string()
+    } else if val == f64::INFINITY {
+        "inf".to_string()
+    } else if val == f64::NEG_INFINITY {
+        "-inf".to_string()
+    } else {
+        format!("{:?}", val)
+    }
 }
 
-fn get_decimal_bump(src: &mut Cursor<&[u8]>) -> Result<u64> {
-    let line = get_line_bump(src).ok_or(FrameError::Incomplete)?;
-    let utf8_num = std::str::from_utf8(line)?;
-    Ok(utf8_num.parse::<u64>()?)
+/// Parses a double in the form produced by [`format_double`]. Plain integers
+/// such as `3` are accepted as well.
+pub fn parse_double(text: &str) -> Result<f64> {
+    Ok(text.parse::<f64>()?)
 }
 
 #[cfg(test)]
 mod tests {
+    use tokio::net::TcpStream;
+
     use super::*;
 
     #[test]
     fn test_array_frame() {
         let literal_frame = b"*2\r\n+SET\r\n+123\r\n";
-        let mut cursor: Cursor<&[u8]> = Cursor::new(literal_frame);
-        let parsed_frame = Frame::parse(&mut cursor).unwrap().unwrap();
+        let mut src = Bytes::from_static(literal_frame);
+        let parsed_frame = Frame::parse(&mut src).unwrap().unwrap();
         let arr_frames = Frame::Array(vec![
             Frame::Text("SET".to_string()),
             Frame::Text("123".to_string()),
         ]);
         assert_eq!(parsed_frame, arr_frames)
     }
+
+    #[test]
+    fn test_double_text_round_trip() {
+        for val in [
+            0.0,
+            -2.5,
+            0.1,
+            1.0,
+            1e300,
+            1e-7,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+        ] {
+            assert_eq!(parse_double(&format_double(val)).unwrap(), val);
+        }
+        assert!(parse_double(&format_double(f64::NAN)).unwrap().is_nan());
+        assert_eq!(format_double(1.0), "1.0");
+        assert_eq!(format_double(f64::NEG_INFINITY), "-inf");
+    }
+
+    #[test]
+    fn test_scalar_frames() {
+        let literal_frame = b"*4\r\n:-42\r\n,1.5\r\n_\r\n$-1\r\n";
+        let mut cursor: Cursor<&[u8]> = Cursor::new(literal_frame);
+        let limits = FrameLimits::default();
+        assert!(Frame::check(&mut cursor, &limits).unwrap().is_some());
+        let parsed_frame = Frame::parse(&mut Bytes::from_static(literal_frame))
+            .unwrap()
+            .unwrap();
+        let arr_frames = Frame::Array(vec![
+            Frame::Integer(-42),
+            Frame::Double(1.5),
+            Frame::Null,
+            Frame::Null,
+        ]);
+        assert_eq!(parsed_frame, arr_frames)
+    }
+
+    #[test]
+    fn test_line_breaks_in_simple_frames() {
+        let err = Frame::error(ErrorCode::Err, "unknown parameter 'x\r\n-ERR zzzz'");
+        let mut dst = BytesMut::new();
+        err.encode(Protocol::V2, &mut dst).unwrap();
+        assert_eq!(&dst[..], b"-ERR unknown parameter 'x  -ERR zzzz'\r\n");
+        let mut src = dst.freeze();
+        assert!(matches!(Frame::parse(&mut src), Ok(Some(Frame::Error(_)))));
+        assert!(src.is_empty());
+
+        let mut dst = BytesMut::new();
+        Frame::Text("a\nb".to_string())
+            .encode(Protocol::V2, &mut dst)
+            .unwrap();
+        assert_eq!(&dst[..], b"+a b\r\n");
+    }
+
+    #[test]
+    fn test_bulk_frames_share_the_source() {
+        let small = vec![b'a'; 16];
+        let large = vec![b'b'; ZERO_COPY_MIN];
+        let mut src = BytesMut::new();
+        for payload in [&small, &large] {
+            src.extend_from_slice(format!("${}\r\n", payload.len()).as_bytes());
+            src.extend_from_slice(payload);
+            src.extend_from_slice(b"\r\n");
+        }
+        let mut src = src.freeze();
+        let range = src.as_ptr_range();
+
+        let Some(Frame::Binary(small_parsed)) = Frame::parse(&mut src).unwrap() else {
+            panic!("expected a bulk string");
+        };
+        assert_eq!(small_parsed, small);
+        assert!(!range.contains(&small_parsed.as_ptr()));
+
+        let Some(Frame::Binary(large_parsed)) = Frame::parse(&mut src).unwrap() else {
+            panic!("expected a bulk string");
+        };
+        assert_eq!(large_parsed, large);
+        assert!(range.contains(&large_parsed.as_ptr()));
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_frame_limits() {
+        let limits = FrameLimits {
+            max_bulk_len: 16,
+            max_array_len: 4,
+            max_frame_size: 64,
+        };
+        let check = |frame: &[u8]| Frame::check(&mut Cursor::new(frame), &limits);
+        let limit = |frame: &[u8]| match check(frame) {
+            Err(err) => err.downcast::<FrameError>().unwrap(),
+            Ok(checked) => panic!("{:?} passed the limits", checked),
+        };
+
+        assert!(check(b"$16\r\n0123456789abcdef\r\n").unwrap().is_some());
+        assert!(check(b"$16\r\n0123").unwrap().is_none());
+        assert!(check(b"*2\r\n$1\r\na\r\n$3\r\nab").unwrap().is_none());
+        assert!(check(b"*2\r\n+a\r\n+b").unwrap().is_none());
+        assert!(matches!(
+            limit(b"$999999999999\r\n"),
+            FrameError::BulkTooLong(_)
+        ));
+        assert!(matches!(
+            limit(b"*999999999\r\n"),
+            FrameError::ArrayTooLong(_)
+        ));
+        let large = b"*4\r\n$16\r\n0123456789abcdef\r\n$16\r\n0123456789abcdef\r\n$16\r\n";
+        assert!(matches!(limit(large), FrameError::FrameTooLarge(64)));
+        assert!(matches!(limit(&[b'+'; 100]), FrameError::FrameTooLarge(64)));
+    }
+
+    #[test]
+    fn test_frame_check_resumes() {
+        let mut frame = b"*100\r\n".to_vec();
+        for _ in 0..100 {
+            frame.extend_from_slice(b"$3\r\nabc\r\n");
+        }
+        let limits = FrameLimits::default();
+        let mut check = FrameCheck::default();
+        for end in 1..frame.len() {
+            assert_eq!(check.advance(&frame[..end], &limits).unwrap(), None);
+        }
+        // Only the last entry is left to check.
+        assert_eq!(check.checked, frame.len() - 9);
+        assert_eq!(check.entries, Some(1));
+        assert_eq!(check.advance(&frame, &limits).unwrap(), Some(frame.len()));
+        assert_eq!(check, FrameCheck::default());
+
+        let line = b"+a long line\r\n";
+        for end in 1..line.len() {
+            assert_eq!(check.advance(&line[..end], &limits).unwrap(), None);
+        }
+        assert_eq!(check.scanned, line.len() - 2);
+        assert_eq!(check.advance(line, &limits).unwrap(), Some(line.len()));
+    }
+
+    #[test]
+    fn test_malformed_frames() {
+        let limits = FrameLimits::default();
+        for frame in [&b"?1\r\n"[..], b"*2\r\n*1\r\n:1\r\n:2\r\n", b"*1\r\n!\r\n"] {
+            assert!(Frame::check(&mut Cursor::new(frame), &limits).is_err());
+            assert!(Frame::parse(&mut Bytes::copy_from_slice(frame)).is_err());
+        }
+        let truncated = Frame::parse(&mut Bytes::from_static(b"*999999999999\r\n:1\r\n"));
+        let err = truncated.unwrap_err().downcast::<FrameError>().unwrap();
+        assert!(matches!(err, FrameError::Incomplete));
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_replies_are_sent_together() {
+        let (mut server, mut client) = connection_pair().await;
+        server.set_flush_policy(FlushPolicy::Pipelined);
+        for i in 0..3 {
+            client.queue_frame(&Frame::Integer(i)).unwrap();
+        }
+        client.flush().await.unwrap();
+
+        for i in 0..3 {
+            assert_eq!(server.read_frame().await.unwrap(), Some(Frame::Integer(i)));
+            server.write_frame(&Frame::Integer(-i)).await.unwrap();
+            // Held while the next request is buffered already.
+            assert_eq!(server.output.is_empty(), i == 2);
+        }
+        for i in 0..3 {
+            assert_eq!(client.read_frame().await.unwrap(), Some(Frame::Integer(-i)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inline_commands() {
+        let (mut server, client) = connection_pair().await;
+        let mut client = client.stream;
+        client
+            .write_all(b"SET foo  bar\r\n\r\nGET foo\n*1\r\n$4\r\nPING\r\nECHO")
+            .await
+            .unwrap();
+        client.flush().await.unwrap();
+        let words = |words: &[&'static str]| {
+            let words = words.iter().map(|word| Frame::Binary(Bytes::from(*word)));
+            Some(Frame::Array(words.collect()))
+        };
+
+        assert_eq!(
+            server.read_frame().await.unwrap(),
+            words(&["SET", "foo", "bar"])
+        );
+        assert!(server.has_buffered_frame());
+        assert_eq!(server.read_frame().await.unwrap(), words(&["GET", "foo"]));
+        assert_eq!(server.read_frame().await.unwrap(), words(&["PING"]));
+        assert!(!server.has_buffered_frame());
+        client.write_all(b" hi\r\n").await.unwrap();
+        client.flush().await.unwrap();
+        assert_eq!(server.read_frame().await.unwrap(), words(&["ECHO", "hi"]));
+    }
+
+    #[tokio::test]
+    async fn test_large_frames_shrink_the_buffer() {
+        let (mut server, mut client) = connection_pair().await;
+        server.set_buffer_config(BufferConfig {
+            initial_size: 1024,
+            max_size: 16 * 1024,
+        });
+        let large = Frame::Binary(Bytes::from(vec![b'x'; 4 * 1024 * 1024]));
+        let frames = [large.clone(), Frame::Integer(1)];
+        let writing = tokio::spawn(async move {
+            for frame in &frames {
+                client.write_frame(frame).await.unwrap();
+            }
+            client
+        });
+
+        assert_eq!(server.read_frame().await.unwrap(), Some(large));
+        assert_eq!(server.read_size.get(), 16 * 1024);
+        assert!(server.buffer.capacity() <= 16 * 1024);
+        assert_eq!(server.read_frame().await.unwrap(), Some(Frame::Integer(1)));
+        writing.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_bulk_in_chunks() {
+        let (mut server, mut client) = connection_pair().await;
+        server.set_buffer_config(BufferConfig {
+            initial_size: 1024,
+            max_size: 1024,
+        });
+        let value = Bytes::from(vec![b'x'; 64 * 1024]);
+        let frames = [
+            Frame::Binary(value.clone()),
+            Frame::Null,
+            Frame::Binary(Bytes::new()),
+            Frame::Integer(1),
+        ];
+        let writing = tokio::spawn(async move {
+            for frame in &frames {
+                client.write_frame(frame).await.unwrap();
+            }
+            client
+        });
+
+        let head = server.read_head().await.unwrap();
+        assert_eq!(head, Some(FrameHead::Bulk(value.len())));
+        let (mut body, mut chunks) = (BytesMut::new(), 0);
+        while body.len() < value.len() {
+            let chunk = server.read_chunk(value.len() - body.len()).await.unwrap();
+            assert!(!chunk.is_empty());
+            body.extend_from_slice(&chunk);
+            chunks += 1;
+        }
+        assert_eq!(body, value);
+        assert!(chunks > 1);
+        let head = server.read_head().await.unwrap();
+        assert_eq!(head, Some(FrameHead::Frame(Frame::Null)));
+        assert_eq!(server.read_head().await.unwrap(), Some(FrameHead::Bulk(0)));
+        assert_eq!(server.read_chunk(0).await.unwrap(), Bytes::new());
+        let head = server.read_head().await.unwrap();
+        assert_eq!(head, Some(FrameHead::Frame(Frame::Integer(1))));
+        writing.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_write_streamed() {
+        let (mut server, mut client) = connection_pair().await;
+        server.set_buffer_config(BufferConfig {
+            initial_size: 1024,
+            max_size: 1024,
+        });
+        let value = Bytes::from(vec![b'x'; 64 * 1024]);
+        let entries = [
+            Frame::Text("SET".to_string()),
+            Frame::Text("key".to_string()),
+            Frame::Null,
+            Frame::Integer(1),
+        ];
+        let body = value.clone();
+        let writing = tokio::spawn(async move {
+            let mut body = &body[..];
+            client
+                .write_streamed(&entries, 2, body.len(), &mut body)
+                .await
+                .unwrap();
+            let mut short = &body[..0];
+            assert!(client
+                .write_streamed(&entries, 2, 1, &mut short)
+                .await
+                .is_err());
+        });
+
+        let frame = server.read_frame().await.unwrap();
+        let expected = Frame::Array(vec![
+            Frame::Text("SET".to_string()),
+            Frame::Text("key".to_string()),
+            Frame::Binary(value),
+            Frame::Integer(1),
+        ]);
+        assert_eq!(frame, Some(expected));
+        writing.await.unwrap();
+    }
+
+    async fn connection_pair() -> (Connection, Connection) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (Connection::new(server), Connection::new(client))
+    }
+
+    #[tokio::test]
+    async fn test_double_negotiated_encoding() {
+        let (mut server, mut client) = connection_pair().await;
+
+        server.write_frame(&Frame::Double(0.1)).await.unwrap();
+        let received = client.read_frame().await.unwrap().unwrap();
+        assert_eq!(received, Frame::Binary("0.1".into()));
+
+        server.set_protocol(Protocol::V3);
+        let frames = Frame::Array(vec![Frame::Double(-0.25), Frame::Integer(7), Frame::Null]);
+        server.write_frame(&frames).await.unwrap();
+        let received = client.read_frame().await.unwrap().unwrap();
+        assert_eq!(received, frames);
+    }
 }
@@ -0,0 +1,388 @@
+//! Logging configuration
+//!
+//! [`init_logging`] installs the subscriber logging the events of the server
+//! as a [`LogConfig`] says: as text or as JSON objects, one per line, to
+//! stdout or to a file rotated once it is large or old enough. Rotated files
+//! are shifted to `path.1`, `path.2` and so on, as trace files are, see
+//! [`crate::trace`].
+//!
+//! Which events are logged is set by filter directives, as
+//! `info,uranus_s::aof=debug`: a default level, then the levels of modules,
+//! in the syntax of tracing-subscriber's `EnvFilter`. The [`LogLevel`] it
+//! returns changes them at runtime, which `CONFIG SET loglevel` does once the
+//! server is given it, see [`ServerConfig::log_level`](crate::ServerConfig::log_level).
+//!
+//! Requests are logged through [`Redacted`], which keeps passwords and large
+//! values out of the logs.
+
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use tracing::Subscriber;
+use tracing_subscriber::{
+    fmt::writer::BoxMakeWriter, layer::SubscriberExt, registry::LookupSpan, reload,
+    util::SubscriberInitExt, EnvFilter, Layer, Registry,
+};
+
+use crate::Frame;
+
+/// How each event is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// A line of text, as `2023-10-16T10:00:00.000000Z  INFO uranus started to
+    /// serve requests`.
+    #[default]
+    Text,
+    /// A JSON object per line, with the `timestamp`, `level`, `target`,
+    /// `fields` and `spans` of the event.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<LogFormat> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(anyhow!("unknown log format '{}', expected text or json", s)),
+        }
+    }
+}
+
+/// How the server logs, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// The filter directives, `info` by default.
+    pub level: String,
+    /// Log to this file rather than to stdout.
+    pub file: Option<LogFile>,
+}
+
+const DEFAULT_LOG_LEVEL: &str = "info";
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            format: LogFormat::default(),
+            level: DEFAULT_LOG_LEVEL.to_string(),
+            file: None,
+        }
+    }
+}
+
+/// A log file, and when it is rotated.
+#[derive(Debug, Clone)]
+pub struct LogFile {
+    pub path: PathBuf,
+    /// The file is rotated before it grows past this many bytes, if given.
+    pub max_size: Option<u64>,
+    /// The file is rotated once the server has written to it for this long,
+    /// if given.
+    pub max_age: Option<Duration>,
+    /// How many files are kept, the current one included.
+    pub max_files: usize,
+}
+
+const DEFAULT_MAX_LOG_SIZE: u64 = 64 * 1024 * 1024;
+const DEFAULT_MAX_LOG_FILES: usize = 4;
+
+impl LogFile {
+    /// The file at `path`, rotated past 64 MiB, 4 of them being kept.
+    pub fn new(path: impl Into<PathBuf>) -> LogFile {
+        LogFile {
+            path: path.into(),
+            max_size: Some(DEFAULT_MAX_LOG_SIZE),
+            max_age: None,
+            max_files: DEFAULT_MAX_LOG_FILES,
+        }
+    }
+}
+
+/// The filter directives of the log, which can be changed while it runs.
+#[derive(Clone)]
+pub struct LogLevel {
+    handle: reload::Handle<EnvFilter, Registry>,
+    directives: Arc<Mutex<String>>,
+}
+
+impl LogLevel {
+    /// The current filter directives.
+    pub fn get(&self) -> String {
+        self.directives.lock().unwrap().clone()
+    }
+
+    /// Replaces the filter directives, leaving them as they were if they are
+    /// malformed.
+    pub fn set(&self, directives: &str) -> Result<()> {
+        let filter = EnvFilter::try_new(directives)?;
+        let mut current = self.directives.lock().unwrap();
+        self.handle.reload(filter)?;
+        *current = directives.to_string();
+        Ok(())
+    }
+}
+
+impl fmt::Debug for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("LogLevel").field(&self.get()).finish()
+    }
+}
+
+/// Installs the subscriber logging as `config` says for the whole process.
+/// With the `console` feature, it serves the tasks to tokio-console too, see
+/// [`crate::tasks`].
+pub fn init_logging(config: &LogConfig) -> Result<LogLevel> {
+    let (subscriber, level) = subscriber(config)?;
+    #[cfg(feature = "console")]
+    let subscriber = subscriber.with(crate::console_layer());
+    subscriber.try_init()?;
+    Ok(level)
+}
+
+fn subscriber(
+    config: &LogConfig,
+) -> Result<(
+    impl Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+    LogLevel,
+)> {
+    let (filter, handle) = reload::Layer::new(EnvFilter::try_new(&config.level)?);
+    let writer = match &config.file {
+        Some(file) => BoxMakeWriter::new(Mutex::new(RotatingFile::open(file.clone())?)),
+        None => BoxMakeWriter::new(io::stdout),
+    };
+    let layer = match config.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_ansi(config.file.is_none())
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(writer)
+            .boxed(),
+    };
+    let level = LogLevel {
+        handle,
+        directives: Arc::new(Mutex::new(config.level.clone())),
+    };
+    Ok((Registry::default().with(layer.with_filter(filter)), level))
+}
+
+/// The file of a [`LogFile`], rotated before the write that would make it due.
+#[derive(Debug)]
+struct RotatingFile {
+    config: LogFile,
+    file: File,
+    size: u64,
+    opened: Instant,
+}
+
+impl RotatingFile {
+    fn open(config: LogFile) -> io::Result<RotatingFile> {
+        if let Some(dir) = config.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        Ok(RotatingFile {
+            size: file.metadata()?.len(),
+            file,
+            config,
+            opened: Instant::now(),
+        })
+    }
+
+    fn is_due(&self, len: usize) -> bool {
+        let too_large = (self.config.max_size).is_some_and(|max| self.size + len as u64 > max);
+        let too_old = (self.config.max_age).is_some_and(|max| self.opened.elapsed() >= max);
+        self.size > 0 && (too_large || too_old)
+    }
+
+    /// Shifts `path.1` to `path.2` and so on, dropping the oldest, moves the
+    /// current file to `path.1`, then starts a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        let path = &self.config.path;
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+        let kept = self.config.max_files.max(1) - 1;
+        if kept == 0 {
+            fs::remove_file(path)?;
+        } else {
+            for n in (1..kept).rev() {
+                if rotated(n).exists() {
+                    fs::rename(rotated(n), rotated(n + 1))?;
+                }
+            }
+            fs::rename(path, rotated(1))?;
+        }
+        *self = RotatingFile::open(self.config.clone())?;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.is_due(buf.len()) {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Bulk strings longer than this are logged by their length, see [`Redacted`].
+const LOGGED_BULK_MAX: usize = 128;
+
+/// Formats a request for the logs as its [`Frame`] would be, but with the
+/// passwords among its arguments replaced by `<redacted>`: those of `AUTH`,
+/// the rules of `ACL SETUSER`, the password of `HELLO ... AUTH` and the secret
+/// of `REPLCONF PRIMARY`. Bulk strings longer than [`LOGGED_BULK_MAX`] bytes
+/// are logged by their length.
+pub struct Redacted<'a>(pub &'a Frame);
+
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Frame::Array(parts) = self.0 else {
+            return fmt::Debug::fmt(&Logged(self.0, false), f);
+        };
+        let parts = (parts.iter().enumerate()).map(|(i, part)| Logged(part, is_secret(parts, i)));
+        write!(f, "Array(")?;
+        f.debug_list().entries(parts).finish()?;
+        write!(f, ")")
+    }
+}
+
+/// A part of a request, see [`Redacted`].
+struct Logged<'a>(&'a Frame, bool);
+
+impl fmt::Debug for Logged<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Logged(_, true) => write!(f, "<redacted>"),
+            Logged(Frame::Binary(bulk), _) if bulk.len() > LOGGED_BULK_MAX => {
+                write!(f, "Binary(<{} bytes>)", bulk.len())
+            }
+            Logged(frame, _) => fmt::Debug::fmt(frame, f),
+        }
+    }
+}
+
+/// Whether the `i`th of the `parts` of a request is a password.
+fn is_secret(parts: &[Frame], i: usize) -> bool {
+    let is = |i: usize, name: &str| match parts.get(i) {
+        Some(Frame::Text(text)) => text.eq_ignore_ascii_case(name),
+        Some(Frame::Binary(bulk)) => bulk.eq_ignore_ascii_case(name.as_bytes()),
+        _ => false,
+    };
+    if is(0, "auth") {
+        i >= 1
+    } else if is(0, "acl") && is(1, "setuser") {
+        i >= 3
+    } else if is(0, "hello") {
+        // HELLO protover AUTH username password
+        i == 4 && is(2, "auth")
+    } else if is(0, "replconf") {
+        i >= 2
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::{debug, info};
+
+    use super::*;
+    use crate::Command;
+
+    #[test]
+    fn test_logging() {
+        let dir = std::env::temp_dir().join(format!("uranus-log-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("uranus.log");
+        let config = LogConfig {
+            format: LogFormat::Json,
+            level: "info".to_string(),
+            file: Some(LogFile {
+                max_size: Some(200),
+                max_files: 2,
+                ..LogFile::new(&path)
+            }),
+        };
+        let (subscriber, level) = subscriber(&config).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            info!(key = "first", "stored");
+            debug!("hidden");
+            assert!(level.set("uranus_s=loud").is_err());
+            level.set("debug").unwrap();
+            debug!(key = "second", "stored");
+        });
+        assert_eq!(level.get(), "debug");
+
+        // Each event is a JSON object, and the second one rotated the file.
+        let rotated = fs::read_to_string(dir.join("uranus.log.1")).unwrap();
+        let current = fs::read_to_string(&path).unwrap();
+        assert_eq!(rotated.lines().count(), 1);
+        assert!(rotated.contains(r#""level":"INFO""#), "{}", rotated);
+        assert!(rotated.contains(r#""key":"first""#), "{}", rotated);
+        assert_eq!(current.lines().count(), 1);
+        assert!(current.contains(r#""level":"DEBUG""#), "{}", current);
+        assert!(!current.contains("hidden"));
+
+        assert_eq!("JSON".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert!("xml".parse::<LogFormat>().is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_redacted() {
+        let frame = |parts: &[&str]| {
+            let parts = parts
+                .iter()
+                .map(|part| Frame::Binary(part.to_string().into()));
+            Frame::Array(parts.collect())
+        };
+        let request = |parts: &[&str]| format!("{:?}", Redacted(&frame(parts)));
+        for parts in [
+            &["AUTH", "alice", "secret"][..],
+            &["acl", "setuser", "alice", "on", ">secret"],
+            &["HELLO", "3", "AUTH", "alice", "secret", "SETNAME", "conn"],
+            &["REPLCONF", "PRIMARY", "secret"],
+        ] {
+            let logged = request(parts);
+            assert!(!logged.contains("secret"), "{}", logged);
+            assert!(logged.contains("<redacted>"), "{}", logged);
+            // The commands are logged too, though HELLO doesn't parse AUTH.
+            if let Ok(command) = Command::from_frame(frame(parts)) {
+                assert!(
+                    !format!("{:?}", command).contains("secret"),
+                    "{:?}",
+                    command
+                );
+            }
+        }
+        let logged = request(&["HELLO", "3", "AUTH", "alice", "secret", "SETNAME", "conn"]);
+        assert!(
+            logged.contains("alice") && logged.contains("conn"),
+            "{}",
+            logged
+        );
+        let logged = request(&["SET", "key", &"v".repeat(1000)]);
+        assert!(logged.contains("<1000 bytes>"), "{}", logged);
+    }
+}
@@ -1,7 +1,17 @@
-use anyhow::Result;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
 use tokio::net::TcpListener;
+use uranus_s::{
+    FrameLimits, HealthConfig, KeyspaceEvents, LogConfig, LogFile, ServerConfig, StorageEngine,
+    WalSync,
+};
 
 const DEFAULT_PORT: u16 = 12322;
+const DEFAULT_DATA_DIR: &str = "./data";
 
 #[tokio::main]
 pub async fn main() {
@@ -9,12 +19,129 @@ pub async fn main() {
 }
 
 async fn smain() -> Result<()> {
-    setup_logging()?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some((flag, paths)) = args.split_first() {
+        if flag == "--verify" {
+            uranus_s::init_logging(&LogConfig::default())?;
+            return verify(paths);
+        }
+    }
+    let options = Options::parse(&args)?;
+    let log_level = uranus_s::init_logging(&options.log)?;
+    let config = ServerConfig {
+        storage: options.storage,
+        log_level: Some(log_level),
+        health: options.health,
+        keyspace_events: options.keyspace_events,
+        wal_sync: options.wal_sync,
+        ..Default::default()
+    };
     let listener = TcpListener::bind(&format!("127.0.0.1:{}", DEFAULT_PORT)).await?;
-    uranus_s::run(listener).await;
+    uranus_s::run_with_config(listener, config).await;
     Ok(())
 }
 
-fn setup_logging() -> Result<()> {
-    tracing_subscriber::fmt::try_init().map_err(|err| anyhow::anyhow!(err))
+/// The command line of the server.
+///
+/// `--storage memory`, the default, keeps the keys in memory only, while
+/// `--storage lsm` keeps them on disk in `--data-dir`, `./data` by default.
+/// `--wal-fsync always|everysec|no` says when its log is synced to the disk,
+/// `everysec` by default, see [`WalSync`].
+///
+/// The log is written as `--log-format text`, the default, or `json`, with
+/// the filter directives of `--log-level`, `info` by default, which `CONFIG
+/// SET loglevel` changes later. `--log-file <path>` writes it to a file rather
+/// than to stdout, rotated past `--log-max-size <bytes>`, 64 MiB by default,
+/// and every `--log-rotate <seconds>` if given.
+///
+/// `--health-addr <addr>` serves the liveness and readiness probes over HTTP,
+/// see [`uranus_s::health`].
+///
+/// `--notify-keyspace-events <flags>` publishes keyspace notifications, as
+/// `KEA` for all of them, see [`KeyspaceEvents`].
+struct Options {
+    storage: StorageEngine,
+    log: LogConfig,
+    health: Option<HealthConfig>,
+    keyspace_events: KeyspaceEvents,
+    wal_sync: WalSync,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options> {
+        let mut engine = "memory";
+        let mut data_dir = PathBuf::from(DEFAULT_DATA_DIR);
+        let mut log = LogConfig::default();
+        let mut health = None;
+        let mut keyspace_events = KeyspaceEvents::none();
+        let mut wal_sync = WalSync::default();
+        let (mut max_size, mut max_age) = (None, None);
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow!("{} needs a value", arg))?;
+            let number = || (value.parse::<u64>()).map_err(|_| anyhow!("{} expects a number", arg));
+            match arg.as_str() {
+                "--storage" => engine = value.as_str(),
+                "--data-dir" => data_dir = PathBuf::from(value),
+                "--wal-fsync" => wal_sync = value.parse()?,
+                "--log-format" => log.format = value.parse()?,
+                "--log-level" => log.level = value.clone(),
+                "--log-file" => log.file = Some(LogFile::new(value)),
+                "--log-max-size" => max_size = Some(number()?),
+                "--log-rotate" => max_age = Some(Duration::from_secs(number()?)),
+                "--health-addr" => {
+                    health = Some(HealthConfig {
+                        addr: value.clone(),
+                    })
+                }
+                "--notify-keyspace-events" => keyspace_events = value.parse()?,
+                _ => bail!("unknown argument '{}'", arg),
+            }
+        }
+        match &mut log.file {
+            Some(file) => {
+                file.max_size = max_size.or(file.max_size);
+                file.max_age = max_age;
+            }
+            None if max_size.is_some() || max_age.is_some() => {
+                bail!("--log-max-size and --log-rotate need a --log-file")
+            }
+            None => {}
+        }
+        let storage = match engine {
+            "memory" => StorageEngine::Memory,
+            "lsm" => StorageEngine::Lsm { data_dir },
+            _ => bail!(
+                "unknown storage engine '{}', expected memory or lsm",
+                engine
+            ),
+        };
+        Ok(Options {
+            storage,
+            log,
+            health,
+            keyspace_events,
+            wal_sync,
+        })
+    }
+}
+
+/// `uranus-s --verify <file> ...` checks append-only files without loading
+/// them, and exits with 1 if one of them is corrupted.
+fn verify(paths: &[String]) -> Result<()> {
+    if paths.is_empty() {
+        bail!("usage: uranus-s --verify <file> ...");
+    }
+    let mut corrupted = false;
+    for path in paths {
+        let report = uranus_s::verify_aof(Path::new(path), FrameLimits::default())?;
+        println!("{}: {}", path, report);
+        corrupted |= !report.is_ok();
+    }
+    if corrupted {
+        std::process::exit(1);
+    }
+    Ok(())
 }
@@ -0,0 +1,58 @@
+//! Publish/subscribe messaging between connections
+//!
+//! Every published message is broadcast to all subscribed connections, each
+//! keeping the ones matching its own channels and patterns.
+//!
+
+use bytes::Bytes;
+use tokio::sync::broadcast;
+
+/// Channels on which writes to keys are announced, when keyspace events are
+/// enabled: a write to `key` publishes the command name on `__keyspace@0__:key`,
+/// its expiry `expired` and its eviction `evicted`.
+pub const KEYSPACE_PREFIX: &str = "__keyspace@0__:";
+
+/// Channels on which the same events are announced by kind, when enabled: a
+/// write to `key` by `set` publishes `key` on `__keyevent@0__:set`, and its
+/// expiry on `__keyevent@0__:expired`. See
+/// [`KeyspaceEvents`](crate::KeyspaceEvents).
+pub const KEYEVENT_PREFIX: &str = "__keyevent@0__:";
+
+const CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub channel: String,
+    pub payload: Bytes,
+}
+
+#[derive(Debug, Clone)]
+pub struct PubSub {
+    sender: broadcast::Sender<Message>,
+}
+
+impl PubSub {
+    pub fn new() -> PubSub {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        PubSub { sender }
+    }
+
+    /// Publishes `payload` on `channel`, returns the number of subscribed connections.
+    pub fn publish(&self, channel: impl ToString, payload: impl Into<Bytes>) -> usize {
+        let message = Message {
+            channel: channel.to_string(),
+            payload: payload.into(),
+        };
+        self.sender.send(message).unwrap_or(0)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Message> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for PubSub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,311 @@
+//! Lua scripts run by `EVAL`, atomically
+//!
+//! A script runs on a blocking thread, as Lua can't yield to the event loop.
+//! Each `redis.call` is sent back to the connection running the script, which
+//! applies it through the normal command path on an in-memory connection and
+//! returns the reply. Meanwhile no other command runs: commands hold the
+//! [`Scripts`] lock shared while they are applied, scripts hold it exclusive,
+//! so scripts running past their time limit are aborted.
+//! Blocking commands don't take it, lest they hold scripts up while they
+//! wait, and scripts may not call them. Calls are checked against the
+//! [`Acl`](crate::Acl) as the user running the script.
+//!
+//! Scripts are cached by the SHA1 digest of their source, for `EVALSHA`.
+//!
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+use mlua::{HookTriggers, Lua, Value as LuaValue, Variadic};
+use tokio::{
+    io,
+    sync::{mpsc, oneshot, OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock},
+    task,
+};
+
+use crate::{error_reply, format_double, Command, Connection, Database, ErrorCode, Frame, Shared};
+
+#[derive(Debug, Clone)]
+pub struct Scripts {
+    /// Sources by digest.
+    cache: Arc<Mutex<HashMap<String, Arc<str>>>>,
+    running: Arc<RwLock<()>>,
+    time_limit: Duration,
+}
+
+/// Held while a request is applied, see [`Scripts::lock`].
+#[derive(Debug)]
+pub enum ScriptLock {
+    Command(OwnedRwLockReadGuard<()>),
+    Script(OwnedRwLockWriteGuard<()>),
+}
+
+impl Scripts {
+    /// Scripts running longer than `time_limit` are aborted with an error.
+    pub fn new(time_limit: Duration) -> Scripts {
+        Scripts {
+            cache: Arc::default(),
+            running: Arc::default(),
+            time_limit,
+        }
+    }
+
+    /// The SHA1 digest of `source` in hex, which `EVALSHA` names it by.
+    pub fn digest(source: &str) -> String {
+        sha1_smol::Sha1::from(source).digest().to_string()
+    }
+
+    /// Caches `source`, returns it as [`Scripts::get`] does.
+    pub fn load(&self, source: String) -> Arc<str> {
+        let digest = Scripts::digest(&source);
+        let mut cache = self.cache.lock().unwrap();
+        cache.entry(digest).or_insert_with(|| source.into()).clone()
+    }
+
+    /// The source of the script cached under `digest`, in any case.
+    pub fn get(&self, digest: &str) -> Option<Arc<str>> {
+        let cache = self.cache.lock().unwrap();
+        cache.get(&digest.to_ascii_lowercase()).cloned()
+    }
+
+    /// Waits until a request can be applied, until the returned guard is
+    /// dropped. A script waits for the commands being applied to complete,
+    /// and commands wait for the script being run to complete.
+    pub async fn lock(&self, script: bool) -> ScriptLock {
+        if script {
+            ScriptLock::Script(self.running.clone().write_owned().await)
+        } else {
+            ScriptLock::Command(self.running.clone().read_owned().await)
+        }
+    }
+}
+
+/// A `redis.call` of a script, and where its reply goes.
+struct Call {
+    request: Frame,
+    reply: oneshot::Sender<Frame>,
+}
+
+/// Runs `source` with `keys` in `KEYS` and `args` in `ARGV` for `user`,
+/// returns the reply to send for its result.
+pub async fn eval_script<D: Database>(
+    source: Arc<str>,
+    keys: Vec<Bytes>,
+    args: Vec<Bytes>,
+    user: Option<&str>,
+    db: &D,
+    shared: &Shared,
+) -> Result<Frame> {
+    let (sender, mut calls) = mpsc::channel(1);
+    let deadline = Instant::now() + shared.scripts.time_limit;
+    let script = task::spawn_blocking(move || match run(&source, keys, args, deadline, sender) {
+        Ok(frame) => frame,
+        Err(err) => Frame::error(ErrorCode::Err, format!("Error running script: {}", err)),
+    });
+    let mut loopback = Loopback::connect(user);
+    let mut db = db.clone();
+    // Ends once the script returns, dropping the sender.
+    while let Some(Call { request, reply }) = calls.recv().await {
+        let _ = reply.send(loopback.apply(request, &mut db, shared).await?);
+    }
+    Ok(script.await?)
+}
+
+/// The size of the pipe between the ends of a [`Loopback`]. Replies larger
+/// than this are read as they are written.
+const LOOPBACK_BUFFER: usize = 64 * 1024;
+
+/// An in-memory connection, which the calls of a script are applied on and
+/// their replies read back from.
+struct Loopback {
+    server: Connection,
+    client: Connection,
+}
+
+impl Loopback {
+    fn connect(user: Option<&str>) -> Loopback {
+        let (client, server) = io::duplex(LOOPBACK_BUFFER);
+        // Acts as the user running the script.
+        let mut server = Connection::new(server);
+        if let Some(user) = user {
+            server.set_user(user.to_string());
+        }
+        Loopback {
+            server,
+            client: Connection::new(client),
+        }
+    }
+
+    async fn apply<D: Database>(
+        &mut self,
+        request: Frame,
+        db: &mut D,
+        shared: &Shared,
+    ) -> Result<Frame> {
+        let cmd = match Command::from_frame(request) {
+            Ok(cmd) => cmd,
+            Err(err) => return Ok(Frame::error(ErrorCode::Err, err)),
+        };
+        if cmd.is_blocking() || cmd.is_subscribe() || matches!(cmd, Command::Eval(_)) {
+            let reply = "This command is not allowed from script";
+            return Ok(Frame::error(ErrorCode::Err, reply));
+        }
+        if let Err(denied) = shared.acl.check(self.server.user(), &cmd) {
+            return Ok(error_reply(&denied.into()));
+        }
+        // Boxed, as commands may run scripts in turn. The reply is read while
+        // it is written, lest it fill the pipe.
+        let applied = Box::pin(cmd.apply(&mut self.server, db, shared));
+        let ((), reply) = tokio::try_join!(applied, self.client.read_frame())?;
+        Ok(reply.unwrap_or(Frame::Null))
+    }
+}
+
+fn run(
+    source: &str,
+    keys: Vec<Bytes>,
+    args: Vec<Bytes>,
+    deadline: Instant,
+    calls: mpsc::Sender<Call>,
+) -> mlua::Result<Frame> {
+    let lua = Lua::new();
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(1000),
+        move |_, _| {
+            if Instant::now() < deadline {
+                return Ok(());
+            }
+            Err(mlua::Error::RuntimeError(
+                "Script killed after exceeding the time limit".to_string(),
+            ))
+        },
+    );
+    let globals = lua.globals();
+    let keys = keys
+        .iter()
+        .map(|key| lua.create_string(key))
+        .collect::<mlua::Result<Vec<_>>>()?;
+    globals.set("KEYS", keys)?;
+    let args = args
+        .iter()
+        .map(|arg| lua.create_string(arg))
+        .collect::<mlua::Result<Vec<_>>>()?;
+    globals.set("ARGV", args)?;
+
+    let redis = lua.create_table()?;
+    let pcalls = calls.clone();
+    let raising = lua.create_function(move |lua, args: Variadic<LuaValue>| {
+        let reply = call(&calls, args)?;
+        match reply {
+            Frame::Error(err) => Err(mlua::Error::RuntimeError(err)),
+            reply => to_lua(lua, reply),
+        }
+    })?;
+    redis.set("call", raising)?;
+    // Errors are returned as a table holding them in `err`, rather than raised.
+    let protected = lua.create_function(move |lua, args: Variadic<LuaValue>| {
+        let reply = call(&pcalls, args)?;
+        to_lua(lua, reply)
+    })?;
+    redis.set("pcall", protected)?;
+    globals.set("redis", redis)?;
+
+    let result = lua.load(source).set_name("@user_script").eval()?;
+    Ok(from_lua(result))
+}
+
+/// Sends a `redis.call` to the connection running the script, waits for its
+/// reply.
+fn call(calls: &mpsc::Sender<Call>, args: Variadic<LuaValue>) -> mlua::Result<Frame> {
+    let mut request = vec![];
+    for arg in args.iter() {
+        let arg = match arg {
+            LuaValue::String(string) => Bytes::copy_from_slice(string.as_bytes()),
+            LuaValue::Integer(integer) => Bytes::from(integer.to_string()),
+            LuaValue::Number(number) => Bytes::from(format_double(*number)),
+            _ => Err(mlua::Error::RuntimeError(
+                "Lua redis lib command arguments must be strings or integers".to_string(),
+            ))?,
+        };
+        request.push(Frame::Binary(arg));
+    }
+    if request.is_empty() {
+        Err(mlua::Error::RuntimeError(
+            "Please specify at least one argument for this redis lib call".to_string(),
+        ))?
+    }
+    let (reply, replied) = oneshot::channel();
+    let stopped = || mlua::Error::RuntimeError("The script was stopped".to_string());
+    let request = Frame::Array(request);
+    calls
+        .blocking_send(Call { request, reply })
+        .map_err(|_| stopped())?;
+    replied.blocking_recv().map_err(|_| stopped())
+}
+
+/// Converts a reply as Redis does: a status to a table holding it in `ok`, an
+/// error to one holding it in `err`, and nil to false.
+fn to_lua(lua: &Lua, frame: Frame) -> mlua::Result<LuaValue<'_>> {
+    let value = match frame {
+        Frame::Text(status) => {
+            let table = lua.create_table()?;
+            table.set("ok", status)?;
+            LuaValue::Table(table)
+        }
+        Frame::Error(err) => {
+            let table = lua.create_table()?;
+            table.set("err", err)?;
+            LuaValue::Table(table)
+        }
+        Frame::Binary(binary) => LuaValue::String(lua.create_string(&binary)?),
+        Frame::Integer(integer) => LuaValue::Integer(integer),
+        Frame::Double(double) => LuaValue::Number(double),
+        Frame::Null => LuaValue::Boolean(false),
+        Frame::Array(frames) => {
+            let frames = frames
+                .into_iter()
+                .map(|frame| to_lua(lua, frame))
+                .collect::<mlua::Result<Vec<_>>>()?;
+            LuaValue::Table(lua.create_sequence_from(frames)?)
+        }
+    };
+    Ok(value)
+}
+
+/// Converts the result of a script back, the other way around from
+/// [`to_lua`]. Numbers are truncated to integers, and tables to their
+/// sequence part.
+fn from_lua(value: LuaValue) -> Frame {
+    match value {
+        LuaValue::Boolean(true) => Frame::Integer(1),
+        LuaValue::Integer(integer) => Frame::Integer(integer),
+        LuaValue::Number(number) => Frame::Integer(number as i64),
+        LuaValue::String(string) => Frame::Binary(Bytes::copy_from_slice(string.as_bytes())),
+        LuaValue::Table(table) => {
+            if let Ok(Some(err)) = table.get::<_, Option<String>>("err") {
+                return Frame::Error(err);
+            }
+            if let Ok(Some(status)) = table.get::<_, Option<String>>("ok") {
+                return Frame::Text(status);
+            }
+            let mut frames = vec![];
+            for value in table.sequence_values() {
+                match value.map(from_lua) {
+                    Ok(Frame::Array(_)) => {
+                        let err = "Scripts can't reply with nested arrays";
+                        return Frame::error(ErrorCode::Err, err);
+                    }
+                    Ok(frame) => frames.push(frame),
+                    Err(err) => return Frame::error(ErrorCode::Err, err),
+                }
+            }
+            Frame::Array(frames)
+        }
+        _ => Frame::Null,
+    }
+}
@@ -0,0 +1,186 @@
+//! Request shadowing
+//!
+//! A sample of the commands served is mirrored to a secondary uranus server,
+//! to validate it (say, a new storage engine) with production traffic. Mirroring
+//! never delays the primary: commands are queued to a background task, and
+//! dropped when the queue is full or the secondary is unreachable.
+//!
+//! Mirroring every write makes the secondary an asynchronous replica. Given
+//! the secret of the replica, it is told so by a `REPLCONF PRIMARY` when the
+//! connection is established, see [`ReplConf`], and [`Shadow::wait_acked`]
+//! tells when it has caught up.
+//!
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use tokio::{
+    net::TcpStream,
+    sync::{mpsc, watch},
+    time,
+};
+use tracing::{debug, warn};
+
+use crate::{spawn_named, Command, Connection, Frame, ReplConf};
+
+#[derive(Debug, Clone)]
+pub struct ShadowConfig {
+    /// Address of the secondary server.
+    pub addr: String,
+    /// Share of the eligible commands mirrored, from 0 to 100.
+    pub percentage: u8,
+    /// Mirror writes.
+    pub writes: bool,
+    /// Mirror reads.
+    pub reads: bool,
+    /// Commands waiting to be mirrored at most, further ones are dropped.
+    pub queue_size: usize,
+    /// The [replication secret](crate::ServerConfig::replication_secret) of
+    /// the secondary, announcing this server as its primary.
+    pub secret: Option<String>,
+}
+
+impl ShadowConfig {
+    pub fn new(addr: impl ToString, percentage: u8) -> ShadowConfig {
+        ShadowConfig {
+            addr: addr.to_string(),
+            percentage,
+            writes: true,
+            reads: true,
+            queue_size: 1024,
+            secret: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Shadow {
+    sender: mpsc::Sender<Frame>,
+    config: ShadowConfig,
+    seen: Arc<AtomicU64>,
+    /// The number of commands queued so far.
+    queued: Arc<AtomicU64>,
+    /// The number of commands queued so far when the secondary replied to the
+    /// last one.
+    acked: watch::Receiver<u64>,
+}
+
+impl Shadow {
+    /// Spawns the task forwarding mirrored commands, so it must be called
+    /// within a tokio runtime.
+    pub fn start(config: ShadowConfig) -> Shadow {
+        let (sender, receiver) = mpsc::channel(config.queue_size);
+        let (acks, acked) = watch::channel(0);
+        spawn_named(
+            "shadow forwarder",
+            forward(config.addr.clone(), config.secret.clone(), receiver, acks),
+        );
+        Shadow {
+            sender,
+            config,
+            seen: Arc::new(AtomicU64::new(0)),
+            queued: Arc::new(AtomicU64::new(0)),
+            acked,
+        }
+    }
+
+    /// Decides whether `command` is mirrored. Every command of an eligible kind
+    /// counts, and `percentage` out of each hundred of them are picked.
+    pub fn sample(&self, command: &Command) -> bool {
+        let eligible = if command.is_write() {
+            self.config.writes
+        } else {
+            command.is_read() && self.config.reads
+        };
+        if !eligible {
+            return false;
+        }
+        let n = self.seen.fetch_add(1, Ordering::Relaxed);
+        n % 100 < self.config.percentage as u64
+    }
+
+    /// Queues `frame` for the secondary without waiting.
+    pub fn mirror(&self, frame: Frame) {
+        match self.sender.try_send(frame) {
+            Ok(()) => {
+                self.queued.fetch_add(1, Ordering::SeqCst);
+            }
+            Err(_) => debug!("shadow queue is full, dropped a command"),
+        }
+    }
+
+    /// Waits until the secondary replied to every command queued so far, for
+    /// at most `timeout`, or indefinitely without one. Returns whether it did.
+    /// Commands dropped meanwhile never are, so it times out then.
+    pub async fn wait_acked(&self, timeout: Option<Duration>) -> bool {
+        let queued = self.queued.load(Ordering::SeqCst);
+        let mut acked = self.acked.clone();
+        let caught_up = async move { acked.wait_for(|acked| *acked >= queued).await.is_ok() };
+        match timeout {
+            Some(timeout) => time::timeout(timeout, caught_up).await.unwrap_or(false),
+            None => caught_up.await,
+        }
+    }
+}
+
+async fn forward(
+    addr: String,
+    secret: Option<String>,
+    mut receiver: mpsc::Receiver<Frame>,
+    acks: watch::Sender<u64>,
+) {
+    let mut connection: Option<Connection> = None;
+    let mut received = 0;
+    while let Some(frame) = receiver.recv().await {
+        received += 1;
+        if connection.is_none() {
+            match connect(&addr, secret.as_deref()).await {
+                Ok(conn) => connection = Some(conn),
+                Err(err) => {
+                    warn!(%addr, cause = %err, "can't reach the shadow server");
+                    continue;
+                }
+            }
+        }
+        let Some(conn) = connection.as_mut() else {
+            continue;
+        };
+        let replied = match conn.write_frame(&frame).await {
+            Ok(()) => conn.read_frame().await,
+            Err(err) => Err(err),
+        };
+        match replied {
+            Ok(Some(reply)) => {
+                debug!(?reply, "shadow server replied");
+                acks.send_replace(received);
+            }
+            Ok(None) | Err(_) => {
+                warn!(%addr, "lost the connection to the shadow server");
+                connection = None;
+            }
+        }
+    }
+}
+
+/// Connects to the secondary, announcing this server as its primary if given
+/// its `secret`.
+async fn connect(addr: &str, secret: Option<&str>) -> Result<Connection> {
+    let mut connection = Connection::new(TcpStream::connect(addr).await?);
+    let Some(secret) = secret else {
+        return Ok(connection);
+    };
+    connection
+        .write_frame(&ReplConf::new(secret).into_frame())
+        .await?;
+    match connection.read_frame().await? {
+        Some(Frame::Text(_)) => Ok(connection),
+        Some(reply) => Err(anyhow!("the shadow server refused to replicate: {}", reply)),
+        None => Err(anyhow!("connection reset by the shadow server")),
+    }
+}
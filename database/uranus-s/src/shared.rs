@@ -0,0 +1,88 @@
+//! Server-wide state shared by every connection, besides the database
+//!
+
+use crate::{
+    notify_keyspace, spawn_named, Acl, AppendOnlyFile, BufferSizes, Clients, ClusterState,
+    CommandStats, EventBus, History, IdempotencyCache, LazyFree, Membership, PubSub, Readiness,
+    RuntimeConfig, Scripts, ServerConfig, Shadow, Stats, Tracer, Waiters,
+};
+
+#[derive(Debug, Clone)]
+pub struct Shared {
+    pub idempotency: IdempotencyCache,
+    pub shadow: Option<Shadow>,
+    pub pubsub: PubSub,
+    /// Connections blocked until a key is written, see [`Waiters`].
+    pub waiters: Waiters,
+    /// Revisions of the keys keeping some.
+    pub history: History,
+    /// Whether the traffic of connections is dumped, see [`Tracer`].
+    pub tracer: Tracer,
+    /// The read buffer sizes of the connections.
+    pub buffer_sizes: BufferSizes,
+    /// The connections served.
+    pub clients: Clients,
+    /// The scripts cached, and the lock keeping them atomic.
+    pub scripts: Scripts,
+    /// The users, and what they may run.
+    pub acl: Acl,
+    /// What happens in the server, for the subsystems reacting to it.
+    pub events: EventBus,
+    /// The keyspace counters and samples, see [`crate::stats`].
+    pub stats: Stats,
+    /// The calls of each command, see [`crate::commandstats`].
+    pub commands: CommandStats,
+    /// Drops the large values deleted, see [`crate::lazyfree`].
+    pub lazyfree: LazyFree,
+    /// Where the writes are logged, opened by [`run_with_database`](crate::run_with_database)
+    /// once it is replayed.
+    pub aof: Option<AppendOnlyFile>,
+    /// The tunables changed at runtime by `CONFIG SET`.
+    pub config: RuntimeConfig,
+    /// The slots served by this node in cluster mode.
+    pub cluster: Option<ClusterState>,
+    /// The nodes known by gossip, started by
+    /// [`run_with_database`](crate::run_with_database).
+    pub membership: Option<Membership>,
+    /// Whether the data is loaded, see [`crate::health`].
+    pub readiness: Readiness,
+    /// The secret of the link of the primary, see [`ReplConf`](crate::ReplConf).
+    pub replication_secret: Option<String>,
+}
+
+impl Shared {
+    /// Starts the lazy free task, and the shadowing and keyspace notification
+    /// tasks if they are configured, so it must be called within a tokio runtime.
+    pub fn new(config: &ServerConfig) -> Shared {
+        let pubsub = PubSub::new();
+        let events = EventBus::new();
+        if config.keyspace_events.is_enabled() {
+            spawn_named(
+                "keyspace notifications",
+                notify_keyspace(events.subscribe(), pubsub.clone(), config.keyspace_events),
+            );
+        }
+        Shared {
+            idempotency: IdempotencyCache::new(config.idempotency_ttl, config.idempotency_capacity),
+            shadow: config.shadow.clone().map(Shadow::start),
+            pubsub,
+            waiters: Waiters::new(),
+            history: History::new(config.max_history_depth),
+            tracer: Tracer::new(config.trace.clone()),
+            buffer_sizes: BufferSizes::new(config.buffers),
+            clients: Clients::new(),
+            scripts: Scripts::new(config.script_time_limit),
+            acl: Acl::new(&config.acl),
+            events,
+            stats: Stats::new(config.stats),
+            commands: CommandStats::new(),
+            lazyfree: LazyFree::new(config.lazyfree),
+            aof: None,
+            config: RuntimeConfig::new(config),
+            cluster: config.cluster.clone().map(ClusterState::new),
+            membership: None,
+            readiness: Readiness::new(),
+            replication_secret: config.replication_secret.clone(),
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Hash slots partitioning the keyspace among the nodes of a cluster
+//!
+//! A key belongs to one of [`SLOTS`] slots, the CRC16 of the key modulo the
+//! number of slots. Only the part between the first `{` and the next `}` is
+//! hashed if it isn't empty, so that keys sharing such a hash tag, like
+//! `{user:1}:name` and `{user:1}:email`, are served by the same node.
+//!
+
+use std::ops::RangeInclusive;
+
+/// The number of hash slots.
+pub const SLOTS: u16 = 16384;
+
+/// CRC16-CCITT (XMODEM) of `data`.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// The hash slot of `key`.
+pub fn key_slot(key: &[u8]) -> u16 {
+    let tag = key.iter().position(|&b| b == b'{').and_then(|open| {
+        let rest = &key[open + 1..];
+        let close = rest.iter().position(|&b| b == b'}')?;
+        (close > 0).then(|| &rest[..close])
+    });
+    crc16(tag.unwrap_or(key)) % SLOTS
+}
+
+/// Which node serves each slot, nodes being known by the address clients
+/// reach them at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topology {
+    nodes: Vec<String>,
+    /// The index in `nodes` of the owner of each slot.
+    owners: Vec<Option<usize>>,
+}
+
+impl Default for Topology {
+    fn default() -> Self {
+        Topology {
+            nodes: vec![],
+            owners: vec![None; SLOTS as usize],
+        }
+    }
+}
+
+impl Topology {
+    /// A topology where no node serves any slot.
+    pub fn new() -> Topology {
+        Topology::default()
+    }
+
+    /// Splits the slots over `addrs` in contiguous ranges of about the same size.
+    pub fn even<I>(addrs: I) -> Topology
+    where
+        I: IntoIterator,
+        I::Item: ToString,
+    {
+        let addrs: Vec<String> = addrs.into_iter().map(|addr| addr.to_string()).collect();
+        let mut topology = Topology::new();
+        let count = addrs.len();
+        for (i, addr) in addrs.iter().enumerate() {
+            let start = SLOTS as usize * i / count;
+            let end = SLOTS as usize * (i + 1) / count;
+            if start < end {
+                topology.assign(start as u16..=(end - 1) as u16, addr);
+            }
+        }
+        topology
+    }
+
+    /// Makes `addr` serve `slots`.
+    pub fn assign(&mut self, slots: RangeInclusive<u16>, addr: &str) {
+        let node = match self.nodes.iter().position(|node| node == addr) {
+            Some(node) => node,
+            None => {
+                self.nodes.push(addr.to_string());
+                self.nodes.len() - 1
+            }
+        };
+        for slot in slots.filter(|&slot| slot < SLOTS) {
+            self.owners[slot as usize] = Some(node);
+        }
+    }
+
+    /// The address of the node serving `slot`, none if the slot isn't served.
+    pub fn owner(&self, slot: u16) -> Option<&str> {
+        let node = (*self.owners.get(slot as usize)?)?;
+        Some(&self.nodes[node])
+    }
+
+    /// The contiguous ranges of slots served by the same node, in slot order.
+    pub fn ranges(&self) -> Vec<(RangeInclusive<u16>, &str)> {
+        let mut ranges: Vec<(RangeInclusive<u16>, &str)> = vec![];
+        for slot in 0..SLOTS {
+            let Some(owner) = self.owner(slot) else {
+                continue;
+            };
+            match ranges.last_mut() {
+                Some((range, last)) if *last == owner && *range.end() + 1 == slot => {
+                    *range = *range.start()..=slot;
+                }
+                _ => ranges.push((slot..=slot, owner)),
+            }
+        }
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_slot() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"bar"), 5061);
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+        assert_eq!(
+            key_slot(b"{user1000}.following"),
+            key_slot(b"{user1000}.followers")
+        );
+        // Empty tags hash the whole key.
+        assert_eq!(key_slot(b"foo{}{bar}"), crc16(b"foo{}{bar}") % SLOTS);
+        assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+    }
+
+    #[test]
+    fn test_topology() {
+        let mut topology = Topology::even(["a", "b", "c"]);
+        assert_eq!(topology.owner(0), Some("a"));
+        assert_eq!(topology.owner(5461), Some("b"));
+        assert_eq!(topology.owner(SLOTS - 1), Some("c"));
+        assert_eq!(topology.ranges().len(), 3);
+
+        topology.assign(100..=199, "c");
+        let ranges = topology.ranges();
+        assert_eq!(ranges[0], (0..=99, "a"));
+        assert_eq!(ranges[1], (100..=199, "c"));
+        assert_eq!(ranges[2], (200..=5460, "a"));
+        assert_eq!(Topology::new().owner(0), None);
+    }
+}
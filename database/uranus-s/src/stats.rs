@@ -0,0 +1,205 @@
+//! Keyspace statistics sampling
+//!
+//! Read commands count their lookups as keyspace hits or misses on their
+//! connection, which handlers add up here, and [`sample_stats`] counts the
+//! expired keys off the [event bus](crate::events).
+//! Every [`StatsConfig::interval`] it also records the number of keys along
+//! with these counters and the evicted keys in a ring buffer of the last
+//! [`StatsConfig::samples`], which `INFO stats` reports. Operators see how the
+//! keyspace trends over the last minutes without polling the server every
+//! second.
+//!
+//! Expired keys are counted best effort, like any event: those announced while
+//! the sampler lags more than the bus buffers are missed.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use tokio::{
+    sync::broadcast::{error::RecvError, Receiver},
+    time,
+};
+use tracing::warn;
+
+use crate::{Database, ServerEvent};
+
+#[derive(Debug, Clone, Copy)]
+pub struct StatsConfig {
+    /// How often the keyspace is sampled.
+    pub interval: Duration,
+    /// How many samples are kept, 0 to not sample at all.
+    pub samples: usize,
+}
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_SAMPLES: usize = 60;
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        StatsConfig {
+            interval: DEFAULT_INTERVAL,
+            samples: DEFAULT_SAMPLES,
+        }
+    }
+}
+
+/// The state of the keyspace at some point. The counters are totals since the
+/// server started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatsSample {
+    pub taken_at: SystemTime,
+    /// The number of keys of all logical databases.
+    pub keys: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub expired: u64,
+    pub evicted: u64,
+}
+
+/// The keyspace counters and the last samples, shared by all connections.
+#[derive(Debug, Clone)]
+pub struct Stats {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: StatsConfig,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    expired: AtomicU64,
+    /// The oldest sample first.
+    samples: Mutex<VecDeque<StatsSample>>,
+}
+
+impl Stats {
+    pub fn new(config: StatsConfig) -> Stats {
+        Stats {
+            inner: Arc::new(Inner {
+                config,
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+                expired: AtomicU64::new(0),
+                samples: Mutex::new(VecDeque::with_capacity(config.samples)),
+            }),
+        }
+    }
+
+    pub fn config(&self) -> StatsConfig {
+        self.inner.config
+    }
+
+    /// Counts a lookup of a key by a read command, which found it if `hit`.
+    pub fn record_lookup(&self, hit: bool) {
+        let counter = if hit {
+            &self.inner.hits
+        } else {
+            &self.inner.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts `hits` lookups which found their key and `misses` which didn't.
+    pub fn record_lookups(&self, hits: u64, misses: u64) {
+        self.inner.hits.fetch_add(hits, Ordering::Relaxed);
+        self.inner.misses.fetch_add(misses, Ordering::Relaxed);
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.inner.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.inner.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn expired(&self) -> u64 {
+        self.inner.expired.load(Ordering::Relaxed)
+    }
+
+    /// The samples kept, the oldest first.
+    pub fn samples(&self) -> Vec<StatsSample> {
+        self.inner.samples.lock().unwrap().iter().copied().collect()
+    }
+
+    fn record(&self, sample: StatsSample) {
+        let mut samples = self.inner.samples.lock().unwrap();
+        if samples.len() == self.inner.config.samples {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    fn sample<D: Database>(&self, db: &D) -> anyhow::Result<StatsSample> {
+        let mut keys = 0;
+        for index in 0..db.databases() {
+            if let Some(db) = db.select(index) {
+                keys += db.len()?;
+            }
+        }
+        Ok(StatsSample {
+            taken_at: SystemTime::now(),
+            keys,
+            hits: self.hits(),
+            misses: self.misses(),
+            expired: self.expired(),
+            evicted: db.memory_limit().map_or(0, |limit| limit.evicted()),
+        })
+    }
+}
+
+/// Counts the expired keys announced on `events`, and samples `db` into
+/// `stats` periodically.
+pub async fn sample_stats<D: Database>(stats: Stats, db: D, mut events: Receiver<ServerEvent>) {
+    let config = stats.config();
+    let mut ticks = time::interval(config.interval);
+    loop {
+        tokio::select! {
+            _ = ticks.tick(), if config.samples > 0 => match stats.sample(&db) {
+                Ok(sample) => stats.record(sample),
+                Err(err) => warn!(cause = %err, "failed to sample the keyspace"),
+            },
+            event = events.recv() => match event {
+                Ok(ServerEvent::KeyExpired { .. }) => {
+                    stats.inner.expired.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer() {
+        let stats = Stats::new(StatsConfig {
+            interval: DEFAULT_INTERVAL,
+            samples: 3,
+        });
+        for keys in 0..5 {
+            stats.record_lookup(keys % 2 == 0);
+            stats.record(StatsSample {
+                taken_at: SystemTime::now(),
+                keys,
+                hits: stats.hits(),
+                misses: stats.misses(),
+                expired: 0,
+                evicted: 0,
+            });
+        }
+        let samples = stats.samples();
+        let keys: Vec<usize> = samples.iter().map(|sample| sample.keys).collect();
+        assert_eq!(keys, [2, 3, 4]);
+        assert_eq!((samples[2].hits, samples[2].misses), (3, 2));
+    }
+}
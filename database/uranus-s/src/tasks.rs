@@ -0,0 +1,47 @@
+//! Naming the tasks of the server, for tokio-console
+//!
+//! The server spawns its tasks with [`spawn_named`]: connection handlers are
+//! named after the address of their peer, as `connection 127.0.0.1:50312`,
+//! and background tasks after their role, as `expiry sweep`. Built with the
+//! `console` feature and `RUSTFLAGS="--cfg tokio_unstable"`, as tokio requires
+//! to instrument its tasks, the server serves them to
+//! [tokio-console](https://github.com/tokio-rs/console) on port 6669, see
+//! [`console_layer`], which shows where a stuck handler waits, or which tasks
+//! pile up.
+//!
+//! Without `tokio_unstable`, tasks have no names and are spawned as usual.
+
+use std::future::Future;
+
+use tokio::task::JoinHandle;
+
+/// Spawns `future` as a task named `name`, see the [module documentation](self).
+pub fn spawn_named<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(tokio_unstable)]
+    {
+        tokio::task::Builder::new()
+            .name(name)
+            .spawn(future)
+            .expect("a task can be spawned within the runtime")
+    }
+    #[cfg(not(tokio_unstable))]
+    {
+        let _ = name;
+        tokio::spawn(future)
+    }
+}
+
+/// The layer serving the tasks to tokio-console, with the settings of its
+/// `TOKIO_CONSOLE_*` environment variables. It has to be installed before the
+/// runtime spawns anything.
+#[cfg(feature = "console")]
+pub fn console_layer<S>() -> impl tracing_subscriber::Layer<S>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    console_subscriber::spawn()
+}
@@ -0,0 +1,124 @@
+//! Servers for tests, without ports
+//!
+//! A [`TestServer`] serves its database to connections made in-process over
+//! [`tokio::io::duplex`] pipes, so that tests don't need a TCP port, and run
+//! in parallel without stepping on each other:
+//!
+//! ```ignore
+//! let server = TestServer::start();
+//! let mut client = uranus_c::Client::with_connection(server.connect());
+//! client.set("key", "value").await?;
+//! ```
+//!
+//! Each connection is given an address of its own, as `127.0.0.1:1`, which
+//! `CLIENT LIST` shows.
+
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::atomic::{AtomicU16, Ordering},
+};
+
+use tokio::{
+    io::{duplex, DuplexStream},
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+use crate::{serve, spawn_named, Connection, DBHandle, Incoming, ServerConfig};
+
+/// The size of the pipes of the connections, in bytes, each way.
+const PIPE_SIZE: usize = 64 * 1024;
+
+/// A server run in the background of the current runtime, until dropped.
+#[derive(Debug)]
+pub struct TestServer {
+    connections: mpsc::UnboundedSender<(DuplexStream, SocketAddr)>,
+    db: DBHandle,
+    /// The port of the address of the next connection.
+    next_port: AtomicU16,
+    task: JoinHandle<()>,
+}
+
+impl TestServer {
+    /// A server with the default config, see [`TestServer::with_config`].
+    pub fn start() -> TestServer {
+        TestServer::with_config(ServerConfig::default())
+    }
+
+    /// A server with `config`, on an in-memory database. It must be started
+    /// within a tokio runtime.
+    pub fn with_config(config: ServerConfig) -> TestServer {
+        let db = DBHandle::with_databases(config.databases, config.shards)
+            .limit_memory(config.max_memory, config.eviction_policy)
+            .compress_values(config.compression, config.compression_threshold);
+        TestServer::with_database(config, db)
+    }
+
+    /// A server with `config` on `db`.
+    pub fn with_database(config: ServerConfig, db: DBHandle) -> TestServer {
+        let (connections, incoming) = mpsc::unbounded_channel();
+        let no_port = Err(std::io::Error::other("the server has no port"));
+        let server = serve(Incoming::Channel(incoming), no_port, config, db.clone());
+        TestServer {
+            connections,
+            db,
+            next_port: AtomicU16::new(1),
+            task: spawn_named("test server", server),
+        }
+    }
+
+    /// A new connection to the server.
+    pub fn connect(&self) -> Connection {
+        Connection::new(self.pipe())
+    }
+
+    /// A new connection to the server, whose end of the pipe fails as
+    /// `faults` say. Those of the server's end are
+    /// [`ServerConfig::faults`](crate::ServerConfig::faults).
+    #[cfg(feature = "faults")]
+    pub fn connect_with_faults(&self, faults: crate::Faults) -> Connection {
+        Connection::with_faults(self.pipe(), faults)
+    }
+
+    /// The client end of a new pipe to the server.
+    fn pipe(&self) -> DuplexStream {
+        let (client, server) = duplex(PIPE_SIZE);
+        let port = self.next_port.fetch_add(1, Ordering::Relaxed);
+        let peer = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        // The server only stops once dropped.
+        let _ = self.connections.send((server, peer));
+        client
+    }
+
+    /// The database served, to inspect it without going through a connection.
+    pub fn db(&self) -> &DBHandle {
+        &self.db
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Frame;
+
+    #[tokio::test]
+    async fn test_in_process_server() {
+        let server = TestServer::start();
+        let mut connections = [server.connect(), server.connect()];
+        for (i, connection) in connections.iter_mut().enumerate() {
+            let ping = Frame::Array(vec![
+                Frame::Binary("ping".into()),
+                Frame::Binary(i.to_string().into()),
+            ]);
+            connection.write_frame(&ping).await.unwrap();
+            let reply = connection.read_frame().await.unwrap();
+            assert_eq!(reply, Some(Frame::Binary(i.to_string().into())));
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Protocol trace mode, dumping the raw bytes of connections to files
+//!
+//! While tracing is on, each connection appends what it reads and writes to its
+//! own file in [`TraceConfig::dir`], one record per line:
+//!
+//! ```text
+//! 1697452800.123456 < 23 *2\r\n$3\r\nget\r\n$3\r\nkey\r\n
+//! 1697452800.123502 > 9 $3\r\nval\r\n
+//! ```
+//!
+//! That is the time in seconds since the epoch, `<` for inbound or `>` for
+//! outbound, the number of bytes, and the bytes with CR, LF, backslashes and
+//! non-printable ones escaped. Files are rotated when they grow past
+//! [`TraceConfig::max_file_size`]. Tracing is toggled at runtime by `DEBUG TRACE ON|OFF`.
+
+use std::{
+    fmt::Write as _,
+    fs::{self, File, OpenOptions},
+    io::Write as _,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tracing::warn;
+
+#[derive(Debug, Clone)]
+pub struct TraceConfig {
+    /// Whether connections are traced from the start.
+    pub enabled: bool,
+    /// Where the trace files are written.
+    pub dir: PathBuf,
+    /// A trace file is rotated once it is this large.
+    pub max_file_size: u64,
+    /// How many files a connection keeps, the current one included.
+    pub max_files: usize,
+}
+
+const DEFAULT_MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+const DEFAULT_MAX_FILES: usize = 4;
+
+impl Default for TraceConfig {
+    fn default() -> Self {
+        TraceConfig {
+            enabled: false,
+            dir: std::env::temp_dir().join("uranus-trace"),
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            max_files: DEFAULT_MAX_FILES,
+        }
+    }
+}
+
+/// Switches tracing for the whole server, and hands out a [`ConnectionTrace`]
+/// to each connection.
+#[derive(Debug, Clone)]
+pub struct Tracer {
+    config: Arc<TraceConfig>,
+    enabled: Arc<AtomicBool>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Tracer {
+    pub fn new(config: TraceConfig) -> Tracer {
+        Tracer {
+            enabled: Arc::new(AtomicBool::new(config.enabled)),
+            config: Arc::new(config),
+            next_id: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Takes effect on every connection, from their next read or write.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// The trace of a new connection from `peer`. Its file is only created once
+    /// something is recorded.
+    pub fn connection(&self, peer: SocketAddr) -> ConnectionTrace {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let peer = peer.to_string().replace([':', '[', ']'], "_");
+        ConnectionTrace {
+            tracer: self.clone(),
+            path: self.config.dir.join(format!("conn-{}-{}.trace", id, peer)),
+            file: None,
+            size: 0,
+            outbound: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// The trace file of one connection.
+#[derive(Debug)]
+pub struct ConnectionTrace {
+    tracer: Tracer,
+    path: PathBuf,
+    file: Option<File>,
+    size: u64,
+    /// The bytes of the frame being written, recorded once it is flushed.
+    outbound: Vec<u8>,
+}
+
+impl ConnectionTrace {
+    /// Appends a record of `bytes`, if tracing is on. Failing to write the
+    /// trace is logged, it doesn't affect the connection.
+    pub fn record(&mut self, direction: Direction, bytes: &[u8]) {
+        if !self.tracer.is_enabled() {
+            self.file = None;
+            return;
+        }
+        let line = format_record(SystemTime::now(), direction, bytes);
+        if let Err(err) = self.append(line.as_bytes()) {
+            warn!(cause = %err, path = %self.path.display(), "failed to write the trace");
+            self.file = None;
+        }
+    }
+
+    /// Buffers bytes being written, see [`ConnectionTrace::flush_outbound`].
+    pub(crate) fn capture(&mut self, bytes: &[u8]) {
+        if self.tracer.is_enabled() {
+            self.outbound.extend_from_slice(bytes);
+        }
+    }
+
+    /// Records the bytes captured since the last call as one outbound record.
+    pub(crate) fn flush_outbound(&mut self) {
+        if !self.outbound.is_empty() {
+            let outbound = std::mem::take(&mut self.outbound);
+            self.record(Direction::Outbound, &outbound);
+        }
+    }
+
+    fn append(&mut self, line: &[u8]) -> std::io::Result<()> {
+        let config = &self.tracer.config;
+        if self.file.is_some() && self.size + line.len() as u64 > config.max_file_size {
+            self.file = None;
+            self.rotate()?;
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                fs::create_dir_all(&config.dir)?;
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?;
+                self.size = file.metadata()?.len();
+                self.file.insert(file)
+            }
+        };
+        file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shifts `path.1` to `path.2` and so on, dropping the oldest, then moves
+    /// the current file to `path.1`.
+    fn rotate(&self) -> std::io::Result<()> {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        let kept = self.tracer.config.max_files.max(1) - 1;
+        if kept == 0 {
+            return fs::remove_file(&self.path);
+        }
+        for n in (1..kept).rev() {
+            if rotated(n).exists() {
+                fs::rename(rotated(n), rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated(1))
+    }
+}
+
+fn format_record(at: SystemTime, direction: Direction, bytes: &[u8]) -> String {
+    let since_epoch = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let direction = match direction {
+        Direction::Inbound => '<',
+        Direction::Outbound => '>',
+    };
+    let mut line = format!(
+        "{}.{:06} {} {} ",
+        since_epoch.as_secs(),
+        since_epoch.subsec_micros(),
+        direction,
+        bytes.len()
+    );
+    for &byte in bytes {
+        match byte {
+            b'\r' => line.push_str("\\r"),
+            b'\n' => line.push_str("\\n"),
+            b'\\' => line.push_str("\\\\"),
+            0x20..=0x7e => line.push(byte as char),
+            _ => write!(line, "\\x{:02x}", byte).unwrap(),
+        }
+    }
+    line.push('\n');
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_format_record() {
+        let at = UNIX_EPOCH + Duration::from_micros(1_500_000);
+        assert_eq!(
+            format_record(at, Direction::Inbound, b"+OK\r\n\x00\\"),
+            "1.500000 < 7 +OK\\r\\n\\x00\\\\\n"
+        );
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir =
+            std::env::temp_dir().join(format!("uranus-trace-rotation-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let tracer = Tracer::new(TraceConfig {
+            enabled: true,
+            dir: dir.clone(),
+            max_file_size: 64,
+            max_files: 3,
+        });
+        let mut trace = tracer.connection("127.0.0.1:4242".parse().unwrap());
+        for _ in 0..10 {
+            trace.record(Direction::Inbound, b"*1\r\n$4\r\nping\r\n");
+        }
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                "conn-0-127.0.0.1_4242.trace",
+                "conn-0-127.0.0.1_4242.trace.1",
+                "conn-0-127.0.0.1_4242.trace.2"
+            ]
+        );
+
+        tracer.set_enabled(false);
+        let size = fs::metadata(dir.join(&files[0])).unwrap().len();
+        trace.record(Direction::Outbound, b"+PONG\r\n");
+        assert_eq!(fs::metadata(dir.join(&files[0])).unwrap().len(), size);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
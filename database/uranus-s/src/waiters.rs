@@ -0,0 +1,105 @@
+//! Connections blocked until a key is written
+//!
+//! A blocking command registers as a waiter on its key, then waits for a wake
+//! up before looking at the key again. Writers wake one waiter per element they
+//! add, first come first served, while other connections keep being served.
+//! Writes which take nothing away, such as `XADD`, wake every waiter instead.
+//!
+
+use std::{
+    collections::HashMap,
+    future::{self, Future},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::Poll,
+};
+
+use bytes::Bytes;
+use tokio::sync::{futures::Notified, Notify};
+
+#[derive(Debug, Clone, Default)]
+pub struct Waiters {
+    keys: Arc<Mutex<HashMap<Bytes, Arc<Notify>>>>,
+}
+
+impl Waiters {
+    pub fn new() -> Waiters {
+        Waiters::default()
+    }
+
+    /// Registers a waiter on `key`, until the returned [`Waiter`] is dropped.
+    pub fn register(&self, key: Bytes) -> Waiter {
+        let notify = self
+            .keys
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        Waiter {
+            waiters: self.clone(),
+            key,
+            notify,
+        }
+    }
+
+    /// Wakes up to `count` waiters on `key`.
+    pub fn wake(&self, key: &Bytes, count: usize) {
+        if let Some(notify) = self.keys.lock().unwrap().get(key) {
+            for _ in 0..count {
+                notify.notify_one();
+            }
+        }
+    }
+
+    /// Wakes every waiter listening on `key`.
+    pub fn wake_all(&self, key: &Bytes) {
+        if let Some(notify) = self.keys.lock().unwrap().get(key) {
+            notify.notify_waiters();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Waiter {
+    waiters: Waiters,
+    key: Bytes,
+    notify: Arc<Notify>,
+}
+
+impl Waiter {
+    /// Starts listening for a wake up. Call it before looking at the key, so
+    /// that a write in between isn't missed, and await it if the key had
+    /// nothing to take.
+    pub fn listen(&self) -> Pin<Box<Notified<'_>>> {
+        let mut notified = Box::pin(self.notify.notified());
+        notified.as_mut().enable();
+        notified
+    }
+}
+
+/// Completes once any of `listening` does, for commands waiting on several
+/// keys. See [`Waiter::listen`].
+pub async fn wake_any(mut listening: Vec<Pin<Box<Notified<'_>>>>) {
+    future::poll_fn(|cx| {
+        let woken = listening
+            .iter_mut()
+            .any(|notified| notified.as_mut().poll(cx).is_ready());
+        if woken {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        let mut keys = self.waiters.keys.lock().unwrap();
+        // The map holds the last reference besides ours once nobody else waits.
+        if Arc::strong_count(&self.notify) == 2 {
+            keys.remove(&self.key);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Connections from the router to one uranus server
+//!
+
+use anyhow::Result;
+use tracing::info;
+use uranus_c::{Pool, PoolConfig, PooledClient};
+
+#[derive(Debug, Clone)]
+pub struct BackendConfig {
+    /// Connections opened and health-checked at startup, before the first request.
+    pub min_pool_size: usize,
+    /// Connections open at most to one backend.
+    pub max_pool_size: usize,
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        BackendConfig {
+            min_pool_size: 1,
+            max_pool_size: 16,
+        }
+    }
+}
+
+/// A uranus server behind the router, with a pool of connections to it.
+pub struct Backend {
+    pool: Pool,
+}
+
+impl Backend {
+    pub fn new(addr: impl ToString, config: BackendConfig) -> Backend {
+        let config = PoolConfig {
+            max_size: config.max_pool_size,
+            min_idle: config.min_pool_size,
+        };
+        Backend {
+            pool: Pool::new(addr, config),
+        }
+    }
+
+    pub fn addr(&self) -> &str {
+        self.pool.addr()
+    }
+
+    pub fn idle_connections(&self) -> usize {
+        self.pool.idle_connections()
+    }
+
+    /// Fills the pool up to `min_pool_size` healthy connections, so that requests
+    /// right after startup don't pay for connecting.
+    pub async fn warm_up(&self) -> Result<()> {
+        self.pool.warm_up().await?;
+        info!(addr = %self.addr(), connections = self.idle_connections(), "backend warmed up");
+        Ok(())
+    }
+
+    /// Takes a connection from the pool, it goes back when dropped.
+    pub async fn checkout(&self) -> Result<PooledClient> {
+        self.pool.get().await
+    }
+}
@@ -1,9 +1,84 @@
+//! Uranus router: spreads the keyspace over several uranus servers.
+//!
+//! Keys are partitioned by [hash slot](uranus_s::slots), the same way as
+//! servers in [cluster mode](uranus_s::cluster) do, so the servers behind a
+//! router can check and redirect the requests they are sent. Routers can agree
+//! on the slot assignments and config of the cluster with [`raft`].
+
+use anyhow::Result;
+use thiserror::Error;
+use uranus_s::{key_slot, Topology};
+
+pub mod backend;
+pub use backend::*;
+
+pub mod metadata;
+pub use metadata::*;
+
+pub mod raft;
+pub use raft::*;
+
 pub fn add(left: usize, right: usize) -> usize {
     left + right
 }
 
-#[derive(Debug)]
-struct Router {}
+#[derive(Debug, Error)]
+pub enum RouterError {
+    #[error("a router needs at least one backend")]
+    NoBackends,
+}
+
+/// [`Router`] decides which [`Backend`] serves a key.
+pub struct Router {
+    backends: Vec<Backend>,
+    /// Which backend serves each slot, by address.
+    topology: Topology,
+}
+
+impl Router {
+    /// Creates a router over the servers at `addrs`, splitting the slots evenly
+    /// among them in order, see [`Topology::even`], and warms up the connection
+    /// pool of every one of them. Fails if any backend is unreachable or unhealthy,
+    /// so a bad deploy is noticed at startup rather than on the first request.
+    pub async fn connect<I>(addrs: I, config: BackendConfig) -> Result<Router>
+    where
+        I: IntoIterator,
+        I::Item: ToString,
+    {
+        let backends: Vec<Backend> = addrs
+            .into_iter()
+            .map(|addr| Backend::new(addr, config.clone()))
+            .collect();
+        if backends.is_empty() {
+            Err(RouterError::NoBackends)?
+        }
+        for backend in &backends {
+            backend.warm_up().await?;
+        }
+        let topology = Topology::even(backends.iter().map(Backend::addr));
+        Ok(Router { backends, topology })
+    }
+
+    pub fn backends(&self) -> &[Backend] {
+        &self.backends
+    }
+
+    pub fn topology(&self) -> &Topology {
+        &self.topology
+    }
+
+    /// The backend owning `key`.
+    pub fn backend(&self, key: &[u8]) -> &Backend {
+        let owner = self
+            .topology
+            .owner(key_slot(key))
+            .expect("every slot is assigned");
+        self.backends
+            .iter()
+            .find(|backend| backend.addr() == owner)
+            .expect("the topology only has backends")
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,78 @@
+//! Cluster metadata, kept consistent among routers by [`crate::raft`]
+//!
+
+use std::{collections::BTreeMap, ops::RangeInclusive};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use uranus_s::{Frame, Topology};
+
+/// A change to the [`Metadata`], committed by Raft before it is applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Makes the node at `addr` serve `slots`.
+    Assign {
+        slots: RangeInclusive<u16>,
+        addr: String,
+    },
+    /// Sets the config parameter `name`.
+    SetConfig { name: String, value: String },
+}
+
+impl Change {
+    pub(crate) fn frames(&self) -> Vec<Frame> {
+        match self {
+            Change::Assign { slots, addr } => vec![
+                Frame::Text("assign".to_string()),
+                Frame::Integer(*slots.start() as i64),
+                Frame::Integer(*slots.end() as i64),
+                Frame::Binary(Bytes::from(addr.clone())),
+            ],
+            Change::SetConfig { name, value } => vec![
+                Frame::Text("config".to_string()),
+                Frame::Binary(Bytes::from(name.clone())),
+                Frame::Binary(Bytes::from(value.clone())),
+            ],
+        }
+    }
+
+    pub(crate) fn parse(frames: &[Frame]) -> Result<Change> {
+        let string = |frame: &Frame| match frame {
+            Frame::Binary(bytes) => Ok(String::from_utf8(bytes.to_vec())?),
+            _ => Err(anyhow!("malformed change")),
+        };
+        match frames {
+            [Frame::Text(kind), Frame::Integer(start), Frame::Integer(end), addr]
+                if kind == "assign" =>
+            {
+                Ok(Change::Assign {
+                    slots: u16::try_from(*start)?..=u16::try_from(*end)?,
+                    addr: string(addr)?,
+                })
+            }
+            [Frame::Text(kind), name, value] if kind == "config" => Ok(Change::SetConfig {
+                name: string(name)?,
+                value: string(value)?,
+            }),
+            _ => Err(anyhow!("malformed change")),
+        }
+    }
+}
+
+/// The slot assignments and config shared by the cluster.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub topology: Topology,
+    pub config: BTreeMap<String, String>,
+}
+
+impl Metadata {
+    pub fn apply(&mut self, change: &Change) {
+        match change {
+            Change::Assign { slots, addr } => self.topology.assign(slots.clone(), addr),
+            Change::SetConfig { name, value } => {
+                self.config.insert(name.clone(), value.clone());
+            }
+        }
+    }
+}
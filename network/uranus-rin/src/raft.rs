@@ -0,0 +1,667 @@
+//! Raft consensus on the cluster [`Metadata`]
+//!
+//! Routers run a Raft node each, so that slot assignments and config changes
+//! are agreed on by a majority of them and survive the failure of any
+//! minority. Changes are [proposed](Raft::propose) to the leader, which
+//! appends them to its log and replicates it to the followers. Once a
+//! majority stored an entry, it is committed and every node applies it to
+//! its copy of the metadata.
+//!
+//! Followers which don't hear from a leader within their election timeout,
+//! randomized so that they seldom run at once, become candidates and ask the
+//! others for their vote. Nodes talk over the frame protocol of the servers:
+//!
+//! - `RAFT VOTE term candidate last-index last-term`, replied with the term of
+//!   the node and whether it voted for the candidate.
+//! - `RAFT APPEND term leader prev-index prev-term commit entries...`, replied
+//!   with the term of the node, whether its log matched `prev-index` and the
+//!   index of its last entry.
+//!
+//! The log, the term and the vote are persisted before the node replies.
+//!
+
+mod log;
+
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use thiserror::Error;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{watch, Notify},
+    task::JoinSet,
+    time,
+};
+use tracing::{debug, info, warn};
+use uranus_s::{Connection, Frame};
+
+use self::log::{Entry, RaftLog};
+use crate::{Change, Metadata};
+
+/// A leader sends this many entries per request at most.
+const MAX_BATCH: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct RaftConfig {
+    /// The address of the node, which identifies it among its peers.
+    pub addr: String,
+    /// The addresses of the other nodes.
+    pub peers: Vec<String>,
+    /// Where the log, the term and the vote are persisted.
+    pub dir: PathBuf,
+    /// Followers which don't hear from a leader for a random time between
+    /// this and twice this start an election.
+    pub election_timeout: Duration,
+    /// How often a leader sends its log to its followers, if only to tell
+    /// them it is alive.
+    pub heartbeat_interval: Duration,
+}
+
+impl RaftConfig {
+    pub fn new<I>(addr: impl ToString, peers: I, dir: impl Into<PathBuf>) -> RaftConfig
+    where
+        I: IntoIterator,
+        I::Item: ToString,
+    {
+        RaftConfig {
+            addr: addr.to_string(),
+            peers: peers.into_iter().map(|peer| peer.to_string()).collect(),
+            dir: dir.into(),
+            election_timeout: Duration::from_millis(300),
+            heartbeat_interval: Duration::from_millis(50),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+#[derive(Debug, Error)]
+pub enum RaftError {
+    /// Changes are proposed to the leader, at this address if it is known.
+    #[error("this node isn't the leader, the leader is {0:?}")]
+    NotLeader(Option<String>),
+    #[error("the change was dropped by a new leader")]
+    Dropped,
+}
+
+/// A running Raft node, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct Raft {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: RaftConfig,
+    state: Mutex<State>,
+    /// The commit index, for proposals waiting for theirs.
+    committed: watch::Sender<u64>,
+    /// Wakes the replication of a leader up when entries are appended.
+    appended: Notify,
+    /// Every task of the node, aborted by [`Raft::shutdown`].
+    tasks: Mutex<JoinSet<()>>,
+}
+
+#[derive(Debug)]
+struct State {
+    log: RaftLog,
+    role: Role,
+    leader: Option<String>,
+    /// The index of the latest entry known to be committed.
+    commit_index: u64,
+    /// The index of the latest entry applied to `metadata`.
+    last_applied: u64,
+    metadata: Metadata,
+    /// The leader sends each follower its log from this index on.
+    next_index: HashMap<String, u64>,
+    /// The leader knows each follower stored its log up to this index.
+    match_index: HashMap<String, u64>,
+    /// When this node last heard from a leader, or voted.
+    last_heard: Instant,
+    election_timeout: Duration,
+}
+
+impl Raft {
+    /// Starts the node, serving its peers on `listener`, so it must be called
+    /// within a tokio runtime. The node starts as a follower of whichever
+    /// leader it hears from.
+    pub async fn start(config: RaftConfig, listener: TcpListener) -> Result<Raft> {
+        let log = RaftLog::open(&config.dir)?;
+        info!(
+            addr = %config.addr,
+            term = log.term(),
+            entries = log.last_index(),
+            "raft started"
+        );
+        let state = State {
+            log,
+            role: Role::Follower,
+            leader: None,
+            commit_index: 0,
+            last_applied: 0,
+            metadata: Metadata::default(),
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            last_heard: Instant::now(),
+            election_timeout: random_timeout(config.election_timeout),
+        };
+        let raft = Raft {
+            inner: Arc::new(Inner {
+                config,
+                state: Mutex::new(state),
+                committed: watch::channel(0).0,
+                appended: Notify::new(),
+                tasks: Mutex::new(JoinSet::new()),
+            }),
+        };
+        raft.spawn(raft.clone().listen(listener));
+        raft.spawn(raft.clone().elect());
+        for peer in raft.inner.config.peers.clone() {
+            raft.spawn(raft.clone().replicate(peer));
+        }
+        Ok(raft)
+    }
+
+    /// Stops the node, closing its port and its connections. Its tasks hold
+    /// on to it, so dropping it doesn't.
+    pub fn shutdown(&self) {
+        self.inner.tasks.lock().unwrap().abort_all();
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.inner.config.addr
+    }
+
+    pub fn role(&self) -> Role {
+        self.inner.state.lock().unwrap().role
+    }
+
+    pub fn term(&self) -> u64 {
+        self.inner.state.lock().unwrap().log.term()
+    }
+
+    /// The address of the leader this node knows of.
+    pub fn leader(&self) -> Option<String> {
+        self.inner.state.lock().unwrap().leader.clone()
+    }
+
+    /// The metadata made of the entries committed so far.
+    pub fn metadata(&self) -> Metadata {
+        self.inner.state.lock().unwrap().metadata.clone()
+    }
+
+    /// Appends `change` to the log of the leader, and waits until it is
+    /// committed and applied here.
+    pub async fn propose(&self, change: Change) -> Result<()> {
+        let (index, term) = {
+            let mut state = self.inner.state.lock().unwrap();
+            if state.role != Role::Leader {
+                Err(RaftError::NotLeader(state.leader.clone()))?
+            }
+            let term = state.log.term();
+            state.log.append(&[Entry {
+                term,
+                change: Some(change),
+            }])?;
+            let index = state.log.last_index();
+            self.advance_commit(&mut state);
+            (index, term)
+        };
+        self.inner.appended.notify_waiters();
+
+        let mut committed = self.inner.committed.subscribe();
+        committed.wait_for(|commit| *commit >= index).await?;
+        let state = self.inner.state.lock().unwrap();
+        if state.log.term_at(index) != Some(term) {
+            Err(RaftError::Dropped)?
+        }
+        Ok(())
+    }
+
+    /// Spawns `task` among those of the node. The finished ones, as those of
+    /// closed connections, are reaped meanwhile, so they don't pile up.
+    fn spawn<F>(&self, task: F)
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let mut tasks = self.inner.tasks.lock().unwrap();
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(task);
+    }
+
+    /// Serves the requests of the peers.
+    async fn listen(self, listener: TcpListener) {
+        loop {
+            let socket = match listener.accept().await {
+                Ok((socket, _)) => socket,
+                Err(err) => {
+                    warn!(cause = %err, "failed to accept a peer");
+                    continue;
+                }
+            };
+            let raft = self.clone();
+            self.spawn(async move {
+                let mut connection = Connection::new(socket);
+                loop {
+                    let reply = match connection.read_frame().await {
+                        Ok(Some(request)) => raft.handle(request),
+                        Ok(None) => return,
+                        Err(err) => Err(err),
+                    };
+                    let reply = match reply {
+                        Ok(reply) => reply,
+                        Err(err) => {
+                            debug!(cause = %err, "failed to serve a peer");
+                            return;
+                        }
+                    };
+                    if connection.write_frame(&reply).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    }
+
+    fn handle(&self, request: Frame) -> Result<Frame> {
+        let malformed = || anyhow!("malformed raft request");
+        let Frame::Array(frames) = request else {
+            return Err(malformed());
+        };
+        let mut args = frames.into_iter();
+        if !matches!(args.next(), Some(Frame::Text(name)) if name == "raft") {
+            return Err(malformed());
+        }
+        let kind = text(args.next()).ok_or_else(malformed)?;
+        let term = integer(args.next()).ok_or_else(malformed)?;
+        let node = text(args.next()).ok_or_else(malformed)?;
+        match kind.as_str() {
+            "vote" => {
+                let last_index = integer(args.next()).ok_or_else(malformed)?;
+                let last_term = integer(args.next()).ok_or_else(malformed)?;
+                self.handle_vote(term, node, last_index, last_term)
+            }
+            "append" => {
+                let prev_index = integer(args.next()).ok_or_else(malformed)?;
+                let prev_term = integer(args.next()).ok_or_else(malformed)?;
+                let commit = integer(args.next()).ok_or_else(malformed)?;
+                let entries = args
+                    .map(|frame| match frame {
+                        Frame::Binary(data) => Entry::decode(data),
+                        _ => Err(malformed()),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                self.handle_append(term, node, prev_index, prev_term, commit, entries)
+            }
+            _ => Err(malformed()),
+        }
+    }
+
+    fn handle_vote(
+        &self,
+        term: u64,
+        candidate: String,
+        last_index: u64,
+        last_term: u64,
+    ) -> Result<Frame> {
+        let mut state = self.inner.state.lock().unwrap();
+        if term > state.log.term() {
+            self.step_down(&mut state, term)?;
+        }
+        let up_to_date = (last_term, last_index) >= (state.log.last_term(), state.log.last_index());
+        let free = !matches!(state.log.voted_for(), Some(vote) if vote != candidate);
+        let granted = term == state.log.term() && up_to_date && free;
+        if granted {
+            debug!(term, %candidate, "voted");
+            state.log.set_state(term, Some(candidate))?;
+            state.last_heard = Instant::now();
+        }
+        Ok(reply(&[state.log.term(), granted as u64]))
+    }
+
+    fn handle_append(
+        &self,
+        term: u64,
+        leader: String,
+        prev_index: u64,
+        prev_term: u64,
+        commit: u64,
+        entries: Vec<Entry>,
+    ) -> Result<Frame> {
+        let mut state = self.inner.state.lock().unwrap();
+        if term < state.log.term() {
+            return Ok(reply(&[state.log.term(), 0, state.log.last_index()]));
+        }
+        if term > state.log.term() || state.role != Role::Follower {
+            self.step_down(&mut state, term)?;
+        }
+        state.leader = Some(leader);
+        state.last_heard = Instant::now();
+        if state.log.term_at(prev_index) != Some(prev_term) {
+            return Ok(reply(&[term, 0, state.log.last_index()]));
+        }
+
+        let last = prev_index + entries.len() as u64;
+        let mut index = prev_index;
+        for (i, entry) in entries.iter().enumerate() {
+            index += 1;
+            match state.log.term_at(index) {
+                Some(existing) if existing == entry.term => continue,
+                Some(_) => state.log.truncate(index)?,
+                None => {}
+            }
+            state.log.append(&entries[i..])?;
+            break;
+        }
+        if commit.min(last) > state.commit_index {
+            state.commit_index = commit.min(last);
+            self.apply(&mut state);
+        }
+        Ok(reply(&[term, 1, last]))
+    }
+
+    /// Becomes a follower in `term`, a later one than this node has seen or
+    /// the one of a leader it heard from.
+    fn step_down(&self, state: &mut State, term: u64) -> Result<()> {
+        if state.role != Role::Follower {
+            info!(term, "stepped down");
+        }
+        state.role = Role::Follower;
+        if term > state.log.term() {
+            state.log.set_state(term, None)?;
+            state.leader = None;
+        }
+        Ok(())
+    }
+
+    /// Starts an election whenever the election timeout elapses without a
+    /// leader.
+    async fn elect(self) {
+        let tick = self.inner.config.heartbeat_interval / 2;
+        loop {
+            time::sleep(tick).await;
+            let (term, vote) = {
+                let mut state = self.inner.state.lock().unwrap();
+                let timed_out = state.last_heard.elapsed() >= state.election_timeout;
+                if state.role == Role::Leader || !timed_out {
+                    continue;
+                }
+                let term = state.log.term() + 1;
+                if let Err(err) = state.log.set_state(term, Some(self.addr().to_string())) {
+                    warn!(cause = %err, "failed to persist the vote");
+                    continue;
+                }
+                info!(term, "started an election");
+                state.role = Role::Candidate;
+                state.leader = None;
+                state.last_heard = Instant::now();
+                state.election_timeout = random_timeout(self.inner.config.election_timeout);
+                let last = [state.log.last_index(), state.log.last_term()];
+                (
+                    term,
+                    Frame::Array(request("vote", term, self.addr(), &last)),
+                )
+            };
+            self.campaign(term, vote).await;
+        }
+    }
+
+    /// Asks the peers for their vote in `term`, and becomes the leader once a
+    /// majority granted it.
+    async fn campaign(&self, term: u64, request: Frame) {
+        let mut votes = 1;
+        let mut calls = JoinSet::new();
+        for peer in self.inner.config.peers.clone() {
+            let request = request.clone();
+            let timeout = self.inner.config.election_timeout;
+            calls.spawn(async move {
+                let mut connection = None;
+                time::timeout(timeout, call(&mut connection, &peer, &request)).await
+            });
+        }
+        loop {
+            {
+                let mut state = self.inner.state.lock().unwrap();
+                if state.role != Role::Candidate || state.log.term() != term {
+                    return;
+                }
+                if votes >= self.majority() {
+                    self.lead(&mut state);
+                    return;
+                }
+            }
+            let Some(reply) = calls.join_next().await else {
+                return;
+            };
+            let Ok(Ok(Ok(reply))) = reply else {
+                continue;
+            };
+            let Some([peer_term, granted]) = parse_reply::<2>(reply) else {
+                continue;
+            };
+            if peer_term > term {
+                let mut state = self.inner.state.lock().unwrap();
+                if let Err(err) = self.step_down(&mut state, peer_term) {
+                    warn!(cause = %err, "failed to persist the term");
+                }
+                return;
+            }
+            votes += granted as usize;
+        }
+    }
+
+    fn lead(&self, state: &mut State) {
+        info!(term = state.log.term(), "elected leader");
+        state.role = Role::Leader;
+        state.leader = Some(self.addr().to_string());
+        let next = state.log.last_index() + 1;
+        for peer in &self.inner.config.peers {
+            state.next_index.insert(peer.clone(), next);
+            state.match_index.insert(peer.clone(), 0);
+        }
+        // Entries of the previous terms only commit along with one of this term.
+        let noop = Entry {
+            term: state.log.term(),
+            change: None,
+        };
+        if let Err(err) = state.log.append(&[noop]) {
+            warn!(cause = %err, "failed to append to the raft log");
+        }
+        self.advance_commit(state);
+        self.inner.appended.notify_waiters();
+    }
+
+    /// Sends the log to `peer` while this node leads.
+    async fn replicate(self, peer: String) {
+        let mut connection = None;
+        let mut ticks = time::interval(self.inner.config.heartbeat_interval);
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => {}
+                _ = self.inner.appended.notified() => {}
+            }
+            let Some((term, request)) = self.append_request(&peer) else {
+                continue;
+            };
+            let timeout = self.inner.config.election_timeout;
+            let reply = match time::timeout(timeout, call(&mut connection, &peer, &request)).await {
+                Ok(Ok(reply)) => reply,
+                Ok(Err(err)) => {
+                    debug!(%peer, cause = %err, "failed to replicate");
+                    connection = None;
+                    continue;
+                }
+                Err(_) => {
+                    connection = None;
+                    continue;
+                }
+            };
+            if let Some([peer_term, success, last]) = parse_reply::<3>(reply) {
+                self.handle_append_reply(&peer, term, peer_term, success == 1, last);
+            }
+        }
+    }
+
+    /// The entries `peer` is missing, none if this node doesn't lead.
+    fn append_request(&self, peer: &str) -> Option<(u64, Frame)> {
+        let state = self.inner.state.lock().unwrap();
+        if state.role != Role::Leader {
+            return None;
+        }
+        let term = state.log.term();
+        let next = state.next_index.get(peer).copied().unwrap_or(1);
+        let prev_index = next - 1;
+        let prev_term = state.log.term_at(prev_index).unwrap_or(0);
+        let args = [prev_index, prev_term, state.commit_index];
+        let mut frames = request("append", term, self.addr(), &args);
+        for entry in state.log.entries(next, MAX_BATCH) {
+            match entry.encode() {
+                Ok(data) => frames.push(Frame::Binary(data)),
+                Err(err) => {
+                    warn!(cause = %err, "failed to encode a log entry");
+                    return None;
+                }
+            }
+        }
+        Some((term, Frame::Array(frames)))
+    }
+
+    fn handle_append_reply(&self, peer: &str, term: u64, peer_term: u64, success: bool, last: u64) {
+        let mut state = self.inner.state.lock().unwrap();
+        if peer_term > state.log.term() {
+            if let Err(err) = self.step_down(&mut state, peer_term) {
+                warn!(cause = %err, "failed to persist the term");
+            }
+            return;
+        }
+        if state.role != Role::Leader || state.log.term() != term {
+            return;
+        }
+        if success {
+            let matched = state.match_index.entry(peer.to_string()).or_default();
+            *matched = (*matched).max(last);
+            state.next_index.insert(peer.to_string(), last + 1);
+            self.advance_commit(&mut state);
+        } else {
+            // Back off until the logs match, skipping what the peer lacks.
+            let next = state.next_index.entry(peer.to_string()).or_insert(1);
+            *next = (*next - 1).min(last + 1).max(1);
+        }
+    }
+
+    /// Commits the latest entry of the current term a majority stored.
+    fn advance_commit(&self, state: &mut State) {
+        let term = state.log.term();
+        for index in (state.commit_index + 1..=state.log.last_index()).rev() {
+            if state.log.term_at(index) != Some(term) {
+                break;
+            }
+            let stored = 1 + state
+                .match_index
+                .values()
+                .filter(|&&matched| matched >= index)
+                .count();
+            if stored >= self.majority() {
+                state.commit_index = index;
+                self.apply(state);
+                break;
+            }
+        }
+    }
+
+    fn apply(&self, state: &mut State) {
+        while state.last_applied < state.commit_index {
+            state.last_applied += 1;
+            let entry = state.log.entry(state.last_applied).cloned();
+            if let Some(change) = entry.and_then(|entry| entry.change) {
+                debug!(?change, "applied");
+                state.metadata.apply(&change);
+            }
+        }
+        self.inner.committed.send_replace(state.commit_index);
+    }
+
+    fn majority(&self) -> usize {
+        self.inner.config.peers.len().div_ceil(2) + 1
+    }
+}
+
+/// A duration between `base` and twice `base`.
+fn random_timeout(base: Duration) -> Duration {
+    let jitter = RandomState::new().build_hasher().finish();
+    let jitter = jitter % (base.as_millis() as u64).max(1);
+    base + Duration::from_millis(jitter)
+}
+
+fn request(kind: &str, term: u64, node: &str, args: &[u64]) -> Vec<Frame> {
+    let mut frames = vec![
+        Frame::Text("raft".to_string()),
+        Frame::Text(kind.to_string()),
+        Frame::Integer(term as i64),
+        Frame::Binary(Bytes::from(node.to_string())),
+    ];
+    frames.extend(args.iter().map(|&arg| Frame::Integer(arg as i64)));
+    frames
+}
+
+fn reply(fields: &[u64]) -> Frame {
+    Frame::Array(
+        fields
+            .iter()
+            .map(|&field| Frame::Integer(field as i64))
+            .collect(),
+    )
+}
+
+fn parse_reply<const N: usize>(reply: Frame) -> Option<[u64; N]> {
+    let Frame::Array(frames) = reply else {
+        return None;
+    };
+    let fields: Vec<u64> = frames
+        .into_iter()
+        .map(|frame| integer(Some(frame)))
+        .collect::<Option<_>>()?;
+    fields.try_into().ok()
+}
+
+fn text(frame: Option<Frame>) -> Option<String> {
+    match frame? {
+        Frame::Text(text) => Some(text),
+        Frame::Binary(data) => String::from_utf8(data.to_vec()).ok(),
+        _ => None,
+    }
+}
+
+fn integer(frame: Option<Frame>) -> Option<u64> {
+    match frame? {
+        Frame::Integer(val) => u64::try_from(val).ok(),
+        _ => None,
+    }
+}
+
+/// Sends `request` to `peer` on `connection`, connecting first if it isn't.
+async fn call(connection: &mut Option<Connection>, peer: &str, request: &Frame) -> Result<Frame> {
+    if connection.is_none() {
+        *connection = Some(Connection::new(TcpStream::connect(peer).await?));
+    }
+    let Some(connection) = connection.as_mut() else {
+        unreachable!()
+    };
+    connection.write_frame(request).await?;
+    connection
+        .read_frame()
+        .await?
+        .ok_or_else(|| anyhow!("the peer closed the connection"))
+}
@@ -0,0 +1,238 @@
+//! The persistent state of a Raft node: its log, its term and its vote
+//!
+//! Entries are appended to `<dir>/log` as frames, and synced before the node
+//! acknowledges them. The term and the vote are written to `<dir>/state`,
+//! which is replaced at once.
+//!
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Cursor, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use bytes::{Bytes, BytesMut};
+use tracing::warn;
+use uranus_s::{Frame, FrameLimits, Protocol};
+
+use crate::Change;
+
+const LOG: &str = "log";
+const STATE: &str = "state";
+
+/// An entry of the log, at the index of its position from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Entry {
+    /// The term of the leader which appended it.
+    pub term: u64,
+    /// None for the entry a new leader appends, which commits the entries of
+    /// the previous terms along with it.
+    pub change: Option<Change>,
+}
+
+impl Entry {
+    pub fn encode(&self) -> Result<Bytes> {
+        let mut frames = vec![Frame::Integer(self.term as i64)];
+        frames.extend(self.change.iter().flat_map(Change::frames));
+        let mut data = BytesMut::new();
+        Frame::Array(frames).encode(Protocol::V2, &mut data)?;
+        Ok(data.freeze())
+    }
+
+    pub fn decode(mut data: Bytes) -> Result<Entry> {
+        match Frame::parse(&mut data)? {
+            Some(Frame::Array(frames)) => match frames.split_first() {
+                Some((Frame::Integer(term), [])) => Ok(Entry {
+                    term: *term as u64,
+                    change: None,
+                }),
+                Some((Frame::Integer(term), change)) => Ok(Entry {
+                    term: *term as u64,
+                    change: Some(Change::parse(change)?),
+                }),
+                _ => Err(anyhow!("malformed log entry")),
+            },
+            _ => Err(anyhow!("malformed log entry")),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct RaftLog {
+    dir: PathBuf,
+    file: File,
+    entries: Vec<Entry>,
+    /// The latest term the node has seen.
+    term: u64,
+    /// The candidate the node voted for in `term`.
+    voted_for: Option<String>,
+}
+
+impl RaftLog {
+    /// Loads the state persisted in `dir`, creating it if need be. An entry
+    /// cut short by a crash is dropped, it was never acknowledged.
+    pub fn open(dir: &Path) -> Result<RaftLog> {
+        fs::create_dir_all(dir)?;
+        let (term, voted_for) = match fs::read_to_string(dir.join(STATE)) {
+            Ok(state) => {
+                let (term, voted_for) = state.split_once('\n').unwrap_or((&state, ""));
+                let voted_for = voted_for.trim_end();
+                let voted_for = (!voted_for.is_empty()).then(|| voted_for.to_string());
+                (term.parse()?, voted_for)
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => (0, None),
+            Err(err) => Err(err)?,
+        };
+
+        let path = dir.join(LOG);
+        let mut data = match fs::read(&path) {
+            Ok(data) => Bytes::from(data),
+            Err(err) if err.kind() == ErrorKind::NotFound => Bytes::new(),
+            Err(err) => Err(err)?,
+        };
+        let len = data.len();
+        let mut entries = vec![];
+        let mut size = 0;
+        loop {
+            let mut cursor = Cursor::new(&data[..]);
+            if Frame::check(&mut cursor, &FrameLimits::default())?.is_none() {
+                break;
+            }
+            let entry = data.split_to(cursor.position() as usize);
+            size += entry.len();
+            entries.push(Entry::decode(entry)?);
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        if size < len {
+            warn!(size, len, "truncating an incomplete entry of the raft log");
+            file.set_len(size as u64)?;
+        }
+        Ok(RaftLog {
+            dir: dir.to_path_buf(),
+            file,
+            entries,
+            term,
+            voted_for,
+        })
+    }
+
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    pub fn voted_for(&self) -> Option<&str> {
+        self.voted_for.as_deref()
+    }
+
+    pub fn set_state(&mut self, term: u64, voted_for: Option<String>) -> Result<()> {
+        let state = format!("{}\n{}\n", term, voted_for.as_deref().unwrap_or(""));
+        let path = self.dir.join(format!("{}.tmp", STATE));
+        let mut file = File::create(&path)?;
+        file.write_all(state.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&path, self.dir.join(STATE))?;
+        self.term = term;
+        self.voted_for = voted_for;
+        Ok(())
+    }
+
+    pub fn last_index(&self) -> u64 {
+        self.entries.len() as u64
+    }
+
+    pub fn last_term(&self) -> u64 {
+        self.entries.last().map_or(0, |entry| entry.term)
+    }
+
+    /// The term of the entry at `index`, 0 before the first one.
+    pub fn term_at(&self, index: u64) -> Option<u64> {
+        match index {
+            0 => Some(0),
+            _ => self.entry(index).map(|entry| entry.term),
+        }
+    }
+
+    pub fn entry(&self, index: u64) -> Option<&Entry> {
+        let index = index.checked_sub(1)?;
+        self.entries.get(index as usize)
+    }
+
+    /// At most `max` entries from `index` on.
+    pub fn entries(&self, index: u64, max: usize) -> &[Entry] {
+        let start = (index.max(1) - 1) as usize;
+        let start = start.min(self.entries.len());
+        let end = (start + max).min(self.entries.len());
+        &self.entries[start..end]
+    }
+
+    pub fn append(&mut self, entries: &[Entry]) -> Result<()> {
+        let mut data = BytesMut::new();
+        for entry in entries {
+            data.extend_from_slice(&entry.encode()?);
+        }
+        self.file.write_all(&data)?;
+        self.file.sync_data()?;
+        self.entries.extend_from_slice(entries);
+        Ok(())
+    }
+
+    /// Drops the entries from `index` on, which conflict with the leader.
+    pub fn truncate(&mut self, index: u64) -> Result<()> {
+        self.entries.truncate((index.max(1) - 1) as usize);
+        let mut data = BytesMut::new();
+        for entry in &self.entries {
+            data.extend_from_slice(&entry.encode()?);
+        }
+        let path = self.dir.join(format!("{}.tmp", LOG));
+        let mut file = File::create(&path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(&path, self.dir.join(LOG))?;
+        self.file = OpenOptions::new().append(true).open(self.dir.join(LOG))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raft_log() {
+        let dir = std::env::temp_dir().join(format!("uranus-raft-log-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let assign = |term, addr: &str| Entry {
+            term,
+            change: Some(Change::Assign {
+                slots: 0..=99,
+                addr: addr.to_string(),
+            }),
+        };
+
+        let mut log = RaftLog::open(&dir).unwrap();
+        assert_eq!((log.term(), log.last_index()), (0, 0));
+        log.set_state(2, Some("a".to_string())).unwrap();
+        let noop = Entry {
+            term: 1,
+            change: None,
+        };
+        log.append(&[noop.clone(), assign(1, "a"), assign(2, "b")])
+            .unwrap();
+        log.truncate(3).unwrap();
+        log.append(&[assign(2, "c")]).unwrap();
+        drop(log);
+
+        // A crash left half an entry behind.
+        let mut file = OpenOptions::new().append(true).open(dir.join(LOG)).unwrap();
+        file.write_all(&assign(2, "d").encode().unwrap()[..10])
+            .unwrap();
+        let log = RaftLog::open(&dir).unwrap();
+        assert_eq!((log.term(), log.voted_for()), (2, Some("a")));
+        assert_eq!(log.entries(1, 10), [noop, assign(1, "a"), assign(2, "c")]);
+        assert_eq!((log.last_index(), log.last_term()), (3, 2));
+        assert_eq!(log.term_at(0), Some(0));
+        assert_eq!(log.term_at(4), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
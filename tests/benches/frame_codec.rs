@@ -0,0 +1,43 @@
+//! Decoding frames: `Frame::check`, run on every read until a frame has fully
+//! arrived, and `Frame::parse`, run once it has. Bulk strings from
+//! `ZERO_COPY_MIN` bytes on are sliced instead of copied, so parsing them
+//! doesn't grow with their size.
+
+use std::io::Cursor;
+
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use support::{array_frame, bulk_frame, encode, Rng};
+use uranus_s::{Frame, FrameLimits};
+
+fn corpus() -> Vec<(String, Bytes)> {
+    let mut rng = Rng::new(0);
+    let mut corpus = vec![];
+    for len in [16, 1024, 64 * 1024] {
+        let frame = array_frame(&mut rng, len);
+        corpus.push((format!("array/{}", len), encode(&frame).into()));
+    }
+    for len in [64, 16 * 1024, 1024 * 1024] {
+        let frame = bulk_frame(&mut rng, len);
+        corpus.push((format!("bulk/{}", len), encode(&frame).into()));
+    }
+    corpus
+}
+
+fn frame_codec(c: &mut Criterion) {
+    let limits = FrameLimits::default();
+    let mut group = c.benchmark_group("frame");
+    for (name, bytes) in corpus() {
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::new("check", &name), &bytes, |b, bytes| {
+            b.iter(|| Frame::check(&mut Cursor::new(&bytes[..]), &limits).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("parse", &name), &bytes, |b, bytes| {
+            b.iter(|| Frame::parse(&mut bytes.clone()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, frame_codec);
+criterion_main!(benches);
@@ -0,0 +1,59 @@
+//! Put and get throughput of the storage engines.
+//!
+//! The memtable doesn't implement [`Storage`] yet, add it to [`ENGINES`] once
+//! it does.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use support::{keys, value, Rng};
+use uranus_kv::{sharded::ShardedKV, StdHashKV, Storage, Value};
+
+const KEYS: usize = 10_000;
+const VALUE_LEN: usize = 64;
+
+type Engine = fn() -> Box<dyn Storage>;
+
+const ENGINES: [(&str, Engine); 2] = [
+    ("StdHashKV", || Box::new(StdHashKV::new())),
+    ("ShardedKV", || Box::new(ShardedKV::new(16))),
+];
+
+fn storage(c: &mut Criterion) {
+    let mut rng = Rng::new(0);
+    let keys = keys(KEYS);
+    let values: Vec<Value> = keys.iter().map(|_| value(&mut rng, VALUE_LEN)).collect();
+
+    let mut group = c.benchmark_group("storage");
+    group.throughput(Throughput::Elements(KEYS as u64));
+    for (name, engine) in ENGINES {
+        group.bench_function(BenchmarkId::new("put", name), |b| {
+            b.iter_batched(
+                engine,
+                |mut storage| {
+                    for (key, value) in keys.iter().zip(&values) {
+                        storage.put(key.clone(), value.clone()).unwrap();
+                    }
+                    storage
+                },
+                BatchSize::SmallInput,
+            )
+        });
+
+        let mut storage = engine();
+        for (key, value) in keys.iter().zip(&values) {
+            storage.put(key.clone(), value.clone()).unwrap();
+        }
+        group.bench_function(BenchmarkId::new("get", name), |b| {
+            b.iter(|| {
+                for key in &keys {
+                    black_box(storage.get(key.clone()).unwrap());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, storage);
+criterion_main!(benches);
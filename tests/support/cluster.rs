@@ -0,0 +1,262 @@
+//! Several servers in one process, for tests of multi-node setups.
+//!
+//! Each [`Node`] serves its own [`DBHandle`] on an ephemeral port, from a
+//! runtime of its own on a dedicated thread, so that killing it drops its
+//! listener, its connections and its background tasks at once, the way a
+//! crashed process would.
+//!
+//! Servers are linked by request shadowing, see [`uranus_s::shadow`]: a node
+//! mirroring its writes to another keeps it as an asynchronous replica, which
+//! [`ClusterBuilder::replicate`] sets up. They can also share the keyspace in
+//! cluster mode, see [`ClusterBuilder::sharded`], and learn about each other by
+//! gossip, see [`ClusterBuilder::gossip`].
+
+use std::{
+    net::{SocketAddr, TcpListener},
+    thread,
+    time::Duration,
+};
+
+use bytes::Bytes;
+use tokio::{runtime, sync::oneshot};
+use uranus_c::Client;
+use uranus_s::{
+    ClusterConfig, DBHandle, Database, GossipConfig, ServerConfig, ShadowConfig, Topology, Value,
+};
+
+const REPLICATION_QUEUE_SIZE: usize = 64 * 1024;
+const REPLICATION_SECRET: &str = "replication secret";
+pub const GOSSIP_INTERVAL: Duration = Duration::from_millis(20);
+pub const FAIL_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// A server running in the background until killed or dropped.
+pub struct Node {
+    addr: SocketAddr,
+    db: DBHandle,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Node {
+    /// Starts a server with `config` on an ephemeral port.
+    pub fn start(config: ServerConfig) -> Node {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        Node::serve(listener, config)
+    }
+
+    /// Starts a server with `config` on `addr`, say to restart a killed node.
+    pub fn start_at(addr: SocketAddr, config: ServerConfig) -> Node {
+        let listener = TcpListener::bind(addr).unwrap();
+        Node::serve(listener, config)
+    }
+
+    fn serve(listener: TcpListener, config: ServerConfig) -> Node {
+        let addr = listener.local_addr().unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let db = DBHandle::open(config.databases, config.shards, &config.storage)
+            .unwrap()
+            .limit_memory(config.max_memory, config.eviction_policy);
+        let served = db.clone();
+        let (shutdown, stopped) = oneshot::channel();
+        let thread = thread::spawn(move || {
+            let runtime = runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                tokio::select! {
+                    _ = uranus_s::run_with_database(listener, config, served) => {}
+                    _ = stopped => {}
+                }
+            });
+            // Dropping the runtime cancels every task of the node.
+        });
+        Node {
+            addr,
+            db,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The storage of the node, to inspect it without going through a client.
+    pub fn db(&self) -> &DBHandle {
+        &self.db
+    }
+
+    /// A client of the node, once it has loaded its data.
+    pub async fn client(&self) -> Client {
+        let mut client = Client::connect(self.addr).await.unwrap();
+        while client.ready().await.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        client
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.thread.is_some()
+    }
+
+    /// Stops the node, closing its port and the connections of its clients.
+    pub fn kill(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+/// Describes the nodes of a [`Cluster`] and how they are linked.
+#[derive(Default)]
+pub struct ClusterBuilder {
+    configs: Vec<ServerConfig>,
+    replicas: Vec<(usize, usize)>,
+    sharded: bool,
+    gossip: bool,
+}
+
+impl ClusterBuilder {
+    /// Adds a node served with `config`. Nodes are numbered in order from 0.
+    pub fn node(mut self, config: ServerConfig) -> ClusterBuilder {
+        self.configs.push(config);
+        self
+    }
+
+    /// Adds `count` nodes with the default config.
+    pub fn nodes(mut self, count: usize) -> ClusterBuilder {
+        self.configs
+            .extend((0..count).map(|_| ServerConfig::default()));
+        self
+    }
+
+    /// Makes `primary` mirror all its writes to `replica`, which refuses writes
+    /// from its other clients. A node has one replica at most, but replicas can
+    /// have their own, forming a chain.
+    pub fn replicate(mut self, primary: usize, replica: usize) -> ClusterBuilder {
+        self.replicas.push((primary, replica));
+        self
+    }
+
+    /// Serves every node in cluster mode, splitting the slots evenly among
+    /// them in order, see [`Topology::even`].
+    pub fn sharded(mut self) -> ClusterBuilder {
+        self.sharded = true;
+        self
+    }
+
+    /// Makes every node gossip, joining through node 0. Nodes are named
+    /// `node-<n>`, and deemed failed after [`FAIL_TIMEOUT`].
+    pub fn gossip(mut self) -> ClusterBuilder {
+        self.gossip = true;
+        self
+    }
+
+    pub fn start(mut self) -> Cluster {
+        // Ports are taken first, so that nodes can be pointed at the later ones.
+        let listeners: Vec<TcpListener> = self
+            .configs
+            .iter()
+            .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let addrs: Vec<String> = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap().to_string())
+            .collect();
+        if self.sharded {
+            let topology = Topology::even(&addrs);
+            for (config, addr) in self.configs.iter_mut().zip(&addrs) {
+                config.cluster = Some(ClusterConfig::new(addr, topology.clone()));
+            }
+        }
+        if self.gossip {
+            // Nodes bind their gossip port themselves, so free ones are found
+            // by binding them briefly.
+            let gossip_addrs: Vec<String> = self
+                .configs
+                .iter()
+                .map(|_| {
+                    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                    listener.local_addr().unwrap().to_string()
+                })
+                .collect();
+            for (i, config) in self.configs.iter_mut().enumerate() {
+                let seeds = gossip_addrs.iter().take(1).filter(|_| i > 0);
+                let mut gossip = GossipConfig::new(&gossip_addrs[i], seeds);
+                gossip.node_id = format!("node-{}", i);
+                gossip.interval = GOSSIP_INTERVAL;
+                gossip.fail_timeout = FAIL_TIMEOUT;
+                config.gossip = Some(gossip);
+            }
+        }
+        for (primary, replica) in self.replicas {
+            let mut shadow = ShadowConfig::new(&addrs[replica], 100);
+            shadow.reads = false;
+            // Tests write in bursts, which a replica must not miss.
+            shadow.queue_size = REPLICATION_QUEUE_SIZE;
+            shadow.secret = Some(REPLICATION_SECRET.to_string());
+            self.configs[primary].shadow = Some(shadow);
+            self.configs[replica].replica_read_only = true;
+            self.configs[replica].replication_secret = Some(REPLICATION_SECRET.to_string());
+        }
+        let nodes = listeners
+            .into_iter()
+            .zip(self.configs)
+            .map(|(listener, config)| Node::serve(listener, config))
+            .collect();
+        Cluster { nodes }
+    }
+}
+
+pub struct Cluster {
+    nodes: Vec<Node>,
+}
+
+impl Cluster {
+    pub fn builder() -> ClusterBuilder {
+        ClusterBuilder::default()
+    }
+
+    pub fn node(&self, index: usize) -> &Node {
+        &self.nodes[index]
+    }
+
+    pub fn kill(&mut self, index: usize) {
+        self.nodes[index].kill();
+    }
+
+    /// Waits until `replica` holds the same data as `primary`, panicking after
+    /// `timeout`. Replication is asynchronous, so a test has to wait for it
+    /// before failing over.
+    pub async fn wait_replicated(&self, primary: usize, replica: usize, timeout: Duration) {
+        let converged = async {
+            loop {
+                if snapshot(self.node(primary).db()) == snapshot(self.node(replica).db()) {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        };
+        if tokio::time::timeout(timeout, converged).await.is_err() {
+            panic!("node {} didn't catch up with node {}", replica, primary);
+        }
+    }
+}
+
+fn snapshot(db: &DBHandle) -> Vec<(Bytes, Value)> {
+    let mut pairs = db.scan().unwrap();
+    pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    pairs
+}
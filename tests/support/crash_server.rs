@@ -0,0 +1,56 @@
+//! A server for `test_crash`, which runs it as a child process to kill it.
+//!
+//! `crash_server lsm <dir> <always|everysec|no>` keeps the keys on disk in
+//! `dir`, with a memtable small enough that the workload flushes and merges
+//! tables, while `crash_server aof <dir>` keeps them in memory and logs the
+//! writes to `dir/appendonly.aof`, rewriting it past a few kilobytes.
+//!
+//! It listens to an ephemeral port and prints `listening on <addr>` once bound.
+
+use std::{io::Write, path::Path, sync::Arc};
+
+use anyhow::{anyhow, bail, Result};
+use tokio::net::TcpListener;
+use uranus_kv::{LsmConfig, WalSyncPolicy};
+use uranus_s::{AofConfig, DBHandle, ServerConfig};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (mode, dir) = match &args[..] {
+        [mode, dir, ..] => (mode.as_str(), dir),
+        _ => bail!("usage: crash_server <lsm|aof> <dir> [wal-fsync]"),
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let mut config = ServerConfig::default();
+    let db = match mode {
+        "lsm" => {
+            let policy = args
+                .get(2)
+                .ok_or_else(|| anyhow!("lsm needs a wal-fsync"))?;
+            let lsm = LsmConfig {
+                memtable_size: 16 << 10,
+                max_tables: 4,
+                background_merges: true,
+                block_size: 1 << 10,
+                wal_sync: Arc::new(WalSyncPolicy::new(policy.parse()?)),
+                ..LsmConfig::new(dir)
+            };
+            DBHandle::with_lsm(config.databases, &lsm)?
+        }
+        "aof" => {
+            std::fs::create_dir_all(dir)?;
+            config.aof = Some(AofConfig {
+                rewrite_min_size: 16 << 10,
+                ..AofConfig::new(Path::new(dir).join("appendonly.aof"))
+            });
+            DBHandle::with_databases(config.databases, config.shards)
+        }
+        _ => bail!("unknown mode '{}', expected lsm or aof", mode),
+    };
+    println!("listening on {}", addr);
+    std::io::stdout().flush()?;
+    uranus_s::run_with_database(listener, config, db).await;
+    Ok(())
+}
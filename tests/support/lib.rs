@@ -0,0 +1,107 @@
+//! Helpers shared by the integration tests and the benchmarks: a reproducible
+//! random source, generators of representative inputs, and in-process clusters
+//! in [`cluster`].
+
+pub mod cluster;
+
+use bytes::Bytes;
+use uranus_kv::Value;
+use uranus_s::{format_double, Frame};
+
+/// xorshift64, enough to draw reproducible operations without a dependency.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+
+    /// `len` random bytes, CR and LF included, as binary payloads may hold them.
+    pub fn bytes(&mut self, len: usize) -> Bytes {
+        (0..len).map(|_| self.below(256) as u8).collect()
+    }
+}
+
+/// Encodes `frame` as a connection speaking RESP3 writes it.
+pub fn encode(frame: &Frame) -> Vec<u8> {
+    let mut out = vec![];
+    match frame {
+        Frame::Array(entries) => {
+            out.extend_from_slice(format!("*{}\r\n", entries.len()).as_bytes());
+            for entry in entries {
+                encode_scalar(entry, &mut out);
+            }
+        }
+        _ => encode_scalar(frame, &mut out),
+    }
+    out
+}
+
+fn encode_scalar(frame: &Frame, out: &mut Vec<u8>) {
+    match frame {
+        Frame::Text(text) => out.extend_from_slice(format!("+{}", text).as_bytes()),
+        Frame::Error(err) => out.extend_from_slice(format!("-{}", err).as_bytes()),
+        Frame::Integer(val) => out.extend_from_slice(format!(":{}", val).as_bytes()),
+        Frame::Double(val) => out.extend_from_slice(format!(",{}", format_double(*val)).as_bytes()),
+        Frame::Binary(bin) => {
+            out.extend_from_slice(format!("${}\r\n", bin.len()).as_bytes());
+            out.extend_from_slice(bin);
+        }
+        Frame::Null => out.push(b'_'),
+        Frame::Array(_) => panic!("arrays don't nest"),
+    }
+    out.extend_from_slice(b"\r\n");
+}
+
+/// A bulk string frame of `len` random bytes.
+pub fn bulk_frame(rng: &mut Rng, len: usize) -> Frame {
+    Frame::Binary(rng.bytes(len))
+}
+
+/// An array of `len` frames, mostly small bulk strings with some text and
+/// integers in between, like a large `MSET` or a `KEYS` reply.
+pub fn array_frame(rng: &mut Rng, len: usize) -> Frame {
+    let entries = (0..len).map(|i| match rng.below(8) {
+        0 => Frame::Text(format!("key:{}", i)),
+        1 => Frame::Integer(rng.below(1 << 32) as i64),
+        _ => {
+            let len = 8 + rng.below(56) as usize;
+            bulk_frame(rng, len)
+        }
+    });
+    Frame::Array(entries.collect())
+}
+
+/// `count` distinct keys, shaped like the ones applications use.
+pub fn keys(count: usize) -> Vec<Bytes> {
+    (0..count)
+        .map(|i| Bytes::from(format!("user:{}:session", i)))
+        .collect()
+}
+
+/// A string value of `len` random bytes.
+pub fn value(rng: &mut Rng, len: usize) -> Value {
+    Value::String(rng.bytes(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_round_trip() {
+        let mut rng = Rng::new(0);
+        for frame in [array_frame(&mut rng, 64), bulk_frame(&mut rng, 1024)] {
+            let bytes = encode(&frame);
+            let parsed = Frame::parse(&mut Bytes::from(bytes)).unwrap();
+            assert_eq!(parsed, Some(frame));
+        }
+    }
+}
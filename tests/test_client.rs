@@ -1,13 +1,47 @@
-use std::net::SocketAddr;
+use std::{
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
+use bytes::Bytes;
 use tokio::{net::TcpListener, task::JoinHandle};
+use tokio_stream::StreamExt;
+use uranus_c::{NamespacedClient, Pool, PoolConfig, ServerError};
+use uranus_s::{
+    BufferConfig, Compression, Connection, DBHandle, Database, ErrorCode, EvictionPolicy, Frame,
+    HealthConfig, KeyTtl, ListEnd, QueueEnd, Scan, ServerConfig, ShadowConfig, StatsConfig,
+    StorageEngine, Subscribe, TraceConfig, Value, ZRangeBy,
+};
 
 const TEST_ADDR: &str = "127.0.0.1:0";
 
+/// The config of the servers of these tests. They run on the in-memory engine,
+/// or on the LSM one in a fresh directory with `URANUS_TEST_STORAGE=lsm`, so
+/// that the suite can be run against either.
+fn test_config() -> ServerConfig {
+    static STARTED: AtomicU64 = AtomicU64::new(0);
+    let storage = match std::env::var("URANUS_TEST_STORAGE").as_deref() {
+        Ok("lsm") => {
+            let n = STARTED.fetch_add(1, Ordering::Relaxed);
+            let name = format!("uranus-client-test-{}-{}", std::process::id(), n);
+            let data_dir = std::env::temp_dir().join(name);
+            let _ = std::fs::remove_dir_all(&data_dir);
+            StorageEngine::Lsm { data_dir }
+        }
+        _ => StorageEngine::Memory,
+    };
+    ServerConfig {
+        storage,
+        ..Default::default()
+    }
+}
+
 async fn start_server() -> (SocketAddr, JoinHandle<()>) {
     let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
     let addr = listener.local_addr().unwrap();
-    let handle = tokio::spawn(async move { uranus_s::run(listener).await });
+    let config = test_config();
+    let handle = tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
     (addr, handle)
 }
 
@@ -19,12 +53,2705 @@ async fn echo_test() {
     assert_eq!("hello", pong);
 }
 
+#[tokio::test]
+async fn ping_test() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    assert_eq!(client.ping(None).await.unwrap(), Bytes::from("PONG"));
+    let message = Bytes::from_static(b"are you\r\nthere\x00");
+    assert_eq!(client.ping(Some(message.clone())).await.unwrap(), message);
+
+    // What a health check speaking the Redis protocol sends.
+    let mut socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+    socket.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+    let mut reply = [0; 7];
+    socket.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"+PONG\r\n");
+}
+
+#[tokio::test]
+async fn readiness_test() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let health = std::net::TcpListener::bind(TEST_ADDR).unwrap();
+    let health_addr = health.local_addr().unwrap();
+    drop(health);
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        health: Some(HealthConfig {
+            addr: health_addr.to_string(),
+        }),
+        ..test_config()
+    };
+    tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
+
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    client.ready().await.unwrap();
+    let mut probe = tokio::net::TcpStream::connect(health_addr).await.unwrap();
+    probe
+        .write_all(b"GET /readyz HTTP/1.1\r\n\r\n")
+        .await
+        .unwrap();
+    let mut reply = String::new();
+    probe.read_to_string(&mut reply).await.unwrap();
+    assert!(reply.starts_with("HTTP/1.1 200 OK\r\n"), "{}", reply);
+}
+
+#[tokio::test]
+async fn pipelining_test() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (addr, _handle) = start_server().await;
+    let mut socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let mut pipeline = Vec::new();
+    pipeline.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n");
+    pipeline.extend_from_slice(b"*2\r\n$3\r\nGET\r\n$1\r\na\r\n");
+    pipeline.extend_from_slice(b"*1\r\n$4\r\nPING\r\n");
+    // Fails alone, the requests after it are still served.
+    pipeline.extend_from_slice(b"*1\r\n$5\r\nBOGUS\r\n");
+    pipeline.extend_from_slice(b"*1\r\n$4\r\nPING\r\n");
+    socket.write_all(&pipeline).await.unwrap();
+    socket.shutdown().await.unwrap();
+
+    let mut replies = Vec::new();
+    socket.read_to_end(&mut replies).await.unwrap();
+    let expected = "+OK\r\n$1\r\n1\r\n+PONG\r\n\
+                    -ERR The command is not implemented in this system.\r\n+PONG\r\n";
+    assert_eq!(String::from_utf8(replies).unwrap(), expected);
+}
+
+#[tokio::test]
+async fn frame_limits_test() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use uranus_s::FrameLimits;
+
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        frame_limits: FrameLimits {
+            max_bulk_len: 1024 * 1024,
+            ..Default::default()
+        },
+        ..test_config()
+    };
+    tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
+
+    // Values larger than a read still arrive in pieces.
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let value = Bytes::from(vec![b'x'; 512 * 1024]);
+    client.set("large", value.clone()).await.unwrap();
+    assert_eq!(
+        client.get::<Option<Bytes>>("large").await.unwrap(),
+        Some(value)
+    );
+
+    let mut socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+    socket
+        .write_all(b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$2000000\r\n")
+        .await
+        .unwrap();
+    let mut reply = String::new();
+    socket.read_to_string(&mut reply).await.unwrap();
+    let expected = "-ERR Protocol error: bulk string of 2000000 bytes exceeds the limit\r\n";
+    assert_eq!(reply, expected);
+}
+
 #[tokio::test]
 async fn getset_hashmap_test() {
     _ = tracing_subscriber::fmt::try_init();
     let (addr, _handle) = start_server().await;
     let mut client = uranus_c::Client::connect(addr).await.unwrap();
     client.set("hello", "world").await.unwrap();
-    let result = client.get("hello").await.unwrap();
+    let result = client.get::<Option<Bytes>>("hello").await.unwrap();
     println!("{:?}", result);
 }
+
+#[tokio::test]
+async fn binary_key_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let key = Bytes::from_static(b"\xff\xfekey");
+    client.set(&key, "value").await.unwrap();
+    let value: Bytes = client.get(&key).await.unwrap();
+    assert_eq!(value, "value");
+    assert_eq!(client.keys("*").await.unwrap(), std::slice::from_ref(&key));
+
+    let hash = Bytes::from_static(b"hash\x80");
+    let hset = vec!["hset".into(), hash.clone(), "field".into(), "1".into()];
+    assert_eq!(client.raw_command(hset).await.unwrap(), Frame::Integer(1));
+    let hget = vec!["hget".into(), hash, "field".into()];
+    assert_eq!(
+        client.raw_command(hget).await.unwrap(),
+        Frame::Binary(Bytes::from("1"))
+    );
+
+    let renamed = Bytes::from_static(b"\xc3\x28");
+    let rename = vec!["rename".into(), key.clone(), renamed.clone()];
+    client.raw_command(rename).await.unwrap();
+    assert_eq!(client.get::<Option<Bytes>>(&key).await.unwrap(), None);
+    let value: Bytes = client.get(&renamed).await.unwrap();
+    assert_eq!(value, "value");
+}
+
+#[tokio::test]
+async fn hello_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    assert_eq!(client.get::<Option<Bytes>>("missing").await.unwrap(), None);
+    assert_eq!(client.hello(3).await.unwrap(), 3);
+    assert_eq!(client.get::<Option<Bytes>>("missing").await.unwrap(), None);
+    assert!(client.hello(4).await.is_err());
+    assert_eq!(client.echo("still here").await.unwrap(), "still here");
+}
+
+#[tokio::test]
+async fn conversions_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    client.set(42, vec![1u8, 2, 3]).await.unwrap();
+    let value: Vec<u8> = client.get("42").await.unwrap();
+    assert_eq!(value, [1, 2, 3]);
+
+    client.set("counter", 42i64).await.unwrap();
+    let n: i64 = client.get("counter").await.unwrap();
+    assert_eq!(n, 42);
+    let missing: Option<i64> = client.get("missing").await.unwrap();
+    assert_eq!(missing, None);
+
+    let err = client.get::<i64>(42).await.unwrap_err();
+    assert!(
+        err.to_string()
+            .starts_with("Expected an integer of type i64"),
+        "{}",
+        err
+    );
+    let err = client.get::<String>("missing").await.unwrap_err();
+    assert_eq!(err.to_string(), "Expected a string, received nil.");
+}
+
+#[tokio::test]
+async fn in_process_test() {
+    use uranus_s::test_util::TestServer;
+
+    // Servers without ports don't share anything.
+    let servers = [TestServer::start(), TestServer::start()];
+    for (i, server) in servers.iter().enumerate() {
+        let mut client = uranus_c::Client::with_connection(server.connect());
+        client.set("server", i as i64).await.unwrap();
+    }
+    for (i, server) in servers.iter().enumerate() {
+        let mut client = uranus_c::Client::with_connection(server.connect());
+        assert_eq!(client.get::<i64>("server").await.unwrap(), i as i64);
+        assert!(server.db().get(Bytes::from("server")).unwrap().is_some());
+        // The first client is gone, the second one has the next address.
+        let clients = client.client_list().await.unwrap();
+        assert!(clients.contains("addr=127.0.0.1:2 "), "{}", clients);
+    }
+}
+
+#[tokio::test]
+async fn fault_injection_test() {
+    use uranus_s::{test_util::TestServer, Faults};
+
+    let faults = Faults::new(42)
+        .with_partial_writes(0.5)
+        .with_latency(Duration::from_millis(1), 0.1);
+    let server = TestServer::with_config(ServerConfig {
+        faults: Some(faults),
+        ..test_config()
+    });
+    // Requests and replies arriving in pieces, late, are read whole.
+    let mut client = uranus_c::Client::with_connection(server.connect_with_faults(faults));
+    for i in 0..50 {
+        let value = i.to_string().repeat(100);
+        client
+            .set(format!("key{}", i), value.as_str())
+            .await
+            .unwrap();
+        let read = client.get::<Option<String>>(format!("key{}", i)).await;
+        assert_eq!(read.unwrap().unwrap(), value);
+    }
+
+    // Clients reconnect to retry the writes failed by a dropped connection.
+    let faults = Faults::new(7).with_disconnects(0.2);
+    let mut client = uranus_c::Client::with_connection(server.connect_with_faults(faults));
+    let mut disconnects = 0;
+    for i in 0..20 {
+        while client.set(format!("retried{}", i), i).await.is_err() {
+            disconnects += 1;
+            let faults = faults.reseed(disconnects);
+            client = uranus_c::Client::with_connection(server.connect_with_faults(faults));
+        }
+    }
+    assert!(disconnects > 0);
+    for i in 0..20 {
+        let key = Bytes::from(format!("retried{}", i));
+        assert!(server.db().get(key).unwrap().is_some());
+    }
+}
+
+#[test]
+fn blocking_client_test() {
+    let listener = std::net::TcpListener::bind(TEST_ADDR).unwrap();
+    let addr = listener.local_addr().unwrap();
+    listener.set_nonblocking(true).unwrap();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let listener = TcpListener::from_std(listener).unwrap();
+            uranus_s::run_with_config(listener, test_config()).await
+        });
+    });
+
+    let mut client = uranus_c::blocking::Client::connect(addr).unwrap();
+    assert_eq!(client.echo("hello").unwrap(), "hello");
+    client.set("key", "value").unwrap();
+    client.set("n", 7).unwrap();
+    assert_eq!(
+        client.get::<Option<String>>("key").unwrap().unwrap(),
+        "value"
+    );
+    assert_eq!(client.get::<i64>("n").unwrap(), 7);
+    assert_eq!(client.del(&["key", "missing"]).unwrap(), 1);
+    assert_eq!(client.get::<Option<Bytes>>("key").unwrap(), None);
+}
+
+#[tokio::test]
+async fn command_test() {
+    use uranus_c::Value as Reply;
+
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    assert_eq!(
+        client.command(&["SET", "key", "value"]).await.unwrap(),
+        Reply::Text("OK".to_string())
+    );
+    assert_eq!(
+        client.command(&["GET", "key"]).await.unwrap(),
+        Reply::Bytes(Bytes::from("value"))
+    );
+    assert_eq!(
+        client.command(&["GET", "missing"]).await.unwrap(),
+        Reply::Nil
+    );
+    assert_eq!(
+        client.command(&["HSET", "hash", "a", "1"]).await.unwrap(),
+        Reply::Int(1)
+    );
+    let hash = client.command(&["HGETALL", "hash"]).await.unwrap();
+    assert_eq!(
+        hash.into_map().unwrap(),
+        Reply::Map(vec![(
+            Reply::Bytes(Bytes::from("a")),
+            Reply::Bytes(Bytes::from("1"))
+        )])
+    );
+    let err = client.command(&["GET", "hash"]).await.unwrap_err();
+    let err = err.downcast_ref::<ServerError>().unwrap();
+    assert_eq!(err.code, ErrorCode::WrongType);
+}
+
+#[tokio::test]
+async fn bad_arguments_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    // Bad requests are answered with an error, the connection staying usable.
+    let bad = [
+        (&["GET"][..], "wrong number of arguments for 'get' command"),
+        (
+            &["GETRANGE", "key", "0", "end"],
+            "value is not an integer or out of range",
+        ),
+        (
+            &["SETBIT", "key", "-"],
+            "wrong number of arguments for 'setbit' command",
+        ),
+        (&["BLPOP", "list", "soon"], "value is not a valid float"),
+        (
+            &["ZADD", "z", "1", "a", "two", "b"],
+            "value is not a valid float",
+        ),
+        // Line breaks echoed back would split the reply in two.
+        (
+            &["CONFIG", "GET", "x\r\n-ERR zzzz"],
+            "unknown parameter 'x  -ERR zzzz'",
+        ),
+    ];
+    for (args, message) in bad {
+        let err = client.command(args).await.unwrap_err();
+        let err = err.downcast_ref::<ServerError>().unwrap();
+        assert_eq!((err.code, err.message.as_str()), (ErrorCode::Err, message));
+    }
+    assert_eq!(client.echo("still here").await.unwrap(), "still here");
+    assert_eq!(client.dbsize().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn cloned_client_test() {
+    let (addr, _handle) = start_server().await;
+    let client = uranus_c::Client::connect(addr).await.unwrap();
+
+    // Each task gets the replies to its own requests, however they interleave.
+    let tasks: Vec<_> = (0..16)
+        .map(|task| {
+            let mut client = client.clone();
+            tokio::spawn(async move {
+                for i in 0..50 {
+                    let key = format!("key:{}:{}", task, i);
+                    client.set(&key, format!("{}", i)).await.unwrap();
+                    let value = client.get::<Option<Bytes>>(&key).await.unwrap();
+                    assert_eq!(value, Some(Bytes::from(format!("{}", i))));
+                    assert_eq!(client.echo(&key).await.unwrap(), key);
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    let mut client = client;
+    assert_eq!(client.dbsize().await.unwrap(), 16 * 50);
+}
+
+#[tokio::test]
+async fn pool_test() {
+    let (addr, _handle) = start_server().await;
+    let config = PoolConfig {
+        max_size: 2,
+        min_idle: 0,
+    };
+    let pool = Pool::new(addr, config);
+
+    let mut first = pool.get().await.unwrap();
+    let second = pool.get().await.unwrap();
+    let third = tokio::time::timeout(Duration::from_millis(100), pool.get()).await;
+    assert!(third.is_err(), "the pool should be exhausted");
+
+    first.set("hello", "world").await.unwrap();
+    drop(first);
+    assert_eq!(pool.idle_connections(), 1);
+    let mut third = pool.get().await.unwrap();
+    assert_eq!(
+        third.get::<Option<Bytes>>("hello").await.unwrap(),
+        Some(Bytes::from("world"))
+    );
+    drop((second, third));
+    assert_eq!(pool.idle_connections(), 2);
+
+    let tasks: Vec<_> = (0..8)
+        .map(|i| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                let mut client = pool.get().await.unwrap();
+                client.set(&format!("key{}", i), "value").await.unwrap();
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert_eq!(pool.idle_connections(), 2);
+}
+
+#[tokio::test]
+async fn online_reshard_test() {
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        shards: Some(4),
+        ..Default::default()
+    };
+    tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
+
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    assert_eq!(
+        client.config_get("shards").await.unwrap().as_deref(),
+        Some("4")
+    );
+    for i in 0..200 {
+        client.set(&format!("key{}", i), "value").await.unwrap();
+    }
+
+    client.config_set("shards", 32).await.unwrap();
+    for i in 0..200 {
+        let value = client
+            .get::<Option<Bytes>>(&format!("key{}", i))
+            .await
+            .unwrap();
+        assert_eq!(value, Some(Bytes::from("value")));
+    }
+    assert_eq!(
+        client.config_get("shards").await.unwrap().as_deref(),
+        Some("32")
+    );
+    assert!(client.config_set("shards", 0).await.is_err());
+}
+
+#[tokio::test]
+async fn runtime_config_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    assert_eq!(
+        client.config_get("timeout").await.unwrap().as_deref(),
+        Some("0")
+    );
+    assert_eq!(
+        client
+            .config_get("slowlog-log-slower-than")
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("10000")
+    );
+    client
+        .config_set("slowlog-log-slower-than", 500)
+        .await
+        .unwrap();
+    assert_eq!(
+        client
+            .config_get("slowlog-log-slower-than")
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("500")
+    );
+    client
+        .config_set("expiry-sweep-interval", 20)
+        .await
+        .unwrap();
+    assert_eq!(
+        client
+            .config_get("expiry-sweep-interval")
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("20")
+    );
+    assert!(client.config_set("expiry-sweep-interval", 0).await.is_err());
+    assert!(client.config_set("timeout", "soon").await.is_err());
+    // The test server doesn't install its log.
+    assert_eq!(client.config_get("loglevel").await.unwrap(), None);
+    assert!(client.config_set("loglevel", "debug").await.is_err());
+
+    client.config_set("timeout", 1).await.unwrap();
+    let mut idle = uranus_c::Client::connect(addr).await.unwrap();
+    idle.ping(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(idle.ping(None).await.is_err());
+}
+
+#[tokio::test]
+async fn ttl_test() {
+    // The default engine expires keys natively, the sharded one relies on the
+    // database handle.
+    for shards in [None, Some(4)] {
+        let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = ServerConfig {
+            shards,
+            ..test_config()
+        };
+        tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
+
+        let mut client = uranus_c::Client::connect(addr).await.unwrap();
+        let ttl = Duration::from_millis(100);
+        client.set_with_ttl("session", "token", ttl).await.unwrap();
+        client.set_with_ttl("renewed", "v1", ttl).await.unwrap();
+        client.set("renewed", "v2").await.unwrap();
+        client.set_with_ttl("cart", "x", ttl).await.unwrap();
+        assert_eq!(
+            client.get::<Option<Bytes>>("session").await.unwrap(),
+            Some(Bytes::from("token"))
+        );
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(client.get::<Option<Bytes>>("session").await.unwrap(), None);
+        assert_eq!(client.get::<Option<Bytes>>("cart").await.unwrap(), None);
+        assert_eq!(
+            client.get::<Option<Bytes>>("renewed").await.unwrap(),
+            Some(Bytes::from("v2"))
+        );
+    }
+}
+
+#[tokio::test]
+async fn expire_test() {
+    for shards in [None, Some(4)] {
+        let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = ServerConfig {
+            shards,
+            ..test_config()
+        };
+        tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
+
+        let mut client = uranus_c::Client::connect(addr).await.unwrap();
+        assert_eq!(client.ttl("missing").await.unwrap(), KeyTtl::Missing);
+        assert!(!client
+            .expire("missing", Duration::from_secs(1))
+            .await
+            .unwrap());
+        assert!(!client.persist("missing").await.unwrap());
+
+        client.hset("user", [("name", "uranus")]).await.unwrap();
+        assert_eq!(client.ttl("user").await.unwrap(), KeyTtl::Persistent);
+        assert!(!client.persist("user").await.unwrap());
+        assert!(client
+            .expire("user", Duration::from_secs(100))
+            .await
+            .unwrap());
+        let KeyTtl::Remaining(remaining) = client.ttl("user").await.unwrap() else {
+            panic!("user should expire");
+        };
+        assert!(remaining > Duration::from_secs(99) && remaining <= Duration::from_secs(100));
+        let ttl = ["ttl", "user"].map(Bytes::from).to_vec();
+        assert_eq!(
+            client.raw_command(ttl).await.unwrap(),
+            uranus_s::Frame::Integer(100)
+        );
+
+        // Writes keep the TTL, until the key is persisted.
+        client.hset("user", [("moon", "ariel")]).await.unwrap();
+        assert!(matches!(
+            client.ttl("user").await.unwrap(),
+            KeyTtl::Remaining(_)
+        ));
+        assert!(client.persist("user").await.unwrap());
+        assert_eq!(client.ttl("user").await.unwrap(), KeyTtl::Persistent);
+
+        client.set("session", "token").await.unwrap();
+        assert!(client
+            .expire("session", Duration::from_millis(50))
+            .await
+            .unwrap());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(client.get::<Option<Bytes>>("session").await.unwrap(), None);
+        assert_eq!(client.ttl("session").await.unwrap(), KeyTtl::Missing);
+
+        client.set("doomed", "value").await.unwrap();
+        let expire = ["expire", "doomed", "-1"].map(Bytes::from).to_vec();
+        assert_eq!(
+            client.raw_command(expire).await.unwrap(),
+            uranus_s::Frame::Integer(1)
+        );
+        assert_eq!(client.get::<Option<Bytes>>("doomed").await.unwrap(), None);
+    }
+}
+
+#[tokio::test]
+async fn rename_test() {
+    for shards in [None, Some(4)] {
+        let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = ServerConfig {
+            shards,
+            ..test_config()
+        };
+        tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
+
+        let mut client = uranus_c::Client::connect(addr).await.unwrap();
+        assert!(client.rename("missing", "dst").await.is_err());
+        let ttl = Duration::from_secs(100);
+        client.set_with_ttl("session", "token", ttl).await.unwrap();
+        client.set("renamed", "old").await.unwrap();
+        client.rename("session", "renamed").await.unwrap();
+        assert_eq!(client.get::<Option<Bytes>>("session").await.unwrap(), None);
+        assert_eq!(
+            client.get::<Option<Bytes>>("renamed").await.unwrap(),
+            Some(Bytes::from("token"))
+        );
+        assert!(matches!(
+            client.ttl("renamed").await.unwrap(),
+            KeyTtl::Remaining(_)
+        ));
+
+        client.set("other", "old").await.unwrap();
+        assert!(!client.copy("renamed", "other", false).await.unwrap());
+        assert!(client.copy("renamed", "other", true).await.unwrap());
+        assert_eq!(
+            client.get::<Option<Bytes>>("other").await.unwrap(),
+            Some(Bytes::from("token"))
+        );
+        assert!(matches!(
+            client.ttl("other").await.unwrap(),
+            KeyTtl::Remaining(_)
+        ));
+        assert!(!client.copy("missing", "copy", false).await.unwrap());
+        assert!(client.copy("renamed", "renamed", true).await.is_err());
+    }
+}
+
+#[tokio::test]
+async fn dump_restore_test() {
+    let (source, _source) = start_server().await;
+    let (target, _target) = start_server().await;
+    let mut source = uranus_c::Client::connect(source).await.unwrap();
+    let mut target = uranus_c::Client::connect(target).await.unwrap();
+    source
+        .hset("user", [("name", "ada"), ("lang", "en")])
+        .await
+        .unwrap();
+    source
+        .zadd("ranks", [(1.5, "a"), (-2.0, "b")])
+        .await
+        .unwrap();
+    assert_eq!(source.dump("missing").await.unwrap(), None);
+
+    for key in ["user", "ranks"] {
+        let blob = source.dump(key).await.unwrap().unwrap();
+        target.restore(key, None, blob, false).await.unwrap();
+    }
+    let mut fields = target.hgetall("user").await.unwrap();
+    fields.sort();
+    assert_eq!(
+        fields,
+        [
+            (Bytes::from("lang"), Bytes::from("en")),
+            (Bytes::from("name"), Bytes::from("ada"))
+        ]
+    );
+    assert_eq!(
+        target.zrange("ranks", ZRangeBy::Rank(0, -1)).await.unwrap(),
+        source.zrange("ranks", ZRangeBy::Rank(0, -1)).await.unwrap()
+    );
+
+    source.set("string", "new").await.unwrap();
+    let blob = source.dump("string").await.unwrap().unwrap();
+    target.set("string", "old").await.unwrap();
+    let err = target
+        .restore("string", None, blob.clone(), false)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().starts_with("BUSYKEY"), "{}", err);
+    let ttl = Some(Duration::from_secs(100));
+    target
+        .restore("string", ttl, blob.clone(), true)
+        .await
+        .unwrap();
+    assert_eq!(
+        target.get::<Option<Bytes>>("string").await.unwrap(),
+        Some(Bytes::from("new"))
+    );
+    assert!(matches!(
+        target.ttl("string").await.unwrap(),
+        KeyTtl::Remaining(_)
+    ));
+
+    let mut damaged = blob.to_vec();
+    damaged[0] ^= 1;
+    let err = target
+        .restore("damaged", None, Bytes::from(damaged), false)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("bad DUMP payload"), "{}", err);
+    assert_eq!(target.get::<Option<Bytes>>("damaged").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn migrate_test() {
+    let (source, _source) = start_server().await;
+    let (target, _target) = start_server().await;
+    let mut source = uranus_c::Client::connect(source).await.unwrap();
+    let mut client = uranus_c::Client::connect(target).await.unwrap();
+    let (host, port) = (target.ip().to_string(), target.port());
+    let timeout = Some(Duration::from_secs(5));
+
+    assert!(!source.migrate(&host, port, "key", timeout).await.unwrap());
+    source.set("key", "value").await.unwrap();
+    source
+        .expire("key", Duration::from_secs(100))
+        .await
+        .unwrap();
+    assert!(source.migrate(&host, port, "key", timeout).await.unwrap());
+    assert_eq!(source.get::<Option<Bytes>>("key").await.unwrap(), None);
+    assert_eq!(
+        client.get::<Option<Bytes>>("key").await.unwrap(),
+        Some(Bytes::from("value"))
+    );
+    assert!(matches!(
+        client.ttl("key").await.unwrap(),
+        KeyTtl::Remaining(_)
+    ));
+
+    // The target has the key already.
+    source.set("key", "other").await.unwrap();
+    let err = source
+        .migrate(&host, port, "key", timeout)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().starts_with("BUSYKEY"), "{}", err);
+    assert_eq!(
+        source.get::<Option<Bytes>>("key").await.unwrap(),
+        Some(Bytes::from("other"))
+    );
+
+    // A target which never replies.
+    let silent = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let port = silent.local_addr().unwrap().port();
+    let timeout = Some(Duration::from_millis(100));
+    let err = source
+        .migrate(&host, port, "key", timeout)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().starts_with("IOERR"), "{}", err);
+    assert_eq!(
+        source.get::<Option<Bytes>>("key").await.unwrap(),
+        Some(Bytes::from("other"))
+    );
+}
+
+#[tokio::test]
+async fn concurrent_rename_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    client.set("a", "value").await.unwrap();
+    let mut swappers = vec![];
+    for (src, dst) in [("a", "b"), ("b", "a")] {
+        let mut client = uranus_c::Client::connect(addr).await.unwrap();
+        swappers.push(tokio::spawn(async move {
+            for _ in 0..500 {
+                // Fails whenever the other swapper got there first.
+                let _ = client.rename(src, dst).await;
+            }
+        }));
+    }
+    for swapper in swappers {
+        swapper.await.unwrap();
+    }
+    // The value is always under exactly one of the keys.
+    assert_eq!(client.dbsize().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn mset_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    client
+        .set_with_ttl("a", "old", Duration::from_secs(100))
+        .await
+        .unwrap();
+    let pairs: [(&str, &[u8]); 3] = [("a", b"1"), ("b", b"2"), ("c", b"3")];
+    client.mset(&pairs).await.unwrap();
+    for (key, value) in pairs {
+        assert_eq!(
+            client.get::<Option<Bytes>>(key).await.unwrap(),
+            Some(Bytes::from(value))
+        );
+    }
+    assert_eq!(client.ttl("a").await.unwrap(), KeyTtl::Persistent);
+
+    let odd = ["mset", "a", "1", "b"].map(Bytes::from).to_vec();
+    assert!(client.raw_command(odd).await.is_err());
+}
+
+#[tokio::test]
+async fn conditional_set_test() {
+    let (addr, _handle) = start_server().await;
+    let mut contenders = vec![];
+    for id in 0..8 {
+        let mut client = uranus_c::Client::connect(addr).await.unwrap();
+        contenders.push(tokio::spawn(async move {
+            let ttl = Some(Duration::from_secs(10));
+            client.set_nx("lock", format!("owner{}", id), ttl).await
+        }));
+    }
+    let mut winners = 0;
+    for contender in contenders {
+        if contender.await.unwrap().unwrap() {
+            winners += 1;
+        }
+    }
+    assert_eq!(winners, 1);
+
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    assert!(!client.set_xx("missing", "value").await.unwrap());
+    assert_eq!(client.get::<Option<Bytes>>("missing").await.unwrap(), None);
+    assert!(client.set_xx("lock", "released").await.unwrap());
+    assert_eq!(
+        client.get::<Option<Bytes>>("lock").await.unwrap(),
+        Some(Bytes::from("released"))
+    );
+}
+
+#[tokio::test]
+async fn protocol_trace_test() {
+    let dir = std::env::temp_dir().join(format!("uranus-trace-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        trace: TraceConfig {
+            dir: dir.clone(),
+            ..Default::default()
+        },
+        ..test_config()
+    };
+    tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
+
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    client.set("untraced", "value").await.unwrap();
+    let on = ["debug", "trace", "on"].map(Bytes::from).to_vec();
+    assert_eq!(
+        client.raw_command(on).await.unwrap(),
+        uranus_s::Frame::Text("OK".into())
+    );
+    client.set("traced", "value").await.unwrap();
+    let off = ["debug", "trace", "off"].map(Bytes::from).to_vec();
+    client.raw_command(off).await.unwrap();
+    client.set("untraced", "again").await.unwrap();
+
+    let mut trace = String::new();
+    for entry in std::fs::read_dir(&dir).unwrap() {
+        trace += &std::fs::read_to_string(entry.unwrap().path()).unwrap();
+    }
+    assert!(trace.contains(" > 5 +OK\\r\\n\n"));
+    assert!(trace.contains("$6\\r\\ntraced\\r\\n$5\\r\\nvalue\\r\\n"));
+    assert!(trace.contains("$3\\r\\noff\\r\\n"));
+    assert!(!trace.contains("untraced"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn command_panic_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let panic = ["debug", "panic"].map(Bytes::from).to_vec();
+    let err = client.raw_command(panic).await.unwrap_err();
+    assert_eq!(err.to_string(), "ERR internal error");
+
+    // The connection is still usable.
+    client.set("key", "value").await.unwrap();
+    assert_eq!(
+        client.get::<Option<Bytes>>("key").await.unwrap(),
+        Some("value".into())
+    );
+}
+
+#[tokio::test]
+async fn buffer_sizes_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let mut large = uranus_c::Client::connect(addr).await.unwrap();
+    large.set("large", vec![b'x'; 20_000]).await.unwrap();
+
+    let buffers = ["debug", "buffers"].map(Bytes::from).to_vec();
+    let uranus_s::Frame::Array(frames) = client.raw_command(buffers).await.unwrap() else {
+        panic!("expected an array");
+    };
+    let counts: Vec<(i64, i64)> = frames
+        .chunks(2)
+        .map(|pair| match pair {
+            [uranus_s::Frame::Integer(size), uranus_s::Frame::Integer(n)] => (*size, *n),
+            _ => panic!("expected integers"),
+        })
+        .filter(|(_, n)| *n > 0)
+        .collect();
+    assert_eq!(counts, [(512, 1), (32768, 1)]);
+}
+
+#[tokio::test]
+async fn large_value_test() {
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        buffers: BufferConfig {
+            initial_size: 1024,
+            max_size: 16 * 1024,
+        },
+        ..test_config()
+    };
+    tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
+
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let value = support::Rng::new(338).bytes(8 * 1024 * 1024);
+    client.set("large", value.clone()).await.unwrap();
+    assert_eq!(
+        client.get::<Option<Bytes>>("large").await.unwrap(),
+        Some(value)
+    );
+
+    let buffers = ["debug", "buffers"].map(Bytes::from).to_vec();
+    let uranus_s::Frame::Array(frames) = client.raw_command(buffers).await.unwrap() else {
+        panic!("expected an array");
+    };
+    // The sizes from 1 KiB to 16 KiB, the read size of this connection capped.
+    assert_eq!(frames.len(), 2 * 5);
+    assert_eq!(
+        &frames[8..],
+        [
+            uranus_s::Frame::Integer(16 * 1024),
+            uranus_s::Frame::Integer(1)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn get_stream_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let value = support::Rng::new(347).bytes(8 * 1024 * 1024);
+    client.set("large", value.clone()).await.unwrap();
+    client.set("small", "value").await.unwrap();
+    assert!(client.get_stream("missing").await.unwrap().is_none());
+
+    let mut chunks = client.get_stream("large").await.unwrap().unwrap();
+    // Waits behind the stream.
+    let mut clone = client.clone();
+    let behind = tokio::spawn(async move { clone.get::<Option<Bytes>>("small").await.unwrap() });
+    let (mut received, mut count) = (Vec::new(), 0);
+    while let Some(chunk) = chunks.next().await {
+        received.extend_from_slice(&chunk.unwrap());
+        count += 1;
+    }
+    assert_eq!(received, value);
+    assert!(count > 1);
+    assert_eq!(behind.await.unwrap(), Some(Bytes::from("value")));
+
+    // The rest of a stream dropped early is skipped.
+    let mut chunks = client.get_stream("large").await.unwrap().unwrap();
+    chunks.next().await.unwrap().unwrap();
+    drop(chunks);
+    assert_eq!(
+        client.get::<Option<Bytes>>("small").await.unwrap(),
+        Some(Bytes::from("value"))
+    );
+
+    client.hset("hash", [("field", "value")]).await.unwrap();
+    assert!(client.get_stream("hash").await.is_err());
+}
+
+#[tokio::test]
+async fn set_from_reader_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let value = support::Rng::new(348).bytes(8 * 1024 * 1024);
+    let reader = std::io::Cursor::new(value.clone());
+    client
+        .set_from_reader("large", reader, value.len())
+        .await
+        .unwrap();
+    assert_eq!(
+        client.get::<Option<Bytes>>("large").await.unwrap(),
+        Some(value.clone())
+    );
+    client
+        .set_from_reader("empty", tokio::io::empty(), 0)
+        .await
+        .unwrap();
+    assert_eq!(
+        client.get::<Option<Bytes>>("empty").await.unwrap(),
+        Some(Bytes::new())
+    );
+
+    // Part of the value went out, so the connection is given up.
+    let reader = std::io::Cursor::new(value[..1000].to_vec());
+    assert!(client.set_from_reader("large", reader, 2000).await.is_err());
+    assert!(client.get::<Option<Bytes>>("large").await.is_err());
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    assert_eq!(client.strlen("large").await.unwrap(), value.len() as i64);
+}
+
+#[tokio::test]
+async fn keys_and_scan_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    for key in ["user:1", "user:2", "user:10", "order:1", "session"] {
+        client.set(key, "value").await.unwrap();
+    }
+    assert_eq!(
+        client.keys("user:?").await.unwrap(),
+        [Bytes::from("user:1"), Bytes::from("user:2")]
+    );
+    assert_eq!(
+        client.keys("[ou]*:1*").await.unwrap(),
+        [
+            Bytes::from("order:1"),
+            Bytes::from("user:1"),
+            Bytes::from("user:10")
+        ]
+    );
+    assert_eq!(client.keys("*").await.unwrap().len(), 5);
+
+    let mut cursor = 0;
+    let mut scanned = vec![];
+    loop {
+        let scan = Scan::new(cursor).matching("user:*").with_count(2);
+        let (next, keys) = client.scan(scan).await.unwrap();
+        assert!(keys.len() <= 2);
+        scanned.extend(keys);
+        if next == 0 {
+            break;
+        }
+        cursor = next;
+    }
+    scanned.sort();
+    assert_eq!(scanned, client.keys("user:*").await.unwrap());
+}
+
+#[tokio::test]
+async fn scan_guarantee_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let stable: Vec<String> = (0..200).map(|n| format!("stable:{}", n)).collect();
+    for key in &stable {
+        client.set(key, "value").await.unwrap();
+    }
+    for n in 0..200 {
+        client
+            .set(&format!("removed:{}", n), "value")
+            .await
+            .unwrap();
+    }
+
+    // Keys come and go between the steps, which mustn't make the others missed.
+    let mut cursor = 0;
+    let mut scanned = vec![];
+    let mut step = 0;
+    loop {
+        let (next, keys) = client.scan(Scan::new(cursor).with_count(7)).await.unwrap();
+        scanned.extend(keys);
+        for n in step * 10..(step + 1) * 10 {
+            client.set(&format!("added:{}", n), "value").await.unwrap();
+            client.del(&[&format!("removed:{}", n)]).await.unwrap();
+        }
+        step += 1;
+        if next == 0 {
+            break;
+        }
+        cursor = next;
+    }
+    for key in &stable {
+        let seen = scanned.iter().filter(|scanned| *scanned == key).count();
+        assert_eq!(seen, 1, "{}", key);
+    }
+    let others = scanned.len() - stable.len();
+    assert!(others <= 200 + step * 10, "{}", others);
+}
+
+#[tokio::test]
+async fn namespaced_client_test() {
+    let (addr, _handle) = start_server().await;
+    let client = uranus_c::Client::connect(addr).await.unwrap();
+    // The namespace has glob specials, which must not match other keys.
+    let mut tenant = NamespacedClient::new(client.clone(), "t[1]:");
+    let mut other = NamespacedClient::new(client.clone(), "t1:");
+    for key in ["user:1", "user:2", "order:1"] {
+        tenant.set(key, "value").await.unwrap();
+        other.set(key, "other").await.unwrap();
+    }
+    assert_eq!(tenant.get("user:1").await.unwrap(), Some("value".into()));
+    assert_eq!(
+        tenant.keys("user:*").await.unwrap(),
+        [Bytes::from("user:1"), Bytes::from("user:2")]
+    );
+    let (cursor, mut keys) = tenant.scan(Scan::new(0).with_count(100)).await.unwrap();
+    keys.sort();
+    assert_eq!(cursor, 0);
+    assert_eq!(keys, tenant.keys("*").await.unwrap());
+    assert_eq!(tenant.del(&["user:2", "user:3"]).await.unwrap(), 1);
+    assert!(tenant
+        .expire("order:1", Duration::from_secs(60))
+        .await
+        .unwrap());
+    assert!(matches!(
+        tenant.ttl("order:1").await.unwrap(),
+        KeyTtl::Remaining(_)
+    ));
+
+    assert_eq!(tenant.flush().await.unwrap(), 2);
+    assert!(tenant.keys("*").await.unwrap().is_empty());
+    assert_eq!(other.keys("*").await.unwrap().len(), 3);
+    let mut client = client;
+    assert_eq!(client.del_prefix("t1:user:").await.unwrap(), 2);
+    assert_eq!(client.keys("*").await.unwrap(), [Bytes::from("t1:order:1")]);
+}
+
+#[tokio::test]
+async fn write_stall_test() {
+    let dir = std::env::temp_dir().join(format!("uranus-write-stall-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let lsm = uranus_kv::LsmConfig {
+        memtable_size: 64,
+        max_tables: 2,
+        background_merges: true,
+        slowdown_tables: 4,
+        stop_tables: 6,
+        ..uranus_kv::LsmConfig::new(&dir)
+    };
+    let db = DBHandle::with_lsm(1, &lsm).unwrap();
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = test_config();
+    tokio::spawn(async move { uranus_s::run_with_database(listener, config, db).await });
+
+    // Writes flush tables faster than they are merged, until they are stopped.
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let mut stopped = None;
+    for i in 0..1000 {
+        if let Err(err) = client.set(&format!("key:{}", i), "value").await {
+            stopped = Some(err);
+            break;
+        }
+    }
+    let err = stopped.expect("writes should be stopped");
+    let err = err.downcast_ref::<ServerError>().unwrap();
+    assert_eq!(err.code, ErrorCode::Stalled);
+    assert!(client
+        .get::<Option<Bytes>>("key:0")
+        .await
+        .unwrap()
+        .is_some());
+
+    // They resume once the tables are merged.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    client.set("after", "value").await.unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn sst_stats_test() {
+    let dir = std::env::temp_dir().join(format!("uranus-sst-stats-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let lsm = uranus_kv::LsmConfig {
+        memtable_size: 1024,
+        block_size: 256,
+        block_compression: Compression::Lz4,
+        ..uranus_kv::LsmConfig::new(&dir)
+    };
+    let db = DBHandle::with_lsm(1, &lsm).unwrap();
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { uranus_s::run_with_database(listener, test_config(), db).await });
+
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    for i in 0..100 {
+        client
+            .set(&format!("key:{}", i), "value".repeat(20))
+            .await
+            .unwrap();
+    }
+    client.get::<Option<Bytes>>("key:0").await.unwrap();
+    let info = client.info(Some("persistence")).await.unwrap();
+    let field = |name: &str| -> f64 {
+        let prefix = format!("{}:", name);
+        let line = info.lines().find(|line| line.starts_with(&prefix)).unwrap();
+        line[prefix.len()..].parse().unwrap()
+    };
+    assert!(field("sst_blocks_written") > 1.0);
+    assert!(field("sst_blocks_read") >= 1.0);
+    assert!(field("sst_compression_ratio") > 2.0);
+    assert!(field("sst_read_amplification") > 0.0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Under `always` every write syncs the log before it is acknowledged, under
+/// `no` none does.
+#[tokio::test]
+async fn wal_fsync_test() {
+    let dir = std::env::temp_dir().join(format!("uranus-wal-fsync-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let db = DBHandle::with_lsm(1, &uranus_kv::LsmConfig::new(&dir)).unwrap();
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { uranus_s::run_with_database(listener, test_config(), db).await });
+
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let fsyncs = |info: String| -> u64 {
+        let line = info.lines().find(|line| line.starts_with("wal_fsyncs:"));
+        line.unwrap()["wal_fsyncs:".len()..].parse().unwrap()
+    };
+    assert_eq!(
+        client.config_get("wal-fsync").await.unwrap().as_deref(),
+        Some("everysec")
+    );
+    client.config_set("wal-fsync", "always").await.unwrap();
+    let before = fsyncs(client.info(Some("persistence")).await.unwrap());
+    for i in 0..20 {
+        client.set(&format!("always:{}", i), "value").await.unwrap();
+    }
+    let after = fsyncs(client.info(Some("persistence")).await.unwrap());
+    assert_eq!(after - before, 20);
+
+    client.config_set("wal-fsync", "no").await.unwrap();
+    for i in 0..20 {
+        client.set(&format!("no:{}", i), "value").await.unwrap();
+    }
+    let info = client.info(Some("persistence")).await.unwrap();
+    assert!(info.contains("wal_fsync:no\r\n"), "{}", info);
+    assert_eq!(fsyncs(info), after);
+    assert!(client.config_set("wal-fsync", "sometimes").await.is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Reads leave the keys as they are: concurrent ones through the commands
+/// don't write them back, which would log them to the engine.
+#[tokio::test]
+async fn concurrent_reads_test() {
+    let dir = std::env::temp_dir().join(format!("uranus-concurrent-reads-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let db = DBHandle::with_lsm(1, &uranus_kv::LsmConfig::new(&dir)).unwrap();
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { uranus_s::run_with_database(listener, test_config(), db).await });
+
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let fsyncs = |info: String| -> u64 {
+        let line = info.lines().find(|line| line.starts_with("wal_fsyncs:"));
+        line.unwrap()["wal_fsyncs:".len()..].parse().unwrap()
+    };
+    // Every write to the log syncs it.
+    client.config_set("wal-fsync", "always").await.unwrap();
+    for i in 0..10 {
+        client.set(&format!("key:{}", i), "value").await.unwrap();
+        client
+            .hset(&format!("hash:{}", i), [("field", "value")])
+            .await
+            .unwrap();
+    }
+    let before = fsyncs(client.info(Some("persistence")).await.unwrap());
+
+    let mut readers = vec![];
+    for _ in 0..4 {
+        let mut client = uranus_c::Client::connect(addr).await.unwrap();
+        readers.push(tokio::spawn(async move {
+            for _ in 0..20 {
+                for i in 0..10 {
+                    let key = format!("key:{}", i);
+                    let value: Option<Bytes> = client.get(key.as_str()).await.unwrap();
+                    assert_eq!(value.as_deref(), Some(&b"value"[..]));
+                    assert_eq!(client.strlen(&key).await.unwrap(), 5);
+                    let hash = format!("hash:{}", i);
+                    let field = client.hget(&hash, "field").await.unwrap();
+                    assert_eq!(field.as_deref(), Some(&b"value"[..]));
+                }
+            }
+        }));
+    }
+    for reader in readers {
+        reader.await.unwrap();
+    }
+    let after = fsyncs(client.info(Some("persistence")).await.unwrap());
+    assert_eq!(after, before);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Commands going through a large keyspace must let the other connections of
+/// the same worker be served meanwhile. The test runtime has a single thread,
+/// so a command that didn't yield would finish before the PING is answered.
+#[tokio::test]
+async fn long_commands_yield_test() {
+    let db = DBHandle::new(&StorageEngine::Memory).unwrap();
+    for i in 0..200_000 {
+        let value = Value::String(Bytes::from("value"));
+        db.put(Bytes::from(format!("key:{}", i)), value).unwrap();
+    }
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig::default();
+    tokio::spawn(async move { uranus_s::run_with_database(listener, config, db).await });
+
+    let mut prober = uranus_c::Client::connect(addr).await.unwrap();
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let keys = tokio::spawn(async move {
+        // Without a literal prefix, every key is matched.
+        let keys = client.keys("*nothing*").await.unwrap();
+        (client, keys)
+    });
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    prober.ping(None).await.unwrap();
+    assert!(!keys.is_finished(), "KEYS held up the other connections");
+    let (mut client, keys) = keys.await.unwrap();
+    assert!(keys.is_empty());
+
+    let flush = tokio::spawn(async move { client.flushdb().await.unwrap() });
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    prober.ping(None).await.unwrap();
+    assert!(
+        !flush.is_finished(),
+        "FLUSHDB held up the other connections"
+    );
+    flush.await.unwrap();
+    assert_eq!(prober.dbsize().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn select_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let mut other = uranus_c::Client::connect(addr).await.unwrap();
+    client.set("key", "db0").await.unwrap();
+    client.select(1).await.unwrap();
+    assert_eq!(client.get::<Option<Bytes>>("key").await.unwrap(), None);
+    client.set("key", "db1").await.unwrap();
+    assert_eq!(
+        other.get::<Option<Bytes>>("key").await.unwrap(),
+        Some(Bytes::from("db0"))
+    );
+    assert!(client.select(16).await.is_err());
+
+    client.set("other", "db1").await.unwrap();
+    assert_eq!(client.dbsize().await.unwrap(), 2);
+    assert_eq!(other.dbsize().await.unwrap(), 1);
+    client.flushdb().await.unwrap();
+    assert_eq!(client.dbsize().await.unwrap(), 0);
+    assert_eq!(client.get::<Option<Bytes>>("key").await.unwrap(), None);
+    assert_eq!(
+        other.get::<Option<Bytes>>("key").await.unwrap(),
+        Some(Bytes::from("db0"))
+    );
+
+    client.set("key", "db1").await.unwrap();
+    other.flushall().await.unwrap();
+    assert_eq!(client.get::<Option<Bytes>>("key").await.unwrap(), None);
+    assert_eq!(other.get::<Option<Bytes>>("key").await.unwrap(), None);
+    assert_eq!(other.dbsize().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn compare_and_swap_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    client.set("counter", "0").await.unwrap();
+    assert!(!client.cas("counter", "1", "2").await.unwrap());
+    assert!(!client.cas("missing", "0", "1").await.unwrap());
+
+    // Concurrent increments, each retried until its swap wins.
+    let mut workers = vec![];
+    for _ in 0..4 {
+        let mut client = uranus_c::Client::connect(addr).await.unwrap();
+        workers.push(tokio::spawn(async move {
+            for _ in 0..10 {
+                loop {
+                    let current = client
+                        .get::<Option<Bytes>>("counter")
+                        .await
+                        .unwrap()
+                        .unwrap();
+                    let next = std::str::from_utf8(&current)
+                        .unwrap()
+                        .parse::<i64>()
+                        .unwrap()
+                        + 1;
+                    if client
+                        .cas("counter", current, next.to_string())
+                        .await
+                        .unwrap()
+                    {
+                        break;
+                    }
+                }
+            }
+        }));
+    }
+    for worker in workers {
+        worker.await.unwrap();
+    }
+    assert_eq!(
+        client.get::<Option<Bytes>>("counter").await.unwrap(),
+        Some(Bytes::from("40"))
+    );
+}
+
+#[tokio::test]
+async fn idempotent_set_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    client.set_with_id("key", "first", "token").await.unwrap();
+    client.set("key", "second").await.unwrap();
+    client.set_with_id("key", "retried", "token").await.unwrap();
+    let reused = client.set_with_id("other", "value", "token").await;
+    assert!(reused.is_err());
+    assert_eq!(
+        client.get::<Option<Bytes>>("key").await.unwrap(),
+        Some(Bytes::from("second"))
+    );
+    client.set_with_id("key", "third", "another").await.unwrap();
+    assert_eq!(
+        client.get::<Option<Bytes>>("key").await.unwrap(),
+        Some(Bytes::from("third"))
+    );
+}
+
+#[tokio::test]
+async fn shadow_test() {
+    let (secondary, _handle) = start_server().await;
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        shadow: Some(ShadowConfig::new(secondary, 100)),
+        ..test_config()
+    };
+    tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
+
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    client.set("hello", "shadow").await.unwrap();
+
+    let mut shadow_client = uranus_c::Client::connect(secondary).await.unwrap();
+    for _ in 0..100 {
+        if let Some(value) = shadow_client.get::<Option<Bytes>>("hello").await.unwrap() {
+            assert_eq!(value, Bytes::from("shadow"));
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("the write was never mirrored to the secondary");
+}
+
+#[tokio::test]
+async fn string_commands_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    assert_eq!(client.append("greeting", "Hello").await.unwrap(), 5);
+    assert_eq!(client.append("greeting", ", Uranus").await.unwrap(), 13);
+    assert_eq!(client.strlen("greeting").await.unwrap(), 13);
+    assert_eq!(client.strlen("missing").await.unwrap(), 0);
+
+    assert_eq!(client.getrange("greeting", 0, 4).await.unwrap(), "Hello");
+    assert_eq!(client.getrange("greeting", -6, -1).await.unwrap(), "Uranus");
+    assert_eq!(client.getrange("greeting", 7, 100).await.unwrap(), "Uranus");
+    assert_eq!(client.getrange("greeting", 5, 2).await.unwrap(), "");
+    assert_eq!(client.getrange("missing", 0, -1).await.unwrap(), "");
+
+    client.sadd("set", ["member"]).await.unwrap();
+    assert!(client.append("set", "suffix").await.is_err());
+}
+
+#[tokio::test]
+async fn bit_commands_test() {
+    use uranus_s::{BitCount, BitUnit, LPos};
+
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    assert_eq!(client.setrange("padded", 3, "abc").await.unwrap(), 6);
+    assert_eq!(
+        client
+            .get::<Option<Bytes>>("padded")
+            .await
+            .unwrap()
+            .unwrap(),
+        "\0\0\0abc"
+    );
+    assert_eq!(client.setrange("padded", 0, "xy").await.unwrap(), 6);
+    assert_eq!(
+        client
+            .get::<Option<Bytes>>("padded")
+            .await
+            .unwrap()
+            .unwrap(),
+        "xy\0abc"
+    );
+    assert_eq!(client.setrange("missing", 5, "").await.unwrap(), 0);
+    assert_eq!(client.get::<Option<Bytes>>("missing").await.unwrap(), None);
+
+    // 0b0000_0101
+    assert!(!client.setbit("bits", 5, true).await.unwrap());
+    assert!(!client.setbit("bits", 7, true).await.unwrap());
+    assert!(client.setbit("bits", 7, true).await.unwrap());
+    assert!(client.getbit("bits", 5).await.unwrap());
+    assert!(!client.getbit("bits", 6).await.unwrap());
+    assert!(!client.getbit("bits", 1000).await.unwrap());
+    assert_eq!(
+        client.get::<Option<Bytes>>("bits").await.unwrap().unwrap(),
+        "\x05"
+    );
+    client.setbit("bits", 8, true).await.unwrap();
+    assert_eq!(client.bitcount(BitCount::new("bits")).await.unwrap(), 3);
+    let second_byte = BitCount::new("bits").with_range(-1, -1, BitUnit::Byte);
+    assert_eq!(client.bitcount(second_byte).await.unwrap(), 1);
+    let first_bits = BitCount::new("bits").with_range(0, 6, BitUnit::Bit);
+    assert_eq!(client.bitcount(first_bits).await.unwrap(), 1);
+
+    client
+        .push("list", ListEnd::Right, ["a", "b", "a", "c", "a"])
+        .await
+        .unwrap();
+    let lpos = |rank, count| {
+        let mut lpos = LPos::new("list", Bytes::from("a"));
+        lpos.rank = rank;
+        lpos.count = count;
+        lpos
+    };
+    assert_eq!(client.lpos(lpos(1, None)).await.unwrap(), [0]);
+    assert_eq!(client.lpos(lpos(2, None)).await.unwrap(), [2]);
+    assert_eq!(client.lpos(lpos(-1, None)).await.unwrap(), [4]);
+    assert_eq!(client.lpos(lpos(1, Some(0))).await.unwrap(), [0, 2, 4]);
+    assert_eq!(client.lpos(lpos(-2, Some(5))).await.unwrap(), [2, 0]);
+    assert!(client.lpos(lpos(0, None)).await.is_err());
+    assert!(client.setbit("list", 0, true).await.is_err());
+}
+
+#[tokio::test]
+async fn hyperloglog_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    // Random 16 byte elements, distinct but for a negligible chance.
+    let mut rng = support::Rng::new(328);
+    for key in ["left", "right"] {
+        for _ in 0..20 {
+            let batch: Vec<_> = (0..1000).map(|_| rng.bytes(16)).collect();
+            assert!(client.pfadd(key, batch).await.unwrap());
+        }
+    }
+    let within = |count: i64, actual: f64| (count as f64 - actual).abs() / actual < 0.025;
+    let left = client.pfcount(&["left"]).await.unwrap();
+    assert!(within(left, 20_000.0), "{}", left);
+    let both = client.pfcount(&["left", "right", "missing"]).await.unwrap();
+    assert!(within(both, 40_000.0), "{}", both);
+
+    client.pfmerge("both", &["left", "right"]).await.unwrap();
+    assert_eq!(client.pfcount(&["both"]).await.unwrap(), both);
+    client.pfmerge("both", &["left"]).await.unwrap();
+    assert_eq!(client.pfcount(&["both"]).await.unwrap(), both);
+
+    assert!(client.pfadd("empty", Vec::<Bytes>::new()).await.unwrap());
+    assert!(!client.pfadd("empty", Vec::<Bytes>::new()).await.unwrap());
+    assert_eq!(client.pfcount(&["empty"]).await.unwrap(), 0);
+
+    client.set("plain", "not a hyperloglog").await.unwrap();
+    assert!(client.pfadd("plain", ["a"]).await.is_err());
+    assert!(client.pfcount(&["left", "plain"]).await.is_err());
+    assert!(client.pfmerge("both", &["plain"]).await.is_err());
+}
+
+#[tokio::test]
+async fn stream_test() {
+    use uranus_s::{NewId, StreamId, XAdd, XRange, XRead, XReadFrom};
+
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let entry = |value: &'static str| vec![(Bytes::from("field"), Bytes::from(value))];
+    let first = client.xadd(XAdd::new("events", entry("a"))).await.unwrap();
+    let second = client.xadd(XAdd::new("events", entry("b"))).await.unwrap();
+    assert!(second > first);
+    // Entries added at a past time come after the last one all the same.
+    let third = XAdd::new("events", entry("c")).with_id(NewId::Time(1));
+    let third = client.xadd(third).await.unwrap();
+    assert!(third > second);
+    let stale = XAdd::new("events", entry("d")).with_id(NewId::Exact(first));
+    assert!(client.xadd(stale).await.is_err());
+
+    let all = XRange::new("events", StreamId::MIN, StreamId::MAX);
+    let entries = client.xrange(all).await.unwrap();
+    let ids: Vec<_> = entries.iter().map(|(id, _)| *id).collect();
+    assert_eq!(ids, [first, second, third]);
+    assert_eq!(entries[1].1, entry("b"));
+    let mut from_second = XRange::new("events", second, StreamId::MAX);
+    from_second.count = Some(1);
+    assert_eq!(
+        client.xrange(from_second).await.unwrap(),
+        [(second, entry("b"))]
+    );
+
+    let after_first = XRead::new(vec![
+        (Bytes::from("events"), XReadFrom::After(first)),
+        (Bytes::from("missing"), XReadFrom::After(StreamId::MIN)),
+    ]);
+    let read = client.xread(after_first).await.unwrap();
+    let ids: Vec<_> = read
+        .iter()
+        .map(|(key, id, _)| (key.as_ref(), *id))
+        .collect();
+    assert_eq!(ids, [(&b"events"[..], second), (&b"events"[..], third)]);
+    let new = || XRead::new(vec![(Bytes::from("events"), XReadFrom::Last)]);
+    assert!(client.xread(new()).await.unwrap().is_empty());
+    let timeout = new().blocking(Some(Duration::from_millis(50)));
+    assert!(client.xread(timeout).await.unwrap().is_empty());
+
+    // Every blocked reader gets the new entry.
+    let mut readers = vec![];
+    for _ in 0..2 {
+        let mut reader = uranus_c::Client::connect(addr).await.unwrap();
+        let xread = new().blocking(None);
+        readers.push(tokio::spawn(async move { reader.xread(xread).await }));
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let added = client.xadd(XAdd::new("events", entry("e"))).await.unwrap();
+    for reader in readers {
+        let read = tokio::time::timeout(Duration::from_secs(1), reader).await;
+        let read = read.unwrap().unwrap().unwrap();
+        assert_eq!(read, [(Bytes::from("events"), added, entry("e"))]);
+    }
+
+    client.set("plain", "value").await.unwrap();
+    assert!(client.xadd(XAdd::new("plain", entry("a"))).await.is_err());
+    let all = XRange::new("plain", StreamId::MIN, StreamId::MAX);
+    assert!(client.xrange(all).await.is_err());
+}
+
+#[tokio::test]
+async fn scripting_test() {
+    use uranus_s::{Frame, Scripts};
+
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let set = "return redis.call('SET', KEYS[1], ARGV[1])";
+    let reply = client.eval(set, &["greeting"], ["hello"]).await.unwrap();
+    assert_eq!(reply, Frame::Text("OK".to_string()));
+    let append = "local value = redis.call('GET', KEYS[1])
+        redis.call('SET', KEYS[1], value .. ARGV[1])
+        return {redis.call('GET', KEYS[1]), #value, redis.call('GET', 'missing')}";
+    let reply = client.eval(append, &["greeting"], [" world"]).await;
+    let expected = vec![
+        Frame::Binary(Bytes::from("hello world")),
+        Frame::Integer(5),
+        Frame::Null,
+    ];
+    assert_eq!(reply.unwrap(), Frame::Array(expected));
+
+    // Scripts run by EVAL are cached.
+    let digest = Scripts::digest(set);
+    let reply = client.evalsha(&digest, &["other"], ["value"]).await;
+    assert_eq!(reply.unwrap(), Frame::Text("OK".to_string()));
+    assert_eq!(
+        client.get::<Option<Bytes>>("other").await.unwrap().unwrap(),
+        "value"
+    );
+    let unknown = Scripts::digest("return 1");
+    assert!(client
+        .evalsha(&unknown, &[], Vec::<Bytes>::new())
+        .await
+        .is_err());
+
+    let wrong_type = "return redis.call('HGET', KEYS[1], 'field')";
+    assert!(client
+        .eval(wrong_type, &["other"], Vec::<Bytes>::new())
+        .await
+        .is_err());
+    let caught = "return redis.pcall('HGET', KEYS[1], 'field').err ~= nil";
+    let reply = client.eval(caught, &["other"], Vec::<Bytes>::new()).await;
+    assert_eq!(reply.unwrap(), Frame::Integer(1));
+    let blocking = "return redis.call('BLPOP', 'list', '0')";
+    assert!(client
+        .eval(blocking, &[], Vec::<Bytes>::new())
+        .await
+        .is_err());
+
+    // Reading and writing back is atomic within a script.
+    let increment = "local count = tonumber(redis.call('GET', KEYS[1]) or '0')
+        redis.call('SET', KEYS[1], tostring(count + 1))";
+    let mut tasks = vec![];
+    for _ in 0..4 {
+        let mut client = uranus_c::Client::connect(addr).await.unwrap();
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..25 {
+                client
+                    .eval(increment, &["counter"], Vec::<Bytes>::new())
+                    .await
+                    .unwrap();
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert_eq!(
+        client
+            .get::<Option<Bytes>>("counter")
+            .await
+            .unwrap()
+            .unwrap(),
+        "100"
+    );
+}
+
+#[tokio::test]
+async fn script_limits_test() {
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        script_time_limit: Duration::from_millis(200),
+        ..test_config()
+    };
+    tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+
+    // Replies larger than the pipe to the script are read as they are written.
+    let big = Bytes::from(vec![b'x'; 16 * 1024 * 1024]);
+    client.set("big", big.clone()).await.unwrap();
+    let get = "return redis.call('GET', KEYS[1])";
+    let reply = client.eval(get, &["big"], Vec::<Bytes>::new()).await;
+    assert_eq!(reply.unwrap(), Frame::Binary(big));
+
+    let endless = "while true do end";
+    let err = client
+        .eval(endless, &[], Vec::<Bytes>::new())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("time limit"), "{}", err);
+    let mut other = uranus_c::Client::connect(addr).await.unwrap();
+    other.ping(None).await.unwrap();
+}
+
+#[tokio::test]
+async fn acl_test() {
+    use uranus_s::AclUser;
+
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let reader = AclUser::with_rules("reader", ["on", ">secret", "+@read", "~cache:*"]);
+    let config = ServerConfig {
+        acl: vec![reader.unwrap()],
+        ..test_config()
+    };
+    tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
+
+    let mut admin = uranus_c::Client::connect(addr).await.unwrap();
+    admin.set("cache:a", "1").await.unwrap();
+    admin.set("other", "2").await.unwrap();
+    assert_eq!(admin.acl_whoami().await.unwrap(), "default");
+
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    assert!(client.auth(Some("reader"), "wrong").await.is_err());
+    client.auth(Some("reader"), "secret").await.unwrap();
+    assert_eq!(
+        client.acl_whoami().await.unwrap_err().to_string(),
+        "NOPERM User reader has no permissions to run the 'acl' command"
+    );
+    assert_eq!(
+        client
+            .get::<Option<Bytes>>("cache:a")
+            .await
+            .unwrap()
+            .unwrap(),
+        "1"
+    );
+    assert!(client.set("cache:a", "2").await.is_err());
+    assert!(client.get::<Option<Bytes>>("other").await.is_err());
+    let script = "return redis.call('GET', KEYS[1])";
+    let read = client.eval(script, &["cache:a"], Vec::<Bytes>::new()).await;
+    assert!(read.is_err());
+
+    admin
+        .acl_setuser("reader", &["+@scripting", "+set"])
+        .await
+        .unwrap();
+    let read = client.eval(script, &["cache:a"], Vec::<Bytes>::new()).await;
+    assert_eq!(read.unwrap(), uranus_s::Frame::Binary(Bytes::from("1")));
+    let script = "return redis.call('GET', 'other')";
+    assert!(client.eval(script, &[], Vec::<Bytes>::new()).await.is_err());
+    client.set("cache:a", "2").await.unwrap();
+
+    // Requiring a password for the default user locks out new connections.
+    admin.acl_setuser("default", &[">admin"]).await.unwrap();
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    client.ping(None).await.unwrap();
+    assert!(client.get::<Option<Bytes>>("cache:a").await.is_err());
+    client.auth(None, "admin").await.unwrap();
+    assert_eq!(
+        client
+            .get::<Option<Bytes>>("cache:a")
+            .await
+            .unwrap()
+            .unwrap(),
+        "2"
+    );
+    assert_eq!(client.acl_deluser(&["reader", "nobody"]).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn hash_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let added = client
+        .hset("user", [("name", "uranus"), ("kind", "planet")])
+        .await;
+    assert_eq!(added.unwrap(), 2);
+    let added = client
+        .hset("user", [("kind", "ice giant"), ("moons", "27")])
+        .await;
+    assert_eq!(added.unwrap(), 1);
+    assert_eq!(
+        client.hget("user", "kind").await.unwrap(),
+        Some(Bytes::from("ice giant"))
+    );
+    assert_eq!(client.hget("user", "rings").await.unwrap(), None);
+
+    let mut all = client.hgetall("user").await.unwrap();
+    all.sort();
+    let fields: Vec<_> = all.iter().map(|(field, _)| field.clone()).collect();
+    assert_eq!(fields, ["kind", "moons", "name"]);
+
+    assert_eq!(client.hdel("user", ["name", "rings"]).await.unwrap(), 1);
+    assert_eq!(client.hdel("user", ["kind", "moons"]).await.unwrap(), 2);
+    assert!(client.hgetall("user").await.unwrap().is_empty());
+
+    client.set("plain", "string").await.unwrap();
+    assert!(client.hget("plain", "field").await.is_err());
+    client.hset("user", [("name", "uranus")]).await.unwrap();
+    assert!(client.get::<Option<Bytes>>("user").await.is_err());
+}
+
+#[tokio::test]
+async fn strict_types_test() {
+    use uranus_c::WrongType;
+
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    client.hset("user", [("name", "uranus")]).await.unwrap();
+    client.set("plain", "string").await.unwrap();
+
+    let err = client.get::<Option<Bytes>>("user").await.unwrap_err();
+    assert!(err.to_string().starts_with("WRONGTYPE"));
+    assert_eq!(client.try_get("user").await.unwrap(), Err(WrongType));
+    assert_eq!(client.try_get("missing").await.unwrap(), Ok(None));
+    let value = client.try_get("plain").await.unwrap();
+    assert_eq!(value, Ok(Some(Bytes::from("string"))));
+
+    client.set_strict_types(true);
+    let err = client.get::<Option<Bytes>>("user").await.unwrap_err();
+    assert_eq!(err.downcast_ref::<WrongType>(), Some(&WrongType));
+    let err = client.hget("plain", "field").await.unwrap_err();
+    assert!(err.is::<WrongType>());
+    assert_eq!(client.try_get("user").await.unwrap(), Err(WrongType));
+    assert_eq!(client.get::<Option<Bytes>>("missing").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn error_codes_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    client.hset("user", [("name", "uranus")]).await.unwrap();
+
+    let err = client.get::<Option<Bytes>>("user").await.unwrap_err();
+    let err = err.downcast_ref::<ServerError>().unwrap();
+    assert_eq!(err.code, ErrorCode::WrongType);
+    assert!(err.to_string().starts_with("WRONGTYPE Operation"));
+
+    let dump = client.dump("user").await.unwrap().unwrap();
+    let err = client.restore("user", None, dump, false).await.unwrap_err();
+    let err = err.downcast_ref::<ServerError>().unwrap();
+    assert_eq!(err.code, ErrorCode::BusyKey);
+
+    let err = client.select(16).await.unwrap_err();
+    let err = err.downcast_ref::<ServerError>().unwrap();
+    assert_eq!(err.code, ErrorCode::Err);
+    assert_eq!(err.message, "DB index is out of range");
+}
+
+#[tokio::test]
+async fn set_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    assert_eq!(client.sadd("moons", ["ariel", "miranda"]).await.unwrap(), 2);
+    assert_eq!(client.sadd("moons", ["ariel", "oberon"]).await.unwrap(), 1);
+    assert!(client.sismember("moons", "oberon").await.unwrap());
+    assert!(!client.sismember("moons", "titan").await.unwrap());
+
+    let mut members = client.smembers("moons").await.unwrap();
+    members.sort();
+    assert_eq!(members, ["ariel", "miranda", "oberon"]);
+
+    assert_eq!(client.srem("moons", ["titan", "ariel"]).await.unwrap(), 1);
+    assert_eq!(
+        client.srem("moons", ["miranda", "oberon"]).await.unwrap(),
+        2
+    );
+    assert!(client.smembers("moons").await.unwrap().is_empty());
+
+    client.set("plain", "string").await.unwrap();
+    assert!(client.sadd("plain", ["member"]).await.is_err());
+}
+
+#[tokio::test]
+async fn set_algebra_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let sorted = |mut members: Vec<Bytes>| {
+        members.sort();
+        members
+    };
+    client.sadd("a", ["1", "2", "3", "4"]).await.unwrap();
+    client.sadd("b", ["2", "3", "5"]).await.unwrap();
+    client.sadd("c", ["3", "4", "5"]).await.unwrap();
+
+    assert_eq!(
+        sorted(client.sinter(&["a", "b"]).await.unwrap()),
+        ["2", "3"]
+    );
+    assert_eq!(client.sinter(&["a", "b", "c"]).await.unwrap(), ["3"]);
+    assert!(client.sinter(&["a", "missing"]).await.unwrap().is_empty());
+    assert_eq!(
+        sorted(client.sunion(&["b", "c", "missing"]).await.unwrap()),
+        ["2", "3", "4", "5"]
+    );
+    assert_eq!(
+        sorted(client.sdiff(&["a", "b", "missing"]).await.unwrap()),
+        ["1", "4"]
+    );
+    assert_eq!(client.sdiff(&["a", "b", "c"]).await.unwrap(), ["1"]);
+
+    assert_eq!(client.sintercard(&["a", "b"], None).await.unwrap(), 2);
+    assert_eq!(client.sintercard(&["a", "b"], Some(1)).await.unwrap(), 1);
+    assert_eq!(client.sintercard(&["a", "missing"], None).await.unwrap(), 0);
+
+    assert_eq!(client.sunionstore("u", &["a", "b"]).await.unwrap(), 5);
+    assert_eq!(client.sinterstore("a", &["a", "c"]).await.unwrap(), 2);
+    assert_eq!(sorted(client.smembers("a").await.unwrap()), ["3", "4"]);
+    assert_eq!(client.sdiffstore("d", &["c", "u"]).await.unwrap(), 0);
+    assert_eq!(client.get::<Option<Bytes>>("d").await.unwrap(), None);
+
+    client.set("plain", "string").await.unwrap();
+    let err = client.sinter(&["missing", "plain"]).await.unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<ServerError>().unwrap().code,
+        ErrorCode::WrongType
+    );
+    assert!(client.sunionstore("u", &["a", "plain"]).await.is_err());
+    assert_eq!(client.smembers("u").await.unwrap().len(), 5);
+}
+
+#[tokio::test]
+async fn sorted_set_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let scores = [(120.0, "ada"), (95.5, "bob"), (150.0, "cyd")];
+    assert_eq!(client.zadd("board", scores).await.unwrap(), 3);
+    assert_eq!(client.zadd("board", [(99.0, "bob")]).await.unwrap(), 0);
+    assert_eq!(client.zscore("board", "bob").await.unwrap(), Some(99.0));
+    assert_eq!(client.zscore("board", "eve").await.unwrap(), None);
+
+    let top = client
+        .zrange("board", ZRangeBy::Rank(-2, -1))
+        .await
+        .unwrap();
+    assert_eq!(
+        top,
+        [(Bytes::from("ada"), 120.0), (Bytes::from("cyd"), 150.0)]
+    );
+    let range = ZRangeBy::Score(f64::NEG_INFINITY, 120.0);
+    let low = client.zrange("board", range).await.unwrap();
+    assert_eq!(
+        low,
+        [(Bytes::from("bob"), 99.0), (Bytes::from("ada"), 120.0)]
+    );
+
+    // Doubles are native frames in protocol version 3.
+    client.hello(3).await.unwrap();
+    assert_eq!(client.zscore("board", "cyd").await.unwrap(), Some(150.0));
+}
+
+#[tokio::test]
+async fn priority_queue_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let jobs = [
+        (3.0, "backup"),
+        (1.0, "deploy"),
+        (2.0, "report"),
+        (5.0, "cleanup"),
+    ];
+    assert_eq!(client.pqadd("jobs", jobs).await.unwrap(), 4);
+
+    let peeked = client.pqpeek("jobs", QueueEnd::Max).await.unwrap();
+    assert_eq!(peeked, Some((Bytes::from("cleanup"), 5.0)));
+    let popped = client.pqpop("jobs", QueueEnd::Min, 2).await.unwrap();
+    assert_eq!(
+        popped,
+        [(Bytes::from("deploy"), 1.0), (Bytes::from("report"), 2.0)]
+    );
+    let popped = client.pqpop("jobs", QueueEnd::Max, 5).await.unwrap();
+    assert_eq!(popped.len(), 2);
+    assert_eq!(client.pqpeek("jobs", QueueEnd::Min).await.unwrap(), None);
+
+    let timeout = Some(Duration::from_millis(50));
+    let popped = client.bpqpop("jobs", QueueEnd::Min, timeout).await.unwrap();
+    assert_eq!(popped, None);
+
+    let mut producer = uranus_c::Client::connect(addr).await.unwrap();
+    let worker = tokio::spawn(async move { client.bpqpop("jobs", QueueEnd::Min, None).await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    producer.pqadd("jobs", [(0.5, "urgent")]).await.unwrap();
+    let popped = tokio::time::timeout(Duration::from_secs(1), worker).await;
+    assert_eq!(
+        popped.unwrap().unwrap().unwrap(),
+        Some((Bytes::from("urgent"), 0.5))
+    );
+}
+
+#[tokio::test]
+async fn blocking_list_pop_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    assert_eq!(
+        client
+            .push("tasks", ListEnd::Right, ["a", "b"])
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(client.push("tasks", ListEnd::Left, ["z"]).await.unwrap(), 3);
+    assert_eq!(
+        client.pop("tasks", ListEnd::Right).await.unwrap(),
+        Some(Bytes::from("b"))
+    );
+    let popped = client.blpop("tasks", None).await.unwrap();
+    assert_eq!(popped, Some(Bytes::from("z")));
+    client.pop("tasks", ListEnd::Left).await.unwrap();
+    let popped = client.blpop("tasks", Some(Duration::from_millis(50))).await;
+    assert_eq!(popped.unwrap(), None);
+
+    let mut workers = vec![];
+    for _ in 0..2 {
+        let mut worker = uranus_c::Client::connect(addr).await.unwrap();
+        workers.push(tokio::spawn(
+            async move { worker.blpop("tasks", None).await },
+        ));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    // Blocked connections don't hold up the others.
+    client.set("unrelated", "value").await.unwrap();
+    client
+        .push("tasks", ListEnd::Right, ["first", "second"])
+        .await
+        .unwrap();
+    for (worker, expected) in workers.into_iter().zip(["first", "second"]) {
+        let popped = tokio::time::timeout(Duration::from_secs(1), worker).await;
+        assert_eq!(
+            popped.unwrap().unwrap().unwrap(),
+            Some(Bytes::from(expected))
+        );
+    }
+}
+
+#[tokio::test]
+async fn revision_history_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    client.set("config", "v1").await.unwrap();
+    client.keep_revisions("config", 3).await.unwrap();
+    for value in ["v2", "v3", "v4"] {
+        client.set("config", value).await.unwrap();
+    }
+    client.set("untracked", "value").await.unwrap();
+
+    for (back, expected) in [(0, "v4"), (1, "v3"), (2, "v2")] {
+        let (value, _) = client.get_revision("config", back).await.unwrap().unwrap();
+        assert_eq!(value, expected);
+    }
+    // v1 was pruned.
+    assert_eq!(client.get_revision("config", 3).await.unwrap(), None);
+    assert_eq!(client.get_revision("untracked", 0).await.unwrap(), None);
+
+    client.keep_revisions("config", 1).await.unwrap();
+    assert_eq!(client.get_revision("config", 1).await.unwrap(), None);
+    assert!(client.keep_revisions("config", 1_000_000).await.is_err());
+}
+
+#[tokio::test]
+async fn deadline_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    client.set_deadline(Some(Duration::from_secs(5)));
+    client.set("key", "value").await.unwrap();
+    assert_eq!(
+        client.get::<Option<Bytes>>("key").await.unwrap(),
+        Some(Bytes::from("value"))
+    );
+
+    // No command can start within no time at all.
+    client.set_deadline(Some(Duration::ZERO));
+    let err = client.set("key", "late").await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(uranus_c::ClientError::DeadlineExceeded)
+    ));
+    client.set_deadline(None);
+    assert_eq!(
+        client.get::<Option<Bytes>>("key").await.unwrap(),
+        Some(Bytes::from("value"))
+    );
+
+    // A malformed prefix fails its request only, the connection still serves.
+    for prefix in [&["deadline"][..], &["deadline", "soon", "get", "key"]] {
+        let request = prefix.iter().map(|&word| Bytes::from(word)).collect();
+        assert!(client.raw_command(request).await.is_err());
+        assert_eq!(
+            client.get::<Option<Bytes>>("key").await.unwrap(),
+            Some(Bytes::from("value"))
+        );
+    }
+}
+
+#[tokio::test]
+async fn watch_keys_test() {
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        keyspace_events: "KA".parse().unwrap(),
+        ..test_config()
+    };
+    tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
+
+    let watcher = uranus_c::Client::connect(addr).await.unwrap();
+    let events = watcher.watch_keys("user:*").await.unwrap();
+    tokio::pin!(events);
+
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    client.set("user:1", "uranus").await.unwrap();
+    client.set("planet", "neptune").await.unwrap();
+    client.hset("user:2", [("name", "ariel")]).await.unwrap();
+    let ttl = Duration::from_millis(50);
+    client.set_with_ttl("user:3", "titania", ttl).await.unwrap();
+
+    let expected = [
+        ("user:1", "set"),
+        ("user:2", "hset"),
+        ("user:3", "set"),
+        ("user:3", "expired"),
+    ];
+    for (key, op) in expected {
+        let event = tokio::time::timeout(Duration::from_secs(1), events.next()).await;
+        let event = event.unwrap().unwrap().unwrap();
+        assert_eq!((event.key.as_str(), event.op.as_str()), (key, op));
+    }
+}
+
+#[tokio::test]
+async fn keyevent_notifications_test() {
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        // Room for 2 keys of 1000 bytes, only evictions and expiries announced.
+        max_memory: 2_500,
+        eviction_policy: EvictionPolicy::Lru,
+        keyspace_events: "Exe".parse().unwrap(),
+        expiry_sweep_interval: Duration::from_millis(10),
+        ..test_config()
+    };
+    tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
+
+    let socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let mut subscriber = Connection::new(socket);
+    let channels = vec![
+        "__keyevent@0__:evicted".to_string(),
+        "__keyevent@0__:expired".to_string(),
+        "__keyevent@0__:set".to_string(),
+    ];
+    let frame = Subscribe::channels(channels).into_frame();
+    subscriber.write_frame(&frame).await.unwrap();
+    for _ in 0..3 {
+        subscriber.read_frame().await.unwrap().unwrap();
+    }
+
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    for i in 0..4 {
+        let key = format!("key:{}", i);
+        client.set(&key, vec![0; 1000]).await.unwrap();
+    }
+    let ttl = Duration::from_millis(20);
+    client.set_with_ttl("short", "lived", ttl).await.unwrap();
+
+    let mut received = vec![];
+    while received.len() < 3 {
+        let frame = tokio::time::timeout(Duration::from_secs(1), subscriber.read_frame());
+        let Frame::Array(parts) = frame.await.unwrap().unwrap().unwrap() else {
+            panic!("not a message");
+        };
+        let [_, Frame::Text(channel), Frame::Binary(key)] = &parts[..] else {
+            panic!("not a message: {:?}", parts);
+        };
+        received.push(format!("{} {}", channel, String::from_utf8_lossy(key)));
+    }
+    received.sort();
+    assert_eq!(
+        received,
+        [
+            "__keyevent@0__:evicted key:0",
+            "__keyevent@0__:evicted key:1",
+            "__keyevent@0__:expired short",
+        ]
+    );
+}
+
+/// A [`Database`] refusing every write, to check the server really serves
+/// whatever backend it is given.
+#[derive(Clone, Default)]
+struct ReadOnlyDatabase {
+    inner: DBHandle,
+}
+
+impl Database for ReadOnlyDatabase {
+    fn get(&self, key: Bytes) -> anyhow::Result<Option<Value>> {
+        self.inner.get(key)
+    }
+
+    fn put(&self, _: Bytes, _: Value) -> anyhow::Result<()> {
+        Err(anyhow::anyhow!("read only"))
+    }
+
+    fn delete(&self, _: Bytes) -> anyhow::Result<()> {
+        Err(anyhow::anyhow!("read only"))
+    }
+
+    fn scan(&self) -> anyhow::Result<Vec<(Bytes, Value)>> {
+        self.inner.scan()
+    }
+}
+
+#[tokio::test]
+async fn custom_database_test() {
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let db = ReadOnlyDatabase::default();
+    db.inner
+        .put(Bytes::from("hello"), Value::String(Bytes::from("world")))
+        .unwrap();
+    tokio::spawn(async move {
+        uranus_s::run_with_database(listener, ServerConfig::default(), db).await
+    });
+
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let result = client.get::<Option<Bytes>>("hello").await.unwrap();
+    assert_eq!(result, Some(Bytes::from("world")));
+    assert!(client.set("hello", "mars").await.is_err());
+}
+
+async fn start_server_with_memory(max_memory: usize, policy: EvictionPolicy) -> SocketAddr {
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        max_memory,
+        eviction_policy: policy,
+        ..test_config()
+    };
+    tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
+    addr
+}
+
+#[tokio::test]
+async fn unlink_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    let members: Vec<String> = (0..1000).map(|n| n.to_string()).collect();
+    client.sadd("big", members.clone()).await.unwrap();
+    client.sadd("small", ["a", "b"]).await.unwrap();
+    client.set("string", "value").await.unwrap();
+
+    assert_eq!(
+        client.unlink(&["big", "small", "missing"]).await.unwrap(),
+        2
+    );
+    assert_eq!(client.unlink(&["big"]).await.unwrap(), 0);
+    assert_eq!(client.get::<Option<Bytes>>("big").await.unwrap(), None);
+
+    client
+        .config_set("lazyfree-lazy-user-del", "yes")
+        .await
+        .unwrap();
+    let lazy = client.config_get("lazyfree-lazy-user-del").await.unwrap();
+    assert_eq!(lazy.as_deref(), Some("yes"));
+    client.sadd("big", members).await.unwrap();
+    assert_eq!(client.del(&["big", "string"]).await.unwrap(), 2);
+
+    // Both large sets were handed to the background task.
+    let mut info = String::new();
+    for _ in 0..100 {
+        info = client.info(Some("memory")).await.unwrap();
+        if info.contains("lazyfreed_objects:2\r\n") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(info.contains("lazyfree_pending_objects:0\r\n"), "{}", info);
+    assert!(info.contains("lazyfreed_objects:2\r\n"), "{}", info);
+
+    client
+        .config_set("lazyfree-threshold", "5000")
+        .await
+        .unwrap();
+    let threshold = client.config_get("lazyfree-threshold").await.unwrap();
+    assert_eq!(threshold.as_deref(), Some("5000"));
+}
+
+#[tokio::test]
+async fn lru_eviction_test() {
+    // Room for 9 keys of 1000 bytes.
+    let addr = start_server_with_memory(10_000, EvictionPolicy::Lru).await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    for i in 0..10 {
+        client
+            .set(&format!("key:{}", i), vec![0; 1000])
+            .await
+            .unwrap();
+    }
+    // Memory is reclaimed before a write, so the limit is exceeded by the last one.
+    assert_eq!(client.dbsize().await.unwrap(), 10);
+    client.get::<Option<Bytes>>("key:0").await.unwrap();
+
+    client.set("key:10", vec![0; 1000]).await.unwrap();
+    assert!(client
+        .get::<Option<Bytes>>("key:0")
+        .await
+        .unwrap()
+        .is_some());
+    assert_eq!(client.get::<Option<Bytes>>("key:1").await.unwrap(), None);
+    let info = client.info(Some("stats")).await.unwrap();
+    assert!(
+        info.starts_with("# Stats\r\nevicted_keys:1\r\n"),
+        "{}",
+        info
+    );
+
+    for i in 11..100 {
+        client
+            .set(&format!("key:{}", i), vec![0; 1000])
+            .await
+            .unwrap();
+    }
+    assert_eq!(client.dbsize().await.unwrap(), 10);
+    let info = client.info(None).await.unwrap();
+    assert!(info.contains("maxmemory:10000\r\n"));
+    assert!(info.contains("maxmemory_policy:allkeys-lru\r\n"));
+    assert!(info.contains("evicted_keys:90\r\n"));
+}
+
+#[tokio::test]
+async fn lfu_eviction_test() {
+    // Room for 9 keys of 1000 bytes.
+    let addr = start_server_with_memory(10_000, EvictionPolicy::Lfu).await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    for i in 0..9 {
+        client
+            .set(&format!("key:{}", i), vec![0; 1000])
+            .await
+            .unwrap();
+    }
+    assert_eq!(client.object_freq("key:0").await.unwrap(), Some(5));
+    for _ in 0..100 {
+        for hot in ["key:0", "key:1", "key:2"] {
+            client.get::<Option<Bytes>>(hot).await.unwrap();
+        }
+    }
+    // Updating the value keeps its counter.
+    client.set("key:3", "").await.unwrap();
+    for _ in 0..100 {
+        client.append("key:3", "x").await.unwrap();
+    }
+    let hot = client.object_freq("key:0").await.unwrap().unwrap();
+    assert!(hot > 5, "{}", hot);
+    assert!(client.object_freq("key:3").await.unwrap().unwrap() > 5);
+    assert_eq!(client.object_freq("missing").await.unwrap(), None);
+
+    for i in 9..50 {
+        client
+            .set(&format!("key:{}", i), vec![0; 1000])
+            .await
+            .unwrap();
+    }
+    for hot in ["key:0", "key:1", "key:2", "key:3"] {
+        let value = client.get::<Option<Bytes>>(hot).await.unwrap();
+        assert!(value.is_some(), "{}", hot);
+    }
+    let info = client.info(None).await.unwrap();
+    assert!(info.contains("maxmemory_policy:allkeys-lfu\r\n"));
+
+    client
+        .config_set("maxmemory-policy", "allkeys-lru")
+        .await
+        .unwrap();
+    assert!(client.object_freq("key:0").await.is_err());
+}
+
+#[tokio::test]
+async fn stats_test() {
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        stats: StatsConfig {
+            interval: Duration::from_millis(20),
+            samples: 3,
+        },
+        expiry_sweep_interval: Duration::from_millis(10),
+        ..test_config()
+    };
+    tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+
+    client.set("key", "value").await.unwrap();
+    client.set("short", "lived").await.unwrap();
+    client
+        .expire("short", Duration::from_millis(1))
+        .await
+        .unwrap();
+    client.get::<Option<Bytes>>("key").await.unwrap();
+    client.get::<Option<Bytes>>("missing").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let info = client.info(Some("stats")).await.unwrap();
+    assert!(info.contains("expired_keys:1\r\n"), "{}", info);
+    assert!(info.contains("keyspace_hits:1\r\n"), "{}", info);
+    assert!(info.contains("keyspace_misses:1\r\n"), "{}", info);
+    let samples: Vec<&str> = info
+        .lines()
+        .filter(|line| line.starts_with("sample"))
+        .collect();
+    assert_eq!(samples.len(), 3, "{}", info);
+    assert!(
+        samples[2].ends_with(",keys=1,hits=1,misses=1,expired=1,evicted=0"),
+        "{}",
+        info
+    );
+}
+
+/// Read commands count each key they look up, writes reading keys don't.
+#[tokio::test]
+async fn keyspace_lookups_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    client.sadd("set", ["member"]).await.unwrap();
+    client.pfadd("hll", ["element"]).await.unwrap();
+
+    client.sinter(&["set", "missing"]).await.unwrap();
+    client.pfcount(&["hll", "missing"]).await.unwrap();
+    client.ttl("set").await.unwrap();
+    client.ttl("missing").await.unwrap();
+    client
+        .sinterstore("both", &["set", "missing"])
+        .await
+        .unwrap();
+
+    let info = client.info(Some("stats")).await.unwrap();
+    assert!(info.contains("keyspace_hits:3\r\n"), "{}", info);
+    assert!(info.contains("keyspace_misses:3\r\n"), "{}", info);
+}
+
+#[tokio::test]
+async fn command_stats_test() {
+    let (addr, _handle) = start_server().await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    client.set("key", "value").await.unwrap();
+    client.get::<Option<Bytes>>("key").await.unwrap();
+    client.get::<Option<Bytes>>("key").await.unwrap();
+    client.hset("user", [("name", "uranus")]).await.unwrap();
+    client.get::<Option<Bytes>>("user").await.unwrap_err();
+
+    let info = client.info(Some("commandstats")).await.unwrap();
+    assert!(info.starts_with("# Commandstats\r\n"), "{}", info);
+    assert!(info.contains("cmdstat_set:calls=1,"), "{}", info);
+    let get = info
+        .lines()
+        .find(|line| line.starts_with("cmdstat_get:"))
+        .unwrap();
+    assert!(get.starts_with("cmdstat_get:calls=3,"), "{}", get);
+    assert!(get.ends_with(",rejected_calls=0,failed_calls=1"), "{}", get);
+    assert!(!info.contains("# Stats"), "{}", info);
+
+    let info = client.info(Some("latencystats")).await.unwrap();
+    assert!(
+        info.contains("latency_percentiles_usec_get:p50="),
+        "{}",
+        info
+    );
+    assert!(info.contains(",p99.9="), "{}", info);
+    let info = client.info(None).await.unwrap();
+    assert!(!info.contains("cmdstat_"), "{}", info);
+}
+
+#[tokio::test]
+async fn value_compression_test() {
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = ServerConfig {
+        compression: Compression::Lz4,
+        compression_threshold: 100,
+        ..test_config()
+    };
+    tokio::spawn(async move { uranus_s::run_with_config(listener, config).await });
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+
+    let json = Bytes::from("{\"name\":\"uranus\",\"tags\":[]}".repeat(1000));
+    client.set("json", json.clone()).await.unwrap();
+    client.set("small", "value").await.unwrap();
+    let binary = Bytes::from_static(&[0xff, 0xfe, 0xfd]);
+    client.set("binary", binary.clone()).await.unwrap();
+    assert_eq!(
+        client.get::<Option<Bytes>>("json").await.unwrap(),
+        Some(json.clone())
+    );
+    assert_eq!(
+        client.get::<Option<Bytes>>("small").await.unwrap(),
+        Some("value".into())
+    );
+    assert_eq!(
+        client.get::<Option<Bytes>>("binary").await.unwrap(),
+        Some(binary)
+    );
+    // The memory accounted for is that of the compressed value.
+    let info = client.info(Some("memory")).await.unwrap();
+    let used: usize = info
+        .lines()
+        .find_map(|line| line.strip_prefix("used_memory:"))
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(used < json.len() / 10, "{}", info);
+    assert!(info.contains("value_compression:lz4\r\n"), "{}", info);
+    assert!(info.contains("compressed_values:1\r\n"), "{}", info);
+
+    client
+        .config_set("value-compression", "none")
+        .await
+        .unwrap();
+    client.set("plain", json.clone()).await.unwrap();
+    assert_eq!(
+        client.get::<Option<Bytes>>("json").await.unwrap(),
+        Some(json.clone())
+    );
+    assert_eq!(
+        client.get::<Option<Bytes>>("plain").await.unwrap(),
+        Some(json)
+    );
+    let info = client.info(Some("memory")).await.unwrap();
+    assert!(info.contains("compressed_values:1\r\n"), "{}", info);
+    assert!(client
+        .config_set("value-compression", "gzip")
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn noeviction_test() {
+    let addr = start_server_with_memory(10_000, EvictionPolicy::NoEviction).await;
+    let mut client = uranus_c::Client::connect(addr).await.unwrap();
+    for i in 0..10 {
+        client
+            .set(&format!("key:{}", i), vec![0; 1000])
+            .await
+            .unwrap();
+    }
+    let err = client.set("key:10", "value").await.unwrap_err();
+    assert!(err.to_string().contains("OOM command not allowed"));
+    // Reads and removals are still served.
+    assert!(client
+        .get::<Option<Bytes>>("key:0")
+        .await
+        .unwrap()
+        .is_some());
+    client.flushdb().await.unwrap();
+    client.set("key:10", "value").await.unwrap();
+
+    assert_eq!(
+        client.config_get("maxmemory-policy").await.unwrap(),
+        Some("noeviction".into())
+    );
+    client
+        .config_set("maxmemory-policy", "allkeys-random")
+        .await
+        .unwrap();
+    client.config_set("maxmemory", 1).await.unwrap();
+    client.set("key:11", "value").await.unwrap();
+    assert_eq!(client.dbsize().await.unwrap(), 1);
+    assert!(client
+        .config_set("maxmemory-policy", "volatile-ttl")
+        .await
+        .is_err());
+}
+
+/// An engine waiting on a disk on every read.
+struct SlowKV(uranus_kv::StdHashKV);
+
+impl uranus_kv::Storage for SlowKV {
+    fn put(&mut self, key: Bytes, value: Value) -> anyhow::Result<()> {
+        self.0.put(key, value)
+    }
+
+    fn delete(&mut self, key: Bytes) -> anyhow::Result<()> {
+        self.0.delete(key)
+    }
+
+    fn get(&self, key: Bytes) -> anyhow::Result<Option<Value>> {
+        std::thread::sleep(Duration::from_millis(500));
+        self.0.get(key)
+    }
+
+    fn remove(&mut self, key: Bytes) -> anyhow::Result<Option<Value>> {
+        self.0.remove(key)
+    }
+
+    fn scan(&self) -> anyhow::Result<Vec<(Bytes, Value)>> {
+        self.0.scan()
+    }
+
+    fn blocks(&self) -> bool {
+        true
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn blocking_storage_test() {
+    let listener = TcpListener::bind(TEST_ADDR).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let db = DBHandle::with_storage(1, |_| SlowKV(uranus_kv::StdHashKV::new()));
+    tokio::spawn(uranus_s::run_with_database(
+        listener,
+        ServerConfig::default(),
+        db,
+    ));
+    let mut slow = uranus_c::Client::connect(addr).await.unwrap();
+    let mut fast = uranus_c::Client::connect(addr).await.unwrap();
+    slow.set("key", "value").await.unwrap();
+
+    let read = tokio::spawn(async move { slow.get::<Option<Bytes>>("key").await.unwrap() });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    // The only worker is not stuck in the read.
+    let start = std::time::Instant::now();
+    assert_eq!(fast.ping(None).await.unwrap(), Bytes::from("PONG"));
+    assert!(start.elapsed() < Duration::from_millis(300));
+    assert_eq!(read.await.unwrap(), Some(Bytes::from("value")));
+}
+
+#[tokio::test]
+async fn client_list_test() {
+    let (addr, _handle) = start_server().await;
+    let mut admin = uranus_c::Client::connect(addr).await.unwrap();
+    let mut worker = uranus_c::Client::connect(addr).await.unwrap();
+    assert_eq!(worker.client_getname().await.unwrap(), None);
+    worker.client_setname("worker").await.unwrap();
+    assert_eq!(
+        worker.client_getname().await.unwrap(),
+        Some("worker".to_string())
+    );
+    assert!(worker.client_setname("bad name").await.is_err());
+    worker.get::<Option<Bytes>>("key").await.unwrap();
+
+    let list = admin.client_list().await.unwrap();
+    assert_eq!(list.lines().count(), 2);
+    let line = list
+        .lines()
+        .find(|line| line.contains("name=worker"))
+        .unwrap();
+    assert!(line.ends_with("cmd=get"), "{}", line);
+    let id = line
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("id="))
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    admin.client_kill(id).await.unwrap();
+    assert!(worker.get::<Option<Bytes>>("key").await.is_err());
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let list = admin.client_list().await.unwrap();
+    assert_eq!(list.lines().count(), 1);
+    assert!(admin.client_kill(id).await.is_err());
+}
+
+#[tokio::test]
+async fn inline_command_test() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (addr, _handle) = start_server().await;
+    // As typed in a telnet session.
+    let mut socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+    socket
+        .write_all(b"SET greeting hello\r\nGET greeting\r\n")
+        .await
+        .unwrap();
+    let expected = b"+OK\r\n$5\r\nhello\r\n";
+    let mut reply = [0; 16];
+    socket.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, expected);
+}
@@ -0,0 +1,387 @@
+//! Scenarios spanning several servers, run in-process with
+//! [`support::cluster`].
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use support::cluster::{Cluster, Node, FAIL_TIMEOUT};
+use uranus_c::{Client, ShardedClient};
+use uranus_s::{key_slot, Database, ServerConfig, Topology};
+
+const REPLICATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[tokio::test]
+async fn failover_test() {
+    let mut cluster = Cluster::builder().nodes(2).replicate(0, 1).start();
+    let mut client = cluster.node(0).client().await;
+    for i in 0..100 {
+        client
+            .set(&format!("key:{}", i), i.to_string())
+            .await
+            .unwrap();
+    }
+    client.set("key:0", "overwritten").await.unwrap();
+    cluster.wait_replicated(0, 1, REPLICATION_TIMEOUT).await;
+
+    cluster.kill(0);
+    assert!(!cluster.node(0).is_alive());
+    assert!(client.get::<Option<Bytes>>("key:1").await.is_err());
+    assert!(uranus_c::Client::connect(cluster.node(0).addr())
+        .await
+        .is_err());
+
+    let mut client = cluster.node(1).client().await;
+    client.config_set("replica-read-only", "no").await.unwrap();
+    let value = client.get::<Option<Bytes>>("key:0").await.unwrap();
+    assert_eq!(value, Some(Bytes::from("overwritten")));
+    assert_eq!(client.dbsize().await.unwrap(), 100);
+    client.set("key:100", "after failover").await.unwrap();
+    assert_eq!(client.dbsize().await.unwrap(), 101);
+}
+
+#[tokio::test]
+async fn chained_replicas_test() {
+    let cluster = Cluster::builder()
+        .nodes(3)
+        .replicate(0, 1)
+        .replicate(1, 2)
+        .start();
+    let mut client = cluster.node(0).client().await;
+    for i in 0..50 {
+        client.set(&format!("key:{}", i), "value").await.unwrap();
+    }
+    client.hset("user", [("name", "uranus")]).await.unwrap();
+    cluster.wait_replicated(0, 2, REPLICATION_TIMEOUT).await;
+
+    // Only writes are mirrored.
+    client.get::<Option<Bytes>>("key:0").await.unwrap();
+    assert_eq!(cluster.node(2).client().await.dbsize().await.unwrap(), 51);
+}
+
+#[tokio::test]
+async fn resharding_under_replication_test() {
+    let config = ServerConfig {
+        shards: Some(2),
+        ..ServerConfig::default()
+    };
+    let cluster = Cluster::builder()
+        .node(config)
+        .node(ServerConfig::default())
+        .replicate(0, 1)
+        .start();
+
+    let mut writer = cluster.node(0).client().await;
+    let writes = tokio::spawn(async move {
+        for i in 0..500 {
+            writer.set(&format!("key:{}", i), "value").await.unwrap();
+        }
+    });
+    let mut admin = cluster.node(0).client().await;
+    for shards in [8, 3, 16] {
+        admin.config_set("shards", shards).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    writes.await.unwrap();
+
+    assert_eq!(admin.config_get("shards").await.unwrap(), Some("16".into()));
+    assert_eq!(admin.dbsize().await.unwrap(), 500);
+    cluster.wait_replicated(0, 1, REPLICATION_TIMEOUT).await;
+}
+
+#[tokio::test]
+async fn kill_test() {
+    let mut node = Node::start(ServerConfig::default());
+    let mut client = node.client().await;
+    client.set("key", "value").await.unwrap();
+    assert_eq!(node.db().len().unwrap(), 1);
+
+    node.kill();
+    assert!(client.get::<Option<Bytes>>("key").await.is_err());
+    // The port is free again, as after a crash.
+    std::net::TcpListener::bind(node.addr()).unwrap();
+}
+
+#[tokio::test]
+async fn read_only_replica_test() {
+    let cluster = Cluster::builder().nodes(2).replicate(0, 1).start();
+    let mut replica = cluster.node(1).client().await;
+    let err = replica.set("key", "value").await.unwrap_err();
+    assert!(err.to_string().starts_with("READONLY"));
+    assert!(replica.del(&["key"]).await.is_err());
+    // Only the primary knows the secret making its link trusted.
+    let replconf = vec!["replconf".into(), "primary".into(), "guess".into()];
+    let err = replica.raw_command(replconf).await.unwrap_err();
+    assert!(err.to_string().starts_with("WRONGPASS"), "{}", err);
+    let err = replica.set("key", "value").await.unwrap_err();
+    assert!(err.to_string().starts_with("READONLY"));
+
+    let mut primary = cluster.node(0).client().await;
+    primary.set("key", "value").await.unwrap();
+    assert_eq!(primary.wait(1, Some(REPLICATION_TIMEOUT)).await.unwrap(), 1);
+    assert_eq!(
+        replica.get::<Option<Bytes>>("key").await.unwrap(),
+        Some(Bytes::from("value"))
+    );
+    assert_eq!(
+        replica.config_get("replica-read-only").await.unwrap(),
+        Some("yes".into())
+    );
+}
+
+#[tokio::test]
+async fn wait_test() {
+    let mut cluster = Cluster::builder().nodes(2).replicate(0, 1).start();
+    let mut client = cluster.node(0).client().await;
+    for i in 0..100 {
+        client.set(&format!("key:{}", i), "value").await.unwrap();
+    }
+    assert_eq!(client.wait(1, None).await.unwrap(), 1);
+    assert_eq!(cluster.node(1).db().len().unwrap(), 100);
+    assert_eq!(client.wait(0, None).await.unwrap(), 1);
+
+    // Writes the replica misses are never acknowledged.
+    cluster.kill(1);
+    client.set("key:100", "value").await.unwrap();
+    let timeout = Duration::from_millis(100);
+    assert_eq!(client.wait(1, Some(timeout)).await.unwrap(), 0);
+
+    // A node without replicas has none to wait for.
+    let node = Node::start(ServerConfig::default());
+    let mut client = node.client().await;
+    assert_eq!(client.wait(1, Some(timeout)).await.unwrap(), 0);
+}
+
+/// A key of the form `key:<n>` served by `addr`.
+fn key_served_by(topology: &Topology, addr: &str) -> String {
+    (0..)
+        .map(|i| format!("key:{}", i))
+        .find(|key| topology.owner(key_slot(key.as_bytes())) == Some(addr))
+        .unwrap()
+}
+
+fn cluster_command(args: &[&str]) -> Vec<Bytes> {
+    args.iter()
+        .map(|arg| Bytes::from(arg.to_string()))
+        .collect()
+}
+
+#[tokio::test]
+async fn hash_slot_routing_test() {
+    let cluster = Cluster::builder().nodes(3).sharded().start();
+    let addrs: Vec<String> = (0..3).map(|i| cluster.node(i).addr().to_string()).collect();
+    let topology = Topology::even(&addrs);
+
+    let mut client = Client::connect_cluster(&addrs[1]).await.unwrap();
+    for i in 0..300 {
+        client
+            .set(&format!("key:{}", i), i.to_string())
+            .await
+            .unwrap();
+    }
+    for i in 0..300 {
+        let value = client
+            .get::<Option<Bytes>>(&format!("key:{}", i))
+            .await
+            .unwrap();
+        assert_eq!(value, Some(Bytes::from(i.to_string())));
+    }
+    for (i, addr) in addrs.iter().enumerate() {
+        let keys = cluster.node(i).db().keys().unwrap();
+        assert!(!keys.is_empty());
+        for key in keys {
+            assert_eq!(topology.owner(key_slot(&key)), Some(addr.as_str()));
+        }
+    }
+
+    // Clients outside of cluster mode get the redirections.
+    let mut plain = cluster.node(0).client().await;
+    let key = key_served_by(&topology, &addrs[2]);
+    let err = plain.get::<Option<Bytes>>(&key).await.unwrap_err();
+    let slot = key_slot(key.as_bytes());
+    assert_eq!(err.to_string(), format!("MOVED {} {}", slot, addrs[2]));
+
+    // Keys sharing a hash tag share a slot.
+    client.set("{user:1}:name", "uranus").await.unwrap();
+    client
+        .rename("{user:1}:name", "{user:1}:nick")
+        .await
+        .unwrap();
+    let err = client.rename("{user:1}:nick", "nick").await.unwrap_err();
+    assert!(err.to_string().starts_with("CROSSSLOT"));
+}
+
+#[tokio::test]
+async fn ask_redirection_test() {
+    let cluster = Cluster::builder().nodes(2).sharded().start();
+    let (source, target) = (
+        cluster.node(0).addr().to_string(),
+        cluster.node(1).addr().to_string(),
+    );
+    let topology = Topology::even([&source, &target]);
+    let key = key_served_by(&topology, &source);
+    let slot = key_slot(key.as_bytes()).to_string();
+    let mut client = Client::connect_cluster(&target).await.unwrap();
+    client.set(&key, "migrated").await.unwrap();
+
+    let mut admin = cluster.node(1).client().await;
+    let importing = ["cluster", "setslot", &slot, "importing", &source];
+    admin
+        .raw_command(cluster_command(&importing))
+        .await
+        .unwrap();
+    let mut admin = cluster.node(0).client().await;
+    let migrating = ["cluster", "setslot", &slot, "migrating", &target];
+    admin
+        .raw_command(cluster_command(&migrating))
+        .await
+        .unwrap();
+
+    // Keys still on the source are served there, new ones on the target.
+    assert_eq!(
+        client.get::<Option<Bytes>>(&key).await.unwrap(),
+        Some(Bytes::from("migrated"))
+    );
+    let new_key = format!("{{{}}}:new", key);
+    client.set(&new_key, "new").await.unwrap();
+    assert!(cluster
+        .node(1)
+        .db()
+        .get(new_key.clone().into())
+        .unwrap()
+        .is_some());
+    let mut plain = cluster.node(1).client().await;
+    let err = plain.get::<Option<Bytes>>(&new_key).await.unwrap_err();
+    assert!(err.to_string().starts_with("MOVED"));
+
+    // Once the keys moved, the slot is handed over.
+    let value = cluster
+        .node(0)
+        .db()
+        .get(key.clone().into())
+        .unwrap()
+        .unwrap();
+    cluster.node(1).db().put(key.clone().into(), value).unwrap();
+    cluster.node(0).db().delete(key.clone().into()).unwrap();
+    for node in 0..2 {
+        let mut admin = cluster.node(node).client().await;
+        let assign = ["cluster", "setslot", &slot, "node", &target];
+        admin.raw_command(cluster_command(&assign)).await.unwrap();
+    }
+    assert_eq!(
+        client.get::<Option<Bytes>>(&key).await.unwrap(),
+        Some(Bytes::from("migrated"))
+    );
+    assert_eq!(
+        plain.get::<Option<Bytes>>(&new_key).await.unwrap(),
+        Some(Bytes::from("new"))
+    );
+}
+
+/// The flags of each node in the `CLUSTER NODES` of `node`, by id.
+async fn gossip_view(cluster: &Cluster, node: usize) -> Vec<(String, String)> {
+    let mut client = cluster.node(node).client().await;
+    let nodes = client.cluster_nodes().await.unwrap();
+    let mut view: Vec<(String, String)> = nodes
+        .lines()
+        .map(|line| {
+            let fields: Vec<&str> = line.split(' ').collect();
+            (fields[0].to_string(), fields[3].to_string())
+        })
+        .collect();
+    view.sort();
+    view
+}
+
+#[tokio::test]
+async fn gossip_membership_test() {
+    let mut cluster = Cluster::builder().nodes(3).sharded().gossip().start();
+    let converged = async {
+        for node in 0..3 {
+            loop {
+                let view = gossip_view(&cluster, node).await;
+                if view.iter().filter(|(_, flags)| flags == "ok").count() == 2 {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    };
+    tokio::time::timeout(REPLICATION_TIMEOUT, converged)
+        .await
+        .expect("nodes didn't learn about each other");
+
+    // Node 2 joined through node 0, and node 1 learned about it from there.
+    let mut client = cluster.node(1).client().await;
+    let nodes = client.cluster_nodes().await.unwrap();
+    let lines: Vec<&str> = nodes.lines().collect();
+    assert!(lines[0].starts_with("node-1 "));
+    assert!(lines[0].contains(" myself "));
+    let node2 = format!("node-2 {} ", cluster.node(2).addr());
+    let line = lines.iter().find(|line| line.starts_with(&node2)).unwrap();
+    assert!(line.ends_with(" ok 10922-16383"));
+
+    cluster.kill(2);
+    tokio::time::sleep(FAIL_TIMEOUT).await;
+    let failed = async {
+        loop {
+            let view = gossip_view(&cluster, 0).await;
+            if view.contains(&("node-2".to_string(), "fail".to_string())) {
+                assert!(view.contains(&("node-1".to_string(), "ok".to_string())));
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(REPLICATION_TIMEOUT, failed)
+        .await
+        .expect("the failure of node 2 wasn't detected");
+}
+
+#[tokio::test]
+async fn sharded_client_test() {
+    let mut nodes: Vec<Node> = (0..3)
+        .map(|_| Node::start(ServerConfig::default()))
+        .collect();
+    let addrs: Vec<_> = nodes.iter().map(Node::addr).collect();
+    let mut client = ShardedClient::connect(&addrs).await.unwrap();
+    let keys: Vec<String> = (0..300).map(|i| format!("key:{}", i)).collect();
+    for key in &keys {
+        client.set(key, key.clone()).await.unwrap();
+    }
+    for key in &keys {
+        assert_eq!(
+            client.get(key).await.unwrap(),
+            Some(Bytes::from(key.clone()))
+        );
+    }
+    for (node, addr) in nodes.iter().zip(&addrs) {
+        let stored = node.db().keys().unwrap();
+        assert!(!stored.is_empty());
+        for key in stored {
+            let key = String::from_utf8(key.to_vec()).unwrap();
+            assert_eq!(client.node(&key), addr.to_string());
+        }
+    }
+    let some: Vec<&str> = keys[..10].iter().map(String::as_str).collect();
+    assert_eq!(client.del(&some).await.unwrap(), 10);
+    assert_eq!(client.get(&keys[0]).await.unwrap(), None);
+
+    // Only the keys of a failed server are unavailable, until it is back.
+    nodes[1].kill();
+    let on_failed = |key: &&String| client.node(key) == addrs[1].to_string();
+    let failed = keys.iter().find(on_failed).unwrap().clone();
+    let other = keys[10..]
+        .iter()
+        .find(|key| !on_failed(key))
+        .unwrap()
+        .clone();
+    assert!(client.get(&failed).await.is_err());
+    assert!(client.get(&failed).await.is_err());
+    assert!(client.get(&other).await.unwrap().is_some());
+    nodes[1] = Node::start_at(addrs[1], ServerConfig::default());
+    client.set(&failed, "again").await.unwrap();
+    assert_eq!(
+        client.get(&failed).await.unwrap(),
+        Some(Bytes::from("again"))
+    );
+}
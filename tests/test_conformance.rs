@@ -0,0 +1,191 @@
+//! Conformance of the wire protocol to RESP, which uranus speaks so that
+//! Redis clients and tools can talk to it.
+//!
+//! The corpus below pins the bytes of the replies to requests Redis clients
+//! commonly send, as Redis itself sends them. The tests against `redis-cli`
+//! are ignored by default as they need it installed; run them with
+//!
+//! ```sh
+//! cargo test -p tests --test test_conformance -- --ignored
+//! ```
+//!
+//! `URANUS_REDIS_CLI` names the binary to run instead of the `redis-cli` of
+//! the path.
+
+use std::{net::SocketAddr, process::Command, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+async fn start_server() -> (SocketAddr, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handle = tokio::spawn(uranus_s::run(listener));
+    (addr, handle)
+}
+
+/// Requests, as their arguments, and the replies Redis sends to them, in
+/// order on one connection.
+const CORPUS: &[(&[&str], &str)] = &[
+    (&["PING"], "+PONG\r\n"),
+    (&["ping"], "+PONG\r\n"),
+    (&["PING", "hello"], "$5\r\nhello\r\n"),
+    (&["GET", "key"], "$-1\r\n"),
+    (&["SET", "key", "value"], "+OK\r\n"),
+    (&["GET", "key"], "$5\r\nvalue\r\n"),
+    (&["SET", "key", ""], "+OK\r\n"),
+    (&["GET", "key"], "$0\r\n\r\n"),
+    (&["SET", "key", "a\r\nb"], "+OK\r\n"),
+    (&["GET", "key"], "$4\r\na\r\nb\r\n"),
+    (&["SET", "key", "value", "NX"], "$-1\r\n"),
+    (&["SET", "other", "value", "XX"], "$-1\r\n"),
+    (&["SET", "other", "value", "NX"], "+OK\r\n"),
+    (&["DEL", "key", "other", "missing"], ":2\r\n"),
+    (&["DEL", "key"], ":0\r\n"),
+    (&["TTL", "key"], ":-2\r\n"),
+    (&["EXPIRE", "key", "100"], ":0\r\n"),
+    (&["SET", "key", "value"], "+OK\r\n"),
+    (&["TTL", "key"], ":-1\r\n"),
+    (&["EXPIRE", "key", "100"], ":1\r\n"),
+    (&["TTL", "key"], ":100\r\n"),
+    (&["PERSIST", "key"], ":1\r\n"),
+    (&["TTL", "key"], ":-1\r\n"),
+    (&["SET", "key", "value", "EX", "100"], "+OK\r\n"),
+    (&["TTL", "key"], ":100\r\n"),
+    (&["SET", "key", "value"], "+OK\r\n"),
+    (&["TTL", "key"], ":-1\r\n"),
+    (
+        &["GET"],
+        "-ERR wrong number of arguments for 'get' command\r\n",
+    ),
+    (
+        &["EXPIRE", "key", "soon"],
+        "-ERR value is not an integer or out of range\r\n",
+    ),
+    (&["GET", "key"], "$5\r\nvalue\r\n"),
+    (&["SADD", "set", "a", "b"], ":2\r\n"),
+    (&["SADD", "other", "b", "c"], ":2\r\n"),
+    (&["SINTER", "set", "other"], "*1\r\n$1\r\nb\r\n"),
+    (&["SINTERCARD", "2", "set", "other", "LIMIT", "5"], ":1\r\n"),
+    (&["SINTERSTORE", "both", "set", "other"], ":1\r\n"),
+    (&["SDIFF", "both", "set"], "*0\r\n"),
+    (
+        &["SINTERCARD", "many", "set"],
+        "-ERR value is not an integer or out of range\r\n",
+    ),
+];
+
+fn encode_request(args: &[&str]) -> Vec<u8> {
+    let mut request = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        request.extend_from_slice(format!("${}\r\n{}\r\n", arg.len(), arg).as_bytes());
+    }
+    request
+}
+
+/// Reads exactly the reply expected, so that a reply too short fails rather
+/// than waits.
+async fn read_reply(socket: &mut TcpStream, expected: &str) -> String {
+    let mut reply = vec![0; expected.len()];
+    let read = tokio::time::timeout(Duration::from_secs(5), socket.read_exact(&mut reply));
+    match read.await {
+        Ok(Ok(_)) => String::from_utf8_lossy(&reply).into_owned(),
+        Ok(Err(err)) => format!("<{}>", err),
+        Err(_) => "<no reply>".to_string(),
+    }
+}
+
+#[tokio::test]
+async fn resp_corpus_test() {
+    let (addr, _handle) = start_server().await;
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+    for (args, expected) in CORPUS {
+        socket.write_all(&encode_request(args)).await.unwrap();
+        let reply = read_reply(&mut socket, expected).await;
+        assert_eq!(&reply, expected, "reply to {:?}", args);
+    }
+}
+
+#[tokio::test]
+async fn resp_pipelined_corpus_test() {
+    let (addr, _handle) = start_server().await;
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+    let requests: Vec<u8> = CORPUS
+        .iter()
+        .flat_map(|(args, _)| encode_request(args))
+        .collect();
+    socket.write_all(&requests).await.unwrap();
+    let expected: String = CORPUS.iter().map(|(_, reply)| *reply).collect();
+    assert_eq!(read_reply(&mut socket, &expected).await, expected);
+}
+
+#[tokio::test]
+async fn inline_commands_test() {
+    let (addr, _handle) = start_server().await;
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+    socket
+        .write_all(b"PING\r\nSET key value\r\nGET key\r\nDEL key\r\n")
+        .await
+        .unwrap();
+    let expected = "+PONG\r\n+OK\r\n$5\r\nvalue\r\n:1\r\n";
+    assert_eq!(read_reply(&mut socket, expected).await, expected);
+}
+
+/// Runs `redis-cli` against the server at `addr` with `args`, returns what
+/// it prints, formatted as it does on a terminal.
+async fn redis_cli(addr: SocketAddr, args: &[&str]) -> String {
+    let cli = std::env::var("URANUS_REDIS_CLI").unwrap_or_else(|_| "redis-cli".to_string());
+    let mut command = Command::new(cli);
+    command
+        .args(["-h", &addr.ip().to_string(), "-p", &addr.port().to_string()])
+        .arg("--no-raw")
+        .args(args);
+    let output = tokio::task::spawn_blocking(move || command.output())
+        .await
+        .unwrap()
+        .expect("redis-cli must be installed, or named by URANUS_REDIS_CLI");
+    assert!(output.status.success(), "redis-cli {:?} failed", args);
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .trim_end()
+        .to_string()
+}
+
+#[tokio::test]
+#[ignore = "needs redis-cli"]
+async fn redis_cli_test() {
+    let (addr, _handle) = start_server().await;
+    let cli = |args: &'static [&'static str]| redis_cli(addr, args);
+
+    assert_eq!(cli(&["PING"]).await, "PONG");
+    assert_eq!(cli(&["PING", "hello"]).await, "\"hello\"");
+    assert_eq!(cli(&["GET", "key"]).await, "(nil)");
+    assert_eq!(cli(&["SET", "key", "value"]).await, "OK");
+    assert_eq!(cli(&["GET", "key"]).await, "\"value\"");
+    assert_eq!(cli(&["SET", "key", "other", "NX"]).await, "(nil)");
+    assert_eq!(cli(&["DEL", "key", "missing"]).await, "(integer) 1");
+    assert_eq!(cli(&["DEL", "key"]).await, "(integer) 0");
+
+    assert_eq!(cli(&["SET", "key", "value"]).await, "OK");
+    assert_eq!(cli(&["EXPIRE", "key", "100"]).await, "(integer) 1");
+    assert_eq!(cli(&["TTL", "key"]).await, "(integer) 100");
+    assert_eq!(cli(&["EXPIRE", "missing", "100"]).await, "(integer) 0");
+    assert_eq!(cli(&["PEXPIRE", "key", "50"]).await, "(integer) 1");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(cli(&["GET", "key"]).await, "(nil)");
+    assert_eq!(cli(&["TTL", "key"]).await, "(integer) -2");
+}
+
+#[tokio::test]
+#[ignore = "needs redis-cli"]
+async fn redis_cli_binary_values_test() {
+    let (addr, _handle) = start_server().await;
+    // redis-cli quotes and escapes the values it prints.
+    assert_eq!(redis_cli(addr, &["SET", "key", "a\nb"]).await, "OK");
+    assert_eq!(redis_cli(addr, &["GET", "key"]).await, "\"a\\nb\"");
+    assert_eq!(redis_cli(addr, &["SET", "key", ""]).await, "OK");
+    assert_eq!(redis_cli(addr, &["GET", "key"]).await, "\"\"");
+}
@@ -0,0 +1,205 @@
+//! Killing the server in the middle of a workload, then checking that every
+//! write it acknowledged survived the restart.
+//!
+//! The server runs as a child process, `crash_server`, which gets SIGKILL at a
+//! random point: nothing is flushed or closed on the way out, unlike when
+//! [`support::cluster::Node`] is killed. The writes which reached the kernel
+//! outlive the process whatever the fsync policy, so none of those answered
+//! may be lost, while the one in flight may have been applied or not.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    net::SocketAddr,
+    path::Path,
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use support::Rng;
+use uranus_c::Client;
+
+const ROUNDS: u64 = 4;
+const WRITERS: u64 = 4;
+const KEYS: u64 = 256;
+/// The longest a workload runs before the server is killed.
+const MAX_UPTIME: Duration = Duration::from_millis(300);
+
+/// A server process, killed when dropped.
+struct Server {
+    child: Child,
+    addr: SocketAddr,
+}
+
+impl Server {
+    fn start(args: &[&str]) -> Server {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_crash_server"))
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        let stdout = child.stdout.take().unwrap();
+        BufReader::new(stdout).read_line(&mut line).unwrap();
+        let addr = line
+            .trim()
+            .strip_prefix("listening on ")
+            .unwrap_or_else(|| panic!("unexpected output {:?}", line))
+            .parse()
+            .unwrap();
+        Server { child, addr }
+    }
+
+    /// A client, once the server has loaded its data.
+    async fn client(&mut self) -> Client {
+        let mut client = Client::connect(self.addr).await.unwrap();
+        while client.ready().await.is_err() {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                panic!("the server exited with {}", status);
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        client
+    }
+
+    /// Sends SIGKILL and reaps the process.
+    fn kill(&mut self) {
+        let _ = self.child.kill();
+        self.child.wait().unwrap();
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+/// What a writer knows of its keys: the values of the acknowledged writes,
+/// none for a deletion, and the write it was waiting for when the server died.
+#[derive(Default)]
+struct Model {
+    acked: HashMap<String, Option<Bytes>>,
+    pending: Option<(String, Option<Bytes>)>,
+    writes: u64,
+}
+
+impl Model {
+    /// Checks the keys against the server, and settles the pending write on
+    /// what it finds.
+    async fn verify(&mut self, client: &mut Client) {
+        for (key, expected) in &self.acked {
+            if self
+                .pending
+                .as_ref()
+                .is_some_and(|(pending, _)| pending == key)
+            {
+                continue;
+            }
+            let value: Option<Bytes> = client.get(key.as_str()).await.unwrap();
+            assert_eq!(&value, expected, "acknowledged write to {} lost", key);
+        }
+        if let Some((key, written)) = self.pending.take() {
+            let value: Option<Bytes> = client.get(key.as_str()).await.unwrap();
+            let before = self.acked.get(&key).cloned().flatten();
+            assert!(
+                value == before || value == written,
+                "{} is neither its last acknowledged value nor the one in flight",
+                key
+            );
+            self.acked.insert(key, value);
+        }
+    }
+}
+
+/// Sets and deletes keys of `writer` until the server dies.
+async fn write(mut client: Client, writer: u64, mut rng: Rng, mut model: Model) -> Model {
+    loop {
+        let key = format!("writer:{}:key:{}", writer, rng.below(KEYS));
+        let value = match rng.below(8) {
+            0 => None,
+            1 => Some(rng.bytes(2048)),
+            _ => {
+                let len = rng.below(128) as usize;
+                Some(rng.bytes(len))
+            }
+        };
+        model.pending = Some((key.clone(), value.clone()));
+        let res = match &value {
+            Some(value) => client.set(key.as_str(), value.clone()).await,
+            None => client.del(&[key.as_str()]).await.map(|_| ()),
+        };
+        if res.is_err() {
+            return model;
+        }
+        model.pending = None;
+        model.acked.insert(key, value);
+        model.writes += 1;
+    }
+}
+
+/// Runs `ROUNDS` workloads against servers started with `args(round)`, each
+/// killed at a random point, and checks the keys after every restart.
+async fn crash_recovery(name: &str, args: impl Fn(&Path, u64) -> Vec<String>) {
+    let dir = std::env::temp_dir().join(format!("uranus-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let start = |round| {
+        let args = args(&dir, round);
+        Server::start(&args.iter().map(String::as_str).collect::<Vec<_>>())
+    };
+    let mut models: Vec<Model> = (0..WRITERS).map(|_| Model::default()).collect();
+    for round in 0..ROUNDS {
+        let mut server = start(round);
+        let mut client = server.client().await;
+        for model in &mut models {
+            model.verify(&mut client).await;
+        }
+
+        let mut rng = Rng::new(round);
+        let mut writers = vec![];
+        for (writer, model) in models.drain(..).enumerate() {
+            let client = server.client().await;
+            let rng = Rng::new(rng.below(u64::MAX));
+            writers.push(tokio::spawn(write(client, writer as u64, rng, model)));
+        }
+        let uptime = rng.below(MAX_UPTIME.as_millis() as u64);
+        tokio::time::sleep(Duration::from_millis(uptime)).await;
+        server.kill();
+        for writer in writers {
+            models.push(writer.await.unwrap());
+        }
+    }
+
+    let mut server = start(ROUNDS);
+    let mut client = server.client().await;
+    for model in &mut models {
+        model.verify(&mut client).await;
+    }
+    assert!(models.iter().map(|model| model.writes).sum::<u64>() > 0);
+    drop(server);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn lsm_crash_test() {
+    // Whatever the policy, as the kernel outlives the process.
+    let policies = ["always", "everysec", "no"];
+    crash_recovery("crash-lsm", |dir, round| {
+        let policy = policies[round as usize % policies.len()];
+        vec![
+            "lsm".to_string(),
+            dir.display().to_string(),
+            policy.to_string(),
+        ]
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn aof_crash_test() {
+    crash_recovery("crash-aof", |dir, _| {
+        vec!["aof".to_string(), dir.display().to_string()]
+    })
+    .await;
+}
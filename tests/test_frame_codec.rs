@@ -0,0 +1,202 @@
+//! Property tests of the frame codec: any frame a `Connection` writes is read
+//! back as the same frame, however its bytes are split across reads, and no
+//! input makes decoding panic.
+
+use std::io::Cursor;
+
+use bytes::{Bytes, BytesMut};
+use proptest::{collection::vec, prelude::*, sample::Index};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    runtime::Runtime,
+};
+use uranus_s::{
+    format_double, Connection, Frame, FrameCheck, FrameLimits, Protocol, ZERO_COPY_MIN,
+};
+
+fn binary() -> impl Strategy<Value = Frame> {
+    prop_oneof![
+        8 => vec(any::<u8>(), 0..64),
+        // Sliced out of the read buffer rather than copied.
+        1 => vec(any::<u8>(), ZERO_COPY_MIN..ZERO_COPY_MIN + 64),
+    ]
+    .prop_map(|bytes| Frame::Binary(bytes.into()))
+}
+
+fn scalar() -> impl Strategy<Value = Frame> {
+    use proptest::num::f64::{INFINITE, NEGATIVE, NORMAL, POSITIVE, SUBNORMAL, ZERO};
+    // NaN isn't equal to itself, it is covered by the unit tests.
+    let double = POSITIVE | NEGATIVE | NORMAL | SUBNORMAL | ZERO | INFINITE;
+    prop_oneof![
+        1 => "[^\r\n]*".prop_map(Frame::Text),
+        1 => "[^\r\n]*".prop_map(Frame::Error),
+        1 => any::<i64>().prop_map(Frame::Integer),
+        1 => double.prop_map(Frame::Double),
+        4 => binary(),
+        1 => Just(Frame::Null),
+    ]
+}
+
+fn frame() -> impl Strategy<Value = Frame> {
+    prop_oneof![
+        3 => scalar(),
+        1 => vec(scalar(), 0..16).prop_map(Frame::Array),
+    ]
+}
+
+fn protocol() -> impl Strategy<Value = Protocol> {
+    prop_oneof![Just(Protocol::V2), Just(Protocol::V3)]
+}
+
+/// `frame` as read back after it was written in `protocol`: version 2 sends
+/// doubles as their text.
+fn expected(frame: &Frame, protocol: Protocol) -> Frame {
+    match frame {
+        Frame::Double(val) if protocol == Protocol::V2 => {
+            Frame::Binary(Bytes::from(format_double(*val)))
+        }
+        Frame::Array(entries) => Frame::Array(
+            entries
+                .iter()
+                .map(|entry| expected(entry, protocol))
+                .collect(),
+        ),
+        frame => frame.clone(),
+    }
+}
+
+fn encode(frame: &Frame, protocol: Protocol) -> Vec<u8> {
+    let mut dst = BytesMut::new();
+    frame.encode(protocol, &mut dst).unwrap();
+    dst.to_vec()
+}
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+async fn socket_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = TcpStream::connect(addr).await.unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+    (server, client)
+}
+
+/// The bytes a connection speaking `protocol` sends for `frames`.
+async fn written(frames: &[Frame], protocol: Protocol) -> Vec<u8> {
+    let (writer, mut reader) = socket_pair().await;
+    let mut writer = Connection::new(writer);
+    writer.set_protocol(protocol);
+    for frame in frames {
+        writer.write_frame(frame).await.unwrap();
+    }
+    drop(writer);
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes).await.unwrap();
+    bytes
+}
+
+proptest! {
+    #[test]
+    fn test_round_trip(
+        frames in vec(frame(), 1..8),
+        protocol in protocol(),
+        cuts in vec(any::<Index>(), 0..4),
+    ) {
+        runtime().block_on(async {
+            let bytes = written(&frames, protocol).await;
+            let mut cuts: Vec<usize> = cuts.iter().map(|cut| cut.index(bytes.len() + 1)).collect();
+            cuts.sort();
+            cuts.push(bytes.len());
+
+            let (reader, mut writer) = socket_pair().await;
+            let mut reader = Connection::new(reader);
+            let writing = tokio::spawn(async move {
+                let mut start = 0;
+                for cut in cuts {
+                    writer.write_all(&bytes[start..cut]).await.unwrap();
+                    writer.flush().await.unwrap();
+                    // Lets the reader see the part written so far on its own.
+                    tokio::task::yield_now().await;
+                    start = cut;
+                }
+            });
+            for frame in &frames {
+                let read = reader.read_frame().await.unwrap();
+                assert_eq!(read, Some(expected(frame, protocol)));
+            }
+            writing.await.unwrap();
+            assert_eq!(reader.read_frame().await.unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn test_prefixes_are_incomplete(frame in frame(), protocol in protocol()) {
+        let bytes = encode(&frame, protocol);
+        let limits = FrameLimits::default();
+        for end in 0..bytes.len() {
+            let mut src = Cursor::new(&bytes[..end]);
+            prop_assert!(Frame::check(&mut src, &limits).unwrap().is_none(), "{}", end);
+        }
+        let mut src = Cursor::new(&bytes[..]);
+        prop_assert!(Frame::check(&mut src, &limits).unwrap().is_some());
+        prop_assert_eq!(src.position() as usize, bytes.len());
+        let parsed = Frame::parse(&mut Bytes::from(bytes)).unwrap();
+        prop_assert_eq!(parsed, Some(expected(&frame, protocol)));
+    }
+
+    #[test]
+    fn test_resumed_check(
+        frames in vec(frame(), 1..4),
+        protocol in protocol(),
+        cuts in vec(any::<Index>(), 0..8),
+    ) {
+        let bytes: Vec<u8> = frames.iter().flat_map(|frame| encode(frame, protocol)).collect();
+        let mut cuts: Vec<usize> = cuts.iter().map(|cut| cut.index(bytes.len() + 1)).collect();
+        cuts.sort();
+        cuts.push(bytes.len());
+
+        // Frames are taken off the front as they complete, as a connection does.
+        let limits = FrameLimits::default();
+        let mut check = FrameCheck::default();
+        let (mut start, mut lens) = (0, vec![]);
+        for cut in cuts {
+            while let Some(len) = check.advance(&bytes[start..cut], &limits).unwrap() {
+                lens.push(len);
+                start += len;
+            }
+        }
+        let expected: Vec<usize> = frames.iter().map(|frame| encode(frame, protocol).len()).collect();
+        prop_assert_eq!(lens, expected);
+    }
+
+    #[test]
+    fn test_arbitrary_input_never_panics(bytes in vec(any::<u8>(), 0..256)) {
+        let limits = FrameLimits::default();
+        let _ = Frame::check(&mut Cursor::new(&bytes[..]), &limits);
+        let _ = Frame::parse(&mut Bytes::from(bytes));
+    }
+
+    #[test]
+    fn test_corrupted_frames_never_panic(
+        frame in frame(),
+        protocol in protocol(),
+        flips in vec((any::<Index>(), any::<u8>()), 1..4),
+        end in any::<Index>(),
+    ) {
+        let mut bytes = encode(&frame, protocol);
+        for (at, byte) in flips {
+            let at = at.index(bytes.len());
+            bytes[at] = byte;
+        }
+        bytes.truncate(end.index(bytes.len() + 1));
+        let limits = FrameLimits::default();
+        let _ = Frame::check(&mut Cursor::new(&bytes[..]), &limits);
+        let _ = Frame::parse(&mut Bytes::from(bytes));
+    }
+}
@@ -0,0 +1,538 @@
+//! Restarting servers on their append-only file, run in-process with
+//! [`support::cluster`].
+
+use std::{fs, io::Write, path::PathBuf, time::Duration};
+
+use bytes::Bytes;
+use support::cluster::Node;
+use uranus_c::ServerError;
+use uranus_s::{
+    verify_aof, AofConfig, AppendOnlyFile, Batch, CorruptedAof, Corruption, DBHandle, Database,
+    ErrorCode, FrameLimits, KeyTtl, ListEnd, QueueEnd, ServerConfig, Shared, StorageEngine, Value,
+    ZRangeBy,
+};
+
+const REWRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A config logging to a fresh file named after `test`.
+fn aof_config(test: &str) -> (ServerConfig, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("uranus-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("appendonly.aof");
+    let config = ServerConfig {
+        aof: Some(AofConfig::new(&path)),
+        ..Default::default()
+    };
+    (config, path)
+}
+
+fn info_field(info: &str, field: &str) -> u64 {
+    let prefix = format!("{}:", field);
+    info.lines()
+        .find_map(|line| line.strip_prefix(&prefix))
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn aof_restart_test() {
+    let (config, _) = aof_config("aof-restart");
+    let mut node = Node::start(config.clone());
+    let mut client = node.client().await;
+    client.set("string", "value").await.unwrap();
+    client.hset("hash", [("a", "1"), ("b", "2")]).await.unwrap();
+    client.hdel("hash", ["a"]).await.unwrap();
+    client.sadd("set", ["x", "y", "z"]).await.unwrap();
+    client
+        .zadd("zset", [(2.0, "two"), (1.0, "one")])
+        .await
+        .unwrap();
+    client
+        .pqadd("queue", [(1.0, "low"), (9.0, "high")])
+        .await
+        .unwrap();
+    client.pqpop("queue", QueueEnd::Max, 1).await.unwrap();
+    client.set("old", "renamed").await.unwrap();
+    client.rename("old", "new").await.unwrap();
+    client.set("gone", "deleted").await.unwrap();
+    assert_eq!(client.del(&["gone", "missing"]).await.unwrap(), 1);
+    client
+        .expire("string", Duration::from_secs(3600))
+        .await
+        .unwrap();
+    client.select(1).await.unwrap();
+    client.set("string", "in db 1").await.unwrap();
+    client.select(0).await.unwrap();
+
+    // A blocking pop waits while other writes are logged.
+    let mut blocked = node.client().await;
+    let pop = tokio::spawn(async move {
+        blocked
+            .blpop("jobs", Some(Duration::from_secs(5)))
+            .await
+            .unwrap()
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    client
+        .push("jobs", ListEnd::Right, ["first", "second"])
+        .await
+        .unwrap();
+    assert_eq!(pop.await.unwrap(), Some(Bytes::from("first")));
+    client
+        .push("jobs", ListEnd::Right, ["third"])
+        .await
+        .unwrap();
+
+    node.kill();
+    let node = Node::start(config);
+    let mut client = node.client().await;
+    assert_eq!(client.dbsize().await.unwrap(), 7);
+    assert_eq!(
+        client.get::<Option<Bytes>>("string").await.unwrap(),
+        Some(Bytes::from("value"))
+    );
+    assert!(matches!(
+        client.ttl("string").await.unwrap(),
+        KeyTtl::Remaining(_)
+    ));
+    assert_eq!(
+        client.hgetall("hash").await.unwrap(),
+        vec![(Bytes::from("b"), Bytes::from("2"))]
+    );
+    let mut members = client.smembers("set").await.unwrap();
+    members.sort();
+    assert_eq!(members, ["x", "y", "z"].map(Bytes::from));
+    assert_eq!(
+        client.zrange("zset", ZRangeBy::Rank(0, -1)).await.unwrap(),
+        vec![(Bytes::from("one"), 1.0), (Bytes::from("two"), 2.0)]
+    );
+    assert_eq!(
+        client.pqpeek("queue", QueueEnd::Max).await.unwrap(),
+        Some((Bytes::from("low"), 1.0))
+    );
+    assert_eq!(client.get::<Option<Bytes>>("old").await.unwrap(), None);
+    assert_eq!(
+        client.get::<Option<Bytes>>("new").await.unwrap(),
+        Some(Bytes::from("renamed"))
+    );
+    assert_eq!(client.get::<Option<Bytes>>("gone").await.unwrap(), None);
+    assert_eq!(
+        client.pop("jobs", ListEnd::Left).await.unwrap(),
+        Some(Bytes::from("second"))
+    );
+    assert_eq!(
+        client.pop("jobs", ListEnd::Left).await.unwrap(),
+        Some(Bytes::from("third"))
+    );
+    client.select(1).await.unwrap();
+    assert_eq!(
+        client.get::<Option<Bytes>>("string").await.unwrap(),
+        Some(Bytes::from("in db 1"))
+    );
+}
+
+#[tokio::test]
+async fn aof_rewrite_test() {
+    let (config, path) = aof_config("aof-rewrite");
+    let mut node = Node::start(config.clone());
+    let mut client = node.client().await;
+    for i in 0..1000 {
+        client.set("counter", i.to_string()).await.unwrap();
+    }
+    client.select(3).await.unwrap();
+    for i in 0..200 {
+        client
+            .push("list", ListEnd::Right, [i.to_string()])
+            .await
+            .unwrap();
+    }
+    let before = info_field(
+        &client.info(Some("persistence")).await.unwrap(),
+        "aof_current_size",
+    );
+
+    client.bgrewriteaof().await.unwrap();
+    // Writes go on during the rewrite.
+    client.set("during", "rewrite").await.unwrap();
+    let deadline = tokio::time::Instant::now() + REWRITE_TIMEOUT;
+    loop {
+        let info = client.info(Some("persistence")).await.unwrap();
+        if info_field(&info, "aof_rewrite_in_progress") == 0 {
+            assert!(info_field(&info, "aof_current_size") < before);
+            break;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "rewrite timed out");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    client.select(0).await.unwrap();
+    client.set("after", "rewrite").await.unwrap();
+    assert!(fs::metadata(&path).unwrap().len() < before);
+
+    node.kill();
+    let node = Node::start(config);
+    let mut client = node.client().await;
+    assert_eq!(
+        client.get::<Option<Bytes>>("counter").await.unwrap(),
+        Some(Bytes::from("999"))
+    );
+    assert_eq!(
+        client.get::<Option<Bytes>>("after").await.unwrap(),
+        Some(Bytes::from("rewrite"))
+    );
+    client.select(3).await.unwrap();
+    assert_eq!(
+        client.get::<Option<Bytes>>("during").await.unwrap(),
+        Some(Bytes::from("rewrite"))
+    );
+    for i in 0..200 {
+        assert_eq!(
+            client.pop("list", ListEnd::Left).await.unwrap(),
+            Some(Bytes::from(i.to_string()))
+        );
+    }
+}
+
+#[tokio::test]
+async fn aof_expiry_test() {
+    let (config, _) = aof_config("aof-expiry");
+    let mut node = Node::start(config.clone());
+    let mut client = node.client().await;
+    let short = Duration::from_millis(300);
+    client.set_with_ttl("set", "value", short).await.unwrap();
+    client.set("expired", "value").await.unwrap();
+    client.expire("expired", short).await.unwrap();
+    client.set("kept", "value").await.unwrap();
+    client
+        .expire("kept", Duration::from_secs(3600))
+        .await
+        .unwrap();
+    client.set("persistent", "value").await.unwrap();
+
+    // The rewritten file keeps the deadlines too.
+    client.bgrewriteaof().await.unwrap();
+    let deadline = tokio::time::Instant::now() + REWRITE_TIMEOUT;
+    loop {
+        let info = client.info(Some("persistence")).await.unwrap();
+        if info_field(&info, "aof_rewrite_in_progress") == 0 {
+            break;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "rewrite timed out");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let blob = client.dump("persistent").await.unwrap().unwrap();
+    client
+        .restore("restored", Some(short), blob, false)
+        .await
+        .unwrap();
+    client
+        .set_with_ttl("later", "value", Duration::from_secs(2))
+        .await
+        .unwrap();
+
+    // Restarting past the short TTLs doesn't bring their keys back, nor
+    // extend the others.
+    node.kill();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let node = Node::start(config);
+    let mut client = node.client().await;
+    assert_eq!(client.dbsize().await.unwrap(), 3);
+    for key in ["set", "expired", "restored"] {
+        assert_eq!(
+            client.get::<Option<Bytes>>(key).await.unwrap(),
+            None,
+            "{}",
+            key
+        );
+    }
+    match client.ttl("later").await.unwrap() {
+        KeyTtl::Remaining(ttl) => assert!(ttl <= Duration::from_millis(1500), "{:?}", ttl),
+        ttl => panic!("unexpected TTL {:?}", ttl),
+    }
+    assert!(matches!(
+        client.ttl("kept").await.unwrap(),
+        KeyTtl::Remaining(_)
+    ));
+    assert_eq!(client.ttl("persistent").await.unwrap(), KeyTtl::Persistent);
+}
+
+#[tokio::test]
+async fn aof_truncated_entry_test() {
+    let (config, path) = aof_config("aof-truncated");
+    let mut node = Node::start(config.clone());
+    let mut client = node.client().await;
+    client.set("key", "value").await.unwrap();
+    node.kill();
+    let size = fs::metadata(&path).unwrap().len();
+    let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"*3\r\n$3\r\nset\r\n$3\r\nkey").unwrap();
+    drop(file);
+
+    let node = Node::start(config);
+    let mut client = node.client().await;
+    assert_eq!(
+        client.get::<Option<Bytes>>("key").await.unwrap(),
+        Some(Bytes::from("value"))
+    );
+    assert_eq!(fs::metadata(&path).unwrap().len(), size);
+}
+
+#[tokio::test]
+async fn aof_checksum_test() {
+    let (config, path) = aof_config("aof-checksum");
+    let mut node = Node::start(config.clone());
+    let mut client = node.client().await;
+    client.set("first", "value").await.unwrap();
+    client.set("second", "value").await.unwrap();
+    node.kill();
+    let report = verify_aof(&path, FrameLimits::default()).unwrap();
+    assert_eq!((report.records, report.unchecked), (2, 0));
+    assert!(report.is_ok());
+
+    // Hand-written requests aren't checked.
+    let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"*3\r\n$3\r\nset\r\n$4\r\nhand\r\n$1\r\n1\r\n")
+        .unwrap();
+    drop(file);
+    let report = verify_aof(&path, FrameLimits::default()).unwrap();
+    assert_eq!((report.records, report.unchecked), (2, 1));
+
+    // Flip a bit of the value of the second record.
+    let mut data = fs::read(&path).unwrap();
+    let first = data.windows(5).position(|w| w == b"value").unwrap();
+    let second = first
+        + 5
+        + data[first + 5..]
+            .windows(5)
+            .position(|w| w == b"value")
+            .unwrap();
+    data[second] ^= 1;
+    fs::write(&path, &data).unwrap();
+    let report = verify_aof(&path, FrameLimits::default()).unwrap();
+    let corruption = report.corruption.unwrap();
+    assert_eq!(corruption.corruption, Corruption::Checksum);
+    assert_eq!(corruption.offset, report.size);
+    assert_eq!(report.records, 1);
+
+    // The server refuses to load it.
+    let db = DBHandle::new(&StorageEngine::Memory).unwrap();
+    let shared = Shared::new(&config);
+    let aof = config.aof.clone().unwrap();
+    let err = AppendOnlyFile::open(aof, &db, &shared, FrameLimits::default())
+        .await
+        .unwrap_err();
+    assert!(err.downcast_ref::<CorruptedAof>().is_some());
+}
+
+#[tokio::test]
+async fn aof_disabled_test() {
+    let node = Node::start(ServerConfig::default());
+    let mut client = node.client().await;
+    let err = client.bgrewriteaof().await.unwrap_err();
+    assert!(err.to_string().contains("append only file is disabled"));
+    let info = client.info(Some("persistence")).await.unwrap();
+    assert_eq!(info_field(&info, "aof_enabled"), 0);
+}
+
+#[tokio::test]
+async fn lsm_restart_test() {
+    let dir = std::env::temp_dir().join(format!("uranus-lsm-restart-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let config = ServerConfig {
+        storage: StorageEngine::Lsm {
+            data_dir: dir.clone(),
+        },
+        ..Default::default()
+    };
+    let node = Node::start(config.clone());
+    let mut client = node.client().await;
+    client.set("string", "value").await.unwrap();
+    client
+        .expire("string", Duration::from_secs(3600))
+        .await
+        .unwrap();
+    client.hset("hash", [("a", "1"), ("b", "2")]).await.unwrap();
+    client.hdel("hash", ["a"]).await.unwrap();
+    client
+        .zadd("zset", [(2.0, "two"), (1.0, "one")])
+        .await
+        .unwrap();
+    client.set("gone", "deleted").await.unwrap();
+    client.del(&["gone"]).await.unwrap();
+    for i in 0..1000 {
+        client
+            .set(&format!("key:{}", i), "x".repeat(100))
+            .await
+            .unwrap();
+    }
+    client.select(1).await.unwrap();
+    client.set("string", "in db 1").await.unwrap();
+    drop(node);
+
+    // Without an append-only file, the data comes back from the engine alone.
+    let node = Node::start(config);
+    let mut client = node.client().await;
+    assert_eq!(client.dbsize().await.unwrap(), 1003);
+    assert_eq!(
+        client.get::<Option<Bytes>>("string").await.unwrap(),
+        Some(Bytes::from("value"))
+    );
+    assert!(matches!(
+        client.ttl("string").await.unwrap(),
+        KeyTtl::Remaining(_)
+    ));
+    assert_eq!(
+        client.hgetall("hash").await.unwrap(),
+        vec![(Bytes::from("b"), Bytes::from("2"))]
+    );
+    assert_eq!(
+        client.zrange("zset", ZRangeBy::Rank(0, -1)).await.unwrap(),
+        vec![(Bytes::from("one"), 1.0), (Bytes::from("two"), 2.0)]
+    );
+    assert_eq!(client.get::<Option<Bytes>>("gone").await.unwrap(), None);
+    assert_eq!(
+        client.get::<Option<Bytes>>("key:999").await.unwrap(),
+        Some(Bytes::from("x".repeat(100)))
+    );
+    client.select(1).await.unwrap();
+    assert_eq!(
+        client.get::<Option<Bytes>>("string").await.unwrap(),
+        Some(Bytes::from("in db 1"))
+    );
+    drop(node);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn lsm_expiry_test() {
+    let dir = std::env::temp_dir().join(format!("uranus-lsm-expiry-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let config = ServerConfig {
+        storage: StorageEngine::Lsm {
+            data_dir: dir.clone(),
+        },
+        ..Default::default()
+    };
+    let node = Node::start(config.clone());
+    let mut client = node.client().await;
+    client.set("key", "old").await.unwrap();
+    client
+        .set_with_ttl("key", "new", Duration::from_millis(300))
+        .await
+        .unwrap();
+    client.set("kept", "value").await.unwrap();
+    drop(node);
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let node = Node::start(config);
+    let mut client = node.client().await;
+    assert_eq!(client.dbsize().await.unwrap(), 1);
+    assert_eq!(client.get::<Option<Bytes>>("key").await.unwrap(), None);
+    assert_eq!(client.ttl("key").await.unwrap(), KeyTtl::Missing);
+    assert_eq!(
+        client.get::<Option<Bytes>>("kept").await.unwrap(),
+        Some(Bytes::from("value"))
+    );
+    drop(node);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn aof_loading_test() {
+    let (config, path) = aof_config("aof-loading");
+    let mut file = fs::File::create(&path).unwrap();
+    for i in 0..100_000 {
+        let (key, value) = (format!("key:{}", i), i.to_string());
+        write!(
+            file,
+            "*3\r\n$3\r\nset\r\n${}\r\n{}\r\n${}\r\n{}\r\n",
+            key.len(),
+            key,
+            value.len(),
+            value
+        )
+        .unwrap();
+    }
+    drop(file);
+    let size = fs::metadata(&path).unwrap().len();
+
+    // Connections are accepted while the file is replayed, but only a few
+    // commands are served.
+    let node = Node::start(config);
+    let mut client = uranus_c::Client::connect(node.addr()).await.unwrap();
+    let err = client.get::<Option<Bytes>>("key:0").await.unwrap_err();
+    let err = err.downcast_ref::<ServerError>().unwrap();
+    assert_eq!(err.code, ErrorCode::Loading);
+    client.ping(None).await.unwrap();
+    let info = client.info(Some("persistence")).await.unwrap();
+    assert_eq!(info_field(&info, "loading"), 1);
+    assert_eq!(info_field(&info, "loading_total_bytes"), size);
+    assert!(info_field(&info, "loading_loaded_bytes") <= size);
+    assert!(info.contains("loading_loaded_perc:"), "{}", info);
+
+    let started = std::time::Instant::now();
+    while client.ready().await.is_err() {
+        assert!(started.elapsed() < Duration::from_secs(60), "still loading");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(
+        client.get::<Option<Bytes>>("key:99999").await.unwrap(),
+        Some(Bytes::from("99999"))
+    );
+    client.set("key:0", "written").await.unwrap();
+    let info = client.info(Some("persistence")).await.unwrap();
+    assert_eq!(info_field(&info, "loading"), 0);
+    assert!(!info.contains("loading_total_bytes"), "{}", info);
+    // The connection opened while loading logs its writes.
+    assert!(info_field(&info, "aof_current_size") > size);
+}
+
+#[test]
+fn embedded_test() {
+    let dir = std::env::temp_dir().join(format!("uranus-embedded-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let storage = StorageEngine::Lsm {
+        data_dir: dir.clone(),
+    };
+    let string = |s: &str| Value::String(Bytes::from(s.to_string()));
+
+    let db = DBHandle::new(&storage).unwrap();
+    db.put(Bytes::from("gone"), string("deleted")).unwrap();
+    let mut batch = Batch::new();
+    batch
+        .put("user:1", string("ada"))
+        .put("user:2", string("grace"))
+        .put("other", string("x"))
+        .delete("gone");
+    db.write_batch(batch).unwrap();
+
+    // Later writes leave the snapshot as it is.
+    let snapshot = db.snapshot().unwrap();
+    db.delete(Bytes::from("user:1")).unwrap();
+    assert_eq!(snapshot.len(), 3);
+    assert_eq!(snapshot.get(b"gone"), None);
+    let users: Vec<_> = snapshot
+        .scan_prefix(b"user:")
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    assert_eq!(
+        users,
+        vec![
+            (Bytes::from("user:1"), string("ada")),
+            (Bytes::from("user:2"), string("grace")),
+        ]
+    );
+    db.close().unwrap();
+
+    let db = DBHandle::new(&storage).unwrap();
+    let keys: Vec<Bytes> = db
+        .snapshot()
+        .unwrap()
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(keys, vec![Bytes::from("other"), Bytes::from("user:2")]);
+    db.close().unwrap();
+    fs::remove_dir_all(dir).unwrap();
+}
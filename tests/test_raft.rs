@@ -0,0 +1,119 @@
+//! Routers agreeing on the cluster metadata, see [`uranus_rin::raft`].
+
+use std::{fs, path::PathBuf, time::Duration};
+
+use tokio::net::TcpListener;
+use uranus_rin::{Change, Metadata, Raft, RaftConfig, RaftError, Role};
+
+const ELECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where `node` of `test` persists its log.
+fn raft_dir(test: &str, node: usize) -> PathBuf {
+    let name = format!("uranus-{}-{}-{}", test, std::process::id(), node);
+    std::env::temp_dir().join(name)
+}
+
+fn raft_config(test: &str, addrs: &[String], node: usize) -> RaftConfig {
+    let peers = addrs.iter().filter(|addr| **addr != addrs[node]);
+    let mut config = RaftConfig::new(&addrs[node], peers, raft_dir(test, node));
+    config.election_timeout = Duration::from_millis(150);
+    config.heartbeat_interval = Duration::from_millis(30);
+    config
+}
+
+/// Waits until one of `nodes` leads.
+async fn wait_leader(nodes: &[Raft]) -> Raft {
+    let elected = async {
+        loop {
+            if let Some(leader) = nodes.iter().find(|node| node.role() == Role::Leader) {
+                return leader.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(ELECTION_TIMEOUT, elected)
+        .await
+        .expect("no leader was elected")
+}
+
+async fn wait_metadata(node: &Raft, metadata: &Metadata) {
+    let applied = async {
+        while node.metadata() != *metadata {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(ELECTION_TIMEOUT, applied)
+        .await
+        .expect("the metadata didn't converge");
+}
+
+#[tokio::test]
+async fn raft_replication_test() {
+    let mut listeners = vec![];
+    for _ in 0..3 {
+        listeners.push(TcpListener::bind("127.0.0.1:0").await.unwrap());
+    }
+    let addrs: Vec<String> = listeners
+        .iter()
+        .map(|listener| listener.local_addr().unwrap().to_string())
+        .collect();
+    let mut nodes = vec![];
+    for (i, listener) in listeners.into_iter().enumerate() {
+        let _ = fs::remove_dir_all(raft_dir("raft", i));
+        let config = raft_config("raft", &addrs, i);
+        nodes.push(Raft::start(config, listener).await.unwrap());
+    }
+
+    let leader = wait_leader(&nodes).await;
+    let follower = nodes
+        .iter()
+        .find(|node| node.role() != Role::Leader)
+        .unwrap();
+    let change = Change::SetConfig {
+        name: "maxmemory".to_string(),
+        value: "1gb".to_string(),
+    };
+    let err = follower.propose(change.clone()).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<RaftError>(),
+        Some(RaftError::NotLeader(_))
+    ));
+    leader.propose(change).await.unwrap();
+    let assign = Change::Assign {
+        slots: 0..=8191,
+        addr: "10.0.0.1:6379".to_string(),
+    };
+    leader.propose(assign).await.unwrap();
+    for node in &nodes {
+        wait_metadata(node, &leader.metadata()).await;
+    }
+
+    // The others elect a new leader when the leader fails.
+    leader.shutdown();
+    let index = nodes
+        .iter()
+        .position(|node| node.addr() == leader.addr())
+        .unwrap();
+    let survivors: Vec<Raft> = nodes
+        .iter()
+        .filter(|node| node.addr() != leader.addr())
+        .cloned()
+        .collect();
+    let new_leader = wait_leader(&survivors).await;
+    assert!(new_leader.term() > leader.term());
+    let assign = Change::Assign {
+        slots: 8192..=16383,
+        addr: "10.0.0.2:6379".to_string(),
+    };
+    new_leader.propose(assign).await.unwrap();
+
+    // The failed node restarts from its log and catches up.
+    let listener = TcpListener::bind(&addrs[index]).await.unwrap();
+    let config = raft_config("raft", &addrs, index);
+    let restarted = Raft::start(config, listener).await.unwrap();
+    let metadata = new_leader.metadata();
+    wait_metadata(&restarted, &metadata).await;
+    assert_eq!(metadata.topology.owner(0), Some("10.0.0.1:6379"));
+    assert_eq!(metadata.topology.owner(16383), Some("10.0.0.2:6379"));
+    assert_eq!(metadata.config["maxmemory"], "1gb");
+}
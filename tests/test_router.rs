@@ -0,0 +1,59 @@
+use std::net::SocketAddr;
+
+use tokio::net::TcpListener;
+use uranus_rin::{BackendConfig, Router};
+use uranus_s::key_slot;
+
+async fn start_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { uranus_s::run(listener).await });
+    addr
+}
+
+#[tokio::test]
+async fn warm_up_test() {
+    let addrs = [start_server().await, start_server().await];
+    let config = BackendConfig {
+        min_pool_size: 3,
+        ..Default::default()
+    };
+    let router = Router::connect(addrs, config).await.unwrap();
+    for backend in router.backends() {
+        assert_eq!(backend.idle_connections(), 3);
+    }
+
+    let backend = router.backend(b"hello");
+    let mut client = backend.checkout().await.unwrap();
+    assert_eq!(backend.idle_connections(), 2);
+    client.set("hello", "world").await.unwrap();
+    drop(client);
+    assert_eq!(backend.idle_connections(), 3);
+}
+
+#[tokio::test]
+async fn unreachable_backend_test() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    let result = Router::connect([addr], BackendConfig::default()).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn slot_routing_test() {
+    let addrs = [start_server().await, start_server().await];
+    let router = Router::connect(addrs, BackendConfig::default())
+        .await
+        .unwrap();
+    assert_eq!(router.topology().ranges().len(), 2);
+    for key in [&b"foo"[..], b"bar", b"{user:1}:name", b"{user:1}:email"] {
+        let owner = router.topology().owner(key_slot(key));
+        assert_eq!(Some(router.backend(key).addr()), owner);
+    }
+    // Keys sharing a hash tag are on the same backend.
+    let name = router.backend(b"{user:1}:name").addr();
+    assert_eq!(router.backend(b"{user:1}:email").addr(), name);
+    // "foo" and "bar" are on either side of the middle slot.
+    assert_ne!(router.backend(b"foo").addr(), router.backend(b"bar").addr());
+}
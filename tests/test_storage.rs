@@ -0,0 +1,245 @@
+//! Model-based tests of the storage engines: random sequences of operations run
+//! against every engine and against a plain `HashMap`, and both must agree on
+//! everything observable.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+use support::Rng;
+use uranus_kv::{sharded::ShardedKV, LsmConfig, LsmKV, StdHashKV, Storage, Value};
+
+const SEEDS: u64 = 64;
+const STEPS: usize = 300;
+/// Few keys, so that operations keep hitting the same ones.
+const KEYS: u64 = 16;
+
+#[derive(Debug)]
+enum Op {
+    Put(Bytes, Value),
+    /// Expired already when `expired`, so that no step depends on timing.
+    PutWithTtl(Bytes, Value, bool),
+    Get(Bytes),
+    Delete(Bytes),
+    Remove(Bytes),
+    Scan,
+    Len,
+    Clear,
+    Expire,
+    Reshard(usize),
+}
+
+impl Op {
+    fn draw(rng: &mut Rng) -> Op {
+        let key = Bytes::from(format!("key{}", rng.below(KEYS)));
+        let value = Value::String(Bytes::from(format!("value{}", rng.below(1000))));
+        match rng.below(24) {
+            0..=3 => Op::Put(key, value),
+            4 | 5 => Op::PutWithTtl(key, value, rng.below(2) == 0),
+            6..=9 => Op::Get(key),
+            10 | 11 => Op::Delete(key),
+            12 | 13 => Op::Remove(key),
+            14 | 15 => Op::Scan,
+            16 | 17 => Op::Len,
+            18 => Op::Clear,
+            19 | 20 => Op::Expire,
+            _ => Op::Reshard(1 + rng.below(8) as usize),
+        }
+    }
+}
+
+/// The reference: every key with its value, and its deadline if it has a TTL.
+#[derive(Default)]
+struct Model {
+    entries: HashMap<Bytes, (Value, Option<SystemTime>)>,
+}
+
+impl Model {
+    fn live(&self, key: &Bytes, now: SystemTime) -> Option<&Value> {
+        match self.entries.get(key)? {
+            (_, Some(expires_at)) if *expires_at <= now => None,
+            (value, _) => Some(value),
+        }
+    }
+
+    fn remove(&mut self, key: &Bytes, now: SystemTime) -> Option<Value> {
+        let value = self.live(key, now).cloned();
+        self.entries.remove(key);
+        value
+    }
+
+    fn scan(&self, now: SystemTime) -> Vec<(Bytes, Value)> {
+        let keys = self.entries.keys();
+        keys.filter_map(|key| Some((key.clone(), self.live(key, now)?.clone())))
+            .collect()
+    }
+}
+
+fn sorted(mut pairs: Vec<(Bytes, Value)>) -> Vec<(Bytes, Value)> {
+    pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    pairs
+}
+
+fn check(name: &str, engine: fn() -> Box<dyn Storage>) {
+    for seed in 0..SEEDS {
+        let mut rng = Rng::new(seed);
+        let mut storage = engine();
+        let mut model = Model::default();
+        for step in 0..STEPS {
+            let op = Op::draw(&mut rng);
+            let context = format!("{} diverged at seed {} step {}: {:?}", name, seed, step, op);
+            let now = SystemTime::now();
+            match op {
+                Op::Put(key, value) => {
+                    storage.put(key.clone(), value.clone()).unwrap();
+                    model.entries.insert(key, (value, None));
+                }
+                Op::PutWithTtl(key, value, expired) => {
+                    if !storage.supports_ttl() {
+                        continue;
+                    }
+                    let expires_at = if expired {
+                        now - Duration::from_secs(1)
+                    } else {
+                        now + Duration::from_secs(3600)
+                    };
+                    storage
+                        .put_with_ttl(key.clone(), value.clone(), expires_at)
+                        .unwrap();
+                    assert_eq!(storage.expires_at(&key), Some(expires_at), "{}", context);
+                    model.entries.insert(key, (value, Some(expires_at)));
+                }
+                Op::Get(key) => {
+                    let expected = model.live(&key, now).cloned();
+                    assert_eq!(storage.get(key).unwrap(), expected, "{}", context);
+                }
+                Op::Delete(key) => {
+                    let existed = model.remove(&key, now).is_some();
+                    assert_eq!(storage.delete(key).is_ok(), existed, "{}", context);
+                }
+                Op::Remove(key) => {
+                    let expected = model.remove(&key, now);
+                    assert_eq!(storage.remove(key).unwrap(), expected, "{}", context);
+                }
+                Op::Scan => {
+                    let expected = sorted(model.scan(now));
+                    let mut keys = storage.keys().unwrap();
+                    keys.sort();
+                    let expected_keys: Vec<Bytes> =
+                        expected.iter().map(|(k, _)| k.clone()).collect();
+                    assert_eq!(sorted(storage.scan().unwrap()), expected, "{}", context);
+                    assert_eq!(keys, expected_keys, "{}", context);
+                }
+                Op::Len => {
+                    let expected = model.scan(now).len();
+                    assert_eq!(storage.len().unwrap(), expected, "{}", context);
+                    assert_eq!(storage.is_empty().unwrap(), expected == 0, "{}", context);
+                }
+                Op::Clear => {
+                    storage.clear().unwrap();
+                    model.entries.clear();
+                }
+                Op::Expire => {
+                    // Reclaiming expired keys must not change what is visible.
+                    storage.expire(now);
+                }
+                Op::Reshard(shards) => {
+                    if storage.shard_count().is_some() {
+                        storage.reshard(shards).unwrap();
+                        assert_eq!(storage.shard_count(), Some(shards), "{}", context);
+                    }
+                }
+            }
+            // Resharding engines move keys in between operations.
+            storage.migrate_step();
+        }
+    }
+}
+
+#[test]
+fn std_hash_kv_matches_model() {
+    check("StdHashKV", || Box::new(StdHashKV::new()));
+}
+
+#[test]
+fn sharded_kv_matches_model() {
+    check("ShardedKV", || Box::new(ShardedKV::new(4)));
+}
+
+/// A fresh directory for every engine opened by `test`.
+fn lsm_dir(test: &str) -> PathBuf {
+    static OPENED: AtomicU64 = AtomicU64::new(0);
+    let n = OPENED.fetch_add(1, Ordering::Relaxed);
+    let name = format!("uranus-{}-{}-{}", test, std::process::id(), n);
+    let dir = std::env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Small enough that a few dozen writes flush the memtable and merge tables.
+fn small_lsm(dir: PathBuf) -> LsmConfig {
+    LsmConfig {
+        memtable_size: 512,
+        max_tables: 3,
+        ..LsmConfig::new(dir)
+    }
+}
+
+#[test]
+fn lsm_kv_matches_model() {
+    check("LsmKV", || {
+        Box::new(LsmKV::open(small_lsm(lsm_dir("lsm-model"))).unwrap())
+    });
+}
+
+#[test]
+fn lsm_kv_reopen_matches_model() {
+    for seed in 0..SEEDS / 8 {
+        let mut rng = Rng::new(seed);
+        let dir = lsm_dir("lsm-reopen");
+        let mut storage = LsmKV::open(small_lsm(dir.clone())).unwrap();
+        let mut model = Model::default();
+        for step in 0..STEPS {
+            let key = Bytes::from(format!("key{}", rng.below(KEYS)));
+            let value = Value::String(Bytes::from(format!("value{}", rng.below(1000))));
+            let now = SystemTime::now();
+            match rng.below(8) {
+                0..=2 => {
+                    storage.put(key.clone(), value.clone()).unwrap();
+                    model.entries.insert(key, (value, None));
+                }
+                3 => {
+                    let expires_at = now + Duration::from_secs(3600);
+                    storage
+                        .put_with_ttl(key.clone(), value.clone(), expires_at)
+                        .unwrap();
+                    model.entries.insert(key, (value, Some(expires_at)));
+                }
+                4 | 5 => {
+                    storage.remove(key.clone()).unwrap();
+                    model.remove(&key, now);
+                }
+                6 => {
+                    storage.clear().unwrap();
+                    model.entries.clear();
+                }
+                _ => {
+                    drop(storage);
+                    storage = LsmKV::open(small_lsm(dir.clone())).unwrap();
+                    let context = format!("diverged at seed {} step {}", seed, step);
+                    let expected = sorted(model.scan(now));
+                    assert_eq!(sorted(storage.scan().unwrap()), expected, "{}", context);
+                    for (key, (_, expires_at)) in &model.entries {
+                        assert_eq!(storage.expires_at(key), *expires_at, "{}", context);
+                    }
+                }
+            }
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}